@@ -0,0 +1,12 @@
+fn main() {
+    #[cfg(feature = "grpc")]
+    {
+        std::env::set_var(
+            "PROTOC",
+            protoc_bin_vendored::protoc_bin_path().expect("no protoc for this platform"),
+        );
+        tonic_build::configure()
+            .compile_protos(&["proto/control.proto"], &["proto"])
+            .expect("failed to compile proto/control.proto");
+    }
+}
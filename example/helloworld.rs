@@ -1,7 +1,8 @@
 use anyhow::Result;
-use libdns::config::{GeneralConfigBuilder, RecordBuilder, RecordType, RunConfigBuilder};
+use libdns::config::{GeneralConfigBuilder, RecordBuilder, RecordData, RunConfigBuilder};
 use libdns::Server;
 use maplit::hashmap;
+use std::net::Ipv4Addr;
 use std::time::Duration;
 use tokio::signal;
 use tracing::info;
@@ -19,16 +20,15 @@ async fn main() -> Result<()> {
         .zones(hashmap! {
             "et.internal".to_string() => vec![
                 RecordBuilder::default()
-                .rr_type(RecordType::A)
                 .name("www.et.internal".to_string())
-                .value("123.123.123.123".to_string())
+                .data(RecordData::A(Ipv4Addr::new(123, 123, 123, 123)))
                 .ttl(Duration::from_secs(60))
                 .build()?
-            ]
+            ].into()
         })
         .build()?;
 
-    let mut server = Server::new(config);
+    let mut server = Server::try_new(config)?;
     server.run().await?;
     info!("Server listening on {}", server.udp_local_addr().unwrap());
     info!("Try `nslookup www.et.internal 127.0.0.1` in another terminal session");
@@ -0,0 +1,16 @@
+use anyhow::{bail, Result};
+use libdns::RunConfig;
+
+/// Imports the zones of a config file into a SQLite database, after which
+/// they can be served with `backend = "sqlite"`.
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+    let [_, config, database] = args.as_slice() else {
+        bail!("usage: sqlite-import <config> <database>");
+    };
+    let config = RunConfig::from_path(config)?;
+    for zone in libdns::sqlite::import(&config, database.as_ref())? {
+        println!("imported zone {}", zone);
+    }
+    Ok(())
+}
@@ -0,0 +1,136 @@
+use anyhow::{anyhow, Result};
+use hickory_proto::rr::LowerName;
+use ipnet::IpNet;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// Clients allowed to perform an operation on a zone, by address, network or
+/// the TSIG key their request is signed with.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Acl {
+    networks: Vec<IpNet>,
+    keys: Vec<LowerName>,
+}
+
+impl Acl {
+    /// Parses addresses (`192.0.2.1`), networks (`10.0.0.0/8`) and TSIG key
+    /// names (`key transfer`).
+    pub(crate) fn parse(entries: &[String]) -> Result<Self> {
+        let mut acl = Self::default();
+        for entry in entries {
+            if let Some(key) = entry.strip_prefix("key ") {
+                let key = LowerName::from_str(key.trim())
+                    .map_err(|_| anyhow!("invalid key name in acl: {}", entry))?;
+                acl.keys.push(key);
+                continue;
+            }
+            let network = entry
+                .parse::<IpNet>()
+                .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| anyhow!("invalid address or network in acl: {}", entry))?;
+            acl.networks.push(network);
+        }
+        Ok(acl)
+    }
+
+    /// Allows exactly the addresses `ips`.
+    pub(crate) fn from_ips(ips: impl IntoIterator<Item = IpAddr>) -> Self {
+        let networks = ips.into_iter().map(IpNet::from).collect();
+        Self {
+            networks,
+            keys: Vec::new(),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.networks.is_empty() && self.keys.is_empty()
+    }
+
+    /// Names of the TSIG keys the acl refers to.
+    pub(crate) fn keys(&self) -> &[LowerName] {
+        &self.keys
+    }
+
+    /// Whether a request from `ip`, signed with the verified TSIG `key` if
+    /// any, matches one of the entries.
+    pub(crate) fn allows(&self, ip: IpAddr, key: Option<&LowerName>) -> bool {
+        let ip = ip.to_canonical();
+        self.networks.iter().any(|network| network.contains(&ip))
+            || key.is_some_and(|key| self.keys.contains(key))
+    }
+}
+
+/// Clients allowed to query, all of them when `allow` is empty, except those
+/// matching `deny`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ClientAcl {
+    allow: Acl,
+    deny: Acl,
+}
+
+impl ClientAcl {
+    pub(crate) fn parse(allow: &[String], deny: &[String]) -> Result<Self> {
+        Ok(Self {
+            allow: Acl::parse(allow)?,
+            deny: Acl::parse(deny)?,
+        })
+    }
+
+    /// Names of the TSIG keys either list refers to.
+    pub(crate) fn keys(&self) -> impl Iterator<Item = &LowerName> {
+        self.allow.keys().iter().chain(self.deny.keys())
+    }
+
+    pub(crate) fn allows(&self, ip: IpAddr, key: Option<&LowerName>) -> bool {
+        !self.deny.allows(ip, key) && (self.allow.is_empty() || self.allow.allows(ip, key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_addresses_and_networks() -> Result<()> {
+        let acl = Acl::parse(&["192.0.2.1".to_string(), "10.0.0.0/8".to_string()])?;
+        assert!(acl.allows("192.0.2.1".parse()?, None));
+        assert!(!acl.allows("192.0.2.2".parse()?, None));
+        assert!(acl.allows("10.1.2.3".parse()?, None));
+        assert!(acl.allows("::ffff:10.1.2.3".parse()?, None));
+        assert!(!Acl::default().allows("10.1.2.3".parse()?, None));
+        assert!(Acl::from_ips(["10.1.2.3".parse()?]).allows("10.1.2.3".parse()?, None));
+        assert!(Acl::parse(&["10.0.0.0/33".to_string()]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn matches_keys() -> Result<()> {
+        let acl = Acl::parse(&["key Transfer".to_string()])?;
+        let key = LowerName::from_str("transfer.")?;
+        assert_eq!(acl.keys().len(), 1);
+        assert!(acl.allows("192.0.2.1".parse()?, Some(&key)));
+        assert!(!acl.allows("192.0.2.1".parse()?, None));
+        assert!(!acl.allows("192.0.2.1".parse()?, Some(&LowerName::from_str("other.")?)));
+        Ok(())
+    }
+
+    #[test]
+    fn denies_before_allowing() -> Result<()> {
+        let acl = ClientAcl::parse(
+            &["10.0.0.0/8".to_string(), "key internal".to_string()],
+            &["10.0.0.1".to_string()],
+        )?;
+        assert!(acl.allows("10.1.2.3".parse()?, None));
+        assert!(!acl.allows("10.0.0.1".parse()?, None));
+        assert!(!acl.allows("192.0.2.1".parse()?, None));
+        let key = LowerName::from_str("internal.")?;
+        assert!(acl.allows("192.0.2.1".parse()?, Some(&key)));
+        assert_eq!(acl.keys().count(), 1);
+
+        let open = ClientAcl::parse(&[], &["192.0.2.0/24".to_string()])?;
+        assert!(open.allows("10.1.2.3".parse()?, None));
+        assert!(!open.allows("192.0.2.1".parse()?, None));
+        assert!(ClientAcl::default().allows("192.0.2.1".parse()?, None));
+        Ok(())
+    }
+}
@@ -0,0 +1,180 @@
+//! ACME DNS-01 challenges (RFC 8555 section 8.4): TXT records set at runtime
+//! while a certificate authority validates a domain, and removed once they
+//! expire.
+
+use crate::config::AcmeConfig;
+use crate::dns::RecordError;
+use crate::error::Error;
+use crate::zones::{RecordChange, ZoneSet};
+use anyhow::{bail, Result};
+use hickory_proto::rr;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::debug;
+
+/// Challenges answered by the in-memory zones of a server.
+pub(crate) struct Challenges {
+    zones: Arc<ZoneSet>,
+    ttl: u32,
+    expire: Duration,
+    /// When each challenge expires, by its name and token. Setting a
+    /// challenge again postpones its removal.
+    expiries: Mutex<HashMap<(rr::Name, String), Instant>>,
+}
+
+impl Challenges {
+    pub(crate) fn new(zones: Arc<ZoneSet>, config: &AcmeConfig) -> Self {
+        Self {
+            zones,
+            ttl: config.ttl().as_secs().try_into().unwrap_or(u32::MAX),
+            expire: config.expire(),
+            expiries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The name of the challenge of `domain`, `_acme-challenge.<domain>`. A
+    /// wildcard domain is validated at the name it stands under.
+    pub(crate) fn name(domain: &rr::Name) -> Result<rr::Name> {
+        let domain = match domain.is_wildcard() {
+            true => domain.base_name(),
+            false => domain.clone(),
+        };
+        Ok(rr::Name::from_ascii("_acme-challenge")?.append_domain(&domain)?)
+    }
+
+    /// Answers the challenge at `name` with `token` until it expires. Other
+    /// tokens of the name are answered too, as a name may be validated for
+    /// several certificates at once.
+    pub(crate) async fn set(self: &Arc<Self>, name: rr::Name, token: &str) -> Result<()> {
+        let record = self.record(name.clone(), token)?;
+        self.zones
+            .commit(vec![RecordChange::Add(record.clone())])
+            .await?;
+
+        let key = (name, token.to_string());
+        let expires = Instant::now() + self.expire;
+        self.expiries.lock().unwrap().insert(key.clone(), expires);
+        let challenges = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep_until(expires).await;
+            let expired = {
+                let mut expiries = challenges.expiries.lock().unwrap();
+                let expired = expiries.get(&key) == Some(&expires);
+                if expired {
+                    expiries.remove(&key);
+                }
+                expired
+            };
+            if expired {
+                if let Err(e) = challenges.remove(record).await {
+                    debug!("failed to remove expired acme challenge {}: {:#}", key.0, e);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Stops answering the challenge at `name` with `token`, returns whether
+    /// it was answered.
+    pub(crate) async fn clear(&self, name: rr::Name, token: &str) -> Result<bool> {
+        let record = self.record(name.clone(), token)?;
+        self.expiries
+            .lock()
+            .unwrap()
+            .remove(&(name, token.to_string()));
+        self.remove(record).await
+    }
+
+    async fn remove(&self, record: rr::Record) -> Result<bool> {
+        match self.zones.commit(vec![RecordChange::Remove(record)]).await {
+            Ok(_) => Ok(true),
+            Err(RecordError::RecordNotFound(_)) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn record(&self, name: rr::Name, token: &str) -> Result<rr::Record> {
+        if token.is_empty() || token.len() > 255 || !token.bytes().all(|b| b.is_ascii_graphic()) {
+            bail!(Error::Record {
+                name: name.to_string(),
+                source: format!("invalid acme challenge token {:?}", token).into(),
+            });
+        }
+        let txt = rr::rdata::TXT::new(vec![token.to_string()]);
+        Ok(rr::Record::from_rdata(name, self.ttl, rr::RData::TXT(txt)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AcmeConfigBuilder, RecordBuilder, RecordData};
+    use crate::tsig::Keyring;
+    use maplit::hashmap;
+    use std::net::Ipv4Addr;
+    use std::str::FromStr;
+
+    async fn tokens(zones: &ZoneSet, name: &rr::Name) -> Vec<String> {
+        let authority = zones.find(name).unwrap();
+        let records = authority.records().await;
+        let mut tokens: Vec<String> = records
+            .values()
+            .filter(|rrset| rrset.name() == name)
+            .flat_map(|rrset| rrset.records_without_rrsigs())
+            .filter_map(|record| match record.data() {
+                Some(rr::RData::TXT(txt)) => {
+                    Some(String::from_utf8_lossy(&txt.txt_data()[0]).into_owned())
+                }
+                _ => None,
+            })
+            .collect();
+        tokens.sort();
+        tokens
+    }
+
+    #[tokio::test]
+    async fn sets_and_expires_challenges() -> Result<()> {
+        let record = RecordBuilder::default()
+            .name("www".to_string())
+            .data(RecordData::A(Ipv4Addr::new(10, 0, 0, 1)))
+            .build()?;
+        let zones = hashmap! {
+            "et.internal".to_string() => vec![record].into(),
+        };
+        let zones = Arc::new(ZoneSet::new(&zones, Keyring::new(&[])?)?);
+        let config = AcmeConfigBuilder::default()
+            .ttl(Duration::from_secs(5))
+            .expire(Duration::from_millis(300))
+            .build()?;
+        let challenges = Arc::new(Challenges::new(zones.clone(), &config));
+
+        let name = Challenges::name(&rr::Name::from_str("*.www.et.internal.")?)?;
+        assert_eq!(
+            name,
+            rr::Name::from_str("_acme-challenge.www.et.internal.")?
+        );
+        challenges.set(name.clone(), "first").await?;
+        challenges.set(name.clone(), "second").await?;
+        assert_eq!(tokens(&zones, &name).await, ["first", "second"]);
+
+        assert!(challenges.clear(name.clone(), "first").await?);
+        assert!(!challenges.clear(name.clone(), "first").await?);
+        assert_eq!(tokens(&zones, &name).await, ["second"]);
+
+        // setting it again postpones the expiry
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        challenges.set(name.clone(), "second").await?;
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(tokens(&zones, &name).await, ["second"]);
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(tokens(&zones, &name).await.is_empty());
+
+        assert!(challenges.set(name.clone(), "").await.is_err());
+        assert!(challenges.set(name, "two words").await.is_err());
+        let outside = Challenges::name(&rr::Name::from_str("et.example.")?)?;
+        assert!(challenges.set(outside, "token").await.is_err());
+        Ok(())
+    }
+}
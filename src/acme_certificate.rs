@@ -0,0 +1,840 @@
+//! Certificate of the TLS listeners obtained from an ACME certificate
+//! authority (RFC 8555), which validates its domains through DNS-01
+//! challenges answered by the server itself, and renewed before it expires.
+
+use crate::acme::Challenges;
+use crate::config::AcmeCertificateConfig;
+use crate::dnssec::load_or_generate;
+use anyhow::{bail, Context, Result};
+use data_encoding::BASE64URL_NOPAD;
+use hickory_proto::rr;
+use hickory_proto::rr::dnssec::{tbs::TBS, Algorithm, DigestType, KeyFormat, KeyPair, Private};
+use serde_json::{json, Value};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// How long to wait before trying again to obtain a certificate.
+const RETRY_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// The longest wait between two checks of the expiry of the certificate.
+const CHECK_INTERVAL: Duration = Duration::from_secs(86400);
+
+pub(crate) struct AcmeCertificate {
+    config: AcmeCertificateConfig,
+    domains: Vec<rr::Name>,
+    challenges: Arc<Challenges>,
+    client: Client,
+}
+
+impl AcmeCertificate {
+    pub(crate) fn new(config: &AcmeCertificateConfig, challenges: Arc<Challenges>) -> Result<Self> {
+        if config.domains().is_empty() {
+            bail!("acme certificate has no domains");
+        }
+        let domains = config
+            .domains()
+            .iter()
+            .map(|domain| {
+                rr::Name::from_str(domain)
+                    .with_context(|| format!("invalid acme certificate domain {}", domain))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            config: config.clone(),
+            domains,
+            challenges,
+            client: Client::new(config)?,
+        })
+    }
+
+    /// Writes a self-signed certificate when there is none yet, for the TLS
+    /// listeners to start before the first one is obtained.
+    pub(crate) fn prepare(&self) -> Result<()> {
+        if self.config.cert_path().exists() && self.config.key_path().exists() {
+            return Ok(());
+        }
+        std::fs::create_dir_all(self.config.cert_dir())
+            .with_context(|| format!("failed to create {}", self.config.cert_dir().display()))?;
+        let (cert, key) = self.client.self_signed(self.config.domains())?;
+        self.store(&cert, &key)
+    }
+
+    /// Obtains the certificate whenever it is due for renewal, until
+    /// `token` is cancelled.
+    pub(crate) async fn renew(self: Arc<Self>, token: CancellationToken) {
+        loop {
+            let mut wait = self.renewal_wait();
+            if wait.is_zero() {
+                let issued = tokio::select! {
+                    issued = self.issue() => issued,
+                    _ = token.cancelled() => break,
+                };
+                wait = match issued {
+                    Ok(()) => {
+                        info!("obtained acme certificate for {}", self.names());
+                        self.renewal_wait().max(RETRY_INTERVAL)
+                    }
+                    Err(e) => {
+                        warn!(
+                            "failed to obtain acme certificate for {}: {:#}",
+                            self.names(),
+                            e
+                        );
+                        RETRY_INTERVAL
+                    }
+                };
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(wait.min(CHECK_INTERVAL)) => {}
+                _ = token.cancelled() => break,
+            }
+        }
+    }
+
+    /// How long until the certificate is renewed, zero when it is due or
+    /// can't be read.
+    fn renewal_wait(&self) -> Duration {
+        crate::tls::not_after(&self.config.cert_path())
+            .ok()
+            .and_then(|expiry| expiry.checked_sub(self.config.renew_before()))
+            .and_then(|renewal| renewal.duration_since(SystemTime::now()).ok())
+            .unwrap_or_default()
+    }
+
+    fn names(&self) -> String {
+        self.config.domains().join(", ")
+    }
+
+    async fn issue(&self) -> Result<()> {
+        let path = self.config.account_key_path();
+        let account = Account::new(&load_or_generate(&path, Algorithm::ECDSAP256SHA256)?)
+            .with_context(|| format!("invalid account key {}", path.display()))?;
+        let (cert, key) = self
+            .client
+            .order(&account, &self.config, &self.domains, &self.challenges)
+            .await?;
+        self.store(&cert, &key)
+    }
+
+    /// Replaces the certificate and its key, the key first as the listeners
+    /// reload them once both changed.
+    fn store(&self, cert: &str, key: &str) -> Result<()> {
+        replace(&self.config.key_path(), key.as_bytes(), true)?;
+        replace(&self.config.cert_path(), cert.as_bytes(), false)
+    }
+}
+
+/// Writes `contents` to a temporary file renamed to `path`, only readable by
+/// the user of the server if `private`.
+fn replace(path: &Path, contents: &[u8], private: bool) -> Result<()> {
+    let temporary = path.with_extension("tmp");
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    if private {
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+    let mut file = options
+        .open(&temporary)
+        .with_context(|| format!("failed to create {}", temporary.display()))?;
+    std::io::Write::write_all(&mut file, contents)?;
+    std::fs::rename(&temporary, path)
+        .with_context(|| format!("failed to replace {}", path.display()))
+}
+
+/// The ECDSA P-256 key of an account, signing its requests as JWS with
+/// ES256 (RFC 7518 section 3.4).
+#[cfg_attr(not(feature = "acme"), allow(dead_code))]
+struct Account {
+    key: KeyPair<Private>,
+    /// The uncompressed point of the public key, without its prefix.
+    public: Vec<u8>,
+}
+
+#[cfg_attr(not(feature = "acme"), allow(dead_code))]
+impl Account {
+    fn new(pkcs8: &[u8]) -> Result<Self> {
+        let key = KeyFormat::Pkcs8.decode_key(pkcs8, None, Algorithm::ECDSAP256SHA256)?;
+        let public = key.to_public_bytes()?;
+        if public.len() != 64 {
+            bail!("expected a P-256 key");
+        }
+        Ok(Self { key, public })
+    }
+
+    fn jwk(&self) -> Value {
+        json!({
+            "crv": "P-256",
+            "kty": "EC",
+            "x": BASE64URL_NOPAD.encode(&self.public[..32]),
+            "y": BASE64URL_NOPAD.encode(&self.public[32..]),
+        })
+    }
+
+    /// The JWK thumbprint of RFC 7638: the hash of the required members of
+    /// the key, in lexicographic order and without whitespace.
+    fn thumbprint(&self) -> Result<String> {
+        let jwk = format!(
+            r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#,
+            BASE64URL_NOPAD.encode(&self.public[..32]),
+            BASE64URL_NOPAD.encode(&self.public[32..]),
+        );
+        Ok(BASE64URL_NOPAD.encode(DigestType::SHA256.hash(jwk.as_bytes())?.as_ref()))
+    }
+
+    /// The TXT value answering the DNS-01 challenge with `token`, the hash
+    /// of its key authorization (RFC 8555 section 8.4).
+    fn challenge_value(&self, token: &str) -> Result<String> {
+        let authorization = format!("{}.{}", token, self.thumbprint()?);
+        Ok(BASE64URL_NOPAD.encode(DigestType::SHA256.hash(authorization.as_bytes())?.as_ref()))
+    }
+
+    /// The flattened JWS of a request to `url`, a POST-as-GET without
+    /// `payload`. The account is identified by its key until it has a `kid`.
+    fn sign(
+        &self,
+        url: &str,
+        nonce: &str,
+        kid: Option<&str>,
+        payload: Option<&Value>,
+    ) -> Result<Value> {
+        let mut protected = json!({ "alg": "ES256", "nonce": nonce, "url": url });
+        match kid {
+            Some(kid) => protected["kid"] = kid.into(),
+            None => protected["jwk"] = self.jwk(),
+        }
+        let protected = BASE64URL_NOPAD.encode(protected.to_string().as_bytes());
+        let payload = payload.map_or(String::new(), |payload| {
+            BASE64URL_NOPAD.encode(payload.to_string().as_bytes())
+        });
+        let input = format!("{}.{}", protected, payload);
+        // DNSSEC and JWS both use the fixed size form r || s of signatures
+        let signature = self
+            .key
+            .sign(Algorithm::ECDSAP256SHA256, &TBS::from(input.as_bytes()))?;
+        Ok(json!({
+            "protected": protected,
+            "payload": payload,
+            "signature": BASE64URL_NOPAD.encode(&signature),
+        }))
+    }
+}
+
+/// Client of the certificate authority.
+#[cfg(feature = "acme")]
+struct Client {
+    http: reqwest::Client,
+}
+
+#[cfg(feature = "acme")]
+mod client {
+    use super::*;
+    use reqwest::header::{CONTENT_TYPE, LOCATION, RETRY_AFTER};
+    use serde::de::DeserializeOwned;
+    use serde::Deserialize;
+    use std::fmt;
+    use tracing::debug;
+
+    /// How often, and how many times, a pending authorization or order is
+    /// checked.
+    const POLL_INTERVAL: Duration = Duration::from_secs(2);
+    const POLL_ATTEMPTS: usize = 60;
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Directory {
+        new_nonce: String,
+        new_account: String,
+        new_order: String,
+    }
+
+    #[derive(Deserialize)]
+    struct Order {
+        status: String,
+        #[serde(default)]
+        authorizations: Vec<String>,
+        finalize: String,
+        certificate: Option<String>,
+        error: Option<Problem>,
+    }
+
+    #[derive(Deserialize)]
+    struct Authorization {
+        status: String,
+        identifier: Identifier,
+        #[serde(default)]
+        challenges: Vec<Challenge>,
+    }
+
+    #[derive(Deserialize)]
+    struct Identifier {
+        value: String,
+    }
+
+    #[derive(Deserialize)]
+    struct Challenge {
+        #[serde(rename = "type")]
+        kind: String,
+        url: String,
+        #[serde(default)]
+        token: String,
+        error: Option<Problem>,
+    }
+
+    /// An error of the certificate authority, RFC 7807.
+    #[derive(Deserialize, Default)]
+    struct Problem {
+        #[serde(default, rename = "type")]
+        kind: String,
+        #[serde(default)]
+        detail: String,
+    }
+
+    impl fmt::Display for Problem {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let kind = self.kind.trim_start_matches("urn:ietf:params:acme:error:");
+            write!(f, "{} ({})", self.detail, kind)
+        }
+    }
+
+    impl Client {
+        pub(super) fn new(config: &AcmeCertificateConfig) -> Result<Self> {
+            let mut http = reqwest::Client::builder();
+            if let Some(file) = config.ca_file() {
+                let pem = std::fs::read(file)
+                    .with_context(|| format!("failed to read {}", file.display()))?;
+                http = http.add_root_certificate(reqwest::Certificate::from_pem(&pem)?);
+            }
+            Ok(Self {
+                http: http.build()?,
+            })
+        }
+
+        pub(super) fn self_signed(&self, domains: &[String]) -> Result<(String, String)> {
+            let mut params = rcgen::CertificateParams::new(domains.to_vec());
+            // already expired, for it to be replaced right away
+            params.not_before = rcgen::date_time_ymd(2000, 1, 1);
+            params.not_after = rcgen::date_time_ymd(2000, 1, 2);
+            let cert = rcgen::Certificate::from_params(params)?;
+            Ok((cert.serialize_pem()?, cert.serialize_private_key_pem()))
+        }
+
+        /// Orders a certificate for `domains`, returns it with its key.
+        pub(super) async fn order(
+            &self,
+            account: &Account,
+            config: &AcmeCertificateConfig,
+            domains: &[rr::Name],
+            challenges: &Arc<Challenges>,
+        ) -> Result<(String, String)> {
+            let response = self
+                .http
+                .get(config.directory())
+                .send()
+                .await?
+                .error_for_status()?;
+            let directory: Directory = json(response).await.context("invalid acme directory")?;
+            let mut session = Session {
+                http: &self.http,
+                account,
+                new_nonce: directory.new_nonce,
+                nonce: None,
+                kid: None,
+            };
+
+            let contact: Vec<String> = config
+                .email()
+                .map(|email| format!("mailto:{}", email))
+                .into_iter()
+                .collect();
+            let response = session
+                .post(
+                    &directory.new_account,
+                    Some(&json!({ "termsOfServiceAgreed": true, "contact": contact })),
+                )
+                .await?;
+            session.kid = Some(location(&response)?);
+
+            let identifiers: Vec<Value> = domains
+                .iter()
+                .map(|domain| json!({ "type": "dns", "value": domain.to_string().trim_end_matches('.') }))
+                .collect();
+            let response = session
+                .post(
+                    &directory.new_order,
+                    Some(&json!({ "identifiers": identifiers })),
+                )
+                .await?;
+            let order_url = location(&response)?;
+            let order: Order = json(response).await?;
+
+            let mut answered = Vec::new();
+            let result = async {
+                for url in &order.authorizations {
+                    let authorization: Authorization = session.get(url).await?;
+                    if authorization.status == "valid" {
+                        continue;
+                    }
+                    let challenge = authorization
+                        .challenges
+                        .iter()
+                        .find(|challenge| challenge.kind == "dns-01")
+                        .with_context(|| {
+                            format!("no dns-01 challenge for {}", authorization.identifier.value)
+                        })?;
+                    let name =
+                        Challenges::name(&rr::Name::from_str(&authorization.identifier.value)?)?;
+                    let value = account.challenge_value(&challenge.token)?;
+                    challenges.set(name.clone(), &value).await?;
+                    answered.push((name, value));
+                    session.post(&challenge.url, Some(&json!({}))).await?;
+                }
+                for url in &order.authorizations {
+                    let authorization: Authorization = session
+                        .poll(url, |a: &Authorization| a.status != "pending")
+                        .await?;
+                    if authorization.status != "valid" {
+                        let problem = authorization
+                            .challenges
+                            .iter()
+                            .find_map(|challenge| challenge.error.as_ref())
+                            .map_or(String::new(), |problem| format!(": {}", problem));
+                        bail!(
+                            "authorization of {} is {}{}",
+                            authorization.identifier.value,
+                            authorization.status,
+                            problem
+                        );
+                    }
+                }
+
+                let mut params = rcgen::CertificateParams::new(
+                    domains
+                        .iter()
+                        .map(|domain| domain.to_string().trim_end_matches('.').to_string())
+                        .collect::<Vec<_>>(),
+                );
+                params.distinguished_name = rcgen::DistinguishedName::new();
+                let request = rcgen::Certificate::from_params(params)?;
+                let csr = BASE64URL_NOPAD.encode(&request.serialize_request_der()?);
+                session
+                    .post(&order.finalize, Some(&json!({ "csr": csr })))
+                    .await?;
+                let order: Order = session
+                    .poll(&order_url, |o: &Order| {
+                        o.status != "pending" && o.status != "ready" && o.status != "processing"
+                    })
+                    .await?;
+                let certificate = match (order.status.as_str(), order.certificate) {
+                    ("valid", Some(certificate)) => certificate,
+                    (status, _) => {
+                        let problem = order.error.map_or(String::new(), |p| format!(": {}", p));
+                        bail!("order is {}{}", status, problem)
+                    }
+                };
+                let cert = session.post(&certificate, None).await?.text().await?;
+                Ok((cert, request.serialize_private_key_pem()))
+            }
+            .await;
+
+            for (name, value) in answered {
+                if let Err(e) = challenges.clear(name.clone(), &value).await {
+                    debug!("failed to clear acme challenge {}: {:#}", name, e);
+                }
+            }
+            result
+        }
+    }
+
+    /// The requests of an order, each signed with a nonce of the previous
+    /// response.
+    struct Session<'a> {
+        http: &'a reqwest::Client,
+        account: &'a Account,
+        new_nonce: String,
+        nonce: Option<String>,
+        kid: Option<String>,
+    }
+
+    impl Session<'_> {
+        async fn nonce(&mut self) -> Result<String> {
+            if let Some(nonce) = self.nonce.take() {
+                return Ok(nonce);
+            }
+            let response = self
+                .http
+                .head(&self.new_nonce)
+                .send()
+                .await?
+                .error_for_status()?;
+            replay_nonce(&response).context("no nonce in the newNonce response")
+        }
+
+        /// Sends a signed request to `url`, a POST-as-GET without `payload`.
+        async fn post(&mut self, url: &str, payload: Option<&Value>) -> Result<reqwest::Response> {
+            let mut attempts = 0;
+            loop {
+                let nonce = self.nonce().await?;
+                let body = self
+                    .account
+                    .sign(url, &nonce, self.kid.as_deref(), payload)?;
+                let response = self
+                    .http
+                    .post(url)
+                    .header(CONTENT_TYPE, "application/jose+json")
+                    .body(body.to_string())
+                    .send()
+                    .await?;
+                self.nonce = replay_nonce(&response);
+                let status = response.status();
+                if status.is_success() {
+                    return Ok(response);
+                }
+                let problem: Problem = json(response).await.unwrap_or_default();
+                // a nonce may be rejected, the response carries a new one
+                attempts += 1;
+                if problem.kind == "urn:ietf:params:acme:error:badNonce" && attempts < 3 {
+                    continue;
+                }
+                bail!("{} failed with {}: {}", url, status, problem);
+            }
+        }
+
+        async fn get<T: DeserializeOwned>(&mut self, url: &str) -> Result<T> {
+            json(self.post(url, None).await?).await
+        }
+
+        /// Gets `url` until `done` holds, waiting as long as the certificate
+        /// authority asks between attempts.
+        async fn poll<T: DeserializeOwned>(
+            &mut self,
+            url: &str,
+            done: impl Fn(&T) -> bool,
+        ) -> Result<T> {
+            for _ in 0..POLL_ATTEMPTS {
+                let response = self.post(url, None).await?;
+                let wait = response
+                    .headers()
+                    .get(RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok()?.parse().ok())
+                    .map_or(POLL_INTERVAL, |seconds: u64| {
+                        Duration::from_secs(seconds).min(POLL_INTERVAL * 5)
+                    });
+                let value: T = json(response).await?;
+                if done(&value) {
+                    return Ok(value);
+                }
+                tokio::time::sleep(wait).await;
+            }
+            bail!("{} is still pending", url)
+        }
+    }
+
+    async fn json<T: DeserializeOwned>(response: reqwest::Response) -> Result<T> {
+        Ok(serde_json::from_slice(&response.bytes().await?)?)
+    }
+
+    fn replay_nonce(response: &reqwest::Response) -> Option<String> {
+        let nonce = response.headers().get("replay-nonce")?;
+        Some(nonce.to_str().ok()?.to_string())
+    }
+
+    fn location(response: &reqwest::Response) -> Result<String> {
+        let location = response
+            .headers()
+            .get(LOCATION)
+            .context("no location in the response")?;
+        Ok(location.to_str()?.to_string())
+    }
+}
+
+#[cfg(not(feature = "acme"))]
+enum Client {}
+
+#[cfg(not(feature = "acme"))]
+impl Client {
+    fn new(_config: &AcmeCertificateConfig) -> Result<Self> {
+        bail!("ACME certificates require the `acme` feature")
+    }
+
+    fn self_signed(&self, _domains: &[String]) -> Result<(String, String)> {
+        match *self {}
+    }
+
+    async fn order(
+        &self,
+        _account: &Account,
+        _config: &AcmeCertificateConfig,
+        _domains: &[rr::Name],
+        _challenges: &Arc<Challenges>,
+    ) -> Result<(String, String)> {
+        match *self {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::rr::dnssec::{PublicKey, PublicKeyBuf};
+
+    #[test]
+    fn signs_requests() -> Result<()> {
+        let pkcs8 = KeyFormat::Pkcs8.generate_and_encode(Algorithm::ECDSAP256SHA256, None)?;
+        let account = Account::new(&pkcs8)?;
+
+        let payload = json!({ "identifiers": [{ "type": "dns", "value": "www.et.internal" }] });
+        let jws = account.sign(
+            "https://ca.et.internal/order",
+            "n0nce",
+            None,
+            Some(&payload),
+        )?;
+        let decode = |field: &str| BASE64URL_NOPAD.decode(jws[field].as_str().unwrap().as_bytes());
+        let protected: Value = serde_json::from_slice(&decode("protected")?)?;
+        assert_eq!(protected["alg"], "ES256");
+        assert_eq!(protected["nonce"], "n0nce");
+        assert_eq!(protected["url"], "https://ca.et.internal/order");
+        assert_eq!(protected["jwk"], account.jwk());
+        assert_eq!(
+            serde_json::from_slice::<Value>(&decode("payload")?)?,
+            payload
+        );
+
+        // the signature verifies with the public key of the JWK
+        let jwk = &protected["jwk"];
+        let mut public = BASE64URL_NOPAD.decode(jwk["x"].as_str().unwrap().as_bytes())?;
+        public.extend(BASE64URL_NOPAD.decode(jwk["y"].as_str().unwrap().as_bytes())?);
+        let input = format!(
+            "{}.{}",
+            jws["protected"].as_str().unwrap(),
+            jws["payload"].as_str().unwrap()
+        );
+        PublicKeyBuf::new(public).verify(
+            Algorithm::ECDSAP256SHA256,
+            input.as_bytes(),
+            &decode("signature")?,
+        )?;
+
+        let jws = account.sign(
+            "https://ca.et.internal/cert",
+            "n0nce",
+            Some("https://ca.et.internal/acct/1"),
+            None,
+        )?;
+        assert_eq!(jws["payload"], "");
+        let protected = BASE64URL_NOPAD.decode(jws["protected"].as_str().unwrap().as_bytes())?;
+        let protected: Value = serde_json::from_slice(&protected)?;
+        assert_eq!(protected["kid"], "https://ca.et.internal/acct/1");
+        assert!(protected.get("jwk").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn answers_challenges() -> Result<()> {
+        let pkcs8 = KeyFormat::Pkcs8.generate_and_encode(Algorithm::ECDSAP256SHA256, None)?;
+        let account = Account::new(&pkcs8)?;
+        let jwk = account.jwk();
+        let canonical = format!(
+            r#"{{"crv":"P-256","kty":"EC","x":{},"y":{}}}"#,
+            jwk["x"], jwk["y"]
+        );
+        let thumbprint =
+            BASE64URL_NOPAD.encode(DigestType::SHA256.hash(canonical.as_bytes())?.as_ref());
+        assert_eq!(account.thumbprint()?, thumbprint);
+
+        let authorization = format!("evaGxfADs6pSRb2LAv9IZf17Dt3juxGJ-PCt92wr-oA.{}", thumbprint);
+        assert_eq!(
+            account.challenge_value("evaGxfADs6pSRb2LAv9IZf17Dt3juxGJ-PCt92wr-oA")?,
+            BASE64URL_NOPAD.encode(DigestType::SHA256.hash(authorization.as_bytes())?.as_ref())
+        );
+        assert!(
+            Account::new(&KeyFormat::Pkcs8.generate_and_encode(Algorithm::ED25519, None)?).is_err()
+        );
+        Ok(())
+    }
+
+    /// Answers the requests of an order for www.et.internal, the challenge
+    /// being valid if its TXT record is in `zones` when it is responded to.
+    #[cfg(feature = "acme")]
+    async fn serve_ca(
+        listener: tokio::net::TcpListener,
+        zones: Arc<crate::zones::ZoneSet>,
+        cert: String,
+        validated: Arc<std::sync::Mutex<Option<String>>>,
+    ) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let mut finalized = false;
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 4096];
+            let (head, body) = loop {
+                let n = stream.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..n]);
+                let text = String::from_utf8_lossy(&request).into_owned();
+                let Some((head, body)) = text.split_once("\r\n\r\n") else {
+                    continue;
+                };
+                let length = head
+                    .lines()
+                    .find_map(|line| {
+                        line.to_lowercase()
+                            .strip_prefix("content-length: ")?
+                            .parse()
+                            .ok()
+                    })
+                    .unwrap_or(0);
+                if body.len() >= length {
+                    break (head.to_string(), body.to_string());
+                }
+            };
+            let path = head.split(' ').nth(1).unwrap().to_string();
+            let payload = match serde_json::from_str::<Value>(&body) {
+                Ok(jws) => BASE64URL_NOPAD
+                    .decode(jws["payload"].as_str().unwrap().as_bytes())
+                    .unwrap(),
+                Err(_) => Vec::new(),
+            };
+            let (status, location, body) = match path.as_str() {
+                "/directory" => ("200 OK", "", json!({
+                    "newNonce": format!("{}/nonce", base),
+                    "newAccount": format!("{}/account", base),
+                    "newOrder": format!("{}/order", base),
+                }).to_string()),
+                "/nonce" => ("200 OK", "", String::new()),
+                "/account" => ("201 Created", "/account/1", "{}".to_string()),
+                "/order" | "/order/1" => {
+                    let status = match (finalized, path.as_str()) {
+                        (true, "/order/1") => "valid",
+                        (true, _) => "processing",
+                        (false, _) => "pending",
+                    };
+                    ("201 Created", "/order/1", json!({
+                        "status": status,
+                        "authorizations": [format!("{}/authz/1", base)],
+                        "finalize": format!("{}/finalize", base),
+                        "certificate": format!("{}/cert", base),
+                    }).to_string())
+                }
+                "/authz/1" => ("200 OK", "", json!({
+                    "status": match validated.lock().unwrap().is_some() {
+                        true => "valid",
+                        false => "pending",
+                    },
+                    "identifier": { "type": "dns", "value": "www.et.internal" },
+                    "challenges": [
+                        { "type": "http-01", "url": format!("{}/challenge/0", base), "token": "http" },
+                        { "type": "dns-01", "url": format!("{}/challenge/1", base), "token": "DGyRejmCefe7v4NfDGDKfA" },
+                    ],
+                }).to_string()),
+                "/challenge/1" => {
+                    *validated.lock().unwrap() = challenge_value(&zones).await;
+                    ("200 OK", "", "{}".to_string())
+                }
+                "/finalize" => {
+                    let finalize: Value = serde_json::from_slice(&payload).unwrap();
+                    finalized = finalize["csr"].as_str().is_some_and(|csr| !csr.is_empty());
+                    ("200 OK", "", "{}".to_string())
+                }
+                "/cert" => ("200 OK", "", cert.clone()),
+                _ => ("404 Not Found", "", "{}".to_string()),
+            };
+            let location = match location {
+                "" => String::new(),
+                location => format!("location: {}{}\r\n", base, location),
+            };
+            let response = format!(
+                "HTTP/1.1 {}\r\nreplay-nonce: {}\r\n{}content-length: {}\r\nconnection: close\r\n\r\n{}",
+                status,
+                rand::random::<u64>(),
+                location,
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+        }
+    }
+
+    #[cfg(feature = "acme")]
+    async fn challenge_value(zones: &crate::zones::ZoneSet) -> Option<String> {
+        let name = rr::Name::from_str("_acme-challenge.www.et.internal.").unwrap();
+        let records = zones.find(&name)?.records().await;
+        let value = records
+            .values()
+            .filter(|rrset| rrset.name() == &name)
+            .flat_map(|rrset| rrset.records_without_rrsigs())
+            .find_map(|record| match record.data() {
+                Some(rr::RData::TXT(txt)) => {
+                    Some(String::from_utf8_lossy(&txt.txt_data()[0]).into_owned())
+                }
+                _ => None,
+            });
+        value
+    }
+
+    #[cfg(feature = "acme")]
+    #[tokio::test]
+    async fn obtains_certificates() -> Result<()> {
+        use crate::config::{AcmeCertificateConfigBuilder, AcmeConfig, RecordBuilder, RecordData};
+        use crate::tsig::Keyring;
+        use crate::zones::ZoneSet;
+        use maplit::hashmap;
+        use std::net::Ipv4Addr;
+        use std::sync::Mutex;
+
+        let record = RecordBuilder::default()
+            .name("www".to_string())
+            .data(RecordData::A(Ipv4Addr::new(10, 0, 0, 1)))
+            .build()?;
+        let zones = hashmap! {
+            "et.internal".to_string() => vec![record].into(),
+        };
+        let zones = Arc::new(ZoneSet::new(&zones, Keyring::new(&[])?)?);
+        let challenges = Arc::new(Challenges::new(zones.clone(), &AcmeConfig::default()));
+
+        let mut params = rcgen::CertificateParams::new(vec!["www.et.internal".to_string()]);
+        params.not_after = rcgen::date_time_ymd(2051, 1, 1);
+        let issued = rcgen::Certificate::from_params(params)?.serialize_pem()?;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let directory = format!("http://{}/directory", listener.local_addr()?);
+        let validated = Arc::new(Mutex::new(None));
+        tokio::spawn(serve_ca(
+            listener,
+            zones.clone(),
+            issued.clone(),
+            validated.clone(),
+        ));
+
+        let dir = tempfile::tempdir()?;
+        let config = AcmeCertificateConfigBuilder::default()
+            .directory(directory)
+            .domains(vec!["www.et.internal".to_string()])
+            .cert_dir(dir.path().join("certs"))
+            .build()?;
+        let certificate = AcmeCertificate::new(&config, challenges)?;
+        // an expired placeholder until the first certificate is obtained
+        certificate.prepare()?;
+        crate::tls::load_cert_and_key(&config.cert_path(), &config.key_path())?;
+        assert!(certificate.renewal_wait().is_zero());
+
+        certificate.issue().await?;
+        assert_eq!(std::fs::read_to_string(config.cert_path())?, issued);
+        assert!(certificate.renewal_wait() > Duration::from_secs(86400));
+        let account = Account::new(&std::fs::read(config.account_key_path())?)?;
+        assert_eq!(
+            *validated.lock().unwrap(),
+            Some(account.challenge_value("DGyRejmCefe7v4NfDGDKfA")?)
+        );
+        // the challenge is cleared once the certificate is issued
+        assert_eq!(challenge_value(&zones).await, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,674 @@
+use crate::acme::Challenges;
+use crate::auth::{secret_eq, BearerToken};
+use crate::cache::Flush;
+use crate::config::AcmeDnsConfig;
+use crate::dns::RecordError;
+use crate::edit::{self, EditError, EditResult};
+use crate::forward::Forwarder;
+use crate::zones::ZoneSet;
+use anyhow::anyhow;
+use hickory_proto::rr;
+use hickory_proto::rr::LowerName;
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::{Bytes, Incoming};
+use hyper::header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use hyper::{Method, StatusCode};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info};
+
+const MAX_BODY_SIZE: usize = 1 << 20;
+
+/// What the admin API works on, shared by its connections.
+struct Admin {
+    zones: Arc<ZoneSet>,
+    forwarder: Option<Arc<Forwarder>>,
+    acme: Arc<Challenges>,
+    acme_dns: Option<AcmeDnsConfig>,
+}
+
+/// Serves the admin API until `token` is cancelled, and the acme-dns API
+/// under `/acme-dns` when `acme_dns` is set.
+pub(crate) async fn serve(
+    listener: TcpListener,
+    zones: Arc<ZoneSet>,
+    forwarder: Option<Arc<Forwarder>>,
+    auth_token: Option<String>,
+    acme: Arc<Challenges>,
+    acme_dns: Option<AcmeDnsConfig>,
+    token: CancellationToken,
+) {
+    let auth = auth_token.map(|t| Arc::new(BearerToken::new(&t)));
+    let admin = Arc::new(Admin {
+        zones,
+        forwarder,
+        acme,
+        acme_dns,
+    });
+    loop {
+        let (stream, src) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    debug!("error accepting admin connection: {}", e);
+                    continue;
+                }
+            },
+            _ = token.cancelled() => break,
+        };
+
+        let admin = admin.clone();
+        let auth = auth.clone();
+        let token = token.clone();
+        tokio::spawn(async move {
+            let service = hyper::service::service_fn(move |request| {
+                let admin = admin.clone();
+                let auth = auth.clone();
+                async move { Ok::<_, Infallible>(handle(request, &admin, auth.as_deref()).await) }
+            });
+            let builder = auto::Builder::new(TokioExecutor::new());
+            let connection = builder.serve_connection(TokioIo::new(stream), service);
+            tokio::select! {
+                result = connection => if let Err(e) = result {
+                    debug!("admin connection with {} failed: {}", src, e);
+                },
+                _ = token.cancelled() => {},
+            }
+        });
+    }
+}
+
+async fn handle(
+    request: hyper::Request<Incoming>,
+    admin: &Admin,
+    auth: Option<&BearerToken>,
+) -> hyper::Response<Full<Bytes>> {
+    if request.uri().path().starts_with("/acme-dns/") {
+        // its accounts authenticate themselves
+        return match acme_dns(request, admin).await {
+            Ok(response) => response,
+            Err(EditError::NotFound(message)) => error(StatusCode::NOT_FOUND, message),
+            Err(EditError::BadRequest(e)) => error(StatusCode::BAD_REQUEST, format!("{:#}", e)),
+        };
+    }
+    if let Some(auth) = auth {
+        if !auth.matches(request.headers().get(AUTHORIZATION).map(|v| v.as_bytes())) {
+            return status(StatusCode::UNAUTHORIZED);
+        }
+    }
+    match route(request, admin).await {
+        Ok(response) => response,
+        Err(EditError::NotFound(message)) => error(StatusCode::NOT_FOUND, message),
+        Err(EditError::BadRequest(e)) => error(StatusCode::BAD_REQUEST, format!("{:#}", e)),
+    }
+}
+
+async fn route(
+    request: hyper::Request<Incoming>,
+    admin: &Admin,
+) -> EditResult<hyper::Response<Full<Bytes>>> {
+    let zones = &*admin.zones;
+    let path = request.uri().path().to_string();
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let method = request.method().clone();
+    let response = match (&method, segments.as_slice()) {
+        (&Method::GET, ["zones"]) => json(&edit::zone_names(zones).await)?,
+        (&Method::GET, ["zones", zone]) => json(&edit::zone(zones, zone).await?)?,
+        (&Method::PUT, ["zones", zone]) => {
+            edit::put_zone(zones, zone, body(request).await?).await?;
+            status(StatusCode::NO_CONTENT)
+        }
+        (&Method::DELETE, ["zones", zone]) => {
+            edit::remove_zone(zones, zone).await?;
+            status(StatusCode::NO_CONTENT)
+        }
+        (&Method::GET, ["zones", zone, "export"]) => {
+            let name = LowerName::from_str(zone)
+                .map_err(|e| anyhow!("invalid zone name {}: {}", zone, e))?;
+            let text = zones
+                .export(&name)
+                .await
+                .ok_or_else(|| EditError::NotFound(format!("no such zone: {}", zone)))?;
+            let mut response = hyper::Response::new(Full::new(Bytes::from(text)));
+            response
+                .headers_mut()
+                .insert(CONTENT_TYPE, HeaderValue::from_static("text/dns"));
+            response
+        }
+        (&Method::GET, ["zones", zone, "records"]) => {
+            json(edit::zone(zones, zone).await?.records())?
+        }
+        (&Method::POST, ["zones", zone, "records"]) => {
+            edit::add_record(zones, zone, body(request).await?).await?;
+            status(StatusCode::NO_CONTENT)
+        }
+        (&Method::PUT, ["zones", zone, "records"]) => {
+            edit::replace_record(zones, zone, body(request).await?).await?;
+            status(StatusCode::NO_CONTENT)
+        }
+        (&Method::DELETE, ["zones", zone, "records"]) => {
+            edit::remove_record(zones, zone, body(request).await?).await?;
+            status(StatusCode::NO_CONTENT)
+        }
+        (&Method::POST, ["zones", zone, "batch"]) => {
+            edit::apply_batch(zones, zone, body(request).await?).await?;
+            status(StatusCode::NO_CONTENT)
+        }
+        (&Method::POST, ["reload"]) => {
+            zones.restore().await?;
+            status(StatusCode::NO_CONTENT)
+        }
+        (&Method::POST, ["cache", "flush"]) => {
+            let request: FlushRequest = body(request).await?;
+            let flush = Flush::new(&request.name, &request.query_type, request.subtree)?;
+            let forwarder = admin.forwarder.as_ref();
+            let flushed = forwarder.map_or(0, |f| f.flush_cache(&flush));
+            json(&serde_json::json!({ "flushed": flushed }))?
+        }
+        (&Method::POST, ["acme", "challenges"]) => {
+            let challenge: AcmeChallenge = body(request).await?;
+            let name = Challenges::name(&challenge.domain()?)?;
+            admin
+                .acme
+                .set(name, &challenge.token)
+                .await
+                .map_err(acme_error)?;
+            status(StatusCode::NO_CONTENT)
+        }
+        (&Method::DELETE, ["acme", "challenges"]) => {
+            let challenge: AcmeChallenge = body(request).await?;
+            let name = Challenges::name(&challenge.domain()?)?;
+            if !admin
+                .acme
+                .clear(name, &challenge.token)
+                .await
+                .map_err(acme_error)?
+            {
+                return Err(EditError::NotFound(format!(
+                    "no such challenge of {}",
+                    challenge.domain
+                )));
+            }
+            status(StatusCode::NO_CONTENT)
+        }
+        (
+            _,
+            ["zones"]
+            | ["zones", _]
+            | ["zones", _, "records" | "export" | "batch"]
+            | ["reload"]
+            | ["cache", "flush"]
+            | ["acme", "challenges"],
+        ) => status(StatusCode::METHOD_NOT_ALLOWED),
+        _ => return Err(EditError::NotFound(format!("no such endpoint: {}", path))),
+    };
+    if method != Method::GET && response.status().is_success() {
+        info!("admin: {} {}", method, path);
+    }
+    Ok(response)
+}
+
+/// Cached answers to drop, see [`Flush::new`]: every answer by default.
+#[derive(Deserialize)]
+struct FlushRequest {
+    #[serde(default)]
+    name: String,
+    #[serde(default, rename = "type")]
+    query_type: String,
+    #[serde(default)]
+    subtree: bool,
+}
+
+/// An ACME DNS-01 challenge of `domain`, answered with `token`.
+#[derive(Deserialize)]
+struct AcmeChallenge {
+    domain: String,
+    token: String,
+}
+
+impl AcmeChallenge {
+    fn domain(&self) -> EditResult<rr::Name> {
+        let mut domain = rr::Name::from_str(&self.domain)
+            .map_err(|e| anyhow!("invalid domain {}: {}", self.domain, e))?;
+        domain.set_fqdn(true);
+        Ok(domain)
+    }
+}
+
+fn acme_error(e: anyhow::Error) -> EditError {
+    match e.downcast_ref::<RecordError>() {
+        Some(RecordError::ZoneNotFound(name)) => {
+            EditError::NotFound(format!("no zone found for {}", name))
+        }
+        _ => EditError::BadRequest(e),
+    }
+}
+
+/// The update of an acme-dns client, which sets the challenge of its
+/// account's subdomain.
+#[derive(Deserialize)]
+struct AcmeDnsUpdate {
+    subdomain: String,
+    txt: String,
+}
+
+/// Serves the `update` endpoint of the acme-dns API, so that the acme-dns
+/// hooks of ACME clients such as certbot and lego work with the server.
+/// Accounts are configured rather than registered.
+async fn acme_dns(
+    request: hyper::Request<Incoming>,
+    admin: &Admin,
+) -> EditResult<hyper::Response<Full<Bytes>>> {
+    let Some(config) = &admin.acme_dns else {
+        return Err(EditError::NotFound(
+            "the acme-dns API isn't enabled".to_string(),
+        ));
+    };
+    let path = request.uri().path().to_string();
+    match (request.method(), path.as_str()) {
+        (&Method::POST, "/acme-dns/update") => {}
+        (&Method::POST, "/acme-dns/register") => {
+            return Ok(error(
+                StatusCode::FORBIDDEN,
+                "accounts are configured in general.acme.acme_dns".to_string(),
+            ))
+        }
+        (_, "/acme-dns/update" | "/acme-dns/register") => {
+            return Ok(status(StatusCode::METHOD_NOT_ALLOWED))
+        }
+        _ => return Err(EditError::NotFound(format!("no such endpoint: {}", path))),
+    }
+    let header = |name: &str| {
+        request
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+    let (user, key) = (header("X-Api-User"), header("X-Api-Key"));
+    let Some(account) = config.accounts().iter().find(|account| {
+        Some(account.username()) == user.as_deref()
+            && key
+                .as_deref()
+                .is_some_and(|key| secret_eq(key.as_bytes(), account.password().as_bytes()))
+    }) else {
+        return Ok(error(StatusCode::UNAUTHORIZED, "forbidden".to_string()));
+    };
+    let update: AcmeDnsUpdate = body(request).await?;
+    if update.subdomain != account.subdomain() {
+        return Ok(error(StatusCode::UNAUTHORIZED, "forbidden".to_string()));
+    }
+    let name = rr::Name::from_str(&format!(
+        "{}.{}.",
+        account.subdomain(),
+        config.domain().trim_end_matches('.')
+    ))
+    .map_err(|e| anyhow!("invalid acme-dns domain: {}", e))?;
+    admin
+        .acme
+        .set(name, &update.txt)
+        .await
+        .map_err(acme_error)?;
+    info!("acme-dns: updated {}", account.subdomain());
+    json(&serde_json::json!({ "txt": update.txt }))
+}
+
+async fn body<T: DeserializeOwned>(request: hyper::Request<Incoming>) -> EditResult<T> {
+    let bytes = Limited::new(request.into_body(), MAX_BODY_SIZE)
+        .collect()
+        .await
+        .map_err(|e| anyhow!("failed to read request body: {}", e))?
+        .to_bytes();
+    let value = serde_json::from_slice(&bytes).map_err(|e| anyhow!("invalid body: {}", e))?;
+    Ok(value)
+}
+
+fn json<T: Serialize + ?Sized>(value: &T) -> EditResult<hyper::Response<Full<Bytes>>> {
+    let bytes = serde_json::to_vec(value).map_err(anyhow::Error::from)?;
+    let mut response = hyper::Response::new(Full::new(Bytes::from(bytes)));
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    Ok(response)
+}
+
+fn error(code: StatusCode, message: String) -> hyper::Response<Full<Bytes>> {
+    let mut response = json(&serde_json::json!({ "error": message }))
+        .unwrap_or_else(|_| status(StatusCode::INTERNAL_SERVER_ERROR));
+    *response.status_mut() = code;
+    response
+}
+
+fn status(code: StatusCode) -> hyper::Response<Full<Bytes>> {
+    let mut response = hyper::Response::new(Full::default());
+    *response.status_mut() = code;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{
+        AcmeConfigBuilder, AcmeDnsAccountBuilder, AcmeDnsConfigBuilder, AdminListenConfigBuilder,
+        GeneralConfigBuilder, RecordBuilder, RecordData, RecordType, RunConfigBuilder,
+    };
+    use crate::Server;
+    use anyhow::Result;
+    use hickory_client::client::{AsyncClient, ClientHandle};
+    use hickory_proto::rr;
+    use hickory_proto::udp::UdpClientStream;
+    use maplit::hashmap;
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpStream, UdpSocket};
+
+    async fn start() -> Result<Server> {
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .listen_admin(
+                        AdminListenConfigBuilder::default()
+                            .address("127.0.0.1:0")
+                            .token("secret")
+                            .build()?,
+                    )
+                    .acme(
+                        AcmeConfigBuilder::default()
+                            .acme_dns(
+                                AcmeDnsConfigBuilder::default()
+                                    .domain("auth.et.internal")
+                                    .accounts(vec![AcmeDnsAccountBuilder::default()
+                                        .username("lego")
+                                        .password("hunter2")
+                                        .subdomain("d420c923")
+                                        .build()?])
+                                    .build()?,
+                            )
+                            .build()?,
+                    )
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.internal".to_string() => vec![
+                    RecordBuilder::default()
+                        .name("www.et.internal".to_string())
+                        .data(RecordData::A(Ipv4Addr::new(123, 123, 123, 123)))
+                        .ttl(Duration::from_secs(60))
+                        .build()?,
+                ].into(),
+            })
+            .build()?;
+        let mut server = Server::new(config);
+        server.run().await?;
+        Ok(server)
+    }
+
+    /// Sends a raw HTTP/1.1 request, returning the status line and headers, and the body.
+    async fn request(server: &mut Server, head: &str, body: &str) -> Result<(String, String)> {
+        let mut stream = TcpStream::connect(server.admin_local_addr().unwrap()).await?;
+        stream
+            .write_all(
+                format!(
+                    "{head}Host: localhost\r\nAuthorization: Bearer secret\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                )
+                .as_bytes(),
+            )
+            .await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        Ok((head.to_string(), body.to_string()))
+    }
+
+    async fn resolve(addr: SocketAddr, name: &str) -> Result<Vec<rr::Record>> {
+        let stream = UdpClientStream::<UdpSocket>::new(addr);
+        let (mut client, background) = AsyncClient::connect(stream).await?;
+        tokio::spawn(background);
+        let response = client
+            .query(rr::Name::from_str(name)?, rr::DNSClass::IN, RecordType::A)
+            .await?;
+        Ok(response.answers().to_vec())
+    }
+
+    #[tokio::test]
+    async fn manages_records_at_runtime() -> Result<()> {
+        let mut server = start().await?;
+        let addr = server.udp_local_addr().unwrap();
+
+        let (head, body) = request(&mut server, "GET /zones HTTP/1.1\r\n", "").await?;
+        assert!(head.starts_with("HTTP/1.1 200"));
+        assert_eq!(body, r#"["et.internal"]"#);
+
+        let record = r#"{"type":"A","name":"api.et.internal","value":"10.0.0.1","ttl":"60s"}"#;
+        let (head, _) = request(
+            &mut server,
+            "POST /zones/et.internal/records HTTP/1.1\r\n",
+            record,
+        )
+        .await?;
+        assert!(head.starts_with("HTTP/1.1 204"));
+        assert_eq!(resolve(addr, "api.et.internal").await?.len(), 1);
+        let (head, body) = request(
+            &mut server,
+            "GET /zones/et.internal/export HTTP/1.1\r\n",
+            "",
+        )
+        .await?;
+        assert!(head.contains("content-type: text/dns"));
+        assert!(body.contains("api.et.internal.\t60\tIN\tA\t10.0.0.1"));
+
+        let record = r#"{"type":"A","name":"api.et.internal","value":"10.0.0.2","ttl":"60s"}"#;
+        let (head, _) = request(
+            &mut server,
+            "PUT /zones/et.internal/records HTTP/1.1\r\n",
+            record,
+        )
+        .await?;
+        assert!(head.starts_with("HTTP/1.1 204"));
+        let answers = resolve(addr, "api.et.internal").await?;
+        assert_eq!(answers.len(), 1);
+        assert_eq!(answers[0].data().unwrap().to_string(), "10.0.0.2");
+
+        let (head, _) = request(
+            &mut server,
+            "DELETE /zones/et.internal/records HTTP/1.1\r\n",
+            record,
+        )
+        .await?;
+        assert!(head.starts_with("HTTP/1.1 204"));
+        assert!(resolve(addr, "api.et.internal").await?.is_empty());
+
+        let (head, _) = request(&mut server, "POST /reload HTTP/1.1\r\n", "").await?;
+        assert!(head.starts_with("HTTP/1.1 204"));
+        assert_eq!(resolve(addr, "www.et.internal").await?.len(), 1);
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn answers_acme_challenges() -> Result<()> {
+        let mut server = start().await?;
+        let addr = server.udp_local_addr().unwrap();
+        let txt = |name: &str| {
+            let name = name.to_string();
+            async move {
+                let response = crate::testing::query(addr, &name, RecordType::TXT).await?;
+                Ok::<_, anyhow::Error>(
+                    response
+                        .answers()
+                        .iter()
+                        .map(|answer| answer.data().unwrap().to_string())
+                        .collect::<Vec<_>>(),
+                )
+            }
+        };
+
+        let challenge = r#"{"domain":"*.www.et.internal","token":"LoqXcYV8q5ONbJQxbmR7SCTNo3tiAXDfowyjxAjEuX0"}"#;
+        let (head, _) =
+            request(&mut server, "POST /acme/challenges HTTP/1.1\r\n", challenge).await?;
+        assert!(head.starts_with("HTTP/1.1 204"), "{}", head);
+        assert_eq!(
+            txt("_acme-challenge.www.et.internal.").await?,
+            ["LoqXcYV8q5ONbJQxbmR7SCTNo3tiAXDfowyjxAjEuX0"]
+        );
+        let (head, _) = request(
+            &mut server,
+            "DELETE /acme/challenges HTTP/1.1\r\n",
+            challenge,
+        )
+        .await?;
+        assert!(head.starts_with("HTTP/1.1 204"), "{}", head);
+        assert!(txt("_acme-challenge.www.et.internal.").await?.is_empty());
+        let (head, _) = request(
+            &mut server,
+            "DELETE /acme/challenges HTTP/1.1\r\n",
+            challenge,
+        )
+        .await?;
+        assert!(head.starts_with("HTTP/1.1 404"), "{}", head);
+        let (head, _) = request(
+            &mut server,
+            "POST /acme/challenges HTTP/1.1\r\n",
+            r#"{"domain":"et.example","token":"abc"}"#,
+        )
+        .await?;
+        assert!(head.starts_with("HTTP/1.1 404"), "{}", head);
+
+        // as acme-dns clients update their subdomain
+        let update =
+            r#"{"subdomain":"d420c923","txt":"___validation_token_received_from_the_ca___"}"#;
+        let (head, body) = request(
+            &mut server,
+            "POST /acme-dns/update HTTP/1.1\r\nX-Api-User: lego\r\nX-Api-Key: hunter2\r\n",
+            update,
+        )
+        .await?;
+        assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+        assert_eq!(
+            body,
+            r#"{"txt":"___validation_token_received_from_the_ca___"}"#
+        );
+        assert_eq!(
+            txt("d420c923.auth.et.internal.").await?,
+            ["___validation_token_received_from_the_ca___"]
+        );
+        let (head, _) = request(
+            &mut server,
+            "POST /acme-dns/update HTTP/1.1\r\nX-Api-User: lego\r\nX-Api-Key: wrong\r\n",
+            update,
+        )
+        .await?;
+        assert!(head.starts_with("HTTP/1.1 401"), "{}", head);
+        let (head, _) = request(&mut server, "POST /acme-dns/register HTTP/1.1\r\n", "").await?;
+        assert!(head.starts_with("HTTP/1.1 403"), "{}", head);
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn applies_record_batches() -> Result<()> {
+        let mut server = start().await?;
+        let addr = server.udp_local_addr().unwrap();
+
+        let batch = r#"[
+            {"op":"add","record":{"type":"A","name":"api.et.internal","value":"10.0.0.1","ttl":"60s"}},
+            {"op":"replace","record":{"type":"A","name":"www.et.internal","value":"10.0.0.2","ttl":"60s"}}
+        ]"#;
+        let (head, _) = request(
+            &mut server,
+            "POST /zones/et.internal/batch HTTP/1.1\r\n",
+            batch,
+        )
+        .await?;
+        assert!(head.starts_with("HTTP/1.1 204"));
+        assert_eq!(resolve(addr, "api.et.internal").await?.len(), 1);
+        let answers = resolve(addr, "www.et.internal").await?;
+        assert_eq!(answers[0].data().unwrap().to_string(), "10.0.0.2");
+
+        // the missing record fails the batch before anything is applied
+        let batch = r#"[
+            {"op":"remove","record":{"type":"A","name":"api.et.internal","value":"10.0.0.1","ttl":"60s"}},
+            {"op":"remove","record":{"type":"A","name":"db.et.internal","value":"10.0.0.3","ttl":"60s"}}
+        ]"#;
+        let (head, _) = request(
+            &mut server,
+            "POST /zones/et.internal/batch HTTP/1.1\r\n",
+            batch,
+        )
+        .await?;
+        assert!(head.starts_with("HTTP/1.1 404"));
+        assert_eq!(resolve(addr, "api.et.internal").await?.len(), 1);
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn manages_zones_at_runtime() -> Result<()> {
+        let mut server = start().await?;
+        let addr = server.udp_local_addr().unwrap();
+
+        let zone = r#"[{"type":"A","name":"www.et.top","value":"10.0.0.3","ttl":"60s"}]"#;
+        let (head, _) = request(&mut server, "PUT /zones/et.top HTTP/1.1\r\n", zone).await?;
+        assert!(head.starts_with("HTTP/1.1 204"));
+        assert_eq!(resolve(addr, "www.et.top").await?.len(), 1);
+
+        let (head, _) = request(&mut server, "DELETE /zones/et.top HTTP/1.1\r\n", "").await?;
+        assert!(head.starts_with("HTTP/1.1 204"));
+        assert!(!server.contains(&rr::LowerName::from_str("et.top")?).await);
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_requests() -> Result<()> {
+        let mut server = start().await?;
+
+        let (head, _) = request(&mut server, "GET /zones/et.top HTTP/1.1\r\n", "").await?;
+        assert!(head.starts_with("HTTP/1.1 404"));
+
+        let record = r#"{"type":"A","name":"api.et.internal","value":"nope","ttl":"60s"}"#;
+        let (head, body) = request(
+            &mut server,
+            "POST /zones/et.internal/records HTTP/1.1\r\n",
+            record,
+        )
+        .await?;
+        assert!(head.starts_with("HTTP/1.1 400"));
+        assert!(body.contains("error"));
+
+        let (head, _) = request(&mut server, "PATCH /zones HTTP/1.1\r\n", "").await?;
+        assert!(head.starts_with("HTTP/1.1 405"));
+
+        let (head, body) = request(&mut server, "POST /cache/flush HTTP/1.1\r\n", "{}").await?;
+        assert!(head.starts_with("HTTP/1.1 200"));
+        assert_eq!(body, r#"{"flushed":0}"#);
+        let flush = r#"{"name":"www.et.internal","type":"A","subtree":true}"#;
+        let (head, _) = request(&mut server, "POST /cache/flush HTTP/1.1\r\n", flush).await?;
+        assert!(head.starts_with("HTTP/1.1 400"));
+
+        let mut stream = TcpStream::connect(server.admin_local_addr().unwrap()).await?;
+        stream
+            .write_all(b"GET /zones HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        assert!(response.starts_with("HTTP/1.1 401"));
+
+        server.shutdown().await?;
+        Ok(())
+    }
+}
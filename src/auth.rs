@@ -0,0 +1,40 @@
+//! Credentials of the admin and gRPC APIs, compared in constant time so that the
+//! time taken to refuse a request tells nothing of the expected one.
+
+use subtle::ConstantTimeEq;
+
+/// The `Authorization` header carrying a bearer token.
+pub(crate) struct BearerToken(String);
+
+impl BearerToken {
+    pub(crate) fn new(token: &str) -> Self {
+        Self(format!("Bearer {}", token))
+    }
+
+    /// Whether `header`, the `Authorization` header of a request if any,
+    /// carries the token.
+    pub(crate) fn matches(&self, header: Option<&[u8]>) -> bool {
+        header.is_some_and(|header| secret_eq(header, self.0.as_bytes()))
+    }
+}
+
+/// Whether `given` is `expected`, in a time that only depends on their
+/// lengths.
+pub(crate) fn secret_eq(given: &[u8], expected: &[u8]) -> bool {
+    given.ct_eq(expected).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_bearer_tokens() {
+        let token = BearerToken::new("secret");
+        assert!(token.matches(Some(b"Bearer secret")));
+        assert!(!token.matches(Some(b"Bearer secreT")));
+        assert!(!token.matches(Some(b"Bearer secret2")));
+        assert!(!token.matches(Some(b"secret")));
+        assert!(!token.matches(None));
+    }
+}
@@ -0,0 +1,131 @@
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand};
+use hickory_proto::rr::Name;
+use libdns::{RunConfig, Server};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::str::FromStr;
+use tracing::info;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+
+/// Authoritative and forwarding DNS server.
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Serves the config until interrupted, reloading its zones on SIGHUP.
+    Run {
+        #[arg(short, long)]
+        config: PathBuf,
+    },
+    /// Checks that the config loads and its zones build, without binding
+    /// any listener, and reports the problems of its zones.
+    Check {
+        #[arg(short, long)]
+        config: PathBuf,
+    },
+    /// Prints a zone of the config as an RFC 1035 zone file.
+    ExportZone {
+        #[arg(short, long)]
+        config: PathBuf,
+        name: String,
+    },
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    match execute(Cli::parse().command).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {:#}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn execute(command: Command) -> Result<()> {
+    match command {
+        Command::Run { config } => run(&config).await,
+        Command::Check { config: path } => {
+            let config = RunConfig::from_path(&path)?;
+            let diagnostics = config.validate();
+            for diagnostic in &diagnostics {
+                println!("{}: {}", path.display(), diagnostic);
+            }
+            drop(Server::try_new(config)?);
+            if !diagnostics.is_empty() {
+                bail!("{} problems found", diagnostics.len());
+            }
+            println!("{}: ok", path.display());
+            Ok(())
+        }
+        Command::ExportZone { config, name } => {
+            let server = load(&config)?;
+            let name = Name::from_str(&name).with_context(|| format!("invalid zone {}", name))?;
+            print!("{}", server.export_zone(&name).await?);
+            Ok(())
+        }
+    }
+}
+
+fn load(path: &Path) -> Result<Server> {
+    let config = RunConfig::from_path(path)?;
+    Ok(Server::try_new(config)?)
+}
+
+async fn run(path: &Path) -> Result<()> {
+    let config = RunConfig::from_path(path)?;
+    #[cfg(feature = "otel")]
+    let exporter = match config.general().otlp() {
+        Some(otlp) => Some(libdns::telemetry::OtlpExporter::new(otlp)?),
+        None => None,
+    };
+    // the level `tracing_subscriber::fmt::init` defaults to
+    let registry = tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().with_filter(LevelFilter::INFO));
+    #[cfg(feature = "otel")]
+    let registry = registry.with(exporter.as_ref().map(|exporter| exporter.layer()));
+    registry.init();
+
+    let mut server = Server::try_new(config)?;
+    server.run().await?;
+    server.watch(path);
+    info!("serving {}", path.display());
+    tokio::select! {
+        result = server.block_until_done() => result?,
+        result = shutdown_signal() => {
+            result?;
+            info!("shutting down");
+            server.handle().shutdown().await?;
+        }
+    }
+    #[cfg(feature = "otel")]
+    if let Some(exporter) = exporter {
+        tokio::task::spawn_blocking(move || exporter.shutdown()).await??;
+    }
+    Ok(())
+}
+
+/// Waits for SIGINT or, on Unix, SIGTERM.
+async fn shutdown_signal() -> Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result?,
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
@@ -0,0 +1,92 @@
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+/// Controls a running dns-server through its control socket.
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    /// Path of the control socket, `general.control_socket` of the config.
+    #[arg(short, long, default_value = "/run/dns-server/control.sock")]
+    socket: PathBuf,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Reloads the config file, or the zones without one.
+    Reload,
+    /// Drops the cached answers, only of NAME and the names below it if given.
+    Flush { name: Option<String> },
+    /// Drops the cached answers of NAME alone, only those of TYPE if given.
+    Flushname {
+        name: String,
+        #[arg(value_name = "TYPE")]
+        query_type: Option<String>,
+    },
+    /// Prints the counters of the server.
+    Status,
+    /// Adds a zone, configured by CONFIG as JSON, or empty.
+    Addzone {
+        zone: String,
+        config: Option<String>,
+    },
+    /// Removes a zone.
+    Delzone { zone: String },
+    /// Sends a NOTIFY to the secondaries of a zone.
+    Notify { zone: String },
+}
+
+impl Command {
+    /// The line sent to the control socket.
+    fn line(&self) -> String {
+        match self {
+            Command::Reload => "reload".to_string(),
+            Command::Flush { name: None } => "flush".to_string(),
+            Command::Flush { name: Some(name) } => format!("flush {}", name),
+            Command::Flushname {
+                name,
+                query_type: None,
+            } => format!("flushname {}", name),
+            Command::Flushname {
+                name,
+                query_type: Some(query_type),
+            } => format!("flushname {} {}", name, query_type),
+            Command::Status => "status".to_string(),
+            Command::Addzone { zone, config: None } => format!("addzone {}", zone),
+            Command::Addzone {
+                zone,
+                config: Some(config),
+            } => format!("addzone {} {}", zone, config.replace('\n', " ")),
+            Command::Delzone { zone } => format!("delzone {}", zone),
+            Command::Notify { zone } => format!("notify {}", zone),
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    match execute(Cli::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {:#}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn execute(cli: Cli) -> Result<()> {
+    print!(
+        "{}",
+        libdns::control::send(&cli.socket, &cli.command.line()).await?
+    );
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn execute(_cli: Cli) -> Result<()> {
+    anyhow::bail!("the control socket requires a Unix system")
+}
@@ -0,0 +1,331 @@
+use crate::config::{is_url, BlockResponse, BlocklistConfig};
+use anyhow::{bail, Context, Result};
+use hickory_proto::op::Query;
+use hickory_proto::rr::rdata::{A, AAAA};
+use hickory_proto::rr::{LowerName, Name, RData, Record, RecordType};
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+/// Names of hosts files that aren't meant to be blocked.
+const HOSTS_NAMES: [&str; 6] = [
+    "localhost",
+    "localhost.localdomain",
+    "local",
+    "broadcasthost",
+    "ip6-localhost",
+    "ip6-loopback",
+];
+
+/// Domains blocked by a set of lists, reloaded periodically. A source that
+/// fails to load keeps its previous domains.
+pub(crate) struct Blocklist {
+    sources: Vec<String>,
+    allow: HashSet<LowerName>,
+    refresh: Duration,
+    #[cfg(feature = "blocklist-url")]
+    http: reqwest::Client,
+    response: BlockResponse,
+    sinkhole_ipv4: Option<Ipv4Addr>,
+    sinkhole_ipv6: Option<Ipv6Addr>,
+    ttl: u32,
+    domains: RwLock<HashMap<String, HashSet<LowerName>>>,
+}
+
+impl Blocklist {
+    pub(crate) fn new(config: &BlocklistConfig) -> Result<Self> {
+        if !cfg!(feature = "blocklist-url") && config.sources().iter().any(|s| is_url(s)) {
+            bail!("blocklist URLs require the `blocklist-url` feature");
+        }
+        if config.response() == BlockResponse::Sinkhole
+            && config.sinkhole_ipv4().is_none()
+            && config.sinkhole_ipv6().is_none()
+        {
+            bail!("sinkhole blocklist response requires sinkhole_ipv4 or sinkhole_ipv6");
+        }
+        let allow = config
+            .allow()
+            .iter()
+            .map(|domain| {
+                LowerName::from_str(domain)
+                    .with_context(|| format!("invalid allowed domain {}", domain))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            sources: config.sources().clone(),
+            allow,
+            refresh: config.refresh(),
+            #[cfg(feature = "blocklist-url")]
+            http: reqwest::Client::builder()
+                .connect_timeout(config.timeout())
+                .timeout(config.timeout())
+                .build()?,
+            response: config.response(),
+            sinkhole_ipv4: config.sinkhole_ipv4(),
+            sinkhole_ipv6: config.sinkhole_ipv6(),
+            ttl: config.ttl().as_secs().try_into().unwrap_or(u32::MAX),
+            domains: RwLock::new(HashMap::new()),
+        })
+    }
+
+    /// Whether `name` or one of its parents is blocked and none of them is
+    /// allowed.
+    pub(crate) fn blocks(&self, name: &LowerName) -> bool {
+        let domains = self.domains.read().unwrap();
+        let mut blocked = false;
+        let mut name = name.clone();
+        loop {
+            if self.allow.contains(&name) {
+                return false;
+            }
+            blocked = blocked || domains.values().any(|list| list.contains(&name));
+            if name.is_root() {
+                return blocked;
+            }
+            name = name.base_name();
+        }
+    }
+
+    /// The answer to `query` of a blocked name, `None` for NXDOMAIN.
+    pub(crate) fn answer(&self, query: &Query) -> Option<Vec<Record>> {
+        let (ipv4, ipv6) = match self.response {
+            BlockResponse::NxDomain => return None,
+            BlockResponse::Null => (Some(Ipv4Addr::UNSPECIFIED), Some(Ipv6Addr::UNSPECIFIED)),
+            BlockResponse::Sinkhole => (self.sinkhole_ipv4, self.sinkhole_ipv6),
+        };
+        let rdata = match query.query_type() {
+            RecordType::A => ipv4.map(|ip| RData::A(A(ip))),
+            RecordType::AAAA => ipv6.map(|ip| RData::AAAA(AAAA(ip))),
+            _ => None,
+        };
+        let record = rdata.map(|rdata| Record::from_rdata(query.name().clone(), self.ttl, rdata));
+        Some(record.into_iter().collect())
+    }
+
+    /// Loads every source, keeping the domains of those that fail.
+    pub(crate) async fn load(&self) {
+        self.load_sources(self.sources.iter()).await
+    }
+
+    /// Loads the files among the sources, quick enough to be waited for
+    /// before serving, unlike the URLs which `watch` loads first.
+    pub(crate) async fn load_files(&self) {
+        self.load_sources(self.sources.iter().filter(|s| !is_url(s)))
+            .await
+    }
+
+    async fn load_sources(&self, sources: impl Iterator<Item = &String>) {
+        for source in sources {
+            match self.read_source(source).await {
+                Ok(text) => {
+                    let domains = parse_domains(&text);
+                    info!("loaded {} blocked domains from {}", domains.len(), source);
+                    self.domains
+                        .write()
+                        .unwrap()
+                        .insert(source.clone(), domains);
+                }
+                Err(e) => warn!("failed to load blocklist {}: {:#}", source, e),
+            }
+        }
+    }
+
+    /// Loads the URLs among the sources, and then every source again every
+    /// `refresh`, until `token` is cancelled.
+    pub(crate) async fn watch(self: Arc<Self>, token: CancellationToken) {
+        let mut ticker = tokio::time::interval(self.refresh);
+        ticker.tick().await;
+        let urls = self.sources.iter().filter(|s| is_url(s));
+        tokio::select! {
+            _ = self.load_sources(urls) => {}
+            _ = token.cancelled() => return,
+        }
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = token.cancelled() => break,
+            }
+            debug!("refreshing blocklists");
+            self.load().await;
+        }
+    }
+}
+
+impl Blocklist {
+    async fn read_source(&self, source: &str) -> Result<String> {
+        if is_url(source) {
+            return self.fetch(source).await;
+        }
+        Ok(tokio::fs::read_to_string(source).await?)
+    }
+
+    #[cfg(feature = "blocklist-url")]
+    async fn fetch(&self, url: &str) -> Result<String> {
+        let response = self.http.get(url).send().await?.error_for_status()?;
+        Ok(response.text().await?)
+    }
+
+    #[cfg(not(feature = "blocklist-url"))]
+    async fn fetch(&self, _url: &str) -> Result<String> {
+        bail!("blocklist URLs require the `blocklist-url` feature")
+    }
+}
+
+/// Domains of a hosts file or a list of one domain per line, skipping the
+/// invalid ones.
+fn parse_domains(text: &str) -> HashSet<LowerName> {
+    let mut domains = HashSet::new();
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut fields = line.split_whitespace().peekable();
+        // hosts files start with the address the names resolve to
+        if fields
+            .peek()
+            .is_some_and(|field| field.parse::<IpAddr>().is_ok())
+        {
+            fields.next();
+        }
+        for field in fields {
+            if HOSTS_NAMES.contains(&field.trim_end_matches('.')) {
+                continue;
+            }
+            match Name::from_str(field) {
+                Ok(name) if !name.is_root() => {
+                    domains.insert(LowerName::new(&name));
+                }
+                _ => debug!("skipped invalid blocklist entry {}", field),
+            }
+        }
+    }
+    domains
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::BlocklistConfigBuilder;
+    use std::io::Write;
+
+    #[test]
+    fn parses_hosts_files_and_domain_lists() {
+        let domains = parse_domains(
+            "# ads\n\
+             0.0.0.0 ads.example.com tracker.example.com # trackers\n\
+             127.0.0.1 localhost\n\
+             ::1 ip6-localhost\n\
+             \n\
+             telemetry.example.net\n\
+             bad..name\n",
+        );
+        let names: HashSet<_> = [
+            "ads.example.com.",
+            "tracker.example.com.",
+            "telemetry.example.net.",
+        ]
+        .iter()
+        .map(|name| LowerName::from_str(name).unwrap())
+        .collect();
+        assert_eq!(domains, names);
+    }
+
+    #[tokio::test]
+    async fn blocks_subdomains() -> Result<()> {
+        let mut file = tempfile::NamedTempFile::new()?;
+        writeln!(file, "example.com\nads.example.net")?;
+        let blocklist = Blocklist::new(
+            &BlocklistConfigBuilder::default()
+                .sources(vec![
+                    file.path().display().to_string(),
+                    "/nonexistent/blocklist".to_string(),
+                ])
+                .allow(vec!["cdn.example.com".to_string()])
+                .response(BlockResponse::Null)
+                .build()?,
+        )?;
+        blocklist.load().await;
+        let blocks = |name: &str| blocklist.blocks(&LowerName::from_str(name).unwrap());
+        assert!(blocks("example.com."));
+        assert!(blocks("www.example.com."));
+        assert!(blocks("ADS.example.net."));
+        assert!(!blocks("example.net."));
+        assert!(!blocks("cdn.example.com."));
+        assert!(!blocks("img.cdn.example.com."));
+
+        let name = Name::from_str("www.example.com.")?;
+        let answer = blocklist.answer(&Query::query(name.clone(), RecordType::AAAA));
+        assert_eq!(
+            answer.unwrap()[0].data(),
+            Some(&RData::AAAA(AAAA(Ipv6Addr::UNSPECIFIED)))
+        );
+        let answer = blocklist.answer(&Query::query(name, RecordType::MX));
+        assert!(answer.unwrap().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_sinkhole_without_addresses() -> Result<()> {
+        let config = BlocklistConfigBuilder::default()
+            .sources(vec![])
+            .response(BlockResponse::Sinkhole)
+            .build()?;
+        assert!(Blocklist::new(&config).is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "blocklist-url")]
+    #[tokio::test]
+    async fn fetches_urls() -> Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}/hosts", listener.local_addr()?);
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).await.unwrap();
+            let body = "0.0.0.0 ads.example.com\n";
+            let response = format!(
+                "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+        });
+        let blocklist = Blocklist::new(
+            &BlocklistConfigBuilder::default()
+                .sources(vec![url])
+                .build()?,
+        )?;
+        blocklist.load().await;
+        assert!(blocklist.blocks(&LowerName::from_str("ads.example.com.")?));
+        Ok(())
+    }
+
+    #[cfg(feature = "blocklist-url")]
+    #[tokio::test]
+    async fn times_out_fetching_urls() -> Result<()> {
+        // accepts connections and never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}/hosts", listener.local_addr()?);
+        tokio::spawn(async move {
+            let mut accepted = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                accepted.push(stream);
+            }
+        });
+        let blocklist = Blocklist::new(
+            &BlocklistConfigBuilder::default()
+                .sources(vec![url])
+                .timeout(Duration::from_millis(200))
+                .build()?,
+        )?;
+        let load = tokio::time::timeout(Duration::from_secs(2), blocklist.load());
+        assert!(load.await.is_ok());
+        assert!(blocklist.domains.read().unwrap().is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,667 @@
+use anyhow::{bail, Context, Result};
+use data_encoding::BASE64;
+use hickory_proto::op::{Message, Query, ResponseCode};
+use hickory_proto::rr::{DNSClass, LowerName, Name, RData, RecordType};
+use hickory_proto::serialize::binary::{BinDecodable, BinEncodable};
+use ipnet::IpNet;
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+use std::num::NonZeroUsize;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::debug;
+
+/// Counters of a response cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub stale_hits: u64,
+    /// Responses forwarded again before they expired.
+    pub prefetches: u64,
+    pub entries: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct CacheKey {
+    name: LowerName,
+    query_type: RecordType,
+    query_class: DNSClass,
+    /// Client subnet the query was forwarded with.
+    subnet: Option<IpNet>,
+}
+
+impl CacheKey {
+    pub(crate) fn new(query: &Query, subnet: Option<IpNet>) -> Self {
+        Self {
+            name: query.name().into(),
+            query_type: query.query_type(),
+            query_class: query.query_class(),
+            subnet,
+        }
+    }
+}
+
+/// The cached answers dropped by a flush.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Flush {
+    All,
+    /// The answers for a name, only those of a type if given.
+    Name(LowerName, Option<RecordType>),
+    /// The answers for a name and the names below it.
+    Subtree(LowerName),
+}
+
+impl Flush {
+    /// The flush of `name`, or every answer when it is empty: of the name
+    /// and the names below it with `subtree`, or else of the name, only of
+    /// `query_type` if not empty.
+    pub(crate) fn new(name: &str, query_type: &str, subtree: bool) -> Result<Self> {
+        if name.is_empty() {
+            if !query_type.is_empty() {
+                bail!("a type can only be flushed with a name");
+            }
+            return Ok(Flush::All);
+        }
+        let name = LowerName::from_str(name).with_context(|| format!("invalid name {}", name))?;
+        match (subtree, query_type) {
+            (true, "") => Ok(Flush::Subtree(name)),
+            (true, _) => bail!("a type can't be flushed with the names below"),
+            (false, "") => Ok(Flush::Name(name, None)),
+            (false, query_type) => {
+                let query_type = RecordType::from_str(&query_type.to_ascii_uppercase())
+                    .with_context(|| format!("invalid type {}", query_type))?;
+                Ok(Flush::Name(name, Some(query_type)))
+            }
+        }
+    }
+
+    fn matches(&self, key: &CacheKey) -> bool {
+        match self {
+            Flush::All => true,
+            Flush::Name(name, query_type) => {
+                *name == key.name && query_type.is_none_or(|t| t == key.query_type)
+            }
+            Flush::Subtree(name) => name.zone_of(&key.name),
+        }
+    }
+}
+
+struct CacheEntry {
+    message: Message,
+    inserted: Instant,
+    ttl: Duration,
+    hits: u32,
+    /// Whether the response is being prefetched.
+    prefetched: bool,
+}
+
+impl CacheEntry {
+    fn new(message: Message, inserted: Instant, ttl: Duration) -> Self {
+        Self {
+            message,
+            inserted,
+            ttl,
+            hits: 0,
+            prefetched: false,
+        }
+    }
+}
+
+/// A cache entry as saved to the persist file, one JSON object per line.
+#[derive(Serialize, Deserialize)]
+struct SavedEntry {
+    name: String,
+    #[serde(rename = "type")]
+    query_type: u16,
+    class: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    subnet: Option<String>,
+    /// When the response was cached, in milliseconds since the Unix epoch.
+    inserted: u64,
+    /// TTL of the entry, in seconds.
+    ttl: u64,
+    /// The response in wire format, base64 encoded.
+    message: String,
+}
+
+impl SavedEntry {
+    /// The entry as cached at `now`, `system_now` on the wall clock, or
+    /// `None` if it expired since it was saved, or was saved longer ago than
+    /// the monotonic clock goes back, as after a reboot.
+    fn restore(
+        self,
+        now: Instant,
+        system_now: SystemTime,
+        max_stale: Duration,
+    ) -> Result<Option<(CacheKey, CacheEntry)>> {
+        let inserted = UNIX_EPOCH + Duration::from_millis(self.inserted);
+        let age = system_now.duration_since(inserted).unwrap_or_default();
+        let ttl = Duration::from_secs(self.ttl);
+        if age >= ttl + max_stale {
+            return Ok(None);
+        }
+        let Some(inserted) = now.checked_sub(age) else {
+            return Ok(None);
+        };
+        let key = CacheKey {
+            name: LowerName::from(Name::from_ascii(&self.name)?),
+            query_type: RecordType::from(self.query_type),
+            query_class: DNSClass::from(self.class),
+            subnet: self.subnet.as_deref().map(str::parse).transpose()?,
+        };
+        let message = Message::from_bytes(&BASE64.decode(self.message.as_bytes())?)?;
+        Ok(Some((key, CacheEntry::new(message, inserted, ttl))))
+    }
+}
+
+/// TTL of answers served from expired entries, as recommended by RFC 8767.
+const STALE_TTL: u32 = 30;
+
+/// LRU cache of upstream responses, keyed by question and the client subnet
+/// sent along with it.
+pub(crate) struct ResponseCache {
+    entries: Mutex<LruCache<CacheKey, CacheEntry>>,
+    /// How long expired entries are kept around to be served stale.
+    max_stale: Duration,
+    /// Hits after which an entry is prefetched, and how long before it
+    /// expires.
+    prefetch: Option<(u32, Duration)>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    stale_hits: AtomicU64,
+    prefetches: AtomicU64,
+}
+
+impl ResponseCache {
+    pub(crate) fn new(max_entries: NonZeroUsize, max_stale: Duration) -> Self {
+        Self {
+            entries: Mutex::new(LruCache::new(max_entries)),
+            max_stale,
+            prefetch: None,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            stale_hits: AtomicU64::new(0),
+            prefetches: AtomicU64::new(0),
+        }
+    }
+
+    /// Asks for the entries answered `hits` times to be prefetched within
+    /// `window` of their expiry, see [`ResponseCache::lookup`].
+    pub(crate) fn with_prefetch(mut self, hits: u32, window: Duration) -> Self {
+        self.prefetch = Some((hits, window));
+        self
+    }
+
+    #[cfg(test)]
+    fn get(&self, query: &Query, subnet: Option<IpNet>) -> Option<Message> {
+        self.get_at(query, subnet, Instant::now())
+    }
+
+    #[cfg(test)]
+    fn get_at(&self, query: &Query, subnet: Option<IpNet>, now: Instant) -> Option<Message> {
+        self.lookup_at(query, subnet, now)
+            .map(|(message, _)| message)
+    }
+
+    /// Returns the cached response, and whether it is to be forwarded again
+    /// now that it is popular and about to expire. Only the first lookup
+    /// asking for it prefetches an entry.
+    pub(crate) fn lookup(&self, query: &Query, subnet: Option<IpNet>) -> Option<(Message, bool)> {
+        self.lookup_at(query, subnet, Instant::now())
+    }
+
+    /// Returns the cached response with its TTLs decayed by the time spent in
+    /// the cache.
+    fn lookup_at(
+        &self,
+        query: &Query,
+        subnet: Option<IpNet>,
+        now: Instant,
+    ) -> Option<(Message, bool)> {
+        let key = CacheKey::new(query, subnet);
+        let mut entries = self.entries.lock().unwrap();
+        let elapsed = match entries.get(&key) {
+            Some(entry) => now.saturating_duration_since(entry.inserted),
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+        };
+        let entry = entries.get_mut(&key).unwrap();
+        if elapsed >= entry.ttl {
+            if elapsed >= entry.ttl + self.max_stale {
+                entries.pop(&key);
+            }
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+
+        entry.hits = entry.hits.saturating_add(1);
+        let prefetch = self.prefetch.is_some_and(|(hits, window)| {
+            !entry.prefetched && entry.hits >= hits && entry.ttl - elapsed <= window
+        });
+        if prefetch {
+            entry.prefetched = true;
+            self.prefetches.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let mut message = entry.message.clone();
+        let elapsed = elapsed.as_secs() as u32;
+        for record in message.answers_mut().iter_mut() {
+            record.set_ttl(record.ttl().saturating_sub(elapsed));
+        }
+        for record in message.name_servers_mut().iter_mut() {
+            record.set_ttl(record.ttl().saturating_sub(elapsed));
+        }
+        for record in message.additionals_mut().iter_mut() {
+            record.set_ttl(record.ttl().saturating_sub(elapsed));
+        }
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some((message, prefetch))
+    }
+
+    pub(crate) fn get_stale(&self, query: &Query, subnet: Option<IpNet>) -> Option<Message> {
+        self.get_stale_at(query, subnet, Instant::now())
+    }
+
+    /// Returns an expired response that is still within the stale window,
+    /// with every TTL set to a short fixed value.
+    fn get_stale_at(&self, query: &Query, subnet: Option<IpNet>, now: Instant) -> Option<Message> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get(&CacheKey::new(query, subnet))?;
+        if now.saturating_duration_since(entry.inserted) >= entry.ttl + self.max_stale {
+            return None;
+        }
+
+        let mut message = entry.message.clone();
+        for record in message.answers_mut().iter_mut() {
+            record.set_ttl(STALE_TTL);
+        }
+        for record in message.name_servers_mut().iter_mut() {
+            record.set_ttl(STALE_TTL);
+        }
+        for record in message.additionals_mut().iter_mut() {
+            record.set_ttl(STALE_TTL);
+        }
+        self.stale_hits.fetch_add(1, Ordering::Relaxed);
+        Some(message)
+    }
+
+    pub(crate) fn insert(&self, query: &Query, subnet: Option<IpNet>, message: &Message) {
+        self.insert_at(query, subnet, message, Instant::now());
+    }
+
+    fn insert_at(&self, query: &Query, subnet: Option<IpNet>, message: &Message, now: Instant) {
+        let Some(ttl) = cache_ttl(message) else {
+            return;
+        };
+        self.entries.lock().unwrap().put(
+            CacheKey::new(query, subnet),
+            CacheEntry::new(message.clone(), now, Duration::from_secs(ttl.into())),
+        );
+    }
+
+    /// Drops the entries matching `flush`, returning how many were dropped.
+    pub(crate) fn flush(&self, flush: &Flush) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        if *flush == Flush::All {
+            let flushed = entries.len();
+            entries.clear();
+            return flushed;
+        }
+        let keys: Vec<CacheKey> = entries
+            .iter()
+            .map(|(key, _)| key)
+            .filter(|key| flush.matches(key))
+            .cloned()
+            .collect();
+        for key in keys.iter() {
+            entries.pop(key);
+        }
+        keys.len()
+    }
+
+    /// The entries that are fresh or may still be served stale, as lines
+    /// of JSON from the least to the most recently used.
+    pub(crate) fn save(&self) -> String {
+        self.save_at(Instant::now(), SystemTime::now())
+    }
+
+    fn save_at(&self, now: Instant, system_now: SystemTime) -> String {
+        let entries = self.entries.lock().unwrap();
+        let mut text = String::new();
+        for (key, entry) in entries.iter().rev() {
+            let age = now.saturating_duration_since(entry.inserted);
+            if age >= entry.ttl + self.max_stale {
+                continue;
+            }
+            let Ok(message) = entry.message.to_bytes() else {
+                continue;
+            };
+            let inserted = system_now.checked_sub(age).unwrap_or(system_now);
+            let saved = SavedEntry {
+                name: key.name.to_string(),
+                query_type: key.query_type.into(),
+                class: key.query_class.into(),
+                subnet: key.subnet.map(|subnet| subnet.to_string()),
+                inserted: inserted
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis() as u64,
+                ttl: entry.ttl.as_secs(),
+                message: BASE64.encode(&message),
+            };
+            text.push_str(&serde_json::to_string(&saved).unwrap());
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Adds the entries saved in `text` that didn't expire since, their
+    /// TTLs decayed by the time spent on disk. Invalid entries are skipped.
+    /// Returns how many were loaded.
+    pub(crate) fn load(&self, text: &str) -> usize {
+        self.load_at(text, Instant::now(), SystemTime::now())
+    }
+
+    fn load_at(&self, text: &str, now: Instant, system_now: SystemTime) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let mut loaded = 0;
+        for (number, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let restored = serde_json::from_str::<SavedEntry>(line)
+                .map_err(anyhow::Error::from)
+                .and_then(|saved| saved.restore(now, system_now, self.max_stale));
+            match restored {
+                Ok(Some((key, entry))) => {
+                    entries.put(key, entry);
+                    loaded += 1;
+                }
+                Ok(None) => {}
+                Err(e) => debug!("invalid cache entry on line {}: {:#}", number + 1, e),
+            }
+        }
+        loaded
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            stale_hits: self.stale_hits.load(Ordering::Relaxed),
+            prefetches: self.prefetches.load(Ordering::Relaxed),
+            entries: self.entries.lock().unwrap().len(),
+        }
+    }
+}
+
+/// How long `message` may be cached: the smallest answer TTL, or the
+/// negative caching TTL of the SOA for NXDOMAIN and NODATA answers.
+fn cache_ttl(message: &Message) -> Option<u32> {
+    let ttl = match message.response_code() {
+        ResponseCode::NoError if !message.answers().is_empty() => {
+            message.answers().iter().map(|r| r.ttl()).min()
+        }
+        ResponseCode::NoError | ResponseCode::NXDomain => {
+            message.name_servers().iter().find_map(|r| match r.data() {
+                Some(RData::SOA(soa)) => Some(r.ttl().min(soa.minimum())),
+                _ => None,
+            })
+        }
+        _ => None,
+    };
+    ttl.filter(|ttl| *ttl > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::rr::{rdata, Name, Record};
+
+    fn query(name: &str) -> Query {
+        Query::query(Name::from_str(name).unwrap(), RecordType::A)
+    }
+
+    fn answer(name: &str, ttl: u32) -> Message {
+        let mut message = Message::new();
+        message.add_answer(Record::from_rdata(
+            Name::from_str(name).unwrap(),
+            ttl,
+            RData::A(rdata::A::new(100, 100, 100, 100)),
+        ));
+        message
+    }
+
+    #[test]
+    fn decays_ttl_and_expires() {
+        let cache = ResponseCache::new(NonZeroUsize::new(8).unwrap(), Duration::ZERO);
+        let now = Instant::now();
+        cache.insert_at(&query("www.et.top"), None, &answer("www.et.top", 60), now);
+
+        let hit = cache
+            .get_at(&query("WWW.et.top"), None, now + Duration::from_secs(20))
+            .unwrap();
+        assert_eq!(hit.answers()[0].ttl(), 40);
+        assert!(cache
+            .get_at(&query("www.et.top"), None, now + Duration::from_secs(60))
+            .is_none());
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 1,
+                misses: 1,
+                stale_hits: 0,
+                prefetches: 0,
+                entries: 0
+            }
+        );
+    }
+
+    #[test]
+    fn serves_stale_within_window() {
+        let cache = ResponseCache::new(NonZeroUsize::new(8).unwrap(), Duration::from_secs(60));
+        let now = Instant::now();
+        cache.insert_at(&query("www.et.top"), None, &answer("www.et.top", 60), now);
+
+        let later = now + Duration::from_secs(90);
+        assert!(cache.get_at(&query("www.et.top"), None, later).is_none());
+        let stale = cache
+            .get_stale_at(&query("www.et.top"), None, later)
+            .unwrap();
+        assert_eq!(stale.answers()[0].ttl(), STALE_TTL);
+
+        let expired = now + Duration::from_secs(120);
+        assert!(cache
+            .get_stale_at(&query("www.et.top"), None, expired)
+            .is_none());
+        assert!(cache.get_at(&query("www.et.top"), None, expired).is_none());
+        assert_eq!(cache.stats().stale_hits, 1);
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    fn prefetches_popular_entries() {
+        let cache = ResponseCache::new(NonZeroUsize::new(8).unwrap(), Duration::ZERO)
+            .with_prefetch(2, Duration::from_secs(10));
+        let now = Instant::now();
+        cache.insert_at(&query("www.et.top"), None, &answer("www.et.top", 60), now);
+        cache.insert_at(&query("a.et.top"), None, &answer("a.et.top", 60), now);
+        let lookup = |name, elapsed| {
+            let later = now + Duration::from_secs(elapsed);
+            cache.lookup_at(&query(name), None, later).unwrap().1
+        };
+        // popular, but not about to expire
+        assert!(!lookup("www.et.top", 1));
+        assert!(!lookup("www.et.top", 2));
+        assert!(lookup("www.et.top", 55));
+        assert!(!lookup("www.et.top", 56));
+        // about to expire, but not popular
+        assert!(!lookup("a.et.top", 55));
+        assert_eq!(cache.stats().prefetches, 1);
+
+        // a new answer is prefetched again
+        cache.insert_at(&query("www.et.top"), None, &answer("www.et.top", 60), now);
+        assert!(!lookup("www.et.top", 55));
+        assert!(lookup("www.et.top", 56));
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let cache = ResponseCache::new(NonZeroUsize::new(2).unwrap(), Duration::ZERO);
+        cache.insert(&query("a.et.top"), None, &answer("a.et.top", 60));
+        cache.insert(&query("b.et.top"), None, &answer("b.et.top", 60));
+        assert!(cache.get(&query("a.et.top"), None).is_some());
+        cache.insert(&query("c.et.top"), None, &answer("c.et.top", 60));
+
+        assert!(cache.get(&query("a.et.top"), None).is_some());
+        assert!(cache.get(&query("b.et.top"), None).is_none());
+        assert!(cache.get(&query("c.et.top"), None).is_some());
+    }
+
+    #[test]
+    fn flushes_names_and_below() {
+        let cache = ResponseCache::new(NonZeroUsize::new(8).unwrap(), Duration::ZERO);
+        for name in ["et.top", "www.et.top", "a.b.et.top", "www.et.internal"] {
+            cache.insert(&query(name), None, &answer(name, 60));
+        }
+        let name = LowerName::from(Name::from_str("B.et.top").unwrap());
+        assert_eq!(cache.flush(&Flush::Subtree(name)), 1);
+        assert!(cache.get(&query("a.b.et.top"), None).is_none());
+        let name = LowerName::from(Name::from_str("et.top").unwrap());
+        assert_eq!(cache.flush(&Flush::Subtree(name)), 2);
+        assert!(cache.get(&query("www.et.internal"), None).is_some());
+        assert_eq!(cache.flush(&Flush::All), 1);
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    fn parses_flushes() -> anyhow::Result<()> {
+        let name = LowerName::from_str("www.et.top")?;
+        assert_eq!(Flush::new("", "", true)?, Flush::All);
+        assert_eq!(
+            Flush::new("www.et.top", "", true)?,
+            Flush::Subtree(name.clone())
+        );
+        assert_eq!(
+            Flush::new("www.et.top", "aaaa", false)?,
+            Flush::Name(name, Some(RecordType::AAAA))
+        );
+        assert!(Flush::new("", "A", false).is_err());
+        assert!(Flush::new("www.et.top", "A", true).is_err());
+        assert!(Flush::new("www.et.top", "NOPE", false).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn flushes_single_names_and_types() {
+        let cache = ResponseCache::new(NonZeroUsize::new(8).unwrap(), Duration::ZERO);
+        let mx = Query::query(Name::from_str("www.et.top").unwrap(), RecordType::MX);
+        cache.insert(&query("www.et.top"), None, &answer("www.et.top", 60));
+        cache.insert(&mx, None, &answer("www.et.top", 60));
+        cache.insert(&query("a.www.et.top"), None, &answer("a.www.et.top", 60));
+
+        let name = LowerName::from(Name::from_str("WWW.et.top").unwrap());
+        assert_eq!(
+            cache.flush(&Flush::Name(name.clone(), Some(RecordType::MX))),
+            1
+        );
+        assert!(cache.get(&query("www.et.top"), None).is_some());
+        assert_eq!(cache.flush(&Flush::Name(name, None)), 1);
+        assert!(cache.get(&query("a.www.et.top"), None).is_some());
+    }
+
+    #[test]
+    fn saves_and_loads_entries() -> anyhow::Result<()> {
+        let cache = ResponseCache::new(NonZeroUsize::new(8).unwrap(), Duration::from_secs(60));
+        let now = Instant::now();
+        let system_now = SystemTime::now();
+        let subnet = Some("192.0.2.0/24".parse()?);
+        cache.insert_at(&query("www.et.top"), subnet, &answer("www.et.top", 60), now);
+        cache.insert_at(&query("a.et.top"), None, &answer("a.et.top", 10), now);
+        let later = now + Duration::from_secs(20);
+        cache.insert_at(&query("b.et.top"), None, &answer("b.et.top", 60), later);
+        // expired and past the stale window
+        cache.insert_at(&query("c.et.top"), None, &answer("c.et.top", 1), now);
+        let saved = cache.save_at(now + Duration::from_secs(65), system_now);
+        assert_eq!(saved.lines().count(), 3);
+
+        // restarted ten seconds later
+        let restarted = ResponseCache::new(NonZeroUsize::new(8).unwrap(), Duration::from_secs(60));
+        let now = Instant::now();
+        let system_now = system_now + Duration::from_secs(10);
+        assert_eq!(restarted.load_at(&saved, now, system_now), 2);
+        let hit = restarted.get_at(&query("b.et.top"), None, now).unwrap();
+        assert_eq!(hit.answers()[0].ttl(), 5);
+        assert!(restarted
+            .get_at(&query("www.et.top"), subnet, now)
+            .is_none());
+        assert!(restarted
+            .get_stale_at(&query("www.et.top"), subnet, now)
+            .is_some());
+        assert!(restarted
+            .get_stale_at(&query("a.et.top"), None, now)
+            .is_none());
+
+        // invalid lines are skipped
+        let restarted = ResponseCache::new(NonZeroUsize::new(8).unwrap(), Duration::from_secs(60));
+        let text = format!("not json\n{}", saved);
+        assert_eq!(restarted.load_at(&text, now, system_now), 2);
+
+        // saved longer ago than the monotonic clock may go back, as after a
+        // reboot, which doesn't keep the other entries from loading
+        let years = Duration::from_secs(20 * 365 * 86400);
+        let cache = ResponseCache::new(NonZeroUsize::new(8).unwrap(), Duration::ZERO);
+        cache.insert_at(&query("d.et.top"), None, &answer("d.et.top", u32::MAX), now);
+        let fresh = now + years;
+        cache.insert_at(&query("e.et.top"), None, &answer("e.et.top", 60), fresh);
+        let saved = format!("{}\n{}", saved, cache.save_at(fresh, system_now + years));
+        let restarted = ResponseCache::new(NonZeroUsize::new(8).unwrap(), Duration::from_secs(60));
+        let now = Instant::now();
+        let representable = now.checked_sub(years).is_some();
+        let loaded = restarted.load_at(&saved, now, system_now + years);
+        assert_eq!(loaded, 1 + usize::from(representable));
+        assert!(restarted.get_at(&query("e.et.top"), None, now).is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn separates_client_subnets() -> anyhow::Result<()> {
+        let cache = ResponseCache::new(NonZeroUsize::new(8).unwrap(), Duration::ZERO);
+        let subnet = Some("192.0.2.0/24".parse()?);
+        cache.insert(&query("www.et.top"), subnet, &answer("www.et.top", 60));
+        assert!(cache.get(&query("www.et.top"), subnet).is_some());
+        assert!(cache.get(&query("www.et.top"), None).is_none());
+        assert!(cache
+            .get(&query("www.et.top"), Some("198.51.100.0/24".parse()?))
+            .is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn caches_negative_answers_by_soa_minimum() {
+        let mut message = Message::new();
+        message.set_response_code(ResponseCode::NXDomain);
+        message.add_name_server(Record::from_rdata(
+            Name::from_str("et.top").unwrap(),
+            3600,
+            RData::SOA(rdata::SOA::new(
+                Name::from_str("ns1.et.top").unwrap(),
+                Name::from_str("hostmaster.et.top").unwrap(),
+                1,
+                3600,
+                600,
+                604800,
+                30,
+            )),
+        ));
+        assert_eq!(cache_ttl(&message), Some(30));
+
+        message.set_response_code(ResponseCode::ServFail);
+        assert_eq!(cache_ttl(&message), None);
+    }
+}
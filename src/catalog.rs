@@ -0,0 +1,87 @@
+use arc_swap::ArcSwap;
+use hickory_proto::rr::LowerName;
+use hickory_server::authority::{AuthorityObject, Catalog};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// A catalog read without locking: queries load the current snapshot, and
+/// writers build a new catalog from the authorities and publish it at once,
+/// so a query sees either every change of a write or none.
+pub(crate) struct SharedCatalog {
+    snapshot: ArcSwap<Catalog>,
+    /// The authorities of the snapshot, from which the next one is built.
+    authorities: Mutex<HashMap<LowerName, Box<dyn AuthorityObject>>>,
+}
+
+impl SharedCatalog {
+    pub(crate) fn new(authorities: HashMap<LowerName, Box<dyn AuthorityObject>>) -> Self {
+        Self {
+            snapshot: ArcSwap::from_pointee(build(&authorities)),
+            authorities: Mutex::new(authorities),
+        }
+    }
+
+    /// The catalog currently served. Writes published afterwards don't
+    /// change it.
+    pub(crate) fn snapshot(&self) -> Arc<Catalog> {
+        self.snapshot.load_full()
+    }
+
+    /// Applies `write` to the authorities and publishes the resulting
+    /// catalog. Writes are serialized, reads never wait for them.
+    pub(crate) fn write<T>(
+        &self,
+        write: impl FnOnce(&mut HashMap<LowerName, Box<dyn AuthorityObject>>) -> T,
+    ) -> T {
+        let mut authorities = self.authorities.lock().unwrap();
+        let result = write(&mut authorities);
+        self.snapshot.store(Arc::new(build(&authorities)));
+        result
+    }
+}
+
+fn build(authorities: &HashMap<LowerName, Box<dyn AuthorityObject>>) -> Catalog {
+    let mut catalog = Catalog::new();
+    for (name, authority) in authorities {
+        catalog.upsert(name.clone(), authority.box_clone());
+    }
+    catalog
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::rr::Name;
+    use hickory_server::authority::ZoneType;
+    use hickory_server::store::in_memory::InMemoryAuthority;
+    use std::str::FromStr;
+
+    fn authority(zone: &str) -> Box<dyn AuthorityObject> {
+        let origin = Name::from_str(zone).unwrap();
+        Box::new(Arc::new(InMemoryAuthority::empty(
+            origin,
+            ZoneType::Primary,
+            false,
+        )))
+    }
+
+    #[test]
+    fn publishes_snapshots() {
+        let internal = LowerName::from_str("et.internal.").unwrap();
+        let top = LowerName::from_str("et.top.").unwrap();
+        let catalog = SharedCatalog::new(HashMap::from([(
+            internal.clone(),
+            authority("et.internal."),
+        )]));
+        let before = catalog.snapshot();
+        catalog.write(|authorities| {
+            authorities.remove(&internal);
+            authorities.insert(top.clone(), authority("et.top."));
+        });
+        assert!(before.contains(&internal));
+        assert!(!before.contains(&top));
+        let after = catalog.snapshot();
+        assert!(!after.contains(&internal));
+        assert!(after.contains(&top));
+    }
+}
@@ -0,0 +1,148 @@
+//! Catalog zones (RFC 9432): zones listing member zones with a PTR record
+//! each, `<id>.zones.<catalog> PTR <member>`, so that secondaries serve
+//! the members without configuring them one by one.
+
+use crate::config;
+use anyhow::{anyhow, bail, Result};
+use data_encoding::HEXLOWER;
+use hickory_proto::rr::dnssec::DigestType;
+use hickory_proto::rr::rdata::{NS, PTR, TXT};
+use hickory_proto::rr::{Name, RData, Record};
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+/// Schema version of the catalog zones served and understood.
+const VERSION: &str = "2";
+
+/// The member zones listed by the `records` of the catalog zone `catalog`.
+pub(crate) fn members(catalog: &Name, records: &[Record]) -> Result<BTreeSet<Name>> {
+    let version_name = Name::from_ascii("version")?.append_domain(catalog)?;
+    let version = records
+        .iter()
+        .filter(|r| r.name() == &version_name)
+        .find_map(|r| r.data()?.as_txt())
+        .map(|txt| txt.to_string())
+        .ok_or_else(|| anyhow!("catalog zone {} has no version", catalog))?;
+    if version != VERSION {
+        bail!(
+            "catalog zone {} has unsupported version {}",
+            catalog,
+            version
+        );
+    }
+    let zones = Name::from_ascii("zones")?.append_domain(catalog)?;
+    Ok(records
+        .iter()
+        .filter(|r| r.name().num_labels() == zones.num_labels() + 1 && zones.zone_of(r.name()))
+        .filter_map(|r| r.data()?.as_ptr())
+        .map(|ptr| ptr.0.to_lowercase())
+        .collect())
+}
+
+/// The records of the primary catalog zone `catalog` listing its
+/// `members`, or every other primary zone of `zones`, with the NS record
+/// `invalid.` that catalog zones have.
+pub(crate) fn records(
+    zones: &config::Zone,
+    catalog: &Name,
+    zone_config: &config::ZoneConfig,
+) -> Result<Vec<Record>> {
+    let members: BTreeSet<Name> = if zone_config.members().is_empty() {
+        zones
+            .iter()
+            .filter(|(_, member)| {
+                member.zone_type() == config::ZoneType::Primary && !member.catalog()
+            })
+            .map(|(domain, _)| Name::from_str(domain))
+            .filter(|member| member.as_ref().map_or(true, |member| member != catalog))
+            .collect::<Result<_, _>>()?
+    } else {
+        zone_config
+            .members()
+            .iter()
+            .map(|domain| Name::from_str(domain))
+            .collect::<Result<_, _>>()?
+    };
+
+    let mut records = vec![
+        Record::from_rdata(
+            catalog.clone(),
+            0,
+            RData::NS(NS(Name::from_ascii("invalid.")?)),
+        ),
+        Record::from_rdata(
+            Name::from_ascii("version")?.append_domain(catalog)?,
+            0,
+            RData::TXT(TXT::new(vec![VERSION.to_string()])),
+        ),
+    ];
+    let zones = Name::from_ascii("zones")?.append_domain(catalog)?;
+    for mut member in members {
+        member.set_fqdn(true);
+        let member = member.to_lowercase();
+        let owner = Name::from_ascii(id(&member)?)?.append_domain(&zones)?;
+        records.push(Record::from_rdata(owner, 0, RData::PTR(PTR(member))));
+    }
+    Ok(records)
+}
+
+/// The unique ID of the member zone `member`, stable across restarts so
+/// that consumers don't see members removed and added again.
+fn id(member: &Name) -> Result<String> {
+    let digest = DigestType::SHA256.hash(member.to_ascii().as_bytes())?;
+    Ok(HEXLOWER.encode(&digest.as_ref()[..8]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::rr::RecordType;
+
+    #[test]
+    fn lists_member_zones() -> Result<()> {
+        let catalog = Name::from_str("catalog.et.internal.")?;
+        let zones: config::Zone = [
+            (
+                "catalog.et.internal".to_string(),
+                config::ZoneConfig::default(),
+            ),
+            ("et.internal".to_string(), config::ZoneConfig::default()),
+            ("ET.top".to_string(), config::ZoneConfig::default()),
+            (
+                "et.example".to_string(),
+                config::ZoneConfigBuilder::default()
+                    .zone_type(config::ZoneType::Secondary)
+                    .build()?,
+            ),
+        ]
+        .into();
+        let zone_config = config::ZoneConfigBuilder::default().catalog(true).build()?;
+        let records = records(&zones, &catalog, &zone_config)?;
+        let listed: Vec<String> = members(&catalog, &records)?
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(listed, ["et.internal.", "et.top."]);
+        // the IDs don't depend on the other members
+        let only = config::ZoneConfigBuilder::default()
+            .catalog(true)
+            .members(vec!["et.top".to_string()])
+            .build()?;
+        let ptr = |records: &[Record]| {
+            records
+                .iter()
+                .find(|r| {
+                    r.record_type() == RecordType::PTR && r.data().unwrap().to_string() == "et.top."
+                })
+                .map(|r| r.name().clone())
+        };
+        assert_eq!(ptr(&records), ptr(&self::records(&zones, &catalog, &only)?));
+
+        let unversioned: Vec<Record> = records
+            .into_iter()
+            .filter(|r| r.record_type() != RecordType::TXT)
+            .collect();
+        assert!(members(&catalog, &unversioned).is_err());
+        Ok(())
+    }
+}
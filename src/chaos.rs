@@ -0,0 +1,93 @@
+use crate::config::ChaosConfig;
+use hickory_proto::op::Query;
+use hickory_proto::rr::rdata::TXT;
+use hickory_proto::rr::{DNSClass, LowerName, Name, RData, Record, RecordType};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// The CHAOS class names identifying servers and the strings they answer.
+#[derive(Default)]
+pub(crate) struct Chaos {
+    strings: HashMap<LowerName, String>,
+}
+
+impl Chaos {
+    pub(crate) fn new(config: Option<&ChaosConfig>) -> Self {
+        let Some(config) = config else {
+            return Self::default();
+        };
+        let strings = [
+            ("version.bind.", config.version()),
+            ("version.server.", config.version()),
+            ("hostname.bind.", config.hostname()),
+            ("id.server.", config.id()),
+        ]
+        .into_iter()
+        .filter_map(|(name, string)| {
+            let name = LowerName::from(Name::from_str(name).ok()?);
+            Some((name, string?.to_string()))
+        })
+        .collect();
+        Self { strings }
+    }
+
+    /// The TXT answer to the CHAOS class `query`, empty for other types,
+    /// `None` when it is to be refused.
+    pub(crate) fn answer(&self, query: &Query) -> Option<Vec<Record>> {
+        let string = self.strings.get(&LowerName::from(query.name()))?;
+        if !matches!(query.query_type(), RecordType::TXT | RecordType::ANY) {
+            return Some(Vec::new());
+        }
+        let mut record = Record::from_rdata(
+            query.name().clone(),
+            0,
+            RData::TXT(TXT::new(vec![string.clone()])),
+        );
+        record.set_dns_class(DNSClass::CH);
+        Some(vec![record])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ChaosConfigBuilder;
+
+    #[test]
+    fn answers_configured_strings() -> anyhow::Result<()> {
+        let chaos = Chaos::new(Some(
+            &ChaosConfigBuilder::default()
+                .version("libdns 1.0")
+                .id("ns1")
+                .build()?,
+        ));
+        let answer = |name: &str, query_type| {
+            let mut query = Query::query(Name::from_str(name).unwrap(), query_type);
+            query.set_query_class(DNSClass::CH);
+            let answers = chaos.answer(&query)?;
+            Some(
+                answers
+                    .iter()
+                    .map(|r| r.data().unwrap().to_string())
+                    .collect::<Vec<_>>(),
+            )
+        };
+        assert_eq!(
+            answer("VERSION.bind.", RecordType::TXT).unwrap(),
+            ["libdns 1.0"]
+        );
+        assert_eq!(
+            answer("version.server.", RecordType::ANY).unwrap(),
+            ["libdns 1.0"]
+        );
+        assert_eq!(answer("id.server.", RecordType::TXT).unwrap(), ["ns1"]);
+        assert!(answer("id.server.", RecordType::A).unwrap().is_empty());
+        assert!(answer("hostname.bind.", RecordType::TXT).is_none());
+        assert!(answer("authors.bind.", RecordType::TXT).is_none());
+
+        let disabled = Chaos::new(None);
+        let query = Query::query(Name::from_str("version.bind.")?, RecordType::TXT);
+        assert!(disabled.answer(&query).is_none());
+        Ok(())
+    }
+}
@@ -1,162 +1,6821 @@
+use crate::error::Error;
+use crate::generate;
+use crate::lint::{self, Diagnostic};
+use crate::strict;
+use anyhow::{anyhow, bail, Context};
 use hickory_proto::rr;
 use hickory_proto::rr::RData;
+use hickory_proto::serialize::txt::Parser;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 #[derive(Serialize, Deserialize, Debug, Clone, derive_builder::Builder)]
 pub struct RunConfig {
     general: GeneralConfig,
 
+    #[serde(default)]
     #[builder(default = HashMap::new())]
     zones: Zone,
+
+    /// Files holding more `zones`, as glob patterns relative to the config
+    /// file such as `zones.d/*.toml`, read by `RunConfig::from_path`.
+    #[serde(default)]
+    #[builder(default)]
+    include: Vec<String>,
+
+    /// Directory of zones, relative to the config file: a zone of each
+    /// `<zone>.zone` zone file and `<zone>.toml` zone config in it, read by
+    /// `RunConfig::from_path`.
+    #[serde(default)]
+    #[builder(setter(into, strip_option), default = None)]
+    zone_dir: Option<PathBuf>,
+
+    #[serde(default)]
+    #[builder(setter(strip_option), default = None)]
+    forward: Option<ForwardConfig>,
+
+    #[serde(default)]
+    #[builder(setter(strip_option), default = None)]
+    blocklist: Option<BlocklistConfig>,
+
+    #[serde(default)]
+    #[builder(setter(strip_option), default = None)]
+    hosts: Option<HostsConfig>,
+
+    /// Answers the names that don't exist with fixed addresses, but for
+    /// those of the zones with a redirect of their own.
+    #[serde(default)]
+    #[builder(setter(strip_option), default = None)]
+    nxdomain_redirect: Option<NxdomainRedirectConfig>,
+
+    #[serde(default)]
+    #[builder(setter(strip_option), default = None)]
+    geoip: Option<GeoIpConfig>,
+
+    /// Records synthesized from the Services, Endpoints and Ingresses of a
+    /// Kubernetes cluster, which require the `kubernetes` feature.
+    #[serde(default)]
+    #[builder(setter(strip_option), default = None)]
+    kubernetes: Option<KubernetesConfig>,
+
+    /// Records of the running containers of a Docker daemon, which require
+    /// the `docker` feature.
+    #[serde(default)]
+    #[builder(setter(strip_option), default = None)]
+    docker: Option<DockerConfig>,
+
+    /// Zones whose records are kept in etcd, which require the `etcd`
+    /// feature.
+    #[serde(default)]
+    #[builder(setter(strip_option), default = None)]
+    etcd: Option<EtcdConfig>,
+
+    /// Records synthesized from the services of a Consul catalog, which
+    /// require the `consul` feature.
+    #[serde(default)]
+    #[builder(setter(strip_option), default = None)]
+    consul: Option<ConsulConfig>,
+
+    /// Redis server holding the records of the `redis` zones, which require
+    /// the `redis` feature.
+    #[serde(default)]
+    #[builder(setter(strip_option), default = None)]
+    redis: Option<RedisConfig>,
+
+    /// Zones of a PowerDNS database, which require the `postgres` feature.
+    #[serde(default)]
+    #[builder(setter(strip_option), default = None)]
+    postgres: Option<PostgresConfig>,
+
+    /// TSIG keys, referenced by name from the ACLs of zones.
+    #[serde(default)]
+    #[builder(default)]
+    keys: Vec<TsigKeyConfig>,
+
+    /// Split-horizon views, tried in order. Clients matching none of them
+    /// are served `zones`.
+    #[serde(default)]
+    #[builder(default)]
+    views: Vec<ViewConfig>,
+
+    /// Rules mapping queried names to others before they are looked up,
+    /// the first matching one applies.
+    #[serde(default)]
+    #[builder(default)]
+    rewrites: Vec<RewriteRule>,
 }
 
 impl RunConfig {
+    /// Checks the zones of the config and of its views for problems that
+    /// don't keep the server from starting: records outside their zone,
+    /// CNAME records next to other records, duplicate records, names that
+    /// aren't host names where host names are expected, NS records with a
+    /// TTL of 0 and misplaced SOA records.
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        lint::validate(self)
+    }
+
+    /// Reads a config file, picking the format from its extension: `.toml`,
+    /// `.yaml`/`.yml` or `.json`. Parse errors point at the offending line
+    /// and column.
+    ///
+    /// Relative zone file, persist file and DNSSEC key file paths are
+    /// resolved against the directory of the config file, or of the
+    /// included file holding the zone. The zones of the `include` files and
+    /// of `zone_dir` are added to `zones`.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let mut config = Self::parse_file(path).map_err(|e| Error::Config(e.into()))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let view_zones = config
+            .views
+            .iter_mut()
+            .flat_map(|view| view.zones.values_mut());
+        for zone in config.zones.values_mut().chain(view_zones) {
+            zone.resolve_paths(dir);
+        }
+        config
+            .load_included_zones(dir)
+            .map_err(|e| Error::Config(e.into()))?;
+        if let Some(blocklist) = config.blocklist.as_mut() {
+            for source in &mut blocklist.sources {
+                if !is_url(source) && Path::new(source).is_relative() {
+                    *source = dir.join(&*source).to_string_lossy().into_owned();
+                }
+            }
+        }
+        if let Some(hosts) = config.hosts.as_mut() {
+            for file in &mut hosts.files {
+                if file.is_relative() {
+                    *file = dir.join(&*file);
+                }
+            }
+        }
+        if let Some(kubernetes) = config.kubernetes.as_mut() {
+            for file in [kubernetes.token_file.as_mut(), kubernetes.ca_file.as_mut()]
+                .into_iter()
+                .flatten()
+            {
+                if file.is_relative() {
+                    *file = dir.join(&*file);
+                }
+            }
+        }
+        if let Some(docker) = config.docker.as_mut() {
+            if docker.socket.is_relative() {
+                docker.socket = dir.join(&docker.socket);
+            }
+        }
+        if let Some(file) = config.etcd.as_mut().and_then(|etcd| etcd.ca_file.as_mut()) {
+            if file.is_relative() {
+                *file = dir.join(&*file);
+            }
+        }
+        if let Some(forward) = config.forward.as_mut() {
+            for file in [
+                forward.ca_file.as_mut(),
+                forward.cache.persist_file.as_mut(),
+            ]
+            .into_iter()
+            .flatten()
+            {
+                if file.is_relative() {
+                    *file = dir.join(&*file);
+                }
+            }
+            for record in &mut forward.local_data {
+                record.resolve_paths(dir);
+            }
+        }
+        if let Some(geoip) = config.geoip.as_mut() {
+            for file in [geoip.database.as_mut(), geoip.asn_database.as_mut()]
+                .into_iter()
+                .flatten()
+            {
+                if file.is_relative() {
+                    *file = dir.join(&*file);
+                }
+            }
+        }
+        Ok(config)
+    }
+
+    fn parse_file(path: &Path) -> anyhow::Result<Self> {
+        let text = read_config_text(path)?;
+        let config: Self = parse_config_text(path, &text)?;
+        if config.general.strict {
+            let mut problems =
+                strict::unknown_keys::<Self>(&text, parse_config_text(path, &text)?)?;
+            problems.extend(strict::invalid_listen_addresses(&text, &config.general));
+            strict::check(path, problems)?;
+        }
+        Ok(config)
+    }
+
+    /// Parses the file at `path` included by the config, rejecting the keys
+    /// it doesn't know when the config is strict.
+    fn parse_included<T: serde::de::DeserializeOwned>(&self, path: &Path) -> anyhow::Result<T> {
+        let text = read_config_text(path)?;
+        let parsed = parse_config_text(path, &text)?;
+        if self.general.strict {
+            strict::check(
+                path,
+                strict::unknown_keys::<T>(&text, parse_config_text(path, &text)?)?,
+            )?;
+        }
+        Ok(parsed)
+    }
+
+    /// Adds the zones of the files matching the `include` patterns and of
+    /// `zone_dir`, both relative to `dir`. A zone configured twice is an
+    /// error.
+    fn load_included_zones(&mut self, dir: &Path) -> anyhow::Result<()> {
+        let mut included = Vec::new();
+        for pattern in &self.include {
+            let pattern = dir.join(pattern);
+            let pattern = pattern.to_string_lossy();
+            let paths = glob::glob(&pattern)
+                .with_context(|| format!("invalid include pattern {}", pattern))?;
+            for path in paths {
+                let path = path?;
+                let file: IncludedZones = self.parse_included(&path)?;
+                let file_dir = path.parent().unwrap_or(Path::new(""));
+                for (name, mut zone) in file.zones {
+                    zone.resolve_paths(file_dir);
+                    included.push((path.clone(), name, zone));
+                }
+            }
+        }
+        if let Some(zone_dir) = &self.zone_dir {
+            let zone_dir = dir.join(zone_dir);
+            let mut paths = std::fs::read_dir(&zone_dir)
+                .with_context(|| format!("failed to read {}", zone_dir.display()))?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()?;
+            paths.sort();
+            for path in paths {
+                let Some(name) = path.file_stem().and_then(|name| name.to_str()) else {
+                    continue;
+                };
+                let name = name.to_string();
+                let zone = match path.extension().and_then(|e| e.to_str()) {
+                    Some("zone") => ZoneConfig {
+                        file: Some(path.clone()),
+                        ..Default::default()
+                    },
+                    Some("toml") => {
+                        let mut zone: ZoneConfig = self.parse_included(&path)?;
+                        zone.resolve_paths(&zone_dir);
+                        zone
+                    }
+                    _ => continue,
+                };
+                included.push((path, name, zone));
+            }
+        }
+        for (path, name, zone) in included {
+            if self.zones.contains_key(&name) {
+                bail!("{}: zone {} is already configured", path.display(), name);
+            }
+            self.zones.insert(name, zone);
+        }
+        Ok(())
+    }
+
     pub fn general(&self) -> &GeneralConfig {
         &self.general
     }
 
+    pub(crate) fn general_mut(&mut self) -> &mut GeneralConfig {
+        &mut self.general
+    }
+
     pub fn zones(&self) -> &Zone {
         &self.zones
     }
-}
 
-#[derive(Serialize, Deserialize, Debug, Clone, derive_builder::Builder)]
-pub struct GeneralConfig {
-    #[builder(setter(into, strip_option), default = None)]
-    listen_tcp: Option<String>,
+    pub fn include(&self) -> &[String] {
+        &self.include
+    }
 
-    #[builder(setter(into, strip_option), default = None)]
-    listen_udp: Option<String>,
-}
+    pub fn zone_dir(&self) -> Option<&Path> {
+        self.zone_dir.as_deref()
+    }
 
-impl GeneralConfig {
-    pub fn listen_tcp(&self) -> &Option<String> {
-        &self.listen_tcp
+    pub fn forward(&self) -> &Option<ForwardConfig> {
+        &self.forward
+    }
+
+    pub fn blocklist(&self) -> &Option<BlocklistConfig> {
+        &self.blocklist
+    }
+
+    pub fn hosts(&self) -> &Option<HostsConfig> {
+        &self.hosts
+    }
+
+    pub fn nxdomain_redirect(&self) -> &Option<NxdomainRedirectConfig> {
+        &self.nxdomain_redirect
+    }
+
+    pub fn geoip(&self) -> &Option<GeoIpConfig> {
+        &self.geoip
+    }
+
+    pub fn kubernetes(&self) -> &Option<KubernetesConfig> {
+        &self.kubernetes
+    }
+
+    pub fn docker(&self) -> &Option<DockerConfig> {
+        &self.docker
+    }
+
+    pub fn etcd(&self) -> &Option<EtcdConfig> {
+        &self.etcd
+    }
+
+    pub fn consul(&self) -> &Option<ConsulConfig> {
+        &self.consul
+    }
+
+    pub fn redis(&self) -> &Option<RedisConfig> {
+        &self.redis
+    }
+
+    pub fn postgres(&self) -> &Option<PostgresConfig> {
+        &self.postgres
+    }
+
+    pub fn keys(&self) -> &Vec<TsigKeyConfig> {
+        &self.keys
+    }
+
+    pub fn views(&self) -> &Vec<ViewConfig> {
+        &self.views
     }
 
-    pub fn listen_udp(&self) -> &Option<String> {
-        &self.listen_udp
+    pub fn rewrites(&self) -> &Vec<RewriteRule> {
+        &self.rewrites
     }
 }
 
-pub type Zone = HashMap<String, Vec<Record>>; // domain -> records
+/// The zones of a file included by a config.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IncludedZones {
+    #[serde(default)]
+    zones: Zone,
+}
 
-pub type RecordType = rr::RecordType;
+fn read_config_text(path: &Path) -> anyhow::Result<String> {
+    std::fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))
+}
 
-#[derive(Serialize, Deserialize, Debug, Clone, derive_builder::Builder)]
-pub struct Record {
-    #[serde(rename = "type")]
-    rr_type: RecordType,
+/// An error parsing a config file, with its line and column when known.
+type ParseError = (String, Option<(usize, usize)>);
 
-    name: String,
-    value: String,
+/// Parses the text of the config file at `path`, picking the format from
+/// its extension, with the references to environment variables in its
+/// string values expanded. Errors point at the offending line and column,
+/// those of the deserialization of a file with references excepted.
+fn parse_config_text<T: serde::de::DeserializeOwned>(path: &Path, text: &str) -> anyhow::Result<T> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    let parsed = match extension.as_deref() {
+        Some("toml") => parse_toml(text),
+        Some("yaml") | Some("yml") => parse_yaml(text),
+        Some("json") => parse_json(text),
+        _ => bail!(
+            "unknown config format of {}, expected a .toml, .yaml, .yml or .json file",
+            path.display()
+        ),
+    };
+    let (message, location) = match parsed {
+        Ok(value) => return Ok(value),
+        Err(e) => e,
+    };
+    match location {
+        Some((line, column)) => bail!("{}:{}:{}: {}", path.display(), line, column, message),
+        None => bail!("{}: {}", path.display(), message),
+    }
+}
 
-    #[serde(with = "humantime_serde")]
-    ttl: Duration,
+fn parse_toml<T: serde::de::DeserializeOwned>(text: &str) -> Result<T, ParseError> {
+    let located = |e: toml::de::Error| {
+        let location = e.span().map(|span| line_column(text, span.start));
+        (e.message().to_string(), location)
+    };
+    let mut value = toml::Value::Table(toml::from_str(text).map_err(located)?);
+    if !expand_toml(&mut value, text)? {
+        return toml::from_str(text).map_err(located);
+    }
+    value
+        .try_into()
+        .map_err(|e| (e.message().to_string(), None))
 }
 
-impl Record {
-    fn name(&self) -> anyhow::Result<rr::Name> {
-        let name = rr::Name::from_str(self.name.as_str())?;
-        Ok(name)
+fn parse_yaml<T: serde::de::DeserializeOwned>(text: &str) -> Result<T, ParseError> {
+    let located = |e: serde_yaml::Error| {
+        let location = e.location().map(|l| (l.line(), l.column()));
+        (strip_location(e.to_string(), location), location)
+    };
+    let mut value: serde_yaml::Value = serde_yaml::from_str(text).map_err(located)?;
+    if !expand_yaml(&mut value, text)? {
+        return serde_yaml::from_str(text).map_err(located);
     }
+    serde_yaml::from_value(value).map_err(|e| (e.to_string(), None))
+}
 
-    fn rr_type(&self) -> rr::RecordType {
-        self.rr_type.clone().into()
+fn parse_json<T: serde::de::DeserializeOwned>(text: &str) -> Result<T, ParseError> {
+    let located = |e: serde_json::Error| {
+        let location = Some((e.line(), e.column()));
+        (strip_location(e.to_string(), location), location)
+    };
+    let mut value: serde_json::Value = serde_json::from_str(text).map_err(located)?;
+    if !expand_json(&mut value, text)? {
+        return serde_json::from_str(text).map_err(located);
     }
+    serde_json::from_value(value).map_err(|e| (e.to_string(), None))
 }
 
-impl TryFrom<Record> for rr::Record {
-    type Error = anyhow::Error;
+/// Expands the references to environment variables in the strings of
+/// `value`, parsed from `text`. Returns whether any was expanded.
+fn expand_toml(value: &mut toml::Value, text: &str) -> Result<bool, ParseError> {
+    let mut expanded = false;
+    match value {
+        toml::Value::String(string) => expanded = expand_string(string, text)?,
+        toml::Value::Array(items) => {
+            for item in items {
+                expanded |= expand_toml(item, text)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (_, item) in table.iter_mut() {
+                expanded |= expand_toml(item, text)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(expanded)
+}
 
-    fn try_from(value: Record) -> Result<Self, Self::Error> {
-        let r: rr::Record = (&value).try_into()?;
-        Ok(r)
+fn expand_yaml(value: &mut serde_yaml::Value, text: &str) -> Result<bool, ParseError> {
+    let mut expanded = false;
+    match value {
+        serde_yaml::Value::String(string) => expanded = expand_string(string, text)?,
+        serde_yaml::Value::Sequence(items) => {
+            for item in items {
+                expanded |= expand_yaml(item, text)?;
+            }
+        }
+        serde_yaml::Value::Mapping(mapping) => {
+            for item in mapping.values_mut() {
+                expanded |= expand_yaml(item, text)?;
+            }
+        }
+        serde_yaml::Value::Tagged(tagged) => expanded = expand_yaml(&mut tagged.value, text)?,
+        _ => {}
     }
+    Ok(expanded)
 }
 
-impl TryFrom<&Record> for rr::Record {
-    type Error = anyhow::Error;
+fn expand_json(value: &mut serde_json::Value, text: &str) -> Result<bool, ParseError> {
+    let mut expanded = false;
+    match value {
+        serde_json::Value::String(string) => expanded = expand_string(string, text)?,
+        serde_json::Value::Array(items) => {
+            for item in items {
+                expanded |= expand_json(item, text)?;
+            }
+        }
+        serde_json::Value::Object(object) => {
+            for item in object.values_mut() {
+                expanded |= expand_json(item, text)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(expanded)
+}
 
-    fn try_from(value: &Record) -> Result<Self, Self::Error> {
-        let name = value.name()?;
-        let mut record = Self::with(name, value.rr_type(), value.ttl.as_secs() as u32);
-        record.set_dns_class(rr::DNSClass::IN);
-        match value.rr_type {
-            RecordType::A => {
-                let addr: Ipv4Addr = value.value.parse()?;
-                record.set_data(Some(RData::A(rr::rdata::a::A(addr))));
+/// Expands the references of `string`, returns whether it changed. An unset
+/// variable fails with the location of its first reference in `text`
+/// outside a comment.
+fn expand_string(string: &mut String, text: &str) -> Result<bool, ParseError> {
+    if !string.contains("${") {
+        return Ok(false);
+    }
+    let expanded = expand_env(string).map_err(|(name, message)| {
+        let reference = format!("${{{}", name);
+        let offset = text.match_indices(&reference).map(|(i, _)| i).find(|&i| {
+            let line = &text[text[..i].rfind('\n').map_or(0, |start| start + 1)..i];
+            !line.trim_start().starts_with('#') && !text[..i].ends_with('$')
+        });
+        (message, offset.map(|offset| line_column(text, offset)))
+    })?;
+    let changed = expanded != *string;
+    *string = expanded;
+    Ok(changed)
+}
+
+/// 1-based line and column of the byte `offset` of `text`.
+pub(crate) fn line_column(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+    (line, column)
+}
+
+/// Expands the `${NAME}` references to environment variables in `text`,
+/// `${NAME:-default}` to `default` when `NAME` is unset, and `$${NAME}` to a
+/// literal `${NAME}`. Other uses of `${`, such as the modifiers of
+/// generators, are left as they are. Fails with the name of a variable that
+/// isn't set.
+fn expand_env(text: &str) -> Result<String, (String, String)> {
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        let after = &rest[start + 2..];
+        let length = after
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(after.len());
+        let name = &after[..length];
+        let is_name = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_');
+        let tail = &after[length..];
+        // the default of the reference and the length of the rest of it
+        let reference = match tail {
+            _ if !is_name => None,
+            _ if tail.starts_with('}') => Some((None, 1)),
+            _ if tail.starts_with(":-") => tail.find('}').map(|end| (Some(&tail[2..end]), end + 1)),
+            _ => None,
+        };
+        let Some((default, end)) = reference else {
+            expanded.push_str(&rest[..start + 2]);
+            rest = after;
+            continue;
+        };
+        if rest[..start].ends_with('$') {
+            expanded.push_str(&rest[..start - 1]);
+            expanded.push_str(&rest[start..start + 2 + length + end]);
+        } else {
+            expanded.push_str(&rest[..start]);
+            match (std::env::var(name), default) {
+                (Ok(value), _) => expanded.push_str(&value),
+                (Err(_), Some(default)) => expanded.push_str(default),
+                (Err(_), None) => {
+                    let message = format!("environment variable {} is not set", name);
+                    return Err((name.to_string(), message));
+                }
             }
-            _ => todo!(),
         }
-        Ok(record)
+        rest = &after[length + end..];
     }
+    expanded.push_str(rest);
+    Ok(expanded)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use anyhow::anyhow;
+/// Drops the ` at line L column C` suffix serde_json and serde_yaml append to
+/// their messages.
+fn strip_location(message: String, location: Option<(usize, usize)>) -> String {
+    let Some((line, column)) = location else {
+        return message;
+    };
+    match message.strip_suffix(&format!(" at line {} column {}", line, column)) {
+        Some(stripped) => stripped.to_string(),
+        None => message,
+    }
+}
 
-    #[tokio::test]
-    async fn it_works() -> anyhow::Result<()> {
-        let text = r#"
-[general]
-listen_tcp = "127.0.0.1:5300"
-listen_udp = "127.0.0.1:5353"
+#[derive(Serialize, Deserialize, Debug, Clone, derive_builder::Builder)]
+pub struct GeneralConfig {
+    #[serde(default)]
+    #[builder(setter(into), default)]
+    listen_tcp: ListenAddrs,
 
-[[zones."et.internal"]]
-type = "A"
-name = "www"
-value = "123.123.123.123"
-ttl = "60s"
+    #[serde(default)]
+    #[builder(setter(into), default)]
+    listen_udp: ListenAddrs,
 
-[[zones."et.top"]]
-type = "A"
-name = "@"
-value = "100.100.100.100"
-ttl = "61s"
-"#;
+    /// Number of sockets bound to each `listen_udp` address with `SO_REUSEPORT`, each
+    /// received by a task of its own. Unix only when more than one.
+    #[serde(default = "default_udp_workers")]
+    #[builder(default = default_udp_workers())]
+    udp_workers: usize,
 
-        let config = toml::from_str::<RunConfig>(text)?;
-        assert_eq!(
-            config.general.listen_tcp().clone().unwrap(),
-            "127.0.0.1:5300"
-        );
-        assert_eq!(
-            config.general.listen_udp().clone().unwrap(),
-            "127.0.0.1:5353"
-        );
-        assert_eq!(config.zones.len(), 2);
+    #[builder(setter(strip_option), default = None)]
+    listen_tls: Option<TlsListenConfig>,
 
-        let (domain, records) = config
-            .zones
-            .get_key_value("et.internal")
-            .map_or(Err(anyhow!("parse error")), |x| Ok(x))?;
-        assert_eq!(domain, "et.internal");
-        assert_eq!(records.len(), 1);
-        let record = &records[0];
-        assert_eq!(record.rr_type, RecordType::A);
-        assert_eq!(record.name, "www");
-        assert_eq!(record.value, "123.123.123.123");
-        assert_eq!(record.ttl.as_secs(), 60);
+    /// DNS-over-QUIC listener address, requires the `doq` feature. Uses the
+    /// certificate of `listen_tls`, which is loaded once at startup.
+    #[builder(setter(into, strip_option), default = None)]
+    listen_quic: Option<String>,
 
-        let (domain, records) = config
-            .zones
-            .get_key_value("et.top")
-            .map_or(Err(anyhow!("parse error")), |x| Ok(x))?;
-        assert_eq!(domain, "et.top");
-        assert_eq!(records.len(), 1);
-        let record = &records[0];
-        assert_eq!(record.rr_type, RecordType::A);
-        assert_eq!(record.name, "@");
-        assert_eq!(record.value, "100.100.100.100");
-        assert_eq!(record.ttl.as_secs(), 61);
+    /// DNS-over-HTTPS listener, requires the `doh` feature.
+    #[builder(setter(strip_option), default = None)]
+    listen_https: Option<HttpsListenConfig>,
+
+    /// HTTP admin API listener, requires the `admin` feature.
+    #[builder(setter(strip_option), default = None)]
+    listen_admin: Option<AdminListenConfig>,
+
+    /// gRPC control API listener, requires the `grpc` feature. Its `token`
+    /// is checked like that of the admin API.
+    #[builder(setter(strip_option), default = None)]
+    listen_grpc: Option<AdminListenConfig>,
+
+    /// Unix socket taking the commands of `dns-serverctl`, accessible to the
+    /// user of the server only.
+    #[serde(default)]
+    #[builder(setter(into, strip_option), default = None)]
+    control_socket: Option<PathBuf>,
+
+    /// Multicast DNS responder answering for the `.local` records of the
+    /// zones, on 224.0.0.251 and ff02::fb.
+    #[serde(default)]
+    #[builder(setter(strip_option), default = None)]
+    mdns: Option<MdnsConfig>,
+
+    /// Identifier of the server, returned in the NSID option (RFC 5001) of
+    /// the responses to queries asking for it, to tell the instances of an
+    /// anycast address apart.
+    #[serde(default)]
+    #[builder(setter(into, strip_option), default = None)]
+    nsid: Option<String>,
+
+    /// Answers to the CHAOS class TXT queries identifying the server, such
+    /// as `version.bind`. They are refused without it.
+    #[serde(default)]
+    #[builder(setter(strip_option), default = None)]
+    chaos: Option<ChaosConfig>,
+
+    /// Idle time after which a TCP connection is closed.
+    #[serde(with = "humantime_serde", default = "default_tcp_timeout")]
+    #[builder(default = default_tcp_timeout())]
+    tcp_timeout: Duration,
+
+    /// Longest wait for in-flight queries on shutdown, after which they are
+    /// dropped.
+    #[serde(with = "humantime_serde", default = "default_shutdown_timeout")]
+    #[builder(default = default_shutdown_timeout())]
+    shutdown_timeout: Duration,
+
+    /// Limits the UDP queries of each client network.
+    #[serde(default)]
+    #[builder(setter(strip_option), default = None)]
+    rate_limit: Option<RateLimitConfig>,
+
+    /// Clients answered at all, as addresses, networks or TSIG keys, every
+    /// client when empty. Others are refused.
+    #[serde(default)]
+    #[builder(default)]
+    allow_query: Vec<String>,
+
+    /// Clients refused whatever `allow_query` says.
+    #[serde(default)]
+    #[builder(default)]
+    deny_query: Vec<String>,
+
+    /// Limits identical UDP responses to each client network.
+    #[serde(default)]
+    #[builder(setter(strip_option), default = None)]
+    response_rate_limit: Option<ResponseRateLimitConfig>,
+
+    /// UDP payload size advertised in the EDNS option of the responses,
+    /// the largest query clients should send.
+    #[serde(default = "default_udp_size")]
+    #[builder(default = default_udp_size())]
+    edns_buffer_size: u16,
+
+    /// Largest UDP response, whatever the client advertises. Larger answers
+    /// are sent without records and with the TC bit, for the client to
+    /// retry over TCP.
+    #[serde(default = "default_udp_size")]
+    #[builder(default = default_udp_size())]
+    max_udp_response_size: u16,
+
+    /// Lower bound of the TTLs of every answer, on top of those of its zone.
+    #[serde(with = "humantime_serde", default)]
+    #[builder(setter(strip_option), default = None)]
+    min_ttl: Option<Duration>,
+
+    /// Upper bound of the TTLs of every answer, on top of those of its zone.
+    #[serde(with = "humantime_serde", default)]
+    #[builder(setter(strip_option), default = None)]
+    max_ttl: Option<Duration>,
+
+    /// How queries of type ANY, a common amplification vector, are answered.
+    #[serde(default)]
+    #[builder(default)]
+    any_queries: AnyQueries,
+
+    /// Maximum number of CNAME records followed when answering a query.
+    #[serde(default = "default_max_cname_depth")]
+    #[builder(default = default_max_cname_depth())]
+    max_cname_depth: usize,
+
+    /// Reload the zones when the config file passed to `Server::watch` changes.
+    #[serde(default)]
+    #[builder(default)]
+    watch_config: bool,
+
+    /// How often the watched config file is checked for changes.
+    #[serde(with = "humantime_serde", default = "default_watch_interval")]
+    #[builder(default = default_watch_interval())]
+    watch_interval: Duration,
+
+    /// OpenTelemetry trace exporter, requires the `otel` feature. It is
+    /// installed by the embedding program, see `telemetry::OtlpExporter`.
+    #[serde(default)]
+    #[builder(setter(strip_option), default = None)]
+    otlp: Option<OtlpConfig>,
+
+    /// Fail to load a config file with keys the config doesn't know, such as
+    /// misspelled ones, or with listen addresses that aren't a host and a
+    /// port, instead of ignoring them.
+    #[serde(default)]
+    #[builder(default)]
+    strict: bool,
+
+    /// ACME DNS-01 challenges, see `Server::set_acme_challenge`.
+    #[serde(default)]
+    #[builder(default)]
+    acme: AcmeConfig,
+}
+
+fn default_udp_workers() -> usize {
+    1
+}
+
+fn default_tcp_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_shutdown_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+/// The size recommended by the DNS flag day 2020, which avoids IP
+/// fragmentation.
+fn default_udp_size() -> u16 {
+    1232
+}
+
+fn default_max_cname_depth() -> usize {
+    16
+}
+
+fn default_watch_interval() -> Duration {
+    Duration::from_secs(5)
+}
+
+impl GeneralConfig {
+    pub fn listen_tcp(&self) -> &[String] {
+        &self.listen_tcp.0
+    }
+
+    pub fn listen_udp(&self) -> &[String] {
+        &self.listen_udp.0
+    }
+
+    pub fn udp_workers(&self) -> usize {
+        self.udp_workers
+    }
+
+    pub fn listen_tls(&self) -> &Option<TlsListenConfig> {
+        &self.listen_tls
+    }
+
+    pub fn listen_quic(&self) -> &Option<String> {
+        &self.listen_quic
+    }
+
+    pub fn listen_https(&self) -> &Option<HttpsListenConfig> {
+        &self.listen_https
+    }
+
+    pub fn listen_admin(&self) -> &Option<AdminListenConfig> {
+        &self.listen_admin
+    }
+
+    pub fn listen_grpc(&self) -> &Option<AdminListenConfig> {
+        &self.listen_grpc
+    }
+
+    pub fn control_socket(&self) -> Option<&Path> {
+        self.control_socket.as_deref()
+    }
+
+    pub fn mdns(&self) -> &Option<MdnsConfig> {
+        &self.mdns
+    }
+
+    pub fn acme(&self) -> &AcmeConfig {
+        &self.acme
+    }
+
+    pub fn nsid(&self) -> Option<&str> {
+        self.nsid.as_deref()
+    }
+
+    pub fn chaos(&self) -> &Option<ChaosConfig> {
+        &self.chaos
+    }
+
+    pub fn tcp_timeout(&self) -> Duration {
+        self.tcp_timeout
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        self.shutdown_timeout
+    }
+
+    pub fn max_cname_depth(&self) -> usize {
+        self.max_cname_depth
+    }
+
+    pub fn any_queries(&self) -> AnyQueries {
+        self.any_queries
+    }
+
+    pub fn min_ttl(&self) -> Option<Duration> {
+        self.min_ttl
+    }
+
+    pub fn max_ttl(&self) -> Option<Duration> {
+        self.max_ttl
+    }
+
+    pub fn edns_buffer_size(&self) -> u16 {
+        self.edns_buffer_size
+    }
 
+    pub fn max_udp_response_size(&self) -> u16 {
+        self.max_udp_response_size
+    }
+
+    pub fn rate_limit(&self) -> &Option<RateLimitConfig> {
+        &self.rate_limit
+    }
+
+    pub fn allow_query(&self) -> &Vec<String> {
+        &self.allow_query
+    }
+
+    pub fn deny_query(&self) -> &Vec<String> {
+        &self.deny_query
+    }
+
+    pub fn response_rate_limit(&self) -> &Option<ResponseRateLimitConfig> {
+        &self.response_rate_limit
+    }
+
+    pub fn watch_config(&self) -> bool {
+        self.watch_config
+    }
+
+    pub fn watch_interval(&self) -> Duration {
+        self.watch_interval
+    }
+
+    pub fn otlp(&self) -> &Option<OtlpConfig> {
+        &self.otlp
+    }
+
+    pub fn strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Listens for UDP and TCP on `address` only.
+    pub(crate) fn listen_on(&mut self, address: &str) {
+        self.listen_udp = ListenAddrs(vec![address.to_string()]);
+        self.listen_tcp = ListenAddrs(vec![address.to_string()]);
+    }
+}
+
+/// Addresses a listener is bound to, written as one address or a list.
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(into = "Vec<String>")]
+pub struct ListenAddrs(Vec<String>);
+
+impl<'de> Deserialize<'de> for ListenAddrs {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct AddrsVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for AddrsVisitor {
+            type Value = ListenAddrs;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("an address or a list of addresses")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, address: &str) -> Result<ListenAddrs, E> {
+                Ok(address.into())
+            }
+
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(
+                self,
+                seq: A,
+            ) -> Result<ListenAddrs, A::Error> {
+                Vec::deserialize(serde::de::value::SeqAccessDeserializer::new(seq)).map(ListenAddrs)
+            }
+        }
+
+        deserializer.deserialize_any(AddrsVisitor)
+    }
+}
+
+impl From<ListenAddrs> for Vec<String> {
+    fn from(value: ListenAddrs) -> Self {
+        value.0
+    }
+}
+
+impl From<&str> for ListenAddrs {
+    fn from(address: &str) -> Self {
+        Self(vec![address.to_string()])
+    }
+}
+
+impl From<String> for ListenAddrs {
+    fn from(address: String) -> Self {
+        Self(vec![address])
+    }
+}
+
+impl From<SocketAddr> for ListenAddrs {
+    fn from(address: SocketAddr) -> Self {
+        Self(vec![address.to_string()])
+    }
+}
+
+impl<T: ToString> From<Vec<T>> for ListenAddrs {
+    fn from(addresses: Vec<T>) -> Self {
+        Self(addresses.iter().map(ToString::to_string).collect())
+    }
+}
+
+/// DNS-over-TLS listener, the certificate files are reloaded when they change.
+#[derive(Serialize, Deserialize, Debug, Clone, derive_builder::Builder)]
+pub struct TlsListenConfig {
+    #[builder(setter(into))]
+    address: String,
+
+    /// PEM encoded certificate chain, the one of `general.acme.certificate`
+    /// when it is left out along with `key`.
+    #[serde(default)]
+    #[builder(setter(into, strip_option), default = None)]
+    cert: Option<PathBuf>,
+
+    /// PEM encoded private key.
+    #[serde(default)]
+    #[builder(setter(into, strip_option), default = None)]
+    key: Option<PathBuf>,
+
+    /// How often the certificate files are checked for changes.
+    #[serde(with = "humantime_serde", default = "default_tls_reload_interval")]
+    #[builder(default = default_tls_reload_interval())]
+    reload_interval: Duration,
+}
+
+fn default_tls_reload_interval() -> Duration {
+    Duration::from_secs(60)
+}
+
+impl TlsListenConfig {
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    pub fn cert(&self) -> Option<&Path> {
+        self.cert.as_deref()
+    }
+
+    pub fn key(&self) -> Option<&Path> {
+        self.key.as_deref()
+    }
+
+    pub fn reload_interval(&self) -> Duration {
+        self.reload_interval
+    }
+}
+
+/// DNS-over-HTTPS listener answering RFC 8484 requests on `path`.
+#[derive(Serialize, Deserialize, Debug, Clone, derive_builder::Builder)]
+pub struct HttpsListenConfig {
+    #[serde(flatten)]
+    tls: TlsListenConfig,
+
+    #[serde(default = "default_https_path")]
+    #[builder(setter(into), default = default_https_path())]
+    path: String,
+}
+
+fn default_https_path() -> String {
+    "/dns-query".to_string()
+}
+
+impl HttpsListenConfig {
+    pub fn tls(&self) -> &TlsListenConfig {
+        &self.tls
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+/// Plain HTTP listener of the admin API, meant to be bound to a trusted
+/// interface.
+#[derive(Serialize, Deserialize, Debug, Clone, derive_builder::Builder)]
+pub struct AdminListenConfig {
+    #[builder(setter(into))]
+    address: String,
+
+    /// Bearer token every request must carry, if set.
+    #[serde(default)]
+    #[builder(setter(into, strip_option), default = None)]
+    token: Option<String>,
+}
+
+impl AdminListenConfig {
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+}
+
+/// TXT records answering ACME DNS-01 challenges (RFC 8555), set at runtime
+/// and removed once they expire.
+#[derive(Serialize, Deserialize, Debug, Clone, derive_builder::Builder)]
+pub struct AcmeConfig {
+    #[serde(with = "humantime_serde", default = "default_acme_ttl")]
+    #[builder(default = default_acme_ttl())]
+    ttl: Duration,
+
+    /// How long a challenge is answered, unless it is cleared before.
+    #[serde(with = "humantime_serde", default = "default_acme_expire")]
+    #[builder(default = default_acme_expire())]
+    expire: Duration,
+
+    /// The acme-dns compatible API of the admin listener, for the acme-dns
+    /// hooks of ACME clients.
+    #[serde(default)]
+    #[builder(setter(strip_option), default = None)]
+    acme_dns: Option<AcmeDnsConfig>,
+
+    /// Certificate of the TLS listeners obtained and renewed from an ACME
+    /// certificate authority, requires the `acme` feature.
+    #[serde(default)]
+    #[builder(setter(strip_option), default = None)]
+    certificate: Option<AcmeCertificateConfig>,
+}
+
+fn default_acme_ttl() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_acme_expire() -> Duration {
+    Duration::from_secs(3600)
+}
+
+impl Default for AcmeConfig {
+    fn default() -> Self {
+        AcmeConfigBuilder::default().build().unwrap()
+    }
+}
+
+impl AcmeConfig {
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    pub fn expire(&self) -> Duration {
+        self.expire
+    }
+
+    pub fn acme_dns(&self) -> &Option<AcmeDnsConfig> {
+        &self.acme_dns
+    }
+
+    pub fn certificate(&self) -> &Option<AcmeCertificateConfig> {
+        &self.certificate
+    }
+}
+
+/// A certificate for `domains` validated through the DNS-01 challenges of
+/// the server, kept in `cert_dir` as `cert.pem` and `key.pem` along with
+/// the account key `account.key`.
+#[derive(Serialize, Deserialize, Debug, Clone, derive_builder::Builder)]
+pub struct AcmeCertificateConfig {
+    /// Directory URL of the certificate authority.
+    #[serde(default = "default_acme_directory")]
+    #[builder(setter(into), default = default_acme_directory())]
+    directory: String,
+
+    /// Names of the certificate, whose `_acme-challenge` names must be in
+    /// the zones of the server.
+    #[builder(setter(into))]
+    domains: Vec<String>,
+
+    /// Contact address of the account.
+    #[serde(default)]
+    #[builder(setter(into, strip_option), default = None)]
+    email: Option<String>,
+
+    #[builder(setter(into))]
+    cert_dir: PathBuf,
+
+    /// How long before it expires the certificate is renewed.
+    #[serde(with = "humantime_serde", default = "default_acme_renew_before")]
+    #[builder(default = default_acme_renew_before())]
+    renew_before: Duration,
+
+    /// PEM encoded CA certificate trusted for the directory, for a private
+    /// certificate authority.
+    #[serde(default)]
+    #[builder(setter(into, strip_option), default = None)]
+    ca_file: Option<PathBuf>,
+}
+
+fn default_acme_directory() -> String {
+    "https://acme-v02.api.letsencrypt.org/directory".to_string()
+}
+
+fn default_acme_renew_before() -> Duration {
+    Duration::from_secs(30 * 86400)
+}
+
+impl AcmeCertificateConfig {
+    pub fn directory(&self) -> &str {
+        &self.directory
+    }
+
+    pub fn domains(&self) -> &[String] {
+        &self.domains
+    }
+
+    pub fn email(&self) -> Option<&str> {
+        self.email.as_deref()
+    }
+
+    pub fn cert_dir(&self) -> &Path {
+        &self.cert_dir
+    }
+
+    pub fn renew_before(&self) -> Duration {
+        self.renew_before
+    }
+
+    pub fn ca_file(&self) -> Option<&Path> {
+        self.ca_file.as_deref()
+    }
+
+    pub fn cert_path(&self) -> PathBuf {
+        self.cert_dir.join("cert.pem")
+    }
+
+    pub fn key_path(&self) -> PathBuf {
+        self.cert_dir.join("key.pem")
+    }
+
+    pub fn account_key_path(&self) -> PathBuf {
+        self.cert_dir.join("account.key")
+    }
+}
+
+/// Accounts of the acme-dns API, each updating the challenge of
+/// `<subdomain>.<domain>`, to which the `_acme-challenge` names of the
+/// domains it validates are CNAMEs.
+#[derive(Serialize, Deserialize, Debug, Clone, derive_builder::Builder)]
+pub struct AcmeDnsConfig {
+    #[builder(setter(into))]
+    domain: String,
+
+    #[serde(default)]
+    #[builder(default)]
+    accounts: Vec<AcmeDnsAccount>,
+}
+
+impl AcmeDnsConfig {
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    pub fn accounts(&self) -> &Vec<AcmeDnsAccount> {
+        &self.accounts
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, derive_builder::Builder)]
+pub struct AcmeDnsAccount {
+    #[builder(setter(into))]
+    username: String,
+
+    #[builder(setter(into))]
+    password: String,
+
+    #[builder(setter(into))]
+    subdomain: String,
+}
+
+impl AcmeDnsAccount {
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    pub fn password(&self) -> &str {
+        &self.password
+    }
+
+    pub fn subdomain(&self) -> &str {
+        &self.subdomain
+    }
+}
+
+/// Multicast DNS responder (RFC 6762). Its records are announced when the
+/// server starts and withdrawn when it shuts down.
+#[derive(Serialize, Deserialize, Debug, Clone, derive_builder::Builder)]
+pub struct MdnsConfig {
+    /// Port of the multicast group, 5353 except for tests.
+    #[serde(default = "default_mdns_port")]
+    #[builder(default = default_mdns_port())]
+    port: u16,
+
+    /// Joins only 224.0.0.251, not ff02::fb.
+    #[serde(default)]
+    #[builder(default)]
+    ipv4_only: bool,
+}
+
+fn default_mdns_port() -> u16 {
+    5353
+}
+
+impl MdnsConfig {
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn ipv4_only(&self) -> bool {
+        self.ipv4_only
+    }
+}
+
+/// Strings answered to the CHAOS class TXT queries, each refused when unset.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, derive_builder::Builder)]
+pub struct ChaosConfig {
+    /// Answer to `version.bind` and `version.server`.
+    #[serde(default)]
+    #[builder(setter(into, strip_option), default = None)]
+    version: Option<String>,
+
+    /// Answer to `hostname.bind`.
+    #[serde(default)]
+    #[builder(setter(into, strip_option), default = None)]
+    hostname: Option<String>,
+
+    /// Answer to `id.server` (RFC 4892).
+    #[serde(default)]
+    #[builder(setter(into, strip_option), default = None)]
+    id: Option<String>,
+}
+
+impl ChaosConfig {
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    pub fn hostname(&self) -> Option<&str> {
+        self.hostname.as_deref()
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+}
+
+/// Token bucket per client network, refilled at `queries_per_second` up to
+/// `burst` queries. Clients are grouped by the networks of `ipv4_prefix` and
+/// `ipv6_prefix` bits.
+#[derive(Serialize, Deserialize, Debug, Clone, derive_builder::Builder)]
+pub struct RateLimitConfig {
+    queries_per_second: u32,
+
+    /// Queries allowed at once, `queries_per_second` by default.
+    #[serde(default)]
+    #[builder(setter(strip_option), default = None)]
+    burst: Option<u32>,
+
+    #[serde(default = "default_ipv4_prefix")]
+    #[builder(default = default_ipv4_prefix())]
+    ipv4_prefix: u8,
+
+    #[serde(default = "default_ipv6_prefix")]
+    #[builder(default = default_ipv6_prefix())]
+    ipv6_prefix: u8,
+
+    /// What happens to queries over the limit.
+    #[serde(default)]
+    #[builder(default)]
+    action: RateLimitAction,
+
+    /// Client networks tracked at once, the least recently seen are
+    /// forgotten first.
+    #[serde(default = "default_rate_limit_clients")]
+    #[builder(default = default_rate_limit_clients())]
+    max_clients: usize,
+}
+
+/// How queries of type ANY are answered.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AnyQueries {
+    /// Every record of the name.
+    #[default]
+    Full,
+    /// A single HINFO record (RFC 8482), or the first RRset and its
+    /// signatures when the answer is signed.
+    Minimal,
+    /// Refused.
+    Refuse,
+}
+
+/// How queries over the rate limit are answered.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitAction {
+    /// An empty response with the TC bit, so that clients retry over TCP.
+    #[default]
+    Truncate,
+    /// No response at all.
+    Drop,
+}
+
+fn default_ipv4_prefix() -> u8 {
+    32
+}
+
+fn default_ipv6_prefix() -> u8 {
+    64
+}
+
+fn default_rate_limit_clients() -> usize {
+    100000
+}
+
+impl RateLimitConfig {
+    pub fn queries_per_second(&self) -> u32 {
+        self.queries_per_second
+    }
+
+    pub fn burst(&self) -> u32 {
+        self.burst.unwrap_or(self.queries_per_second)
+    }
+
+    pub fn ipv4_prefix(&self) -> u8 {
+        self.ipv4_prefix
+    }
+
+    pub fn ipv6_prefix(&self) -> u8 {
+        self.ipv6_prefix
+    }
+
+    pub fn action(&self) -> RateLimitAction {
+        self.action
+    }
+
+    pub fn max_clients(&self) -> usize {
+        self.max_clients
+    }
+}
+
+/// Response rate limiting (RRL) as in BIND: identical responses to a client
+/// network beyond their rate per second are limited, averaged over `window`.
+/// A rate of zero leaves the kind of responses unlimited.
+#[derive(Serialize, Deserialize, Debug, Clone, derive_builder::Builder)]
+pub struct ResponseRateLimitConfig {
+    /// Answers of one name and type, and empty answers of one zone.
+    responses_per_second: u32,
+
+    /// NXDOMAIN answers of one zone, `responses_per_second` by default.
+    #[serde(default)]
+    #[builder(setter(strip_option), default = None)]
+    nxdomains_per_second: Option<u32>,
+
+    /// Other errors, `responses_per_second` by default.
+    #[serde(default)]
+    #[builder(setter(strip_option), default = None)]
+    errors_per_second: Option<u32>,
+
+    #[serde(with = "humantime_serde", default = "default_rrl_window")]
+    #[builder(default = default_rrl_window())]
+    window: Duration,
+
+    /// Every `slip`-th limited response is sent truncated, so that real
+    /// clients retry over TCP, and the others dropped. Zero drops them all.
+    #[serde(default = "default_rrl_slip")]
+    #[builder(default = default_rrl_slip())]
+    slip: u32,
+
+    #[serde(default = "default_subnet_ipv4_prefix")]
+    #[builder(default = default_subnet_ipv4_prefix())]
+    ipv4_prefix: u8,
+
+    #[serde(default = "default_subnet_ipv6_prefix")]
+    #[builder(default = default_subnet_ipv6_prefix())]
+    ipv6_prefix: u8,
+
+    /// Client addresses or networks never limited.
+    #[serde(default)]
+    #[builder(default)]
+    exempt_clients: Vec<String>,
+
+    /// Accounts tracked at once, the least recently used are forgotten
+    /// first.
+    #[serde(default = "default_rate_limit_clients")]
+    #[builder(default = default_rate_limit_clients())]
+    max_entries: usize,
+}
+
+fn default_rrl_window() -> Duration {
+    Duration::from_secs(15)
+}
+
+fn default_rrl_slip() -> u32 {
+    2
+}
+
+fn default_subnet_ipv4_prefix() -> u8 {
+    24
+}
+
+fn default_subnet_ipv6_prefix() -> u8 {
+    56
+}
+
+impl ResponseRateLimitConfig {
+    pub fn responses_per_second(&self) -> u32 {
+        self.responses_per_second
+    }
+
+    pub fn nxdomains_per_second(&self) -> u32 {
+        self.nxdomains_per_second
+            .unwrap_or(self.responses_per_second)
+    }
+
+    pub fn errors_per_second(&self) -> u32 {
+        self.errors_per_second.unwrap_or(self.responses_per_second)
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    pub fn slip(&self) -> u32 {
+        self.slip
+    }
+
+    pub fn ipv4_prefix(&self) -> u8 {
+        self.ipv4_prefix
+    }
+
+    pub fn ipv6_prefix(&self) -> u8 {
+        self.ipv6_prefix
+    }
+
+    pub fn exempt_clients(&self) -> &[String] {
+        &self.exempt_clients
+    }
+
+    pub fn max_entries(&self) -> usize {
+        self.max_entries
+    }
+}
+
+/// Collector receiving the spans of handled requests over OTLP/gRPC.
+#[derive(Serialize, Deserialize, Debug, Clone, derive_builder::Builder)]
+pub struct OtlpConfig {
+    #[serde(default = "default_otlp_endpoint")]
+    #[builder(setter(into), default = default_otlp_endpoint())]
+    endpoint: String,
+
+    /// `service.name` of the exported spans.
+    #[serde(default = "default_otlp_service_name")]
+    #[builder(setter(into), default = default_otlp_service_name())]
+    service_name: String,
+
+    /// Time to wait for the collector to accept a batch of spans.
+    #[serde(with = "humantime_serde", default = "default_otlp_timeout")]
+    #[builder(default = default_otlp_timeout())]
+    timeout: Duration,
+}
+
+fn default_otlp_endpoint() -> String {
+    "http://localhost:4317".to_string()
+}
+
+fn default_otlp_service_name() -> String {
+    "libdns".to_string()
+}
+
+fn default_otlp_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+impl OtlpConfig {
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    pub fn service_name(&self) -> &str {
+        &self.service_name
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+}
+
+/// Domain blocklists, answering the names they hold and their subdomains
+/// with `response` rather than forwarding them. Names of the configured
+/// zones are never blocked.
+#[derive(Serialize, Deserialize, Debug, Clone, derive_builder::Builder)]
+pub struct BlocklistConfig {
+    /// Files or `http(s)://` URLs, the latter requiring the `blocklist-url`
+    /// feature, of hosts files (`0.0.0.0 ads.example.com`) or lists of one
+    /// domain per line. `#` starts a comment.
+    sources: Vec<String>,
+
+    /// Domains never blocked, along with their subdomains.
+    #[serde(default)]
+    #[builder(default)]
+    allow: Vec<String>,
+
+    /// How often the sources are loaded again.
+    #[serde(with = "humantime_serde", default = "default_blocklist_refresh")]
+    #[builder(default = default_blocklist_refresh())]
+    refresh: Duration,
+
+    /// How long fetching a URL may take, connecting included, before the
+    /// source fails.
+    #[serde(with = "humantime_serde", default = "default_blocklist_timeout")]
+    #[builder(default = default_blocklist_timeout())]
+    timeout: Duration,
+
+    #[serde(default)]
+    #[builder(default)]
+    response: BlockResponse,
+
+    /// Address A queries of blocked names are answered with by `sinkhole`.
+    #[serde(default)]
+    #[builder(setter(strip_option), default = None)]
+    sinkhole_ipv4: Option<Ipv4Addr>,
+
+    /// Address AAAA queries of blocked names are answered with by
+    /// `sinkhole`.
+    #[serde(default)]
+    #[builder(setter(strip_option), default = None)]
+    sinkhole_ipv6: Option<Ipv6Addr>,
+
+    /// TTL of the records answering blocked names.
+    #[serde(with = "humantime_serde", default = "default_blocklist_ttl")]
+    #[builder(default = default_blocklist_ttl())]
+    ttl: Duration,
+}
+
+/// A split-horizon view, serving its own zones to the clients it matches,
+/// e.g. internal addresses to internal clients.
+#[derive(Serialize, Deserialize, Debug, Clone, derive_builder::Builder)]
+pub struct ViewConfig {
+    name: String,
+
+    /// Addresses, networks or TSIG keys (`key <name>`) of the clients.
+    match_clients: Vec<String>,
+
+    #[serde(default)]
+    #[builder(default)]
+    zones: Zone,
+}
+
+impl ViewConfig {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn match_clients(&self) -> &Vec<String> {
+        &self.match_clients
+    }
+
+    pub fn zones(&self) -> &Zone {
+        &self.zones
+    }
+}
+
+/// Looks up the queried names matching `from` as `to`, e.g. a legacy host
+/// name as its name in a new zone.
+#[derive(Serialize, Deserialize, Debug, Clone, derive_builder::Builder)]
+pub struct RewriteRule {
+    /// How `from` matches the queried names.
+    #[serde(rename = "match", default)]
+    #[builder(default)]
+    match_kind: RewriteMatch,
+
+    #[builder(setter(into))]
+    from: String,
+
+    /// The name looked up, with `$1` style references to the groups of a
+    /// `regex` rule.
+    #[builder(setter(into))]
+    to: String,
+
+    /// Renames the records of the looked up name in the answers back to the
+    /// queried name.
+    #[serde(default)]
+    #[builder(default)]
+    rewrite_answers: bool,
+}
+
+impl RewriteRule {
+    pub fn match_kind(&self) -> RewriteMatch {
+        self.match_kind
+    }
+
+    pub fn from(&self) -> &str {
+        &self.from
+    }
+
+    pub fn to(&self) -> &str {
+        &self.to
+    }
+
+    pub fn rewrite_answers(&self) -> bool {
+        self.rewrite_answers
+    }
+}
+
+/// How a [`RewriteRule`] matches names.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RewriteMatch {
+    /// The name itself.
+    #[default]
+    Exact,
+    /// The name and the names below it, whose suffix is replaced.
+    Suffix,
+    /// A regular expression matching the whole name, without the trailing
+    /// dot and in lowercase.
+    Regex,
+}
+
+/// MaxMind databases locating clients for the `geo` selectors of records,
+/// which require the `geoip` feature.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, derive_builder::Builder)]
+pub struct GeoIpConfig {
+    /// GeoIP2 or GeoLite2 Country or City database, for `continent:` and
+    /// `country:` selectors.
+    #[serde(default)]
+    #[builder(setter(into, strip_option), default = None)]
+    database: Option<PathBuf>,
+
+    /// GeoLite2 ASN database, for `asn:` selectors.
+    #[serde(default)]
+    #[builder(setter(into, strip_option), default = None)]
+    asn_database: Option<PathBuf>,
+}
+
+impl GeoIpConfig {
+    pub fn database(&self) -> Option<&Path> {
+        self.database.as_deref()
+    }
+
+    pub fn asn_database(&self) -> Option<&Path> {
+        self.asn_database.as_deref()
+    }
+}
+
+/// Kubernetes API server watched for Services, Endpoints and Ingresses. The
+/// backend serves `domain` as a zone of its own, kept in sync with the
+/// cluster: `<service>.<namespace>.svc.<domain>` resolves to the cluster IPs
+/// of a Service, or to the ready endpoints of a headless one, and the hosts
+/// of Ingresses within `domain` to their load balancers.
+#[derive(Serialize, Deserialize, Debug, Clone, derive_builder::Builder)]
+pub struct KubernetesConfig {
+    #[serde(default = "default_kubernetes_domain")]
+    #[builder(setter(into), default = default_kubernetes_domain())]
+    domain: String,
+
+    /// URL of the API server, that of the cluster the server runs in by
+    /// default.
+    #[serde(default)]
+    #[builder(setter(into, strip_option), default = None)]
+    api_server: Option<String>,
+
+    /// Bearer token file, the service account token by default.
+    #[serde(default)]
+    #[builder(setter(into, strip_option), default = None)]
+    token_file: Option<PathBuf>,
+
+    /// CA certificate of the API server, the service account CA by default.
+    #[serde(default)]
+    #[builder(setter(into, strip_option), default = None)]
+    ca_file: Option<PathBuf>,
+
+    /// Namespaces watched, every namespace when empty.
+    #[serde(default)]
+    #[builder(default)]
+    namespaces: Vec<String>,
+
+    #[serde(with = "humantime_serde", default = "default_kubernetes_ttl")]
+    #[builder(default = default_kubernetes_ttl())]
+    ttl: Duration,
+}
+
+fn default_kubernetes_domain() -> String {
+    "cluster.local".to_string()
+}
+
+fn default_kubernetes_ttl() -> Duration {
+    Duration::from_secs(30)
+}
+
+impl KubernetesConfig {
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    pub fn api_server(&self) -> Option<&str> {
+        self.api_server.as_deref()
+    }
+
+    pub fn token_file(&self) -> Option<&Path> {
+        self.token_file.as_deref()
+    }
+
+    pub fn ca_file(&self) -> Option<&Path> {
+        self.ca_file.as_deref()
+    }
+
+    pub fn namespaces(&self) -> &Vec<String> {
+        &self.namespaces
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+}
+
+/// Docker daemon watched for containers starting and stopping. The backend
+/// serves `domain` as a zone of its own: `<container>.<domain>` resolves to
+/// the addresses of a running container, unless its labels say otherwise:
+/// `libdns.enable=false` leaves it out, and `libdns.name` lists the names it
+/// has instead, comma separated and relative to `domain`.
+#[derive(Serialize, Deserialize, Debug, Clone, derive_builder::Builder)]
+pub struct DockerConfig {
+    #[serde(default = "default_docker_domain")]
+    #[builder(setter(into), default = default_docker_domain())]
+    domain: String,
+
+    /// Unix socket of the daemon.
+    #[serde(default = "default_docker_socket")]
+    #[builder(setter(into), default = default_docker_socket())]
+    socket: PathBuf,
+
+    /// Network whose addresses are published, those of every network the
+    /// container is attached to by default.
+    #[serde(default)]
+    #[builder(setter(into, strip_option), default = None)]
+    network: Option<String>,
+
+    #[serde(with = "humantime_serde", default = "default_docker_ttl")]
+    #[builder(default = default_docker_ttl())]
+    ttl: Duration,
+}
+
+fn default_docker_domain() -> String {
+    "docker.internal".to_string()
+}
+
+fn default_docker_socket() -> PathBuf {
+    PathBuf::from("/var/run/docker.sock")
+}
+
+fn default_docker_ttl() -> Duration {
+    Duration::from_secs(30)
+}
+
+impl DockerConfig {
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    pub fn socket(&self) -> &Path {
+        &self.socket
+    }
+
+    pub fn network(&self) -> Option<&str> {
+        self.network.as_deref()
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+}
+
+/// etcd cluster holding the records of `zones` in the SkyDNS schema, as the
+/// CoreDNS etcd plugin reads them: the key `/skydns/local/example/www` holds
+/// a JSON value such as `{"host": "192.0.2.1", "port": 80}` for
+/// `www.example.local`. The zones are watched for changes, so that servers
+/// sharing the cluster answer alike without reloads.
+#[derive(Serialize, Deserialize, Debug, Clone, derive_builder::Builder)]
+pub struct EtcdConfig {
+    /// URLs of the etcd members, tried in order.
+    #[serde(default = "default_etcd_endpoints")]
+    #[builder(default = default_etcd_endpoints())]
+    endpoints: Vec<String>,
+
+    /// Key prefix of the records.
+    #[serde(default = "default_etcd_prefix")]
+    #[builder(setter(into), default = default_etcd_prefix())]
+    prefix: String,
+
+    /// Zones served from the records, each record belonging to the longest
+    /// zone its name is within.
+    zones: Vec<String>,
+
+    /// CA certificate of `https://` endpoints, the system roots by default.
+    #[serde(default)]
+    #[builder(setter(into, strip_option), default = None)]
+    ca_file: Option<PathBuf>,
+
+    /// TTL of the records whose value sets none.
+    #[serde(with = "humantime_serde", default = "default_etcd_ttl")]
+    #[builder(default = default_etcd_ttl())]
+    ttl: Duration,
+}
+
+fn default_etcd_endpoints() -> Vec<String> {
+    vec!["http://127.0.0.1:2379".to_string()]
+}
+
+fn default_etcd_prefix() -> String {
+    "/skydns".to_string()
+}
+
+fn default_etcd_ttl() -> Duration {
+    Duration::from_secs(300)
+}
+
+impl EtcdConfig {
+    pub fn endpoints(&self) -> &Vec<String> {
+        &self.endpoints
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn zones(&self) -> &Vec<String> {
+        &self.zones
+    }
+
+    pub fn ca_file(&self) -> Option<&Path> {
+        self.ca_file.as_deref()
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+}
+
+/// Consul agent whose catalog is watched with blocking queries. The backend
+/// serves `domain` as a zone of its own, named as the Consul DNS interface
+/// names things: `<service>.service.<domain>` and `<tag>.<service>.service.<domain>`
+/// resolve to the healthy instances of a service with A/AAAA and SRV records,
+/// and `<node>.node.<domain>` to the address of a node.
+#[derive(Serialize, Deserialize, Debug, Clone, derive_builder::Builder)]
+pub struct ConsulConfig {
+    /// URL of the HTTP API of the agent.
+    #[serde(default = "default_consul_address")]
+    #[builder(setter(into), default = default_consul_address())]
+    address: String,
+
+    #[serde(default = "default_consul_domain")]
+    #[builder(setter(into), default = default_consul_domain())]
+    domain: String,
+
+    /// Datacenter of the services, that of the agent by default.
+    #[serde(default)]
+    #[builder(setter(into, strip_option), default = None)]
+    datacenter: Option<String>,
+
+    /// ACL token of the requests.
+    #[serde(default)]
+    #[builder(setter(into, strip_option), default = None)]
+    token: Option<String>,
+
+    /// Whether instances with a failing health check are left out.
+    #[serde(default = "default_consul_only_passing")]
+    #[builder(default = true)]
+    only_passing: bool,
+
+    #[serde(with = "humantime_serde", default = "default_consul_ttl")]
+    #[builder(default = default_consul_ttl())]
+    ttl: Duration,
+}
+
+fn default_consul_address() -> String {
+    "http://127.0.0.1:8500".to_string()
+}
+
+fn default_consul_domain() -> String {
+    "consul".to_string()
+}
+
+fn default_consul_only_passing() -> bool {
+    true
+}
+
+fn default_consul_ttl() -> Duration {
+    Duration::from_secs(30)
+}
+
+impl ConsulConfig {
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    pub fn datacenter(&self) -> Option<&str> {
+        self.datacenter.as_deref()
+    }
+
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+
+    pub fn only_passing(&self) -> bool {
+        self.only_passing
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+}
+
+/// Redis server of the zones with `backend = "redis"`. The records of a zone
+/// are the fields of the hash `<prefix><zone>`: a field `<name> <type>`, the
+/// name relative to the zone as in a zone file, holds one `<ttl> <data>`
+/// line per record. Publishing a zone name on `channel` makes every server
+/// fetch that zone again, publishing `*` all of them.
+#[derive(Serialize, Deserialize, Debug, Clone, derive_builder::Builder)]
+pub struct RedisConfig {
+    #[serde(default = "default_redis_url")]
+    #[builder(setter(into), default = default_redis_url())]
+    url: String,
+
+    #[serde(default = "default_redis_prefix")]
+    #[builder(setter(into), default = default_redis_prefix())]
+    prefix: String,
+
+    #[serde(default = "default_redis_channel")]
+    #[builder(setter(into), default = default_redis_channel())]
+    channel: String,
+}
+
+fn default_redis_url() -> String {
+    "redis://127.0.0.1:6379".to_string()
+}
+
+fn default_redis_prefix() -> String {
+    "libdns:zone:".to_string()
+}
+
+fn default_redis_channel() -> String {
+    "libdns:invalidate".to_string()
+}
+
+impl RedisConfig {
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn channel(&self) -> &str {
+        &self.channel
+    }
+}
+
+/// PostgreSQL database in the generic SQL schema of PowerDNS. Its `NATIVE`
+/// and `MASTER` domains are served as zones of their own, read again every
+/// `refresh`, so the tools managing a PowerDNS install keep working. Domains
+/// that are also configured zones are left to the config.
+#[derive(Serialize, Deserialize, Debug, Clone, derive_builder::Builder)]
+pub struct PostgresConfig {
+    /// Connection string, either a `postgresql://` URL or `key=value` pairs.
+    /// The connection isn't encrypted.
+    #[serde(default = "default_postgres_connection")]
+    #[builder(setter(into), default = default_postgres_connection())]
+    connection: String,
+
+    #[serde(with = "humantime_serde", default = "default_postgres_refresh")]
+    #[builder(default = default_postgres_refresh())]
+    refresh: Duration,
+}
+
+fn default_postgres_connection() -> String {
+    "host=localhost user=pdns dbname=pdns".to_string()
+}
+
+fn default_postgres_refresh() -> Duration {
+    Duration::from_secs(60)
+}
+
+impl PostgresConfig {
+    pub fn connection(&self) -> &str {
+        &self.connection
+    }
+
+    pub fn refresh(&self) -> Duration {
+        self.refresh
+    }
+}
+
+/// How queries of blocked names are answered.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BlockResponse {
+    /// NXDOMAIN, as if the name didn't exist.
+    #[default]
+    NxDomain,
+    /// `0.0.0.0` for A and `::` for AAAA queries, no records for others.
+    Null,
+    /// The `sinkhole_ipv4` and `sinkhole_ipv6` addresses.
+    Sinkhole,
+}
+
+fn default_blocklist_refresh() -> Duration {
+    Duration::from_secs(24 * 60 * 60)
+}
+
+fn default_blocklist_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_blocklist_ttl() -> Duration {
+    Duration::from_secs(60)
+}
+
+impl BlocklistConfig {
+    pub fn sources(&self) -> &Vec<String> {
+        &self.sources
+    }
+
+    pub fn allow(&self) -> &Vec<String> {
+        &self.allow
+    }
+
+    pub fn refresh(&self) -> Duration {
+        self.refresh
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn response(&self) -> BlockResponse {
+        self.response
+    }
+
+    pub fn sinkhole_ipv4(&self) -> Option<Ipv4Addr> {
+        self.sinkhole_ipv4
+    }
+
+    pub fn sinkhole_ipv6(&self) -> Option<Ipv6Addr> {
+        self.sinkhole_ipv6
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+}
+
+/// Files in the hosts(5) format, whose names are answered with A and AAAA
+/// records and whose addresses with PTR records, as dnsmasq does. Names of
+/// the configured zones are answered by the zones. The files are read again
+/// whenever they change.
+#[derive(Serialize, Deserialize, Debug, Clone, derive_builder::Builder)]
+pub struct HostsConfig {
+    #[serde(default = "default_hosts_files")]
+    #[builder(default = default_hosts_files())]
+    files: Vec<PathBuf>,
+
+    /// Domain of the names without a dot, answered under it as well, like
+    /// the `expand-hosts` of dnsmasq.
+    #[serde(default)]
+    #[builder(setter(into, strip_option), default = None)]
+    domain: Option<String>,
+
+    /// How often the files are checked for changes.
+    #[serde(with = "humantime_serde", default = "default_hosts_interval")]
+    #[builder(default = default_hosts_interval())]
+    interval: Duration,
+
+    #[serde(with = "humantime_serde", default = "default_hosts_ttl")]
+    #[builder(default = default_hosts_ttl())]
+    ttl: Duration,
+}
+
+/// Addresses answered to the A and AAAA queries of names that don't exist
+/// in place of NXDOMAIN, to send clients to a captive portal or a search
+/// page. Queries of other types, or of a family without addresses, and
+/// blocked names keep NXDOMAIN.
+#[derive(Serialize, Deserialize, Debug, Clone, derive_builder::Builder)]
+pub struct NxdomainRedirectConfig {
+    /// Domains whose names are redirected, every name when empty.
+    #[serde(default)]
+    #[builder(default)]
+    suffixes: Vec<String>,
+
+    /// Domains whose names are left NXDOMAIN.
+    #[serde(default)]
+    #[builder(default)]
+    exclude: Vec<String>,
+
+    addresses: Vec<IpAddr>,
+
+    #[serde(with = "humantime_serde", default = "default_redirect_ttl")]
+    #[builder(default = default_redirect_ttl())]
+    ttl: Duration,
+}
+
+fn default_redirect_ttl() -> Duration {
+    Duration::from_secs(60)
+}
+
+impl NxdomainRedirectConfig {
+    pub fn suffixes(&self) -> &Vec<String> {
+        &self.suffixes
+    }
+
+    pub fn exclude(&self) -> &Vec<String> {
+        &self.exclude
+    }
+
+    pub fn addresses(&self) -> &Vec<IpAddr> {
+        &self.addresses
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+}
+
+fn default_hosts_files() -> Vec<PathBuf> {
+    vec![PathBuf::from("/etc/hosts")]
+}
+
+fn default_hosts_interval() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn default_hosts_ttl() -> Duration {
+    Duration::from_secs(60)
+}
+
+impl HostsConfig {
+    pub fn files(&self) -> &Vec<PathBuf> {
+        &self.files
+    }
+
+    pub fn domain(&self) -> Option<&str> {
+        self.domain.as_deref()
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+}
+
+/// Whether a blocklist source is fetched over HTTP rather than read from a
+/// file.
+pub(crate) fn is_url(source: &str) -> bool {
+    source.starts_with("http://") || source.starts_with("https://")
+}
+
+/// Upstream resolvers for names outside the configured zones.
+#[derive(Serialize, Deserialize, Debug, Clone, derive_builder::Builder)]
+pub struct ForwardConfig {
+    /// Default upstreams: addresses, the port defaulting to 53, or
+    /// `tls://` and `https://` URLs of encrypted ones, see [`Upstream`].
+    #[serde(default)]
+    #[builder(setter(into), default)]
+    upstreams: Vec<String>,
+
+    /// Upstreams for specific domains, taking precedence over `upstreams`.
+    #[serde(default)]
+    #[builder(default)]
+    rules: Vec<ForwardRule>,
+
+    #[serde(default)]
+    #[builder(default)]
+    cache: CacheConfig,
+
+    /// Answer from expired cache entries when no upstream can be reached
+    /// (RFC 8767).
+    #[serde(default)]
+    #[builder(default)]
+    serve_stale: bool,
+
+    /// How long after expiry a cache entry may still be served stale.
+    #[serde(with = "humantime_serde", default = "default_max_stale")]
+    #[builder(default = default_max_stale())]
+    max_stale: Duration,
+
+    /// Time to wait for each upstream before trying the next one.
+    #[serde(with = "humantime_serde", default = "default_forward_timeout")]
+    #[builder(default = default_forward_timeout())]
+    timeout: Duration,
+
+    /// The order in which the upstreams of a query are tried.
+    #[serde(default)]
+    #[builder(default)]
+    policy: UpstreamPolicy,
+
+    /// Failures in a row, timeouts and SERVFAIL, REFUSED or NOTIMP answers,
+    /// after which an upstream is quarantined. Zero disables quarantine.
+    #[serde(default = "default_max_failures")]
+    #[builder(default = default_max_failures())]
+    max_failures: u32,
+
+    /// How long a quarantined upstream is only tried after the others. It
+    /// is quarantined again by its next failure, until it answers.
+    #[serde(with = "humantime_serde", default = "default_quarantine")]
+    #[builder(default = default_quarantine())]
+    quarantine: Duration,
+
+    /// Check upstream answers against their DNSSEC signatures.
+    #[serde(default)]
+    #[builder(default)]
+    dnssec_validation: DnssecValidation,
+
+    /// DS or DNSKEY records, in zone file syntax, trusted by validation
+    /// without asking the parent zone. Defaults to the root zone KSKs.
+    #[serde(default = "default_trust_anchors")]
+    #[builder(setter(into), default = default_trust_anchors())]
+    trust_anchors: Vec<String>,
+
+    /// Sends an EDNS Client Subnet option (RFC 7871) upstream, from the
+    /// client's option or else its address.
+    #[serde(default)]
+    #[builder(setter(strip_option), default = None)]
+    client_subnet: Option<ClientSubnetConfig>,
+
+    /// PEM encoded CA certificates trusted for the certificates of encrypted
+    /// upstreams, in place of the public roots.
+    #[serde(default)]
+    #[builder(setter(into, strip_option), default = None)]
+    ca_file: Option<PathBuf>,
+
+    /// Records answered in place of forwarding for their names, given in
+    /// full: a name holding local data isn't forwarded for any type, while
+    /// the other names of its domain are.
+    #[serde(default, deserialize_with = "record_entries")]
+    #[builder(default)]
+    local_data: Vec<Record>,
+
+    /// Sends the names of UDP queries in random case (0x20 encoding),
+    /// refusing the answers that don't keep it, as a forged answer has to
+    /// guess it on top of the query ID and port.
+    #[serde(default)]
+    #[builder(default)]
+    randomize_case: bool,
+
+    /// Connections kept open to each TCP or TLS upstream, the queries being
+    /// pipelined over them: another is opened while they are all busy.
+    #[serde(default = "default_max_connections")]
+    #[builder(default = default_max_connections())]
+    max_connections: usize,
+}
+
+/// How the upstreams of a query are ordered, quarantined ones last.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum UpstreamPolicy {
+    /// In their configured order, the next one when one fails.
+    #[default]
+    Sequential,
+    /// Lowest smoothed round-trip time first, unmeasured ones before.
+    Fastest,
+    /// In a random order, spreading the queries.
+    Random,
+}
+
+/// Bits of the client address sent upstream in EDNS Client Subnet options.
+/// Shorter prefixes of the client's own option are kept.
+#[derive(Serialize, Deserialize, Debug, Clone, derive_builder::Builder)]
+pub struct ClientSubnetConfig {
+    #[serde(default = "default_subnet_ipv4_prefix")]
+    #[builder(default = default_subnet_ipv4_prefix())]
+    ipv4_prefix: u8,
+
+    #[serde(default = "default_subnet_ipv6_prefix")]
+    #[builder(default = default_subnet_ipv6_prefix())]
+    ipv6_prefix: u8,
+}
+
+impl ClientSubnetConfig {
+    pub fn ipv4_prefix(&self) -> u8 {
+        self.ipv4_prefix
+    }
+
+    pub fn ipv6_prefix(&self) -> u8 {
+        self.ipv6_prefix
+    }
+}
+
+/// How upstream answers are checked against their DNSSEC signatures.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DnssecValidation {
+    /// Answers are passed on unchecked.
+    #[default]
+    Off,
+    /// Bogus answers are logged and passed on without the AD bit.
+    Permissive,
+    /// Bogus answers are replaced with SERVFAIL.
+    Strict,
+}
+
+fn default_trust_anchors() -> Vec<String> {
+    vec![
+        ". IN DS 20326 8 2 E06D44B80B8F1D39A95C0B0D7C65D08458E880409BBC683457104237C7F8EC8D"
+            .to_string(),
+        ". IN DS 38696 8 2 683D2D0ACB8C9B712A1948B27F741219298D0A450D612C483AF444A4C0FB2B16"
+            .to_string(),
+    ]
+}
+
+fn default_forward_timeout() -> Duration {
+    Duration::from_secs(2)
+}
+
+fn default_max_stale() -> Duration {
+    Duration::from_secs(86400)
+}
+
+fn default_max_failures() -> u32 {
+    3
+}
+
+fn default_max_connections() -> usize {
+    2
+}
+
+fn default_quarantine() -> Duration {
+    Duration::from_secs(30)
+}
+
+impl Default for ForwardConfig {
+    fn default() -> Self {
+        ForwardConfigBuilder::default().build().unwrap()
+    }
+}
+
+impl ForwardConfig {
+    pub fn upstreams(&self) -> &Vec<String> {
+        &self.upstreams
+    }
+
+    pub fn rules(&self) -> &Vec<ForwardRule> {
+        &self.rules
+    }
+
+    pub fn cache(&self) -> &CacheConfig {
+        &self.cache
+    }
+
+    pub fn serve_stale(&self) -> bool {
+        self.serve_stale
+    }
+
+    pub fn randomize_case(&self) -> bool {
+        self.randomize_case
+    }
+
+    pub fn max_connections(&self) -> usize {
+        self.max_connections
+    }
+
+    pub fn max_stale(&self) -> Duration {
+        self.max_stale
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn policy(&self) -> UpstreamPolicy {
+        self.policy
+    }
+
+    pub fn max_failures(&self) -> u32 {
+        self.max_failures
+    }
+
+    pub fn quarantine(&self) -> Duration {
+        self.quarantine
+    }
+
+    pub fn upstream_servers(&self) -> anyhow::Result<Vec<Upstream>> {
+        parse_upstream_servers(&self.upstreams)
+    }
+
+    pub fn dnssec_validation(&self) -> DnssecValidation {
+        self.dnssec_validation
+    }
+
+    pub fn trust_anchors(&self) -> &Vec<String> {
+        &self.trust_anchors
+    }
+
+    pub fn client_subnet(&self) -> &Option<ClientSubnetConfig> {
+        &self.client_subnet
+    }
+
+    pub fn ca_file(&self) -> Option<&Path> {
+        self.ca_file.as_deref()
+    }
+
+    pub fn local_data(&self) -> &Vec<Record> {
+        &self.local_data
+    }
+
+    /// The DS and DNSKEY records of `trust_anchors`.
+    pub fn trust_anchor_records(&self) -> anyhow::Result<Vec<rr::Record>> {
+        // the zone file parser refuses DNSKEY records, which are read here
+        let (dnskeys, others): (Vec<&String>, Vec<&String>) = self
+            .trust_anchors
+            .iter()
+            .partition(|anchor| anchor.split_whitespace().any(|t| t == "DNSKEY"));
+        let mut records = dnskeys
+            .into_iter()
+            .map(|anchor| parse_dnskey_anchor(anchor))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let text = format!(
+            "$TTL 0\n{}\n",
+            others
+                .iter()
+                .map(|s| s.as_str())
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+        let (_, rrsets) = Parser::new(text, None, Some(rr::Name::root()))
+            .parse()
+            .map_err(|e| anyhow!("failed to parse trust anchors: {}", e))?;
+        records.extend(rrsets.into_values().flatten());
+        if let Some(record) = records
+            .iter()
+            .find(|r| !matches!(r.record_type(), rr::RecordType::DS | rr::RecordType::DNSKEY))
+        {
+            bail!("trust anchor {} is not a DS or DNSKEY record", record);
+        }
+        Ok(records)
+    }
+}
+
+/// Reads `<name> [ttl] [class] DNSKEY <flags> <protocol> <algorithm> <key>`.
+fn parse_dnskey_anchor(anchor: &str) -> anyhow::Result<rr::Record> {
+    use hickory_proto::rr::dnssec::rdata::{DNSSECRData, DNSKEY};
+    use hickory_proto::rr::dnssec::Algorithm;
+
+    let invalid = || anyhow!("invalid DNSKEY trust anchor: {}", anchor);
+    let tokens: Vec<&str> = anchor.split_whitespace().collect();
+    let at = tokens
+        .iter()
+        .position(|t| *t == "DNSKEY")
+        .ok_or_else(invalid)?;
+    let [flags, _protocol, algorithm, key @ ..] = &tokens[at + 1..] else {
+        return Err(invalid());
+    };
+    let name = rr::Name::from_str(tokens.first().ok_or_else(invalid)?)?;
+    let flags: u16 = flags.parse().map_err(|_| invalid())?;
+    let algorithm = Algorithm::from_u8(algorithm.parse().map_err(|_| invalid())?);
+    let key = base64::Engine::decode(&base64::engine::general_purpose::STANDARD, key.concat())
+        .map_err(|_| invalid())?;
+    let dnskey = DNSKEY::new(
+        flags & 0x0100 != 0,
+        flags & 0x0001 != 0,
+        flags & 0x0080 != 0,
+        algorithm,
+        key,
+    );
+    Ok(rr::Record::from_rdata(
+        name,
+        0,
+        RData::DNSSEC(DNSSECRData::DNSKEY(dnskey)),
+    ))
+}
+
+/// Cache of forwarded responses.
+#[derive(Serialize, Deserialize, Debug, Clone, derive_builder::Builder)]
+#[serde(default)]
+pub struct CacheConfig {
+    /// Maximum number of cached responses, `0` disables the cache.
+    #[builder(default = "10000")]
+    max_entries: usize,
+
+    /// Lower bound of the TTLs of forwarded answers, and so of how long
+    /// they are cached.
+    #[serde(with = "humantime_serde")]
+    #[builder(setter(strip_option), default = None)]
+    min_ttl: Option<Duration>,
+
+    /// Upper bound of the TTLs of forwarded answers.
+    #[serde(with = "humantime_serde")]
+    #[builder(setter(strip_option), default = None)]
+    max_ttl: Option<Duration>,
+
+    /// File the cached responses are saved to on shutdown and loaded from
+    /// on startup, those expired in between left out.
+    #[builder(setter(into, strip_option), default = None)]
+    persist_file: Option<PathBuf>,
+
+    /// Refreshes the popular responses in the background before they expire.
+    #[builder(setter(strip_option), default = None)]
+    prefetch: Option<PrefetchConfig>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfigBuilder::default().build().unwrap()
+    }
+}
+
+impl CacheConfig {
+    pub fn max_entries(&self) -> usize {
+        self.max_entries
+    }
+
+    pub fn min_ttl(&self) -> Option<Duration> {
+        self.min_ttl
+    }
+
+    pub fn max_ttl(&self) -> Option<Duration> {
+        self.max_ttl
+    }
+
+    pub fn persist_file(&self) -> Option<&Path> {
+        self.persist_file.as_deref()
+    }
+
+    pub fn prefetch(&self) -> Option<&PrefetchConfig> {
+        self.prefetch.as_ref()
+    }
+}
+
+/// When a cached response is forwarded again before it expires: once it was
+/// answered `hits` times, within `window` of its expiry.
+#[derive(Serialize, Deserialize, Debug, Clone, derive_builder::Builder)]
+pub struct PrefetchConfig {
+    #[serde(default = "default_prefetch_hits")]
+    #[builder(default = default_prefetch_hits())]
+    hits: u32,
+
+    #[serde(with = "humantime_serde", default = "default_prefetch_window")]
+    #[builder(default = default_prefetch_window())]
+    window: Duration,
+}
+
+fn default_prefetch_hits() -> u32 {
+    3
+}
+
+fn default_prefetch_window() -> Duration {
+    Duration::from_secs(10)
+}
+
+impl PrefetchConfig {
+    pub fn hits(&self) -> u32 {
+        self.hits
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+}
+
+/// Forwards queries for `domain` and its subdomains to `upstreams`.
+#[derive(Serialize, Deserialize, Debug, Clone, derive_builder::Builder)]
+pub struct ForwardRule {
+    #[builder(setter(into))]
+    domain: String,
+
+    #[builder(setter(into))]
+    upstreams: Vec<String>,
+}
+
+impl ForwardRule {
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    pub fn upstreams(&self) -> &Vec<String> {
+        &self.upstreams
+    }
+
+    pub fn upstream_servers(&self) -> anyhow::Result<Vec<Upstream>> {
+        parse_upstream_servers(&self.upstreams)
+    }
+}
+
+/// A resolver queries are forwarded to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Upstream {
+    /// Over UDP, and TCP for truncated answers: `10.0.0.53` or
+    /// `10.0.0.53:5353`.
+    Plain(SocketAddr),
+    /// DNS-over-TLS (RFC 7858): `tls://<host>[:<port>][#<server name>]`. The
+    /// host is resolved by the system when it isn't an address, the port
+    /// defaults to 853 and the certificate is validated for the server
+    /// name, the host by default.
+    Tls {
+        host: String,
+        port: u16,
+        server_name: String,
+    },
+    /// DNS-over-HTTPS (RFC 8484): the URL queries are POSTed to.
+    Https(String),
+}
+
+impl FromStr for Upstream {
+    type Err = anyhow::Error;
+
+    fn from_str(upstream: &str) -> anyhow::Result<Self> {
+        if let Some(rest) = upstream.strip_prefix("https://") {
+            let authority = rest.split('/').next().unwrap();
+            if authority.is_empty() || authority.contains(char::is_whitespace) {
+                bail!("invalid upstream URL: {}", upstream);
+            }
+            return Ok(Upstream::Https(upstream.to_string()));
+        }
+        let Some(rest) = upstream.strip_prefix("tls://") else {
+            return parse_upstream(upstream).map(Upstream::Plain);
+        };
+        let invalid = || anyhow!("invalid upstream URL: {}", upstream);
+        let (rest, server_name) = match rest.split_once('#') {
+            Some((rest, name)) if !name.is_empty() => (rest, Some(name)),
+            Some(_) => return Err(invalid()),
+            None => (rest, None),
+        };
+        let (host, port) = if let Ok(address) = rest.parse::<SocketAddr>() {
+            (address.ip().to_string(), address.port())
+        } else if let Ok(ip) = rest.parse::<IpAddr>() {
+            (ip.to_string(), 853)
+        } else {
+            match rest.rsplit_once(':') {
+                Some((host, port)) => (host.to_string(), port.parse().map_err(|_| invalid())?),
+                None => (rest.to_string(), 853),
+            }
+        };
+        let is_name = host.parse::<IpAddr>().is_err();
+        if host.is_empty() || (is_name && host.contains(['/', '[', ']', ':', '@'])) {
+            return Err(invalid());
+        }
+        Ok(Upstream::Tls {
+            server_name: server_name.unwrap_or(&host).to_string(),
+            host,
+            port,
+        })
+    }
+}
+
+impl std::fmt::Display for Upstream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Upstream::Plain(address) => write!(f, "{}", address),
+            Upstream::Tls {
+                host,
+                port,
+                server_name,
+            } => {
+                match host.parse::<IpAddr>() {
+                    Ok(IpAddr::V6(ip)) => write!(f, "tls://[{}]:{}", ip, port)?,
+                    _ => write!(f, "tls://{}:{}", host, port)?,
+                }
+                if server_name != host {
+                    write!(f, "#{}", server_name)?;
+                }
+                Ok(())
+            }
+            Upstream::Https(url) => f.write_str(url),
+        }
+    }
+}
+
+fn parse_upstream_servers(upstreams: &[String]) -> anyhow::Result<Vec<Upstream>> {
+    upstreams.iter().map(|upstream| upstream.parse()).collect()
+}
+
+fn parse_upstreams(upstreams: &[String]) -> anyhow::Result<Vec<SocketAddr>> {
+    upstreams
+        .iter()
+        .map(|upstream| parse_upstream(upstream))
+        .collect()
+}
+
+fn parse_upstream(upstream: &str) -> anyhow::Result<SocketAddr> {
+    if let Ok(addr) = upstream.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+    let ip: IpAddr = upstream
+        .parse()
+        .map_err(|_| anyhow!("invalid upstream address: {}", upstream))?;
+    Ok(SocketAddr::new(ip, 53))
+}
+
+/// Shared secret authenticating transfers, NOTIFY and updates with TSIG
+/// (RFC 8945).
+#[derive(Serialize, Deserialize, Debug, Clone, derive_builder::Builder)]
+pub struct TsigKeyConfig {
+    /// Name of the key, which both ends must agree on.
+    #[builder(setter(into))]
+    name: String,
+
+    /// `hmac-sha256`, `hmac-sha384` or `hmac-sha512`.
+    #[serde(default = "default_tsig_algorithm")]
+    #[builder(setter(into), default = default_tsig_algorithm())]
+    algorithm: String,
+
+    /// Base64 encoded secret.
+    #[builder(setter(into))]
+    secret: String,
+}
+
+fn default_tsig_algorithm() -> String {
+    "hmac-sha256".to_string()
+}
+
+impl TsigKeyConfig {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn algorithm(&self) -> &str {
+        &self.algorithm
+    }
+
+    pub fn secret(&self) -> &str {
+        &self.secret
+    }
+}
+
+/// Inline DNSSEC signing of a zone with a key signing key (KSK), which signs
+/// the DNSKEY records, and a zone signing key (ZSK), which signs the others.
+#[derive(Serialize, Deserialize, Debug, Clone, derive_builder::Builder)]
+pub struct DnssecConfig {
+    /// `ecdsap256sha256`, `ecdsap384sha384` or `ed25519`.
+    #[serde(default = "default_dnssec_algorithm")]
+    #[builder(setter(into), default = default_dnssec_algorithm())]
+    algorithm: String,
+
+    /// PKCS#8 file of the KSK, generated when missing. Without it, a key is
+    /// generated at startup and lost on restart.
+    #[serde(default)]
+    #[builder(setter(into, strip_option), default = None)]
+    ksk: Option<PathBuf>,
+
+    /// PKCS#8 file of the ZSK, generated when missing. Without it, a key is
+    /// generated at startup and lost on restart.
+    #[serde(default)]
+    #[builder(setter(into, strip_option), default = None)]
+    zsk: Option<PathBuf>,
+
+    /// How long signatures stay valid. Zones are signed again once half of
+    /// it has passed.
+    #[serde(with = "humantime_serde", default = "default_signature_validity")]
+    #[builder(default = default_signature_validity())]
+    signature_validity: Duration,
+
+    /// Deny the existence of names with NSEC3 records in place of NSEC, so
+    /// the names of the zone can't be walked.
+    #[serde(default)]
+    #[builder(setter(strip_option), default = None)]
+    nsec3: Option<Nsec3Config>,
+}
+
+/// Parameters of the hashed owner names of NSEC3 records (RFC 5155).
+#[derive(Serialize, Deserialize, Debug, Clone, Default, derive_builder::Builder)]
+pub struct Nsec3Config {
+    /// Additional iterations of the hash, RFC 9276 recommends none.
+    #[serde(default)]
+    #[builder(default)]
+    iterations: u16,
+
+    /// Hex encoded salt of the hash, none by default.
+    #[serde(default)]
+    #[builder(setter(into), default)]
+    salt: String,
+
+    /// Leave delegations without a DS record out of the NSEC3 chain, so
+    /// zones with many unsigned delegations are cheaper to sign.
+    #[serde(default)]
+    #[builder(default)]
+    opt_out: bool,
+}
+
+impl Nsec3Config {
+    pub fn iterations(&self) -> u16 {
+        self.iterations
+    }
+
+    pub fn salt(&self) -> &str {
+        &self.salt
+    }
+
+    pub fn opt_out(&self) -> bool {
+        self.opt_out
+    }
+}
+
+fn default_dnssec_algorithm() -> String {
+    "ecdsap256sha256".to_string()
+}
+
+fn default_signature_validity() -> Duration {
+    Duration::from_secs(30 * 24 * 3600)
+}
+
+impl DnssecConfig {
+    pub fn algorithm(&self) -> &str {
+        &self.algorithm
+    }
+
+    pub fn ksk(&self) -> Option<&Path> {
+        self.ksk.as_deref()
+    }
+
+    pub fn zsk(&self) -> Option<&Path> {
+        self.zsk.as_deref()
+    }
+
+    pub fn signature_validity(&self) -> Duration {
+        self.signature_validity
+    }
+
+    pub fn nsec3(&self) -> Option<&Nsec3Config> {
+        self.nsec3.as_ref()
+    }
+}
+
+pub type Zone = HashMap<String, ZoneConfig>; // domain -> zone
+
+/// A zone served by this server.
+///
+/// Besides the full table form, a zone may be written as a bare array of
+/// records (`[[zones."et.internal"]]`), in which case every other option
+/// takes its default.
+///
+/// Records may also be read from an RFC 1035 zone file with `file`, the
+/// inline `records` are added to those of the file.
+///
+/// A `secondary` zone ignores its records and serves a read-only copy
+/// transferred from one of its `primaries` instead. `forward` and `stub`
+/// zones serve no records: queries of their names are forwarded to
+/// `forwarders`, or sent to the name servers learned from `primaries`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, derive_builder::Builder)]
+#[serde(from = "ZoneRepr")]
+pub struct ZoneConfig {
+    #[serde(rename = "type")]
+    #[builder(default)]
+    zone_type: ZoneType,
+
+    /// Servers a secondary zone is transferred from, tried in order, or
+    /// those a stub zone learns its name servers from.
+    #[builder(default)]
+    primaries: Vec<String>,
+
+    /// Upstreams of a forward zone, in the form of `forward.upstreams`. The
+    /// default upstreams answer its names when empty.
+    #[builder(default)]
+    forwarders: Vec<String>,
+
+    /// Whether a forward zone falls back to the default upstreams when its
+    /// forwarders fail.
+    #[builder(default)]
+    forward: ForwardMode,
+
+    #[builder(default)]
+    records: Vec<Record>,
+
+    /// DNS-SD service instances published in the zone.
+    #[builder(default)]
+    services: Vec<Service>,
+
+    /// Ranges of numbered records, like `$GENERATE` in zone files.
+    #[builder(default)]
+    generate: Vec<GenerateConfig>,
+
+    /// Names answering the A and AAAA records of another name, looked up
+    /// when queried.
+    #[builder(default)]
+    aliases: Vec<Alias>,
+
+    /// A catalog zone (RFC 9432): a secondary one has its member zones
+    /// served as secondary zones of the same primaries, a primary one lists
+    /// `members`.
+    #[builder(default)]
+    catalog: bool,
+
+    /// Member zones of a primary catalog zone, every other primary zone
+    /// when empty.
+    #[builder(default)]
+    members: Vec<String>,
+
+    /// BIND style zone file holding records of the zone.
+    #[builder(setter(into, strip_option), default = None)]
+    file: Option<PathBuf>,
+
+    /// Zone file the records are saved to whenever the zone changes, with
+    /// dynamic updates and other runtime changes included. While it exists,
+    /// the zone starts from it in place of `records` and `file`.
+    #[builder(setter(into, strip_option), default = None)]
+    persist_file: Option<PathBuf>,
+
+    /// Where the records of a primary zone are kept: `memory`, or `sqlite`
+    /// for `database`, which requires the `sqlite` feature. Once the database
+    /// holds the zone, the zone comes from it in place of `records` and
+    /// `file`, on reloads too, and every change is saved to it.
+    #[builder(default)]
+    backend: ZoneBackend,
+
+    /// SQLite database of a `sqlite` zone, which may hold any number of
+    /// zones.
+    #[builder(setter(into, strip_option), default = None)]
+    database: Option<PathBuf>,
+
+    #[builder(default)]
+    soa: SoaConfig,
+
+    /// Name servers of the zone, defaults to the SOA mname.
+    #[builder(default)]
+    ns: Vec<String>,
+
+    /// Synthesize PTR records for the A/AAAA records of this zone.
+    #[builder(default)]
+    auto_reverse: bool,
+
+    /// Clients allowed to transfer the zone with AXFR over TCP, as addresses,
+    /// networks or TSIG keys (`key <name>`). Transfers are refused when empty.
+    #[builder(default)]
+    allow_transfer: Vec<String>,
+
+    /// Secondaries sent a NOTIFY whenever the zone changes.
+    #[builder(default)]
+    notify: Vec<String>,
+
+    /// Clients whose NOTIFY triggers a refresh of a secondary zone, as
+    /// addresses, networks or TSIG keys. Defaults to the primaries.
+    #[builder(default)]
+    allow_notify: Vec<String>,
+
+    /// Clients allowed to change the zone with dynamic updates (RFC 2136), as
+    /// addresses, networks or TSIG keys. Updates are refused when empty.
+    #[builder(default)]
+    allow_update: Vec<String>,
+
+    /// Clients whose queries of the zone are answered, as addresses, networks
+    /// or TSIG keys, every client when empty. Others are refused.
+    #[builder(default)]
+    allow_query: Vec<String>,
+
+    /// Clients whose queries of the zone are refused whatever `allow_query`
+    /// says.
+    #[builder(default)]
+    deny_query: Vec<String>,
+
+    /// TSIG key signing the transfer requests and NOTIFY messages sent for
+    /// the zone.
+    #[builder(setter(into, strip_option), default = None)]
+    key: Option<String>,
+
+    /// Signs the records of a primary zone with DNSSEC.
+    #[builder(setter(strip_option), default = None)]
+    dnssec: Option<DnssecConfig>,
+
+    /// Order of the A and AAAA records of a name in answers.
+    #[builder(default)]
+    answer_order: AnswerOrder,
+
+    /// TTL of the records of the zone without one, defaults to the TTL of
+    /// the SOA record.
+    #[serde(with = "ttl")]
+    #[builder(setter(strip_option), default = None)]
+    default_ttl: Option<Duration>,
+
+    /// Lower bound of the TTLs of the records of the zone in answers.
+    #[serde(with = "humantime_serde")]
+    #[builder(setter(strip_option), default = None)]
+    min_ttl: Option<Duration>,
+
+    /// Upper bound of the TTLs of the records of the zone in answers, to
+    /// shorten them all during a migration.
+    #[serde(with = "humantime_serde")]
+    #[builder(setter(strip_option), default = None)]
+    max_ttl: Option<Duration>,
+
+    /// Answers the names of the zone that don't exist with fixed addresses,
+    /// in place of the global `nxdomain_redirect`.
+    #[builder(setter(strip_option), default = None)]
+    nxdomain_redirect: Option<NxdomainRedirectConfig>,
+}
+
+/// A zone written as a list of records or as a table, told apart by its
+/// shape so that errors point into the records.
+#[allow(clippy::large_enum_variant)]
+enum ZoneRepr {
+    Records(Vec<Record>),
+    Table(ZoneTable),
+}
+
+#[derive(Deserialize)]
+struct ZoneTable {
+    #[serde(default, rename = "type")]
+    zone_type: ZoneType,
+    #[serde(default)]
+    primaries: Vec<String>,
+    #[serde(default)]
+    forwarders: Vec<String>,
+    #[serde(default)]
+    forward: ForwardMode,
+    #[serde(default, deserialize_with = "record_entries")]
+    records: Vec<Record>,
+    #[serde(default)]
+    services: Vec<Service>,
+    #[serde(default)]
+    generate: Vec<GenerateConfig>,
+    #[serde(default)]
+    aliases: Vec<Alias>,
+    #[serde(default)]
+    catalog: bool,
+    #[serde(default)]
+    members: Vec<String>,
+    #[serde(default)]
+    file: Option<PathBuf>,
+    #[serde(default)]
+    persist_file: Option<PathBuf>,
+    #[serde(default)]
+    backend: ZoneBackend,
+    #[serde(default)]
+    database: Option<PathBuf>,
+    #[serde(default)]
+    soa: SoaConfig,
+    #[serde(default)]
+    ns: Vec<String>,
+    #[serde(default)]
+    auto_reverse: bool,
+    #[serde(default)]
+    allow_transfer: Vec<String>,
+    #[serde(default)]
+    notify: Vec<String>,
+    #[serde(default)]
+    allow_notify: Vec<String>,
+    #[serde(default)]
+    allow_update: Vec<String>,
+    #[serde(default)]
+    allow_query: Vec<String>,
+    #[serde(default)]
+    deny_query: Vec<String>,
+    #[serde(default)]
+    key: Option<String>,
+    #[serde(default)]
+    dnssec: Option<DnssecConfig>,
+    #[serde(default)]
+    answer_order: AnswerOrder,
+    #[serde(with = "ttl", default)]
+    default_ttl: Option<Duration>,
+    #[serde(with = "humantime_serde", default)]
+    min_ttl: Option<Duration>,
+    #[serde(with = "humantime_serde", default)]
+    max_ttl: Option<Duration>,
+    #[serde(default)]
+    nxdomain_redirect: Option<NxdomainRedirectConfig>,
+}
+
+impl<'de> Deserialize<'de> for ZoneRepr {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ZoneVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for ZoneVisitor {
+            type Value = ZoneRepr;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a list of records or a zone table")
+            }
+
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(self, seq: A) -> Result<ZoneRepr, A::Error> {
+                record_entries(serde::de::value::SeqAccessDeserializer::new(seq))
+                    .map(ZoneRepr::Records)
+            }
+
+            fn visit_map<A: serde::de::MapAccess<'de>>(self, map: A) -> Result<ZoneRepr, A::Error> {
+                ZoneTable::deserialize(serde::de::value::MapAccessDeserializer::new(map))
+                    .map(ZoneRepr::Table)
+            }
+        }
+
+        deserializer.deserialize_any(ZoneVisitor)
+    }
+}
+
+impl From<ZoneRepr> for ZoneConfig {
+    fn from(value: ZoneRepr) -> Self {
+        match value {
+            ZoneRepr::Records(records) => records.into(),
+            ZoneRepr::Table(ZoneTable {
+                zone_type,
+                primaries,
+                forwarders,
+                forward,
+                records,
+                services,
+                generate,
+                aliases,
+                catalog,
+                members,
+                file,
+                persist_file,
+                backend,
+                database,
+                soa,
+                ns,
+                auto_reverse,
+                allow_transfer,
+                notify,
+                allow_notify,
+                allow_update,
+                allow_query,
+                deny_query,
+                key,
+                dnssec,
+                answer_order,
+                default_ttl,
+                min_ttl,
+                max_ttl,
+                nxdomain_redirect,
+            }) => Self {
+                zone_type,
+                primaries,
+                forwarders,
+                forward,
+                records,
+                services,
+                generate,
+                aliases,
+                catalog,
+                members,
+                file,
+                persist_file,
+                backend,
+                database,
+                soa,
+                ns,
+                auto_reverse,
+                allow_transfer,
+                notify,
+                allow_notify,
+                allow_update,
+                allow_query,
+                deny_query,
+                key,
+                dnssec,
+                answer_order,
+                default_ttl,
+                min_ttl,
+                max_ttl,
+                nxdomain_redirect,
+            },
+        }
+    }
+}
+
+impl From<Vec<Record>> for ZoneConfig {
+    fn from(records: Vec<Record>) -> Self {
+        Self {
+            records,
+            ..Default::default()
+        }
+    }
+}
+
+/// Order of the A and AAAA records of a name in the answers of a zone, so
+/// that clients picking the first address spread over all of them.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AnswerOrder {
+    /// The order the records are stored in.
+    #[default]
+    Fixed,
+    /// Rotated by one record on every query of the zone.
+    RoundRobin,
+    /// Shuffled on every query.
+    Random,
+}
+
+/// How the SOA serial of a zone is raised whenever the zone changes, by a
+/// reload, a dynamic update, the admin API or a record change of the server.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SerialPolicy {
+    /// Raised by one, starting from the unix time the zone was loaded at.
+    #[default]
+    Increment,
+    /// `YYYYMMDDnn`: the date of the change and a counter of the changes of
+    /// that day, or one more than the serial once the counter runs out.
+    Date,
+    /// The unix time of the change, or one more than the serial when the
+    /// zone changed within the same second.
+    Unixtime,
+}
+
+impl SerialPolicy {
+    /// The serial of a zone loaded now.
+    pub(crate) fn initial(self) -> u32 {
+        match self {
+            SerialPolicy::Increment | SerialPolicy::Unixtime => unix_time(),
+            SerialPolicy::Date => date_serial(unix_time()),
+        }
+    }
+
+    /// The serial of a zone changed now whose serial was `serial`.
+    pub(crate) fn next(self, serial: u32) -> u32 {
+        let now = match self {
+            SerialPolicy::Increment => 0,
+            SerialPolicy::Date => date_serial(unix_time()),
+            SerialPolicy::Unixtime => unix_time(),
+        };
+        if now > serial {
+            now
+        } else {
+            serial.wrapping_add(1)
+        }
+    }
+}
+
+fn unix_time() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(1, |d| d.as_secs() as u32)
+}
+
+/// The UTC date of the unix time `time` as the serial `YYYYMMDD00`.
+fn date_serial(time: u32) -> u32 {
+    // civil_from_days of https://howardhinnant.github.io/date_algorithms.html
+    let days = time / 86400 + 719468;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u32::from(month <= 2);
+    (year * 10000 + month * 100 + day) * 100
+}
+
+/// Where a zone's records come from.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ZoneType {
+    /// Records are configured locally.
+    #[default]
+    Primary,
+    /// Records are transferred from the zone's primaries.
+    Secondary,
+    /// Queries are forwarded to the zone's forwarders.
+    Forward,
+    /// Queries are sent, without recursion, to the name servers of the
+    /// zone, learned from its primaries.
+    Stub,
+}
+
+impl std::fmt::Display for ZoneType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ZoneType::Primary => "primary",
+            ZoneType::Secondary => "secondary",
+            ZoneType::Forward => "forward",
+            ZoneType::Stub => "stub",
+        })
+    }
+}
+
+/// How a forward zone uses its forwarders, as the `forward` option of BIND.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ForwardMode {
+    /// The default upstreams are tried after the forwarders.
+    #[default]
+    First,
+    /// Only the forwarders are tried.
+    Only,
+}
+
+/// Where a primary zone's records are kept.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ZoneBackend {
+    /// In memory, from the config.
+    #[default]
+    Memory,
+    /// In a SQLite database.
+    Sqlite,
+    /// In a Redis hash, fetched again whenever it is invalidated.
+    Redis,
+}
+
+impl ZoneConfig {
+    /// Resolves the relative zone file, persist file, database and DNSSEC
+    /// key file paths against `dir`.
+    fn resolve_paths(&mut self, dir: &Path) {
+        let (ksk, zsk) = match self.dnssec.as_mut() {
+            Some(dnssec) => (dnssec.ksk.as_mut(), dnssec.zsk.as_mut()),
+            None => (None, None),
+        };
+        for file in [
+            self.file.as_mut(),
+            self.persist_file.as_mut(),
+            self.database.as_mut(),
+            ksk,
+            zsk,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            if file.is_relative() {
+                *file = dir.join(&*file);
+            }
+        }
+        for record in &mut self.records {
+            record.resolve_paths(dir);
+        }
+    }
+
+    pub fn zone_type(&self) -> ZoneType {
+        self.zone_type
+    }
+
+    pub fn primaries(&self) -> &Vec<String> {
+        &self.primaries
+    }
+
+    /// Addresses of the primaries, the port defaults to 53.
+    pub fn primary_addrs(&self) -> anyhow::Result<Vec<SocketAddr>> {
+        parse_upstreams(&self.primaries)
+    }
+
+    pub fn forwarders(&self) -> &Vec<String> {
+        &self.forwarders
+    }
+
+    pub fn forwarder_servers(&self) -> anyhow::Result<Vec<Upstream>> {
+        parse_upstream_servers(&self.forwarders)
+    }
+
+    pub fn forward(&self) -> ForwardMode {
+        self.forward
+    }
+
+    pub fn records(&self) -> &Vec<Record> {
+        &self.records
+    }
+
+    pub fn records_mut(&mut self) -> &mut Vec<Record> {
+        &mut self.records
+    }
+
+    pub fn services(&self) -> &Vec<Service> {
+        &self.services
+    }
+
+    pub fn generate(&self) -> &Vec<GenerateConfig> {
+        &self.generate
+    }
+
+    pub fn aliases(&self) -> &Vec<Alias> {
+        &self.aliases
+    }
+
+    pub fn catalog(&self) -> bool {
+        self.catalog
+    }
+
+    pub fn members(&self) -> &Vec<String> {
+        &self.members
+    }
+
+    /// The config of the member zones of this secondary catalog zone:
+    /// secondary zones of the same primaries, with its key and access
+    /// rules.
+    pub(crate) fn member_zone(&self) -> ZoneConfig {
+        ZoneConfig {
+            zone_type: ZoneType::Secondary,
+            primaries: self.primaries.clone(),
+            allow_transfer: self.allow_transfer.clone(),
+            notify: self.notify.clone(),
+            allow_notify: self.allow_notify.clone(),
+            allow_query: self.allow_query.clone(),
+            deny_query: self.deny_query.clone(),
+            key: self.key.clone(),
+            ..Default::default()
+        }
+    }
+
+    pub fn file(&self) -> Option<&Path> {
+        self.file.as_deref()
+    }
+
+    pub fn persist_file(&self) -> Option<&Path> {
+        self.persist_file.as_deref()
+    }
+
+    pub fn backend(&self) -> ZoneBackend {
+        self.backend
+    }
+
+    pub fn database(&self) -> Option<&Path> {
+        self.database.as_deref()
+    }
+
+    /// The records last saved to `persist_file`, if it exists.
+    pub fn persisted_records(&self, origin: &rr::Name) -> anyhow::Result<Option<Vec<rr::Record>>> {
+        match &self.persist_file {
+            Some(file) if file.exists() => Ok(Some(load_zone_file(file, origin)?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// The records of a `sqlite` zone held by its database, if any. Those of
+    /// a `redis` zone are fetched by the server.
+    pub fn stored_records(&self, origin: &rr::Name) -> anyhow::Result<Option<Vec<rr::Record>>> {
+        match self.backend {
+            ZoneBackend::Memory | ZoneBackend::Redis => Ok(None),
+            ZoneBackend::Sqlite => {
+                let database = self
+                    .database
+                    .as_deref()
+                    .ok_or_else(|| anyhow!("sqlite zone {} has no database", origin))?;
+                crate::sqlite::load(database, origin)
+            }
+        }
+    }
+
+    pub fn soa(&self) -> &SoaConfig {
+        &self.soa
+    }
+
+    pub fn ns(&self) -> &Vec<String> {
+        &self.ns
+    }
+
+    pub fn auto_reverse(&self) -> bool {
+        self.auto_reverse
+    }
+
+    pub fn allow_transfer(&self) -> &Vec<String> {
+        &self.allow_transfer
+    }
+
+    pub fn notify(&self) -> &Vec<String> {
+        &self.notify
+    }
+
+    /// Addresses of the secondaries to notify, the port defaults to 53.
+    pub fn notify_addrs(&self) -> anyhow::Result<Vec<SocketAddr>> {
+        parse_upstreams(&self.notify)
+    }
+
+    pub fn allow_notify(&self) -> &Vec<String> {
+        &self.allow_notify
+    }
+
+    pub fn allow_update(&self) -> &Vec<String> {
+        &self.allow_update
+    }
+
+    pub fn allow_query(&self) -> &Vec<String> {
+        &self.allow_query
+    }
+
+    pub fn deny_query(&self) -> &Vec<String> {
+        &self.deny_query
+    }
+
+    pub fn key(&self) -> Option<&str> {
+        self.key.as_deref()
+    }
+
+    pub fn dnssec(&self) -> Option<&DnssecConfig> {
+        self.dnssec.as_ref()
+    }
+
+    pub fn answer_order(&self) -> AnswerOrder {
+        self.answer_order
+    }
+
+    /// The TTL of the records of the zone without one.
+    pub fn default_ttl(&self) -> Duration {
+        self.default_ttl.unwrap_or(self.soa.ttl)
+    }
+
+    pub fn min_ttl(&self) -> Option<Duration> {
+        self.min_ttl
+    }
+
+    pub fn max_ttl(&self) -> Option<Duration> {
+        self.max_ttl
+    }
+
+    pub fn nxdomain_redirect(&self) -> Option<&NxdomainRedirectConfig> {
+        self.nxdomain_redirect.as_ref()
+    }
+
+    /// Builds `record` of the zone `origin`, with the default TTL of the
+    /// zone unless it has its own.
+    pub fn build_record(&self, record: &Record, origin: &rr::Name) -> Result<rr::Record, Error> {
+        let mut built = record.to_record(origin)?;
+        if record.ttl.is_none() {
+            built.set_ttl(self.default_ttl().as_secs() as u32);
+        }
+        Ok(built)
+    }
+
+    /// Builds every record of the zone, synthesizing the apex SOA and NS
+    /// records unless they are configured explicitly.
+    pub fn to_records(&self, origin: &rr::Name) -> anyhow::Result<Vec<rr::Record>> {
+        let mut records = match &self.file {
+            Some(file) => load_zone_file(file, origin)?,
+            None => Vec::new(),
+        };
+        for record in self.records.iter() {
+            records.push(self.build_record(record, origin)?);
+        }
+        for service in self.services.iter() {
+            records.extend(service.to_records(origin)?);
+        }
+        for generate in self.generate.iter() {
+            records.extend(generate.to_records(origin)?);
+        }
+        self.with_apex(origin, records)
+    }
+
+    /// Adds the NS and SOA records of the zone to `records`, unless they
+    /// have their own at the apex.
+    pub fn with_apex(
+        &self,
+        origin: &rr::Name,
+        mut records: Vec<rr::Record>,
+    ) -> anyhow::Result<Vec<rr::Record>> {
+        let at_apex = |records: &[rr::Record], rr_type: RecordType| {
+            records
+                .iter()
+                .any(|r| r.record_type() == rr_type && r.name() == origin)
+        };
+
+        let mname = self.soa.mname(origin)?;
+        if !at_apex(&records, RecordType::NS) {
+            let names = if self.ns.is_empty() {
+                vec![mname.clone()]
+            } else {
+                self.ns
+                    .iter()
+                    .map(|ns| rr::Name::from_str(ns))
+                    .collect::<Result<Vec<_>, _>>()?
+            };
+            for name in names {
+                records.push(rr::Record::from_rdata(
+                    origin.clone(),
+                    self.soa.ttl.as_secs() as u32,
+                    RData::NS(rr::rdata::NS(name)),
+                ));
+            }
+        }
+        if !at_apex(&records, RecordType::SOA) {
+            records.push(self.soa.to_record(origin, mname)?);
+        }
+        Ok(records)
+    }
+
+    /// Builds the PTR records for every A/AAAA record of the zone, keyed by
+    /// the reverse zone they belong to: a /24 under `in-addr.arpa` or a /64
+    /// under `ip6.arpa`. Wildcard records have no name to point to.
+    pub fn reverse_records(
+        &self,
+        origin: &rr::Name,
+    ) -> anyhow::Result<HashMap<rr::Name, Vec<rr::Record>>> {
+        let mut zones: HashMap<rr::Name, Vec<rr::Record>> = HashMap::new();
+        for record in self.to_records(origin)? {
+            if record.name().is_wildcard() {
+                continue;
+            }
+            let (ptr_name, labels) = match record.data() {
+                Some(RData::A(a)) => (rr::Name::from(a.0), 5),
+                Some(RData::AAAA(aaaa)) => (rr::Name::from(aaaa.0), 18),
+                _ => continue,
+            };
+            let ptr = rr::Record::from_rdata(
+                ptr_name.clone(),
+                record.ttl(),
+                RData::PTR(rr::rdata::PTR(record.name().clone())),
+            );
+            zones.entry(ptr_name.trim_to(labels)).or_default().push(ptr);
+        }
+        Ok(zones)
+    }
+
+    /// The zone holding synthesized PTR records, sharing this zone's SOA
+    /// parameters and name servers.
+    pub fn reverse_zone(&self, origin: &rr::Name) -> anyhow::Result<ZoneConfig> {
+        let mut soa = self.soa.clone();
+        soa.mname = Some(self.soa.mname(origin)?.to_string());
+        soa.rname = Some(self.soa.rname(origin)?.to_string());
+        let ns = if self.ns.is_empty() {
+            vec![self.soa.mname(origin)?.to_string()]
+        } else {
+            self.ns.clone()
+        };
+        Ok(ZoneConfig {
+            soa,
+            ns,
+            ..Default::default()
+        })
+    }
+}
+
+/// Reads the records of an RFC 1035 zone file, names are relative to
+/// `origin` unless the file sets `$ORIGIN`.
+fn load_zone_file(path: &Path, origin: &rr::Name) -> anyhow::Result<Vec<rr::Record>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read zone file {}", path.display()))?;
+    let text = generate::expand(&text)
+        .with_context(|| format!("failed to parse zone file {}", path.display()))?;
+    let (_, rrsets) = Parser::new(text, Some(path.to_path_buf()), Some(origin.clone()))
+        .parse()
+        .map_err(|e| anyhow!("failed to parse zone file {}: {}", path.display(), e))?;
+    Ok(rrsets.into_values().flatten().collect())
+}
+
+/// Start of authority parameters of a zone, every field has a default.
+#[derive(Serialize, Deserialize, Debug, Clone, derive_builder::Builder)]
+#[serde(default)]
+pub struct SoaConfig {
+    /// Primary name server, defaults to `ns1.<zone>`.
+    #[builder(setter(into, strip_option), default = None)]
+    mname: Option<String>,
+
+    /// Mailbox of the zone administrator, defaults to `hostmaster.<zone>`.
+    #[builder(setter(into, strip_option), default = None)]
+    rname: Option<String>,
+
+    /// Zone serial, defaults to the serial `serial_policy` starts from.
+    #[builder(setter(strip_option), default = None)]
+    serial: Option<u32>,
+
+    /// How the serial is raised when the zone changes.
+    #[builder(default)]
+    serial_policy: SerialPolicy,
+
+    #[serde(with = "humantime_serde")]
+    #[builder(default = Duration::from_secs(3600))]
+    refresh: Duration,
+
+    #[serde(with = "humantime_serde")]
+    #[builder(default = Duration::from_secs(600))]
+    retry: Duration,
+
+    #[serde(with = "humantime_serde")]
+    #[builder(default = Duration::from_secs(604800))]
+    expire: Duration,
+
+    /// TTL of negative answers.
+    #[serde(with = "ttl::required")]
+    #[builder(default = Duration::from_secs(60))]
+    minimum: Duration,
+
+    /// TTL of the synthesized SOA and NS records.
+    #[serde(with = "ttl::required")]
+    #[builder(default = DEFAULT_TTL)]
+    ttl: Duration,
+}
+
+impl Default for SoaConfig {
+    fn default() -> Self {
+        SoaConfigBuilder::default().build().unwrap()
+    }
+}
+
+impl SoaConfig {
+    fn mname(&self, origin: &rr::Name) -> anyhow::Result<rr::Name> {
+        match &self.mname {
+            Some(mname) => Ok(rr::Name::from_str(mname)?),
+            None => Ok(rr::Name::from_str("ns1")?.append_domain(origin)?),
+        }
+    }
+
+    fn rname(&self, origin: &rr::Name) -> anyhow::Result<rr::Name> {
+        match &self.rname {
+            Some(rname) => Ok(rr::Name::from_str(rname)?),
+            None => Ok(rr::Name::from_str("hostmaster")?.append_domain(origin)?),
+        }
+    }
+
+    pub fn serial_policy(&self) -> SerialPolicy {
+        self.serial_policy
+    }
+
+    fn serial(&self) -> u32 {
+        self.serial.unwrap_or_else(|| self.serial_policy.initial())
+    }
+
+    fn to_record(&self, origin: &rr::Name, mname: rr::Name) -> anyhow::Result<rr::Record> {
+        let soa = rr::rdata::SOA::new(
+            mname,
+            self.rname(origin)?,
+            self.serial(),
+            self.refresh.as_secs() as i32,
+            self.retry.as_secs() as i32,
+            self.expire.as_secs() as i32,
+            self.minimum.as_secs() as u32,
+        );
+        Ok(rr::Record::from_rdata(
+            origin.clone(),
+            self.ttl.as_secs() as u32,
+            RData::SOA(soa),
+        ))
+    }
+}
+
+/// TTL of records without one, outside of a zone.
+const DEFAULT_TTL: Duration = Duration::from_secs(3600);
+
+/// (De)serializes an optional TTL written as humantime text such as `"5m"`
+/// or as integer seconds.
+mod ttl {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    struct Ttl(Duration);
+
+    impl<'de> Deserialize<'de> for Ttl {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            struct TtlVisitor;
+
+            impl serde::de::Visitor<'_> for TtlVisitor {
+                type Value = Ttl;
+
+                fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                    f.write_str("a duration such as \"5m\" or seconds")
+                }
+
+                fn visit_u64<E: serde::de::Error>(self, seconds: u64) -> Result<Ttl, E> {
+                    Ok(Ttl(Duration::from_secs(seconds)))
+                }
+
+                fn visit_i64<E: serde::de::Error>(self, seconds: i64) -> Result<Ttl, E> {
+                    u64::try_from(seconds)
+                        .map(|seconds| Ttl(Duration::from_secs(seconds)))
+                        .map_err(|_| E::custom(format!("negative TTL {}", seconds)))
+                }
+
+                fn visit_str<E: serde::de::Error>(self, text: &str) -> Result<Ttl, E> {
+                    humantime::parse_duration(text).map(Ttl).map_err(E::custom)
+                }
+            }
+
+            deserializer.deserialize_any(TtlVisitor)
+        }
+    }
+
+    pub fn serialize<S: Serializer>(
+        ttl: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        humantime_serde::serialize(ttl, serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        Ok(Option::<Ttl>::deserialize(deserializer)?.map(|ttl| ttl.0))
+    }
+
+    /// A TTL that must be given.
+    pub mod required {
+        use super::Ttl;
+        use serde::{Deserialize, Deserializer, Serializer};
+        use std::time::Duration;
+
+        pub fn serialize<S: Serializer>(ttl: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+            humantime_serde::serialize(ttl, serializer)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Duration, D::Error> {
+            Ok(Ttl::deserialize(deserializer)?.0)
+        }
+    }
+}
+
+pub type RecordType = rr::RecordType;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, derive_builder::Builder)]
+pub struct Record {
+    /// The `type` and `value` of the record.
+    #[serde(flatten, with = "record_data")]
+    data: RecordData,
+
+    name: String,
+
+    /// Written as `"5m"` or in seconds, defaults to the `default_ttl` of the
+    /// zone.
+    #[serde(with = "ttl", default, skip_serializing_if = "Option::is_none")]
+    #[builder(setter(strip_option), default = None)]
+    ttl: Option<Duration>,
+
+    /// Probe withdrawing the address of an A or AAAA record from answers
+    /// while it fails.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(setter(strip_option), default = None)]
+    health_check: Option<HealthCheckConfig>,
+
+    /// Clients answered with this record, by their location. Records of the
+    /// same name and type without selectors answer the other clients.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    geo: Vec<GeoSelector>,
+}
+
+impl Record {
+    fn name(&self) -> anyhow::Result<rr::Name> {
+        let name = rr::Name::from_str(self.name.as_str())?;
+        Ok(name)
+    }
+
+    /// The owner name of the record in the zone `origin`: `@` is the apex,
+    /// names ending with a dot are absolute and others are relative to the
+    /// zone, unless they already end with its name.
+    fn owner(&self, origin: &rr::Name) -> anyhow::Result<rr::Name> {
+        let name = resolve_name(&self.name, origin)?;
+        if !origin.zone_of(&name) {
+            bail!("{} is outside zone {}", name, origin);
+        }
+        Ok(name)
+    }
+
+    /// Builds the DNS record, resolving its name in the zone `origin`.
+    pub fn to_record(&self, origin: &rr::Name) -> Result<rr::Record, Error> {
+        self.owner(origin)
+            .and_then(|name| self.with_name(name))
+            .map_err(|e| self.error(e))
+    }
+
+    fn with_name(&self, name: rr::Name) -> anyhow::Result<rr::Record> {
+        let ttl = self.ttl.unwrap_or(DEFAULT_TTL);
+        let mut record = rr::Record::with(name, self.rr_type(), ttl.as_secs() as u32);
+        record.set_dns_class(rr::DNSClass::IN);
+        record.set_data(Some(self.data.to_rdata()?));
+        Ok(record)
+    }
+
+    /// Resolves the relative path of the file the data of the record is
+    /// computed from against `dir`.
+    fn resolve_paths(&mut self, dir: &Path) {
+        let file = match &mut self.data {
+            RecordData::Tlsa(Tlsa {
+                certificate: Some(file),
+                ..
+            })
+            | RecordData::Sshfp(Sshfp {
+                key_file: Some(file),
+                ..
+            }) => file,
+            _ => return,
+        };
+        if file.is_relative() {
+            *file = dir.join(&*file);
+        }
+    }
+
+    fn error(&self, e: anyhow::Error) -> Error {
+        Error::Record {
+            name: self.name.clone(),
+            source: e.into(),
+        }
+    }
+
+    fn rr_type(&self) -> rr::RecordType {
+        self.data.record_type()
+    }
+
+    pub fn data(&self) -> &RecordData {
+        &self.data
+    }
+
+    pub fn ttl(&self) -> Option<Duration> {
+        self.ttl
+    }
+
+    /// Whether both records have the same owner name and type.
+    pub fn same_rrset(&self, other: &Record) -> bool {
+        if self.rr_type() != other.rr_type() {
+            return false;
+        }
+        match (self.name(), other.name()) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => self.name == other.name,
+        }
+    }
+
+    /// Whether both records hold the same data, regardless of their TTL.
+    pub fn same_data(&self, other: &Record) -> bool {
+        self.same_rrset(other) && self.data == other.data
+    }
+
+    pub fn health_check(&self) -> Option<&HealthCheckConfig> {
+        self.health_check.as_ref()
+    }
+
+    pub fn geo(&self) -> &[GeoSelector] {
+        &self.geo
+    }
+}
+
+/// Resolves `name` in the zone `origin`: `@` is the apex, names ending with a
+/// dot are absolute and others are relative to the zone, unless they already
+/// end with its name.
+fn resolve_name(name: &str, origin: &rr::Name) -> anyhow::Result<rr::Name> {
+    if name == "@" {
+        return Ok(origin.clone());
+    }
+    let name = rr::Name::from_str(name)?;
+    if name.is_fqdn() || origin.zone_of(&name) {
+        Ok(name)
+    } else {
+        Ok(name.append_name(origin)?)
+    }
+}
+
+/// Records numbered over `range`, like `$GENERATE` in zone files: `$` in
+/// `name` and `value` stands for the number, and `${offset,width,base}` for
+/// it shifted by `offset` and padded to `width` digits in `base`, one of
+/// `d`, `o`, `x` or `X`.
+///
+/// ```toml
+/// range = "1-254"
+/// type = "A"
+/// name = "host-$.pool"
+/// value = "10.1.0.$"
+/// ttl = "5m"
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, derive_builder::Builder)]
+pub struct GenerateConfig {
+    /// `start-stop`, or `start-stop/step`.
+    #[builder(setter(into))]
+    range: String,
+
+    #[serde(rename = "type")]
+    record_type: RecordType,
+
+    /// Owner name, relative to the zone like the names of records.
+    #[builder(setter(into))]
+    name: String,
+
+    /// Record data in zone file form.
+    #[builder(setter(into))]
+    value: String,
+
+    #[serde(with = "humantime_serde")]
+    ttl: Duration,
+}
+
+impl GenerateConfig {
+    pub fn range(&self) -> &str {
+        &self.range
+    }
+
+    pub fn record_type(&self) -> RecordType {
+        self.record_type
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Builds the records of the range in the zone `origin`.
+    pub fn to_records(&self, origin: &rr::Name) -> anyhow::Result<Vec<rr::Record>> {
+        let range: generate::Range = self.range.parse()?;
+        range
+            .numbers()
+            .map(|n| {
+                let name = resolve_name(&generate::substitute(&self.name, n)?, origin)?;
+                if !origin.zone_of(&name) {
+                    bail!("{} is outside zone {}", name, origin);
+                }
+                crate::zonefile::parse_record(
+                    &crate::zonefile::fqdn(&name).to_string(),
+                    self.ttl.as_secs() as u32,
+                    &self.record_type.to_string(),
+                    &generate::substitute(&self.value, n)?,
+                    origin,
+                )
+            })
+            .collect::<anyhow::Result<_>>()
+            .with_context(|| format!("invalid generate {} {}", self.range, self.name))
+    }
+}
+
+/// An ALIAS pseudo-record: queries of the A or AAAA records of `name` are
+/// answered with those of `target`, so that the apex of a zone, which can't
+/// have a CNAME record, can point to another name.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, derive_builder::Builder)]
+pub struct Alias {
+    /// Name in the zone, the apex by default.
+    #[serde(default = "apex")]
+    #[builder(setter(into), default = "apex()")]
+    name: String,
+
+    /// Name whose addresses are served, looked up in the zones or through
+    /// the forwarder.
+    #[builder(setter(into))]
+    target: String,
+}
+
+fn apex() -> String {
+    "@".to_string()
+}
+
+impl Alias {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// The owner name of the alias in the zone `origin`, and its target.
+    pub fn to_names(&self, origin: &rr::Name) -> anyhow::Result<(rr::Name, rr::Name)> {
+        let name = resolve_name(&self.name, origin)?;
+        if !origin.zone_of(&name) {
+            bail!("alias {} is outside zone {}", name, origin);
+        }
+        let mut target = rr::Name::from_str(&self.target)?;
+        target.set_fqdn(true);
+        Ok((crate::zonefile::fqdn(&name), target))
+    }
+}
+
+/// A DNS-SD service instance (RFC 6763), published in its zone as a PTR
+/// record from its service type, its SRV and TXT records, and a PTR record
+/// from `_services._dns-sd._udp` enumerating the service type.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, derive_builder::Builder)]
+pub struct Service {
+    /// Name of the instance, any text such as `Office Printer`.
+    #[builder(setter(into))]
+    instance: String,
+
+    /// Service type and protocol, such as `_ipp._tcp`.
+    #[serde(rename = "type")]
+    #[builder(setter(into))]
+    service_type: String,
+
+    /// Host offering the service, resolved in the zone like record names.
+    #[builder(setter(into))]
+    host: String,
+
+    port: u16,
+
+    #[serde(default)]
+    #[builder(default)]
+    priority: u16,
+
+    #[serde(default)]
+    #[builder(default)]
+    weight: u16,
+
+    /// `key=value` entries of the TXT record.
+    #[serde(default)]
+    #[builder(default)]
+    txt: Vec<String>,
+
+    #[serde(with = "humantime_serde", default = "default_service_ttl")]
+    #[builder(default = default_service_ttl())]
+    ttl: Duration,
+}
+
+fn default_service_ttl() -> Duration {
+    Duration::from_secs(120)
+}
+
+impl Service {
+    pub fn instance(&self) -> &str {
+        &self.instance
+    }
+
+    pub fn service_type(&self) -> &str {
+        &self.service_type
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn txt(&self) -> &[String] {
+        &self.txt
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// The name of the service type in the zone `origin`, such as
+    /// `_ipp._tcp.local.`.
+    pub fn type_name(&self, origin: &rr::Name) -> Result<rr::Name, Error> {
+        self.type_name_in(origin).map_err(|e| self.error(e))
+    }
+
+    fn type_name_in(&self, origin: &rr::Name) -> anyhow::Result<rr::Name> {
+        let name = rr::Name::from_str(&self.service_type)?;
+        let labels: Vec<_> = name.iter().collect();
+        match labels[..] {
+            [service, b"_tcp" | b"_udp"] if service.len() > 1 && service[0] == b'_' => {}
+            _ => bail!(
+                "invalid service type {}, expected _<service>._tcp or _<service>._udp",
+                self.service_type
+            ),
+        }
+        Ok(name.append_name(origin)?)
+    }
+
+    /// The name of the instance in the zone `origin`, such as
+    /// `Office Printer._ipp._tcp.local.`.
+    pub fn instance_name(&self, origin: &rr::Name) -> Result<rr::Name, Error> {
+        self.instance_name_in(origin).map_err(|e| self.error(e))
+    }
+
+    fn instance_name_in(&self, origin: &rr::Name) -> anyhow::Result<rr::Name> {
+        let instance = rr::domain::Label::from_raw_bytes(self.instance.as_bytes())?;
+        Ok(rr::Name::from_labels([instance])?.append_name(&self.type_name_in(origin)?)?)
+    }
+
+    /// Builds the PTR, SRV and TXT records of the instance, and the PTR
+    /// record enumerating its service type.
+    pub fn to_records(&self, origin: &rr::Name) -> Result<Vec<rr::Record>, Error> {
+        self.records_in(origin).map_err(|e| self.error(e))
+    }
+
+    fn records_in(&self, origin: &rr::Name) -> anyhow::Result<Vec<rr::Record>> {
+        let type_name = self.type_name_in(origin)?;
+        let instance = self.instance_name_in(origin)?;
+        let target = resolve_name(&self.host, origin)?;
+        // a TXT record without entries holds a single empty string (RFC 6763
+        // section 6.1)
+        let txt = if self.txt.is_empty() {
+            vec![String::new()]
+        } else {
+            self.txt.clone()
+        };
+        let ttl = self.ttl.as_secs() as u32;
+        let record = |name: &rr::Name, data| {
+            let mut record = rr::Record::from_rdata(name.clone(), ttl, data);
+            record.set_dns_class(rr::DNSClass::IN);
+            record
+        };
+        Ok(vec![
+            record(
+                &rr::Name::from_str("_services._dns-sd._udp")?.append_name(origin)?,
+                RData::PTR(rr::rdata::PTR(type_name.clone())),
+            ),
+            record(&type_name, RData::PTR(rr::rdata::PTR(instance.clone()))),
+            record(
+                &instance,
+                RData::SRV(rr::rdata::SRV::new(
+                    self.priority,
+                    self.weight,
+                    self.port,
+                    target,
+                )),
+            ),
+            record(&instance, RData::TXT(rr::rdata::TXT::new(txt))),
+        ])
+    }
+
+    fn error(&self, e: anyhow::Error) -> Error {
+        Error::Record {
+            name: self.instance.clone(),
+            source: e.into(),
+        }
+    }
+}
+
+/// Clients selected by location: `continent:EU`, `country:DE` (ISO 3166
+/// codes) or `asn:13335`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(try_from = "String", into = "String")]
+pub enum GeoSelector {
+    Continent(String),
+    Country(String),
+    Asn(u32),
+}
+
+impl FromStr for GeoSelector {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, value) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow!("invalid geo selector {:?}, expected kind:value", s))?;
+        let code = || match value.trim() {
+            code if !code.is_empty() && code.chars().all(|c| c.is_ascii_alphabetic()) => {
+                Ok(code.to_ascii_uppercase())
+            }
+            _ => Err(anyhow!("invalid code in geo selector {:?}", s)),
+        };
+        match kind.trim().to_ascii_lowercase().as_str() {
+            "continent" => Ok(Self::Continent(code()?)),
+            "country" => Ok(Self::Country(code()?)),
+            "asn" => {
+                let asn = value.trim().trim_start_matches(['A', 'S', 'a', 's']);
+                let asn = asn
+                    .parse()
+                    .with_context(|| format!("invalid AS number in geo selector {:?}", s))?;
+                Ok(Self::Asn(asn))
+            }
+            _ => bail!("unknown geo selector {:?}", s),
+        }
+    }
+}
+
+impl TryFrom<String> for GeoSelector {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<GeoSelector> for String {
+    fn from(value: GeoSelector) -> Self {
+        value.to_string()
+    }
+}
+
+impl std::fmt::Display for GeoSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Continent(code) => write!(f, "continent:{}", code),
+            Self::Country(code) => write!(f, "country:{}", code),
+            Self::Asn(asn) => write!(f, "asn:{}", asn),
+        }
+    }
+}
+
+/// Health check of the address of a record, e.g.
+///
+/// ```toml
+/// health_check = { type = "tcp", port = 443, interval = "10s" }
+/// health_check = { type = "http", port = 8080, path = "/healthz" }
+/// health_check = { type = "icmp", fall = 5 }
+/// ```
+///
+/// The address is withdrawn after `fall` failed probes in a row and served
+/// again after `rise` successful ones.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, derive_builder::Builder)]
+pub struct HealthCheckConfig {
+    #[serde(flatten)]
+    probe: HealthProbe,
+
+    #[serde(with = "humantime_serde", default = "default_health_interval")]
+    #[builder(default = default_health_interval())]
+    interval: Duration,
+
+    /// Time a probe may take before it counts as failed.
+    #[serde(with = "humantime_serde", default = "default_health_timeout")]
+    #[builder(default = default_health_timeout())]
+    timeout: Duration,
+
+    #[serde(default = "default_health_fall")]
+    #[builder(default = default_health_fall())]
+    fall: u32,
+
+    #[serde(default = "default_health_rise")]
+    #[builder(default = default_health_rise())]
+    rise: u32,
+}
+
+/// How the address of a record is probed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum HealthProbe {
+    /// Connects to `port` over TCP.
+    Tcp { port: u16 },
+    /// GETs `path` over HTTP, expecting a 2xx or 3xx status.
+    Http {
+        #[serde(default = "default_http_port")]
+        port: u16,
+        #[serde(default = "default_http_path")]
+        path: String,
+    },
+    /// Sends an ICMP echo request, over an unprivileged ICMP socket on Linux
+    /// (see `net.ipv4.ping_group_range`).
+    Icmp,
+}
+
+fn default_health_interval() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_health_timeout() -> Duration {
+    Duration::from_secs(2)
+}
+
+fn default_health_fall() -> u32 {
+    3
+}
+
+fn default_health_rise() -> u32 {
+    2
+}
+
+fn default_http_port() -> u16 {
+    80
+}
+
+fn default_http_path() -> String {
+    "/".to_string()
+}
+
+impl HealthCheckConfig {
+    pub fn probe(&self) -> &HealthProbe {
+        &self.probe
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn fall(&self) -> u32 {
+        self.fall
+    }
+
+    pub fn rise(&self) -> u32 {
+        self.rise
+    }
+}
+
+/// Type and value of a configured record, written as its `type` and `value`
+/// and checked when the config is read.
+///
+/// Simple records take a plain string, while records with several fields
+/// may be written as an inline table or in their zone file form:
+///
+/// ```toml
+/// type = "MX"
+/// value = { preference = 10, exchange = "mail.et.internal" } # or "10 mail.et.internal"
+///
+/// type = "SRV"
+/// value = { priority = 10, weight = 5, port = 443, target = "srv.et.internal" }
+///
+/// type = "TXT"
+/// value = ["v=spf1 -all", "second segment"] # or a single string
+///
+/// type = "HTTPS"
+/// value = { priority = 1, target = ".", alpn = ["h2", "h3"] } # or "1 . alpn=h2,h3"
+///
+/// type = "TYPE65280" # any type without explicit support, RFC 3597
+/// value = '\# 4 0a000001'
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", content = "value", rename_all = "UPPERCASE")]
+pub enum RecordData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Cname(rr::Name),
+    Ns(rr::Name),
+    Ptr(rr::Name),
+    #[serde(deserialize_with = "table_or_text")]
+    Mx(Mx),
+    #[serde(deserialize_with = "segments")]
+    Txt(Vec<String>),
+    #[serde(deserialize_with = "table_or_text")]
+    Srv(Srv),
+    #[serde(deserialize_with = "table_or_text")]
+    Svcb(Svcb),
+    #[serde(deserialize_with = "table_or_text")]
+    Https(Svcb),
+    #[serde(deserialize_with = "table_or_text")]
+    Tlsa(Tlsa),
+    #[serde(deserialize_with = "table_or_text")]
+    Sshfp(Sshfp),
+    /// Written as `TYPE<code>`, see `Record`.
+    #[serde(skip)]
+    Unknown(UnknownData),
+}
+
+impl RecordData {
+    /// Parses the zone file form of a value of type `rr_type`, e.g.
+    /// `"10 mail.et.internal"` for MX. A TXT value is a single segment.
+    pub fn from_text(rr_type: RecordType, text: &str) -> anyhow::Result<Self> {
+        let data = match rr_type {
+            RecordType::A => Self::A(text.parse()?),
+            RecordType::AAAA => Self::Aaaa(text.parse()?),
+            RecordType::CNAME => Self::Cname(rr::Name::from_str(text)?),
+            RecordType::NS => Self::Ns(rr::Name::from_str(text)?),
+            RecordType::PTR => Self::Ptr(rr::Name::from_str(text)?),
+            RecordType::MX => Self::Mx(text.parse()?),
+            RecordType::TXT => Self::Txt(vec![text.to_string()]),
+            RecordType::SRV => Self::Srv(text.parse()?),
+            RecordType::SVCB => Self::Svcb(text.parse()?),
+            RecordType::HTTPS => Self::Https(text.parse()?),
+            RecordType::TLSA => Self::Tlsa(text.parse()?),
+            RecordType::SSHFP => Self::Sshfp(text.parse()?),
+            // the generic form of RFC 3597 for the other types
+            _ if text.starts_with("\\#") => Self::Unknown(UnknownData::from_text(rr_type, text)?),
+            _ => bail!(
+                "unsupported record type TYPE{} ({}), expected \\# <length> <hex data>",
+                u16::from(rr_type),
+                rr_type
+            ),
+        };
+        Ok(data)
+    }
+
+    pub fn record_type(&self) -> RecordType {
+        match self {
+            RecordData::A(_) => RecordType::A,
+            RecordData::Aaaa(_) => RecordType::AAAA,
+            RecordData::Cname(_) => RecordType::CNAME,
+            RecordData::Ns(_) => RecordType::NS,
+            RecordData::Ptr(_) => RecordType::PTR,
+            RecordData::Mx(_) => RecordType::MX,
+            RecordData::Txt(_) => RecordType::TXT,
+            RecordData::Srv(_) => RecordType::SRV,
+            RecordData::Svcb(_) => RecordType::SVCB,
+            RecordData::Https(_) => RecordType::HTTPS,
+            RecordData::Tlsa(_) => RecordType::TLSA,
+            RecordData::Sshfp(_) => RecordType::SSHFP,
+            RecordData::Unknown(unknown) => unknown.rr_type,
+        }
+    }
+
+    /// The record data, with that of a TLSA record computed from its
+    /// certificate.
+    fn to_rdata(&self) -> anyhow::Result<RData> {
+        match self {
+            RecordData::Tlsa(tlsa) => Ok(RData::from(&RecordData::Tlsa(tlsa.load()?))),
+            RecordData::Sshfp(sshfp) => Ok(RData::from(&RecordData::Sshfp(sshfp.load()?))),
+            data => Ok(data.into()),
+        }
+    }
+}
+
+impl From<&RecordData> for RData {
+    /// The record data as configured, without the association data of a
+    /// TLSA record computed from its certificate or the fingerprint of an
+    /// SSHFP record computed from its key file.
+    fn from(data: &RecordData) -> Self {
+        match data.clone() {
+            RecordData::A(addr) => RData::A(rr::rdata::A(addr)),
+            RecordData::Aaaa(addr) => RData::AAAA(rr::rdata::AAAA(addr)),
+            RecordData::Cname(name) => RData::CNAME(rr::rdata::CNAME(name)),
+            RecordData::Ns(name) => RData::NS(rr::rdata::NS(name)),
+            RecordData::Ptr(name) => RData::PTR(rr::rdata::PTR(name)),
+            RecordData::Mx(mx) => RData::MX(rr::rdata::MX::new(mx.preference, mx.exchange)),
+            RecordData::Txt(segments) => RData::TXT(rr::rdata::TXT::new(segments)),
+            RecordData::Srv(srv) => RData::SRV(rr::rdata::SRV::new(
+                srv.priority,
+                srv.weight,
+                srv.port,
+                srv.target,
+            )),
+            RecordData::Svcb(svcb) => RData::SVCB(svcb.into()),
+            RecordData::Https(svcb) => RData::HTTPS(rr::rdata::HTTPS(svcb.into())),
+            RecordData::Tlsa(tlsa) => RData::TLSA(rr::rdata::TLSA::new(
+                tlsa.usage.into(),
+                tlsa.selector.into(),
+                tlsa.matching.into(),
+                tlsa.data,
+            )),
+            RecordData::Sshfp(sshfp) => RData::SSHFP(rr::rdata::SSHFP::new(
+                sshfp.algorithm.into(),
+                sshfp.fingerprint_type.into(),
+                sshfp.fingerprint,
+            )),
+            RecordData::Unknown(unknown) => RData::Unknown {
+                code: unknown.rr_type,
+                rdata: match unknown.data.is_empty() {
+                    true => rr::rdata::NULL::new(),
+                    false => rr::rdata::NULL::with(unknown.data),
+                },
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Mx {
+    pub preference: u16,
+    pub exchange: rr::Name,
+}
+
+impl FromStr for Mx {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let [preference, exchange] = fields(s)?;
+        Ok(Self {
+            preference: preference.parse()?,
+            exchange: rr::Name::from_str(exchange)?,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Srv {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: rr::Name,
+}
+
+impl FromStr for Srv {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let [priority, weight, port, target] = fields(s)?;
+        Ok(Self {
+            priority: priority.parse()?,
+            weight: weight.parse()?,
+            port: port.parse()?,
+            target: rr::Name::from_str(target)?,
+        })
+    }
+}
+
+/// Value of an SVCB or HTTPS record (RFC 9460): an alias of `target` when
+/// `priority` is 0, else an endpoint of the service and its parameters.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Svcb {
+    pub priority: u16,
+    /// `.` for the owner name of the record itself.
+    pub target: rr::Name,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alpn: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ipv4hint: Vec<Ipv4Addr>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ipv6hint: Vec<Ipv6Addr>,
+    /// ECHConfigList of the endpoint, base64 encoded.
+    #[serde(
+        default,
+        with = "base64_bytes",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub ech: Option<Vec<u8>>,
+}
+
+impl FromStr for Svcb {
+    type Err = anyhow::Error;
+
+    /// Parses the zone file form, e.g. `1 . alpn=h2,h3 port=8443`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.split_whitespace();
+        let (Some(priority), Some(target)) = (fields.next(), fields.next()) else {
+            bail!("expected a priority and a target in {:?}", s);
+        };
+        let mut svcb = Self {
+            priority: priority.parse()?,
+            target: rr::Name::from_str(target)?,
+            alpn: Vec::new(),
+            port: None,
+            ipv4hint: Vec::new(),
+            ipv6hint: Vec::new(),
+            ech: None,
+        };
+        for param in fields {
+            let (key, value) = param
+                .split_once('=')
+                .ok_or_else(|| anyhow!("expected key=value, got {:?}", param))?;
+            let value = value.trim_matches('"');
+            let list = || value.trim_end_matches(',').split(',');
+            match key {
+                "alpn" => svcb.alpn = list().map(str::to_string).collect(),
+                "port" => svcb.port = Some(value.parse()?),
+                "ipv4hint" => svcb.ipv4hint = list().map(str::parse).collect::<Result<_, _>>()?,
+                "ipv6hint" => svcb.ipv6hint = list().map(str::parse).collect::<Result<_, _>>()?,
+                // `echconfig` is how hickory writes it in zone files
+                "ech" | "echconfig" => {
+                    svcb.ech = Some(base64::Engine::decode(
+                        &base64::engine::general_purpose::STANDARD,
+                        value,
+                    )?)
+                }
+                _ => bail!("unsupported SvcParam {}", key),
+            }
+        }
+        Ok(svcb)
+    }
+}
+
+impl From<Svcb> for rr::rdata::SVCB {
+    fn from(svcb: Svcb) -> Self {
+        use rr::rdata::svcb::{Alpn, EchConfig, IpHint, SvcParamKey, SvcParamValue};
+        // in the increasing order of their keys
+        let mut params = Vec::new();
+        if !svcb.alpn.is_empty() {
+            params.push((SvcParamKey::Alpn, SvcParamValue::Alpn(Alpn(svcb.alpn))));
+        }
+        if let Some(port) = svcb.port {
+            params.push((SvcParamKey::Port, SvcParamValue::Port(port)));
+        }
+        if !svcb.ipv4hint.is_empty() {
+            let hints = svcb.ipv4hint.into_iter().map(rr::rdata::A).collect();
+            params.push((
+                SvcParamKey::Ipv4Hint,
+                SvcParamValue::Ipv4Hint(IpHint(hints)),
+            ));
+        }
+        if let Some(ech) = svcb.ech {
+            params.push((
+                SvcParamKey::EchConfig,
+                SvcParamValue::EchConfig(EchConfig(ech)),
+            ));
+        }
+        if !svcb.ipv6hint.is_empty() {
+            let hints = svcb.ipv6hint.into_iter().map(rr::rdata::AAAA).collect();
+            params.push((
+                SvcParamKey::Ipv6Hint,
+                SvcParamValue::Ipv6Hint(IpHint(hints)),
+            ));
+        }
+        Self::new(svcb.priority, svcb.target, params)
+    }
+}
+
+/// Value of a TLSA record (RFC 6698), binding the certificate of a TLS
+/// service to its name. In the config, `certificate` may stand for `data`,
+/// which is then computed from the PEM file whenever the record is built.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "TlsaRepr", into = "TlsaRepr")]
+pub struct Tlsa {
+    pub usage: u8,
+    pub selector: u8,
+    pub matching: u8,
+    /// Certificate association data, hex encoded in the config. Empty when
+    /// computed from `certificate`.
+    pub data: Vec<u8>,
+    /// The PEM file of the certificate `data` is computed from, relative to
+    /// the directory of the config file.
+    pub certificate: Option<PathBuf>,
+}
+
+impl Tlsa {
+    /// The record with its association data computed from the first
+    /// certificate of `certificate`, when set.
+    pub fn load(&self) -> anyhow::Result<Self> {
+        let mut tlsa = self.clone();
+        if let Some(path) = &self.certificate {
+            tlsa.data = crate::tls::association_data(path, self.selector, self.matching)?;
+        }
+        Ok(tlsa)
+    }
+}
+
+impl FromStr for Tlsa {
+    type Err = anyhow::Error;
+
+    /// Parses the zone file form, e.g. `3 1 1 0d6fce33...`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.split_whitespace();
+        let (Some(usage), Some(selector), Some(matching)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            bail!(
+                "expected a usage, a selector, a matching type and data in {:?}",
+                s
+            );
+        };
+        let data: String = fields.collect();
+        Ok(Self {
+            usage: usage.parse()?,
+            selector: selector.parse()?,
+            matching: matching.parse()?,
+            data: data_encoding::HEXLOWER_PERMISSIVE.decode(data.as_bytes())?,
+            certificate: None,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct TlsaRepr {
+    usage: u8,
+    selector: u8,
+    matching: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    data: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    certificate: Option<PathBuf>,
+}
+
+impl TryFrom<TlsaRepr> for Tlsa {
+    type Error = String;
+
+    fn try_from(repr: TlsaRepr) -> Result<Self, Self::Error> {
+        match (repr.data, repr.certificate) {
+            (Some(data), None) => Ok(Self {
+                usage: repr.usage,
+                selector: repr.selector,
+                matching: repr.matching,
+                data: data_encoding::HEXLOWER_PERMISSIVE
+                    .decode(data.as_bytes())
+                    .map_err(|e| format!("invalid TLSA data: {}", e))?,
+                certificate: None,
+            }),
+            (None, Some(path)) => Ok(Self {
+                usage: repr.usage,
+                selector: repr.selector,
+                matching: repr.matching,
+                data: Vec::new(),
+                certificate: Some(path),
+            }),
+            _ => Err("a TLSA record takes either data or a certificate".to_string()),
+        }
+    }
+}
+
+impl From<Tlsa> for TlsaRepr {
+    /// Keeps the certificate in place of the data computed from it.
+    fn from(tlsa: Tlsa) -> Self {
+        let data = match tlsa.certificate {
+            Some(_) => None,
+            None => Some(data_encoding::HEXLOWER.encode(&tlsa.data)),
+        };
+        Self {
+            usage: tlsa.usage,
+            selector: tlsa.selector,
+            matching: tlsa.matching,
+            data,
+            certificate: tlsa.certificate,
+        }
+    }
+}
+
+/// Value of an SSHFP record (RFC 4255), the fingerprint of a host key. In
+/// the config, `key_file` may stand for `algorithm` and `fingerprint`, which
+/// are then computed from the OpenSSH public key file whenever the record is
+/// built.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "SshfpRepr", into = "SshfpRepr")]
+pub struct Sshfp {
+    /// The algorithm of the key, 0 to take it from `key_file`.
+    pub algorithm: u8,
+    pub fingerprint_type: u8,
+    /// Hex encoded in the config. Empty when computed from `key_file`.
+    pub fingerprint: Vec<u8>,
+    /// The public key file the fingerprint is computed from, relative to the
+    /// directory of the config file.
+    pub key_file: Option<PathBuf>,
+}
+
+impl Sshfp {
+    /// The record with its algorithm and fingerprint computed from the first
+    /// key of `key_file`, when set.
+    pub fn load(&self) -> anyhow::Result<Self> {
+        let mut sshfp = self.clone();
+        if let Some(path) = &self.key_file {
+            let (algorithm, fingerprint) = crate::ssh::fingerprint(path, self.fingerprint_type)?;
+            if self.algorithm != 0 && self.algorithm != algorithm {
+                bail!(
+                    "{} holds a key of algorithm {}, not {}",
+                    path.display(),
+                    algorithm,
+                    self.algorithm
+                );
+            }
+            sshfp.algorithm = algorithm;
+            sshfp.fingerprint = fingerprint;
+        }
+        Ok(sshfp)
+    }
+}
+
+impl FromStr for Sshfp {
+    type Err = anyhow::Error;
+
+    /// Parses the zone file form, e.g. `4 2 1e008168...`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.split_whitespace();
+        let (Some(algorithm), Some(fingerprint_type)) = (fields.next(), fields.next()) else {
+            bail!(
+                "expected an algorithm, a fingerprint type and a fingerprint in {:?}",
+                s
+            );
+        };
+        let fingerprint: String = fields.collect();
+        Ok(Self {
+            algorithm: algorithm.parse()?,
+            fingerprint_type: fingerprint_type.parse()?,
+            fingerprint: data_encoding::HEXLOWER_PERMISSIVE.decode(fingerprint.as_bytes())?,
+            key_file: None,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SshfpRepr {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    algorithm: Option<u8>,
+    fingerprint_type: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    fingerprint: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key_file: Option<PathBuf>,
+}
+
+impl TryFrom<SshfpRepr> for Sshfp {
+    type Error = String;
+
+    fn try_from(repr: SshfpRepr) -> Result<Self, Self::Error> {
+        match (repr.fingerprint, repr.key_file) {
+            (Some(fingerprint), None) => Ok(Self {
+                algorithm: repr
+                    .algorithm
+                    .ok_or("an SSHFP record with a fingerprint takes its algorithm")?,
+                fingerprint_type: repr.fingerprint_type,
+                fingerprint: data_encoding::HEXLOWER_PERMISSIVE
+                    .decode(fingerprint.as_bytes())
+                    .map_err(|e| format!("invalid SSHFP fingerprint: {}", e))?,
+                key_file: None,
+            }),
+            (None, Some(path)) => Ok(Self {
+                algorithm: repr.algorithm.unwrap_or(0),
+                fingerprint_type: repr.fingerprint_type,
+                fingerprint: Vec::new(),
+                key_file: Some(path),
+            }),
+            _ => Err("an SSHFP record takes either a fingerprint or a key file".to_string()),
+        }
+    }
+}
+
+impl From<Sshfp> for SshfpRepr {
+    /// Keeps the key file in place of the fingerprint computed from it.
+    fn from(sshfp: Sshfp) -> Self {
+        let (algorithm, fingerprint) = match sshfp.key_file {
+            Some(_) => ((sshfp.algorithm != 0).then_some(sshfp.algorithm), None),
+            None => (
+                Some(sshfp.algorithm),
+                Some(data_encoding::HEXLOWER.encode(&sshfp.fingerprint)),
+            ),
+        };
+        Self {
+            algorithm,
+            fingerprint_type: sshfp.fingerprint_type,
+            fingerprint,
+            key_file: sshfp.key_file,
+        }
+    }
+}
+
+/// Value of a record of a type without explicit support, in the generic
+/// form of RFC 3597: `\# <length> <hex data>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownData {
+    pub rr_type: RecordType,
+    pub data: Vec<u8>,
+}
+
+impl UnknownData {
+    pub fn from_text(rr_type: RecordType, text: &str) -> anyhow::Result<Self> {
+        let mut fields = text.split_whitespace();
+        if fields.next() != Some("\\#") {
+            bail!("expected \\# <length> <hex data>, got {:?}", text);
+        }
+        let length: usize = fields
+            .next()
+            .ok_or_else(|| anyhow!("missing the length of {:?}", text))?
+            .parse()?;
+        let hex: String = fields.collect();
+        let data = data_encoding::HEXLOWER_PERMISSIVE.decode(hex.as_bytes())?;
+        if data.len() != length {
+            bail!(
+                "{:?} has {} bytes of data, not {}",
+                text,
+                data.len(),
+                length
+            );
+        }
+        Ok(Self { rr_type, data })
+    }
+}
+
+impl std::fmt::Display for UnknownData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "\\# {}", self.data.len())?;
+        if !self.data.is_empty() {
+            write!(f, " {}", data_encoding::HEXLOWER.encode(&self.data))?;
+        }
+        Ok(())
+    }
+}
+
+/// (De)serializes the `type` and `value` of a record: those of `RecordData`
+/// and `TYPE<code>` for the types without explicit support.
+mod record_data {
+    use super::{RecordData, RecordType};
+    use serde::ser::SerializeMap;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &RecordData, serializer: S) -> Result<S::Ok, S::Error> {
+        match data {
+            RecordData::Unknown(unknown) => {
+                let mut map = serializer.serialize_map(Some(2))?;
+                map.serialize_entry("type", &format!("TYPE{}", u16::from(unknown.rr_type)))?;
+                map.serialize_entry("value", &unknown.to_string())?;
+                map.end()
+            }
+            data => data.serialize(serializer),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<RecordData, D::Error> {
+        #[derive(Deserialize)]
+        struct Tagged {
+            #[serde(rename = "type")]
+            rr_type: String,
+            value: serde_json::Value,
+        }
+        let Tagged { rr_type, value } = Tagged::deserialize(deserializer)?;
+        let code = rr_type
+            .strip_prefix("TYPE")
+            .and_then(|code| code.parse::<u16>().ok());
+        match (code, value) {
+            (Some(code), serde_json::Value::String(text)) => {
+                RecordData::from_text(RecordType::from(code), &text)
+                    .map_err(|e| serde::de::Error::custom(format!("{:#}", e)))
+            }
+            (Some(_), _) => Err(serde::de::Error::custom(format!(
+                "the value of a {} record is text",
+                rr_type
+            ))),
+            (None, value) => {
+                let tagged = serde_json::json!({ "type": rr_type, "value": value });
+                RecordData::deserialize(tagged).map_err(serde::de::Error::custom)
+            }
+        }
+    }
+}
+
+/// (De)serializes optional bytes as base64 text.
+mod base64_bytes {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        bytes: &Option<Vec<u8>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match bytes {
+            Some(bytes) => serializer.serialize_some(&STANDARD.encode(bytes)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Vec<u8>>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|text| STANDARD.decode(text).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+/// A record entry of a zone, the records of each of its `values` when it
+/// has several.
+struct RecordEntry(Vec<Record>);
+
+/// The keys of a record entry, those of [`Record`] and `values`. The others
+/// are ignored here rather than in the flattened [`Record`], for a strict
+/// config to tell them.
+#[derive(Deserialize)]
+struct RecordKeys {
+    #[serde(rename = "type")]
+    rr_type: Option<serde_json::Value>,
+    value: Option<serde_json::Value>,
+    values: Option<serde_json::Value>,
+    name: Option<serde_json::Value>,
+    ttl: Option<serde_json::Value>,
+    health_check: Option<serde_json::Value>,
+    geo: Option<serde_json::Value>,
+}
+
+impl<'de> Deserialize<'de> for RecordEntry {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error as _;
+        let keys = RecordKeys::deserialize(deserializer)?;
+        let entry = [
+            ("type", keys.rr_type),
+            ("value", keys.value),
+            ("values", keys.values),
+            ("name", keys.name),
+            ("ttl", keys.ttl),
+            ("health_check", keys.health_check),
+            ("geo", keys.geo),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| Some((key.to_string(), value?)))
+        .collect();
+        split_values(entry)
+            .map_err(D::Error::custom)?
+            .into_iter()
+            .map(|entry| Record::deserialize(serde_json::Value::Object(entry)))
+            .collect::<Result<_, _>>()
+            .map(RecordEntry)
+            .map_err(D::Error::custom)
+    }
+}
+
+/// Splits a record entry with a list of `values` into an entry with a
+/// `value` for each.
+fn split_values(
+    mut entry: serde_json::Map<String, serde_json::Value>,
+) -> Result<Vec<serde_json::Map<String, serde_json::Value>>, &'static str> {
+    let Some(values) = entry.remove("values") else {
+        return Ok(vec![entry]);
+    };
+    if entry.contains_key("value") {
+        return Err("a record takes a value or values, not both");
+    }
+    match values {
+        serde_json::Value::Array(values) if !values.is_empty() => Ok(values
+            .into_iter()
+            .map(|value| {
+                let mut entry = entry.clone();
+                entry.insert("value".to_string(), value);
+                entry
+            })
+            .collect()),
+        _ => Err("values of a record must be a non-empty list"),
+    }
+}
+
+/// Deserializes the record entries of a zone.
+fn record_entries<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Record>, D::Error> {
+    let entries = Vec::<RecordEntry>::deserialize(deserializer)?;
+    Ok(entries.into_iter().flat_map(|entry| entry.0).collect())
+}
+
+/// Deserializes a value written as a table or in its zone file form.
+fn table_or_text<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de> + FromStr<Err = anyhow::Error>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Value<T> {
+        Text(String),
+        Table(T),
+    }
+    match Value::deserialize(deserializer)? {
+        Value::Text(text) => text
+            .parse()
+            .map_err(|e| serde::de::Error::custom(format!("{:#}", e))),
+        Value::Table(value) => Ok(value),
+    }
+}
+
+/// Deserializes text segments, a single one may be a plain string.
+fn segments<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Segments {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match Segments::deserialize(deserializer)? {
+        Segments::One(segment) => vec![segment],
+        Segments::Many(segments) => segments,
+    })
+}
+
+/// Splits a zone file style value into exactly `N` whitespace separated fields.
+fn fields<const N: usize>(text: &str) -> anyhow::Result<[&str; N]> {
+    let fields: Vec<&str> = text.split_whitespace().collect();
+    fields
+        .try_into()
+        .map_err(|_| anyhow!("expected {} fields in {:?}", N, text))
+}
+
+impl TryFrom<Record> for rr::Record {
+    type Error = Error;
+
+    fn try_from(value: Record) -> Result<Self, Self::Error> {
+        let r: rr::Record = (&value).try_into()?;
+        Ok(r)
+    }
+}
+
+impl TryFrom<&Record> for rr::Record {
+    type Error = Error;
+
+    fn try_from(value: &Record) -> Result<Self, Self::Error> {
+        value
+            .name()
+            .and_then(|name| value.with_name(name))
+            .map_err(|e| value.error(e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn it_works() -> anyhow::Result<()> {
+        let text = r#"
+[general]
+listen_tcp = ["127.0.0.1:5300", "[::1]:5300"]
+listen_udp = "127.0.0.1:5353"
+udp_workers = 4
+tcp_timeout = "30s"
+shutdown_timeout = "2s"
+max_udp_response_size = 4096
+nsid = "ns1.fra"
+any_queries = "minimal"
+max_ttl = "1d"
+watch_config = true
+listen_quic = "127.0.0.1:853"
+deny_query = ["192.0.2.0/24"]
+control_socket = "/run/dns-server/control.sock"
+
+[general.listen_tls]
+address = "127.0.0.1:853"
+cert = "/etc/libdns/cert.pem"
+key = "/etc/libdns/key.pem"
+
+[general.listen_https]
+address = "127.0.0.1:443"
+cert = "/etc/libdns/cert.pem"
+key = "/etc/libdns/key.pem"
+reload_interval = "5m"
+
+[general.listen_admin]
+address = "127.0.0.1:8053"
+token = "secret"
+
+[general.listen_grpc]
+address = "127.0.0.1:50053"
+
+[general.otlp]
+endpoint = "http://otel-collector:4317"
+
+[general.rate_limit]
+queries_per_second = 50
+ipv4_prefix = 24
+action = "drop"
+
+[general.response_rate_limit]
+responses_per_second = 5
+errors_per_second = 0
+slip = 3
+exempt_clients = ["10.0.0.0/8"]
+
+[forward]
+upstreams = ["1.1.1.1", "[2606:4700:4700::1111]:53"]
+serve_stale = true
+max_stale = "1h"
+randomize_case = true
+max_connections = 4
+dnssec_validation = "strict"
+client_subnet = { ipv4_prefix = 20 }
+
+[forward.cache]
+max_entries = 100
+min_ttl = "30s"
+prefetch = { window = "5s" }
+
+[[forward.rules]]
+domain = "corp.example"
+upstreams = ["10.0.0.2"]
+
+[blocklist]
+sources = ["https://example.com/hosts", "/etc/libdns/blocked.txt"]
+allow = ["cdn.example.com"]
+response = "null"
+
+[geoip]
+database = "/var/lib/GeoIP/GeoLite2-Country.mmdb"
+
+[[rewrites]]
+from = "legacy.corp"
+to = "www.et.internal"
+
+[[rewrites]]
+match = "regex"
+from = 'host-(\d+)\.corp'
+to = "host$1.et.internal"
+rewrite_answers = true
+
+[[keys]]
+name = "transfer"
+secret = "c2VjcmV0"
+
+[[zones."et.internal"]]
+type = "A"
+name = "www"
+value = "123.123.123.123"
+ttl = "60s"
+health_check = { type = "http", port = 8080, path = "/healthz", interval = "5s" }
+geo = ["continent:EU", "country:de", "asn:AS13335"]
+
+[[zones."et.top"]]
+type = "A"
+name = "@"
+value = "100.100.100.100"
+ttl = "61s"
+
+[zones."et.apex"]
+aliases = [{ target = "lb.et.top" }]
+
+[zones."catalog.et.internal"]
+catalog = true
+members = ["et.internal", "et.apex"]
+
+[zones."et.example"]
+type = "secondary"
+primaries = ["10.0.0.53", "10.0.0.54:5353"]
+notify = ["10.0.0.55"]
+allow_notify = ["10.0.0.0/24", "key transfer"]
+allow_update = ["key transfer"]
+allow_query = ["10.0.0.0/8", "172.16.0.0/12", "192.168.0.0/16"]
+deny_query = ["10.0.0.66"]
+key = "transfer"
+answer_order = "round_robin"
+max_ttl = "5m"
+"#;
+
+        let config = toml::from_str::<RunConfig>(text)?;
+        assert_eq!(
+            config.general.listen_tcp(),
+            ["127.0.0.1:5300", "[::1]:5300"]
+        );
+        assert_eq!(config.general.listen_udp(), ["127.0.0.1:5353"]);
+        assert_eq!(config.general.udp_workers(), 4);
+        assert_eq!(config.general.tcp_timeout(), Duration::from_secs(30));
+        assert_eq!(config.general.shutdown_timeout(), Duration::from_secs(2));
+        assert_eq!(config.general.edns_buffer_size(), 1232);
+        assert_eq!(config.general.max_udp_response_size(), 4096);
+        assert_eq!(config.general.nsid(), Some("ns1.fra"));
+        assert_eq!(config.general.any_queries(), AnyQueries::Minimal);
+        assert_eq!(config.general.min_ttl(), None);
+        assert_eq!(config.general.max_ttl(), Some(Duration::from_secs(86400)));
+        assert!(config.general.watch_config());
+        assert_eq!(config.general.watch_interval(), Duration::from_secs(5));
+        let tls = config.general.listen_tls().clone().unwrap();
+        assert_eq!(tls.address(), "127.0.0.1:853");
+        assert_eq!(tls.cert(), Some(Path::new("/etc/libdns/cert.pem")));
+        assert_eq!(tls.key(), Some(Path::new("/etc/libdns/key.pem")));
+        assert_eq!(tls.reload_interval(), Duration::from_secs(60));
+        assert_eq!(
+            config.general.listen_quic().clone().unwrap(),
+            "127.0.0.1:853"
+        );
+        let https = config.general.listen_https().clone().unwrap();
+        assert_eq!(https.tls().address(), "127.0.0.1:443");
+        assert_eq!(https.tls().reload_interval(), Duration::from_secs(300));
+        assert_eq!(https.path(), "/dns-query");
+        let admin = config.general.listen_admin().clone().unwrap();
+        assert_eq!(admin.address(), "127.0.0.1:8053");
+        assert_eq!(admin.token(), Some("secret"));
+        let grpc = config.general.listen_grpc().clone().unwrap();
+        assert_eq!(grpc.address(), "127.0.0.1:50053");
+        assert_eq!(grpc.token(), None);
+        assert_eq!(
+            config.general.control_socket(),
+            Some(Path::new("/run/dns-server/control.sock"))
+        );
+        let rate_limit = config.general.rate_limit().clone().unwrap();
+        assert_eq!(rate_limit.queries_per_second(), 50);
+        assert_eq!(rate_limit.burst(), 50);
+        assert_eq!(rate_limit.ipv4_prefix(), 24);
+        assert_eq!(rate_limit.ipv6_prefix(), 64);
+        assert_eq!(rate_limit.action(), RateLimitAction::Drop);
+        assert!(config.general.allow_query().is_empty());
+        assert_eq!(
+            config.general.deny_query(),
+            &vec!["192.0.2.0/24".to_string()]
+        );
+        let rrl = config.general.response_rate_limit().clone().unwrap();
+        assert_eq!(rrl.responses_per_second(), 5);
+        assert_eq!(rrl.nxdomains_per_second(), 5);
+        assert_eq!(rrl.errors_per_second(), 0);
+        assert_eq!(rrl.window(), Duration::from_secs(15));
+        assert_eq!(rrl.slip(), 3);
+        assert_eq!(rrl.ipv4_prefix(), 24);
+        assert_eq!(rrl.exempt_clients(), ["10.0.0.0/8"]);
+        let otlp = config.general.otlp().clone().unwrap();
+        assert_eq!(otlp.endpoint(), "http://otel-collector:4317");
+        assert_eq!(otlp.service_name(), "libdns");
+        assert_eq!(otlp.timeout(), Duration::from_secs(10));
+        assert_eq!(config.zones.len(), 5);
+        let catalog = &config.zones["catalog.et.internal"];
+        assert!(catalog.catalog());
+        assert_eq!(*catalog.members(), ["et.internal", "et.apex"]);
+        assert!(!config.zones["et.apex"].catalog());
+        let alias = &config.zones["et.apex"].aliases()[0];
+        assert_eq!(alias.name(), "@");
+        assert_eq!(alias.target(), "lb.et.top");
+        assert_eq!(config.rewrites().len(), 2);
+        assert_eq!(config.rewrites()[0].match_kind(), RewriteMatch::Exact);
+        assert!(!config.rewrites()[0].rewrite_answers());
+        assert_eq!(config.rewrites()[1].match_kind(), RewriteMatch::Regex);
+        assert_eq!(config.rewrites()[1].from(), r"host-(\d+)\.corp");
+        let forward = config.forward().clone().unwrap();
+        assert_eq!(
+            forward.upstream_servers()?,
+            vec![
+                Upstream::Plain("1.1.1.1:53".parse()?),
+                Upstream::Plain("[2606:4700:4700::1111]:53".parse()?),
+            ]
+        );
+        assert_eq!(forward.timeout(), Duration::from_secs(2));
+        assert_eq!(forward.cache().max_entries(), 100);
+        assert_eq!(forward.cache().min_ttl(), Some(Duration::from_secs(30)));
+        assert_eq!(forward.cache().max_ttl(), None);
+        let prefetch = forward.cache().prefetch().unwrap();
+        assert_eq!(prefetch.hits(), 3);
+        assert_eq!(prefetch.window(), Duration::from_secs(5));
+        assert!(forward.serve_stale());
+        assert!(forward.randomize_case());
+        assert_eq!(forward.max_connections(), 4);
+        assert_eq!(forward.max_stale(), Duration::from_secs(3600));
+        assert_eq!(forward.rules().len(), 1);
+        assert_eq!(forward.rules()[0].domain(), "corp.example");
+        assert_eq!(forward.dnssec_validation(), DnssecValidation::Strict);
+        let client_subnet = forward.client_subnet().clone().unwrap();
+        assert_eq!(client_subnet.ipv4_prefix(), 20);
+        assert_eq!(client_subnet.ipv6_prefix(), 56);
+        let anchors = forward.trust_anchor_records()?;
+        assert_eq!(anchors.len(), 2);
+        assert!(anchors
+            .iter()
+            .all(|r| r.record_type() == rr::RecordType::DS));
+        assert_eq!(
+            forward.rules()[0].upstream_servers()?,
+            vec![Upstream::Plain("10.0.0.2:53".parse()?)]
+        );
+
+        let (domain, records) = config
+            .zones
+            .get_key_value("et.internal")
+            .ok_or_else(|| anyhow!("parse error"))?;
+        assert_eq!(domain, "et.internal");
+        assert_eq!(records.records().len(), 1);
+        let record = &records.records()[0];
+        assert_eq!(record.name, "www");
+        assert_eq!(
+            record.data,
+            RecordData::A(Ipv4Addr::new(123, 123, 123, 123))
+        );
+        assert_eq!(record.ttl, Some(Duration::from_secs(60)));
+        let check = record.health_check().unwrap();
+        assert_eq!(
+            check.probe(),
+            &HealthProbe::Http {
+                port: 8080,
+                path: "/healthz".to_string()
+            }
+        );
+        assert_eq!(check.interval(), Duration::from_secs(5));
+        assert_eq!(check.timeout(), Duration::from_secs(2));
+        assert_eq!((check.fall(), check.rise()), (3, 2));
+        assert_eq!(
+            record.geo(),
+            [
+                GeoSelector::Continent("EU".to_string()),
+                GeoSelector::Country("DE".to_string()),
+                GeoSelector::Asn(13335)
+            ]
+        );
+        assert!("city:Berlin".parse::<GeoSelector>().is_err());
+
+        let (domain, records) = config
+            .zones
+            .get_key_value("et.top")
+            .ok_or_else(|| anyhow!("parse error"))?;
+        assert_eq!(domain, "et.top");
+        assert_eq!(records.records().len(), 1);
+        let record = &records.records()[0];
+        assert_eq!(record.name, "@");
+        assert_eq!(
+            record.data,
+            RecordData::A(Ipv4Addr::new(100, 100, 100, 100))
+        );
+        assert_eq!(record.ttl, Some(Duration::from_secs(61)));
+        assert_eq!(records.zone_type(), ZoneType::Primary);
+        assert_eq!(records.answer_order(), AnswerOrder::Fixed);
+
+        let secondary = &config.zones["et.example"];
+        assert_eq!(secondary.zone_type(), ZoneType::Secondary);
+        assert_eq!(
+            secondary.primary_addrs()?,
+            vec![
+                "10.0.0.53:53".parse::<SocketAddr>()?,
+                "10.0.0.54:5353".parse::<SocketAddr>()?,
+            ]
+        );
+        assert_eq!(
+            secondary.notify_addrs()?,
+            vec!["10.0.0.55:53".parse::<SocketAddr>()?]
+        );
+        assert_eq!(
+            secondary.allow_notify(),
+            &vec!["10.0.0.0/24".to_string(), "key transfer".to_string()]
+        );
+        assert_eq!(secondary.allow_update(), &vec!["key transfer".to_string()]);
+        assert_eq!(secondary.allow_query().len(), 3);
+        assert_eq!(secondary.deny_query(), &vec!["10.0.0.66".to_string()]);
+        assert_eq!(secondary.answer_order(), AnswerOrder::RoundRobin);
+        assert_eq!(secondary.max_ttl(), Some(Duration::from_secs(300)));
+        assert_eq!(records.max_ttl(), None);
+        assert_eq!(secondary.key(), Some("transfer"));
+        let blocklist = config.blocklist().clone().unwrap();
+        assert_eq!(blocklist.sources().len(), 2);
+        assert_eq!(blocklist.allow(), &vec!["cdn.example.com".to_string()]);
+        assert_eq!(blocklist.response(), BlockResponse::Null);
+        assert_eq!(blocklist.refresh(), Duration::from_secs(86400));
+        assert_eq!(blocklist.ttl(), Duration::from_secs(60));
+        let geoip = config.geoip().clone().unwrap();
+        assert_eq!(
+            geoip.database(),
+            Some(Path::new("/var/lib/GeoIP/GeoLite2-Country.mmdb"))
+        );
+        assert_eq!(geoip.asn_database(), None);
+        assert_eq!(config.keys().len(), 1);
+        assert_eq!(config.keys()[0].name(), "transfer");
+        assert_eq!(config.keys()[0].algorithm(), "hmac-sha256");
+        assert_eq!(config.keys()[0].secret(), "c2VjcmV0");
+
+        Ok(())
+    }
+
+    #[test]
+    fn loads_config_by_extension() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let files = [
+            (
+                "config.toml",
+                r#"
+[general]
+listen_udp = "127.0.0.1:53"
+
+[[zones."et.internal"]]
+type = "A"
+name = "www.et.internal"
+value = "10.0.0.1"
+ttl = "60s"
+"#,
+            ),
+            (
+                "config.yaml",
+                r#"
+general:
+  listen_udp: 127.0.0.1:53
+zones:
+  et.internal:
+    - type: A
+      name: www.et.internal
+      value: 10.0.0.1
+      ttl: 60s
+"#,
+            ),
+            (
+                "config.json",
+                r#"{
+  "general": {"listen_udp": "127.0.0.1:53"},
+  "zones": {
+    "et.internal": [
+      {"type": "A", "name": "www.et.internal", "value": "10.0.0.1", "ttl": "60s"}
+    ]
+  }
+}"#,
+            ),
+        ];
+        for (name, text) in files {
+            let path = dir.path().join(name);
+            std::fs::write(&path, text)?;
+            let config = RunConfig::from_path(&path)?;
+            assert_eq!(config.general().listen_udp(), ["127.0.0.1:53"]);
+            assert_eq!(config.zones()["et.internal"].records().len(), 1);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn reports_parse_error_location() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let files = [
+            ("bad.toml", "[general]\nlisten_udp = 53\n", ":2:14: "),
+            ("bad.yaml", "general:\n  listen_udp: [[1]]\n", ":2:16: "),
+            ("bad.json", "{\n  \"general\": 1\n}", ":2:14: "),
+            (
+                "record.toml",
+                "[general]\n\n[[zones.\"et.internal\"]]\ntype = \"A\"\nname = \"www\"\nvalue = \"10.0.0.256\"\nttl = \"60s\"\n",
+                ":3:1: invalid IPv4 address",
+            ),
+        ];
+        for (name, text, location) in files {
+            let path = dir.path().join(name);
+            std::fs::write(&path, text)?;
+            let error = RunConfig::from_path(&path).unwrap_err();
+            assert!(matches!(error, Error::Config(_)));
+            let error = error.to_string();
+            assert!(
+                error.starts_with(&format!("{}{}", path.display(), location)),
+                "{}",
+                error
+            );
+            assert!(!error.contains(" at line "), "{}", error);
+        }
+
+        let path = dir.path().join("config.ini");
+        std::fs::write(&path, "")?;
+        assert!(RunConfig::from_path(&path).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn expands_environment_variables() -> anyhow::Result<()> {
+        // the tests run in parallel: no other test reads these variables
+        std::env::set_var("LIBDNS_TEST_LISTEN", "127.0.0.1:5353");
+        std::env::set_var("LIBDNS_TEST_SECRET", "a\"b\\c\nd = 1");
+        std::env::remove_var("LIBDNS_TEST_UNSET");
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            r#"
+[general]
+listen_udp = "${LIBDNS_TEST_LISTEN}"
+# nsid = "${LIBDNS_TEST_UNSET}"
+nsid = "${LIBDNS_TEST_SECRET}"
+
+[[zones."et.internal"]]
+type = "TXT"
+name = "www.et.internal"
+value = "${LIBDNS_TEST_UNSET:-v=1} $${HOME}"
+ttl = "60s"
+"#,
+        )?;
+        let config = RunConfig::from_path(&path)?;
+        assert_eq!(config.general().listen_udp(), ["127.0.0.1:5353"]);
+        // values are taken as they are, whatever the syntax of the file
+        assert_eq!(config.general().nsid(), Some("a\"b\\c\nd = 1"));
+        assert_eq!(
+            config.zones()["et.internal"].records()[0].data,
+            RecordData::Txt(vec!["v=1 ${HOME}".to_string()])
+        );
+
+        let json = dir.path().join("config.json");
+        let text = r#"{"general": {"nsid": "${LIBDNS_TEST_SECRET}"}}"#;
+        std::fs::write(&json, text)?;
+        let config = RunConfig::from_path(&json)?;
+        assert_eq!(config.general().nsid(), Some("a\"b\\c\nd = 1"));
+
+        std::fs::write(&path, "[general]\nnsid = \"${LIBDNS_TEST_UNSET}\"\n")?;
+        let error = RunConfig::from_path(&path).unwrap_err().to_string();
+        assert_eq!(
+            error,
+            format!(
+                "{}:2:9: environment variable LIBDNS_TEST_UNSET is not set",
+                path.display()
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_unknown_keys_when_strict() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let text = r#"
+[general]
+strict = true
+listn_udp = "127.0.0.1:53"
+listen_tcp = ["127.0.0.1:53", "127.0.0.1"]
+
+[[zones."et.internal"]]
+type = "A"
+name = "www.et.internal"
+value = "10.0.0.1"
+ttl = "60s"
+helth_check = { type = "tcp", port = 80 }
+
+[[zones."et.internal"]]
+type = "A"
+name = "api.et.internal"
+values = ["10.0.0.2"]
+"#;
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, text)?;
+        let error = RunConfig::from_path(&path).unwrap_err().to_string();
+        let path = path.display();
+        assert_eq!(
+            error,
+            format!(
+                "{path}:4:1: unknown key general.listn_udp\n\
+                 {path}:12:1: unknown key zones.\"et.internal\"[0].helth_check\n\
+                 {path}:5:1: invalid listen address \"127.0.0.1\": missing port"
+            )
+        );
+
+        let path = dir.path().join("config.yaml");
+        std::fs::write(
+            &path,
+            "general:\n  strict: true\n  listen_udp: 127.0.0.1:53\nzones: {}\nforwrd: {}\n",
+        )?;
+        let error = RunConfig::from_path(&path).unwrap_err().to_string();
+        assert!(error.ends_with(":5:1: unknown key forwrd"), "{}", error);
+
+        let path = dir.path().join("lax.toml");
+        std::fs::write(&path, text.replace("strict = true", ""))?;
+        assert!(RunConfig::from_path(&path).is_ok());
+
+        // included files are checked too
+        std::fs::create_dir(dir.path().join("zones"))?;
+        let included = dir.path().join("zones/lab.toml");
+        std::fs::write(
+            &included,
+            "[[zones.\"et.lab\"]]\ntype = \"A\"\nname = \"www\"\nvalue = \"10.0.0.3\"\nttll = \"60s\"\n",
+        )?;
+        let path = dir.path().join("included.toml");
+        std::fs::write(
+            &path,
+            "include = [\"zones/*.toml\"]\n\n[general]\nstrict = true\n",
+        )?;
+        let error = RunConfig::from_path(&path).unwrap_err().to_string();
+        assert_eq!(
+            error,
+            format!(
+                "{}:5:1: unknown key zones.\"et.lab\"[0].ttll",
+                included.display()
+            )
+        );
+        std::fs::remove_file(&included)?;
+        std::fs::write(
+            dir.path().join("zones/et.dir.toml"),
+            "default_tll = \"5m\"\n",
+        )?;
+        std::fs::write(&path, "zone_dir = \"zones\"\n\n[general]\nstrict = true\n")?;
+        let error = RunConfig::from_path(&path).unwrap_err().to_string();
+        assert!(
+            error.ends_with("et.dir.toml:1:1: unknown key default_tll"),
+            "{}",
+            error
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn loads_included_zones() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir(dir.path().join("zones.d"))?;
+        std::fs::create_dir(dir.path().join("zones"))?;
+        std::fs::write(
+            dir.path().join("zones.d/internal.toml"),
+            r#"
+[[zones."et.internal"]]
+type = "A"
+name = "www.et.internal"
+value = "10.0.0.1"
+ttl = "60s"
+
+[zones."et.lab"]
+file = "db.et.lab"
+"#,
+        )?;
+        std::fs::write(dir.path().join("zones.d/notes.txt"), "")?;
+        std::fs::write(dir.path().join("zones/et.top.zone"), "")?;
+        std::fs::write(
+            dir.path().join("zones/et.example.toml"),
+            "persist_file = \"et.example.saved\"\n",
+        )?;
+        let path = dir.path().join("config.toml");
+        let text = r#"
+include = ["zones.d/*.toml"]
+zone_dir = "zones"
+
+[general]
+"#;
+        std::fs::write(&path, text)?;
+        let config = RunConfig::from_path(&path)?;
+        let mut zones: Vec<&String> = config.zones().keys().collect();
+        zones.sort();
+        assert_eq!(zones, ["et.example", "et.internal", "et.lab", "et.top"]);
+        assert_eq!(config.zones()["et.internal"].records().len(), 1);
+        assert_eq!(
+            config.zones()["et.lab"].file(),
+            Some(dir.path().join("zones.d/db.et.lab").as_path())
+        );
+        assert_eq!(
+            config.zones()["et.top"].file(),
+            Some(dir.path().join("zones/et.top.zone").as_path())
+        );
+        assert_eq!(
+            config.zones()["et.example"].persist_file(),
+            Some(dir.path().join("zones/et.example.saved").as_path())
+        );
+
+        std::fs::write(&path, format!("{}\n[zones.\"et.top\"]\n", text))?;
+        let error = RunConfig::from_path(&path).unwrap_err().to_string();
+        assert!(
+            error.ends_with("zone et.top is already configured"),
+            "{}",
+            error
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn splits_record_values() -> anyhow::Result<()> {
+        let text = r#"
+[general]
+
+[[zones."et.internal"]]
+type = "A"
+name = "www"
+values = ["10.0.0.1", "10.0.0.2"]
+ttl = "60s"
+
+[[zones."et.internal"]]
+type = "MX"
+name = "@"
+values = ["10 mail1", { preference = 20, exchange = "mail2.et.internal" }]
+"#;
+        let config = toml::from_str::<RunConfig>(text)?;
+        let records = config.zones["et.internal"].records();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[1].data, RecordData::A("10.0.0.2".parse()?));
+        assert_eq!(records[1].ttl, Some(Duration::from_secs(60)));
+        assert_eq!(records[3].data.record_type(), RecordType::MX);
+
+        let parse = |entry: &str| {
+            toml::from_str::<RunConfig>(&format!(
+                "[general]\n\n[[zones.\"et.internal\"]]\ntype = \"A\"\nname = \"www\"\n{}\n",
+                entry
+            ))
+        };
+        let error = parse("values = [\"10.0.0.1\", \"10.0.0.256\"]").unwrap_err();
+        assert!(
+            error.message().contains("invalid IPv4 address"),
+            "{}",
+            error
+        );
+        let error = parse("value = \"10.0.0.1\"\nvalues = [\"10.0.0.2\"]").unwrap_err();
+        assert_eq!(
+            error.message(),
+            "a record takes a value or values, not both"
+        );
+        assert!(parse("values = []").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn defaults_record_ttls() -> anyhow::Result<()> {
+        let text = r#"
+[general]
+
+[zones."et.internal"]
+default_ttl = "5m"
+soa = { ttl = 7200 }
+
+[[zones."et.internal".records]]
+type = "A"
+name = "www"
+value = "10.0.0.1"
+
+[[zones."et.internal".records]]
+type = "A"
+name = "api"
+value = "10.0.0.2"
+ttl = 60
+
+[[zones."et.top"]]
+type = "A"
+name = "www"
+value = "10.0.0.3"
+"#;
+        let config = toml::from_str::<RunConfig>(text)?;
+        let ttl = |zone: &str, name: &str| -> anyhow::Result<u32> {
+            let origin = rr::Name::from_str(zone)?;
+            let records = config.zones[zone].to_records(&origin)?;
+            let name = rr::Name::from_str(name)?;
+            Ok(records.iter().find(|r| *r.name() == name).unwrap().ttl())
+        };
+        assert_eq!(ttl("et.internal", "www.et.internal")?, 300);
+        assert_eq!(ttl("et.internal", "api.et.internal")?, 60);
+        assert_eq!(ttl("et.internal", "et.internal")?, 7200);
+        assert_eq!(ttl("et.top", "www.et.top")?, 3600);
+
+        let error = toml::from_str::<Record>(
+            "type = \"A\"\nname = \"www\"\nvalue = \"10.0.0.1\"\nttl = -1\n",
+        )
+        .unwrap_err();
+        assert!(error.message().contains("negative TTL -1"), "{}", error);
+        Ok(())
+    }
+
+    #[test]
+    fn loads_zone_file() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(
+            dir.path().join("db.et.internal"),
+            r#"$ORIGIN et.internal.
+$TTL 1h
+@       IN SOA  ns1 hostmaster (
+                2024010101 ; serial
+                1h 15m 1w 5m )
+        IN NS   ns1
+        IN MX   10 mail
+ns1     IN A    10.0.0.53
+www  5m IN A    10.0.0.1
+        IN AAAA fd00::1
+api     IN CNAME www
+mail    IN A    10.0.0.25
+        IN TXT  "v=spf1 mx -all"
+_sip._tcp IN SRV 10 5 5060 www.et.internal.
+$GENERATE 1-4 host-$.pool IN A 10.1.0.$
+"#,
+        )?;
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            r#"
+[general]
+listen_udp = "127.0.0.1:53"
+
+[zones."et.internal"]
+file = "db.et.internal"
+persist_file = "db.et.internal.saved"
+
+[zones."et.internal".dnssec]
+ksk = "Ket.internal.ksk"
+signature_validity = "7d"
+nsec3 = { salt = "aabbccdd", opt_out = true }
+
+[[zones."et.internal".records]]
+type = "A"
+name = "extra.et.internal"
+value = "10.0.0.2"
+ttl = "60s"
+
+[[zones."et.internal".generate]]
+range = "10-20/5"
+type = "CNAME"
+name = "alias-${0,3}"
+value = "host-${-9}.pool"
+ttl = "5m"
+"#,
+        )?;
+
+        let config = RunConfig::from_path(&path)?;
+        let zone = &config.zones()["et.internal"];
+        assert_eq!(
+            zone.file(),
+            Some(dir.path().join("db.et.internal").as_path())
+        );
+        assert_eq!(
+            zone.persist_file(),
+            Some(dir.path().join("db.et.internal.saved").as_path())
+        );
+        let dnssec = zone.dnssec().unwrap();
+        assert_eq!(dnssec.algorithm(), "ecdsap256sha256");
+        assert_eq!(
+            dnssec.ksk(),
+            Some(dir.path().join("Ket.internal.ksk").as_path())
+        );
+        assert_eq!(dnssec.zsk(), None);
+        assert_eq!(
+            dnssec.signature_validity(),
+            Duration::from_secs(7 * 24 * 3600)
+        );
+        let nsec3 = dnssec.nsec3().unwrap();
+        assert_eq!(nsec3.iterations(), 0);
+        assert_eq!(nsec3.salt(), "aabbccdd");
+        assert!(nsec3.opt_out());
+
+        let origin = rr::Name::from_str("et.internal.")?;
+        assert!(zone.persisted_records(&origin)?.is_none());
+        let records = zone.to_records(&origin)?;
+        let find = |name: &str, rr_type: RecordType| -> anyhow::Result<Vec<rr::Record>> {
+            let name = rr::Name::from_str(name)?;
+            Ok(records
+                .iter()
+                .filter(|r| r.name() == &name && r.record_type() == rr_type)
+                .cloned()
+                .collect())
+        };
+        let soa = find("et.internal.", RecordType::SOA)?;
+        assert_eq!(soa.len(), 1);
+        assert_eq!(
+            soa[0].data().unwrap().as_soa().unwrap().serial(),
+            2024010101
+        );
+        assert_eq!(find("et.internal.", RecordType::NS)?.len(), 1);
+        assert_eq!(find("et.internal.", RecordType::MX)?.len(), 1);
+        let www = find("www.et.internal.", RecordType::A)?;
+        assert_eq!(www[0].ttl(), 300);
+        assert_eq!(find("www.et.internal.", RecordType::AAAA)?.len(), 1);
+        assert_eq!(find("ns1.et.internal.", RecordType::A)?[0].ttl(), 3600);
+        assert_eq!(
+            find("api.et.internal.", RecordType::CNAME)?[0]
+                .data()
+                .unwrap()
+                .to_string(),
+            "www.et.internal."
+        );
+        assert_eq!(find("mail.et.internal.", RecordType::TXT)?.len(), 1);
+        assert_eq!(find("_sip._tcp.et.internal.", RecordType::SRV)?.len(), 1);
+        assert_eq!(find("extra.et.internal.", RecordType::A)?.len(), 1);
+        assert_eq!(
+            find("host-4.pool.et.internal.", RecordType::A)?[0]
+                .data()
+                .unwrap()
+                .to_string(),
+            "10.1.0.4"
+        );
+        assert!(find("host-5.pool.et.internal.", RecordType::A)?.is_empty());
+        assert_eq!(zone.generate()[0].range(), "10-20/5");
+        let alias = find("alias-015.et.internal.", RecordType::CNAME)?;
+        assert_eq!(alias[0].ttl(), 300);
+        assert_eq!(
+            alias[0].data().unwrap().to_string(),
+            "host-6.pool.et.internal."
+        );
+        assert_eq!(
+            records
+                .iter()
+                .filter(|r| r.record_type() == RecordType::CNAME)
+                .count(),
+            4
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_invalid_zone_file() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("db.et.internal");
+        std::fs::write(&path, "www IN A not-an-address\n")?;
+        let zone = ZoneConfigBuilder::default().file(&path).build()?;
+        let error = zone
+            .to_records(&rr::Name::from_str("et.internal.")?)
+            .unwrap_err();
+        assert!(error.to_string().contains("db.et.internal"));
+        Ok(())
+    }
+
+    #[test]
+    fn can_convert_aaaa_record() -> anyhow::Result<()> {
+        let text = r#"
+[general]
+
+[[zones."et.internal"]]
+type = "AAAA"
+name = "www.et.internal"
+value = "fd00::1"
+ttl = "60s"
+"#;
+
+        let config = toml::from_str::<RunConfig>(text)?;
+        let record = &config.zones["et.internal"].records()[0];
+        assert_eq!(record.data, RecordData::Aaaa("fd00::1".parse()?));
+
+        let r: rr::Record = record.try_into()?;
+        assert_eq!(r.record_type(), RecordType::AAAA);
+        assert_eq!(r.ttl(), 60);
+        assert_eq!(
+            r.data(),
+            Some(&RData::AAAA(rr::rdata::aaaa::AAAA("fd00::1".parse()?)))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn resolves_names_in_zone() -> anyhow::Result<()> {
+        let origin = rr::Name::from_str("et.internal")?;
+        let owner = |name: &str| -> Result<rr::Name, Error> {
+            let record = RecordBuilder::default()
+                .name(name.to_string())
+                .data(RecordData::A(Ipv4Addr::new(10, 0, 0, 1)))
+                .ttl(Duration::from_secs(60))
+                .build()
+                .unwrap();
+            Ok(record.to_record(&origin)?.name().clone())
+        };
+        assert_eq!(owner("@")?, origin);
+        assert_eq!(owner("www")?, rr::Name::from_str("www.et.internal")?);
+        assert_eq!(owner("*.dev")?, rr::Name::from_str("*.dev.et.internal")?);
+        assert_eq!(
+            owner("www.et.internal")?,
+            rr::Name::from_str("www.et.internal")?
+        );
+        assert_eq!(
+            owner("WWW.et.internal.")?,
+            rr::Name::from_str("www.et.internal.")?
+        );
+        assert_eq!(
+            owner("www.et.top")?,
+            rr::Name::from_str("www.et.top.et.internal")?
+        );
+        let outside = owner("www.et.top.").unwrap_err();
+        assert!(matches!(&outside, Error::Record { name, .. } if name == "www.et.top."));
+        assert_eq!(
+            format!("{:#}", anyhow::Error::new(outside)),
+            "invalid record www.et.top.: www.et.top. is outside zone et.internal"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn publishes_service_records() -> anyhow::Result<()> {
+        let config: RunConfig = toml::from_str(
+            r#"
+[general]
+
+[zones.local]
+records = [{ type = "A", name = "printer", value = "192.168.1.20", ttl = "2m" }]
+
+[[zones.local.services]]
+instance = "Office Printer"
+type = "_ipp._tcp"
+host = "printer"
+port = 631
+txt = ["rp=ipp/print"]
+
+[[zones.local.services]]
+instance = "Scanner"
+type = "_uscan._tcp"
+host = "printer"
+port = 8080
+"#,
+        )?;
+        let origin = rr::Name::from_str("local.")?;
+        let records = config.zones()["local"].to_records(&origin)?;
+        let find = |name: &rr::Name, rr_type: RecordType| -> Vec<RData> {
+            records
+                .iter()
+                .filter(|r| r.name() == name && r.record_type() == rr_type)
+                .filter_map(|r| r.data().cloned())
+                .collect()
+        };
+        let name = |name: &str| rr::Name::from_str(name).unwrap();
+        let printer = rr::Name::from_labels([&b"Office Printer"[..], b"_ipp", b"_tcp", b"local"])?;
+        let ptr = |name: rr::Name| RData::PTR(rr::rdata::PTR(name));
+        assert_eq!(
+            find(&name("_services._dns-sd._udp.local."), RecordType::PTR),
+            [
+                ptr(name("_ipp._tcp.local.")),
+                ptr(name("_uscan._tcp.local."))
+            ]
+        );
+        assert_eq!(
+            find(&name("_ipp._tcp.local."), RecordType::PTR),
+            [ptr(printer.clone())]
+        );
+        assert_eq!(
+            find(&printer, RecordType::SRV),
+            [RData::SRV(rr::rdata::SRV::new(
+                0,
+                0,
+                631,
+                name("printer.local.")
+            ))]
+        );
+        assert_eq!(
+            find(&printer, RecordType::TXT),
+            [RData::TXT(rr::rdata::TXT::new(vec!["rp=ipp/print".into()]))]
+        );
+        assert_eq!(
+            find(&name("Scanner._uscan._tcp.local."), RecordType::TXT),
+            [RData::TXT(rr::rdata::TXT::new(vec![String::new()]))]
+        );
+
+        let service = ServiceBuilder::default()
+            .instance("Office Printer")
+            .service_type("_ipp")
+            .host("printer")
+            .port(631)
+            .build()?;
+        let e = service.to_records(&origin).unwrap_err();
+        assert!(matches!(&e, Error::Record { name, .. } if name == "Office Printer"));
+        Ok(())
+    }
+
+    #[test]
+    fn can_convert_cname_record() -> anyhow::Result<()> {
+        let record = RecordBuilder::default()
+            .name("www.et.internal".to_string())
+            .data(RecordData::Cname(rr::Name::from_str("web.et.top")?))
+            .ttl(Duration::from_secs(60))
+            .build()?;
+        let r: rr::Record = (&record).try_into()?;
+        assert_eq!(
+            r.data(),
+            Some(&RData::CNAME(rr::rdata::CNAME(rr::Name::from_str(
+                "web.et.top"
+            )?)))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn can_convert_mx_txt_srv_records() -> anyhow::Result<()> {
+        let text = r#"
+[general]
+
+[[zones."et.internal"]]
+type = "MX"
+name = "et.internal"
+value = { preference = 10, exchange = "mail.et.internal" }
+ttl = "60s"
+
+[[zones."et.internal"]]
+type = "MX"
+name = "et.internal"
+value = "20 backup.et.internal"
+ttl = "60s"
+
+[[zones."et.internal"]]
+type = "TXT"
+name = "et.internal"
+value = ["v=spf1 -all", "hello world"]
+ttl = "60s"
+
+[[zones."et.internal"]]
+type = "SRV"
+name = "_http._tcp.et.internal"
+value = { priority = 1, weight = 5, port = 8080, target = "www.et.internal" }
+ttl = "60s"
+"#;
+
+        let config = toml::from_str::<RunConfig>(text)?;
+        let records = config.zones["et.internal"]
+            .records()
+            .iter()
+            .map(|r| r.try_into())
+            .collect::<Result<Vec<rr::Record>, Error>>()?;
+
+        let mail = rr::Name::from_str("mail.et.internal")?;
+        let backup = rr::Name::from_str("backup.et.internal")?;
+        let www = rr::Name::from_str("www.et.internal")?;
+        assert_eq!(
+            records[0].data(),
+            Some(&RData::MX(rr::rdata::MX::new(10, mail)))
+        );
+        assert_eq!(
+            records[1].data(),
+            Some(&RData::MX(rr::rdata::MX::new(20, backup)))
+        );
+        assert_eq!(
+            records[2].data(),
+            Some(&RData::TXT(rr::rdata::TXT::new(vec![
+                "v=spf1 -all".to_string(),
+                "hello world".to_string()
+            ])))
+        );
+        assert_eq!(
+            records[3].data(),
+            Some(&RData::SRV(rr::rdata::SRV::new(1, 5, 8080, www)))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn synthesizes_soa_and_ns_records() -> anyhow::Result<()> {
+        let text = r#"
+[general]
+
+[zones."et.internal"]
+ns = ["ns1.et.internal", "ns2.et.internal"]
+
+[zones."et.internal".soa]
+serial = 42
+serial_policy = "date"
+minimum = "5m"
+
+[[zones."et.internal".records]]
+type = "A"
+name = "www.et.internal"
+value = "123.123.123.123"
+ttl = "60s"
+"#;
+
+        let config = toml::from_str::<RunConfig>(text)?;
+        let zone = &config.zones["et.internal"];
+        assert_eq!(zone.records().len(), 1);
+
+        let origin = rr::Name::from_str("et.internal")?;
+        let records = zone.to_records(&origin)?;
+        assert_eq!(records.len(), 4);
+        let ns = records
+            .iter()
+            .filter_map(|r| r.data().and_then(|d| d.as_ns()))
+            .map(|ns| ns.0.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(ns, vec!["ns1.et.internal", "ns2.et.internal"]);
+
+        let soa = records
+            .iter()
+            .find_map(|r| r.data().and_then(|d| d.as_soa()))
+            .ok_or_else(|| anyhow!("missing soa"))?;
+        assert_eq!(soa.mname(), &rr::Name::from_str("ns1.et.internal")?);
+        assert_eq!(soa.rname(), &rr::Name::from_str("hostmaster.et.internal")?);
+        assert_eq!(soa.serial(), 42);
+        assert_eq!(soa.minimum(), 300);
+        assert_eq!(zone.soa().serial_policy(), SerialPolicy::Date);
+        Ok(())
+    }
+
+    #[test]
+    fn raises_serials_by_policy() {
+        assert_eq!(date_serial(0), 1970010100);
+        assert_eq!(date_serial(1709251199), 2024022900);
+        assert_eq!(date_serial(1792152000), 2026101600);
+
+        assert_eq!(SerialPolicy::Increment.next(42), 43);
+        assert_eq!(SerialPolicy::Increment.next(u32::MAX), 0);
+        let today = date_serial(unix_time());
+        assert_eq!(SerialPolicy::Date.initial(), today);
+        assert_eq!(SerialPolicy::Date.next(2024010101), today);
+        assert_eq!(SerialPolicy::Date.next(today + 5), today + 6);
+        assert!(SerialPolicy::Unixtime.next(42) >= unix_time());
+        assert_eq!(SerialPolicy::Unixtime.next(u32::MAX - 1), u32::MAX);
+    }
+
+    #[test]
+    fn keeps_explicit_apex_ns_records() -> anyhow::Result<()> {
+        let record = RecordBuilder::default()
+            .name("et.internal".to_string())
+            .data(RecordData::Ns(rr::Name::from_str("dns.et.top")?))
+            .ttl(Duration::from_secs(60))
+            .build()?;
+        let zone = ZoneConfig::from(vec![record]);
+        let records = zone.to_records(&rr::Name::from_str("et.internal")?)?;
+        assert_eq!(
+            records
+                .iter()
+                .filter(|r| r.record_type() == RecordType::NS)
+                .count(),
+            1
+        );
+        assert_eq!(
+            records
+                .iter()
+                .filter(|r| r.record_type() == RecordType::SOA)
+                .count(),
+            1
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn synthesizes_reverse_records() -> anyhow::Result<()> {
+        let text = r#"
+[general]
+
+[zones."et.internal"]
+auto_reverse = true
+
+[[zones."et.internal".records]]
+type = "A"
+name = "www.et.internal"
+value = "10.0.1.2"
+ttl = "60s"
+
+[[zones."et.internal".records]]
+type = "AAAA"
+name = "www.et.internal"
+value = "fd00::1"
+ttl = "60s"
+"#;
+
+        let config = toml::from_str::<RunConfig>(text)?;
+        let origin = rr::Name::from_str("et.internal")?;
+        let zone = &config.zones["et.internal"];
+        assert!(zone.auto_reverse());
+
+        let zones = zone.reverse_records(&origin)?;
+        assert_eq!(zones.len(), 2);
+        let v4 = &zones[&rr::Name::from_str("1.0.10.in-addr.arpa.")?];
+        assert_eq!(v4.len(), 1);
+        assert_eq!(v4[0].name(), &rr::Name::from_str("2.1.0.10.in-addr.arpa.")?);
+        assert_eq!(
+            v4[0].data(),
+            Some(&RData::PTR(rr::rdata::PTR(rr::Name::from_str(
+                "www.et.internal"
+            )?)))
+        );
+        let v6 = &zones[&rr::Name::from_str("0.0.0.0.0.0.0.0.0.0.0.0.0.0.d.f.ip6.arpa.")?];
+        assert_eq!(v6.len(), 1);
+
+        let reverse = zone.reverse_zone(&origin)?;
+        assert_eq!(reverse.ns(), &vec!["ns1.et.internal.".to_string()]);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        let parse = |rr_type: &str, value: &str| {
+            let text = format!(
+                "type = \"{}\"\nname = \"et.internal\"\nvalue = {}\nttl = \"60s\"\n",
+                rr_type, value
+            );
+            toml::from_str::<Record>(&text)
+        };
+        assert!(parse("MX", r#""10 mail.et.internal""#).is_ok());
+        assert!(parse("MX", r#""mail.et.internal""#).is_err());
+        assert!(parse(
+            "MX",
+            r#"{ preference = "high", exchange = "mail.et.internal" }"#
+        )
+        .is_err());
+        assert!(parse("AAAA", r#""123.123.123.123""#).is_err());
+        assert!(parse("A", r#""10.0.0.256""#).is_err());
+        assert!(parse("SOA", r#""ns1.et.internal""#).is_err());
+        assert!(parse("TXT", r#"["v=spf1 -all", "second segment"]"#).is_ok());
+    }
+
+    #[test]
+    fn parses_zone_file_values() -> anyhow::Result<()> {
+        assert_eq!(
+            RecordData::from_text(RecordType::SRV, "1 5 8080 www.et.internal")?,
+            RecordData::Srv(Srv {
+                priority: 1,
+                weight: 5,
+                port: 8080,
+                target: rr::Name::from_str("www.et.internal")?,
+            })
+        );
+        assert!(RecordData::from_text(RecordType::MX, "mail.et.internal").is_err());
+        assert!(RecordData::from_text(RecordType::SOA, "ns1.et.internal").is_err());
+
+        let record = RecordBuilder::default()
+            .name("et.internal".to_string())
+            .data(RecordData::from_text(
+                RecordType::MX,
+                "10 mail.et.internal",
+            )?)
+            .ttl(Duration::from_secs(60))
+            .build()?;
+        let text = toml::to_string(&record)?;
+        assert_eq!(toml::from_str::<Record>(&text)?, record);
+        Ok(())
+    }
+
+    #[test]
+    fn parses_svcb_records() -> anyhow::Result<()> {
+        use rr::rdata::svcb::{SvcParamKey, SvcParamValue};
+
+        let table: Record = toml::from_str(
+            r#"
+type = "HTTPS"
+name = "www.et.internal"
+value = { priority = 1, target = ".", alpn = ["h2", "h3"], port = 8443 }
+"#,
+        )?;
+        let text: Record = toml::from_str(
+            r#"
+type = "HTTPS"
+name = "www.et.internal"
+value = "1 . alpn=h2,h3 port=8443"
+"#,
+        )?;
+        assert_eq!(table, text);
+        let record = table.with_name(rr::Name::from_str("www.et.internal.")?)?;
+        let Some(RData::HTTPS(https)) = record.data() else {
+            panic!("expected an HTTPS record, got {:?}", record.data());
+        };
+        assert_eq!(https.svc_priority(), 1);
+        assert_eq!(https.target_name(), &rr::Name::root());
+        let params = https.svc_params();
+        assert_eq!(params.len(), 2);
+        assert_eq!(params[0].0, SvcParamKey::Alpn);
+        assert_eq!(params[1], (SvcParamKey::Port, SvcParamValue::Port(8443)));
+
+        let svcb = RecordData::from_text(
+            RecordType::SVCB,
+            "2 svc.et.internal ipv4hint=10.0.0.1,10.0.0.2 ipv6hint=fd00::1 ech=AQID",
+        )?;
+        let RecordData::Svcb(value) = &svcb else {
+            panic!("expected an SVCB record, got {:?}", svcb);
+        };
+        assert_eq!(value.ipv4hint.len(), 2);
+        assert_eq!(value.ipv6hint, vec![Ipv6Addr::from_str("fd00::1")?]);
+        assert_eq!(value.ech, Some(vec![1, 2, 3]));
+        let RData::SVCB(rdata) = RData::from(&svcb) else {
+            panic!("expected SVCB rdata");
+        };
+        let keys: Vec<_> = rdata.svc_params().iter().map(|(key, _)| *key).collect();
+        assert_eq!(
+            keys,
+            vec![
+                SvcParamKey::Ipv4Hint,
+                SvcParamKey::EchConfig,
+                SvcParamKey::Ipv6Hint
+            ]
+        );
+
+        assert!(RecordData::from_text(RecordType::SVCB, "1 . mandatory=alpn").is_err());
+        assert!(RecordData::from_text(RecordType::HTTPS, "1 . ech=@@@").is_err());
+        assert!(RecordData::from_text(RecordType::HTTPS, "1").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parses_tlsa_records() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cert = rcgen::generate_simple_self_signed(vec!["www.et.internal".to_string()])?;
+        let cert_path = dir.path().join("www.pem");
+        std::fs::write(&cert_path, cert.serialize_pem()?)?;
+        let parse = |value: &str| {
+            let text = format!(
+                "type = \"TLSA\"\nname = \"_443._tcp.www\"\nvalue = {}\n",
+                value
+            );
+            toml::from_str::<Record>(&text)
+        };
+
+        let record = parse(&format!(
+            "{{ usage = 3, selector = 1, matching = 1, certificate = {:?} }}",
+            cert_path
+        ))?;
+        let RecordData::Tlsa(tlsa) = &record.data else {
+            panic!("expected a TLSA record, got {:?}", record.data);
+        };
+        let expected = crate::tls::association_data(&cert_path, 1, 1)?;
+        assert_eq!(tlsa.load()?.data, expected);
+        assert_eq!(expected.len(), 32);
+        let tlsa_data = |record: &Record| -> anyhow::Result<Vec<u8>> {
+            let built = record.to_record(&rr::Name::from_str("et.internal.")?)?;
+            let Some(RData::TLSA(rdata)) = built.data() else {
+                panic!("expected TLSA rdata, got {:?}", built.data());
+            };
+            Ok(rdata.cert_data().to_vec())
+        };
+        assert_eq!(tlsa_data(&record)?, expected);
+        // the certificate is kept in place of its digest
+        let text = toml::to_string(&record)?;
+        assert!(!text.contains("data"));
+        assert_eq!(toml::from_str::<Record>(&text)?, record);
+
+        let hex = data_encoding::HEXLOWER.encode(&expected);
+        let record = parse(&format!("\"3 1 1 {}\"", hex))?;
+        assert_eq!(tlsa_data(&record)?, expected);
+        assert_eq!(
+            parse(&format!(
+                "{{ usage = 3, selector = 1, matching = 1, data = {:?} }}",
+                hex.to_uppercase()
+            ))?,
+            record
+        );
+
+        assert!(parse(&format!(
+            "{{ usage = 3, selector = 1, matching = 1, data = {:?}, certificate = {:?} }}",
+            hex, cert_path
+        ))
+        .is_err());
+        assert!(parse("{ usage = 3, selector = 1, matching = 1 }").is_err());
+        assert!(parse("\"3 1 1 xyz\"").is_err());
+        let missing = parse(&format!(
+            "{{ usage = 3, selector = 1, matching = 1, certificate = {:?} }}",
+            dir.path().join("missing.pem")
+        ))?;
+        assert!(tlsa_data(&missing).is_err());
+
+        // a relative certificate is found next to the config file
+        std::fs::write(
+            dir.path().join("config.toml"),
+            r#"
+[general]
+
+[[zones."et.internal"]]
+type = "TLSA"
+name = "_443._tcp.www"
+value = { usage = 3, selector = 1, matching = 1, certificate = "www.pem" }
+"#,
+        )?;
+        let config = RunConfig::from_path(dir.path().join("config.toml"))?;
+        let record = &config.zones()["et.internal"].records()[0];
+        assert_eq!(tlsa_data(record)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn parses_generic_records() -> anyhow::Result<()> {
+        let parse = |rr_type: &str, value: &str| {
+            let text = format!(
+                "type = \"{}\"\nname = \"www\"\nvalue = {}\n",
+                rr_type, value
+            );
+            toml::from_str::<Record>(&text)
+        };
+
+        let record = parse("TYPE65280", r"'\# 4 0A00 0001'")?;
+        assert_eq!(
+            record.data,
+            RecordData::Unknown(UnknownData {
+                rr_type: RecordType::Unknown(65280),
+                data: vec![10, 0, 0, 1],
+            })
+        );
+        assert_eq!(
+            RData::from(&record.data),
+            RData::Unknown {
+                code: RecordType::Unknown(65280),
+                rdata: rr::rdata::NULL::with(vec![10, 0, 0, 1]),
+            }
+        );
+        let text = toml::to_string(&record)?;
+        assert!(text.contains(r#"type = "TYPE65280""#), "{}", text);
+        assert_eq!(toml::from_str::<Record>(&text)?, record);
+
+        // types hickory knows, but without explicit support here
+        let record = parse("TYPE257", r"'\# 0'")?;
+        assert_eq!(record.data.record_type(), RecordType::CAA);
+        // and those with it, in their own form
+        assert_eq!(
+            parse("TYPE1", "\"10.0.0.1\"")?.data,
+            RecordData::A(Ipv4Addr::new(10, 0, 0, 1))
+        );
+
+        assert!(parse("TYPE65280", r"'\# 3 0a000001'").is_err());
+        assert!(parse("TYPE65280", r"'\# 4 0a0000zz'").is_err());
+        assert!(parse("TYPE65280", "\"10.0.0.1\"").is_err());
+        assert!(parse("TYPE65280", "[1, 2]").is_err());
+        assert!(parse("TYPE", r"'\# 0'").is_err());
+        assert!(parse("TYPE70000", r"'\# 0'").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parses_sshfp_records() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let key_file = dir.path().join("ssh_host_ed25519_key.pub");
+        std::fs::write(
+            &key_file,
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIO0RWUDHn1LZunfz13G/5Tvg5ThZVVtWwe0fdBdKieqA root@lab\n",
+        )?;
+        let fingerprint = "1e008168bd801dba81ec19ce92ccf7590d29f6d8c28cb23566f521a40731589f";
+        let parse = |value: &str| {
+            let text = format!("type = \"SSHFP\"\nname = \"lab\"\nvalue = {}\n", value);
+            toml::from_str::<Record>(&text)
+        };
+
+        let record = parse(&format!(
+            "{{ fingerprint_type = 2, key_file = {:?} }}",
+            key_file
+        ))?;
+        let RecordData::Sshfp(sshfp) = &record.data else {
+            panic!("expected an SSHFP record, got {:?}", record.data);
+        };
+        let loaded = sshfp.load()?;
+        assert_eq!(loaded.algorithm, 4);
+        assert_eq!(
+            data_encoding::HEXLOWER.encode(&loaded.fingerprint),
+            fingerprint
+        );
+        let rdata = |record: &Record| -> anyhow::Result<RData> {
+            let built = record.to_record(&rr::Name::from_str("et.internal.")?)?;
+            Ok(built.data().cloned().expect("SSHFP rdata"))
+        };
+        // the key file is kept in place of its fingerprint
+        let text = toml::to_string(&record)?;
+        assert!(!text.contains("fingerprint ="), "{}", text);
+        assert!(!text.contains("algorithm"), "{}", text);
+        assert_eq!(toml::from_str::<Record>(&text)?, record);
+
+        let given = parse(&format!("\"4 2 {}\"", fingerprint))?;
+        assert_eq!(rdata(&given)?, rdata(&record)?);
+        assert_eq!(
+            parse(&format!(
+                "{{ algorithm = 4, fingerprint_type = 2, fingerprint = {:?} }}",
+                fingerprint
+            ))?,
+            given
+        );
+        let with_algorithm = parse(&format!(
+            "{{ algorithm = 4, fingerprint_type = 2, key_file = {:?} }}",
+            key_file
+        ))?;
+        assert_eq!(rdata(&with_algorithm)?, rdata(&given)?);
+
+        let mismatch = parse(&format!(
+            "{{ algorithm = 1, fingerprint_type = 2, key_file = {:?} }}",
+            key_file
+        ))?;
+        assert!(rdata(&mismatch).is_err());
+        let missing = parse(&format!(
+            "{{ fingerprint_type = 2, key_file = {:?} }}",
+            dir.path().join("missing.pub")
+        ))?;
+        assert!(rdata(&missing).is_err());
+        assert!(parse(&format!(
+            "{{ fingerprint_type = 2, fingerprint = {:?} }}",
+            fingerprint
+        ))
+        .is_err());
+        assert!(parse("{ fingerprint_type = 2 }").is_err());
+        assert!(parse("\"4 2 xyz\"").is_err());
+
+        // a relative key file is found next to the config file
+        std::fs::write(
+            dir.path().join("config.toml"),
+            r#"
+[general]
+
+[[zones."et.internal"]]
+type = "SSHFP"
+name = "lab"
+value = { fingerprint_type = 2, key_file = "ssh_host_ed25519_key.pub" }
+"#,
+        )?;
+        let config = RunConfig::from_path(dir.path().join("config.toml"))?;
+        let record = &config.zones()["et.internal"].records()[0];
+        assert_eq!(rdata(record)?, rdata(&given)?);
+        Ok(())
+    }
+
+    #[test]
+    fn parses_acme_certificates() -> anyhow::Result<()> {
+        let text = r#"
+[general.listen_tls]
+address = "0.0.0.0:853"
+
+[general.acme.certificate]
+domains = ["ns1.et.internal", "*.et.internal"]
+email = "hostmaster@et.internal"
+cert_dir = "/var/lib/dns-server/acme"
+renew_before = "14d"
+"#;
+        let config = toml::from_str::<RunConfig>(text)?;
+        let tls = config.general().listen_tls().clone().unwrap();
+        assert_eq!(tls.cert(), None);
+        assert_eq!(tls.key(), None);
+        let certificate = config.general().acme().certificate().clone().unwrap();
+        assert_eq!(
+            certificate.directory(),
+            "https://acme-v02.api.letsencrypt.org/directory"
+        );
+        assert_eq!(certificate.domains(), ["ns1.et.internal", "*.et.internal"]);
+        assert_eq!(certificate.email(), Some("hostmaster@et.internal"));
+        assert_eq!(certificate.renew_before(), Duration::from_secs(14 * 86400));
+        assert_eq!(certificate.ca_file(), None);
+        assert_eq!(
+            certificate.cert_path(),
+            Path::new("/var/lib/dns-server/acme/cert.pem")
+        );
+        assert_eq!(
+            certificate.account_key_path(),
+            Path::new("/var/lib/dns-server/acme/account.key")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parses_upstream_urls() -> anyhow::Result<()> {
+        let tls = |host: &str, port, server_name: &str| Upstream::Tls {
+            host: host.to_string(),
+            port,
+            server_name: server_name.to_string(),
+        };
+        let cases = [
+            ("1.1.1.1", Upstream::Plain("1.1.1.1:53".parse()?)),
+            ("tls://1.1.1.1", tls("1.1.1.1", 853, "1.1.1.1")),
+            (
+                "tls://1.1.1.1#one.one.one.one",
+                tls("1.1.1.1", 853, "one.one.one.one"),
+            ),
+            (
+                "tls://[2606:4700::1111]:8853",
+                tls("2606:4700::1111", 8853, "2606:4700::1111"),
+            ),
+            (
+                "tls://dns.quad9.net",
+                tls("dns.quad9.net", 853, "dns.quad9.net"),
+            ),
+            (
+                "https://dns.google/dns-query",
+                Upstream::Https("https://dns.google/dns-query".to_string()),
+            ),
+        ];
+        for (text, upstream) in cases {
+            assert_eq!(text.parse::<Upstream>()?, upstream, "{}", text);
+        }
+        assert_eq!(
+            tls("1.1.1.1", 853, "one.one.one.one").to_string(),
+            "tls://1.1.1.1:853#one.one.one.one"
+        );
+        assert_eq!(
+            tls("2606:4700::1111", 853, "2606:4700::1111").to_string(),
+            "tls://[2606:4700::1111]:853"
+        );
+        for invalid in [
+            "tls://",
+            "tls://1.1.1.1#",
+            "tls://dns:port",
+            "https:///dns-query",
+        ] {
+            assert!(invalid.parse::<Upstream>().is_err(), "{}", invalid);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn parses_forward_and_stub_zones() -> anyhow::Result<()> {
+        let text = r#"
+[general]
+
+[zones."corp.example"]
+type = "forward"
+forwarders = ["10.0.0.53", "tls://10.0.0.54#dns.corp.example"]
+forward = "only"
+
+[zones."lab.example"]
+type = "forward"
+
+[zones."branch.example"]
+type = "stub"
+primaries = ["10.1.0.53"]
+"#;
+        let config = toml::from_str::<RunConfig>(text)?;
+        let corp = &config.zones()["corp.example"];
+        assert_eq!(corp.zone_type(), ZoneType::Forward);
+        assert_eq!(corp.forward(), ForwardMode::Only);
+        assert_eq!(
+            corp.forwarder_servers()?,
+            vec![
+                Upstream::Plain("10.0.0.53:53".parse()?),
+                "tls://10.0.0.54#dns.corp.example".parse()?,
+            ]
+        );
+        let lab = &config.zones()["lab.example"];
+        assert_eq!(lab.forward(), ForwardMode::First);
+        assert!(lab.forwarders().is_empty());
+        let branch = &config.zones()["branch.example"];
+        assert_eq!(branch.zone_type(), ZoneType::Stub);
+        assert_eq!(branch.primary_addrs()?, vec!["10.1.0.53:53".parse()?]);
         Ok(())
     }
 }
@@ -0,0 +1,438 @@
+//! Connections to the TCP and DNS-over-TLS upstreams, kept open for the next
+//! queries, which are pipelined over them, and opened to the addresses of
+//! dual-stack upstreams with Happy Eyeballs (RFC 8305).
+
+use crate::config::{ForwardConfig, Upstream};
+use anyhow::{anyhow, bail, Context, Result};
+use hickory_proto::error::{ProtoError, ProtoErrorKind};
+use hickory_proto::iocompat::AsyncIoTokioAsStd;
+use hickory_proto::op::{Message, NoopMessageFinalizer};
+use hickory_proto::rustls::tls_client_connect;
+use hickory_proto::tcp::TcpClientStream;
+use hickory_proto::xfer::{
+    DnsExchange, DnsHandle, DnsMultiplexer, DnsRequest, DnsRequestOptions, FirstAnswer,
+};
+use hickory_proto::TokioTime;
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio::task::{AbortHandle, JoinSet};
+use tracing::debug;
+
+/// How long after a connection attempt the next address is tried, as RFC
+/// 8305 recommends.
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// How long an unused connection is kept, upstreams closing them soon.
+const MAX_IDLE: Duration = Duration::from_secs(30);
+
+/// Open connections to upstreams, a few each.
+pub(crate) struct Connections {
+    /// For the DNS-over-TLS upstreams, if any.
+    tls: Option<Arc<rustls::ClientConfig>>,
+    timeout: Duration,
+    max_connections: usize,
+    pools: Mutex<HashMap<Upstream, Vec<Pooled>>>,
+}
+
+struct Pooled {
+    connection: Arc<Connection>,
+    last_used: Instant,
+}
+
+impl Pooled {
+    /// Queries being sent over the connection, which the pool doesn't own.
+    fn pending(&self) -> usize {
+        Arc::strong_count(&self.connection) - 1
+    }
+}
+
+/// An open connection, closed when dropped.
+struct Connection {
+    exchange: DnsExchange,
+    background: AbortHandle,
+}
+
+impl Connection {
+    fn new(
+        exchange: DnsExchange,
+        background: impl Future<Output = Result<(), ProtoError>> + Send + 'static,
+    ) -> Self {
+        Self {
+            exchange,
+            background: tokio::spawn(background).abort_handle(),
+        }
+    }
+
+    async fn send(&self, message: Message) -> Result<Message, ProtoError> {
+        let request = DnsRequest::new(message, DnsRequestOptions::default());
+        let response = self.exchange.send(request).first_answer().await?;
+        Ok(response.into_message())
+    }
+
+    /// Whether `error`, of a query sent over the connection, shows that it
+    /// was closed, rather than that the upstream was slow to answer.
+    fn closed(&self, error: &ProtoError) -> bool {
+        if self.background.is_finished() {
+            return true;
+        }
+        match error.kind() {
+            ProtoErrorKind::Busy => true,
+            ProtoErrorKind::Message(message) => {
+                matches!(*message, "stream closed" | "receiver was canceled")
+            }
+            ProtoErrorKind::Io(e) => matches!(
+                e.kind(),
+                std::io::ErrorKind::BrokenPipe
+                    | std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::ConnectionAborted
+                    | std::io::ErrorKind::NotConnected
+                    | std::io::ErrorKind::UnexpectedEof
+            ),
+            _ => false,
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.background.abort();
+    }
+}
+
+impl Connections {
+    /// The connections of `config`, with TLS ones when `tls`.
+    pub(crate) fn new(config: &ForwardConfig, tls: bool) -> Result<Self> {
+        let tls = match tls {
+            true => Some(Arc::new(crate::tls::client_config(config.ca_file())?)),
+            false => None,
+        };
+        Ok(Self {
+            tls,
+            timeout: config.timeout(),
+            max_connections: config.max_connections().max(1),
+            pools: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Sends `message` over the least busy open connection to `upstream`,
+    /// or over a new one when they are all busy and there is room for
+    /// another or when it was closed, as upstreams close the connections
+    /// idle for a while. A query timed out isn't sent again, and the
+    /// connection is kept.
+    pub(crate) async fn exchange(&self, upstream: &Upstream, message: Message) -> Result<Message> {
+        if let Some(connection) = self.checkout(upstream) {
+            match connection.send(message.clone()).await {
+                Ok(response) => return Ok(response),
+                Err(e) if connection.closed(&e) => {
+                    debug!("open connection to {} was closed: {}", upstream, e)
+                }
+                Err(e) => return Err(e.into()),
+            }
+            self.remove(upstream, &connection);
+        }
+        let connection = tokio::time::timeout(self.timeout, self.connect(upstream))
+            .await
+            .with_context(|| format!("timed out connecting to {}", upstream))??;
+        let connection = Arc::new(connection);
+        self.checkin(upstream, &connection);
+        let response = connection.send(message).await;
+        if let Err(e) = &response {
+            if connection.closed(e) {
+                self.remove(upstream, &connection);
+            }
+        }
+        Ok(response?)
+    }
+
+    /// The least busy connection to `upstream`, unless it is busy and
+    /// another may be opened. Drops the connections closed or idle for too
+    /// long on the way.
+    fn checkout(&self, upstream: &Upstream) -> Option<Arc<Connection>> {
+        let now = Instant::now();
+        let mut pools = self.pools.lock().unwrap();
+        pools.retain(|_, pool| {
+            pool.retain(|pooled| {
+                !pooled.connection.background.is_finished()
+                    && (pooled.pending() > 0 || now - pooled.last_used < MAX_IDLE)
+            });
+            !pool.is_empty()
+        });
+        let pool = pools.get_mut(upstream)?;
+        let room = pool.len() < self.max_connections;
+        let pooled = pool.iter_mut().min_by_key(|pooled| pooled.pending())?;
+        if pooled.pending() > 0 && room {
+            return None;
+        }
+        pooled.last_used = now;
+        Some(pooled.connection.clone())
+    }
+
+    /// Keeps `connection` open for the next queries to `upstream` if its
+    /// pool isn't full, it is closed once sent over otherwise.
+    fn checkin(&self, upstream: &Upstream, connection: &Arc<Connection>) {
+        let mut pools = self.pools.lock().unwrap();
+        let pool = pools.entry(upstream.clone()).or_default();
+        if pool.len() < self.max_connections {
+            pool.push(Pooled {
+                connection: connection.clone(),
+                last_used: Instant::now(),
+            });
+        }
+    }
+
+    fn remove(&self, upstream: &Upstream, connection: &Arc<Connection>) {
+        if let Some(pool) = self.pools.lock().unwrap().get_mut(upstream) {
+            pool.retain(|pooled| !Arc::ptr_eq(&pooled.connection, connection));
+        }
+    }
+
+    async fn connect(&self, upstream: &Upstream) -> Result<Connection> {
+        let timeout = self.timeout;
+        match upstream {
+            Upstream::Plain(address) => {
+                let (stream, sender) =
+                    TcpClientStream::<AsyncIoTokioAsStd<TcpStream>>::with_timeout(
+                        *address, timeout,
+                    );
+                let multiplexer = DnsMultiplexer::<_, NoopMessageFinalizer>::with_timeout(
+                    stream, sender, timeout, None,
+                );
+                let (exchange, background) =
+                    DnsExchange::connect::<_, _, TokioTime>(multiplexer).await?;
+                Ok(Connection::new(exchange, background))
+            }
+            Upstream::Tls {
+                host,
+                port,
+                server_name,
+            } => {
+                let config = self.tls.clone().expect("tls upstreams have a tls config");
+                let addresses = tokio::net::lookup_host((host.as_str(), *port))
+                    .await
+                    .with_context(|| format!("failed to resolve {}", host))?;
+                let server_name = server_name.clone();
+                race(interleave(addresses.collect()), move |address| {
+                    let (stream, sender) = tls_client_connect::<AsyncIoTokioAsStd<TcpStream>>(
+                        address,
+                        server_name.clone(),
+                        config.clone(),
+                    );
+                    let multiplexer = DnsMultiplexer::<_, NoopMessageFinalizer>::with_timeout(
+                        stream, sender, timeout, None,
+                    );
+                    async move {
+                        let (exchange, background) =
+                            DnsExchange::connect::<_, _, TokioTime>(multiplexer).await?;
+                        Ok(Connection::new(exchange, background))
+                    }
+                })
+                .await
+            }
+            Upstream::Https(_) => bail!("{} isn't a tcp or tls upstream", upstream),
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn open(&self, upstream: &Upstream) -> usize {
+        self.pools
+            .lock()
+            .unwrap()
+            .get(upstream)
+            .map_or(0, |pool| pool.len())
+    }
+}
+
+/// `addresses` alternating between their families, starting with that of
+/// the first one, as the system sorts them by preference.
+fn interleave(addresses: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let Some(first) = addresses.first() else {
+        return addresses;
+    };
+    let first_family = first.is_ipv6();
+    let (mut preferred, mut other): (Vec<_>, Vec<_>) = addresses
+        .into_iter()
+        .partition(|address| address.is_ipv6() == first_family);
+    let mut interleaved = Vec::with_capacity(preferred.len() + other.len());
+    preferred.reverse();
+    other.reverse();
+    while let Some(address) = preferred.pop() {
+        interleaved.push(address);
+        interleaved.extend(other.pop());
+    }
+    interleaved.extend(other.into_iter().rev());
+    interleaved
+}
+
+/// Connects to the first of `addresses` to accept, starting an attempt to
+/// the next one every `CONNECTION_ATTEMPT_DELAY`, or as soon as the previous
+/// ones all failed. The other attempts are dropped.
+async fn race<T, F>(addresses: Vec<SocketAddr>, connect: impl Fn(SocketAddr) -> F) -> Result<T>
+where
+    T: Send + 'static,
+    F: Future<Output = Result<T>> + Send + 'static,
+{
+    let mut addresses = addresses.into_iter();
+    let mut attempts = JoinSet::new();
+    let mut last_error = None;
+    loop {
+        if let Some(address) = addresses.next() {
+            let attempt = connect(address);
+            attempts.spawn(async move {
+                attempt
+                    .await
+                    .with_context(|| format!("failed to connect to {}", address))
+            });
+        }
+        let more = !addresses.as_slice().is_empty();
+        tokio::select! {
+            attempt = attempts.join_next() => match attempt {
+                Some(attempt) => match attempt? {
+                    Ok(connection) => return Ok(connection),
+                    Err(e) => {
+                        debug!("{:#}", e);
+                        last_error = Some(e);
+                    }
+                },
+                None => return Err(last_error.unwrap_or_else(|| anyhow!("no address"))),
+            },
+            _ = tokio::time::sleep(CONNECTION_ATTEMPT_DELAY), if more => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ForwardConfigBuilder;
+    use crate::forward::request;
+    use crate::testing::TestServer;
+    use hickory_proto::op::Query;
+    use hickory_proto::rr::{Name, RecordType};
+    use std::str::FromStr;
+
+    #[test]
+    fn interleaves_families() {
+        let addresses = |list: &[&str]| -> Vec<SocketAddr> {
+            list.iter().map(|a| a.parse().unwrap()).collect()
+        };
+        let (v6a, v6b, v4a, v4b) = ("[::1]:53", "[::2]:53", "10.0.0.1:53", "10.0.0.2:53");
+        assert_eq!(
+            interleave(addresses(&[v6a, v6b, v4a, v4b])),
+            addresses(&[v6a, v4a, v6b, v4b])
+        );
+        assert_eq!(
+            interleave(addresses(&[v4a, v4b, v6a])),
+            addresses(&[v4a, v6a, v4b])
+        );
+        assert_eq!(interleave(addresses(&[v6a])), addresses(&[v6a]));
+        assert!(interleave(Vec::new()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn races_connections() -> Result<()> {
+        let slow: SocketAddr = "[::1]:53".parse()?;
+        let fast: SocketAddr = "10.0.0.1:53".parse()?;
+        let connect = |delay: Duration, fails: bool| {
+            move |address: SocketAddr| async move {
+                let delay = match address == slow {
+                    true => delay,
+                    false => Duration::from_millis(10),
+                };
+                tokio::time::sleep(delay).await;
+                match fails && address == slow {
+                    true => bail!("refused"),
+                    false => Ok(address),
+                }
+            }
+        };
+
+        // the next address is tried when the first one is slow
+        let start = Instant::now();
+        let winner = race(vec![slow, fast], connect(Duration::from_secs(5), false)).await?;
+        assert_eq!(winner, fast);
+        let elapsed = start.elapsed();
+        assert!(elapsed >= CONNECTION_ATTEMPT_DELAY && elapsed < Duration::from_secs(1));
+
+        // and at once when it fails
+        let start = Instant::now();
+        let winner = race(vec![slow, fast], connect(Duration::ZERO, true)).await?;
+        assert_eq!(winner, fast);
+        assert!(start.elapsed() < CONNECTION_ATTEMPT_DELAY);
+
+        // the first one to connect wins
+        let winner = race(vec![slow, fast], connect(Duration::ZERO, false)).await?;
+        assert_eq!(winner, slow);
+
+        let error = race(vec![slow], connect(Duration::ZERO, true)).await;
+        assert!(error.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pools_tcp_connections() -> Result<()> {
+        let server = TestServer::with_zone(
+            "et.internal",
+            vec![crate::testing::record(
+                crate::config::RecordType::A,
+                "www",
+                "10.0.0.1",
+            )?],
+        )
+        .await?;
+        let upstream = Upstream::Plain(server.tcp_addr());
+        let config = ForwardConfigBuilder::default().max_connections(2).build()?;
+        let connections = Connections::new(&config, false)?;
+        let query = Query::query(Name::from_str("www.et.internal.")?, RecordType::A);
+
+        // one connection for queries one after the other
+        for _ in 0..3 {
+            let response = connections
+                .exchange(&upstream, request(&query, false, None))
+                .await?;
+            assert_eq!(response.answers().len(), 1);
+        }
+        assert_eq!(connections.open(&upstream), 1);
+
+        // a few at most for concurrent ones
+        let exchanges =
+            (0..8).map(|_| connections.exchange(&upstream, request(&query, false, None)));
+        for response in futures_util::future::join_all(exchanges).await {
+            assert_eq!(response?.answers().len(), 1);
+        }
+        assert!((1..=2).contains(&connections.open(&upstream)));
+
+        // a query timed out isn't sent again over a new connection
+        let silent = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let silent_upstream = Upstream::Plain(silent.local_addr()?);
+        tokio::spawn(async move {
+            let mut accepted = Vec::new();
+            while let Ok((stream, _)) = silent.accept().await {
+                accepted.push(stream);
+            }
+        });
+        let timeout = Duration::from_millis(300);
+        let config = ForwardConfigBuilder::default().timeout(timeout).build()?;
+        let slow = Connections::new(&config, false)?;
+        for _ in 0..2 {
+            let start = Instant::now();
+            let message = request(&query, false, None);
+            assert!(slow.exchange(&silent_upstream, message).await.is_err());
+            assert!(start.elapsed() < timeout * 3 / 2);
+        }
+        assert_eq!(slow.open(&silent_upstream), 1);
+
+        // the connections closed by the upstream are dropped
+        server.shutdown().await?;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(connections
+            .exchange(&upstream, request(&query, false, None))
+            .await
+            .is_err());
+        assert_eq!(connections.open(&upstream), 0);
+        Ok(())
+    }
+}
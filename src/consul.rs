@@ -0,0 +1,333 @@
+use crate::config::{self, ConsulConfig};
+use crate::discovery::{self, address_record};
+use crate::zones::ZoneSet;
+use anyhow::Result;
+use futures_util::future::try_join_all;
+use hickory_proto::rr;
+use hickory_proto::rr::RData;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+/// Longest wait of a blocking query, after which it is made again.
+const WAIT: &str = "5m";
+/// Changes whenever a service is registered or deregistered.
+const CATALOG_PATH: &str = "/v1/catalog/services";
+/// Changes whenever a health check changes.
+const HEALTH_PATH: &str = "/v1/health/state/any";
+
+/// Synthesizes the records of the services of a Consul catalog into the
+/// zone of its `domain`, refreshed whenever a blocking query on the catalog
+/// or the health checks returns a change.
+pub(crate) struct Consul {
+    origin: rr::Name,
+    ttl: u32,
+    datacenter: Option<String>,
+    only_passing: bool,
+    client: Client,
+}
+
+impl Consul {
+    pub(crate) fn new(config: &ConsulConfig, zones: &config::Zone) -> Result<Self> {
+        Ok(Self {
+            origin: discovery::origin("consul domain", config.domain(), zones)?,
+            ttl: config.ttl().as_secs() as u32,
+            datacenter: config.datacenter().map(str::to_string),
+            only_passing: config.only_passing(),
+            client: Client::new(config)?,
+        })
+    }
+
+    /// Serves the zone, empty until the services are listed, and keeps it in
+    /// sync with the catalog until `token` is cancelled.
+    pub(crate) async fn start(
+        self: Arc<Self>,
+        zones: Arc<ZoneSet>,
+        token: CancellationToken,
+    ) -> Result<()> {
+        zones.serve_records(&self.origin, Vec::new()).await?;
+        tokio::spawn(async move {
+            discovery::follow("watch the consul catalog", &token, || self.follow(&zones)).await;
+        });
+        Ok(())
+    }
+
+    /// Publishes the instances of every service, again whenever the catalog
+    /// or a health check changes.
+    async fn follow(&self, zones: &ZoneSet) -> Result<()> {
+        let (mut catalog, mut health) =
+            tokio::try_join!(self.index(CATALOG_PATH, 0), self.index(HEALTH_PATH, 0))?;
+        loop {
+            let instances = self.instances().await?;
+            debug!(
+                "listed {} consul service instances at index {}/{}",
+                instances.len(),
+                catalog,
+                health
+            );
+            let records = records(&instances, &self.origin, self.ttl);
+            if let Err(e) = zones.serve_records(&self.origin, records).await {
+                warn!("failed to serve consul zone {}: {:#}", self.origin, e);
+            }
+            tokio::select! {
+                index = self.index(CATALOG_PATH, catalog) => catalog = index?,
+                index = self.index(HEALTH_PATH, health) => health = index?,
+            }
+        }
+    }
+
+    /// Waits until the index of `path` moves past `index`, or the wait
+    /// expires, and returns the index it is at.
+    async fn index(&self, path: &str, index: u64) -> Result<u64> {
+        let path = self.with_datacenter(format!("{}?index={}&wait={}", path, index, WAIT));
+        let (current, _) = self.client.get(&path).await?;
+        // an index going backwards is reset, as Consul documents
+        Ok(if current < index { 0 } else { current })
+    }
+
+    /// The instances of every service in the catalog.
+    async fn instances(&self) -> Result<Vec<Instance>> {
+        let (_, body) = self
+            .client
+            .get(&self.with_datacenter(format!("{}?", CATALOG_PATH)))
+            .await?;
+        let services: BTreeMap<String, Vec<String>> = serde_json::from_slice(&body)?;
+        let instances = try_join_all(services.keys().map(|service| async move {
+            let mut path = format!("/v1/health/service/{}?", service);
+            if self.only_passing {
+                path.push_str("passing=1");
+            }
+            let (_, body) = self.client.get(&self.with_datacenter(path)).await?;
+            anyhow::Ok(serde_json::from_slice::<Vec<Instance>>(&body)?)
+        }))
+        .await?;
+        Ok(instances.into_iter().flatten().collect())
+    }
+
+    fn with_datacenter(&self, mut path: String) -> String {
+        if let Some(datacenter) = &self.datacenter {
+            if !path.ends_with('?') {
+                path.push('&');
+            }
+            path.push_str("dc=");
+            path.push_str(datacenter);
+        }
+        path
+    }
+}
+
+/// The records of `instances` in the zone `origin`, following the names of
+/// the Consul DNS interface. An SRV record targets the node of an instance,
+/// or `<hex address>.addr.<origin>` when the instance has an address of its
+/// own.
+fn records(instances: &[Instance], origin: &rr::Name, ttl: u32) -> Vec<rr::Record> {
+    let mut records = Vec::new();
+    let name = |labels: &[&str]| {
+        rr::Name::from_ascii(labels.join("."))
+            .and_then(|name| name.append_name(origin))
+            .ok()
+    };
+    for instance in instances {
+        let service = &instance.service;
+        let Ok(node_ip) = instance.node.address.parse::<IpAddr>() else {
+            debug!(
+                "skipped consul node {} without an address",
+                instance.node.node
+            );
+            continue;
+        };
+        let ip = if service.address.is_empty() {
+            node_ip
+        } else if let Ok(ip) = service.address.parse() {
+            ip
+        } else {
+            debug!("skipped consul service {} without an address", service.id);
+            continue;
+        };
+        let (Some(node_name), Some(addr_name)) = (
+            name(&[&instance.node.node, "node"]),
+            name(&[&address_label(ip), "addr"]),
+        ) else {
+            continue;
+        };
+        records.push(address_record(&node_name, node_ip, ttl));
+        let target = if ip == node_ip {
+            node_name
+        } else {
+            records.push(address_record(&addr_name, ip, ttl));
+            addr_name
+        };
+        let srv = RData::SRV(rr::rdata::SRV::new(1, 1, service.port, target));
+        // the RFC 2782 names have SRV records only
+        let srv_label = format!("_{}", service.service);
+        let mut owners = vec![
+            (name(&[&service.service, "service"]), true),
+            (name(&[&srv_label, "_tcp", "service"]), false),
+        ];
+        for tag in &service.tags {
+            owners.push((name(&[tag, &service.service, "service"]), true));
+            owners.push((name(&[&srv_label, &format!("_{}", tag), "service"]), false));
+        }
+        for (owner, with_address) in owners {
+            let Some(owner) = owner else {
+                continue;
+            };
+            if with_address {
+                records.push(address_record(&owner, ip, ttl));
+            }
+            records.push(rr::Record::from_rdata(owner, ttl, srv.clone()));
+        }
+    }
+    records
+}
+
+/// The label of `ip` in `<label>.addr.<domain>` names, its bytes in hex.
+fn address_label(ip: IpAddr) -> String {
+    let bytes = match ip {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// An entry of `/v1/health/service/<service>`.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct Instance {
+    node: Node,
+    service: AgentService,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct Node {
+    node: String,
+    #[serde(default)]
+    address: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct AgentService {
+    #[serde(rename = "ID", default)]
+    id: String,
+    service: String,
+    #[serde(default)]
+    tags: Vec<String>,
+    /// Address of the instance, that of its node when empty.
+    #[serde(default)]
+    address: String,
+    #[serde(default)]
+    port: u16,
+}
+
+/// Client of the HTTP API of a Consul agent.
+#[cfg(feature = "consul")]
+struct Client {
+    http: reqwest::Client,
+    address: String,
+    token: Option<String>,
+}
+
+#[cfg(feature = "consul")]
+impl Client {
+    fn new(config: &ConsulConfig) -> Result<Self> {
+        Ok(Self {
+            http: reqwest::Client::builder().build()?,
+            address: config.address().trim_end_matches('/').to_string(),
+            token: config.token().map(str::to_string),
+        })
+    }
+
+    /// Gets `path`, returns the `X-Consul-Index` of the response and its body.
+    async fn get(&self, path: &str) -> Result<(u64, Vec<u8>)> {
+        let mut request = self.http.get(format!("{}{}", self.address, path));
+        if let Some(token) = &self.token {
+            request = request.header("X-Consul-Token", token);
+        }
+        let response = request.send().await?.error_for_status()?;
+        let index = response
+            .headers()
+            .get("X-Consul-Index")
+            .and_then(|index| index.to_str().ok()?.parse().ok())
+            .unwrap_or(0);
+        Ok((index, response.bytes().await?.to_vec()))
+    }
+}
+
+#[cfg(not(feature = "consul"))]
+enum Client {}
+
+#[cfg(not(feature = "consul"))]
+impl Client {
+    fn new(_config: &ConsulConfig) -> Result<Self> {
+        anyhow::bail!("the Consul backend requires the `consul` feature")
+    }
+
+    async fn get(&self, _path: &str) -> Result<(u64, Vec<u8>)> {
+        match *self {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::str::FromStr;
+
+    #[test]
+    fn synthesizes_records() -> Result<()> {
+        let instances: Vec<Instance> = serde_json::from_value(json!([
+            {
+                "Node": { "Node": "node1", "Address": "10.0.0.1" },
+                "Service": { "ID": "web-1", "Service": "web", "Tags": ["v1"], "Address": "", "Port": 80 },
+                "Checks": [],
+            },
+            {
+                "Node": { "Node": "node2", "Address": "10.0.0.2" },
+                "Service": { "ID": "web-2", "Service": "web", "Tags": [], "Address": "10.1.0.2", "Port": 8080 },
+                "Checks": [],
+            },
+        ]))?;
+        let origin = rr::Name::from_str("consul.")?;
+        let records = records(&instances, &origin, 30);
+        let find = |name: &str| -> Vec<String> {
+            let name = rr::Name::from_str(name).unwrap();
+            records
+                .iter()
+                .filter(|r| r.name() == &name)
+                .map(|r| format!("{} {}", r.record_type(), r.data().unwrap()))
+                .collect()
+        };
+        assert_eq!(
+            find("web.service.consul."),
+            [
+                "A 10.0.0.1",
+                "SRV 1 1 80 node1.node.consul.",
+                "A 10.1.0.2",
+                "SRV 1 1 8080 0a010002.addr.consul."
+            ]
+        );
+        assert_eq!(
+            find("_web._tcp.service.consul."),
+            [
+                "SRV 1 1 80 node1.node.consul.",
+                "SRV 1 1 8080 0a010002.addr.consul."
+            ]
+        );
+        assert_eq!(
+            find("v1.web.service.consul."),
+            ["A 10.0.0.1", "SRV 1 1 80 node1.node.consul."]
+        );
+        assert_eq!(
+            find("_web._v1.service.consul."),
+            ["SRV 1 1 80 node1.node.consul."]
+        );
+        assert_eq!(find("node2.node.consul."), ["A 10.0.0.2"]);
+        assert_eq!(find("0a010002.addr.consul."), ["A 10.1.0.2"]);
+        Ok(())
+    }
+}
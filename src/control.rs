@@ -0,0 +1,260 @@
+//! Control socket: a Unix socket taking one command per connection, like
+//! `rndc`, for hosts where no admin port should be opened.
+//!
+//! A client writes a line such as `flush www.example.com` and reads the
+//! answer until the server closes the connection: `ok` and the output of the
+//! command, or `error: ` and the reason it failed.
+//!
+//! | command                      | effect                                    |
+//! |------------------------------|-------------------------------------------|
+//! | `reload`                     | reloads the config file, or the zones     |
+//! | `flush [name]`               | drops cached answers, of `name` and below |
+//! | `flushname <name> [type]`    | drops cached answers of `name` only       |
+//! | `status`                     | prints counters of the server             |
+//! | `addzone <zone> [json]`      | adds a zone, its config as JSON           |
+//! | `delzone <zone>`             | removes a zone                            |
+//! | `notify <zone>`              | sends a NOTIFY to the zone's secondaries  |
+
+use crate::cache::Flush;
+use crate::config;
+use crate::edit::{self, EditError};
+use crate::handler::CatalogRequestHandler;
+use anyhow::{anyhow, bail, Context, Result};
+use hickory_proto::rr::LowerName;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info};
+
+/// Longest command line accepted, enough for the config of a zone.
+const MAX_COMMAND_SIZE: u64 = 1 << 20;
+
+/// Sends `command` to the control socket at `socket`, returning the output
+/// of the command or failing with the error the server answered.
+pub async fn send(socket: &Path, command: &str) -> Result<String> {
+    let mut stream = UnixStream::connect(socket)
+        .await
+        .with_context(|| format!("failed to connect to {}", socket.display()))?;
+    stream
+        .write_all(format!("{}\n", command).as_bytes())
+        .await?;
+    stream.shutdown().await?;
+    let mut answer = String::new();
+    stream.read_to_string(&mut answer).await?;
+    match answer.split_once('\n') {
+        Some(("ok", output)) => Ok(output.to_string()),
+        _ => match answer.strip_prefix("error: ") {
+            Some(message) => bail!("{}", message.trim_end()),
+            None => bail!("unexpected answer: {}", answer.trim_end()),
+        },
+    }
+}
+
+/// Serves the commands sent to `listener` until `token` is cancelled, and
+/// removes the socket file then.
+pub(crate) async fn serve(
+    listener: UnixListener,
+    path: PathBuf,
+    handler: CatalogRequestHandler,
+    token: CancellationToken,
+) {
+    loop {
+        let (stream, _) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    debug!("error accepting control connection: {}", e);
+                    continue;
+                }
+            },
+            _ = token.cancelled() => break,
+        };
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            let (reader, mut writer) = stream.into_split();
+            let mut line = String::new();
+            let read = BufReader::new(reader.take(MAX_COMMAND_SIZE))
+                .read_line(&mut line)
+                .await;
+            let answer = match read {
+                Ok(_) => match execute(&handler, line.trim()).await {
+                    Ok(output) => {
+                        info!("control: {}", line.split_whitespace().next().unwrap_or(""));
+                        format!("ok\n{}", output)
+                    }
+                    Err(e) => format!("error: {:#}\n", e),
+                },
+                Err(e) => format!("error: {}\n", e),
+            };
+            if let Err(e) = writer.write_all(answer.as_bytes()).await {
+                debug!("failed to answer control command: {}", e);
+            }
+        });
+    }
+    let _ = std::fs::remove_file(path);
+}
+
+fn flush(handler: &CatalogRequestHandler, flush: &Flush) -> usize {
+    let forwarder = handler.forwarder.as_ref();
+    forwarder.map_or(0, |f| f.flush_cache(flush))
+}
+
+/// Runs the command `line`, returning its output.
+async fn execute(handler: &CatalogRequestHandler, line: &str) -> Result<String> {
+    let (command, args) = line.split_once(' ').unwrap_or((line, ""));
+    let args = args.trim();
+    let zones = handler.views.default_zones();
+    let mut output = String::new();
+    match (command, args) {
+        ("reload", "") => handler.views.reload_config().await?,
+        ("flush", name) => {
+            let flushed = flush(handler, &Flush::new(name, "", true)?);
+            writeln!(output, "flushed {} cached answers", flushed)?;
+        }
+        ("flushname", args) if !args.is_empty() => {
+            let (name, query_type) = args.split_once(' ').unwrap_or((args, ""));
+            let flushed = flush(handler, &Flush::new(name, query_type.trim(), false)?);
+            writeln!(output, "flushed {} cached answers", flushed)?;
+        }
+        ("status", "") => {
+            writeln!(output, "zones: {}", zones.zone_count())?;
+            writeln!(output, "queries in flight: {}", handler.drain.in_flight())?;
+            if let Some(stats) = handler.forwarder.as_ref().and_then(|f| f.cache_stats()) {
+                writeln!(
+                    output,
+                    "cache: {} entries, {} hits, {} misses, {} stale hits, {} prefetches",
+                    stats.entries, stats.hits, stats.misses, stats.stale_hits, stats.prefetches
+                )?;
+            }
+            let upstreams = handler.forwarder.as_ref().map(|f| f.upstream_stats());
+            for stats in upstreams.unwrap_or_default() {
+                write!(
+                    output,
+                    "upstream {}: {} queries, {} errors, {} servfails",
+                    stats.upstream, stats.queries, stats.errors, stats.servfails
+                )?;
+                if let Some(rtt) = stats.rtt {
+                    write!(output, ", rtt {}ms", rtt.as_millis())?;
+                }
+                if stats.quarantined {
+                    write!(output, ", quarantined")?;
+                }
+                writeln!(output)?;
+            }
+        }
+        ("addzone", args) if !args.is_empty() => {
+            let (zone, json) = args.split_once(' ').unwrap_or((args, ""));
+            let zone_config: config::ZoneConfig = match json.trim() {
+                "" => config::ZoneConfig::default(),
+                json => serde_json::from_str(json).context("invalid zone config")?,
+            };
+            edit::add_zone(zones, zone, zone_config)
+                .await
+                .map_err(edit_error)?;
+        }
+        ("delzone", zone) if !zone.is_empty() && !zone.contains(' ') => {
+            edit::remove_zone(zones, zone).await.map_err(edit_error)?;
+        }
+        ("notify", zone) if !zone.is_empty() && !zone.contains(' ') => {
+            let name =
+                LowerName::from_str(zone).map_err(|e| anyhow!("invalid zone {}: {}", zone, e))?;
+            let notified = zones
+                .notify(&name)
+                .await
+                .ok_or_else(|| anyhow!("no such zone: {}", zone))?;
+            writeln!(output, "notified {} secondaries of {}", notified, zone)?;
+        }
+        ("reload" | "status", _) => bail!("{} takes no arguments", command),
+        ("addzone" | "delzone" | "notify", _) => bail!("usage: {} <zone>", command),
+        ("flushname", _) => bail!("usage: flushname <name> [type]"),
+        _ => bail!("unknown command: {}", command),
+    }
+    Ok(output)
+}
+
+fn edit_error(e: EditError) -> anyhow::Error {
+    match e {
+        EditError::NotFound(message) => anyhow!(message),
+        EditError::BadRequest(e) => e,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{GeneralConfigBuilder, RecordBuilder, RecordData, RunConfigBuilder};
+    use crate::testing;
+    use crate::Server;
+    use hickory_proto::rr::RecordType;
+    use maplit::hashmap;
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn runs_control_commands() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let socket = dir.path().join("control.sock");
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .control_socket(socket.clone())
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.internal".to_string() => vec![
+                    RecordBuilder::default()
+                        .name("www.et.internal".to_string())
+                        .data(RecordData::A(Ipv4Addr::new(123, 123, 123, 123)))
+                        .ttl(Duration::from_secs(60))
+                        .build()?,
+                ].into(),
+            })
+            .build()?;
+        let mut server = Server::new(config);
+        server.run().await?;
+        let addr = server.udp_local_addr().unwrap();
+
+        let status = send(&socket, "status").await?;
+        assert!(status.contains("zones: 1\n"));
+        assert!(status.contains("queries in flight: 0\n"));
+
+        let zone = r#"[{"type":"A","name":"www.et.top","value":"10.0.0.3","ttl":"60s"}]"#;
+        send(&socket, &format!("addzone et.top {}", zone)).await?;
+        let response = testing::query(addr, "www.et.top", RecordType::A).await?;
+        assert_eq!(response.answers().len(), 1);
+        let e = send(&socket, "addzone et.top").await.unwrap_err();
+        assert_eq!(e.to_string(), "zone et.top already exists");
+
+        assert_eq!(
+            send(&socket, "notify et.top").await?,
+            "notified 0 secondaries of et.top\n"
+        );
+        assert_eq!(send(&socket, "flush").await?, "flushed 0 cached answers\n");
+        assert_eq!(
+            send(&socket, "flushname www.et.top aaaa").await?,
+            "flushed 0 cached answers\n"
+        );
+        let e = send(&socket, "flushname").await.unwrap_err();
+        assert_eq!(e.to_string(), "usage: flushname <name> [type]");
+        assert!(send(&socket, "flushname www.et.top NOPE").await.is_err());
+
+        send(&socket, "delzone et.top").await?;
+        let response = testing::query(addr, "www.et.top", RecordType::A).await?;
+        assert!(response.answers().is_empty());
+        send(&socket, "addzone et.top").await?;
+        send(&socket, "reload").await?;
+        assert!(send(&socket, "delzone et.top").await.is_err());
+
+        let e = send(&socket, "restart").await.unwrap_err();
+        assert_eq!(e.to_string(), "unknown command: restart");
+        assert!(send(&socket, "notify").await.is_err());
+
+        server.shutdown().await?;
+        assert!(!socket.exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,127 @@
+use hickory_proto::rr::{LowerName, Name, Record, RecordSet, RecordType, RrKey};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+type Records = BTreeMap<RrKey, Arc<RecordSet>>;
+
+/// The answer of a parent zone for a name of a child zone it delegates.
+pub(crate) struct Referral {
+    /// NS records of the child zone.
+    pub(crate) name_servers: Vec<Record>,
+    /// Addresses of the name servers within the parent zone.
+    pub(crate) glue: Vec<Record>,
+}
+
+/// Whether `records` hold NS records below the apex `origin`, delegating
+/// child zones.
+pub(crate) fn has_delegations(records: &Records, origin: &LowerName) -> bool {
+    records
+        .keys()
+        .any(|key| key.record_type == RecordType::NS && key.name != *origin)
+}
+
+/// The referral to the child zone of the zone `origin` holding `name`, at
+/// the delegation closest to the apex, since the records below it are only
+/// glue. `None` when `name` isn't delegated, or for the DS records of the
+/// delegation itself, which the parent zone answers.
+pub(crate) fn referral(
+    records: &Records,
+    origin: &LowerName,
+    name: &LowerName,
+    query_type: RecordType,
+) -> Option<Referral> {
+    if !origin.zone_of(name) {
+        return None;
+    }
+    let name = Name::from(name);
+    let cut = (origin.num_labels() + 1..=name.num_labels())
+        .map(|labels| LowerName::from(name.trim_to(usize::from(labels))))
+        .find(|cut| records.contains_key(&RrKey::new(cut.clone(), RecordType::NS)))?;
+    if cut == LowerName::from(&name) && query_type == RecordType::DS {
+        return None;
+    }
+
+    let name_servers: Vec<Record> = records[&RrKey::new(cut, RecordType::NS)]
+        .records_without_rrsigs()
+        .cloned()
+        .collect();
+    let glue = name_servers
+        .iter()
+        .filter_map(|ns| ns.data()?.as_ns())
+        .map(|ns| LowerName::from(&ns.0))
+        .filter(|target| origin.zone_of(target))
+        .flat_map(|target| {
+            [RecordType::A, RecordType::AAAA]
+                .into_iter()
+                .filter_map(move |rr_type| records.get(&RrKey::new(target.clone(), rr_type)))
+        })
+        .flat_map(|rrset| rrset.records_without_rrsigs().cloned())
+        .collect();
+    Some(Referral { name_servers, glue })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::rr::rdata::NS;
+    use hickory_proto::rr::RData;
+    use std::str::FromStr;
+
+    fn records(entries: &[(&str, RData)]) -> Records {
+        let mut records = Records::new();
+        for (name, rdata) in entries {
+            let name = Name::from_str(name).unwrap();
+            let record = Record::from_rdata(name.clone(), 60, rdata.clone());
+            let rrset = records
+                .entry(RrKey::new(name.clone().into(), record.record_type()))
+                .or_insert_with(|| Arc::new(RecordSet::new(&name, record.record_type(), 0)));
+            Arc::make_mut(rrset).insert(record, 0);
+        }
+        records
+    }
+
+    fn name(name: &str) -> LowerName {
+        LowerName::from(Name::from_str(name).unwrap())
+    }
+
+    fn ns(name: &str) -> RData {
+        RData::NS(NS(Name::from_str(name).unwrap()))
+    }
+
+    #[test]
+    fn refers_to_child_zones() {
+        let zone = records(&[
+            ("et.internal.", ns("ns1.et.internal.")),
+            ("ns1.et.internal.", RData::A("10.0.0.53".parse().unwrap())),
+            ("lab.et.internal.", ns("ns1.lab.et.internal.")),
+            ("lab.et.internal.", ns("ns.et.top.")),
+            (
+                "ns1.lab.et.internal.",
+                RData::A("10.1.0.53".parse().unwrap()),
+            ),
+            (
+                "ns1.lab.et.internal.",
+                RData::AAAA("fd00::53".parse().unwrap()),
+            ),
+            ("deep.x.lab.et.internal.", ns("ns1.lab.et.internal.")),
+        ]);
+        let origin = name("et.internal.");
+        assert!(has_delegations(&zone, &origin));
+        let referral = |qname: &str, query_type| referral(&zone, &origin, &name(qname), query_type);
+
+        let lab = referral("www.deep.x.lab.et.internal.", RecordType::A).unwrap();
+        assert_eq!(lab.name_servers.len(), 2);
+        assert!(lab
+            .name_servers
+            .iter()
+            .all(|ns| ns.name() == &Name::from_str("lab.et.internal.").unwrap()));
+        assert_eq!(lab.glue.len(), 2);
+        assert!(referral("lab.et.internal.", RecordType::A).is_some());
+        assert!(referral("lab.et.internal.", RecordType::DS).is_none());
+        assert!(referral("www.et.internal.", RecordType::A).is_none());
+        assert!(referral("et.internal.", RecordType::NS).is_none());
+
+        let apex_only = records(&[("et.internal.", ns("ns1.et.internal."))]);
+        assert!(!has_delegations(&apex_only, &origin));
+    }
+}
@@ -0,0 +1,190 @@
+use crate::config;
+use anyhow::{bail, Context, Result};
+use hickory_proto::rr;
+use hickory_proto::rr::RData;
+use std::future::Future;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+/// Delay before a service discovery backend that failed follows its source
+/// again.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The zone `name` of a service discovery backend, which can't also be one
+/// of the configured `zones`. `what` names it in errors, as `docker domain`.
+pub(crate) fn origin(what: &str, name: &str, zones: &config::Zone) -> Result<rr::Name> {
+    let origin = rr::Name::from_str(name).with_context(|| format!("invalid {} {}", what, name))?;
+    if zones
+        .keys()
+        .any(|zone| rr::Name::from_str(zone).is_ok_and(|zone| zone == origin))
+    {
+        bail!("{} {} is also a configured zone", what, origin);
+    }
+    Ok(origin)
+}
+
+/// Runs `follow` until `token` is cancelled, again a while after it fails.
+/// `what` describes it in the warning of a failure, as `watch etcd`.
+pub(crate) async fn follow<F, Fut>(what: &str, token: &CancellationToken, mut follow: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    loop {
+        tokio::select! {
+            result = follow() => {
+                if let Err(e) = result {
+                    warn!("failed to {}: {:#}", what, e);
+                }
+            }
+            _ = token.cancelled() => break,
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(RETRY_INTERVAL) => {}
+            _ = token.cancelled() => break,
+        }
+    }
+}
+
+/// The A or AAAA record of `name` at `ip`.
+pub(crate) fn address_record(name: &rr::Name, ip: IpAddr, ttl: u32) -> rr::Record {
+    let data = match ip {
+        IpAddr::V4(ip) => RData::A(ip.into()),
+        IpAddr::V6(ip) => RData::AAAA(ip.into()),
+    };
+    rr::Record::from_rdata(name.clone(), ttl, data)
+}
+
+/// A response body read as it arrives.
+#[async_trait::async_trait]
+pub(crate) trait Body: Send {
+    /// Appends the next chunk of the body to `buffer`, `false` at its end.
+    async fn read(&mut self, buffer: &mut Vec<u8>) -> Result<bool>;
+}
+
+#[cfg(any(feature = "kubernetes", feature = "etcd"))]
+#[async_trait::async_trait]
+impl Body for reqwest::Response {
+    async fn read(&mut self, buffer: &mut Vec<u8>) -> Result<bool> {
+        match self.chunk().await? {
+            Some(chunk) => {
+                buffer.extend_from_slice(&chunk);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[cfg(feature = "docker")]
+#[async_trait::async_trait]
+impl Body for hyper::body::Incoming {
+    async fn read(&mut self, buffer: &mut Vec<u8>) -> Result<bool> {
+        use http_body_util::BodyExt;
+
+        match self.frame().await.transpose()? {
+            Some(frame) => {
+                if let Ok(data) = frame.into_data() {
+                    buffer.extend_from_slice(&data);
+                }
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+/// The body of a backend built without its feature, which can't be read.
+#[async_trait::async_trait]
+impl Body for std::convert::Infallible {
+    async fn read(&mut self, _buffer: &mut Vec<u8>) -> Result<bool> {
+        match *self {}
+    }
+}
+
+/// The lines of a streamed body, such as the events of a watch with one
+/// JSON object per line. Blank lines are skipped.
+pub(crate) struct Lines<B> {
+    body: B,
+    buffer: Vec<u8>,
+}
+
+impl<B: Body> Lines<B> {
+    #[cfg_attr(
+        not(any(test, feature = "docker", feature = "kubernetes", feature = "etcd")),
+        allow(dead_code)
+    )]
+    pub(crate) fn new(body: B) -> Self {
+        Self {
+            body,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub(crate) async fn next(&mut self) -> Result<Option<Vec<u8>>> {
+        loop {
+            if let Some(end) = self.buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = self.buffer.drain(..=end).collect();
+                if line.iter().all(u8::is_ascii_whitespace) {
+                    continue;
+                }
+                return Ok(Some(line));
+            }
+            if !self.body.read(&mut self.buffer).await? {
+                return Ok(None);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use maplit::hashmap;
+    use std::collections::VecDeque;
+
+    #[async_trait::async_trait]
+    impl Body for VecDeque<&'static str> {
+        async fn read(&mut self, buffer: &mut Vec<u8>) -> Result<bool> {
+            match self.pop_front() {
+                Some(chunk) => {
+                    buffer.extend_from_slice(chunk.as_bytes());
+                    Ok(true)
+                }
+                None => Ok(false),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn splits_lines_across_chunks() -> Result<()> {
+        let chunks = VecDeque::from(["{\"a\"", ":1}\n\n  \n{\"b\":2}\n{\"c\"", ":3}\n"]);
+        let mut lines = Lines::new(chunks);
+        let mut read = Vec::new();
+        while let Some(line) = lines.next().await? {
+            read.push(String::from_utf8(line)?);
+        }
+        assert_eq!(read, ["{\"a\":1}\n", "{\"b\":2}\n", "{\"c\":3}\n"]);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_configured_zones() -> Result<()> {
+        let zones = hashmap! {
+            "example.com".to_string() => config::ZoneConfig::default(),
+        };
+        assert_eq!(
+            origin("docker domain", "docker.internal", &zones)?,
+            rr::Name::from_str("docker.internal")?
+        );
+        let err = origin("docker domain", "example.com.", &zones).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "docker domain example.com. is also a configured zone"
+        );
+        Ok(())
+    }
+}
@@ -1,121 +1,942 @@
+use crate::acl::ClientAcl;
+use crate::acme;
+use crate::acme_certificate::AcmeCertificate;
+use crate::blocklist::Blocklist;
+use crate::cache::{CacheStats, Flush};
+use crate::chaos::Chaos;
 use crate::config;
-use crate::config::GeneralConfig;
-use anyhow::Result;
-use hickory_proto::op::Edns;
+use crate::config::{
+    AdminListenConfig, GeneralConfig, HttpsListenConfig, Service, TlsListenConfig,
+};
+use crate::consul::Consul;
+use crate::docker::Docker;
+use crate::drain::Drain;
+use crate::error::Error;
+use crate::etcd::Etcd;
+use crate::forward::Forwarder;
+use crate::geoip::GeoIp;
+use crate::handler::CatalogRequestHandler;
+use crate::hosts::Hosts;
+use crate::kubernetes::Kubernetes;
+use crate::mdns;
+use crate::mdns::Responder;
+use crate::middleware::{Fallback, Middleware};
+use crate::nsid;
+use crate::postgres::Postgres;
+use crate::ratelimit::{RateLimiter, ResponseRateLimiter};
+use crate::redirect::Redirect;
+use crate::redis::Redis;
+use crate::rewrite::Rewriter;
+use crate::tls::ReloadingCertResolver;
+use crate::truncation::UdpSizes;
+use crate::ttl::TtlLimits;
+use crate::upstream::UpstreamStats;
+use crate::views::{CatalogSnapshot, Views};
+use crate::zones::{RecordChange, ZoneSet};
+use anyhow::{bail, Context, Result};
+use hickory_proto::op::{Edns, Header};
 use hickory_proto::rr;
-use hickory_proto::rr::LowerName;
-use hickory_server::authority::{AuthorityObject, Catalog, ZoneType};
-use hickory_server::server::{Request, RequestHandler, ResponseHandler, ResponseInfo};
+use hickory_proto::rr::{LowerName, RecordSet, RrKey};
+use hickory_server::authority::{AuthorityObject, Catalog, MessageResponseBuilder, ZoneType};
+use hickory_server::server::{Request, ResponseHandler, ResponseInfo};
 use hickory_server::store::in_memory::InMemoryAuthority;
 use hickory_server::ServerFuture;
+use std::collections::BTreeMap;
+use std::fmt;
 use std::io;
-use std::net::SocketAddr;
-use std::str::FromStr;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::net::UdpSocket;
-use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::sync::{broadcast, Mutex};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
 
-pub struct Server {
-    server: ServerFuture<CatalogRequestHandler>,
-    catalog: Arc<RwLock<Catalog>>,
-    general_config: GeneralConfig,
-    udp_local_addr: Option<SocketAddr>,
-}
+/// Runtime record changes buffered for each mDNS responder.
+const MDNS_CHANGES: usize = 16;
 
-struct CatalogRequestHandler {
-    catalog: Arc<RwLock<Catalog>>,
+/// Error of the record-level mutation methods of [`Server`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordError {
+    /// No in-memory zone of the server encloses the record name.
+    ZoneNotFound(rr::Name),
+    /// The enclosing zone is a secondary zone, its records are transferred
+    /// from the primaries.
+    ReadOnly(rr::Name),
+    /// A record removed by a transaction doesn't exist.
+    RecordNotFound(rr::Name),
 }
 
-impl CatalogRequestHandler {
-    fn new(catalog: Arc<RwLock<Catalog>>) -> CatalogRequestHandler {
-        Self { catalog }
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordError::ZoneNotFound(name) => write!(f, "no zone found for {}", name),
+            RecordError::ReadOnly(zone) => write!(f, "secondary zone {} is read-only", zone),
+            RecordError::RecordNotFound(name) => write!(f, "no such record: {}", name),
+        }
     }
 }
 
-#[async_trait::async_trait]
-impl RequestHandler for CatalogRequestHandler {
-    async fn handle_request<R: ResponseHandler>(
-        &self,
-        request: &Request,
-        response_handle: R,
-    ) -> ResponseInfo {
-        self.catalog
-            .read()
-            .await
-            .handle_request(request, response_handle)
-            .await
-    }
+impl std::error::Error for RecordError {}
+
+/// Listeners of a server, shared with its [`ServerHandle`]s.
+type Listeners = Arc<Mutex<ServerFuture<CatalogRequestHandler>>>;
+
+pub struct Server {
+    server: Listeners,
+    handler: CatalogRequestHandler,
+    zones: Arc<ZoneSet>,
+    general_config: GeneralConfig,
+    udp_local_addrs: Vec<SocketAddr>,
+    tcp_local_addrs: Vec<SocketAddr>,
+    tls_local_addr: Option<SocketAddr>,
+    https_local_addr: Option<SocketAddr>,
+    quic_local_addr: Option<SocketAddr>,
+    admin_local_addr: Option<SocketAddr>,
+    grpc_local_addr: Option<SocketAddr>,
+    mdns_local_addrs: Vec<SocketAddr>,
+    /// Records added or withdrawn at runtime, announced by the mDNS responders.
+    mdns_changes: broadcast::Sender<Vec<rr::Record>>,
+    kubernetes: Option<Arc<Kubernetes>>,
+    docker: Option<Arc<Docker>>,
+    etcd: Option<Arc<Etcd>>,
+    consul: Option<Arc<Consul>>,
+    redis: Option<Arc<Redis>>,
+    postgres: Option<Arc<Postgres>>,
+    acme: Arc<acme::Challenges>,
+    acme_certificate: Option<Arc<AcmeCertificate>>,
+    shutdown_token: CancellationToken,
+    /// Cancelled once the shutdown stopped the listeners.
+    stopped: CancellationToken,
 }
 
 impl Server {
+    /// Builds a server for `config`.
+    ///
+    /// # Panics
+    ///
+    /// Panics when the config is invalid, see [`Server::try_new`].
     pub fn new(config: config::RunConfig) -> Self {
         Self::try_new(config).unwrap()
     }
 
-    fn try_new(config: config::RunConfig) -> Result<Self> {
-        let mut catalog = Catalog::new();
-        for (domain, records) in config.zones().iter() {
-            let zone = rr::Name::from_str(domain.as_str())?;
-            let mut authorities = InMemoryAuthority::empty(zone.clone(), ZoneType::Primary, false);
-            for record in records.iter() {
-                let r = record.try_into()?;
-                authorities.upsert_mut(r, 0);
-            }
-            catalog.upsert(zone.clone().into(), Box::new(Arc::new(authorities)));
-        }
-
-        let catalog = Arc::new(RwLock::new(catalog));
-        let handler = CatalogRequestHandler::new(catalog.clone());
-        let server = ServerFuture::new(handler);
+    /// Builds a server for `config`, failing with a [`Error::Record`] or
+    /// [`Error::Zone`] when its zones can't be built and a [`Error::Config`]
+    /// on other invalid settings.
+    pub fn try_new(config: config::RunConfig) -> Result<Self, Error> {
+        let views = Arc::new(Views::new(&config).map_err(|e| Error::classify(e, Error::Zone))?);
+        let zones = views.default_zones().clone();
+        let handler =
+            Self::handler(&config, views).map_err(|e| Error::classify(e, Error::Config))?;
+        let kubernetes = match config.kubernetes() {
+            Some(kubernetes) => Some(Arc::new(
+                Kubernetes::new(kubernetes, config.zones()).map_err(|e| Error::Config(e.into()))?,
+            )),
+            None => None,
+        };
+        let docker = match config.docker() {
+            Some(docker) => Some(Arc::new(
+                Docker::new(docker, config.zones()).map_err(|e| Error::Config(e.into()))?,
+            )),
+            None => None,
+        };
+        let etcd = match config.etcd() {
+            Some(etcd) => Some(Arc::new(
+                Etcd::new(etcd, config.zones()).map_err(|e| Error::Config(e.into()))?,
+            )),
+            None => None,
+        };
+        let consul = match config.consul() {
+            Some(consul) => Some(Arc::new(
+                Consul::new(consul, config.zones()).map_err(|e| Error::Config(e.into()))?,
+            )),
+            None => None,
+        };
+        let redis = match config.redis() {
+            Some(redis) => Some(Arc::new(
+                Redis::new(redis).map_err(|e| Error::Config(e.into()))?,
+            )),
+            None => None,
+        };
+        let postgres = match config.postgres() {
+            Some(postgres) => Some(Arc::new(
+                Postgres::new(postgres).map_err(|e| Error::Config(e.into()))?,
+            )),
+            None => None,
+        };
+        let acme = Arc::new(acme::Challenges::new(
+            zones.clone(),
+            config.general().acme(),
+        ));
+        let acme_certificate = match config.general().acme().certificate() {
+            Some(certificate) => Some(Arc::new(
+                AcmeCertificate::new(certificate, acme.clone())
+                    .map_err(|e| Error::Config(e.into()))?,
+            )),
+            None => None,
+        };
+        let server = Arc::new(Mutex::new(ServerFuture::new(handler.clone())));
         Ok(Self {
             server,
-            catalog,
+            handler,
+            zones,
             general_config: config.general().clone(),
-            udp_local_addr: None,
+            udp_local_addrs: Vec::new(),
+            tcp_local_addrs: Vec::new(),
+            tls_local_addr: None,
+            https_local_addr: None,
+            quic_local_addr: None,
+            admin_local_addr: None,
+            grpc_local_addr: None,
+            mdns_local_addrs: Vec::new(),
+            mdns_changes: broadcast::channel(MDNS_CHANGES).0,
+            kubernetes,
+            docker,
+            etcd,
+            consul,
+            redis,
+            postgres,
+            acme,
+            acme_certificate,
+            shutdown_token: CancellationToken::new(),
+            stopped: CancellationToken::new(),
         })
     }
 
+    fn handler(config: &config::RunConfig, views: Arc<Views>) -> Result<CatalogRequestHandler> {
+        let zones = views.default_zones();
+        let forwards_zones = config.zones().values().any(|zone_config| {
+            matches!(
+                zone_config.zone_type(),
+                config::ZoneType::Forward | config::ZoneType::Stub
+            )
+        });
+        let forwarder = match config.forward() {
+            Some(forward) => Some(Forwarder::new(forward, config.zones())?),
+            None if forwards_zones => Some(Forwarder::new(
+                &config::ForwardConfig::default(),
+                config.zones(),
+            )?),
+            None => None,
+        };
+        let rate_limiter = match config.general().rate_limit() {
+            Some(rate_limit) => Some(RateLimiter::new(rate_limit)?),
+            None => None,
+        };
+        let response_rate_limiter = match config.general().response_rate_limit() {
+            Some(rrl) => Some(ResponseRateLimiter::new(rrl)?),
+            None => None,
+        };
+        let clients = ClientAcl::parse(
+            config.general().allow_query(),
+            config.general().deny_query(),
+        )
+        .context("invalid allow_query or deny_query")?;
+        if let Some(key) = clients
+            .keys()
+            .find(|key| zones.keyring().get(key).is_none())
+        {
+            bail!("client acl refers to unknown TSIG key {}", key);
+        }
+        let blocklist = match config.blocklist() {
+            Some(blocklist) => Some(Blocklist::new(blocklist)?),
+            None => None,
+        };
+        let hosts = match config.hosts() {
+            Some(hosts) => Some(Hosts::new(hosts)?),
+            None => None,
+        };
+        let redirect = match config.nxdomain_redirect() {
+            Some(redirect) => Some(Redirect::new(redirect).context("invalid nxdomain_redirect")?),
+            None => None,
+        };
+        let geoip = match config.geoip() {
+            Some(geoip) => Some(GeoIp::open(geoip)?),
+            None => None,
+        };
+        Ok(CatalogRequestHandler::new(
+            views,
+            config.general().max_cname_depth(),
+            config.general().any_queries(),
+            clients,
+            forwarder,
+            rate_limiter,
+            response_rate_limiter,
+            blocklist,
+            hosts,
+            redirect,
+            geoip,
+            Chaos::new(config.general().chaos().as_ref()),
+            config.general().nsid().map(nsid::option),
+            TtlLimits::new(config.general().min_ttl(), config.general().max_ttl())?,
+            UdpSizes::new(config.general())?,
+            Rewriter::new(config.rewrites())?,
+        ))
+    }
+
+    /// The address bound for the first of `listen_udp`.
     pub fn udp_local_addr(&mut self) -> Option<SocketAddr> {
-        self.udp_local_addr
+        self.udp_local_addrs.first().copied()
+    }
+
+    /// The addresses bound for `listen_udp`, in order.
+    pub fn udp_local_addrs(&self) -> &[SocketAddr] {
+        &self.udp_local_addrs
+    }
+
+    /// The address bound for the first of `listen_tcp`.
+    pub fn tcp_local_addr(&mut self) -> Option<SocketAddr> {
+        self.tcp_local_addrs.first().copied()
+    }
+
+    /// The addresses bound for `listen_tcp`, in order.
+    pub fn tcp_local_addrs(&self) -> &[SocketAddr] {
+        &self.tcp_local_addrs
+    }
+
+    pub fn tls_local_addr(&mut self) -> Option<SocketAddr> {
+        self.tls_local_addr
+    }
+
+    pub fn https_local_addr(&mut self) -> Option<SocketAddr> {
+        self.https_local_addr
+    }
+
+    pub fn quic_local_addr(&mut self) -> Option<SocketAddr> {
+        self.quic_local_addr
+    }
+
+    pub fn admin_local_addr(&mut self) -> Option<SocketAddr> {
+        self.admin_local_addr
+    }
+
+    pub fn grpc_local_addr(&mut self) -> Option<SocketAddr> {
+        self.grpc_local_addr
+    }
+
+    /// The addresses bound by the mDNS responder, IPv4 first.
+    pub fn mdns_local_addrs(&self) -> &[SocketAddr] {
+        &self.mdns_local_addrs
+    }
+
+    /// Loads the listener certificate and keeps it fresh until shutdown.
+    fn cert_resolver(&self, tls: &TlsListenConfig) -> Result<Arc<ReloadingCertResolver>> {
+        let (cert, key) = self.cert_files(tls)?;
+        let resolver = Arc::new(ReloadingCertResolver::new(&cert, &key)?);
+        tokio::spawn(
+            resolver
+                .clone()
+                .watch(tls.reload_interval(), self.shutdown_token.clone()),
+        );
+        Ok(resolver)
+    }
+
+    /// The certificate files of a listener, those of the ACME certificate
+    /// unless it has its own.
+    fn cert_files(&self, tls: &TlsListenConfig) -> Result<(PathBuf, PathBuf)> {
+        match (
+            tls.cert(),
+            tls.key(),
+            self.general_config.acme().certificate(),
+        ) {
+            (Some(cert), Some(key), _) => Ok((cert.to_path_buf(), key.to_path_buf())),
+            (None, None, Some(acme)) => Ok((acme.cert_path(), acme.key_path())),
+            _ => bail!("TLS listeners need a cert and a key, or general.acme.certificate"),
+        }
+    }
+
+    /// Binds the configured listeners and starts the background tasks,
+    /// failing with a [`Error::Bind`] when a listener can't be bound and a
+    /// [`Error::Config`] on invalid listener settings.
+    pub async fn run(&mut self) -> Result<(), Error> {
+        self.listen()
+            .await
+            .map_err(|e| Error::classify(e, Error::Config))
+    }
+
+    async fn listen(&mut self) -> Result<()> {
+        let workers = self.general_config.udp_workers();
+        if workers == 0 {
+            bail!("udp_workers must be at least 1");
+        }
+        for address in self.general_config.listen_udp() {
+            let sockets = match workers {
+                1 => vec![UdpSocket::bind(address)
+                    .await
+                    .map_err(Error::bind(address))?],
+                _ => {
+                    let address = tokio::net::lookup_host(address)
+                        .await?
+                        .next()
+                        .with_context(|| format!("{} resolves to no address", address))?;
+                    bind_reuse_port(address, workers)?
+                }
+            };
+            self.udp_local_addrs.push(sockets[0].local_addr()?);
+            for socket in sockets {
+                self.server.lock().await.register_socket(socket);
+            }
+        }
+        for address in self.general_config.listen_tcp() {
+            let listener = TcpListener::bind(address)
+                .await
+                .map_err(Error::bind(address))?;
+            self.tcp_local_addrs.push(listener.local_addr()?);
+            self.server
+                .lock()
+                .await
+                .register_listener(listener, self.general_config.tcp_timeout());
+        }
+        if let Some(certificate) = &self.acme_certificate {
+            // the challenges are answered by the listeners bound above
+            certificate.prepare()?;
+            tokio::spawn(certificate.clone().renew(self.shutdown_token.clone()));
+        }
+        if let Some(tls) = self.general_config.listen_tls() {
+            let resolver = self.cert_resolver(tls)?;
+            let listener = TcpListener::bind(tls.address())
+                .await
+                .map_err(Error::bind(tls.address()))?;
+            self.tls_local_addr = Some(listener.local_addr()?);
+            self.server
+                .lock()
+                .await
+                .register_tls_listener_with_tls_config(
+                    listener,
+                    self.general_config.tcp_timeout(),
+                    Arc::new(resolver.server_config(&[])),
+                )?;
+        }
+        if let Some(address) = self.general_config.listen_quic() {
+            let Some(tls) = self.general_config.listen_tls() else {
+                anyhow::bail!("listen_quic requires the certificate of listen_tls");
+            };
+            self.run_quic(address.clone(), tls.clone()).await?;
+        }
+        if let Some(https) = self.general_config.listen_https() {
+            self.run_https(https.clone()).await?;
+        }
+        if let Some(admin) = self.general_config.listen_admin() {
+            self.run_admin(admin.clone()).await?;
+        }
+        if let Some(grpc) = self.general_config.listen_grpc() {
+            self.run_grpc(grpc.clone()).await?;
+        }
+        if let Some(path) = self.general_config.control_socket() {
+            self.run_control(path.to_path_buf())?;
+        }
+        if let Some(mdns) = self.general_config.mdns() {
+            let mut groups = vec![IpAddr::from(mdns::GROUP_V4)];
+            if !mdns.ipv4_only() {
+                groups.push(mdns::GROUP_V6.into());
+            }
+            for group in groups {
+                let responder = Responder::bind(group, mdns.port(), self.zones.clone())
+                    .map_err(Error::bind(SocketAddr::new(group, mdns.port())))?;
+                self.mdns_local_addrs.push(responder.local_addr()?);
+                tokio::spawn(
+                    Arc::new(responder)
+                        .serve(self.mdns_changes.subscribe(), self.shutdown_token.clone()),
+                );
+            }
+        }
+        for zones in self.handler.views.zone_sets() {
+            tokio::spawn(
+                zones
+                    .clone()
+                    .refresh_secondaries(self.shutdown_token.clone()),
+            );
+            tokio::spawn(zones.clone().resign(self.shutdown_token.clone()));
+            tokio::spawn(zones.clone().check_health(self.shutdown_token.clone()));
+        }
+        if let Some(kubernetes) = &self.kubernetes {
+            kubernetes
+                .clone()
+                .start(self.zones.clone(), self.shutdown_token.clone())
+                .await?;
+        }
+        if let Some(docker) = &self.docker {
+            docker
+                .clone()
+                .start(self.zones.clone(), self.shutdown_token.clone())
+                .await?;
+        }
+        if let Some(etcd) = &self.etcd {
+            etcd.clone()
+                .start(self.zones.clone(), self.shutdown_token.clone())
+                .await?;
+        }
+        if let Some(consul) = &self.consul {
+            consul
+                .clone()
+                .start(self.zones.clone(), self.shutdown_token.clone())
+                .await?;
+        }
+        if let Some(redis) = &self.redis {
+            redis
+                .clone()
+                .start(self.zones.clone(), self.shutdown_token.clone())
+                .await?;
+        }
+        if let Some(postgres) = &self.postgres {
+            postgres
+                .clone()
+                .start(self.zones.clone(), self.shutdown_token.clone())
+                .await?;
+        }
+        if let Some(blocklist) = &self.handler.blocklist {
+            blocklist.load_files().await;
+            tokio::spawn(blocklist.clone().watch(self.shutdown_token.clone()));
+        }
+        if let Some(hosts) = &self.handler.hosts {
+            hosts.load().await;
+            tokio::spawn(hosts.clone().watch(self.shutdown_token.clone()));
+        }
+        if let Some(forwarder) = &self.handler.forwarder {
+            tokio::spawn(forwarder.clone().prefetch(self.shutdown_token.clone()));
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "doq")]
+    async fn run_quic(&mut self, address: String, tls: TlsListenConfig) -> Result<()> {
+        let (cert, key) = self.cert_files(&tls)?;
+        let socket = UdpSocket::bind(&address)
+            .await
+            .map_err(Error::bind(&address))?;
+        self.quic_local_addr = Some(socket.local_addr()?);
+        self.server.lock().await.register_quic_listener(
+            socket,
+            self.general_config.tcp_timeout(),
+            crate::tls::load_cert_and_key(&cert, &key)?,
+            None,
+        )?;
+        Ok(())
+    }
+
+    #[cfg(not(feature = "doq"))]
+    async fn run_quic(&mut self, _address: String, _tls: TlsListenConfig) -> Result<()> {
+        anyhow::bail!("DNS-over-QUIC requires the `doq` feature")
+    }
+
+    #[cfg(feature = "doh")]
+    async fn run_https(&mut self, https: HttpsListenConfig) -> Result<()> {
+        let resolver = self.cert_resolver(https.tls())?;
+        let config = resolver.server_config(&[b"h2", b"http/1.1"]);
+        let listener = TcpListener::bind(https.tls().address())
+            .await
+            .map_err(Error::bind(https.tls().address()))?;
+        self.https_local_addr = Some(listener.local_addr()?);
+        tokio::spawn(crate::doh::serve(
+            listener,
+            tokio_rustls::TlsAcceptor::from(Arc::new(config)),
+            self.handler.clone(),
+            https.path().to_string(),
+            self.shutdown_token.clone(),
+        ));
+        Ok(())
+    }
+
+    #[cfg(not(feature = "doh"))]
+    async fn run_https(&mut self, _https: HttpsListenConfig) -> Result<()> {
+        anyhow::bail!("DNS-over-HTTPS requires the `doh` feature")
+    }
+
+    #[cfg(feature = "admin")]
+    async fn run_admin(&mut self, admin: AdminListenConfig) -> Result<()> {
+        let listener = TcpListener::bind(admin.address())
+            .await
+            .map_err(Error::bind(admin.address()))?;
+        self.admin_local_addr = Some(listener.local_addr()?);
+        tokio::spawn(crate::admin::serve(
+            listener,
+            self.zones.clone(),
+            self.handler.forwarder.clone(),
+            admin.token().map(str::to_string),
+            self.acme.clone(),
+            self.general_config.acme().acme_dns().clone(),
+            self.shutdown_token.clone(),
+        ));
+        Ok(())
+    }
+
+    #[cfg(not(feature = "admin"))]
+    async fn run_admin(&mut self, _admin: AdminListenConfig) -> Result<()> {
+        anyhow::bail!("the admin API requires the `admin` feature")
+    }
+
+    #[cfg(feature = "grpc")]
+    async fn run_grpc(&mut self, grpc: AdminListenConfig) -> Result<()> {
+        let listener = TcpListener::bind(grpc.address())
+            .await
+            .map_err(Error::bind(grpc.address()))?;
+        self.grpc_local_addr = Some(listener.local_addr()?);
+        tokio::spawn(crate::grpc::serve(
+            listener,
+            self.handler.clone(),
+            self.zones.clone(),
+            grpc.token().map(str::to_string),
+            self.shutdown_token.clone(),
+        ));
+        Ok(())
+    }
+
+    #[cfg(not(feature = "grpc"))]
+    async fn run_grpc(&mut self, _grpc: AdminListenConfig) -> Result<()> {
+        anyhow::bail!("the gRPC API requires the `grpc` feature")
     }
 
-    pub async fn run(&mut self) -> Result<()> {
-        if let Some(address) = self.general_config.listen_udp() {
-            let socket = UdpSocket::bind(address).await?;
-            self.udp_local_addr = Some(socket.local_addr()?);
-            self.server.register_socket(socket);
+    /// Binds the control socket at `path`, replacing the file of a socket
+    /// left behind, and lets only the user of the server connect to it.
+    #[cfg(unix)]
+    fn run_control(&mut self, path: PathBuf) -> Result<()> {
+        use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+        if std::fs::symlink_metadata(&path).is_ok_and(|m| m.file_type().is_socket()) {
+            std::fs::remove_file(&path)?;
         }
+        let listener =
+            tokio::net::UnixListener::bind(&path).map_err(Error::bind(path.display()))?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+        tokio::spawn(crate::control::serve(
+            listener,
+            path,
+            self.handler.clone(),
+            self.shutdown_token.clone(),
+        ));
         Ok(())
     }
 
-    pub async fn shutdown(&mut self) -> Result<()> {
-        self.server.shutdown_gracefully().await?;
+    #[cfg(not(unix))]
+    fn run_control(&mut self, _path: PathBuf) -> Result<()> {
+        anyhow::bail!("the control socket requires a Unix system")
+    }
+
+    /// Counters of the forwarding cache, if forwarding and caching are enabled.
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        self.handler
+            .forwarder
+            .as_ref()
+            .and_then(|f| f.cache_stats())
+    }
+
+    /// Counters of the upstream resolvers, if forwarding is enabled.
+    pub fn upstream_stats(&self) -> Vec<UpstreamStats> {
+        self.handler
+            .forwarder
+            .as_ref()
+            .map_or_else(Vec::new, |f| f.upstream_stats())
+    }
+
+    /// Drops the cached upstream answers for `name`, only those of
+    /// `query_type` if given. Returns how many were dropped.
+    pub fn flush_cache(&self, name: &rr::Name, query_type: Option<rr::RecordType>) -> usize {
+        self.flush(&Flush::Name(LowerName::from(name), query_type))
+    }
+
+    /// Drops the cached upstream answers for `name` and the names below it,
+    /// or every cached answer without a name. Returns how many were dropped.
+    pub fn flush_cache_subtree(&self, name: Option<&rr::Name>) -> usize {
+        self.flush(&match name {
+            Some(name) => Flush::Subtree(LowerName::from(name)),
+            None => Flush::All,
+        })
+    }
+
+    fn flush(&self, flush: &Flush) -> usize {
+        self.handler
+            .forwarder
+            .as_ref()
+            .map_or(0, |f| f.flush_cache(flush))
+    }
+
+    /// Binds the listeners, as [`Server::run`] does, and serves until the
+    /// server is shut down, see [`Server::block_until_done`].
+    pub async fn run_until_done(&mut self) -> Result<(), Error> {
+        self.run().await?;
+        self.block_until_done().await
+    }
+
+    /// Waits until the server is shut down through a [`ServerHandle`], or
+    /// fails with a [`Error::Io`] when a listener stops on its own.
+    pub async fn block_until_done(&self) -> Result<(), Error> {
+        let listening = !self.udp_local_addrs.is_empty()
+            || !self.tcp_local_addrs.is_empty()
+            || self.tls_local_addr.is_some()
+            || self.quic_local_addr.is_some();
+        if listening {
+            // the listeners are released once the shutdown starts, for it
+            // to stop them
+            let listeners = async { self.server.lock().await.block_until_done().await };
+            tokio::select! {
+                biased;
+                _ = self.shutdown_token.cancelled() => {}
+                result = listeners => result.map_err(|e| Error::Io(e.into()))?,
+            }
+        }
+        self.stopped.cancelled().await;
         Ok(())
     }
 
+    /// Stops the server, see [`ServerHandle::shutdown`].
+    pub async fn shutdown(&mut self) -> Result<usize, Error> {
+        self.handle().shutdown().await
+    }
+
+    /// Serves the zones of `config` and of its views in place of the current
+    /// ones. Only zones whose records changed are replaced, in-flight queries
+    /// and listeners are not affected. Listener and forwarding settings, TSIG
+    /// keys and the views themselves are not reloaded.
+    pub async fn reload(&self, config: config::RunConfig) -> Result<(), Error> {
+        self.handle().reload(config).await
+    }
+
+    /// Runs `middleware` on every request, after the middlewares added
+    /// before it. Middlewares can be added while the server runs.
+    pub fn add_middleware(&self, middleware: impl Middleware + 'static) {
+        self.handler.middlewares.push(Arc::new(middleware));
+    }
+
+    /// Resolves the names no zone holds with `fallback`, before they are
+    /// forwarded, in place of the fallback set before.
+    pub fn set_fallback(&self, fallback: impl Fallback + 'static) {
+        *self.handler.fallback.write().unwrap() = Some(Arc::new(fallback));
+    }
+
+    /// A handle shutting down and reloading the server from other tasks.
+    pub fn handle(&self) -> ServerHandle {
+        ServerHandle {
+            server: self.server.clone(),
+            views: self.handler.views.clone(),
+            drain: self.handler.drain.clone(),
+            forwarder: self.handler.forwarder.clone(),
+            shutdown_timeout: self.general_config.shutdown_timeout(),
+            shutdown_token: self.shutdown_token.clone(),
+            stopped: self.stopped.clone(),
+        }
+    }
+
+    /// Reloads the zones from the config file at `path` on SIGHUP and, with
+    /// `general.watch_config`, whenever the file changes.
+    pub fn watch(&self, path: impl Into<PathBuf>) {
+        let interval = self
+            .general_config
+            .watch_config()
+            .then(|| self.general_config.watch_interval());
+        tokio::spawn(self.handler.views.clone().watch(
+            path.into(),
+            interval,
+            self.shutdown_token.clone(),
+        ));
+    }
+
+    /// Replaces the authority of `name`. Records of the zone can't be edited
+    /// with the record-level methods afterwards.
     pub async fn upsert(&self, name: LowerName, authority: Box<dyn AuthorityObject>) {
-        self.catalog.write().await.upsert(name, authority);
+        self.zones.upsert(name, authority);
     }
 
     pub async fn remove(&self, name: &LowerName) -> Option<Box<dyn AuthorityObject>> {
-        self.catalog.write().await.remove(name)
+        self.zones.remove(name)
+    }
+
+    /// The in-memory authority of the primary zone enclosing `name`.
+    fn find_authority(&self, name: &rr::Name) -> Result<Arc<InMemoryAuthority>, RecordError> {
+        let authority = self
+            .zones
+            .find(name)
+            .ok_or_else(|| RecordError::ZoneNotFound(name.clone()))?;
+        if authority.zone_type() == ZoneType::Secondary {
+            return Err(RecordError::ReadOnly(authority.origin().into()));
+        }
+        Ok(authority)
+    }
+
+    /// Dumps the current records of the in-memory zone `name` as an RFC 1035
+    /// zone file, including changes made at runtime.
+    pub async fn export_zone(&self, name: &rr::Name) -> Result<String, RecordError> {
+        self.zones
+            .export(&name.into())
+            .await
+            .ok_or_else(|| RecordError::ZoneNotFound(name.clone()))
+    }
+
+    /// Adds `record` to its zone, returns whether the zone changed. Changes
+    /// raise the SOA serial and are announced to the zone's `notify` list.
+    pub async fn add_record(&self, record: rr::Record) -> Result<bool, RecordError> {
+        let authority = self.find_authority(record.name())?;
+        let changed = authority.upsert(record, 0).await;
+        if changed {
+            self.zones.changed(&authority).await;
+        }
+        Ok(changed)
+    }
+
+    /// Removes the record with the name, type and data of `record`, returns
+    /// whether it existed. The SOA and the last NS record are never removed.
+    pub async fn remove_record(&self, record: &rr::Record) -> Result<bool, RecordError> {
+        let authority = self.find_authority(record.name())?;
+        let removed = remove_from(&mut *authority.records_mut().await, record);
+        if removed {
+            self.zones.changed(&authority).await;
+        }
+        Ok(removed)
+    }
+
+    /// Replaces every record with the name and type of `rrset`, an empty
+    /// `rrset` removes them.
+    pub async fn replace_rrset(&self, rrset: RecordSet) -> Result<(), RecordError> {
+        let authority = self.find_authority(rrset.name())?;
+        let key = RrKey::new(rrset.name().into(), rrset.record_type());
+        let mut records = authority.records_mut().await;
+        if rrset.is_empty() {
+            records.remove(&key);
+        } else {
+            records.insert(key, Arc::new(rrset));
+        }
+        drop(records);
+        self.zones.changed(&authority).await;
+        Ok(())
+    }
+
+    /// Captures the zones served and their records, including the changes
+    /// made at runtime, to be restored with [`Server::restore`].
+    pub async fn snapshot(&self) -> CatalogSnapshot {
+        self.handler.views.snapshot().await
+    }
+
+    /// Serves the zones and records of `snapshot` in place of the current
+    /// ones, e.g. to roll back a config push or recover after a restart.
+    /// The snapshot must be of a server with the same views.
+    pub async fn restore(&self, snapshot: CatalogSnapshot) -> Result<(), Error> {
+        self.handler
+            .views
+            .restore_snapshot(snapshot)
+            .await
+            .map_err(|e| Error::classify(e, Error::Zone))
+    }
+
+    /// Starts a transaction of record changes, which queries see together
+    /// once it is committed.
+    pub fn begin_txn(&self) -> Transaction {
+        Transaction {
+            zones: self.zones.clone(),
+            changes: Vec::new(),
+        }
+    }
+
+    /// Publishes the DNS-SD `service` in `domain`, an in-memory zone or a
+    /// name within one, and announces it over mDNS. Returns whether the zone
+    /// changed. Like other record changes it is dropped by a reload.
+    pub async fn register_service(
+        &self,
+        domain: &rr::Name,
+        service: &Service,
+    ) -> Result<bool, Error> {
+        let records = service.to_records(domain)?;
+        let authority = self
+            .find_authority(domain)
+            .map_err(|e| Error::Zone(e.into()))?;
+        let mut changed = false;
+        for record in records.iter() {
+            changed |= authority.upsert(record.clone(), 0).await;
+        }
+        if changed {
+            self.zones.changed(&authority).await;
+            let _ = self.mdns_changes.send(records);
+        }
+        Ok(changed)
     }
 
+    /// Withdraws the DNS-SD `service` of `domain`, and the enumeration of its
+    /// service type once no instance of it is left. Returns whether it was
+    /// published.
+    pub async fn unregister_service(
+        &self,
+        domain: &rr::Name,
+        service: &Service,
+    ) -> Result<bool, Error> {
+        let [enumeration, instances @ ..] = &service.to_records(domain)?[..] else {
+            unreachable!("a service has an enumeration record");
+        };
+        let authority = self
+            .find_authority(domain)
+            .map_err(|e| Error::Zone(e.into()))?;
+        let mut records = authority.records_mut().await;
+        let mut removed: Vec<_> = instances
+            .iter()
+            .filter(|record| remove_from(&mut records, record))
+            .cloned()
+            .collect();
+        let type_name = service.type_name(domain)?;
+        if !records.contains_key(&RrKey::new(type_name.into(), rr::RecordType::PTR))
+            && remove_from(&mut records, enumeration)
+        {
+            removed.push(enumeration.clone());
+        }
+        drop(records);
+        if removed.is_empty() {
+            return Ok(false);
+        }
+        self.zones.changed(&authority).await;
+        for record in removed.iter_mut() {
+            record.set_ttl(0);
+        }
+        let _ = self.mdns_changes.send(removed);
+        Ok(true)
+    }
+
+    /// Answers the ACME DNS-01 challenge of `domain` with `token`, a TXT
+    /// record of `_acme-challenge.<domain>` with the `ttl` of `general.acme`,
+    /// until it is cleared or its `expire` passes. Like other record changes
+    /// it is dropped by a reload.
+    pub async fn set_acme_challenge(&self, domain: &rr::Name, token: &str) -> Result<(), Error> {
+        let name = acme::Challenges::name(domain).map_err(|e| Error::Zone(e.into()))?;
+        self.acme
+            .set(name, token)
+            .await
+            .map_err(|e| Error::classify(e, Error::Zone))
+    }
+
+    /// Stops answering the ACME challenge of `domain` with `token`, returns
+    /// whether it was answered.
+    pub async fn clear_acme_challenge(
+        &self,
+        domain: &rr::Name,
+        token: &str,
+    ) -> Result<bool, Error> {
+        let name = acme::Challenges::name(domain).map_err(|e| Error::Zone(e.into()))?;
+        self.acme
+            .clear(name, token)
+            .await
+            .map_err(|e| Error::classify(e, Error::Zone))
+    }
+
+    /// Applies the dynamic update `update` (RFC 2136) to its zone and sends
+    /// the result to `response_handle`. The `allow_update` ACL of the zone is
+    /// not checked, it only applies to updates received by the listeners.
     pub async fn update<R: ResponseHandler>(
         &self,
         update: &Request,
         response_edns: Option<Edns>,
-        response_handle: R,
+        mut response_handle: R,
     ) -> io::Result<ResponseInfo> {
-        self.catalog
-            .write()
-            .await
-            .update(update, response_edns, response_handle)
+        let mut header = Header::response_from_request(update.header());
+        if let Err(code) = self.zones.update(update).await {
+            header.set_response_code(code);
+        }
+        let mut response = MessageResponseBuilder::from_message_request(update);
+        if let Some(edns) = response_edns {
+            response.edns(edns);
+        }
+        response_handle
+            .send_response(response.build_no_records(header))
             .await
     }
 
     pub async fn contains(&self, name: &LowerName) -> bool {
-        self.catalog.read().await.contains(name)
+        self.zones.catalog().snapshot().contains(name)
     }
 
     pub async fn lookup<R: ResponseHandler>(
@@ -124,32 +945,184 @@ impl Server {
         response_edns: Option<Edns>,
         response_handle: R,
     ) -> ResponseInfo {
-        self.catalog
-            .read()
-            .await
+        self.zones
+            .catalog()
+            .snapshot()
             .lookup(request, response_edns, response_handle)
             .await
     }
 
-    pub async fn read_catalog(&self) -> RwLockReadGuard<'_, Catalog> {
-        self.catalog.read().await
+    /// The catalog currently served, which later changes of the zones leave
+    /// as it is. Zones are changed with `upsert` and `remove`.
+    pub async fn read_catalog(&self) -> Arc<Catalog> {
+        self.zones.catalog().snapshot()
+    }
+}
+
+/// Record changes of in-memory primary zones applied together, see
+/// [`Server::begin_txn`]. Dropping it discards the changes.
+pub struct Transaction {
+    zones: Arc<ZoneSet>,
+    changes: Vec<RecordChange>,
+}
+
+impl Transaction {
+    /// Adds `record` to its zone.
+    pub fn add(&mut self, record: rr::Record) -> &mut Self {
+        self.changes.push(RecordChange::Add(record));
+        self
+    }
+
+    /// Removes the record with the name, type and data of `record`. The
+    /// commit fails when it doesn't exist.
+    pub fn remove(&mut self, record: rr::Record) -> &mut Self {
+        self.changes.push(RecordChange::Remove(record));
+        self
+    }
+
+    /// Replaces every record with the name and type of `rrset`, an empty
+    /// `rrset` removes them.
+    pub fn replace(&mut self, rrset: RecordSet) -> &mut Self {
+        self.changes.push(RecordChange::Replace(rrset));
+        self
+    }
+
+    /// Applies the changes, all of them or none, returns whether a zone
+    /// changed. The SOA serials of the zones changed are raised once and
+    /// the changes announced to their `notify` lists.
+    pub async fn commit(self) -> Result<bool, RecordError> {
+        self.zones.commit(self.changes).await
+    }
+}
+
+/// Controls a [`Server`] from other tasks, see [`Server::handle`].
+#[derive(Clone)]
+pub struct ServerHandle {
+    server: Listeners,
+    views: Arc<Views>,
+    drain: Arc<Drain>,
+    forwarder: Option<Arc<Forwarder>>,
+    shutdown_timeout: Duration,
+    shutdown_token: CancellationToken,
+    stopped: CancellationToken,
+}
+
+impl ServerHandle {
+    /// Stops the server. New queries are refused while those in flight are
+    /// answered, for up to `general.shutdown_timeout`. The listeners and
+    /// background tasks are stopped then, dropping the queries still in
+    /// flight, whose number is returned. The forward cache is saved to its
+    /// persist file, if any, once the listeners are stopped.
+    pub async fn shutdown(&self) -> Result<usize, Error> {
+        let dropped = self.drain.drain(self.shutdown_timeout).await;
+        if dropped > 0 {
+            warn!("dropping {} queries still in flight", dropped);
+        }
+        self.shutdown_token.cancel();
+        let result = self.server.lock().await.shutdown_gracefully().await;
+        if let Some(forwarder) = &self.forwarder {
+            forwarder.save_cache().await;
+        }
+        self.stopped.cancel();
+        result.map_err(|e| Error::Io(e.into()))?;
+        Ok(dropped)
+    }
+
+    /// Reloads the zones, see [`Server::reload`].
+    pub async fn reload(&self, config: config::RunConfig) -> Result<(), Error> {
+        self.views
+            .reload(&config)
+            .await
+            .map_err(|e| Error::classify(e, Error::Zone))
+    }
+
+    /// The number of queries being answered.
+    pub fn in_flight(&self) -> usize {
+        self.drain.in_flight()
+    }
+
+    /// Whether the server was shut down.
+    pub fn is_shut_down(&self) -> bool {
+        self.shutdown_token.is_cancelled()
+    }
+}
+
+/// Removes the record with the name, type and data of `record` from
+/// `records`, returns whether it existed.
+pub(crate) fn remove_from(
+    records: &mut BTreeMap<RrKey, Arc<RecordSet>>,
+    record: &rr::Record,
+) -> bool {
+    let key = RrKey::new(record.name().into(), record.record_type());
+    let Some(rrset) = records.get_mut(&key) else {
+        return false;
+    };
+    let removed = Arc::make_mut(rrset).remove(record, 0);
+    if rrset.is_empty() {
+        records.remove(&key);
     }
+    removed
+}
+
+/// Binds `count` UDP sockets to `address` with `SO_REUSEPORT`, so that the
+/// kernel spreads the datagrams over them. A port of 0 is resolved by the
+/// first socket and shared by the others.
+#[cfg(unix)]
+fn bind_reuse_port(mut address: SocketAddr, count: usize) -> Result<Vec<UdpSocket>> {
+    use socket2::{Domain, Protocol, Socket, Type};
 
-    pub async fn write_catalog(&self) -> RwLockWriteGuard<'_, Catalog> {
-        self.catalog.write().await
+    let mut sockets = Vec::with_capacity(count);
+    for _ in 0..count {
+        let socket = Socket::new(
+            Domain::for_address(address),
+            Type::DGRAM,
+            Some(Protocol::UDP),
+        )?;
+        socket.set_reuse_port(true)?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&address.into()).map_err(Error::bind(address))?;
+        let socket = UdpSocket::from_std(socket.into())?;
+        address = socket.local_addr()?;
+        sockets.push(socket);
     }
+    Ok(sockets)
+}
+
+#[cfg(not(unix))]
+fn bind_reuse_port(_address: SocketAddr, _count: usize) -> Result<Vec<UdpSocket>> {
+    bail!("more than one of udp_workers requires SO_REUSEPORT, which needs a Unix system")
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::{GeneralConfigBuilder, RecordBuilder, RecordType, RunConfigBuilder};
+    use crate::config::{
+        AnyQueries, ChaosConfigBuilder, GeneralConfigBuilder, RecordBuilder, RecordData,
+        RecordType, RewriteMatch, RewriteRuleBuilder, RunConfigBuilder, ServiceBuilder,
+    };
+    use crate::ede;
+    use crate::middleware::{Action, Fallback, RequestContext};
     use anyhow::Result;
+    use futures_util::StreamExt;
     use hickory_client::client::{AsyncClient, ClientHandle};
+    use hickory_proto::iocompat::AsyncIoTokioAsStd;
+    use hickory_proto::op::{Edns, Message, MessageType, Query, ResponseCode};
     use hickory_proto::rr;
+    use hickory_proto::rr::dnssec::rdata::tsig::TsigAlgorithm;
+    use hickory_proto::rr::dnssec::tsig::TSigner;
+    use hickory_proto::rr::dnssec::Verifier;
+    use hickory_proto::rr::rdata::opt::{ClientSubnet, EdnsCode, EdnsOption};
+    use hickory_proto::rustls::tls_client_connect;
+    use hickory_proto::tcp::TcpClientStream;
     use hickory_proto::udp::UdpClientStream;
+    use hickory_proto::xfer::DnsHandle;
     use maplit::hashmap;
+    use std::collections::HashMap;
+    use std::io::Write;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use std::str::FromStr;
     use std::time::Duration;
+    use tokio::net::TcpStream;
 
     #[tokio::test]
     async fn it_works() -> Result<()> {
@@ -163,12 +1136,47 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn reports_error_kinds() -> Result<()> {
+        let record = RecordBuilder::default()
+            .name("www..et.internal".to_string())
+            .data(RecordData::A(Ipv4Addr::new(10, 0, 0, 1)))
+            .ttl(Duration::from_secs(60))
+            .build()?;
+        let config = RunConfigBuilder::default()
+            .general(GeneralConfigBuilder::default().build()?)
+            .zones(hashmap! {
+                "et.internal".to_string() => vec![record].into(),
+            })
+            .build()?;
+        assert!(matches!(
+            Server::try_new(config),
+            Err(Error::Record { name, .. }) if name == "www..et.internal"
+        ));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let taken = listener.local_addr()?;
+        let mut server = Server::try_new(
+            RunConfigBuilder::default()
+                .general(
+                    GeneralConfigBuilder::default()
+                        .listen_tcp(taken.to_string())
+                        .build()?,
+                )
+                .build()?,
+        )?;
+        assert!(matches!(
+            server.run().await,
+            Err(Error::Bind { address, .. }) if address == taken.to_string()
+        ));
+        Ok(())
+    }
+
     #[tokio::test]
     async fn can_resolve_records() -> Result<()> {
         let configured_record = RecordBuilder::default()
-            .rr_type(RecordType::A)
             .name("www.et.internal".to_string())
-            .value("123.123.123.123".to_string())
+            .data(RecordData::A(Ipv4Addr::new(123, 123, 123, 123)))
             .ttl(Duration::from_secs(60))
             .build()?;
         let config = RunConfigBuilder::default()
@@ -178,10 +1186,10 @@ mod tests {
                     .build()?,
             )
             .zones(hashmap! {
-                "et.internal".to_string() => vec![configured_record.clone()],
+                "et.internal".to_string() => vec![configured_record.clone()].into(),
             })
             .build()?;
-        
+
         let mut server = Server::new(config);
         server.run().await?;
 
@@ -205,4 +1213,2895 @@ mod tests {
         server.shutdown().await?;
         Ok(())
     }
+
+    #[tokio::test]
+    async fn answers_on_every_udp_worker() -> Result<()> {
+        let configured_record = RecordBuilder::default()
+            .name("www.et.internal".to_string())
+            .data(RecordData::A(Ipv4Addr::new(123, 123, 123, 123)))
+            .ttl(Duration::from_secs(60))
+            .build()?;
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .udp_workers(4)
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.internal".to_string() => vec![configured_record].into(),
+            })
+            .build()?;
+
+        let mut server = Server::new(config);
+        server.run().await?;
+
+        let local_addr = server.udp_local_addr().unwrap();
+        // the kernel picks the worker by the source port of each client
+        for _ in 0..16 {
+            let stream =
+                UdpClientStream::<UdpSocket>::with_timeout(local_addr, Duration::from_secs(5));
+            let (mut client, background) = AsyncClient::connect(stream).await?;
+            let background_task = tokio::spawn(background);
+            let response = client
+                .query(
+                    rr::Name::from_str("www.et.internal")?,
+                    rr::DNSClass::IN,
+                    rr::RecordType::A,
+                )
+                .await?;
+            drop(background_task);
+            assert_eq!(response.answers().len(), 1);
+        }
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn answers_on_every_listen_address() -> Result<()> {
+        let configured_record = RecordBuilder::default()
+            .name("www.et.internal".to_string())
+            .data(RecordData::A(Ipv4Addr::new(123, 123, 123, 123)))
+            .ttl(Duration::from_secs(60))
+            .build()?;
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp(vec!["127.0.0.1:0", "127.0.0.1:0"])
+                    .listen_tcp(vec!["127.0.0.1:0", "127.0.0.1:0"])
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.internal".to_string() => vec![configured_record].into(),
+            })
+            .build()?;
+
+        let mut server = Server::new(config);
+        server.run().await?;
+
+        assert_eq!(server.udp_local_addrs().len(), 2);
+        assert_eq!(server.tcp_local_addrs().len(), 2);
+        assert_eq!(
+            server.udp_local_addr(),
+            server.udp_local_addrs().first().copied()
+        );
+        for &local_addr in server.udp_local_addrs() {
+            let stream =
+                UdpClientStream::<UdpSocket>::with_timeout(local_addr, Duration::from_secs(5));
+            let (mut client, background) = AsyncClient::connect(stream).await?;
+            let background_task = tokio::spawn(background);
+            let response = client
+                .query(
+                    rr::Name::from_str("www.et.internal")?,
+                    rr::DNSClass::IN,
+                    rr::RecordType::A,
+                )
+                .await?;
+            drop(background_task);
+            assert_eq!(response.answers().len(), 1);
+        }
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn can_resolve_aaaa_records() -> Result<()> {
+        let configured_record = RecordBuilder::default()
+            .name("www.et.internal".to_string())
+            .data(RecordData::Aaaa(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1)))
+            .ttl(Duration::from_secs(60))
+            .build()?;
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.internal".to_string() => vec![configured_record.clone()].into(),
+            })
+            .build()?;
+
+        let mut server = Server::new(config);
+        server.run().await?;
+
+        let local_addr = server.udp_local_addr().unwrap();
+        let stream = UdpClientStream::<UdpSocket>::with_timeout(local_addr, Duration::from_secs(5));
+        let (mut client, background) = AsyncClient::connect(stream).await?;
+        let background_task = tokio::spawn(background);
+        let response = client
+            .query(
+                rr::Name::from_str("www.et.internal")?,
+                rr::DNSClass::IN,
+                rr::RecordType::AAAA,
+            )
+            .await?;
+        drop(background_task);
+
+        assert_eq!(response.answers().len(), 1);
+        let expected_record: rr::Record = configured_record.try_into()?;
+        assert_eq!(response.answers().first().unwrap(), &expected_record);
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    fn record(rr_type: RecordType, name: &str, value: &str) -> Result<config::Record> {
+        Ok(RecordBuilder::default()
+            .name(name.to_string())
+            .data(RecordData::from_text(rr_type, value)?)
+            .ttl(Duration::from_secs(60))
+            .build()?)
+    }
+
+    async fn query(
+        server: &mut Server,
+        name: &str,
+        rr_type: rr::RecordType,
+    ) -> Result<hickory_proto::xfer::DnsResponse> {
+        query_address(server.udp_local_addr().unwrap(), name, rr_type).await
+    }
+
+    async fn query_address(
+        local_addr: SocketAddr,
+        name: &str,
+        rr_type: rr::RecordType,
+    ) -> Result<hickory_proto::xfer::DnsResponse> {
+        let stream = UdpClientStream::<UdpSocket>::with_timeout(local_addr, Duration::from_secs(5));
+        let (mut client, background) = AsyncClient::connect(stream).await?;
+        let background_task = tokio::spawn(background);
+        let response = client
+            .query(rr::Name::from_str(name)?, rr::DNSClass::IN, rr_type)
+            .await?;
+        drop(background_task);
+        Ok(response)
+    }
+
+    /// Sends `message` to the UDP listener of `server` as it is.
+    async fn exchange_udp(server: &mut Server, message: &Message) -> Result<Message> {
+        let socket = UdpSocket::bind("127.0.0.1:0").await?;
+        socket
+            .send_to(&message.to_vec()?, server.udp_local_addr().unwrap())
+            .await?;
+        let mut buffer = [0; 4096];
+        let len = tokio::time::timeout(Duration::from_secs(5), socket.recv(&mut buffer)).await??;
+        Ok(Message::from_vec(&buffer[..len])?)
+    }
+
+    #[tokio::test]
+    async fn truncates_large_udp_responses() -> Result<()> {
+        let records = (0..4)
+            .map(|i| {
+                record(
+                    RecordType::TXT,
+                    "txt.et.internal",
+                    &format!("{}{}", i, "x".repeat(200)),
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .listen_tcp("127.0.0.1:0")
+                    .build()?,
+            )
+            .zones(hashmap! {"et.internal".to_string() => records.into()})
+            .build()?;
+        let mut server = Server::try_new(config)?;
+        server.run().await?;
+
+        let question = |payload: Option<u16>| {
+            let mut message = Message::new();
+            message.set_id(7).add_query(Query::query(
+                rr::Name::from_str("txt.et.internal.").unwrap(),
+                rr::RecordType::TXT,
+            ));
+            if let Some(payload) = payload {
+                let mut edns = Edns::new();
+                edns.set_max_payload(payload);
+                message.set_edns(edns);
+            }
+            message
+        };
+
+        // without EDNS, the answers don't fit in 512 bytes
+        let response = exchange_udp(&mut server, &question(None)).await?;
+        assert!(response.truncated());
+        assert!(response.answers().is_empty());
+        assert_eq!(response.queries().len(), 1);
+
+        // the EDNS payload size of the server is advertised
+        let response = exchange_udp(&mut server, &question(Some(4096))).await?;
+        assert!(!response.truncated());
+        assert_eq!(response.answers().len(), 4);
+        assert_eq!(response.extensions().as_ref().unwrap().max_payload(), 1232);
+
+        let response = exchange_udp(&mut server, &question(Some(600))).await?;
+        assert!(response.truncated());
+        assert!(response.answers().is_empty());
+        assert!(response.extensions().is_some());
+
+        // the client retries over TCP
+        let tcp_addr = server.tcp_local_addr().unwrap();
+        let response =
+            crate::testing::query_tcp(tcp_addr, "txt.et.internal", rr::RecordType::TXT).await?;
+        assert!(!response.truncated());
+        assert_eq!(response.answers().len(), 4);
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn answers_chaos_queries() -> Result<()> {
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .chaos(ChaosConfigBuilder::default().version("libdns").build()?)
+                    .build()?,
+            )
+            .build()?;
+        let mut server = Server::try_new(config)?;
+        server.run().await?;
+
+        let question = |name: &str| {
+            let mut query = Query::query(rr::Name::from_str(name).unwrap(), rr::RecordType::TXT);
+            query.set_query_class(rr::DNSClass::CH);
+            let mut message = Message::new();
+            message.set_id(7).add_query(query);
+            message
+        };
+        let response = exchange_udp(&mut server, &question("version.bind.")).await?;
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(response.answers()[0].dns_class(), rr::DNSClass::CH);
+        assert_eq!(response.answers()[0].data().unwrap().to_string(), "libdns");
+        let response = exchange_udp(&mut server, &question("hostname.bind.")).await?;
+        assert_eq!(response.response_code(), ResponseCode::Refused);
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn returns_nsid_when_asked() -> Result<()> {
+        let records = vec![record(RecordType::A, "www.et.internal", "10.0.0.1")?];
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .nsid("ns1.fra")
+                    .build()?,
+            )
+            .zones(hashmap! {"et.internal".to_string() => records.into()})
+            .build()?;
+        let mut server = Server::try_new(config)?;
+        server.run().await?;
+
+        let question = |options: Vec<EdnsOption>| {
+            let mut edns = Edns::new();
+            for option in options {
+                edns.options_mut().insert(option);
+            }
+            let mut message = Message::new();
+            message
+                .set_id(7)
+                .add_query(Query::query(
+                    rr::Name::from_str("www.et.internal.").unwrap(),
+                    rr::RecordType::A,
+                ))
+                .set_edns(edns);
+            message
+        };
+        let nsid = |response: &Message| {
+            response
+                .extensions()
+                .as_ref()?
+                .option(EdnsCode::NSID)
+                .cloned()
+        };
+        let response = exchange_udp(
+            &mut server,
+            &question(vec![EdnsOption::Unknown(3, Vec::new())]),
+        )
+        .await?;
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(
+            nsid(&response),
+            Some(EdnsOption::Unknown(3, b"ns1.fra".to_vec()))
+        );
+        let response = exchange_udp(&mut server, &question(Vec::new())).await?;
+        assert_eq!(nsid(&response), None);
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn applies_any_query_policy() -> Result<()> {
+        let start = |policy| async move {
+            let records = vec![
+                record(RecordType::A, "www.et.internal", "10.0.0.1")?,
+                record(RecordType::TXT, "www.et.internal", "hello")?,
+            ];
+            let config = RunConfigBuilder::default()
+                .general(
+                    GeneralConfigBuilder::default()
+                        .listen_udp("127.0.0.1:0")
+                        .any_queries(policy)
+                        .build()?,
+                )
+                .zones(hashmap! {"et.internal".to_string() => records.into()})
+                .build()?;
+            let mut server = Server::try_new(config)?;
+            server.run().await?;
+            anyhow::Ok(server)
+        };
+
+        let mut server = start(AnyQueries::Full).await?;
+        let response = query(&mut server, "www.et.internal", rr::RecordType::ANY).await?;
+        assert_eq!(response.answers().len(), 2);
+        server.shutdown().await?;
+
+        let mut server = start(AnyQueries::Minimal).await?;
+        let response = query(&mut server, "www.et.internal", rr::RecordType::ANY).await?;
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(
+            response.answers()[0].to_string(),
+            "www.et.internal. 60 IN HINFO RFC8482 "
+        );
+        server.shutdown().await?;
+
+        let mut server = start(AnyQueries::Refuse).await?;
+        let response = query(&mut server, "www.et.internal", rr::RecordType::ANY).await?;
+        assert_eq!(response.response_code(), ResponseCode::Refused);
+        let response = query(&mut server, "www.et.internal", rr::RecordType::A).await?;
+        assert_eq!(response.answers().len(), 1);
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn clamps_ttls_of_answers() -> Result<()> {
+        let short = RecordBuilder::default()
+            .name("short.et.internal".to_string())
+            .data(RecordData::from_text(RecordType::A, "10.0.0.2")?)
+            .ttl(Duration::from_secs(2))
+            .build()?;
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .min_ttl(Duration::from_secs(5))
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.internal".to_string() => config::ZoneConfigBuilder::default()
+                    .records(vec![record(RecordType::A, "www.et.internal", "10.0.0.1")?, short])
+                    .max_ttl(Duration::from_secs(30))
+                    .build()?,
+                "et.top".to_string() => vec![record(RecordType::A, "www.et.top", "10.0.0.3")?].into(),
+            })
+            .build()?;
+        let mut server = Server::try_new(config)?;
+        server.run().await?;
+
+        // the limits of the zone, then the global ones
+        let response = query(&mut server, "www.et.internal", rr::RecordType::A).await?;
+        assert_eq!(response.answers()[0].ttl(), 30);
+        let response = query(&mut server, "short.et.internal", rr::RecordType::A).await?;
+        assert_eq!(response.answers()[0].ttl(), 5);
+        let response = query(&mut server, "www.et.top", rr::RecordType::A).await?;
+        assert_eq!(response.answers()[0].ttl(), 60);
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rewrites_queries() -> Result<()> {
+        let rule = |match_kind, from: &str, to: &str, rewrite_answers| {
+            RewriteRuleBuilder::default()
+                .match_kind(match_kind)
+                .from(from)
+                .to(to)
+                .rewrite_answers(rewrite_answers)
+                .build()
+        };
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.internal".to_string() => vec![
+                    record(RecordType::A, "www.et.internal", "10.0.0.1")?,
+                    record(RecordType::A, "api.new.et.internal", "10.0.0.2")?,
+                ].into(),
+            })
+            .rewrites(vec![
+                rule(RewriteMatch::Exact, "legacy.corp", "www.et.internal", false)?,
+                rule(RewriteMatch::Suffix, "old.corp", "new.et.internal", true)?,
+            ])
+            .build()?;
+        let mut server = Server::try_new(config)?;
+        server.run().await?;
+
+        // the question is the client's, the answers keep their names
+        let response = query(&mut server, "legacy.corp", rr::RecordType::A).await?;
+        assert_eq!(response.queries()[0].name().to_string(), "legacy.corp.");
+        assert_eq!(
+            response.answers()[0].to_string(),
+            "www.et.internal. 60 IN A 10.0.0.1"
+        );
+        // unless they are rewritten back
+        let response = query(&mut server, "api.old.corp", rr::RecordType::A).await?;
+        assert_eq!(
+            response.answers()[0].to_string(),
+            "api.old.corp. 60 IN A 10.0.0.2"
+        );
+        let response = query(&mut server, "nope.old.corp", rr::RecordType::A).await?;
+        assert_eq!(response.response_code(), ResponseCode::NXDomain);
+        assert_eq!(response.queries()[0].name().to_string(), "nope.old.corp.");
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn refers_to_delegated_zones() -> Result<()> {
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.internal".to_string() => vec![
+                    record(RecordType::A, "www.et.internal", "10.0.0.1")?,
+                    record(RecordType::NS, "lab.et.internal", "ns1.lab.et.internal")?,
+                    record(RecordType::NS, "lab.et.internal", "ns.et.top")?,
+                    record(RecordType::A, "ns1.lab.et.internal", "10.1.0.53")?,
+                ].into(),
+            })
+            .build()?;
+        let mut server = Server::try_new(config)?;
+        server.run().await?;
+
+        for name in [
+            "www.lab.et.internal",
+            "lab.et.internal",
+            "ns1.lab.et.internal",
+        ] {
+            let response = query(&mut server, name, rr::RecordType::A).await?;
+            assert_eq!(response.response_code(), ResponseCode::NoError);
+            assert!(!response.authoritative());
+            assert!(response.answers().is_empty());
+            let mut name_servers: Vec<String> = response
+                .name_servers()
+                .iter()
+                .map(|r| r.to_string())
+                .collect();
+            name_servers.sort();
+            assert_eq!(
+                name_servers,
+                [
+                    "lab.et.internal. 60 IN NS ns.et.top.",
+                    "lab.et.internal. 60 IN NS ns1.lab.et.internal."
+                ]
+            );
+            assert_eq!(
+                response.additionals()[0].to_string(),
+                "ns1.lab.et.internal. 60 IN A 10.1.0.53"
+            );
+        }
+        let response = query(&mut server, "www.et.internal", rr::RecordType::A).await?;
+        assert!(response.authoritative());
+        assert_eq!(response.answers().len(), 1);
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn can_chase_cname_across_zones() -> Result<()> {
+        let records = vec![
+            record(RecordType::CNAME, "www.et.internal", "web.et.internal")?,
+            record(RecordType::CNAME, "web.et.internal", "host.et.top")?,
+        ];
+        let target = record(RecordType::A, "host.et.top", "100.100.100.100")?;
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.internal".to_string() => records.clone().into(),
+                "et.top".to_string() => vec![target.clone()].into(),
+            })
+            .build()?;
+
+        let mut server = Server::new(config);
+        server.run().await?;
+        let response = query(&mut server, "www.et.internal", rr::RecordType::A).await?;
+
+        let expected = [&records[0], &records[1], &target]
+            .into_iter()
+            .map(|r| r.try_into())
+            .collect::<Result<Vec<_>, crate::Error>>()?;
+        assert_eq!(response.answers(), expected.as_slice());
+        assert!(response.additionals().is_empty());
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stops_on_cname_loop() -> Result<()> {
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.internal".to_string() => vec![
+                    record(RecordType::CNAME, "a.et.internal", "b.et.top")?,
+                ].into(),
+                "et.top".to_string() => vec![
+                    record(RecordType::CNAME, "b.et.top", "a.et.internal")?,
+                ].into(),
+            })
+            .build()?;
+
+        let mut server = Server::new(config);
+        server.run().await?;
+        let response = query(&mut server, "a.et.internal", rr::RecordType::A).await?;
+        assert_eq!(response.answers().len(), 2);
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn expands_wildcard_records() -> Result<()> {
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.internal".to_string() => vec![
+                    record(RecordType::A, "*.dev.et.internal", "10.0.0.1")?,
+                    record(RecordType::CNAME, "*.web.et.internal", "www.dev.et.internal")?,
+                    record(RecordType::A, "api.dev.et.internal", "10.0.0.2")?,
+                    record(RecordType::A, "db.eu.dev.et.internal", "10.0.0.3")?,
+                ].into(),
+            })
+            .build()?;
+
+        let mut server = Server::new(config);
+        server.run().await?;
+
+        for name in ["www.dev.et.internal", "a.b.dev.et.internal"] {
+            let response = query(&mut server, name, rr::RecordType::A).await?;
+            assert_eq!(response.answers().len(), 1);
+            assert_eq!(response.answers()[0].name(), &rr::Name::from_str(name)?);
+            assert_eq!(
+                response.answers()[0].data(),
+                Some(&rr::RData::A("10.0.0.1".parse()?))
+            );
+        }
+        let response = query(&mut server, "www.web.et.internal", rr::RecordType::A).await?;
+        assert_eq!(response.answers().len(), 2);
+        // names that exist are not expanded
+        let response = query(&mut server, "api.dev.et.internal", rr::RecordType::A).await?;
+        assert_eq!(
+            response.answers()[0].data(),
+            Some(&rr::RData::A("10.0.0.2".parse()?))
+        );
+        let response = query(&mut server, "api.dev.et.internal", rr::RecordType::AAAA).await?;
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert!(response.answers().is_empty());
+        let response = query(&mut server, "eu.dev.et.internal", rr::RecordType::A).await?;
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert!(response.answers().is_empty());
+        let response = query(&mut server, "www.eu.dev.et.internal", rr::RecordType::A).await?;
+        assert_eq!(response.response_code(), ResponseCode::NXDomain);
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn serves_zones_of_client_view() -> Result<()> {
+        let run_config = |internal: &str| -> Result<config::RunConfig> {
+            let view = |name: &str, clients: &str, address: &str| -> Result<config::ViewConfig> {
+                Ok(config::ViewConfigBuilder::default()
+                    .name(name.to_string())
+                    .match_clients(vec![clients.to_string()])
+                    .zones(hashmap! {
+                        "et.internal".to_string() => vec![
+                            record(RecordType::A, "www.et.internal", address)?,
+                        ].into(),
+                    })
+                    .build()?)
+            };
+            Ok(RunConfigBuilder::default()
+                .general(
+                    GeneralConfigBuilder::default()
+                        .listen_udp("127.0.0.1:0")
+                        .build()?,
+                )
+                .zones(hashmap! {
+                    "et.internal".to_string() => vec![
+                        record(RecordType::A, "www.et.internal", "192.0.2.1")?,
+                    ].into(),
+                    "et.example".to_string() => vec![
+                        record(RecordType::A, "www.et.example", "192.0.2.2")?,
+                    ].into(),
+                })
+                .views(vec![
+                    view("office", "10.0.0.0/8", "10.0.0.1")?,
+                    view("local", "127.0.0.0/8", internal)?,
+                ])
+                .build()?)
+        };
+
+        let mut server = Server::new(run_config("10.0.0.2")?);
+        server.run().await?;
+
+        let response = query(&mut server, "www.et.internal", rr::RecordType::A).await?;
+        assert_eq!(
+            response.answers()[0].data(),
+            Some(&rr::RData::A("10.0.0.2".parse()?))
+        );
+        // the zones of the config are not served to the clients of a view
+        let response = query(&mut server, "www.et.example", rr::RecordType::A).await?;
+        assert_eq!(response.response_code(), ResponseCode::Refused);
+
+        server.reload(run_config("10.0.0.3")?).await?;
+        let response = query(&mut server, "www.et.internal", rr::RecordType::A).await?;
+        assert_eq!(
+            response.answers()[0].data(),
+            Some(&rr::RData::A("10.0.0.3".parse()?))
+        );
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rate_limits_udp_queries() -> Result<()> {
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .rate_limit(
+                        config::RateLimitConfigBuilder::default()
+                            .queries_per_second(1)
+                            .burst(2)
+                            .build()?,
+                    )
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.internal".to_string() => vec![
+                    record(RecordType::A, "www.et.internal", "10.0.0.1")?,
+                ].into(),
+            })
+            .build()?;
+        let mut server = Server::new(config);
+        server.run().await?;
+
+        for _ in 0..2 {
+            let response = query(&mut server, "www.et.internal", rr::RecordType::A).await?;
+            assert!(!response.truncated());
+            assert_eq!(response.answers().len(), 1);
+        }
+        let response = query(&mut server, "www.et.internal", rr::RecordType::A).await?;
+        assert!(response.truncated());
+        assert!(response.answers().is_empty());
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rate_limits_udp_responses() -> Result<()> {
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .response_rate_limit(
+                        config::ResponseRateLimitConfigBuilder::default()
+                            .responses_per_second(1)
+                            .slip(1)
+                            .build()?,
+                    )
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.internal".to_string() => vec![
+                    record(RecordType::A, "www.et.internal", "10.0.0.1")?,
+                    record(RecordType::A, "mail.et.internal", "10.0.0.2")?,
+                ].into(),
+            })
+            .build()?;
+        let mut server = Server::new(config);
+        server.run().await?;
+
+        let response = query(&mut server, "www.et.internal", rr::RecordType::A).await?;
+        assert!(!response.truncated());
+        assert_eq!(response.answers().len(), 1);
+        let response = query(&mut server, "www.et.internal", rr::RecordType::A).await?;
+        assert!(response.truncated());
+        assert!(response.answers().is_empty());
+        // a different response has its own account
+        let response = query(&mut server, "mail.et.internal", rr::RecordType::A).await?;
+        assert_eq!(response.answers().len(), 1);
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn refuses_clients_outside_acls() -> Result<()> {
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .deny_query(vec!["127.0.0.2".to_string()])
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.internal".to_string() => config::ZoneConfigBuilder::default()
+                    .records(vec![
+                        record(RecordType::A, "www.et.internal", "10.0.0.1")?,
+                        record(RecordType::CNAME, "alias.et.internal", "www.private.internal")?,
+                    ])
+                    .build()?,
+                "private.internal".to_string() => config::ZoneConfigBuilder::default()
+                    .records(vec![record(RecordType::A, "www.private.internal", "10.0.0.2")?])
+                    .allow_query(vec!["10.0.0.0/8".to_string()])
+                    .build()?,
+            })
+            .build()?;
+        let mut server = Server::new(config);
+        server.run().await?;
+
+        let response = query(&mut server, "www.et.internal", rr::RecordType::A).await?;
+        assert_eq!(response.answers().len(), 1);
+        let response = query_dnssec(&mut server, "www.private.internal", rr::RecordType::A).await?;
+        assert_eq!(response.response_code(), ResponseCode::Refused);
+        assert_eq!(extended_error(&response), Some(ede::PROHIBITED));
+        assert!(response.answers().is_empty());
+        // the chain stops at the closed zone
+        let response = query(&mut server, "alias.et.internal", rr::RecordType::A).await?;
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(response.answers()[0].record_type(), RecordType::CNAME);
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn answers_blocked_names() -> Result<()> {
+        let mut file = tempfile::NamedTempFile::new()?;
+        writeln!(file, "0.0.0.0 ads.example")?;
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .build()?,
+            )
+            .blocklist(
+                config::BlocklistConfigBuilder::default()
+                    .sources(vec![file.path().display().to_string()])
+                    .response(config::BlockResponse::Sinkhole)
+                    .sinkhole_ipv4("10.9.9.9".parse::<std::net::Ipv4Addr>()?)
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.internal".to_string() => vec![
+                    record(RecordType::CNAME, "ads.et.internal", "www.ads.example")?,
+                ].into(),
+            })
+            .build()?;
+        let mut server = Server::new(config);
+        server.run().await?;
+
+        let response = query(&mut server, "ads.example", rr::RecordType::A).await?;
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert_eq!(
+            response.answers()[0].data(),
+            Some(&rr::RData::A("10.9.9.9".parse()?))
+        );
+        let response = query_dnssec(&mut server, "www.ads.example", rr::RecordType::AAAA).await?;
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert_eq!(extended_error(&response), Some(ede::BLOCKED));
+        assert!(response.answers().is_empty());
+        // cname targets are blocked as well
+        let response = query(&mut server, "ads.et.internal", rr::RecordType::A).await?;
+        assert_eq!(response.answers().len(), 2);
+        let response = query(&mut server, "example", rr::RecordType::A).await?;
+        assert_eq!(response.response_code(), ResponseCode::Refused);
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    /// Answers `blocked` itself, sends the queries of `alias` to `www` and
+    /// keeps the responses it sees.
+    #[derive(Default)]
+    struct Policy {
+        responses: std::sync::Mutex<Vec<(String, ResponseCode, usize)>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Middleware for Policy {
+        async fn request(&self, _context: &RequestContext, request: &mut Message) -> Action {
+            let query = &mut request.queries_mut()[0];
+            match query.name().to_string().as_str() {
+                "blocked.et.internal." => {
+                    let mut response = Message::new();
+                    response.set_response_code(ResponseCode::NXDomain);
+                    Action::Respond(response)
+                }
+                "alias.et.internal." => {
+                    query.set_name(rr::Name::from_str("www.et.internal.").unwrap());
+                    Action::Continue
+                }
+                _ => Action::Continue,
+            }
+        }
+
+        async fn response(&self, _context: &RequestContext, response: &Message) {
+            self.responses.lock().unwrap().push((
+                response.queries()[0].name().to_string(),
+                response.response_code(),
+                response.answers().len(),
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn runs_middlewares() -> Result<()> {
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.internal".to_string() => vec![
+                    record(RecordType::A, "www.et.internal", "10.0.0.1")?,
+                    record(RecordType::A, "blocked.et.internal", "10.0.0.2")?,
+                ].into(),
+            })
+            .build()?;
+        let mut server = Server::new(config);
+        server.run().await?;
+        let policy = Arc::new(Policy::default());
+        server.add_middleware(policy.clone());
+
+        let response = query(&mut server, "blocked.et.internal", rr::RecordType::A).await?;
+        assert_eq!(response.response_code(), ResponseCode::NXDomain);
+        assert_eq!(
+            response.queries()[0].name(),
+            &rr::Name::from_str("blocked.et.internal.")?
+        );
+        let response = query(&mut server, "alias.et.internal", rr::RecordType::A).await?;
+        assert_eq!(response.answers().len(), 1);
+        let response = query(&mut server, "www.et.internal", rr::RecordType::A).await?;
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(
+            *policy.responses.lock().unwrap(),
+            [
+                (
+                    "blocked.et.internal.".to_string(),
+                    ResponseCode::NXDomain,
+                    0
+                ),
+                ("www.et.internal.".to_string(), ResponseCode::NoError, 1),
+                ("www.et.internal.".to_string(), ResponseCode::NoError, 1),
+            ]
+        );
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    /// Answers the A queries of `dyn.` names with a fixed address.
+    struct DynamicNames;
+
+    #[async_trait::async_trait]
+    impl Fallback for DynamicNames {
+        async fn resolve(&self, _context: &RequestContext, query: &Query) -> Option<Message> {
+            if !rr::Name::from_str("dyn.").unwrap().zone_of(query.name()) {
+                return None;
+            }
+            let mut answer = Message::new();
+            if query.query_type() == rr::RecordType::A {
+                let address = rr::RData::A(Ipv4Addr::new(192, 0, 2, 1).into());
+                answer.add_answer(rr::Record::from_rdata(query.name().clone(), 30, address));
+            }
+            Some(answer)
+        }
+    }
+
+    #[tokio::test]
+    async fn falls_back_for_names_without_zone() -> Result<()> {
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.internal".to_string() => vec![
+                    record(RecordType::CNAME, "app.et.internal", "app.dyn")?,
+                ].into(),
+            })
+            .build()?;
+        let mut server = Server::new(config);
+        server.run().await?;
+        server.set_fallback(DynamicNames);
+
+        let response = query(&mut server, "host.dyn", rr::RecordType::A).await?;
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(
+            response.answers()[0].data().unwrap().to_string(),
+            "192.0.2.1"
+        );
+
+        let response = query(&mut server, "app.et.internal", rr::RecordType::A).await?;
+        assert_eq!(response.answers().len(), 2);
+        assert_eq!(
+            response.answers()[1].name(),
+            &rr::Name::from_str("app.dyn.")?
+        );
+
+        let response = query(&mut server, "host.dyn", rr::RecordType::MX).await?;
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert!(response.answers().is_empty());
+
+        let response = query(&mut server, "www.example", rr::RecordType::A).await?;
+        assert_eq!(response.response_code(), ResponseCode::Refused);
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    async fn query_with_subnet(
+        server: &mut Server,
+        name: &str,
+        subnet: &str,
+    ) -> Result<hickory_proto::xfer::DnsResponse> {
+        let local_addr = server.udp_local_addr().unwrap();
+        let stream = UdpClientStream::<UdpSocket>::with_timeout(local_addr, Duration::from_secs(5));
+        let (client, background) = AsyncClient::connect(stream).await?;
+        let background_task = tokio::spawn(background);
+        let mut edns = Edns::new();
+        edns.options_mut()
+            .insert(EdnsOption::Subnet(ClientSubnet::from_str(subnet)?));
+        let mut message = Message::new();
+        message
+            .add_query(Query::query(rr::Name::from_str(name)?, RecordType::A))
+            .set_recursion_desired(true)
+            .set_edns(edns);
+        let response = client
+            .send(message)
+            .next()
+            .await
+            .ok_or_else(|| anyhow::anyhow!("no response"))??;
+        drop(background_task);
+        Ok(response)
+    }
+
+    /// The info code of the Extended DNS Error of `response`.
+    fn extended_error(response: &Message) -> Option<u16> {
+        match ede::from_edns(response.extensions().as_ref()?)? {
+            EdnsOption::Unknown(_, data) => Some(u16::from_be_bytes([data[0], data[1]])),
+            _ => None,
+        }
+    }
+
+    fn client_subnet(response: &Message) -> Option<EdnsOption> {
+        response
+            .extensions()
+            .as_ref()?
+            .option(EdnsCode::Subnet)
+            .cloned()
+    }
+
+    #[tokio::test]
+    async fn forwards_client_subnets() -> Result<()> {
+        // answers a single query with a /16 scope, returning its subnet option
+        let socket = UdpSocket::bind("127.0.0.1:0").await?;
+        let upstream = socket.local_addr()?;
+        let received = tokio::spawn(async move {
+            let mut buf = [0; 4096];
+            let (len, src) = socket.recv_from(&mut buf).await?;
+            let query = Message::from_vec(&buf[..len])?;
+            let mut response = Message::new();
+            response
+                .set_id(query.id())
+                .set_message_type(MessageType::Response)
+                .add_query(query.queries()[0].clone())
+                .add_answer(rr::Record::from_rdata(
+                    rr::Name::from_str("www.et.top.")?,
+                    60,
+                    rr::RData::A("100.100.100.100".parse()?),
+                ));
+            let mut edns = Edns::new();
+            edns.options_mut()
+                .insert(EdnsOption::Subnet(ClientSubnet::new(
+                    "192.0.0.0".parse()?,
+                    20,
+                    16,
+                )));
+            response.set_edns(edns);
+            socket.send_to(&response.to_vec()?, src).await?;
+            anyhow::Ok(client_subnet(&query))
+        });
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.internal".to_string() => vec![
+                    record(RecordType::A, "www.et.internal", "10.0.0.1")?,
+                ].into(),
+            })
+            .forward(
+                config::ForwardConfigBuilder::default()
+                    .upstreams(vec![upstream.to_string()])
+                    .client_subnet(
+                        config::ClientSubnetConfigBuilder::default()
+                            .ipv4_prefix(20)
+                            .build()?,
+                    )
+                    .build()?,
+            )
+            .build()?;
+        let mut server = Server::new(config);
+        server.run().await?;
+
+        let response = query_with_subnet(&mut server, "www.et.top", "192.0.2.0/24").await?;
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(
+            received.await??,
+            Some(EdnsOption::Subnet(ClientSubnet::new(
+                "192.0.0.0".parse()?,
+                20,
+                0
+            )))
+        );
+        assert_eq!(
+            client_subnet(&response),
+            Some(EdnsOption::Subnet(ClientSubnet::new(
+                "192.0.2.0".parse()?,
+                24,
+                16
+            )))
+        );
+        // local answers are the same for every client
+        let response = query_with_subnet(&mut server, "www.et.internal", "192.0.2.0/24").await?;
+        assert_eq!(
+            client_subnet(&response),
+            Some(EdnsOption::Subnet(ClientSubnet::new(
+                "192.0.2.0".parse()?,
+                24,
+                0
+            )))
+        );
+        let response = query_with_subnet(&mut server, "www.et.internal", "192.0.2.0/20").await?;
+        assert_eq!(response.response_code(), ResponseCode::FormErr);
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn respects_max_cname_depth() -> Result<()> {
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .max_cname_depth(1usize)
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.internal".to_string() => vec![
+                    record(RecordType::CNAME, "a.et.internal", "b.et.top")?,
+                ].into(),
+                "et.top".to_string() => vec![
+                    record(RecordType::CNAME, "b.et.top", "c.et.top")?,
+                    record(RecordType::A, "c.et.top", "100.100.100.100")?,
+                ].into(),
+            })
+            .build()?;
+
+        let mut server = Server::new(config);
+        server.run().await?;
+        // the CNAME of a is followed, that of b isn't
+        let response = query(&mut server, "a.et.internal", rr::RecordType::A).await?;
+        let answers: Vec<_> = response
+            .answers()
+            .iter()
+            .map(|record| (record.name().to_string(), record.record_type()))
+            .collect();
+        assert_eq!(
+            answers,
+            [
+                ("a.et.internal.".to_string(), rr::RecordType::CNAME),
+                ("b.et.top.".to_string(), rr::RecordType::CNAME),
+            ]
+        );
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn answers_soa_and_negative_responses() -> Result<()> {
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.internal".to_string() => vec![
+                    record(RecordType::A, "www.et.internal", "123.123.123.123")?,
+                ].into(),
+            })
+            .build()?;
+
+        let mut server = Server::new(config);
+        server.run().await?;
+
+        let response = query(&mut server, "et.internal", rr::RecordType::SOA).await?;
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(response.answers()[0].record_type(), rr::RecordType::SOA);
+        assert_eq!(response.name_servers().len(), 1);
+        assert_eq!(response.name_servers()[0].record_type(), rr::RecordType::NS);
+
+        let response = query(&mut server, "nope.et.internal", rr::RecordType::A).await?;
+        assert_eq!(response.response_code(), ResponseCode::NXDomain);
+        assert!(response.answers().is_empty());
+        assert_eq!(response.name_servers().len(), 1);
+        assert_eq!(
+            response.name_servers()[0].record_type(),
+            rr::RecordType::SOA
+        );
+
+        let response = query(&mut server, "www.et.internal", rr::RecordType::AAAA).await?;
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert!(response.answers().is_empty());
+        assert_eq!(
+            response.name_servers()[0].record_type(),
+            rr::RecordType::SOA
+        );
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn can_edit_individual_records() -> Result<()> {
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.internal".to_string() => vec![
+                    record(RecordType::A, "www.et.internal", "123.123.123.123")?,
+                ].into(),
+            })
+            .build()?;
+
+        let mut server = Server::new(config);
+        server.run().await?;
+
+        let added: rr::Record = record(RecordType::A, "api.et.internal", "10.0.0.1")?.try_into()?;
+        assert!(server.add_record(added.clone()).await?);
+        let response = query(&mut server, "api.et.internal", rr::RecordType::A).await?;
+        assert_eq!(response.answers(), std::slice::from_ref(&added));
+        let zone = server
+            .export_zone(&rr::Name::from_str("et.internal")?)
+            .await?;
+        assert!(zone.contains("api.et.internal.\t60\tIN\tA\t10.0.0.1\n"));
+
+        let name = rr::Name::from_str("api.et.internal")?;
+        let mut rrset = RecordSet::with_ttl(name.clone(), rr::RecordType::A, 60);
+        rrset.add_rdata(rr::RData::A("10.0.0.2".parse()?));
+        rrset.add_rdata(rr::RData::A("10.0.0.3".parse()?));
+        server.replace_rrset(rrset).await?;
+        let response = query(&mut server, "api.et.internal", rr::RecordType::A).await?;
+        assert_eq!(response.answers().len(), 2);
+
+        assert!(!server.remove_record(&added).await?);
+        let mut removed = added.clone();
+        removed.set_data(Some(rr::RData::A("10.0.0.2".parse()?)));
+        assert!(server.remove_record(&removed).await?);
+        let response = query(&mut server, "api.et.internal", rr::RecordType::A).await?;
+        assert_eq!(response.answers().len(), 1);
+
+        server
+            .replace_rrset(RecordSet::new(&name, rr::RecordType::A, 0))
+            .await?;
+        let response = query(&mut server, "api.et.internal", rr::RecordType::A).await?;
+        assert_eq!(response.response_code(), ResponseCode::NXDomain);
+
+        let outside: rr::Record = record(RecordType::A, "www.et.top", "10.0.0.1")?.try_into()?;
+        assert_eq!(
+            server.add_record(outside).await,
+            Err(RecordError::ZoneNotFound(rr::Name::from_str("www.et.top")?))
+        );
+        assert!(server
+            .export_zone(&rr::Name::from_str("et.top")?)
+            .await
+            .is_err());
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn commits_record_transactions() -> Result<()> {
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.internal".to_string() => vec![
+                    record(RecordType::A, "www.et.internal", "123.123.123.123")?,
+                ].into(),
+                "et.top".to_string() => vec![
+                    record(RecordType::A, "www.et.top", "10.0.0.1")?,
+                ].into(),
+            })
+            .build()?;
+
+        let mut server = Server::new(config);
+        server.run().await?;
+        let serial = |response: &Message| match response.answers()[0].data() {
+            Some(rr::RData::SOA(soa)) => soa.serial(),
+            _ => unreachable!(),
+        };
+        let response = query(&mut server, "et.internal", rr::RecordType::SOA).await?;
+        let before = serial(&response);
+
+        let api: rr::Record = record(RecordType::A, "api.et.internal", "10.0.0.1")?.try_into()?;
+        let txt: rr::Record = record(RecordType::TXT, "api.et.internal", "v=2")?.try_into()?;
+        let www: rr::Record = record(RecordType::A, "www.et.top", "10.0.0.2")?.try_into()?;
+        let mut txn = server.begin_txn();
+        txn.add(api.clone())
+            .add(txt)
+            .replace(RecordSet::from(www.clone()));
+        assert!(txn.commit().await?);
+        let response = query(&mut server, "api.et.internal", rr::RecordType::TXT).await?;
+        assert_eq!(response.answers().len(), 1);
+        let response = query(&mut server, "www.et.top", rr::RecordType::A).await?;
+        assert_eq!(response.answers(), std::slice::from_ref(&www));
+        // raised once for the two records added
+        let response = query(&mut server, "et.internal", rr::RecordType::SOA).await?;
+        assert_eq!(serial(&response), before + 1);
+
+        let db: rr::Record = record(RecordType::A, "db.et.internal", "10.0.0.3")?.try_into()?;
+        let mut txn = server.begin_txn();
+        txn.remove(api).remove(db.clone());
+        assert_eq!(
+            txn.commit().await,
+            Err(RecordError::RecordNotFound(db.name().clone()))
+        );
+        let response = query(&mut server, "api.et.internal", rr::RecordType::A).await?;
+        assert_eq!(response.answers().len(), 1);
+        let response = query(&mut server, "et.internal", rr::RecordType::SOA).await?;
+        assert_eq!(serial(&response), before + 1);
+
+        let mut txn = server.begin_txn();
+        txn.add(db)
+            .add(record(RecordType::A, "www.et.example", "10.0.0.4")?.try_into()?);
+        assert!(matches!(
+            txn.commit().await,
+            Err(RecordError::ZoneNotFound(_))
+        ));
+        let response = query(&mut server, "db.et.internal", rr::RecordType::A).await?;
+        assert_eq!(response.response_code(), ResponseCode::NXDomain);
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn raises_date_serials() -> Result<()> {
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.internal".to_string() => config::ZoneConfigBuilder::default()
+                    .soa(
+                        config::SoaConfigBuilder::default()
+                            .serial_policy(config::SerialPolicy::Date)
+                            .build()?,
+                    )
+                    .records(vec![record(RecordType::A, "www.et.internal", "10.0.0.1")?])
+                    .build()?,
+            })
+            .build()?;
+
+        let mut server = Server::new(config);
+        server.run().await?;
+        let serial = |response: &Message| match response.answers()[0].data() {
+            Some(rr::RData::SOA(soa)) => soa.serial(),
+            _ => unreachable!(),
+        };
+        let today = config::SerialPolicy::Date.initial();
+        let response = query(&mut server, "et.internal", rr::RecordType::SOA).await?;
+        assert_eq!(serial(&response), today);
+
+        let api: rr::Record = record(RecordType::A, "api.et.internal", "10.0.0.2")?.try_into()?;
+        server.add_record(api.clone()).await?;
+        server.remove_record(&api).await?;
+        let response = query(&mut server, "et.internal", rr::RecordType::SOA).await?;
+        assert_eq!(serial(&response), today + 2);
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn restores_snapshots() -> Result<()> {
+        let config = |address: &str, zones: &[&str]| -> Result<RunConfig> {
+            let mut zones: config::Zone = zones
+                .iter()
+                .map(|zone| (zone.to_string(), ZoneConfig::default()))
+                .collect();
+            zones.insert(
+                "et.internal".to_string(),
+                vec![record(RecordType::A, "www.et.internal", address)?].into(),
+            );
+            Ok(RunConfigBuilder::default()
+                .general(
+                    GeneralConfigBuilder::default()
+                        .listen_udp("127.0.0.1:0")
+                        .build()?,
+                )
+                .zones(zones)
+                .build()?)
+        };
+
+        let mut server = Server::new(config("10.0.0.1", &[])?);
+        server.run().await?;
+        let api: rr::Record = record(RecordType::A, "api.et.internal", "10.0.0.2")?.try_into()?;
+        server.add_record(api.clone()).await?;
+        let snapshot = serde_json::to_string(&server.snapshot().await)?;
+
+        server.reload(config("10.0.0.3", &["et.top"])?).await?;
+        let response = query(&mut server, "api.et.internal", rr::RecordType::A).await?;
+        assert_eq!(response.response_code(), ResponseCode::NXDomain);
+        let response = query(&mut server, "et.internal", rr::RecordType::SOA).await?;
+        let serial = response.answers()[0]
+            .data()
+            .unwrap()
+            .as_soa()
+            .unwrap()
+            .serial();
+
+        server.restore(serde_json::from_str(&snapshot)?).await?;
+        let response = query(&mut server, "api.et.internal", rr::RecordType::A).await?;
+        assert_eq!(response.answers(), std::slice::from_ref(&api));
+        let response = query(&mut server, "www.et.internal", rr::RecordType::A).await?;
+        assert_eq!(
+            response.answers()[0].data().unwrap().to_string(),
+            "10.0.0.1"
+        );
+        assert!(!server.contains(&LowerName::from_str("et.top")?).await);
+        let response = query(&mut server, "et.internal", rr::RecordType::SOA).await?;
+        let restored = response.answers()[0]
+            .data()
+            .unwrap()
+            .as_soa()
+            .unwrap()
+            .serial();
+        assert!(restored > serial);
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn can_register_services() -> Result<()> {
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "local".to_string() => vec![
+                    record(RecordType::A, "printer", "192.168.1.20")?,
+                ].into(),
+            })
+            .build()?;
+
+        let mut server = Server::new(config);
+        server.run().await?;
+
+        let local = rr::Name::from_str("local.")?;
+        let service = |instance: &str| {
+            ServiceBuilder::default()
+                .instance(instance)
+                .service_type("_ipp._tcp")
+                .host("printer")
+                .port(631u16)
+                .build()
+        };
+        let (office, lab) = (service("Office")?, service("Lab")?);
+        assert!(server.register_service(&local, &office).await?);
+        assert!(server.register_service(&local, &lab).await?);
+        assert!(!server.register_service(&local, &lab).await?);
+        let response = query(&mut server, "_ipp._tcp.local.", rr::RecordType::PTR).await?;
+        assert_eq!(response.answers().len(), 2);
+        let response = query(&mut server, "Office._ipp._tcp.local.", rr::RecordType::SRV).await?;
+        assert_eq!(
+            response.answers()[0].data(),
+            Some(&rr::RData::SRV(rr::rdata::SRV::new(
+                0,
+                0,
+                631,
+                rr::Name::from_str("printer.local.")?
+            )))
+        );
+
+        // the service type is enumerated while one of its instances is left
+        let services = "_services._dns-sd._udp.local.";
+        assert!(server.unregister_service(&local, &office).await?);
+        let response = query(&mut server, services, rr::RecordType::PTR).await?;
+        assert_eq!(response.answers().len(), 1);
+        assert!(server.unregister_service(&local, &lab).await?);
+        assert!(!server.unregister_service(&local, &lab).await?);
+        let response = query(&mut server, services, rr::RecordType::PTR).await?;
+        assert!(response.answers().is_empty());
+
+        let outside = rr::Name::from_str("et.top.")?;
+        assert!(matches!(
+            server.register_service(&outside, &office).await,
+            Err(Error::Zone(_))
+        ));
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reloads_zones_from_watched_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("config.toml");
+        let config = |address: &str| {
+            format!(
+                r#"
+[general]
+listen_udp = "127.0.0.1:0"
+watch_config = true
+watch_interval = "10ms"
+
+[[zones."et.internal"]]
+type = "A"
+name = "www.et.internal"
+value = "{address}"
+ttl = "60s"
+"#
+            )
+        };
+        std::fs::write(&path, config("10.0.0.1"))?;
+
+        let mut server = Server::new(toml::from_str(&config("10.0.0.1"))?);
+        server.run().await?;
+        server.watch(&path);
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        std::fs::write(&path, config("10.0.0.2"))?;
+        let mut answer = String::new();
+        for _ in 0..100 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            let response = query(&mut server, "www.et.internal", rr::RecordType::A).await?;
+            answer = response.answers()[0].data().unwrap().to_string();
+            if answer == "10.0.0.2" {
+                break;
+            }
+        }
+        assert_eq!(answer, "10.0.0.2");
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn can_resolve_auto_reverse_records() -> Result<()> {
+        let configured_record = record(RecordType::A, "www.et.internal", "10.0.1.2")?;
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.internal".to_string() => config::ZoneConfigBuilder::default()
+                    .records(vec![configured_record])
+                    .auto_reverse(true)
+                    .build()?,
+            })
+            .build()?;
+
+        let mut server = Server::new(config);
+        server.run().await?;
+        let response = query(&mut server, "2.1.0.10.in-addr.arpa", rr::RecordType::PTR).await?;
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(
+            response.answers()[0].data().and_then(|d| d.as_ptr()),
+            Some(&rr::rdata::PTR(rr::Name::from_str("www.et.internal")?))
+        );
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn can_resolve_records_over_tcp() -> Result<()> {
+        let configured_record = record(RecordType::A, "www.et.internal", "123.123.123.123")?;
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_tcp("127.0.0.1:0")
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.internal".to_string() => vec![configured_record.clone()].into(),
+            })
+            .build()?;
+
+        let mut server = Server::new(config);
+        server.run().await?;
+        assert!(server.udp_local_addr().is_none());
+
+        let local_addr = server.tcp_local_addr().unwrap();
+        let (stream, sender) = TcpClientStream::<AsyncIoTokioAsStd<TcpStream>>::new(local_addr);
+        let (mut client, background) = AsyncClient::new(stream, sender, None).await?;
+        let background_task = tokio::spawn(background);
+        let response = client
+            .query(
+                rr::Name::from_str("www.et.internal")?,
+                rr::DNSClass::IN,
+                rr::RecordType::A,
+            )
+            .await?;
+        drop(background_task);
+
+        let expected_record: rr::Record = configured_record.try_into()?;
+        assert_eq!(response.answers(), &[expected_record]);
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn transfers_zones_to_allowed_clients() -> Result<()> {
+        let records = (0..500)
+            .map(|i| record(RecordType::A, &format!("host-{i}.et.internal"), "10.0.0.1"))
+            .collect::<Result<Vec<_>>>()?;
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .listen_tcp("127.0.0.1:0")
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.internal".to_string() => config::ZoneConfigBuilder::default()
+                    .records(records)
+                    .allow_transfer(vec!["127.0.0.0/8".to_string()])
+                    .build()?,
+                "private.internal".to_string() => vec![
+                    record(RecordType::A, "www.private.internal", "10.0.0.2")?,
+                ].into(),
+            })
+            .build()?;
+
+        let mut server = Server::new(config);
+        server.run().await?;
+
+        let local_addr = server.tcp_local_addr().unwrap();
+        let (stream, sender) = TcpClientStream::<AsyncIoTokioAsStd<TcpStream>>::new(local_addr);
+        let (mut client, background) = AsyncClient::new(stream, sender, None).await?;
+        let background_task = tokio::spawn(background);
+
+        let responses: Vec<_> = client
+            .zone_transfer(rr::Name::from_str("et.internal.")?, None)
+            .collect()
+            .await;
+        let mut transferred = Vec::new();
+        for response in responses {
+            let response = response?;
+            assert_eq!(response.response_code(), ResponseCode::NoError);
+            assert!(response.authoritative());
+            transferred.extend(response.answers().iter().cloned());
+        }
+        // 500 A records, the synthesized NS and the SOA at both ends
+        assert_eq!(transferred.len(), 503);
+        assert_eq!(transferred[0].record_type(), rr::RecordType::SOA);
+        assert_eq!(transferred[502].record_type(), rr::RecordType::SOA);
+
+        let refused = client
+            .query(
+                rr::Name::from_str("private.internal.")?,
+                rr::DNSClass::IN,
+                rr::RecordType::AXFR,
+            )
+            .await?;
+        assert_eq!(refused.response_code(), ResponseCode::Refused);
+        drop(background_task);
+
+        let response = query(&mut server, "et.internal.", rr::RecordType::AXFR).await?;
+        assert_eq!(response.response_code(), ResponseCode::Refused);
+        assert!(response.answers().is_empty());
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    /// Polls `server` until the first A record answered for `name` is
+    /// `expected`.
+    async fn wait_for_answer(
+        server: &mut Server,
+        name: &str,
+        expected: Option<&str>,
+    ) -> Result<()> {
+        for _ in 0..100 {
+            let response = query(server, name, rr::RecordType::A).await?;
+            let answer = response.answers().first().and_then(|r| r.data());
+            if answer.map(|d| d.to_string()).as_deref() == expected {
+                return Ok(());
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        anyhow::bail!("{} was not answered with {:?}", name, expected)
+    }
+
+    #[tokio::test]
+    async fn serves_secondary_zones_from_primaries() -> Result<()> {
+        let primary_zone = |serial: u32, address: &str| -> Result<config::Zone> {
+            Ok(hashmap! {
+                "et.internal".to_string() => config::ZoneConfigBuilder::default()
+                    .records(vec![record(RecordType::A, "www.et.internal", address)?])
+                    .soa(
+                        config::SoaConfigBuilder::default()
+                            .serial(serial)
+                            .refresh(Duration::from_secs(1))
+                            .retry(Duration::from_secs(1))
+                            .expire(Duration::from_secs(2))
+                            .build()?,
+                    )
+                    .allow_transfer(vec!["127.0.0.1".to_string()])
+                    .build()?,
+            })
+        };
+        let mut primary = Server::new(
+            RunConfigBuilder::default()
+                .general(
+                    GeneralConfigBuilder::default()
+                        .listen_tcp("127.0.0.1:0")
+                        .build()?,
+                )
+                .zones(primary_zone(1, "10.0.0.1")?)
+                .build()?,
+        );
+        primary.run().await?;
+
+        let mut secondary = Server::new(
+            RunConfigBuilder::default()
+                .general(
+                    GeneralConfigBuilder::default()
+                        .listen_udp("127.0.0.1:0")
+                        .build()?,
+                )
+                .zones(hashmap! {
+                    "et.internal".to_string() => config::ZoneConfigBuilder::default()
+                        .zone_type(config::ZoneType::Secondary)
+                        .primaries(vec![primary.tcp_local_addr().unwrap().to_string()])
+                        .build()?,
+                })
+                .build()?,
+        );
+        secondary.run().await?;
+
+        wait_for_answer(&mut secondary, "www.et.internal", Some("10.0.0.1")).await?;
+        let response = query(&mut secondary, "www.et.internal", rr::RecordType::A).await?;
+        assert!(response.authoritative());
+        assert_eq!(
+            secondary
+                .add_record(rr::Record::from_rdata(
+                    rr::Name::from_str("api.et.internal")?,
+                    60,
+                    rr::RData::A("10.0.0.3".parse()?),
+                ))
+                .await,
+            Err(RecordError::ReadOnly(rr::Name::from_str("et.internal.")?))
+        );
+
+        primary
+            .reload(
+                RunConfigBuilder::default()
+                    .general(GeneralConfigBuilder::default().build()?)
+                    .zones(primary_zone(2, "10.0.0.2")?)
+                    .build()?,
+            )
+            .await?;
+        wait_for_answer(&mut secondary, "www.et.internal", Some("10.0.0.2")).await?;
+
+        primary.shutdown().await?;
+        wait_for_answer(&mut secondary, "www.et.internal", None).await?;
+        let response = query(&mut secondary, "www.et.internal", rr::RecordType::A).await?;
+        assert_eq!(response.response_code(), ResponseCode::Refused);
+
+        secondary.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn provisions_member_zones_of_catalogs() -> Result<()> {
+        let primary_zones = |serial: u32, members: &[&str]| -> Result<config::Zone> {
+            let mut zones = hashmap! {
+                "catalog.et.internal".to_string() => config::ZoneConfigBuilder::default()
+                    .catalog(true)
+                    .soa(
+                        config::SoaConfigBuilder::default()
+                            .serial(serial)
+                            .refresh(Duration::from_secs(1))
+                            .retry(Duration::from_secs(1))
+                            .build()?,
+                    )
+                    .allow_transfer(vec!["127.0.0.1".to_string()])
+                    .build()?,
+            };
+            for member in members {
+                zones.insert(
+                    member.to_string(),
+                    config::ZoneConfigBuilder::default()
+                        .records(vec![record(
+                            RecordType::A,
+                            &format!("www.{}", member),
+                            "10.0.0.1",
+                        )?])
+                        .allow_transfer(vec!["127.0.0.1".to_string()])
+                        .build()?,
+                );
+            }
+            Ok(zones)
+        };
+        let mut primary = Server::new(
+            RunConfigBuilder::default()
+                .general(
+                    GeneralConfigBuilder::default()
+                        .listen_tcp("127.0.0.1:0")
+                        .build()?,
+                )
+                .zones(primary_zones(1, &["et.internal", "et.top"])?)
+                .build()?,
+        );
+        primary.run().await?;
+
+        let mut secondary = Server::new(
+            RunConfigBuilder::default()
+                .general(
+                    GeneralConfigBuilder::default()
+                        .listen_udp("127.0.0.1:0")
+                        .build()?,
+                )
+                .zones(hashmap! {
+                    "catalog.et.internal".to_string() => config::ZoneConfigBuilder::default()
+                        .zone_type(config::ZoneType::Secondary)
+                        .catalog(true)
+                        .primaries(vec![primary.tcp_local_addr().unwrap().to_string()])
+                        .build()?,
+                })
+                .build()?,
+        );
+        secondary.run().await?;
+        wait_for_answer(&mut secondary, "www.et.internal", Some("10.0.0.1")).await?;
+        wait_for_answer(&mut secondary, "www.et.top", Some("10.0.0.1")).await?;
+        let response = query(&mut secondary, "www.et.top", rr::RecordType::A).await?;
+        assert!(response.authoritative());
+
+        // members dropped from the catalog are no longer served
+        primary
+            .reload(
+                RunConfigBuilder::default()
+                    .general(GeneralConfigBuilder::default().build()?)
+                    .zones(primary_zones(2, &["et.internal"])?)
+                    .build()?,
+            )
+            .await?;
+        wait_for_answer(&mut secondary, "www.et.top", None).await?;
+        let response = query(&mut secondary, "www.et.internal", rr::RecordType::A).await?;
+        assert_eq!(response.answers().len(), 1);
+
+        primary.shutdown().await?;
+        secondary.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn notifies_secondaries_of_changes() -> Result<()> {
+        let primary_config = |notify: Vec<String>| -> Result<config::RunConfig> {
+            Ok(RunConfigBuilder::default()
+                .general(
+                    GeneralConfigBuilder::default()
+                        .listen_tcp("127.0.0.1:0")
+                        .build()?,
+                )
+                .zones(hashmap! {
+                    "et.internal".to_string() => config::ZoneConfigBuilder::default()
+                        .records(vec![record(RecordType::A, "www.et.internal", "10.0.0.1")?])
+                        .allow_transfer(vec!["127.0.0.1".to_string()])
+                        .notify(notify)
+                        .build()?,
+                })
+                .build()?)
+        };
+        let mut primary = Server::new(primary_config(vec![])?);
+        primary.run().await?;
+
+        let mut secondary = Server::new(
+            RunConfigBuilder::default()
+                .general(
+                    GeneralConfigBuilder::default()
+                        .listen_udp("127.0.0.1:0")
+                        .build()?,
+                )
+                .zones(hashmap! {
+                    "et.internal".to_string() => config::ZoneConfigBuilder::default()
+                        .zone_type(config::ZoneType::Secondary)
+                        .primaries(vec![primary.tcp_local_addr().unwrap().to_string()])
+                        .build()?,
+                })
+                .build()?,
+        );
+        secondary.run().await?;
+        wait_for_answer(&mut secondary, "www.et.internal", Some("10.0.0.1")).await?;
+
+        // the SOA refresh timer is an hour, only a NOTIFY gets the change over
+        let secondary_addr = secondary.udp_local_addr().unwrap();
+        primary
+            .reload(primary_config(vec![secondary_addr.to_string()])?)
+            .await?;
+        assert!(
+            primary
+                .add_record(rr::Record::from_rdata(
+                    rr::Name::from_str("api.et.internal")?,
+                    60,
+                    rr::RData::A("10.0.0.3".parse()?),
+                ))
+                .await?
+        );
+        wait_for_answer(&mut secondary, "api.et.internal", Some("10.0.0.3")).await?;
+
+        let stream =
+            UdpClientStream::<UdpSocket>::with_timeout(secondary_addr, Duration::from_secs(5));
+        let (mut client, background) = AsyncClient::connect(stream).await?;
+        let background_task = tokio::spawn(background);
+        let response = client
+            .notify(
+                rr::Name::from_str("et.top.")?,
+                rr::DNSClass::IN,
+                rr::RecordType::SOA,
+                None::<rr::RecordSet>,
+            )
+            .await?;
+        assert_eq!(response.response_code(), ResponseCode::Refused);
+        drop(background_task);
+
+        primary.shutdown().await?;
+        secondary.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn authenticates_transfers_with_tsig() -> Result<()> {
+        let key = |secret: &str| {
+            config::TsigKeyConfigBuilder::default()
+                .name("transfer")
+                .secret(secret)
+                .build()
+        };
+        let records = (0..500)
+            .map(|i| record(RecordType::A, &format!("host-{i}.et.internal"), "10.0.0.1"))
+            .collect::<Result<Vec<_>>>()?;
+        let mut primary = Server::new(
+            RunConfigBuilder::default()
+                .general(
+                    GeneralConfigBuilder::default()
+                        .listen_tcp("127.0.0.1:0")
+                        .build()?,
+                )
+                .keys(vec![key("c2VjcmV0")?])
+                .zones(hashmap! {
+                    "et.internal".to_string() => config::ZoneConfigBuilder::default()
+                        .records(records)
+                        .allow_transfer(vec!["key transfer".to_string()])
+                        .build()?,
+                })
+                .build()?,
+        );
+        primary.run().await?;
+        let primary_addr = primary.tcp_local_addr().unwrap();
+
+        let mut secondary = Server::new(
+            RunConfigBuilder::default()
+                .general(
+                    GeneralConfigBuilder::default()
+                        .listen_udp("127.0.0.1:0")
+                        .build()?,
+                )
+                .keys(vec![key("c2VjcmV0")?])
+                .zones(hashmap! {
+                    "et.internal".to_string() => config::ZoneConfigBuilder::default()
+                        .zone_type(config::ZoneType::Secondary)
+                        .primaries(vec![primary_addr.to_string()])
+                        .key("transfer")
+                        .build()?,
+                })
+                .build()?,
+        );
+        secondary.run().await?;
+        wait_for_answer(&mut secondary, "host-499.et.internal", Some("10.0.0.1")).await?;
+
+        // every message of a transfer spanning several is signed
+        let zone = rr::Name::from_str("et.internal.")?;
+        let signer = |secret: &str| -> Result<_> {
+            let keyring = crate::tsig::Keyring::new(&[key(secret)?])?;
+            Ok(keyring.get(&LowerName::from_str("transfer.")?).cloned())
+        };
+        match crate::secondary::fetch(primary_addr, &zone, None, signer("c2VjcmV0")?).await? {
+            crate::secondary::Transfer::Full(records) => assert_eq!(records.len(), 502),
+            transfer => panic!("unexpected {:?}", transfer),
+        }
+
+        // unsigned requests and requests signed with another secret are refused
+        assert!(crate::secondary::fetch(primary_addr, &zone, None, None)
+            .await
+            .is_err());
+        assert!(
+            crate::secondary::fetch(primary_addr, &zone, None, signer("b3RoZXI=")?)
+                .await
+                .is_err()
+        );
+
+        secondary.shutdown().await?;
+        primary.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn applies_dynamic_updates_from_allowed_clients() -> Result<()> {
+        let mut server = Server::new(
+            RunConfigBuilder::default()
+                .general(
+                    GeneralConfigBuilder::default()
+                        .listen_udp("127.0.0.1:0")
+                        .listen_tcp("127.0.0.1:0")
+                        .build()?,
+                )
+                .keys(vec![config::TsigKeyConfigBuilder::default()
+                    .name("update")
+                    .secret("c2VjcmV0")
+                    .build()?])
+                .zones(hashmap! {
+                    "et.internal".to_string() => config::ZoneConfigBuilder::default()
+                        .records(vec![record(RecordType::A, "www.et.internal", "10.0.0.1")?])
+                        .allow_update(vec!["key update".to_string()])
+                        .build()?,
+                })
+                .build()?,
+        );
+        server.run().await?;
+
+        let local_addr = server.tcp_local_addr().unwrap();
+        let connect = |signer: Option<TSigner>| {
+            let (stream, sender) = TcpClientStream::<AsyncIoTokioAsStd<TcpStream>>::new(local_addr);
+            AsyncClient::new(stream, sender, signer.map(|s| Arc::new(s.into())))
+        };
+        let signer = TSigner::new(
+            b"secret".to_vec(),
+            TsigAlgorithm::HmacSha256,
+            rr::Name::from_str("update.")?,
+            300,
+        )?;
+        let (mut client, background) = connect(Some(signer)).await?;
+        let background_task = tokio::spawn(background);
+        let zone = rr::Name::from_str("et.internal.")?;
+        let api = rr::Record::from_rdata(
+            rr::Name::from_str("api.et.internal.")?,
+            60,
+            rr::RData::A("10.0.0.2".parse()?),
+        );
+
+        let response = client.create(api.clone(), zone.clone()).await?;
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        let response = query(&mut server, "api.et.internal.", rr::RecordType::A).await?;
+        assert_eq!(response.answers()[0].data(), api.data());
+
+        // the rrset exists now, so the prerequisite of another create fails
+        let response = client.create(api.clone(), zone.clone()).await?;
+        assert_eq!(response.response_code(), ResponseCode::YXRRSet);
+
+        let response = client.delete_by_rdata(api, zone.clone()).await?;
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        let response = query(&mut server, "api.et.internal.", rr::RecordType::A).await?;
+        assert_eq!(response.response_code(), ResponseCode::NXDomain);
+        drop(background_task);
+
+        let (mut client, background) = connect(None).await?;
+        let background_task = tokio::spawn(background);
+        let www = rr::Record::from_rdata(
+            rr::Name::from_str("www.et.internal.")?,
+            60,
+            rr::RData::A("10.0.0.3".parse()?),
+        );
+        let response = client.append(www, zone, true).await?;
+        assert_eq!(response.response_code(), ResponseCode::Refused);
+        drop(background_task);
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn restores_persisted_zones_on_restart() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let persist_file = dir.path().join("db.et.internal");
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.internal".to_string() => config::ZoneConfigBuilder::default()
+                    .records(vec![record(RecordType::A, "www.et.internal", "10.0.0.1")?])
+                    .persist_file(&persist_file)
+                    .build()?,
+            })
+            .build()?;
+
+        let mut server = Server::new(config.clone());
+        server.run().await?;
+        assert!(!persist_file.exists());
+        server
+            .add_record(rr::Record::from_rdata(
+                rr::Name::from_str("api.et.internal.")?,
+                60,
+                rr::RData::A("10.0.0.2".parse()?),
+            ))
+            .await?;
+        let serial = query(&mut server, "et.internal.", rr::RecordType::SOA)
+            .await?
+            .answers()[0]
+            .data()
+            .and_then(rr::RData::as_soa)
+            .map(|soa| soa.serial());
+        server.shutdown().await?;
+        assert!(persist_file.exists());
+
+        let mut server = Server::new(config);
+        server.run().await?;
+        wait_for_answer(&mut server, "api.et.internal", Some("10.0.0.2")).await?;
+        wait_for_answer(&mut server, "www.et.internal", Some("10.0.0.1")).await?;
+        let response = query(&mut server, "et.internal.", rr::RecordType::SOA).await?;
+        assert_eq!(
+            response.answers()[0]
+                .data()
+                .and_then(rr::RData::as_soa)
+                .map(|soa| soa.serial()),
+            serial
+        );
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    async fn query_dnssec(
+        server: &mut Server,
+        name: &str,
+        rr_type: rr::RecordType,
+    ) -> Result<hickory_proto::xfer::DnsResponse> {
+        let local_addr = server.udp_local_addr().unwrap();
+        let stream = UdpClientStream::<UdpSocket>::with_timeout(local_addr, Duration::from_secs(5));
+        let (client, background) = AsyncClient::connect(stream).await?;
+        let background_task = tokio::spawn(background);
+        let mut edns = Edns::new();
+        edns.set_max_payload(4096).set_dnssec_ok(true);
+        let mut message = Message::new();
+        message
+            .add_query(Query::query(rr::Name::from_str(name)?, rr_type))
+            .set_recursion_desired(true)
+            .set_edns(edns);
+        let response = client
+            .send(message)
+            .next()
+            .await
+            .ok_or_else(|| anyhow::anyhow!("no response"))??;
+        drop(background_task);
+        Ok(response)
+    }
+
+    #[tokio::test]
+    async fn signs_zones_with_dnssec() -> Result<()> {
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.internal".to_string() => config::ZoneConfigBuilder::default()
+                    .records(vec![record(RecordType::A, "www.et.internal", "10.0.0.1")?])
+                    .dnssec(config::DnssecConfigBuilder::default().build()?)
+                    .build()?,
+            })
+            .build()?;
+        let mut server = Server::new(config);
+        server.run().await?;
+
+        let response = query_dnssec(&mut server, "et.internal.", rr::RecordType::DNSKEY).await?;
+        let zsk = response
+            .answers()
+            .iter()
+            .filter_map(|r| r.data()?.as_dnssec()?.as_dnskey())
+            .find(|dnskey| !dnskey.secure_entry_point())
+            .cloned()
+            .unwrap();
+        let verify = |response: &hickory_proto::xfer::DnsResponse, rr_type| -> Result<()> {
+            let records: Vec<rr::Record> = response
+                .answers()
+                .iter()
+                .filter(|r| r.record_type() == rr_type)
+                .cloned()
+                .collect();
+            let rrsig = response
+                .answers()
+                .iter()
+                .find_map(|r| r.data()?.as_dnssec()?.as_rrsig())
+                .ok_or_else(|| anyhow::anyhow!("no RRSIG for {}", rr_type))?;
+            zsk.verify_rrsig(records[0].name(), rr::DNSClass::IN, rrsig, &records)?;
+            Ok(())
+        };
+
+        let response = query_dnssec(&mut server, "www.et.internal.", rr::RecordType::A).await?;
+        verify(&response, rr::RecordType::A)?;
+        let response = query(&mut server, "www.et.internal.", rr::RecordType::A).await?;
+        assert_eq!(response.answers().len(), 1);
+
+        let response = query_dnssec(&mut server, "nope.et.internal.", rr::RecordType::A).await?;
+        assert_eq!(response.response_code(), ResponseCode::NXDomain);
+        let types: Vec<_> = response
+            .name_servers()
+            .iter()
+            .map(|r| r.record_type())
+            .collect();
+        assert!(types.contains(&rr::RecordType::SOA));
+        assert!(types.contains(&rr::RecordType::NSEC));
+        assert!(types.contains(&rr::RecordType::RRSIG));
+
+        // runtime changes are signed and chained
+        server
+            .add_record(rr::Record::from_rdata(
+                rr::Name::from_str("nope.et.internal.")?,
+                60,
+                rr::RData::A("10.0.0.2".parse()?),
+            ))
+            .await?;
+        let response = query_dnssec(&mut server, "nope.et.internal.", rr::RecordType::A).await?;
+        verify(&response, rr::RecordType::A)?;
+        let response = query_dnssec(&mut server, "nope.et.internal.", rr::RecordType::NSEC).await?;
+        let nsec = response
+            .answers()
+            .iter()
+            .find_map(|r| r.data()?.as_dnssec()?.as_nsec())
+            .unwrap();
+        assert_eq!(nsec.next_domain_name().to_string(), "www.et.internal.");
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn denies_with_nsec3() -> Result<()> {
+        let nsec3 = config::Nsec3ConfigBuilder::default()
+            .salt("aabbccdd")
+            .build()?;
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.internal".to_string() => config::ZoneConfigBuilder::default()
+                    .records(vec![
+                        record(RecordType::A, "www.et.internal", "10.0.0.1")?,
+                        record(RecordType::A, "a.b.et.internal", "10.0.0.2")?,
+                    ])
+                    .dnssec(config::DnssecConfigBuilder::default().nsec3(nsec3).build()?)
+                    .build()?,
+            })
+            .build()?;
+        let mut server = Server::new(config);
+        server.run().await?;
+
+        let types = |response: &hickory_proto::xfer::DnsResponse| -> Vec<rr::RecordType> {
+            response
+                .name_servers()
+                .iter()
+                .map(|r| r.record_type())
+                .collect()
+        };
+        let response =
+            query_dnssec(&mut server, "et.internal.", rr::RecordType::NSEC3PARAM).await?;
+        assert_eq!(response.answers().len(), 2);
+        let response = query_dnssec(&mut server, "nope.et.internal.", rr::RecordType::A).await?;
+        assert_eq!(response.response_code(), ResponseCode::NXDomain);
+        let nxdomain = types(&response);
+        assert!(nxdomain.contains(&rr::RecordType::NSEC3));
+        assert!(!nxdomain.contains(&rr::RecordType::NSEC));
+        // no data, also at the empty non-terminal b: the matching NSEC3
+        for name in ["www.et.internal.", "b.et.internal."] {
+            let response = query_dnssec(&mut server, name, rr::RecordType::TXT).await?;
+            assert_eq!(response.response_code(), ResponseCode::NoError);
+            assert_eq!(
+                types(&response),
+                [
+                    rr::RecordType::SOA,
+                    rr::RecordType::RRSIG,
+                    rr::RecordType::NSEC3,
+                    rr::RecordType::RRSIG
+                ]
+            );
+        }
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn can_resolve_records_over_tls() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cert = rcgen::generate_simple_self_signed(vec!["dns.et.internal".to_string()])?;
+        let cert_path = dir.path().join("cert.pem");
+        let key_path = dir.path().join("key.pem");
+        std::fs::write(&cert_path, cert.serialize_pem()?)?;
+        std::fs::write(&key_path, cert.serialize_private_key_pem())?;
+
+        let configured_record = record(RecordType::A, "www.et.internal", "123.123.123.123")?;
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_tls(
+                        config::TlsListenConfigBuilder::default()
+                            .address("127.0.0.1:0")
+                            .cert(cert_path)
+                            .key(key_path)
+                            .build()?,
+                    )
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.internal".to_string() => vec![configured_record.clone()].into(),
+            })
+            .build()?;
+
+        let mut server = Server::new(config);
+        server.run().await?;
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(&rustls::Certificate(cert.serialize_der()?))?;
+        let client_config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let (stream, sender) = tls_client_connect::<AsyncIoTokioAsStd<TcpStream>>(
+            server.tls_local_addr().unwrap(),
+            "dns.et.internal".to_string(),
+            Arc::new(client_config),
+        );
+        let (mut client, background) = AsyncClient::new(stream, sender, None).await?;
+        let background_task = tokio::spawn(background);
+        let response = client
+            .query(
+                rr::Name::from_str("www.et.internal")?,
+                rr::DNSClass::IN,
+                rr::RecordType::A,
+            )
+            .await?;
+        drop(background_task);
+
+        let expected_record: rr::Record = configured_record.try_into()?;
+        assert_eq!(response.answers(), &[expected_record]);
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[cfg(feature = "doq")]
+    #[tokio::test]
+    async fn can_resolve_records_over_quic() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cert = rcgen::generate_simple_self_signed(vec!["dns.et.internal".to_string()])?;
+        let cert_path = dir.path().join("cert.pem");
+        let key_path = dir.path().join("key.pem");
+        std::fs::write(&cert_path, cert.serialize_pem()?)?;
+        std::fs::write(&key_path, cert.serialize_private_key_pem())?;
+
+        let configured_record = record(RecordType::A, "www.et.internal", "123.123.123.123")?;
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_tls(
+                        config::TlsListenConfigBuilder::default()
+                            .address("127.0.0.1:0")
+                            .cert(cert_path)
+                            .key(key_path)
+                            .build()?,
+                    )
+                    .listen_quic("127.0.0.1:0")
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.internal".to_string() => vec![configured_record.clone()].into(),
+            })
+            .build()?;
+
+        let mut server = Server::new(config);
+        server.run().await?;
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(&rustls::Certificate(cert.serialize_der()?))?;
+        let client_config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let mut builder = hickory_proto::quic::QuicClientStream::builder();
+        builder.crypto_config(client_config);
+        let stream = builder.build(
+            server.quic_local_addr().unwrap(),
+            "dns.et.internal".to_string(),
+        );
+        let (mut client, background) = AsyncClient::connect(stream).await?;
+        let background_task = tokio::spawn(background);
+        let response = client
+            .query(
+                rr::Name::from_str("www.et.internal")?,
+                rr::DNSClass::IN,
+                rr::RecordType::A,
+            )
+            .await?;
+        drop(background_task);
+
+        let expected_record: rr::Record = configured_record.try_into()?;
+        assert_eq!(response.answers(), &[expected_record]);
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn quic_requires_tls_certificate() -> Result<()> {
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_quic("127.0.0.1:0")
+                    .build()?,
+            )
+            .build()?;
+        let mut server = Server::new(config);
+        assert!(server.run().await.is_err());
+        Ok(())
+    }
+
+    /// Starts an authoritative server for `et.top` listening on UDP and TCP
+    /// on the same port.
+    async fn start_upstream(records: Vec<config::Record>) -> Result<(Server, SocketAddr)> {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")?
+            .local_addr()?
+            .port();
+        let address = format!("127.0.0.1:{}", port);
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp(address.clone())
+                    .listen_tcp(address.clone())
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.top".to_string() => records.into(),
+            })
+            .build()?;
+        let mut upstream = Server::new(config);
+        upstream.run().await?;
+        Ok((upstream, address.parse()?))
+    }
+
+    async fn start_forwarder(upstream: SocketAddr, zones: config::Zone) -> Result<Server> {
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .build()?,
+            )
+            .zones(zones)
+            .forward(
+                config::ForwardConfigBuilder::default()
+                    .upstreams(vec![upstream.to_string()])
+                    .build()?,
+            )
+            .build()?;
+        let mut server = Server::new(config);
+        server.run().await?;
+        Ok(server)
+    }
+
+    #[tokio::test]
+    async fn forwards_queries_outside_local_zones() -> Result<()> {
+        let target = record(RecordType::A, "www.et.top", "100.100.100.100")?;
+        let (mut upstream, address) = start_upstream(vec![target.clone()]).await?;
+        let mut server = start_forwarder(
+            address,
+            hashmap! {
+                "et.internal".to_string() => vec![
+                    record(RecordType::CNAME, "www.et.internal", "www.et.top")?,
+                ].into(),
+            },
+        )
+        .await?;
+
+        let response = query(&mut server, "www.et.top", rr::RecordType::A).await?;
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert!(response.recursion_available());
+        assert!(!response.authoritative());
+        let expected_record: rr::Record = target.try_into()?;
+        assert_eq!(response.answers(), std::slice::from_ref(&expected_record));
+
+        let response = query(&mut server, "www.et.internal", rr::RecordType::A).await?;
+        assert_eq!(response.answers().len(), 2);
+        assert_eq!(response.answers()[1], expected_record);
+
+        let response = query(&mut server, "nope.et.top", rr::RecordType::A).await?;
+        assert_eq!(response.response_code(), ResponseCode::NXDomain);
+
+        let response = query(&mut server, "www.et.top", rr::RecordType::A).await?;
+        assert_eq!(response.answers().len(), 1);
+        let stats = server.cache_stats().unwrap();
+        assert_eq!(stats.hits, 2);
+        assert_eq!(stats.misses, 2);
+        assert_eq!(stats.entries, 2);
+
+        server.shutdown().await?;
+        upstream.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn flattens_aliases() -> Result<()> {
+        let (mut upstream, address) = start_upstream(vec![
+            record(RecordType::A, "lb.et.top", "100.100.100.100")?,
+            record(RecordType::A, "lb.et.top", "100.100.100.101")?,
+        ])
+        .await?;
+        let alias = |name: &str, target: &str| {
+            config::AliasBuilder::default()
+                .name(name)
+                .target(target)
+                .build()
+        };
+        let mut server = start_forwarder(
+            address,
+            hashmap! {
+                "et.internal".to_string() => config::ZoneConfigBuilder::default()
+                    .records(vec![
+                        record(RecordType::MX, "et.internal", "10 mail.et.internal")?,
+                        record(RecordType::AAAA, "web.et.internal", "fd00::1")?,
+                    ])
+                    .aliases(vec![
+                        config::AliasBuilder::default().target("lb.et.top").build()?,
+                        alias("www", "web.et.internal")?,
+                    ])
+                    .build()?,
+            },
+        )
+        .await?;
+
+        // forwarded, at the apex next to its other records
+        let response = query(&mut server, "et.internal", rr::RecordType::A).await?;
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert!(response.authoritative());
+        let mut answers: Vec<String> = response.answers().iter().map(|r| r.to_string()).collect();
+        answers.sort();
+        assert_eq!(
+            answers,
+            [
+                "et.internal. 60 IN A 100.100.100.100",
+                "et.internal. 60 IN A 100.100.100.101"
+            ]
+        );
+        let response = query(&mut server, "et.internal", rr::RecordType::MX).await?;
+        assert_eq!(response.answers().len(), 1);
+
+        // looked up in the zones
+        let response = query(&mut server, "www.et.internal", rr::RecordType::AAAA).await?;
+        assert_eq!(
+            response.answers()[0].to_string(),
+            "www.et.internal. 60 IN AAAA fd00::1"
+        );
+        let response = query(&mut server, "www.et.internal", rr::RecordType::A).await?;
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert!(response.answers().is_empty());
+        assert_eq!(response.name_servers().len(), 1);
+
+        server.shutdown().await?;
+        upstream.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn retries_truncated_answers_over_tcp() -> Result<()> {
+        let records = (0..40)
+            .map(|i| record(RecordType::TXT, "big.et.top", &format!("{:0>100}", i)))
+            .collect::<Result<Vec<_>>>()?;
+        let (mut upstream, address) = start_upstream(records).await?;
+        let mut server = start_forwarder(address, HashMap::new()).await?;
+
+        let query =
+            hickory_proto::op::Query::query(rr::Name::from_str("big.et.top")?, rr::RecordType::TXT);
+        let forwarder = server.handler.forwarder.clone().unwrap();
+        let response = forwarder.forward(&query).await?;
+        assert!(!response.truncated());
+        assert_eq!(response.answers().len(), 40);
+
+        server.shutdown().await?;
+        upstream.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reports_unreachable_upstreams() -> Result<()> {
+        let upstream = UdpSocket::bind("127.0.0.1:0").await?.local_addr()?;
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .build()?,
+            )
+            .forward(
+                config::ForwardConfigBuilder::default()
+                    .upstreams(vec![upstream.to_string()])
+                    .timeout(Duration::from_millis(200))
+                    .build()?,
+            )
+            .build()?;
+        let mut server = Server::new(config);
+        server.run().await?;
+
+        let response = query_dnssec(&mut server, "www.et.top", rr::RecordType::A).await?;
+        assert_eq!(response.response_code(), ResponseCode::ServFail);
+        assert_eq!(extended_error(&response), Some(ede::NO_REACHABLE_AUTHORITY));
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    /// A forwarder whose queries stay in flight for `timeout`, the upstream
+    /// never answering.
+    async fn start_stalled_forwarder(
+        upstream: &UdpSocket,
+        timeout: Duration,
+        shutdown_timeout: Duration,
+    ) -> Result<Server> {
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .shutdown_timeout(shutdown_timeout)
+                    .build()?,
+            )
+            .forward(
+                config::ForwardConfigBuilder::default()
+                    .upstreams(vec![upstream.local_addr()?.to_string()])
+                    .timeout(timeout)
+                    .build()?,
+            )
+            .build()?;
+        let mut server = Server::new(config);
+        server.run().await?;
+        Ok(server)
+    }
+
+    async fn wait_in_flight(handle: &ServerHandle) {
+        while handle.in_flight() == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn drains_queries_in_flight_on_shutdown() -> Result<()> {
+        let upstream = UdpSocket::bind("127.0.0.1:0").await?;
+        let mut server = start_stalled_forwarder(
+            &upstream,
+            Duration::from_millis(500),
+            Duration::from_secs(5),
+        )
+        .await?;
+        let address = server.udp_local_addr().unwrap();
+        let handle = server.handle();
+        let pending = tokio::spawn(query_address(address, "www.et.top", rr::RecordType::A));
+        wait_in_flight(&handle).await;
+
+        let shutdown = tokio::spawn({
+            let handle = handle.clone();
+            async move { handle.shutdown().await }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let response = query_address(address, "www.et.top", rr::RecordType::A).await?;
+        assert_eq!(response.response_code(), ResponseCode::Refused);
+        assert!(!handle.is_shut_down());
+
+        assert_eq!(shutdown.await??, 0);
+        assert!(handle.is_shut_down());
+        let response = pending.await??;
+        assert_eq!(response.response_code(), ResponseCode::ServFail);
+
+        // queries outlasting the timeout are dropped
+        let mut server = start_stalled_forwarder(
+            &upstream,
+            Duration::from_secs(5),
+            Duration::from_millis(100),
+        )
+        .await?;
+        let address = server.udp_local_addr().unwrap();
+        let pending = tokio::spawn(query_address(address, "www.et.top", rr::RecordType::A));
+        wait_in_flight(&server.handle()).await;
+        assert_eq!(server.shutdown().await?, 1);
+        pending.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn blocks_until_shut_down() -> Result<()> {
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.internal".to_string() => vec![
+                    record(RecordType::A, "www.et.internal", "10.0.0.1")?,
+                ].into(),
+            })
+            .build()?;
+        let mut server = Server::new(config);
+        server.run().await?;
+        let address = server.udp_local_addr().unwrap();
+        let handle = server.handle();
+        let serving = tokio::spawn(async move { server.block_until_done().await });
+
+        let response = query_address(address, "www.et.internal", rr::RecordType::A).await?;
+        assert_eq!(response.answers().len(), 1);
+        assert!(!serving.is_finished());
+
+        handle.shutdown().await?;
+        tokio::time::timeout(Duration::from_secs(5), serving).await???;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn refuses_names_outside_local_zones() -> Result<()> {
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .build()?,
+            )
+            .build()?;
+        let mut server = Server::new(config);
+        server.run().await?;
+
+        let response = query_dnssec(&mut server, "www.et.top", rr::RecordType::A).await?;
+        assert_eq!(response.response_code(), ResponseCode::Refused);
+        assert_eq!(extended_error(&response), Some(ede::NOT_AUTHORITATIVE));
+        assert!(!response.recursion_available());
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    async fn start_validating_forwarder(
+        upstream: SocketAddr,
+        validation: config::DnssecValidation,
+        trust_anchors: Vec<String>,
+    ) -> Result<Server> {
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .build()?,
+            )
+            .forward(
+                config::ForwardConfigBuilder::default()
+                    .upstreams(vec![upstream.to_string()])
+                    .dnssec_validation(validation)
+                    .trust_anchors(trust_anchors)
+                    .build()?,
+            )
+            .build()?;
+        let mut server = Server::new(config);
+        server.run().await?;
+        Ok(server)
+    }
+
+    #[tokio::test]
+    async fn validates_forwarded_answers() -> Result<()> {
+        let address: SocketAddr = format!(
+            "127.0.0.1:{}",
+            std::net::TcpListener::bind("127.0.0.1:0")?
+                .local_addr()?
+                .port()
+        )
+        .parse()?;
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp(address.to_string())
+                    .listen_tcp(address.to_string())
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.top".to_string() => config::ZoneConfigBuilder::default()
+                    .records(vec![record(RecordType::A, "www.et.top", "10.0.0.1")?])
+                    .dnssec(config::DnssecConfigBuilder::default().build()?)
+                    .build()?,
+                "et.plain".to_string() => vec![
+                    record(RecordType::A, "www.et.plain", "10.0.0.2")?,
+                ].into(),
+            })
+            .build()?;
+        let mut upstream = Server::new(config);
+        upstream.run().await?;
+        let response = query_dnssec(&mut upstream, "et.top.", rr::RecordType::DNSKEY).await?;
+        let ksk = response
+            .answers()
+            .iter()
+            .find(|r| {
+                r.data()
+                    .and_then(|data| data.as_dnssec()?.as_dnskey())
+                    .is_some_and(|dnskey| dnskey.secure_entry_point())
+            })
+            .unwrap()
+            .to_string();
+        let mut server =
+            start_validating_forwarder(address, config::DnssecValidation::Strict, vec![ksk])
+                .await?;
+        let response = query_dnssec(&mut server, "www.et.top.", rr::RecordType::A).await?;
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert!(response.authentic_data());
+        assert_eq!(response.answers().len(), 2);
+        // without the DO bit, the signatures are left out
+        let response = query(&mut server, "www.et.top.", rr::RecordType::A).await?;
+        assert_eq!(response.answers().len(), 1);
+        let response = query_dnssec(&mut server, "nope.et.top.", rr::RecordType::A).await?;
+        assert_eq!(response.response_code(), ResponseCode::NXDomain);
+        assert!(response.authentic_data());
+        // zones outside the trust anchors are insecure
+        let response = query_dnssec(&mut server, "www.et.plain.", rr::RecordType::A).await?;
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert!(!response.authentic_data());
+        server.shutdown().await?;
+
+        // keys not matching the trust anchor make answers bogus
+        let wrong = format!("et.top. IN DS 1 13 2 {}", "00".repeat(32));
+        let mut server = start_validating_forwarder(
+            address,
+            config::DnssecValidation::Strict,
+            vec![wrong.clone()],
+        )
+        .await?;
+        let response = query_dnssec(&mut server, "www.et.top.", rr::RecordType::A).await?;
+        assert_eq!(response.response_code(), ResponseCode::ServFail);
+        assert!(response.answers().is_empty());
+        assert_eq!(extended_error(&response), Some(ede::DNSKEY_MISSING));
+        server.shutdown().await?;
+
+        let mut server =
+            start_validating_forwarder(address, config::DnssecValidation::Permissive, vec![wrong])
+                .await?;
+        let response = query_dnssec(&mut server, "www.et.top.", rr::RecordType::A).await?;
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert!(!response.authentic_data());
+        assert_eq!(extended_error(&response), None);
+        server.shutdown().await?;
+
+        upstream.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn forwards_by_domain_rule() -> Result<()> {
+        let target = record(RecordType::A, "www.et.top", "100.100.100.100")?;
+        let (mut upstream, address) = start_upstream(vec![target]).await?;
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .build()?,
+            )
+            .forward(
+                config::ForwardConfigBuilder::default()
+                    .rules(vec![config::ForwardRuleBuilder::default()
+                        .domain("et.top")
+                        .upstreams(vec![address.to_string()])
+                        .build()?])
+                    .build()?,
+            )
+            .build()?;
+        let mut server = Server::new(config);
+        server.run().await?;
+
+        let response = query(&mut server, "www.et.top", rr::RecordType::A).await?;
+        assert_eq!(response.answers().len(), 1);
+        let response = query(&mut server, "www.et.internal", rr::RecordType::A).await?;
+        assert_eq!(response.response_code(), ResponseCode::Refused);
+
+        server.shutdown().await?;
+        upstream.shutdown().await?;
+        Ok(())
+    }
 }
@@ -0,0 +1,718 @@
+use crate::config::{DnssecConfig, Nsec3Config};
+use anyhow::{bail, Context, Result};
+use data_encoding::{BASE32_DNSSEC, HEXLOWER_PERMISSIVE};
+use hickory_proto::rr::dnssec::rdata::{DNSSECRData, DNSKEY, DS, NSEC, NSEC3, NSEC3PARAM, RRSIG};
+use hickory_proto::rr::dnssec::{
+    tbs, Algorithm, DigestType, KeyFormat, Nsec3HashAlgorithm, SigSigner,
+};
+use hickory_proto::rr::{LowerName, Name, RData, Record, RecordSet, RecordType, RrKey};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+type Records = BTreeMap<RrKey, Arc<RecordSet>>;
+
+/// How far signatures are backdated, for validators whose clock is behind.
+const INCEPTION_OFFSET: u32 = 3600;
+
+/// Whether records of `record_type` are generated by signing a zone, rather
+/// than configured.
+pub(crate) fn is_generated(record_type: RecordType) -> bool {
+    matches!(
+        record_type,
+        RecordType::DNSKEY
+            | RecordType::NSEC
+            | RecordType::NSEC3
+            | RecordType::NSEC3PARAM
+            | RecordType::RRSIG
+    )
+}
+
+/// Whether `records` were signed with the keys of `signer`, or are unsigned
+/// when there is no signer.
+pub(crate) fn signed_with(records: &Records, signer: Option<&ZoneSigner>) -> bool {
+    let dnskeys: Vec<&DNSKEY> = records
+        .iter()
+        .filter(|(key, _)| key.record_type == RecordType::DNSKEY)
+        .flat_map(|(_, rrset)| rrset.records_without_rrsigs())
+        .filter_map(|r| r.data()?.as_dnssec()?.as_dnskey())
+        .collect();
+    let nsec3param = records
+        .iter()
+        .filter(|(key, _)| key.record_type == RecordType::NSEC3PARAM)
+        .flat_map(|(_, rrset)| rrset.records_without_rrsigs())
+        .find_map(|r| r.data()?.as_dnssec()?.as_nsec3param())
+        .cloned();
+    match signer {
+        Some(signer) => {
+            dnskeys == [&signer.ksk.dnskey, &signer.zsk.dnskey]
+                && nsec3param == signer.nsec3.as_ref().map(Nsec3Params::nsec3param)
+        }
+        None => dnskeys.is_empty(),
+    }
+}
+
+/// Keys of zones configured without key files, generated once and kept for
+/// the lifetime of the server so reloads don't change them.
+#[derive(Default)]
+pub(crate) struct GeneratedKeys {
+    /// PKCS#8 keys by zone, whether they are a KSK, and algorithm.
+    keys: Mutex<HashMap<GeneratedKey, Arc<Vec<u8>>>>,
+}
+
+type GeneratedKey = (LowerName, bool, Algorithm);
+
+impl GeneratedKeys {
+    fn get(&self, zone: &Name, ksk: bool, algorithm: Algorithm) -> Result<Arc<Vec<u8>>> {
+        let mut keys = self.keys.lock().unwrap();
+        let key = (LowerName::from(zone), ksk, algorithm);
+        if let Some(pkcs8) = keys.get(&key) {
+            return Ok(pkcs8.clone());
+        }
+        let pkcs8 = Arc::new(KeyFormat::Pkcs8.generate_and_encode(algorithm, None)?);
+        keys.insert(key, pkcs8.clone());
+        Ok(pkcs8)
+    }
+}
+
+struct ZoneKey {
+    dnskey: DNSKEY,
+    signer: SigSigner,
+    key_tag: u16,
+}
+
+/// Parameters of the NSEC3 chain of a zone (RFC 5155).
+struct Nsec3Params {
+    iterations: u16,
+    salt: Vec<u8>,
+    opt_out: bool,
+}
+
+impl Nsec3Params {
+    fn new(config: &Nsec3Config) -> Result<Self> {
+        let salt = match config.salt() {
+            "" | "-" => Vec::new(),
+            salt => HEXLOWER_PERMISSIVE
+                .decode(salt.as_bytes())
+                .context("NSEC3 salt is not valid hex")?,
+        };
+        if salt.len() > u8::MAX as usize {
+            bail!("NSEC3 salt is longer than 255 bytes");
+        }
+        Ok(Self {
+            iterations: config.iterations(),
+            salt,
+            opt_out: config.opt_out(),
+        })
+    }
+
+    fn hash(&self, name: &LowerName) -> Result<Vec<u8>> {
+        let digest = Nsec3HashAlgorithm::SHA1.hash(&self.salt, &name.into(), self.iterations)?;
+        Ok(digest.as_ref().to_vec())
+    }
+
+    /// The NSEC3PARAM record data, whose flags are always clear.
+    fn nsec3param(&self) -> NSEC3PARAM {
+        NSEC3PARAM::new(
+            Nsec3HashAlgorithm::SHA1,
+            false,
+            self.iterations,
+            self.salt.clone(),
+        )
+    }
+}
+
+/// Signs a zone with its KSK and ZSK (RFC 4033 to 4035): publishes the
+/// DNSKEY records, chains the names of the zone with NSEC or NSEC3 records
+/// and signs every authoritative RRset.
+pub(crate) struct ZoneSigner {
+    zone: Name,
+    ksk: ZoneKey,
+    zsk: ZoneKey,
+    validity: Duration,
+    nsec3: Option<Nsec3Params>,
+}
+
+impl ZoneSigner {
+    pub(crate) fn new(
+        zone: &Name,
+        config: &DnssecConfig,
+        generated: &GeneratedKeys,
+    ) -> Result<Self> {
+        let algorithm = match config.algorithm().to_ascii_lowercase().as_str() {
+            "ecdsap256sha256" => Algorithm::ECDSAP256SHA256,
+            "ecdsap384sha384" => Algorithm::ECDSAP384SHA384,
+            "ed25519" => Algorithm::ED25519,
+            algorithm => bail!("unsupported DNSSEC algorithm {}", algorithm),
+        };
+        let key = |ksk: bool, path: Option<&Path>| -> Result<ZoneKey> {
+            let pkcs8 = match path {
+                Some(path) => Arc::new(load_or_generate(path, algorithm)?),
+                None => generated.get(zone, ksk, algorithm)?,
+            };
+            let key = KeyFormat::Pkcs8.decode_key(&pkcs8, None, algorithm)?;
+            let dnskey = DNSKEY::new(true, ksk, false, algorithm, key.to_public_bytes()?);
+            let key_tag = dnskey.calculate_key_tag()?;
+            let signer = SigSigner::dnssec(
+                dnskey.clone(),
+                key,
+                zone.clone(),
+                config.signature_validity(),
+            );
+            Ok(ZoneKey {
+                dnskey,
+                signer,
+                key_tag,
+            })
+        };
+        Ok(Self {
+            zone: zone.clone(),
+            ksk: key(true, config.ksk()).context("invalid KSK")?,
+            zsk: key(false, config.zsk()).context("invalid ZSK")?,
+            validity: config.signature_validity(),
+            nsec3: config.nsec3().map(Nsec3Params::new).transpose()?,
+        })
+    }
+
+    /// The DS record of the KSK, to be published in the parent zone.
+    pub(crate) fn ds(&self) -> Result<Record> {
+        let digest = self.ksk.dnskey.to_digest(&self.zone, DigestType::SHA256)?;
+        let ds = DS::new(
+            self.ksk.key_tag,
+            self.ksk.dnskey.algorithm(),
+            DigestType::SHA256,
+            digest.as_ref().to_vec(),
+        );
+        Ok(Record::from_rdata(
+            self.zone.clone(),
+            3600,
+            RData::DNSSEC(DNSSECRData::DS(ds)),
+        ))
+    }
+
+    /// Replaces the DNSKEY, NSEC, NSEC3 and RRSIG records of the zone with
+    /// ones matching its current records.
+    pub(crate) fn sign(&self, records: &mut Records) -> Result<()> {
+        let origin = LowerName::from(&self.zone);
+        records.retain(|key, _| !is_generated(key.record_type));
+        let ttl = negative_ttl(&origin, records);
+
+        let mut dnskeys = RecordSet::with_ttl(self.zone.clone(), RecordType::DNSKEY, ttl);
+        for key in [&self.ksk, &self.zsk] {
+            let data = RData::DNSSEC(DNSSECRData::DNSKEY(key.dnskey.clone()));
+            dnskeys.insert(Record::from_rdata(self.zone.clone(), ttl, data), 0);
+        }
+        records.insert(
+            RrKey::new(origin.clone(), RecordType::DNSKEY),
+            Arc::new(dnskeys),
+        );
+        if let Some(params) = &self.nsec3 {
+            let data = RData::DNSSEC(DNSSECRData::NSEC3PARAM(params.nsec3param()));
+            records.insert(
+                RrKey::new(origin.clone(), RecordType::NSEC3PARAM),
+                Arc::new(RecordSet::from(Record::from_rdata(
+                    self.zone.clone(),
+                    0,
+                    data,
+                ))),
+            );
+        }
+
+        // names below a delegation are glue, neither chained nor signed
+        let delegations: Vec<LowerName> = records
+            .keys()
+            .filter(|key| key.record_type == RecordType::NS && key.name != origin)
+            .map(|key| key.name.clone())
+            .collect();
+        let occluded = |name: &LowerName| {
+            delegations
+                .iter()
+                .any(|delegation| delegation != name && delegation.zone_of(name))
+        };
+
+        let mut names: Vec<(LowerName, Vec<RecordType>)> = Vec::new();
+        for key in records.keys().filter(|key| !occluded(&key.name)) {
+            match names.last_mut() {
+                Some((name, types)) if *name == key.name => types.push(key.record_type),
+                _ => names.push((key.name.clone(), vec![key.record_type])),
+            }
+        }
+        match &self.nsec3 {
+            Some(params) => self.nsec3_chain(params, records, names, &delegations, ttl)?,
+            None => nsec_chain(records, names, ttl),
+        }
+
+        let now = unix_time();
+        let inception = now.saturating_sub(INCEPTION_OFFSET);
+        let expiration = now.saturating_add(self.validity.as_secs() as u32);
+        for (key, rrset) in records.iter_mut() {
+            let delegation = delegations.contains(&key.name)
+                && !matches!(key.record_type, RecordType::DS | RecordType::NSEC);
+            if !rrset.rrsigs().is_empty() {
+                Arc::make_mut(rrset).clear_rrsigs();
+            }
+            if rrset.is_empty() || delegation || occluded(&key.name) {
+                continue;
+            }
+            let signer = match key.record_type {
+                RecordType::DNSKEY => &self.ksk,
+                _ => &self.zsk,
+            };
+            let rrsig = self.rrsig(signer, rrset, inception, expiration)?;
+            Arc::make_mut(rrset).insert_rrsig(rrsig);
+        }
+        Ok(())
+    }
+
+    /// Chains the hashes of `names` with NSEC3 records. Empty non-terminals
+    /// are chained too, while opt-out leaves unsigned delegations out.
+    fn nsec3_chain(
+        &self,
+        params: &Nsec3Params,
+        records: &mut Records,
+        names: Vec<(LowerName, Vec<RecordType>)>,
+        delegations: &[LowerName],
+        ttl: u32,
+    ) -> Result<()> {
+        let origin = LowerName::from(&self.zone);
+        let mut chained: BTreeMap<LowerName, Vec<RecordType>> = BTreeMap::new();
+        for (name, mut types) in names {
+            let signed = !delegations.contains(&name) || types.contains(&RecordType::DS);
+            if !signed && params.opt_out {
+                continue;
+            }
+            if signed {
+                types.push(RecordType::RRSIG);
+            }
+            let mut parent = name.base_name();
+            while parent != origin && origin.zone_of(&parent) {
+                chained.entry(parent.clone()).or_default();
+                parent = parent.base_name();
+            }
+            *chained.entry(name).or_default() = types;
+        }
+
+        let mut hashes = chained
+            .into_iter()
+            .map(|(name, types)| Ok((params.hash(&name)?, types)))
+            .collect::<Result<Vec<_>>>()?;
+        hashes.sort_by(|a, b| a.0.cmp(&b.0));
+        for (i, (hash, types)) in hashes.iter().enumerate() {
+            let next = hashes[(i + 1) % hashes.len()].0.clone();
+            let nsec3 = NSEC3::new(
+                Nsec3HashAlgorithm::SHA1,
+                params.opt_out,
+                params.iterations,
+                params.salt.clone(),
+                next,
+                types.clone(),
+            );
+            let owner = Name::from_ascii(BASE32_DNSSEC.encode(hash))?.append_domain(&self.zone)?;
+            let data = RData::DNSSEC(DNSSECRData::NSEC3(nsec3));
+            records.insert(
+                RrKey::new(owner.clone().into(), RecordType::NSEC3),
+                Arc::new(RecordSet::from(Record::from_rdata(owner, ttl, data))),
+            );
+        }
+        Ok(())
+    }
+
+    /// The NSEC3 records, with their signatures, denying `name` in a zone
+    /// signed with NSEC3 (RFC 5155, section 7.2). Without `nx_domain`, they
+    /// deny the queried type instead of the name.
+    pub(crate) fn nsec3_proof(
+        &self,
+        records: &Records,
+        name: &LowerName,
+        nx_domain: bool,
+    ) -> Option<Vec<Record>> {
+        let params = self.nsec3.as_ref()?;
+        let origin = LowerName::from(&self.zone);
+        let mut chain: Vec<(Vec<u8>, &Arc<RecordSet>)> = records
+            .iter()
+            .filter(|(key, _)| key.record_type == RecordType::NSEC3)
+            .filter_map(|(key, rrset)| {
+                let owner = Name::from(&key.name);
+                let hash = BASE32_DNSSEC.decode(owner.iter().next()?).ok()?;
+                Some((hash, rrset))
+            })
+            .collect();
+        chain.sort_by(|a, b| a.0.cmp(&b.0));
+        let matching = |name: &LowerName| {
+            let hash = params.hash(name).ok()?;
+            chain
+                .iter()
+                .find(|(owner, _)| *owner == hash)
+                .map(|(_, rrset)| *rrset)
+        };
+        let covering = |name: &LowerName| {
+            let hash = params.hash(name).ok()?;
+            chain
+                .iter()
+                .rev()
+                .find(|(owner, _)| *owner < hash)
+                .or(chain.last())
+                .map(|(_, rrset)| *rrset)
+        };
+
+        let mut proof = Vec::new();
+        match matching(name) {
+            Some(rrset) if !nx_domain => proof.push(rrset),
+            _ => {
+                // the closest encloser, the next closer name and, for a name
+                // that doesn't exist, the wildcard at the closest encloser
+                let mut closer = name.clone();
+                let mut encloser = name.base_name();
+                while encloser != origin && matching(&encloser).is_none() {
+                    closer = encloser.clone();
+                    encloser = encloser.base_name();
+                }
+                proof.extend(matching(&encloser));
+                proof.extend(covering(&closer));
+                if nx_domain {
+                    let wildcard = Name::from_ascii("*")
+                        .and_then(|wildcard| wildcard.append_domain(&(&encloser).into()))
+                        .ok()?;
+                    proof.extend(covering(&wildcard.into()));
+                }
+            }
+        }
+        let mut unique: Vec<&Arc<RecordSet>> = Vec::new();
+        for rrset in proof {
+            if !unique.iter().any(|seen| Arc::ptr_eq(seen, rrset)) {
+                unique.push(rrset);
+            }
+        }
+        Some(
+            unique
+                .into_iter()
+                .flat_map(|rrset| rrset.records_without_rrsigs().chain(rrset.rrsigs()))
+                .cloned()
+                .collect(),
+        )
+    }
+
+    fn rrsig(
+        &self,
+        key: &ZoneKey,
+        rrset: &RecordSet,
+        inception: u32,
+        expiration: u32,
+    ) -> Result<Record> {
+        let records: Vec<Record> = rrset.records_without_rrsigs().cloned().collect();
+        let algorithm = key.dnskey.algorithm();
+        let tbs = tbs::rrset_tbs(
+            rrset.name(),
+            rrset.dns_class(),
+            rrset.name().num_labels(),
+            rrset.record_type(),
+            algorithm,
+            rrset.ttl(),
+            expiration,
+            inception,
+            key.key_tag,
+            &self.zone,
+            &records,
+        )?;
+        let rrsig = RRSIG::new(
+            rrset.record_type(),
+            algorithm,
+            rrset.name().num_labels(),
+            rrset.ttl(),
+            expiration,
+            inception,
+            key.key_tag,
+            self.zone.clone(),
+            key.signer.sign(&tbs)?,
+        );
+        Ok(Record::from_rdata(
+            rrset.name().clone(),
+            rrset.ttl(),
+            RData::DNSSEC(DNSSECRData::RRSIG(rrsig)),
+        ))
+    }
+
+    /// Whether the signatures of `records` passed half of their validity and
+    /// should be renewed.
+    pub(crate) fn expiring(&self, records: &Records) -> bool {
+        let origin = LowerName::from(&self.zone);
+        let expiration = records
+            .get(&RrKey::new(origin, RecordType::SOA))
+            .and_then(|rrset| rrset.rrsigs().first())
+            .and_then(Record::data)
+            .and_then(RData::as_dnssec)
+            .and_then(DNSSECRData::as_rrsig)
+            .map(|rrsig| rrsig.sig_expiration());
+        match expiration {
+            Some(expiration) => {
+                let remaining = expiration.saturating_sub(unix_time()) as u64;
+                remaining < self.validity.as_secs() / 2
+            }
+            None => true,
+        }
+    }
+}
+
+/// Chains `names`, in canonical order, with NSEC records.
+fn nsec_chain(records: &mut Records, names: Vec<(LowerName, Vec<RecordType>)>, ttl: u32) {
+    for (i, (name, types)) in names.iter().enumerate() {
+        let next = &names[(i + 1) % names.len()].0;
+        let mut types = types.clone();
+        types.push(RecordType::RRSIG);
+        let nsec = NSEC::new_cover_self(next.into(), types);
+        let data = RData::DNSSEC(DNSSECRData::NSEC(nsec));
+        records.insert(
+            RrKey::new(name.clone(), RecordType::NSEC),
+            Arc::new(RecordSet::from(Record::from_rdata(name.into(), ttl, data))),
+        );
+    }
+}
+
+/// TTL of the NSEC and DNSKEY records: the lesser of the SOA TTL and its
+/// minimum field (RFC 9077).
+fn negative_ttl(origin: &LowerName, records: &Records) -> u32 {
+    records
+        .get(&RrKey::new(origin.clone(), RecordType::SOA))
+        .and_then(|rrset| rrset.records_without_rrsigs().next())
+        .and_then(|soa| match soa.data() {
+            Some(RData::SOA(data)) => Some(soa.ttl().min(data.minimum())),
+            _ => None,
+        })
+        .unwrap_or(3600)
+}
+
+/// Reads the PKCS#8 key at `path`, generating it first when missing.
+pub(crate) fn load_or_generate(path: &Path, algorithm: Algorithm) -> Result<Vec<u8>> {
+    if path.exists() {
+        return std::fs::read(path).with_context(|| format!("failed to read {}", path.display()));
+    }
+    let pkcs8 = KeyFormat::Pkcs8.generate_and_encode(algorithm, None)?;
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("failed to create {}", path.display()))?;
+    std::io::Write::write_all(&mut file, &pkcs8)?;
+    info!("generated key {}", path.display());
+    Ok(pkcs8)
+}
+
+pub(crate) fn unix_time() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DnssecConfigBuilder, Nsec3ConfigBuilder};
+    use hickory_proto::rr::dnssec::Verifier;
+    use hickory_proto::rr::{rdata, DNSClass};
+    use std::str::FromStr;
+
+    fn insert(records: &mut Records, record: Record) {
+        let rrset = records
+            .entry(RrKey::new(record.name().into(), record.record_type()))
+            .or_insert_with(|| Arc::new(RecordSet::new(record.name(), record.record_type(), 0)));
+        Arc::make_mut(rrset).insert(record, 0);
+    }
+
+    fn records() -> Result<Records> {
+        let name = |name: &str| Name::from_str(name).unwrap();
+        let ns = |host: &str| RData::NS(rdata::NS(name(host)));
+        let a = |address: &str| RData::A(address.parse().unwrap());
+        let soa = rdata::SOA::new(
+            name("ns1.et.internal."),
+            name("hostmaster.et.internal."),
+            1,
+            3600,
+            900,
+            604800,
+            300,
+        );
+        let mut records = Records::new();
+        for (owner, ttl, data) in [
+            ("et.internal.", 3600, RData::SOA(soa)),
+            ("et.internal.", 3600, ns("ns1.et.internal.")),
+            ("ns1.et.internal.", 3600, a("10.0.0.53")),
+            ("www.et.internal.", 60, a("10.0.0.1")),
+            ("sub.et.internal.", 3600, ns("ns.sub.et.internal.")),
+            ("ns.sub.et.internal.", 3600, a("10.0.1.53")),
+        ] {
+            insert(&mut records, Record::from_rdata(name(owner), ttl, data));
+        }
+        Ok(records)
+    }
+
+    #[test]
+    fn signs_zones() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let ksk = dir.path().join("ksk.pk8");
+        let config = DnssecConfigBuilder::default().ksk(&ksk).build()?;
+        let zone = Name::from_str("et.internal.")?;
+        let generated = GeneratedKeys::default();
+        let signer = ZoneSigner::new(&zone, &config, &generated)?;
+        assert!(ksk.exists());
+        // the same keys are used again
+        let again = ZoneSigner::new(&zone, &config, &generated)?;
+        assert_eq!(signer.ksk.dnskey, again.ksk.dnskey);
+        assert_eq!(signer.zsk.dnskey, again.zsk.dnskey);
+        assert!(signer.ds().is_ok());
+
+        let mut records = records()?;
+        assert!(signer.expiring(&records));
+        signer.sign(&mut records)?;
+        assert!(!signer.expiring(&records));
+
+        let rrset = |name: &str, record_type| {
+            records[&RrKey::new(LowerName::from_str(name).unwrap(), record_type)].clone()
+        };
+        let dnskey = rrset("et.internal.", RecordType::DNSKEY);
+        assert_eq!(dnskey.records_without_rrsigs().count(), 2);
+
+        // every authoritative rrset verifies with its key
+        for (name, record_type, key) in [
+            ("et.internal.", RecordType::SOA, &signer.zsk),
+            ("www.et.internal.", RecordType::A, &signer.zsk),
+            ("et.internal.", RecordType::DNSKEY, &signer.ksk),
+            ("sub.et.internal.", RecordType::NSEC, &signer.zsk),
+        ] {
+            let rrset = rrset(name, record_type);
+            let rrsig = rrset.rrsigs()[0].data().unwrap();
+            let rrsig = rrsig.as_dnssec().unwrap().as_rrsig().unwrap();
+            let records: Vec<Record> = rrset.records_without_rrsigs().cloned().collect();
+            key.dnskey
+                .verify_rrsig(rrset.name(), rrset.dns_class(), rrsig, &records)?;
+        }
+        // delegations and glue are left unsigned
+        assert!(rrset("sub.et.internal.", RecordType::NS)
+            .rrsigs()
+            .is_empty());
+        assert!(rrset("ns.sub.et.internal.", RecordType::A)
+            .rrsigs()
+            .is_empty());
+        assert!(!records.contains_key(&RrKey::new(
+            LowerName::from_str("ns.sub.et.internal.")?,
+            RecordType::NSEC
+        )));
+
+        // names are chained in canonical order, back to the apex
+        let next = |name: &str| {
+            let nsec = rrset(name, RecordType::NSEC);
+            let record = nsec.records_without_rrsigs().next().unwrap();
+            let nsec = record
+                .data()
+                .unwrap()
+                .as_dnssec()
+                .unwrap()
+                .as_nsec()
+                .unwrap();
+            nsec.next_domain_name().to_string()
+        };
+        assert_eq!(next("et.internal."), "ns1.et.internal.");
+        assert_eq!(next("ns1.et.internal."), "sub.et.internal.");
+        assert_eq!(next("sub.et.internal."), "www.et.internal.");
+        assert_eq!(next("www.et.internal."), "et.internal.");
+        Ok(())
+    }
+
+    #[test]
+    fn chains_with_nsec3() -> Result<()> {
+        let nsec3 = Nsec3ConfigBuilder::default()
+            .salt("aabbccdd")
+            .opt_out(true)
+            .build()?;
+        let config = DnssecConfigBuilder::default().nsec3(nsec3).build()?;
+        let zone = Name::from_str("et.internal.")?;
+        let signer = ZoneSigner::new(&zone, &config, &GeneratedKeys::default())?;
+        let mut records = records()?;
+        insert(
+            &mut records,
+            Record::from_rdata(
+                Name::from_str("a.b.et.internal.")?,
+                60,
+                RData::A("10.0.0.2".parse()?),
+            ),
+        );
+        signer.sign(&mut records)?;
+        assert!(signed_with(&records, Some(&signer)));
+
+        let types = |record_type| {
+            records
+                .keys()
+                .filter(|key| key.record_type == record_type)
+                .count()
+        };
+        assert_eq!(types(RecordType::NSEC), 0);
+        assert_eq!(types(RecordType::NSEC3PARAM), 1);
+        // the apex, ns1, www, a.b and the empty non-terminal b, without the
+        // opted out delegation and its glue
+        assert_eq!(types(RecordType::NSEC3), 5);
+
+        let params = signer.nsec3.as_ref().unwrap();
+        let owner = |name: &str| {
+            let hash = params.hash(&LowerName::from_str(name).unwrap()).unwrap();
+            let owner = Name::from_ascii(BASE32_DNSSEC.encode(&hash)).unwrap();
+            owner.append_domain(&zone).unwrap()
+        };
+        let nsec3s = |proof: &[Record]| -> Vec<Name> {
+            proof
+                .iter()
+                .filter(|r| r.record_type() == RecordType::NSEC3)
+                .map(|r| r.name().clone())
+                .collect()
+        };
+
+        // no data: the NSEC3 of the name itself, with its signature
+        let proof = signer
+            .nsec3_proof(&records, &LowerName::from_str("www.et.internal.")?, false)
+            .unwrap();
+        assert_eq!(nsec3s(&proof), [owner("www.et.internal.")]);
+        assert_eq!(proof.len(), 2);
+        let rrsig = proof[1]
+            .data()
+            .unwrap()
+            .as_dnssec()
+            .unwrap()
+            .as_rrsig()
+            .unwrap();
+        signer.zsk.dnskey.verify_rrsig(
+            &proof[0].name().clone(),
+            DNSClass::IN,
+            rrsig,
+            &proof[..1],
+        )?;
+
+        // name error: the closest encloser and covering NSEC3s
+        let proof = signer
+            .nsec3_proof(&records, &LowerName::from_str("nope.b.et.internal.")?, true)
+            .unwrap();
+        let nsec3s = nsec3s(&proof);
+        assert_eq!(nsec3s[0], owner("b.et.internal."));
+        assert!((2..=3).contains(&nsec3s.len()));
+        assert_eq!(
+            proof
+                .iter()
+                .filter(|r| r.record_type() == RecordType::RRSIG)
+                .count(),
+            nsec3s.len()
+        );
+
+        // zones signed with NSEC have no NSEC3 proofs
+        let plain = ZoneSigner::new(
+            &zone,
+            &DnssecConfigBuilder::default().build()?,
+            &GeneratedKeys::default(),
+        )?;
+        assert!(!signed_with(&records, Some(&plain)));
+        assert!(plain
+            .nsec3_proof(&records, &LowerName::from_str("www.et.internal.")?, false)
+            .is_none());
+        Ok(())
+    }
+}
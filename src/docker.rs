@@ -0,0 +1,308 @@
+use crate::config::{self, DockerConfig};
+use crate::discovery::{self, address_record};
+use crate::zones::ZoneSet;
+#[cfg(feature = "docker")]
+use anyhow::Context;
+use anyhow::{bail, Result};
+use hickory_proto::rr;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info};
+
+/// Label leaving a container out when `false`.
+const ENABLE_LABEL: &str = "libdns.enable";
+/// Label listing the names of a container, comma separated.
+const NAME_LABEL: &str = "libdns.name";
+/// The events changing the running containers or their addresses, that is
+/// `filters={"type":["container","network"],"event":["start","die","rename","connect","disconnect"]}`.
+#[cfg_attr(not(feature = "docker"), allow(dead_code))]
+const EVENTS_PATH: &str = "/events?filters=%7B%22type%22%3A%5B%22container%22%2C%22network%22%5D%2C%22event%22%3A%5B%22start%22%2C%22die%22%2C%22rename%22%2C%22connect%22%2C%22disconnect%22%5D%7D";
+
+/// Synthesizes the records of the running containers of a Docker daemon
+/// into the zone of its `domain`, listing them again on every event that
+/// changes them.
+pub(crate) struct Docker {
+    origin: rr::Name,
+    ttl: u32,
+    network: Option<String>,
+    client: Client,
+}
+
+impl Docker {
+    pub(crate) fn new(config: &DockerConfig, zones: &config::Zone) -> Result<Self> {
+        Ok(Self {
+            origin: discovery::origin("docker domain", config.domain(), zones)?,
+            ttl: config.ttl().as_secs() as u32,
+            network: config.network().map(str::to_string),
+            client: Client::new(config)?,
+        })
+    }
+
+    /// Serves the zone, empty until the containers are listed, and keeps it
+    /// in sync with the daemon until `token` is cancelled.
+    pub(crate) async fn start(
+        self: Arc<Self>,
+        zones: Arc<ZoneSet>,
+        token: CancellationToken,
+    ) -> Result<()> {
+        zones.serve_records(&self.origin, Vec::new()).await?;
+        tokio::spawn(async move {
+            discovery::follow("follow docker events", &token, || self.follow(&zones)).await;
+        });
+        Ok(())
+    }
+
+    /// Publishes the running containers whenever an event changes them, the
+    /// events being subscribed to before the first listing so none is missed.
+    async fn follow(&self, zones: &ZoneSet) -> Result<()> {
+        let mut events = self.client.events(EVENTS_PATH).await?;
+        let count = self.publish(zones).await?;
+        info!("listed {} running docker containers", count);
+        while events.next().await?.is_some() {
+            self.publish(zones).await?;
+        }
+        bail!("docker event stream ended")
+    }
+
+    async fn publish(&self, zones: &ZoneSet) -> Result<usize> {
+        let containers: Vec<Container> =
+            serde_json::from_slice(&self.client.get("/containers/json").await?)?;
+        let records = records(&containers, &self.origin, self.network.as_deref(), self.ttl);
+        zones.serve_records(&self.origin, records).await?;
+        Ok(containers.len())
+    }
+}
+
+/// The records of `containers` in the zone `origin`, with the addresses of
+/// `network` only when set.
+fn records(
+    containers: &[Container],
+    origin: &rr::Name,
+    network: Option<&str>,
+    ttl: u32,
+) -> Vec<rr::Record> {
+    let mut records = Vec::new();
+    for container in containers {
+        if container.labels.get(ENABLE_LABEL).map(String::as_str) == Some("false") {
+            continue;
+        }
+        let names: Vec<&str> = match container.labels.get(NAME_LABEL) {
+            Some(names) => names.split(',').map(str::trim).collect(),
+            None => container
+                .names
+                .iter()
+                .map(|name| name.trim_start_matches('/'))
+                .collect(),
+        };
+        let ips: Vec<IpAddr> = container
+            .network_settings
+            .networks
+            .iter()
+            .filter(|(name, _)| network.is_none_or(|network| network == name.as_str()))
+            .flat_map(|(_, endpoint)| [&endpoint.ip_address, &endpoint.global_ipv6_address])
+            .filter_map(|ip| ip.parse().ok())
+            .collect();
+        for name in names.into_iter().filter(|name| !name.is_empty()) {
+            let Some(name) = absolute(name, origin) else {
+                debug!(
+                    "skipped name {} of docker container {} outside {}",
+                    name, container.id, origin
+                );
+                continue;
+            };
+            for ip in &ips {
+                records.push(address_record(&name, *ip, ttl));
+            }
+        }
+    }
+    records
+}
+
+/// `name` relative to `origin` unless it ends with a dot, `None` if it isn't
+/// a name within `origin`.
+fn absolute(name: &str, origin: &rr::Name) -> Option<rr::Name> {
+    let name = rr::Name::from_ascii(name).ok()?;
+    let name = if name.is_fqdn() {
+        name
+    } else {
+        name.append_name(origin).ok()?
+    };
+    origin.zone_of(&name).then_some(name)
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct Container {
+    id: String,
+    #[serde(default)]
+    names: Vec<String>,
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+    #[serde(default)]
+    network_settings: NetworkSettings,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "PascalCase")]
+struct NetworkSettings {
+    #[serde(default)]
+    networks: BTreeMap<String, EndpointSettings>,
+}
+
+/// The addresses of a container in a network, empty strings when unset.
+#[derive(Deserialize, Debug)]
+struct EndpointSettings {
+    #[serde(default, rename = "IPAddress")]
+    ip_address: String,
+    #[serde(default, rename = "GlobalIPv6Address")]
+    global_ipv6_address: String,
+}
+
+/// Client of the Engine API on the Unix socket of the daemon, with a
+/// connection per request.
+#[cfg(feature = "docker")]
+struct Client {
+    socket: std::path::PathBuf,
+}
+
+#[cfg(feature = "docker")]
+impl Client {
+    fn new(config: &DockerConfig) -> Result<Self> {
+        Ok(Self {
+            socket: config.socket().to_path_buf(),
+        })
+    }
+
+    async fn request(&self, path: &str) -> Result<hyper::Response<hyper::body::Incoming>> {
+        use http_body_util::Empty;
+        use hyper::body::Bytes;
+        use hyper_util::rt::TokioIo;
+
+        let stream = tokio::net::UnixStream::connect(&self.socket)
+            .await
+            .with_context(|| format!("failed to connect to {}", self.socket.display()))?;
+        let (mut sender, connection) =
+            hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                debug!("docker connection failed: {}", e);
+            }
+        });
+        let request = hyper::Request::get(path)
+            .header(hyper::header::HOST, "docker")
+            .body(Empty::<Bytes>::new())?;
+        let response = sender.send_request(request).await?;
+        if !response.status().is_success() {
+            bail!("GET {} returned {}", path, response.status());
+        }
+        Ok(response)
+    }
+
+    async fn get(&self, path: &str) -> Result<Vec<u8>> {
+        use http_body_util::BodyExt;
+
+        let body = self.request(path).await?.into_body().collect().await?;
+        Ok(body.to_bytes().to_vec())
+    }
+
+    async fn events(&self, path: &str) -> Result<Lines> {
+        Ok(Lines::new(self.request(path).await?.into_body()))
+    }
+}
+
+/// The events of the daemon, one JSON object per line.
+#[cfg(feature = "docker")]
+type Lines = discovery::Lines<hyper::body::Incoming>;
+
+#[cfg(not(feature = "docker"))]
+enum Client {}
+
+#[cfg(not(feature = "docker"))]
+impl Client {
+    fn new(_config: &DockerConfig) -> Result<Self> {
+        bail!("the Docker backend requires the `docker` feature")
+    }
+
+    async fn get(&self, _path: &str) -> Result<Vec<u8>> {
+        match *self {}
+    }
+
+    async fn events(&self, _path: &str) -> Result<Lines> {
+        match *self {}
+    }
+}
+
+#[cfg(not(feature = "docker"))]
+type Lines = discovery::Lines<std::convert::Infallible>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::str::FromStr;
+
+    #[test]
+    fn synthesizes_records() -> Result<()> {
+        let containers: Vec<Container> = serde_json::from_value(json!([
+            {
+                "Id": "1",
+                "Names": ["/web"],
+                "Labels": {},
+                "NetworkSettings": { "Networks": {
+                    "bridge": { "IPAddress": "172.17.0.2", "GlobalIPv6Address": "fd00::2" },
+                    "backend": { "IPAddress": "172.18.0.2", "GlobalIPv6Address": "" },
+                } },
+            },
+            {
+                "Id": "2",
+                "Names": ["/project-db-1"],
+                "Labels": { "libdns.name": "db, db.project, db.example.com." },
+                "NetworkSettings": { "Networks": {
+                    "bridge": { "IPAddress": "172.17.0.3", "GlobalIPv6Address": "" },
+                } },
+            },
+            {
+                "Id": "3",
+                "Names": ["/hidden"],
+                "Labels": { "libdns.enable": "false" },
+                "NetworkSettings": { "Networks": {
+                    "bridge": { "IPAddress": "172.17.0.4", "GlobalIPv6Address": "" },
+                } },
+            },
+            { "Id": "4", "Names": ["/host"], "NetworkSettings": { "Networks": {
+                "host": { "IPAddress": "", "GlobalIPv6Address": "" },
+            } } },
+        ]))?;
+
+        let origin = rr::Name::from_str("docker.internal.")?;
+        let find = |records: &[rr::Record], name: &str| -> Vec<String> {
+            let name = rr::Name::from_str(name).unwrap();
+            records
+                .iter()
+                .filter(|r| r.name() == &name)
+                .map(|r| format!("{} {}", r.record_type(), r.data().unwrap()))
+                .collect()
+        };
+        let records = super::records(&containers, &origin, None, 30);
+        assert_eq!(
+            find(&records, "web.docker.internal."),
+            ["A 172.18.0.2", "A 172.17.0.2", "AAAA fd00::2"]
+        );
+        assert_eq!(find(&records, "db.docker.internal."), ["A 172.17.0.3"]);
+        assert_eq!(
+            find(&records, "db.project.docker.internal."),
+            ["A 172.17.0.3"]
+        );
+        assert!(find(&records, "project-db-1.docker.internal.").is_empty());
+        assert!(find(&records, "hidden.docker.internal.").is_empty());
+        assert_eq!(records.len(), 5);
+
+        let records = super::records(&containers, &origin, Some("backend"), 30);
+        assert_eq!(find(&records, "web.docker.internal."), ["A 172.18.0.2"]);
+        assert_eq!(records.len(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,363 @@
+use crate::handler::CatalogRequestHandler;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hickory_proto::op::Message;
+use hickory_proto::rr::Record;
+use hickory_proto::serialize::binary::{BinDecodable, BinEncoder};
+use hickory_server::authority::{MessageRequest, MessageResponse};
+use hickory_server::server::{Protocol, Request, RequestHandler, ResponseHandler, ResponseInfo};
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::{Bytes, Incoming};
+use hyper::header::{HeaderValue, CACHE_CONTROL, CONTENT_TYPE};
+use hyper::{Method, StatusCode};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use std::convert::Infallible;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+const DNS_MESSAGE: &str = "application/dns-message";
+const MAX_MESSAGE_SIZE: usize = 65535;
+
+/// Answers RFC 8484 requests on `path` until `token` is cancelled.
+pub(crate) async fn serve(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    handler: CatalogRequestHandler,
+    path: String,
+    token: CancellationToken,
+) {
+    let path: Arc<str> = path.into();
+    loop {
+        let (stream, src) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    debug!("error accepting https connection: {}", e);
+                    continue;
+                }
+            },
+            _ = token.cancelled() => break,
+        };
+
+        let acceptor = acceptor.clone();
+        let handler = handler.clone();
+        let path = path.clone();
+        let token = token.clone();
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    debug!("tls handshake with {} failed: {}", src, e);
+                    return;
+                }
+            };
+            let service = hyper::service::service_fn(move |request| {
+                let handler = handler.clone();
+                let path = path.clone();
+                async move { Ok::<_, Infallible>(handle(request, src, &handler, &path).await) }
+            });
+            let builder = auto::Builder::new(TokioExecutor::new());
+            let connection = builder.serve_connection(TokioIo::new(stream), service);
+            tokio::select! {
+                result = connection => if let Err(e) = result {
+                    debug!("https connection with {} failed: {}", src, e);
+                },
+                _ = token.cancelled() => {},
+            }
+        });
+    }
+}
+
+async fn handle(
+    request: hyper::Request<Incoming>,
+    src: SocketAddr,
+    handler: &CatalogRequestHandler,
+    path: &str,
+) -> hyper::Response<Full<Bytes>> {
+    if request.uri().path() != path {
+        return status(StatusCode::NOT_FOUND);
+    }
+    let bytes = match *request.method() {
+        Method::GET => {
+            let param = request
+                .uri()
+                .query()
+                .into_iter()
+                .flat_map(|query| query.split('&'))
+                .find_map(|pair| pair.strip_prefix("dns="));
+            match param.map(|dns| URL_SAFE_NO_PAD.decode(dns)) {
+                Some(Ok(bytes)) => bytes,
+                _ => return status(StatusCode::BAD_REQUEST),
+            }
+        }
+        Method::POST => {
+            let content_type = request.headers().get(CONTENT_TYPE);
+            if content_type != Some(&HeaderValue::from_static(DNS_MESSAGE)) {
+                return status(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+            }
+            match Limited::new(request.into_body(), MAX_MESSAGE_SIZE)
+                .collect()
+                .await
+            {
+                Ok(body) => body.to_bytes().to_vec(),
+                Err(_) => return status(StatusCode::PAYLOAD_TOO_LARGE),
+            }
+        }
+        _ => return status(StatusCode::METHOD_NOT_ALLOWED),
+    };
+
+    let message = match MessageRequest::from_bytes(&bytes) {
+        Ok(message) => message,
+        Err(e) => {
+            debug!("invalid dns message from {}: {}", src, e);
+            return status(StatusCode::BAD_REQUEST);
+        }
+    };
+    let response_handle = BufferResponseHandler::default();
+    handler
+        .handle_request(
+            &Request::new(message, src, Protocol::Https),
+            response_handle.clone(),
+        )
+        .await;
+    let Some(bytes) = response_handle.take() else {
+        warn!("no response produced for https request from {}", src);
+        return status(StatusCode::INTERNAL_SERVER_ERROR);
+    };
+
+    let mut response = hyper::Response::new(Full::new(Bytes::from(bytes.clone())));
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static(DNS_MESSAGE));
+    if let Some(ttl) = min_ttl(&bytes) {
+        if let Ok(value) = HeaderValue::from_str(&format!("max-age={}", ttl)) {
+            response.headers_mut().insert(CACHE_CONTROL, value);
+        }
+    }
+    response
+}
+
+fn status(code: StatusCode) -> hyper::Response<Full<Bytes>> {
+    let mut response = hyper::Response::new(Full::default());
+    *response.status_mut() = code;
+    response
+}
+
+/// The freshness lifetime of a response is the smallest TTL it carries.
+fn min_ttl(bytes: &[u8]) -> Option<u32> {
+    let message = Message::from_vec(bytes).ok()?;
+    message
+        .answers()
+        .iter()
+        .chain(message.name_servers())
+        .map(|r| r.ttl())
+        .min()
+}
+
+/// Captures the serialized response so it can be sent as an HTTP body.
+#[derive(Clone, Default)]
+struct BufferResponseHandler(Arc<Mutex<Option<Vec<u8>>>>);
+
+impl BufferResponseHandler {
+    fn take(&self) -> Option<Vec<u8>> {
+        self.0.lock().unwrap().take()
+    }
+}
+
+#[async_trait::async_trait]
+impl ResponseHandler for BufferResponseHandler {
+    async fn send_response<'a>(
+        &mut self,
+        response: MessageResponse<
+            '_,
+            'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+        >,
+    ) -> io::Result<ResponseInfo> {
+        let mut bytes = Vec::with_capacity(512);
+        let info = {
+            let mut encoder = BinEncoder::new(&mut bytes);
+            response.destructive_emit(&mut encoder)?
+        };
+        *self.0.lock().unwrap() = Some(bytes);
+        Ok(info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{
+        GeneralConfigBuilder, HttpsListenConfigBuilder, RecordBuilder, RecordData,
+        RunConfigBuilder, TlsListenConfigBuilder,
+    };
+    use crate::Server;
+    use anyhow::Result;
+    use hickory_proto::op::Query;
+    use hickory_proto::rr;
+    use hickory_proto::serialize::binary::BinEncodable;
+    use maplit::hashmap;
+    use std::net::Ipv4Addr;
+    use std::str::FromStr;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use tokio_rustls::TlsConnector;
+
+    struct Fixture {
+        server: Server,
+        connector: TlsConnector,
+        _dir: tempfile::TempDir,
+    }
+
+    async fn start() -> Result<Fixture> {
+        let dir = tempfile::tempdir()?;
+        let cert = rcgen::generate_simple_self_signed(vec!["dns.et.internal".to_string()])?;
+        let cert_path = dir.path().join("cert.pem");
+        let key_path = dir.path().join("key.pem");
+        std::fs::write(&cert_path, cert.serialize_pem()?)?;
+        std::fs::write(&key_path, cert.serialize_private_key_pem())?;
+
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_https(
+                        HttpsListenConfigBuilder::default()
+                            .tls(
+                                TlsListenConfigBuilder::default()
+                                    .address("127.0.0.1:0")
+                                    .cert(cert_path)
+                                    .key(key_path)
+                                    .build()?,
+                            )
+                            .build()?,
+                    )
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.internal".to_string() => vec![
+                    RecordBuilder::default()
+                        .name("www.et.internal".to_string())
+                        .data(RecordData::A(Ipv4Addr::new(123, 123, 123, 123)))
+                        .ttl(Duration::from_secs(60))
+                        .build()?,
+                ].into(),
+            })
+            .build()?;
+        let mut server = Server::new(config);
+        server.run().await?;
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(&rustls::Certificate(cert.serialize_der()?))?;
+        let client_config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Ok(Fixture {
+            server,
+            connector: TlsConnector::from(Arc::new(client_config)),
+            _dir: dir,
+        })
+    }
+
+    /// Sends a raw HTTP/1.1 request, returning the status line, headers and body.
+    async fn exchange(fixture: &mut Fixture, head: &str, body: &[u8]) -> Result<(String, Vec<u8>)> {
+        let stream = TcpStream::connect(fixture.server.https_local_addr().unwrap()).await?;
+        let name = rustls::ServerName::try_from("dns.et.internal")?;
+        let mut stream = fixture.connector.connect(name, stream).await?;
+        stream
+            .write_all(
+                format!("{head}Host: dns.et.internal\r\nConnection: close\r\n\r\n").as_bytes(),
+            )
+            .await?;
+        stream.write_all(body).await?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+
+        let split = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8(response[..split].to_vec())?;
+        Ok((head, response[split + 4..].to_vec()))
+    }
+
+    fn query_bytes() -> Result<Vec<u8>> {
+        let mut message = Message::new();
+        message.add_query(Query::query(
+            rr::Name::from_str("www.et.internal")?,
+            rr::RecordType::A,
+        ));
+        Ok(message.to_bytes()?)
+    }
+
+    #[tokio::test]
+    async fn answers_get_requests() -> Result<()> {
+        let mut fixture = start().await?;
+        let dns = URL_SAFE_NO_PAD.encode(query_bytes()?);
+        let (head, body) = exchange(
+            &mut fixture,
+            &format!("GET /dns-query?dns={dns} HTTP/1.1\r\n"),
+            &[],
+        )
+        .await?;
+
+        assert!(head.starts_with("HTTP/1.1 200"));
+        assert!(head.contains("content-type: application/dns-message"));
+        assert!(head.contains("cache-control: max-age=60"));
+        let message = Message::from_vec(&body)?;
+        assert_eq!(message.answers().len(), 1);
+
+        fixture.server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn answers_post_requests() -> Result<()> {
+        let mut fixture = start().await?;
+        let query = query_bytes()?;
+        let (head, body) = exchange(
+            &mut fixture,
+            &format!(
+                "POST /dns-query HTTP/1.1\r\nContent-Type: application/dns-message\r\nContent-Length: {}\r\n",
+                query.len()
+            ),
+            &query,
+        )
+        .await?;
+
+        assert!(head.starts_with("HTTP/1.1 200"));
+        let message = Message::from_vec(&body)?;
+        assert_eq!(message.answers().len(), 1);
+
+        fixture.server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_requests() -> Result<()> {
+        let mut fixture = start().await?;
+        let (head, _) = exchange(&mut fixture, "GET /other HTTP/1.1\r\n", &[]).await?;
+        assert!(head.starts_with("HTTP/1.1 404"));
+
+        let (head, _) = exchange(
+            &mut fixture,
+            "POST /dns-query HTTP/1.1\r\nContent-Type: text/plain\r\nContent-Length: 0\r\n",
+            &[],
+        )
+        .await?;
+        assert!(head.starts_with("HTTP/1.1 415"));
+
+        let (head, _) = exchange(&mut fixture, "GET /dns-query?dns=!! HTTP/1.1\r\n", &[]).await?;
+        assert!(head.starts_with("HTTP/1.1 400"));
+
+        fixture.server.shutdown().await?;
+        Ok(())
+    }
+}
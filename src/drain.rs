@@ -0,0 +1,82 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// Counts the queries being answered, so that shutdown can wait for them
+/// once no new query is taken.
+#[derive(Default)]
+pub(crate) struct Drain {
+    draining: AtomicBool,
+    in_flight: AtomicUsize,
+    idle: Notify,
+}
+
+/// A query being answered, counted until dropped.
+pub(crate) struct InFlight(Arc<Drain>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if self.0.in_flight.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+impl Drain {
+    /// Counts a new query, `None` once draining.
+    pub(crate) fn enter(self: &Arc<Self>) -> Option<InFlight> {
+        self.in_flight.fetch_add(1, Ordering::AcqRel);
+        let in_flight = InFlight(self.clone());
+        // checked after counting, so that `drain` sees every query it lets in
+        (!self.draining.load(Ordering::Acquire)).then_some(in_flight)
+    }
+
+    pub(crate) fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Acquire)
+    }
+
+    /// Takes no new query and waits up to `timeout` for those in flight,
+    /// returns how many are still in flight.
+    pub(crate) async fn drain(&self, timeout: Duration) -> usize {
+        self.draining.store(true, Ordering::Release);
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let idle = self.idle.notified();
+            tokio::pin!(idle);
+            idle.as_mut().enable();
+            if self.in_flight() == 0 {
+                return 0;
+            }
+            if tokio::time::timeout_at(deadline, idle).await.is_err() {
+                return self.in_flight();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn waits_for_queries_in_flight() {
+        let drain = Arc::new(Drain::default());
+        let first = drain.enter().unwrap();
+        let second = drain.enter().unwrap();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            drop(first);
+        });
+        let waiting = tokio::spawn({
+            let drain = drain.clone();
+            async move { drain.drain(Duration::from_millis(300)).await }
+        });
+        tokio::task::yield_now().await;
+        assert!(drain.enter().is_none());
+        assert_eq!(waiting.await.unwrap(), 1);
+
+        drop(second);
+        assert_eq!(drain.drain(Duration::from_secs(1)).await, 0);
+    }
+}
@@ -0,0 +1,149 @@
+use anyhow::{bail, Result};
+use hickory_proto::op::Edns;
+use hickory_proto::rr::rdata::opt::{self, EdnsCode, EdnsOption};
+use ipnet::IpNet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// EDNS Client Subnet option (RFC 7871): the network of the client a query
+/// is sent on behalf of, and the network an answer is valid for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ClientSubnet {
+    network: IpNet,
+    scope_prefix: u8,
+}
+
+impl ClientSubnet {
+    pub(crate) fn new(network: IpNet) -> Self {
+        Self {
+            network: network.trunc(),
+            scope_prefix: 0,
+        }
+    }
+
+    /// The option of `edns`, an error when it is malformed, which is
+    /// answered with FORMERR.
+    pub(crate) fn from_edns(edns: &Edns) -> Result<Option<Self>> {
+        match edns.option(EdnsCode::Subnet) {
+            Some(EdnsOption::Subnet(subnet)) => Ok(Some(Self::decode(subnet)?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// hickory keeps the fields of its option private, they are read back
+    /// from the wire format: family, source and scope prefixes, address.
+    fn decode(subnet: &opt::ClientSubnet) -> Result<Self> {
+        let bytes = Vec::<u8>::try_from(subnet)?;
+        let [_, family, source_prefix, scope_prefix, address @ ..] = bytes.as_slice() else {
+            bail!("truncated client subnet option");
+        };
+        let address = match family {
+            1 => {
+                let mut octets = [0; 4];
+                octets[..address.len()].copy_from_slice(address);
+                IpAddr::from(Ipv4Addr::from(octets))
+            }
+            _ => {
+                let mut octets = [0; 16];
+                octets[..address.len()].copy_from_slice(address);
+                IpAddr::from(Ipv6Addr::from(octets))
+            }
+        };
+        let network = IpNet::new(address, *source_prefix)?;
+        if network.trunc() != network {
+            bail!(
+                "client subnet {} has address bits beyond its prefix",
+                network
+            );
+        }
+        Ok(Self {
+            network,
+            scope_prefix: *scope_prefix,
+        })
+    }
+
+    pub(crate) fn network(&self) -> IpNet {
+        self.network
+    }
+
+    pub(crate) fn scope_prefix(&self) -> u8 {
+        self.scope_prefix
+    }
+
+    /// The network shortened to `ipv4_prefix` or `ipv6_prefix` bits, if
+    /// longer.
+    pub(crate) fn truncate(&self, ipv4_prefix: u8, ipv6_prefix: u8) -> Self {
+        let max_prefix = match self.network {
+            IpNet::V4(_) => ipv4_prefix.min(32),
+            IpNet::V6(_) => ipv6_prefix.min(128),
+        };
+        let prefix = self.network.prefix_len().min(max_prefix);
+        // the prefix is within the bounds of the family
+        Self::new(IpNet::new(self.network.addr(), prefix).unwrap())
+    }
+
+    /// The option answering this one, for an answer valid for the networks
+    /// of `scope_prefix` bits. It can't be longer than the source prefix.
+    pub(crate) fn scoped(&self, scope_prefix: u8) -> Self {
+        Self {
+            network: self.network,
+            scope_prefix: scope_prefix.min(self.network.prefix_len()),
+        }
+    }
+
+    pub(crate) fn option(&self) -> EdnsOption {
+        EdnsOption::Subnet(opt::ClientSubnet::new(
+            self.network.addr(),
+            self.network.prefix_len(),
+            self.scope_prefix,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edns(subnet: opt::ClientSubnet) -> Edns {
+        let mut edns = Edns::new();
+        edns.options_mut().insert(EdnsOption::Subnet(subnet));
+        edns
+    }
+
+    #[test]
+    fn reads_options() -> Result<()> {
+        let subnet = ClientSubnet::from_edns(&edns("192.0.2.0/24".parse()?))?.unwrap();
+        assert_eq!(subnet.network(), "192.0.2.0/24".parse::<IpNet>()?);
+        assert_eq!(subnet.scope_prefix(), 0);
+        let subnet = ClientSubnet::from_edns(&edns(opt::ClientSubnet::new(
+            "2001:db8:1::".parse()?,
+            48,
+            40,
+        )))?
+        .unwrap();
+        assert_eq!(subnet.network(), "2001:db8:1::/48".parse::<IpNet>()?);
+        assert_eq!(subnet.scope_prefix(), 40);
+        assert_eq!(ClientSubnet::from_edns(&Edns::new())?, None);
+
+        // the address is sent up to the octet holding the last bit of the prefix
+        let malformed = opt::ClientSubnet::new("192.0.2.0".parse()?, 20, 0);
+        assert!(ClientSubnet::from_edns(&edns(malformed)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn truncates_and_scopes() -> Result<()> {
+        let subnet = ClientSubnet::new("192.0.2.77/32".parse()?);
+        assert_eq!(
+            subnet.truncate(24, 56).network(),
+            "192.0.2.0/24".parse::<IpNet>()?
+        );
+        let short = ClientSubnet::new("10.0.0.0/8".parse()?);
+        assert_eq!(short.truncate(24, 56), short);
+        assert_eq!(short.scoped(24).scope_prefix(), 8);
+        assert_eq!(
+            short.scoped(0).option(),
+            EdnsOption::Subnet(opt::ClientSubnet::new("10.0.0.0".parse()?, 8, 0))
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,46 @@
+use hickory_proto::op::Edns;
+use hickory_proto::rr::rdata::opt::{EdnsCode, EdnsOption};
+
+/// EDNS option code of Extended DNS Errors (RFC 8914).
+const OPTION_CODE: u16 = 15;
+
+// Extended DNS Error info codes
+pub(crate) const FORGED_ANSWER: u16 = 4;
+pub(crate) const DNSSEC_BOGUS: u16 = 6;
+pub(crate) const SIGNATURE_EXPIRED: u16 = 7;
+pub(crate) const SIGNATURE_NOT_YET_VALID: u16 = 8;
+pub(crate) const DNSKEY_MISSING: u16 = 9;
+pub(crate) const RRSIGS_MISSING: u16 = 10;
+pub(crate) const NSEC_MISSING: u16 = 12;
+pub(crate) const BLOCKED: u16 = 15;
+pub(crate) const PROHIBITED: u16 = 18;
+pub(crate) const NOT_AUTHORITATIVE: u16 = 20;
+pub(crate) const NOT_SUPPORTED: u16 = 21;
+pub(crate) const NO_REACHABLE_AUTHORITY: u16 = 22;
+
+/// An Extended DNS Error option with the info `code` and a text for humans.
+pub(crate) fn option(code: u16, text: impl AsRef<str>) -> EdnsOption {
+    let mut data = code.to_be_bytes().to_vec();
+    data.extend_from_slice(text.as_ref().as_bytes());
+    EdnsOption::Unknown(OPTION_CODE, data)
+}
+
+/// The Extended DNS Error of `edns`, such as the one of an upstream answer.
+pub(crate) fn from_edns(edns: &Edns) -> Option<EdnsOption> {
+    edns.option(EdnsCode::Unknown(OPTION_CODE)).cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_code_and_text() {
+        let ede = option(BLOCKED, "blocked");
+        assert_eq!(ede, EdnsOption::Unknown(15, b"\x00\x0fblocked".to_vec()));
+        let mut edns = Edns::new();
+        assert_eq!(from_edns(&edns), None);
+        edns.options_mut().insert(ede.clone());
+        assert_eq!(from_edns(&edns), Some(ede));
+    }
+}
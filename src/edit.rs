@@ -0,0 +1,245 @@
+//! Edits of the zones at runtime, shared by the admin API, the gRPC API
+//! and the control socket.
+
+// each API makes some of the edits, and the APIs are optional
+#![cfg_attr(not(all(feature = "admin", feature = "grpc")), allow(dead_code))]
+
+use crate::config;
+use crate::dns::RecordError;
+use crate::zones::{RecordChange, ZoneSet};
+use anyhow::anyhow;
+use hickory_proto::rr;
+use hickory_proto::rr::{LowerName, RecordSet};
+use hickory_server::authority::Authority;
+use serde::Deserialize;
+use std::str::FromStr;
+
+/// Why an edit of the zones was refused.
+pub(crate) enum EditError {
+    NotFound(String),
+    BadRequest(anyhow::Error),
+}
+
+impl From<anyhow::Error> for EditError {
+    fn from(e: anyhow::Error) -> Self {
+        EditError::BadRequest(e)
+    }
+}
+
+pub(crate) type EditResult<T> = Result<T, EditError>;
+
+pub(crate) async fn zone_names(zones: &ZoneSet) -> Vec<String> {
+    let mut names: Vec<String> = zones.zones().await.into_keys().collect();
+    names.sort();
+    names
+}
+
+pub(crate) async fn zone(zones: &ZoneSet, zone: &str) -> EditResult<config::ZoneConfig> {
+    let mut all = zones.zones().await;
+    let key = zone_key(&all, zone)?;
+    Ok(all.remove(&key).unwrap())
+}
+
+pub(crate) async fn put_zone(
+    zones: &ZoneSet,
+    zone: &str,
+    config: config::ZoneConfig,
+) -> EditResult<()> {
+    rr::Name::from_str(zone).map_err(|e| anyhow!("invalid zone name {}: {}", zone, e))?;
+    zones
+        .edit(|zones| {
+            let key = zone_key(zones, zone).unwrap_or_else(|_| zone.to_string());
+            zones.insert(key, config);
+            Ok(())
+        })
+        .await
+}
+
+/// Adds the zone `zone`, refusing to replace a zone of the same name.
+pub(crate) async fn add_zone(
+    zones: &ZoneSet,
+    zone: &str,
+    config: config::ZoneConfig,
+) -> EditResult<()> {
+    rr::Name::from_str(zone).map_err(|e| anyhow!("invalid zone name {}: {}", zone, e))?;
+    zones
+        .edit(|zones| {
+            if zone_key(zones, zone).is_ok() {
+                return Err(EditError::BadRequest(anyhow!(
+                    "zone {} already exists",
+                    zone
+                )));
+            }
+            zones.insert(zone.to_string(), config);
+            Ok(())
+        })
+        .await
+}
+
+pub(crate) async fn remove_zone(zones: &ZoneSet, zone: &str) -> EditResult<()> {
+    zones
+        .edit(|zones| {
+            let key = zone_key(zones, zone)?;
+            zones.remove(&key);
+            Ok(())
+        })
+        .await
+}
+
+/// An edit of the records of a zone, as applied by `apply_batch`.
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub(crate) enum RecordEdit {
+    Add {
+        record: config::Record,
+    },
+    /// Replaces every record with the owner name and type of the record.
+    Replace {
+        record: config::Record,
+    },
+    Remove {
+        record: config::Record,
+    },
+}
+
+/// The origin and config of `zone` when it is a `sqlite` zone, whose
+/// records are kept in its database rather than in the config, so record
+/// edits apply to the served authority. The records of a `redis` zone are
+/// edited in Redis only.
+async fn stored_zone(
+    zones: &ZoneSet,
+    zone: &str,
+) -> EditResult<Option<(rr::Name, config::ZoneConfig)>> {
+    let zone_config = self::zone(zones, zone).await?;
+    match zone_config.backend() {
+        config::ZoneBackend::Memory => return Ok(None),
+        config::ZoneBackend::Redis => {
+            return Err(anyhow!("records of redis zone {} are edited in Redis", zone).into())
+        }
+        config::ZoneBackend::Sqlite => {}
+    }
+    let origin =
+        rr::Name::from_str(zone).map_err(|e| anyhow!("invalid zone name {}: {}", zone, e))?;
+    zones
+        .find(&origin)
+        .filter(|authority| *authority.origin() == LowerName::from(&origin))
+        .ok_or_else(|| EditError::NotFound(format!("no such zone: {}", zone)))?;
+    Ok(Some((origin, zone_config)))
+}
+
+pub(crate) async fn add_record(
+    zones: &ZoneSet,
+    zone: &str,
+    record: config::Record,
+) -> EditResult<()> {
+    apply_batch(zones, zone, vec![RecordEdit::Add { record }]).await
+}
+
+/// Replaces every record with the owner name and type of `record`.
+pub(crate) async fn replace_record(
+    zones: &ZoneSet,
+    zone: &str,
+    record: config::Record,
+) -> EditResult<()> {
+    apply_batch(zones, zone, vec![RecordEdit::Replace { record }]).await
+}
+
+pub(crate) async fn remove_record(
+    zones: &ZoneSet,
+    zone: &str,
+    record: config::Record,
+) -> EditResult<()> {
+    apply_batch(zones, zone, vec![RecordEdit::Remove { record }]).await
+}
+
+/// Applies the edits of `batch` to the records of `zone` together, so that
+/// queries see either none or all of them. A removed record that doesn't
+/// exist fails the batch, leaving the zone as it was.
+pub(crate) async fn apply_batch(
+    zones: &ZoneSet,
+    zone: &str,
+    batch: Vec<RecordEdit>,
+) -> EditResult<()> {
+    if let Some((origin, zone_config)) = stored_zone(zones, zone).await? {
+        let to_record = |record: config::Record| zone_config.build_record(&record, &origin);
+        let changes = batch
+            .into_iter()
+            .map(|edit| {
+                Ok(match edit {
+                    RecordEdit::Add { record } => RecordChange::Add(to_record(record)?),
+                    RecordEdit::Replace { record } => {
+                        RecordChange::Replace(RecordSet::from(to_record(record)?))
+                    }
+                    RecordEdit::Remove { record } => RecordChange::Remove(to_record(record)?),
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        return match zones.commit(changes).await {
+            Ok(_) => Ok(()),
+            Err(RecordError::RecordNotFound(_)) => {
+                Err(EditError::NotFound(format!("no such record in {}", zone)))
+            }
+            Err(e) => Err(anyhow::Error::from(e).into()),
+        };
+    }
+    zones
+        .edit(|zones| {
+            let records = zone_records(zones, zone)?;
+            for edit in batch {
+                match edit {
+                    RecordEdit::Add { record } => {
+                        if !records.iter().any(|r| r.same_data(&record)) {
+                            records.push(record);
+                        }
+                    }
+                    RecordEdit::Replace { record } => {
+                        records.retain(|r| !r.same_rrset(&record));
+                        records.push(record);
+                    }
+                    RecordEdit::Remove { record } => {
+                        let before = records.len();
+                        records.retain(|r| !r.same_data(&record));
+                        if records.len() == before {
+                            return Err(EditError::NotFound(format!("no such record in {}", zone)));
+                        }
+                    }
+                }
+            }
+            Ok(())
+        })
+        .await
+}
+
+/// Finds the key of `zone` in `zones`, ignoring case and a trailing dot.
+fn zone_key(zones: &config::Zone, zone: &str) -> EditResult<String> {
+    let fqdn = |name: &str| {
+        let mut name = rr::Name::from_str(name).ok()?;
+        name.set_fqdn(true);
+        Some(name)
+    };
+    let wanted = fqdn(zone);
+    zones
+        .keys()
+        .find(|key| match (&wanted, fqdn(key)) {
+            (Some(wanted), Some(name)) => *wanted == name,
+            _ => key.as_str() == zone,
+        })
+        .cloned()
+        .ok_or_else(|| EditError::NotFound(format!("no such zone: {}", zone)))
+}
+
+fn zone_records<'a>(
+    zones: &'a mut config::Zone,
+    zone: &str,
+) -> EditResult<&'a mut Vec<config::Record>> {
+    let key = zone_key(zones, zone)?;
+    let zone_config = zones.get_mut(&key).unwrap();
+    if zone_config.zone_type() != config::ZoneType::Primary {
+        return Err(EditError::BadRequest(anyhow!(
+            "{} zone {} is read-only",
+            zone_config.zone_type(),
+            zone
+        )));
+    }
+    Ok(zone_config.records_mut())
+}
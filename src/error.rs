@@ -0,0 +1,90 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+
+type BoxError = Box<dyn StdError + Send + Sync>;
+
+/// Error of building, running and reloading a [`Server`](crate::Server).
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The config is invalid or its file can't be read or parsed.
+    Config(BoxError),
+    /// A listener couldn't be bound to its address.
+    Bind { address: String, source: io::Error },
+    /// The zones of the config couldn't be built or served.
+    Zone(BoxError),
+    /// A configured record couldn't be converted to a DNS record.
+    Record { name: String, source: BoxError },
+    /// The listeners failed while shutting down.
+    Io(io::Error),
+}
+
+impl Error {
+    /// An `Error` raised below `e` keeps its kind, any other error becomes
+    /// a `kind` error.
+    pub(crate) fn classify(e: anyhow::Error, kind: fn(BoxError) -> Self) -> Self {
+        match e.downcast::<Self>() {
+            Ok(e) => e,
+            Err(e) => kind(e.into()),
+        }
+    }
+
+    pub(crate) fn bind(address: impl fmt::Display) -> impl FnOnce(io::Error) -> Self {
+        move |source| Error::Bind {
+            address: address.to_string(),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config(e) | Error::Zone(e) => e.fmt(f),
+            Error::Bind { address, .. } => write!(f, "failed to bind {}", address),
+            Error::Record { name, .. } => write!(f, "invalid record {}", name),
+            Error::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::Config(e) | Error::Zone(e) => e.source(),
+            Error::Bind { source, .. } => Some(source),
+            Error::Record { source, .. } => Some(source.as_ref()),
+            Error::Io(e) => e.source(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn keeps_kind_of_wrapped_errors() {
+        let record = Error::Record {
+            name: "www.et.internal".to_string(),
+            source: "bad address".into(),
+        };
+        let wrapped = anyhow::Error::new(record).context("invalid zone et.internal");
+        assert!(matches!(
+            Error::classify(wrapped, Error::Zone),
+            Error::Record { name, .. } if name == "www.et.internal"
+        ));
+
+        let other = Err::<(), _>(anyhow::anyhow!("duplicate view internal"))
+            .context("invalid views")
+            .unwrap_err();
+        let e = Error::classify(other, Error::Zone);
+        assert!(matches!(e, Error::Zone(_)));
+        assert_eq!(
+            format!("{:#}", anyhow::Error::new(e)),
+            "invalid views: duplicate view internal"
+        );
+    }
+}
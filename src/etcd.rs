@@ -0,0 +1,505 @@
+use crate::config::{self, EtcdConfig};
+use crate::discovery::{self, address_record};
+use crate::zones::ZoneSet;
+#[cfg(feature = "etcd")]
+use anyhow::Context;
+use anyhow::{bail, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hickory_proto::rr;
+use hickory_proto::rr::RData;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+/// Serves zones from the records kept under a key prefix of etcd, listing
+/// them once and following their changes with the watch API.
+pub(crate) struct Etcd {
+    prefix: String,
+    zones: Vec<rr::Name>,
+    ttl: u32,
+    client: Client,
+}
+
+impl Etcd {
+    pub(crate) fn new(config: &EtcdConfig, zones: &config::Zone) -> Result<Self> {
+        let mut origins = Vec::new();
+        for zone in config.zones() {
+            origins.push(discovery::origin("etcd zone", zone, zones)?);
+        }
+        if origins.is_empty() {
+            bail!("etcd has no zones");
+        }
+        Ok(Self {
+            prefix: format!("{}/", config.prefix().trim_end_matches('/')),
+            zones: origins,
+            ttl: config.ttl().as_secs() as u32,
+            client: Client::new(config)?,
+        })
+    }
+
+    /// Serves the zones, empty until the records are listed, and keeps them
+    /// in sync with etcd until `token` is cancelled.
+    pub(crate) async fn start(
+        self: Arc<Self>,
+        zones: Arc<ZoneSet>,
+        token: CancellationToken,
+    ) -> Result<()> {
+        for zone in &self.zones {
+            zones.serve_records(zone, Vec::new()).await?;
+        }
+        tokio::spawn(async move {
+            let what = format!("watch etcd prefix {}", self.prefix);
+            discovery::follow(&what, &token, || self.follow(&zones)).await;
+        });
+        Ok(())
+    }
+
+    /// Lists the keys of the prefix, then applies their changes from the
+    /// revision listed on.
+    async fn follow(&self, zones: &ZoneSet) -> Result<()> {
+        let range = json!({
+            "key": STANDARD.encode(&self.prefix),
+            "range_end": STANDARD.encode(range_end(&self.prefix)),
+        });
+        let range: RangeResponse =
+            serde_json::from_slice(&self.client.post("/v3/kv/range", &range).await?)?;
+        let revision: i64 = range.header.revision.parse()?;
+        let mut values = BTreeMap::new();
+        for kv in range.kvs {
+            values.insert(kv.key()?, kv.value()?);
+        }
+        self.publish(&values, zones).await;
+        info!(
+            "listed {} etcd keys of {} at revision {}",
+            values.len(),
+            self.prefix,
+            revision
+        );
+
+        let watch = json!({ "create_request": {
+            "key": STANDARD.encode(&self.prefix),
+            "range_end": STANDARD.encode(range_end(&self.prefix)),
+            "start_revision": (revision + 1).to_string(),
+        } });
+        let mut lines = self.client.watch("/v3/watch", &watch).await?;
+        while let Some(line) = lines.next().await? {
+            let response: WatchResponse = serde_json::from_slice(&line)?;
+            let Some(result) = response.result else {
+                bail!("{}", response.error.unwrap_or_default().message);
+            };
+            if result.canceled {
+                // mostly a compacted revision, listed again
+                bail!("watch canceled: {}", result.cancel_reason);
+            }
+            if result.events.is_empty() {
+                continue;
+            }
+            for event in result.events {
+                let key = event.kv.key()?;
+                if event.kind.as_deref() == Some("DELETE") {
+                    values.remove(&key);
+                } else {
+                    values.insert(key, event.kv.value()?);
+                }
+            }
+            self.publish(&values, zones).await;
+        }
+        bail!("etcd watch ended")
+    }
+
+    async fn publish(&self, values: &BTreeMap<String, Vec<u8>>, zones: &ZoneSet) {
+        let mut services = Vec::new();
+        for (key, value) in values {
+            let Some(name) = key_name(&self.prefix, key) else {
+                debug!("skipped etcd key {}", key);
+                continue;
+            };
+            match serde_json::from_slice::<SkyService>(value) {
+                Ok(service) => services.push((name, service)),
+                Err(e) => debug!("skipped etcd key {}: {}", key, e),
+            }
+        }
+        for (zone, records) in records(&self.zones, &services, self.ttl) {
+            if let Err(e) = zones.serve_records(&zone, records).await {
+                warn!("failed to serve etcd zone {}: {:#}", zone, e);
+            }
+        }
+    }
+}
+
+/// The end of the range of keys starting with `prefix`.
+fn range_end(prefix: &str) -> Vec<u8> {
+    let mut end = prefix.as_bytes().to_vec();
+    if let Some(last) = end.last_mut() {
+        *last += 1;
+    }
+    end
+}
+
+/// The name of `key`, its path below `prefix` reversed: `/skydns/local/a/b`
+/// names `b.a.local.`.
+fn key_name(prefix: &str, key: &str) -> Option<rr::Name> {
+    let path = key.strip_prefix(prefix)?;
+    let labels: Vec<&str> = path
+        .split('/')
+        .filter(|label| !label.is_empty())
+        .rev()
+        .collect();
+    let mut name = rr::Name::from_ascii(labels.join(".")).ok()?;
+    name.set_fqdn(true);
+    Some(name)
+}
+
+/// The records of `services` by zone, every zone included. A service is
+/// answered at its own name and, as the CoreDNS etcd plugin does, at the
+/// names above it within its zone, so that `x1.db.example.` and
+/// `x2.db.example.` make up the addresses of `db.example.`.
+fn records(
+    zones: &[rr::Name],
+    services: &[(rr::Name, SkyService)],
+    ttl: u32,
+) -> BTreeMap<rr::Name, Vec<rr::Record>> {
+    let mut records: BTreeMap<rr::Name, Vec<rr::Record>> = zones
+        .iter()
+        .map(|zone| (zone.clone(), Vec::new()))
+        .collect();
+    let mut cnames = Vec::new();
+    for (name, service) in services {
+        let Some(zone) = zones
+            .iter()
+            .filter(|zone| zone.zone_of(name))
+            .max_by_key(|zone| zone.num_labels())
+        else {
+            debug!("skipped etcd record {} outside the etcd zones", name);
+            continue;
+        };
+        let ttl = service.ttl.unwrap_or(ttl);
+        let ip = service.host.parse::<IpAddr>().ok();
+        let target = match ip {
+            Some(_) => Some(name.clone()),
+            None if service.host.is_empty() => None,
+            None => rr::Name::from_ascii(&service.host).ok().map(|mut host| {
+                host.set_fqdn(true);
+                host
+            }),
+        };
+        let zone_records = records.get_mut(zone).expect("every zone is included");
+        if !service.text.is_empty() {
+            let txt = RData::TXT(rr::rdata::TXT::new(vec![service.text.clone()]));
+            zone_records.push(rr::Record::from_rdata(name.clone(), ttl, txt));
+        }
+        if ip.is_none() && !service.mail && *name != *zone {
+            if let Some(target) = &target {
+                let cname = RData::CNAME(rr::rdata::CNAME(target.clone()));
+                cnames.push((
+                    zone.clone(),
+                    rr::Record::from_rdata(name.clone(), ttl, cname),
+                ));
+            }
+        }
+        let mut owner = name.clone();
+        loop {
+            if let Some(ip) = ip {
+                zone_records.push(address_record(&owner, ip, ttl));
+            }
+            if let Some(target) = &target {
+                if service.port != 0 {
+                    let srv = rr::rdata::SRV::new(
+                        service.priority,
+                        service.weight,
+                        service.port,
+                        target.clone(),
+                    );
+                    zone_records.push(rr::Record::from_rdata(owner.clone(), ttl, RData::SRV(srv)));
+                }
+                if service.mail {
+                    let mx = rr::rdata::MX::new(service.priority, target.clone());
+                    zone_records.push(rr::Record::from_rdata(owner.clone(), ttl, RData::MX(mx)));
+                }
+            }
+            if owner == *zone {
+                break;
+            }
+            owner = owner.base_name();
+        }
+    }
+    // a CNAME can't share its name with other records
+    for (zone, cname) in cnames {
+        let zone_records = records.get_mut(&zone).expect("every zone is included");
+        if zone_records
+            .iter()
+            .any(|record| record.name() == cname.name())
+        {
+            debug!("skipped etcd CNAME {} sharing its name", cname.name());
+            continue;
+        }
+        zone_records.push(cname);
+    }
+    records
+}
+
+/// A record value of the SkyDNS schema.
+#[derive(Deserialize, Debug)]
+struct SkyService {
+    /// Address of the name, or the name it is an alias of.
+    #[serde(default)]
+    host: String,
+    #[serde(default)]
+    port: u16,
+    #[serde(default = "default_priority")]
+    priority: u16,
+    #[serde(default = "default_weight")]
+    weight: u16,
+    #[serde(default)]
+    text: String,
+    /// Whether `host` is a mail exchanger of the name.
+    #[serde(default)]
+    mail: bool,
+    #[serde(default)]
+    ttl: Option<u32>,
+}
+
+fn default_priority() -> u16 {
+    10
+}
+
+fn default_weight() -> u16 {
+    100
+}
+
+#[derive(Deserialize)]
+struct ResponseHeader {
+    #[serde(default)]
+    revision: String,
+}
+
+#[derive(Deserialize)]
+struct RangeResponse {
+    header: ResponseHeader,
+    #[serde(default)]
+    kvs: Vec<KeyValue>,
+}
+
+/// A key and its value, base64 encoded by the JSON gateway.
+#[derive(Deserialize)]
+struct KeyValue {
+    key: String,
+    #[serde(default)]
+    value: String,
+}
+
+impl KeyValue {
+    fn key(&self) -> Result<String> {
+        Ok(String::from_utf8(STANDARD.decode(&self.key)?)?)
+    }
+
+    fn value(&self) -> Result<Vec<u8>> {
+        Ok(STANDARD.decode(&self.value)?)
+    }
+}
+
+#[derive(Deserialize)]
+struct WatchResponse {
+    #[serde(default)]
+    result: Option<WatchResult>,
+    #[serde(default)]
+    error: Option<GatewayError>,
+}
+
+#[derive(Deserialize, Default)]
+struct GatewayError {
+    #[serde(default)]
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct WatchResult {
+    #[serde(default)]
+    canceled: bool,
+    #[serde(default)]
+    cancel_reason: String,
+    #[serde(default)]
+    events: Vec<WatchEvent>,
+}
+
+/// A change of a key, a put unless `kind` is `DELETE`.
+#[derive(Deserialize)]
+struct WatchEvent {
+    #[serde(default, rename = "type")]
+    kind: Option<String>,
+    kv: KeyValue,
+}
+
+/// Client of the JSON gateway of etcd, trying the endpoints in order.
+#[cfg(feature = "etcd")]
+struct Client {
+    http: reqwest::Client,
+    endpoints: Vec<String>,
+}
+
+#[cfg(feature = "etcd")]
+impl Client {
+    fn new(config: &EtcdConfig) -> Result<Self> {
+        if config.endpoints().is_empty() {
+            bail!("etcd has no endpoints");
+        }
+        let mut http = reqwest::Client::builder();
+        if let Some(file) = config.ca_file() {
+            let pem = std::fs::read(file)
+                .with_context(|| format!("failed to read {}", file.display()))?;
+            http = http.add_root_certificate(reqwest::Certificate::from_pem(&pem)?);
+        }
+        Ok(Self {
+            http: http.build()?,
+            endpoints: config
+                .endpoints()
+                .iter()
+                .map(|endpoint| endpoint.trim_end_matches('/').to_string())
+                .collect(),
+        })
+    }
+
+    async fn request(&self, path: &str, body: &serde_json::Value) -> Result<reqwest::Response> {
+        let mut error = None;
+        for endpoint in &self.endpoints {
+            let url = format!("{}{}", endpoint, path);
+            let request = self
+                .http
+                .post(&url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.to_string());
+            match request.send().await {
+                Ok(response) => return Ok(response.error_for_status()?),
+                Err(e) => {
+                    debug!("failed to reach etcd at {}: {}", endpoint, e);
+                    error = Some(e);
+                }
+            }
+        }
+        Err(error.expect("there are endpoints").into())
+    }
+
+    async fn post(&self, path: &str, body: &serde_json::Value) -> Result<Vec<u8>> {
+        Ok(self.request(path, body).await?.bytes().await?.to_vec())
+    }
+
+    async fn watch(&self, path: &str, body: &serde_json::Value) -> Result<Lines> {
+        Ok(Lines::new(self.request(path, body).await?))
+    }
+}
+
+/// The responses of a watch, one JSON object per line.
+#[cfg(feature = "etcd")]
+type Lines = discovery::Lines<reqwest::Response>;
+
+#[cfg(not(feature = "etcd"))]
+enum Client {}
+
+#[cfg(not(feature = "etcd"))]
+impl Client {
+    fn new(_config: &EtcdConfig) -> Result<Self> {
+        bail!("the etcd backend requires the `etcd` feature")
+    }
+
+    async fn post(&self, _path: &str, _body: &serde_json::Value) -> Result<Vec<u8>> {
+        match *self {}
+    }
+
+    async fn watch(&self, _path: &str, _body: &serde_json::Value) -> Result<Lines> {
+        match *self {}
+    }
+}
+
+#[cfg(not(feature = "etcd"))]
+type Lines = discovery::Lines<std::convert::Infallible>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn maps_keys_to_names() {
+        assert_eq!(range_end("/skydns/"), b"/skydns0");
+        assert_eq!(
+            key_name("/skydns/", "/skydns/local/example/www"),
+            Some(rr::Name::from_str("www.example.local.").unwrap())
+        );
+        assert_eq!(key_name("/skydns/", "/other/local"), None);
+    }
+
+    #[test]
+    fn synthesizes_records() -> Result<()> {
+        let zone = rr::Name::from_str("example.local.")?;
+        let service = |key: &str, value: serde_json::Value| {
+            (
+                key_name("/skydns/", key).unwrap(),
+                serde_json::from_value::<SkyService>(value).unwrap(),
+            )
+        };
+        let services = [
+            service(
+                "/skydns/local/example/db/x1",
+                json!({ "host": "10.0.0.1", "port": 5432 }),
+            ),
+            service("/skydns/local/example/db/x2", json!({ "host": "10.0.0.2" })),
+            service(
+                "/skydns/local/example/www",
+                json!({ "host": "web.example.com", "text": "hello", "ttl": 60 }),
+            ),
+            service(
+                "/skydns/local/example/api",
+                json!({ "host": "api.example.com" }),
+            ),
+            service("/skydns/local/example/note", json!({ "text": "v=1" })),
+            service(
+                "/skydns/local/example/mx",
+                json!({ "host": "mail.example.com", "mail": true }),
+            ),
+            service("/skydns/local/other/www", json!({ "host": "10.0.0.9" })),
+        ];
+        let records = records(std::slice::from_ref(&zone), &services, 300);
+        assert_eq!(records.len(), 1);
+        let records = &records[&zone];
+        let find = |name: &str| -> Vec<String> {
+            let name = rr::Name::from_str(name).unwrap();
+            records
+                .iter()
+                .filter(|r| r.name() == &name)
+                .map(|r| format!("{} {} {}", r.ttl(), r.record_type(), r.data().unwrap()))
+                .collect()
+        };
+        assert_eq!(
+            find("x1.db.example.local."),
+            ["300 A 10.0.0.1", "300 SRV 10 100 5432 x1.db.example.local."]
+        );
+        assert_eq!(
+            find("db.example.local."),
+            [
+                "300 A 10.0.0.1",
+                "300 SRV 10 100 5432 x1.db.example.local.",
+                "300 A 10.0.0.2"
+            ]
+        );
+        // a CNAME is left out where other records are
+        assert_eq!(find("www.example.local."), ["60 TXT hello"]);
+        assert_eq!(find("api.example.local."), ["300 CNAME api.example.com."]);
+        assert_eq!(find("note.example.local."), ["300 TXT v=1"]);
+        assert_eq!(find("mx.example.local."), ["300 MX 10 mail.example.com."]);
+        assert_eq!(
+            find("example.local."),
+            [
+                "300 A 10.0.0.1",
+                "300 SRV 10 100 5432 x1.db.example.local.",
+                "300 A 10.0.0.2",
+                "300 MX 10 mail.example.com."
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,1273 @@
+use crate::cache::{CacheKey, CacheStats, Flush, ResponseCache};
+use crate::config::{self, ForwardConfig, ForwardMode, Upstream, ZoneType};
+use crate::connections::Connections;
+use crate::ecs::ClientSubnet;
+use crate::stub::StubZone;
+use crate::ttl::TtlLimits;
+use crate::upstream::{UpstreamHealth, UpstreamStats};
+use crate::validate::{Security, Validator};
+use anyhow::{anyhow, bail, Context, Result};
+use hickory_proto::op::{Edns, Message, MessageType, OpCode, Query, ResponseCode};
+use hickory_proto::rr::{LowerName, Name, Record, RecordType};
+use hickory_proto::udp::UdpClientStream;
+use hickory_proto::xfer::{DnsExchange, DnsHandle, DnsRequest, DnsRequestOptions, FirstAnswer};
+use hickory_proto::TokioTime;
+use ipnet::IpNet;
+use rand::Rng;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tokio::sync::{broadcast, mpsc};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, info_span, instrument, warn, Instrument, Span};
+
+/// Proxies queries for names outside the local zones to upstream resolvers.
+pub(crate) struct Forwarder {
+    upstreams: Vec<Upstream>,
+    /// The forward rules and the forward and stub zones, most specific
+    /// domain first.
+    rules: Vec<Rule>,
+    timeout: Duration,
+    /// Whether the names of UDP queries are sent in random case.
+    randomize_case: bool,
+    cache: Option<ResponseCache>,
+    /// File the cache is saved to on shutdown.
+    cache_file: Option<PathBuf>,
+    /// Queries whose popular cached answers are about to expire.
+    prefetches: Option<Prefetches>,
+    serve_stale: bool,
+    /// Bounds of the TTLs of the upstream answers.
+    ttl: TtlLimits,
+    validator: Option<Validator>,
+    /// IPv4 and IPv6 prefixes of the client subnets sent upstream.
+    client_subnet: Option<(u8, u8)>,
+    inflight: Inflight,
+    health: UpstreamHealth,
+    /// Open connections to the TCP and DNS-over-TLS upstreams.
+    connections: Connections,
+    /// Client of the DNS-over-HTTPS upstreams, if any.
+    https: Option<HttpsClient>,
+    /// The local data, by name.
+    local: HashMap<LowerName, Vec<Record>>,
+}
+
+/// Where the queries of the names of a domain are sent.
+struct Rule {
+    domain: LowerName,
+    route: Route,
+    /// Whether it is a forward or stub zone, which takes precedence over the
+    /// local zones above it.
+    zone: bool,
+}
+
+enum Route {
+    /// To these upstreams, and then to the default ones when `first`.
+    Forward {
+        upstreams: Vec<Upstream>,
+        first: bool,
+    },
+    Stub(StubZone),
+}
+
+impl Forwarder {
+    /// The forwarder of `config`, and of the forward and stub zones of
+    /// `zones`.
+    pub(crate) fn new(config: &ForwardConfig, zones: &config::Zone) -> Result<Self> {
+        let mut rules = config
+            .rules()
+            .iter()
+            .map(|rule| {
+                Ok(Rule {
+                    domain: LowerName::from_str(rule.domain())?,
+                    route: Route::Forward {
+                        upstreams: rule.upstream_servers()?,
+                        first: false,
+                    },
+                    zone: false,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        for (domain, zone_config) in zones {
+            let route = match zone_config.zone_type() {
+                ZoneType::Forward => {
+                    let upstreams = zone_config
+                        .forwarder_servers()
+                        .with_context(|| format!("invalid forwarders of zone {}", domain))?;
+                    // as in BIND, a zone without forwarders isn't forwarded
+                    // anywhere special
+                    let first = upstreams.is_empty() || zone_config.forward() == ForwardMode::First;
+                    Route::Forward { upstreams, first }
+                }
+                ZoneType::Stub => {
+                    let primaries = zone_config
+                        .primary_addrs()
+                        .with_context(|| format!("invalid primaries of zone {}", domain))?;
+                    if primaries.is_empty() {
+                        bail!("stub zone {} has no primaries", domain);
+                    }
+                    Route::Stub(StubZone::new(LowerName::from_str(domain)?, primaries))
+                }
+                ZoneType::Primary | ZoneType::Secondary => continue,
+            };
+            let domain = LowerName::from_str(domain)?;
+            if rules.iter().any(|rule| rule.domain == domain) {
+                bail!("zone {} is also a forward rule", domain);
+            }
+            rules.push(Rule {
+                domain,
+                route,
+                zone: true,
+            });
+        }
+        rules.sort_by_key(|rule| std::cmp::Reverse(rule.domain.num_labels()));
+        let upstreams = config.upstream_servers()?;
+        let all: Vec<&Upstream> = upstreams
+            .iter()
+            .chain(rules.iter().flat_map(|rule| match &rule.route {
+                Route::Forward { upstreams, .. } => upstreams.as_slice(),
+                Route::Stub(_) => &[],
+            }))
+            .collect();
+        let tls = all.iter().any(|u| matches!(u, Upstream::Tls { .. }));
+        let connections = Connections::new(config, tls)?;
+        let https = match all.iter().any(|u| matches!(u, Upstream::Https(_))) {
+            true => Some(HttpsClient::new(config)?),
+            false => None,
+        };
+        let mut local: HashMap<LowerName, Vec<Record>> = HashMap::new();
+        for record in config.local_data() {
+            let record = record.to_record(&Name::root())?;
+            local
+                .entry(LowerName::from(record.name()))
+                .or_default()
+                .push(record);
+        }
+        let cache = NonZeroUsize::new(config.cache().max_entries()).map(|max_entries| {
+            let max_stale = if config.serve_stale() {
+                config.max_stale()
+            } else {
+                Duration::ZERO
+            };
+            let cache = ResponseCache::new(max_entries, max_stale);
+            match config.cache().prefetch() {
+                Some(prefetch) => cache.with_prefetch(prefetch.hits(), prefetch.window()),
+                None => cache,
+            }
+        });
+        let prefetches = (cache.is_some() && config.cache().prefetch().is_some()).then(|| {
+            let (sender, receiver) = mpsc::channel(MAX_PREFETCHES);
+            Prefetches {
+                sender,
+                receiver: Mutex::new(Some(receiver)),
+            }
+        });
+        let cache_file = config.cache().persist_file().map(Path::to_path_buf);
+        if let (Some(cache), Some(path)) = (&cache, &cache_file) {
+            load_cache(cache, path);
+        }
+        Ok(Self {
+            local,
+            health: UpstreamHealth::new(config, all),
+            connections,
+            https,
+            upstreams,
+            rules,
+            timeout: config.timeout(),
+            randomize_case: config.randomize_case(),
+            cache,
+            cache_file,
+            prefetches,
+            serve_stale: config.serve_stale(),
+            ttl: TtlLimits::new(config.cache().min_ttl(), config.cache().max_ttl())
+                .context("invalid TTL limits of the forward cache")?,
+            validator: Validator::new(config)?,
+            client_subnet: config
+                .client_subnet()
+                .as_ref()
+                .map(|ecs| (ecs.ipv4_prefix(), ecs.ipv6_prefix())),
+            inflight: Inflight::default(),
+        })
+    }
+
+    /// The most specific rule matching `name`.
+    fn rule(&self, name: &LowerName) -> Option<&Rule> {
+        self.rules.iter().find(|rule| rule.domain.zone_of(name))
+    }
+
+    /// Upstreams responsible for `name`, in the order they are tried: those
+    /// of the most specific matching rule, followed by the default ones for
+    /// a forward-first zone, or the default upstreams. None for the names of
+    /// stub zones.
+    fn upstreams_for(&self, name: &LowerName) -> Vec<&Upstream> {
+        match self.rule(name).map(|rule| &rule.route) {
+            Some(Route::Forward { upstreams, first }) => {
+                let mut order = self.health.order(upstreams);
+                if *first {
+                    for upstream in self.health.order(&self.upstreams) {
+                        if !order.contains(&upstream) {
+                            order.push(upstream);
+                        }
+                    }
+                }
+                order
+            }
+            Some(Route::Stub(_)) => Vec::new(),
+            None => self.health.order(&self.upstreams),
+        }
+    }
+
+    /// The local data of the name of `query`, when it has some: its records
+    /// of the queried type, or its CNAME.
+    pub(crate) fn local_answer(&self, query: &Query) -> Option<Vec<Record>> {
+        let records = self.local.get(&LowerName::from(query.name()))?;
+        let query_type = query.query_type();
+        let answers = records
+            .iter()
+            .filter(|record| {
+                let record_type = record.record_type();
+                query_type == RecordType::ANY
+                    || record_type == query_type
+                    || record_type == RecordType::CNAME
+            })
+            .map(|record| {
+                let mut record = record.clone();
+                record.set_name(query.name().clone());
+                record
+            })
+            .collect();
+        Some(answers)
+    }
+
+    /// Whether any upstream or stub zone is configured for `name`.
+    pub(crate) fn handles(&self, name: &LowerName) -> bool {
+        matches!(
+            self.rule(name),
+            Some(Rule {
+                route: Route::Stub(_),
+                ..
+            })
+        ) || !self.upstreams_for(name).is_empty()
+    }
+
+    /// Whether `name` belongs to a forward or stub zone below the local zone
+    /// `origin`, which then doesn't answer it.
+    pub(crate) fn overrides(&self, origin: &LowerName, name: &LowerName) -> bool {
+        self.rule(name)
+            .is_some_and(|rule| rule.zone && rule.domain.num_labels() > origin.num_labels())
+    }
+
+    /// Answers `query` from the cache, or sends it to each responsible
+    /// upstream in turn, ordered by their health and `policy`, until one
+    /// answers, retrying plain upstreams over TCP when the UDP answer is
+    /// truncated.
+    pub(crate) async fn forward(&self, query: &Query) -> Result<Message> {
+        self.forward_from(query, None).await
+    }
+
+    /// Forwards `query` on behalf of a client of the network `client`, which
+    /// is sent upstream, shortened, when client subnets are enabled. The
+    /// identical queries arriving while it is forwarded share its answer.
+    #[instrument(
+        name = "forward",
+        skip_all,
+        fields(
+            qname = %query.name(),
+            qtype = %query.query_type(),
+            cached = false,
+            deduplicated = false
+        )
+    )]
+    pub(crate) async fn forward_from(
+        &self,
+        query: &Query,
+        client: Option<ClientSubnet>,
+    ) -> Result<Message> {
+        let subnet = self
+            .client_subnet
+            .zip(client)
+            .map(|((ipv4_prefix, ipv6_prefix), client)| client.truncate(ipv4_prefix, ipv6_prefix));
+        let key = subnet.map(|subnet| subnet.network());
+        if let Some((response, prefetch)) = self.cache.as_ref().and_then(|c| c.lookup(query, key)) {
+            Span::current().record("cached", true);
+            if prefetch {
+                self.request_prefetch(query, subnet);
+            }
+            return Ok(response);
+        }
+        let mut receiver = match self.inflight.join(CacheKey::new(query, key)) {
+            Joined::Leader(leader) => {
+                let response = self.forward_missed(query, subnet, key).await;
+                leader.finish(&response);
+                return response;
+            }
+            Joined::Follower(receiver) => receiver,
+        };
+        match receiver.recv().await {
+            Ok(response) => {
+                Span::current().record("deduplicated", true);
+                response.map_err(|e| anyhow!(e))
+            }
+            // the first query was cancelled before it was answered
+            Err(_) => self.forward_missed(query, subnet, key).await,
+        }
+    }
+
+    fn request_prefetch(&self, query: &Query, subnet: Option<ClientSubnet>) {
+        let Some(prefetches) = &self.prefetches else {
+            return;
+        };
+        if prefetches.sender.try_send((query.clone(), subnet)).is_err() {
+            debug!("too many prefetches, not prefetching {}", query);
+        }
+    }
+
+    /// Forwards the queries whose cached answers are prefetched, until
+    /// `token` is cancelled. A query being forwarded already isn't sent
+    /// again.
+    pub(crate) async fn prefetch(self: Arc<Self>, token: CancellationToken) {
+        let receiver = self
+            .prefetches
+            .as_ref()
+            .map(|p| p.receiver.lock().unwrap().take());
+        let Some(Some(mut receiver)) = receiver else {
+            return;
+        };
+        loop {
+            let (query, subnet) = tokio::select! {
+                received = receiver.recv() => match received {
+                    Some(received) => received,
+                    None => break,
+                },
+                _ = token.cancelled() => break,
+            };
+            let forwarder = self.clone();
+            tokio::spawn(async move {
+                let key = subnet.map(|subnet| subnet.network());
+                if let Joined::Leader(leader) = forwarder.inflight.join(CacheKey::new(&query, key))
+                {
+                    debug!("prefetching {}", query);
+                    let response = forwarder.forward_missed(&query, subnet, key).await;
+                    leader.finish(&response);
+                }
+            });
+        }
+    }
+
+    /// Forwards `query` missing from the cache, caching the answer, or
+    /// answers with a stale one if forwarding fails and `serve_stale` is on.
+    async fn forward_missed(
+        &self,
+        query: &Query,
+        subnet: Option<ClientSubnet>,
+        key: Option<IpNet>,
+    ) -> Result<Message> {
+        let mut response = self.forward_uncached(query, subnet).await;
+        if let Ok(response) = &mut response {
+            self.ttl.clamp_message(response);
+        }
+        match (&response, &self.cache) {
+            (Ok(response), Some(cache)) if !is_failure(response) => {
+                cache.insert(query, key, response)
+            }
+            (_, Some(cache)) if self.serve_stale => {
+                if let Some(stale) = cache.get_stale(query, key) {
+                    debug!("serving stale answer for {}", query);
+                    return Ok(stale);
+                }
+            }
+            _ => {}
+        }
+        response
+    }
+
+    /// Checks `response`, the answer to `query`, against its DNSSEC
+    /// signatures. `None` when validation is off.
+    pub(crate) async fn validate(&self, query: &Query, response: &Message) -> Option<Security> {
+        let validator = self.validator.as_ref()?;
+        let security = validator
+            .validate(self, query, response)
+            .instrument(info_span!("validate", qname = %query.name()))
+            .await;
+        Some(security)
+    }
+
+    /// Whether bogus answers are replaced with SERVFAIL.
+    pub(crate) fn strict_validation(&self) -> bool {
+        self.validator.as_ref().is_some_and(Validator::strict)
+    }
+
+    pub(crate) fn cache_stats(&self) -> Option<CacheStats> {
+        self.cache.as_ref().map(|c| c.stats())
+    }
+
+    /// Counters of each upstream, in the order of the config.
+    pub(crate) fn upstream_stats(&self) -> Vec<UpstreamStats> {
+        self.health.stats()
+    }
+
+    /// Drops the cached answers matching `flush`, see [`ResponseCache::flush`].
+    pub(crate) fn flush_cache(&self, flush: &Flush) -> usize {
+        self.cache.as_ref().map_or(0, |c| c.flush(flush))
+    }
+
+    /// Saves the cache to its persist file, if any. The file is replaced
+    /// atomically, so a crash leaves either the old or the new entries.
+    pub(crate) async fn save_cache(&self) {
+        let (Some(cache), Some(path)) = (&self.cache, &self.cache_file) else {
+            return;
+        };
+        let text = cache.save();
+        let mut temp = path.clone().into_os_string();
+        temp.push(".tmp");
+        let result = async {
+            tokio::fs::write(&temp, text).await?;
+            tokio::fs::rename(&temp, path).await
+        };
+        match result.await {
+            Ok(()) => debug!("saved the forward cache to {}", path.display()),
+            Err(e) => warn!(
+                "failed to save the forward cache to {}: {}",
+                path.display(),
+                e
+            ),
+        }
+    }
+
+    async fn forward_uncached(
+        &self,
+        query: &Query,
+        subnet: Option<ClientSubnet>,
+    ) -> Result<Message> {
+        let message = request(query, self.validator.is_some(), subnet);
+        let name = LowerName::from(query.name());
+        if let Some(Rule {
+            route: Route::Stub(stub),
+            ..
+        }) = self.rule(&name)
+        {
+            return stub.resolve(self, message).await;
+        }
+        let mut last_response = None;
+        for upstream in self.upstreams_for(&name) {
+            let start = Instant::now();
+            let response = self.exchange(upstream, message.clone()).await;
+            match &response {
+                Ok(response) => {
+                    self.health
+                        .answered(upstream, start.elapsed(), is_failure(response))
+                }
+                Err(_) => self.health.failed(upstream),
+            }
+            match response {
+                Ok(response) if is_failure(&response) => {
+                    debug!(
+                        "upstream {} answered {} for {}",
+                        upstream,
+                        response.response_code(),
+                        query
+                    );
+                    last_response = Some(response);
+                }
+                Ok(response) => return Ok(response),
+                Err(e) => warn!("upstream {} failed for {}: {}", upstream, query, e),
+            }
+        }
+        last_response.ok_or_else(|| anyhow!("no upstream answered {}", query))
+    }
+
+    /// Sends `message` to `upstream`, retrying over TCP when the UDP answer
+    /// of a plain upstream is truncated or, with `randomize_case`, changed
+    /// the case of the name.
+    pub(crate) async fn exchange(&self, upstream: &Upstream, message: Message) -> Result<Message> {
+        match upstream {
+            Upstream::Plain(address) => {
+                let sent = match self.randomize_case {
+                    true => with_random_case(&message)?,
+                    false => message.clone(),
+                };
+                let response = self.exchange_udp(*address, sent.clone()).await?;
+                match answered(&sent, &response, self.randomize_case) {
+                    Answered::Question if response.truncated() => {
+                        debug!("truncated answer from {}, retrying over tcp", upstream)
+                    }
+                    Answered::Question => return Ok(restore_case(response, &message)),
+                    Answered::OtherCase => {
+                        debug!(
+                            "{} changed the case of the name, retrying over tcp",
+                            upstream
+                        )
+                    }
+                    Answered::OtherQuestion => {
+                        bail!("answer from {} to another question", upstream)
+                    }
+                }
+                let response = self.exchange_tcp(*address, message.clone()).await?;
+                match answered(&message, &response, false) {
+                    Answered::Question => Ok(response),
+                    _ => bail!("answer from {} to another question", upstream),
+                }
+            }
+            Upstream::Tls { .. } => self.exchange_tls(upstream, message).await,
+            Upstream::Https(url) => {
+                let https = self.https.as_ref().expect("https upstreams have a client");
+                https.exchange(url, message).await
+            }
+        }
+    }
+
+    #[instrument(name = "upstream", skip_all, fields(%upstream, transport = "udp"))]
+    async fn exchange_udp(&self, upstream: SocketAddr, message: Message) -> Result<Message> {
+        let stream = UdpClientStream::<UdpSocket>::with_timeout(upstream, self.timeout);
+        let (exchange, background) = DnsExchange::connect::<_, _, TokioTime>(stream).await?;
+        let background = tokio::spawn(background);
+        let response = send(&exchange, message).await;
+        background.abort();
+        response
+    }
+
+    #[instrument(name = "upstream", skip_all, fields(%upstream, transport = "tcp"))]
+    async fn exchange_tcp(&self, upstream: SocketAddr, message: Message) -> Result<Message> {
+        self.connections
+            .exchange(&Upstream::Plain(upstream), message)
+            .await
+    }
+
+    #[instrument(name = "upstream", skip_all, fields(%upstream, transport = "tls"))]
+    async fn exchange_tls(&self, upstream: &Upstream, message: Message) -> Result<Message> {
+        self.connections.exchange(upstream, message).await
+    }
+}
+
+/// The query sent upstream for `query`, with the DO bit set when
+/// `dnssec_ok`, so that signatures come along with the answer, and the
+/// client `subnet` if any.
+pub(crate) fn request(query: &Query, dnssec_ok: bool, subnet: Option<ClientSubnet>) -> Message {
+    let mut message = Message::new();
+    message
+        .add_query(query.clone())
+        .set_message_type(MessageType::Query)
+        .set_op_code(OpCode::Query)
+        .set_recursion_desired(true);
+    let mut edns = Edns::new();
+    edns.set_max_payload(1232).set_dnssec_ok(dnssec_ok);
+    if let Some(subnet) = subnet {
+        edns.options_mut().insert(subnet.option());
+    }
+    message.set_edns(edns);
+    message
+}
+
+/// `message` with the letters of its question name in random case, as
+/// draft-vixie-dnsext-dns0x20 proposes.
+fn with_random_case(message: &Message) -> Result<Message> {
+    let mut message = message.clone();
+    let mut rng = rand::thread_rng();
+    for query in message.queries_mut() {
+        let labels = query.name().iter().map(|label| {
+            label
+                .iter()
+                .map(|byte| match rng.gen::<bool>() {
+                    true => byte.to_ascii_uppercase(),
+                    false => byte.to_ascii_lowercase(),
+                })
+                .collect::<Vec<u8>>()
+        });
+        let mut name = Name::from_labels(labels)?;
+        name.set_fqdn(query.name().is_fqdn());
+        query.set_name(name);
+    }
+    Ok(message)
+}
+
+/// How a response relates to the question of the query it answers.
+#[derive(Debug, PartialEq, Eq)]
+enum Answered {
+    Question,
+    /// The question, its name in another case.
+    OtherCase,
+    OtherQuestion,
+}
+
+/// Whether `response` answers the question of `request`, the case of its
+/// name included when `exact`. Failures may leave the question out.
+fn answered(request: &Message, response: &Message, exact: bool) -> Answered {
+    if response.queries().is_empty() && is_failure(response) {
+        return Answered::Question;
+    }
+    if response.queries() != request.queries() {
+        return Answered::OtherQuestion;
+    }
+    let same_case = |(sent, received): (&Query, &Query)| sent.name().eq_case(received.name());
+    match !exact
+        || request
+            .queries()
+            .iter()
+            .zip(response.queries())
+            .all(same_case)
+    {
+        true => Answered::Question,
+        false => Answered::OtherCase,
+    }
+}
+
+/// `response` with the question name of `request`, and the records owned
+/// by the name in random case renamed.
+fn restore_case(mut response: Message, request: &Message) -> Message {
+    let (Some(sent), Some(original)) = (response.queries().first(), request.queries().first())
+    else {
+        return response;
+    };
+    let (sent, original) = (sent.name().clone(), original.name().clone());
+    if sent.eq_case(&original) {
+        return response;
+    }
+    let rename = |records: &mut Vec<Record>| {
+        for record in records.iter_mut().filter(|r| r.name().eq_case(&sent)) {
+            record.set_name(original.clone());
+        }
+    };
+    rename(response.answers_mut());
+    rename(response.name_servers_mut());
+    rename(response.additionals_mut());
+    *response.queries_mut() = request.queries().to_vec();
+    response
+}
+
+async fn send(exchange: &DnsExchange, message: Message) -> Result<Message> {
+    let request = DnsRequest::new(message, DnsRequestOptions::default());
+    let response = exchange.send(request).first_answer().await?;
+    Ok(response.into_message())
+}
+
+/// Client of DNS-over-HTTPS upstreams, which pools their connections.
+#[cfg(feature = "forward-https")]
+struct HttpsClient {
+    http: reqwest::Client,
+}
+
+#[cfg(feature = "forward-https")]
+impl HttpsClient {
+    fn new(config: &ForwardConfig) -> Result<Self> {
+        let mut http = reqwest::Client::builder().timeout(config.timeout());
+        if let Some(file) = config.ca_file() {
+            let pem = std::fs::read(file)
+                .with_context(|| format!("failed to read {}", file.display()))?;
+            http = http.tls_built_in_root_certs(false);
+            for cert in reqwest::Certificate::from_pem_bundle(&pem)? {
+                http = http.add_root_certificate(cert);
+            }
+        }
+        Ok(Self {
+            http: http.build()?,
+        })
+    }
+
+    /// POSTs `message` to `url`, with the message ID 0 for the answer to be
+    /// cacheable, as RFC 8484 recommends.
+    #[instrument(name = "upstream", skip_all, fields(upstream = %url, transport = "https"))]
+    async fn exchange(&self, url: &str, mut message: Message) -> Result<Message> {
+        message.set_id(0);
+        let response = self
+            .http
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/dns-message")
+            .header(reqwest::header::ACCEPT, "application/dns-message")
+            .body(message.to_vec()?)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(Message::from_vec(&response.bytes().await?)?)
+    }
+}
+
+#[cfg(not(feature = "forward-https"))]
+enum HttpsClient {}
+
+#[cfg(not(feature = "forward-https"))]
+impl HttpsClient {
+    fn new(_config: &ForwardConfig) -> Result<Self> {
+        bail!("DNS-over-HTTPS upstreams require the `forward-https` feature")
+    }
+
+    async fn exchange(&self, _url: &str, _message: Message) -> Result<Message> {
+        match *self {}
+    }
+}
+
+/// Loads the entries saved to `path` into `cache`, if it exists. A cache
+/// that can't be loaded is started empty.
+fn load_cache(cache: &ResponseCache, path: &Path) {
+    if !path.exists() {
+        return;
+    }
+    match std::fs::read_to_string(path)
+        .map_err(anyhow::Error::from)
+        .map(|text| cache.load(&text))
+    {
+        Ok(loaded) => info!("loaded {} cached answers from {}", loaded, path.display()),
+        Err(e) => warn!(
+            "failed to load the forward cache from {}: {:#}",
+            path.display(),
+            e
+        ),
+    }
+}
+
+/// Prefetches waiting to be forwarded, beyond which they are dropped.
+const MAX_PREFETCHES: usize = 1024;
+
+/// A query to prefetch, with the client subnet it was forwarded for.
+type Prefetch = (Query, Option<ClientSubnet>);
+
+/// The queries to prefetch, and their receiving end until the prefetching
+/// task takes it.
+struct Prefetches {
+    sender: mpsc::Sender<Prefetch>,
+    receiver: Mutex<Option<mpsc::Receiver<Prefetch>>>,
+}
+
+/// Queries being forwarded, by cache key, with the channel their answer is
+/// sent to the identical queries waiting for it.
+#[derive(Default)]
+struct Inflight {
+    senders: Mutex<HashMap<CacheKey, broadcast::Sender<Result<Message, String>>>>,
+}
+
+enum Joined<'a> {
+    /// The first of the identical queries, which forwards it.
+    Leader(Leader<'a>),
+    Follower(broadcast::Receiver<Result<Message, String>>),
+}
+
+impl Inflight {
+    fn join(&self, key: CacheKey) -> Joined<'_> {
+        let mut senders = self.senders.lock().unwrap();
+        if let Some(sender) = senders.get(&key) {
+            return Joined::Follower(sender.subscribe());
+        }
+        senders.insert(key.clone(), broadcast::channel(1).0);
+        Joined::Leader(Leader {
+            inflight: self,
+            key: Some(key),
+        })
+    }
+}
+
+/// The forwarding of a query, removed once done. Dropped before it
+/// finishes, its followers forward the query themselves.
+struct Leader<'a> {
+    inflight: &'a Inflight,
+    key: Option<CacheKey>,
+}
+
+impl Leader<'_> {
+    fn finish(mut self, response: &Result<Message>) {
+        let sender = self.remove();
+        if let Some(sender) = sender {
+            let response = match response {
+                Ok(response) => Ok(response.clone()),
+                Err(e) => Err(format!("{:#}", e)),
+            };
+            // there may be no followers
+            let _ = sender.send(response);
+        }
+    }
+
+    fn remove(&mut self) -> Option<broadcast::Sender<Result<Message, String>>> {
+        let key = self.key.take()?;
+        self.inflight.senders.lock().unwrap().remove(&key)
+    }
+}
+
+impl Drop for Leader<'_> {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
+
+pub(crate) fn is_failure(response: &Message) -> bool {
+    matches!(
+        response.response_code(),
+        ResponseCode::ServFail | ResponseCode::Refused | ResponseCode::NotImp
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{CacheConfigBuilder, ForwardConfigBuilder, ForwardRuleBuilder};
+    use hickory_proto::rr::{rdata, RData};
+    use hickory_proto::serialize::binary::{BinDecodable, BinEncodable};
+    use std::net::Ipv4Addr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn picks_most_specific_rule() -> Result<()> {
+        let forwarder = Forwarder::new(
+            &ForwardConfigBuilder::default()
+                .upstreams(vec!["1.1.1.1".to_string()])
+                .rules(vec![
+                    ForwardRuleBuilder::default()
+                        .domain("example")
+                        .upstreams(vec!["10.0.0.1".to_string()])
+                        .build()?,
+                    ForwardRuleBuilder::default()
+                        .domain("corp.example")
+                        .upstreams(vec!["10.0.0.2".to_string()])
+                        .build()?,
+                ])
+                .build()?,
+            &Default::default(),
+        )?;
+
+        let upstreams = |name: &str| -> Result<Vec<Upstream>> {
+            Ok(forwarder
+                .upstreams_for(&LowerName::from_str(name)?)
+                .into_iter()
+                .cloned()
+                .collect())
+        };
+        assert_eq!(
+            upstreams("www.corp.example")?,
+            vec![Upstream::Plain("10.0.0.2:53".parse()?)]
+        );
+        assert_eq!(
+            upstreams("corp.example")?,
+            vec![Upstream::Plain("10.0.0.2:53".parse()?)]
+        );
+        assert_eq!(
+            upstreams("www.example")?,
+            vec![Upstream::Plain("10.0.0.1:53".parse()?)]
+        );
+        assert_eq!(
+            upstreams("www.et.top")?,
+            vec![Upstream::Plain("1.1.1.1:53".parse()?)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn handles_only_matching_rules_without_default() -> Result<()> {
+        let forwarder = Forwarder::new(
+            &ForwardConfigBuilder::default()
+                .rules(vec![ForwardRuleBuilder::default()
+                    .domain("corp.example")
+                    .upstreams(vec!["10.0.0.2".to_string()])
+                    .build()?])
+                .build()?,
+            &Default::default(),
+        )?;
+        assert!(forwarder.handles(&LowerName::from_str("www.corp.example")?));
+        assert!(!forwarder.handles(&LowerName::from_str("www.et.top")?));
+        Ok(())
+    }
+
+    /// An upstream answering every A query with `address`, or SERVFAIL
+    /// without one.
+    async fn fake_upstream(address: Option<Ipv4Addr>) -> Result<String> {
+        let socket = UdpSocket::bind("127.0.0.1:0").await?;
+        let upstream = socket.local_addr()?;
+        tokio::spawn(async move {
+            let mut buffer = [0; 512];
+            loop {
+                let (len, from) = socket.recv_from(&mut buffer).await.unwrap();
+                let mut response = Message::from_bytes(&buffer[..len]).unwrap();
+                response.set_message_type(MessageType::Response);
+                let name = response.queries()[0].name().clone();
+                match address {
+                    Some(address) => {
+                        let a = RData::A(rdata::A(address));
+                        response.add_answer(Record::from_rdata(name, 60, a));
+                    }
+                    None => {
+                        response.set_response_code(ResponseCode::ServFail);
+                    }
+                }
+                socket
+                    .send_to(&response.to_bytes().unwrap(), from)
+                    .await
+                    .unwrap();
+            }
+        });
+        Ok(upstream.to_string())
+    }
+
+    #[tokio::test]
+    async fn forwards_zones_first_or_only() -> Result<()> {
+        use crate::config::{ZoneConfigBuilder, ZoneType};
+
+        let default = fake_upstream(Some(Ipv4Addr::new(10, 0, 0, 1))).await?;
+        let failing = fake_upstream(None).await?;
+        let zone = |forward| {
+            ZoneConfigBuilder::default()
+                .zone_type(ZoneType::Forward)
+                .forwarders(vec![failing.clone()])
+                .forward(forward)
+                .build()
+        };
+        let zones = maplit::hashmap! {
+            "first.example".to_string() => zone(ForwardMode::First)?,
+            "only.example".to_string() => zone(ForwardMode::Only)?,
+            "default.only.example".to_string() => ZoneConfigBuilder::default()
+                .zone_type(ZoneType::Forward)
+                .forward(ForwardMode::Only)
+                .build()?,
+        };
+        let forwarder = Forwarder::new(
+            &ForwardConfigBuilder::default()
+                .upstreams(vec![default])
+                .build()?,
+            &zones,
+        )?;
+
+        let forwarder = &forwarder;
+        let forward = |name: &str| {
+            let query = Query::query(Name::from_str(name).unwrap(), RecordType::A);
+            async move { forwarder.forward(&query).await }
+        };
+        assert_eq!(forward("www.first.example.").await?.answers().len(), 1);
+        let response = forward("www.only.example.").await?;
+        assert_eq!(response.response_code(), ResponseCode::ServFail);
+        // a zone without forwarders uses the default upstreams
+        assert_eq!(
+            forward("www.default.only.example.").await?.answers().len(),
+            1
+        );
+        assert!(forwarder.handles(&LowerName::from_str("www.only.example")?));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn answers_local_data() -> Result<()> {
+        use crate::testing::{assert_answers, TestServer};
+
+        let upstream = fake_upstream(Some(Ipv4Addr::new(10, 0, 0, 1))).await?;
+        let text = format!(
+            r#"
+[general]
+
+[forward]
+upstreams = ["{}"]
+
+[[forward.local_data]]
+name = "intranet.corp.example"
+type = "A"
+values = ["10.0.0.80", "10.0.0.81"]
+"#,
+            upstream
+        );
+        let server = TestServer::start(toml::from_str(&text)?).await?;
+        let response = server.query("intranet.corp.example", RecordType::A).await?;
+        assert_answers(&response, &["10.0.0.80", "10.0.0.81"]);
+        assert!(response.authoritative());
+        // no other type of the name is forwarded
+        let response = server
+            .query("intranet.corp.example", RecordType::AAAA)
+            .await?;
+        assert_answers(&response, &[]);
+        let response = server.query("www.corp.example", RecordType::A).await?;
+        assert_answers(&response, &["10.0.0.1"]);
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn persists_the_cache_across_restarts() -> Result<()> {
+        use crate::config::RunConfig;
+        use crate::testing::{assert_answers, TestServer};
+
+        let dir = tempfile::tempdir()?;
+        let config = |upstream: String| -> Result<RunConfig> {
+            let text = format!(
+                "[general]\n\n[forward]\nupstreams = [\"{}\"]\n\n[forward.cache]\npersist_file = {:?}\n",
+                upstream,
+                dir.path().join("cache.json")
+            );
+            Ok(toml::from_str(&text)?)
+        };
+        let upstream = fake_upstream(Some(Ipv4Addr::new(10, 0, 0, 1))).await?;
+        let server = TestServer::start(config(upstream)?).await?;
+        let response = server.query("www.corp.example", RecordType::A).await?;
+        assert_answers(&response, &["10.0.0.1"]);
+        server.shutdown().await?;
+        assert!(dir.path().join("cache.json").exists());
+
+        // answered from the loaded cache, not the new upstream
+        let upstream = fake_upstream(Some(Ipv4Addr::new(10, 0, 0, 2))).await?;
+        let server = TestServer::start(config(upstream)?).await?;
+        let response = server.query("www.corp.example", RecordType::A).await?;
+        assert_answers(&response, &["10.0.0.1"]);
+        let response = server.query("mail.corp.example", RecordType::A).await?;
+        assert_answers(&response, &["10.0.0.2"]);
+        assert_eq!(server.server().cache_stats().unwrap().hits, 1);
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prefetches_popular_answers() -> Result<()> {
+        use crate::config::RunConfig;
+        use crate::testing::{assert_answers, TestServer};
+
+        // answers the nth query with 10.0.0.n
+        let socket = UdpSocket::bind("127.0.0.1:0").await?;
+        let upstream = socket.local_addr()?;
+        tokio::spawn(async move {
+            let mut buffer = [0; 512];
+            for n in 1.. {
+                let (len, from) = socket.recv_from(&mut buffer).await.unwrap();
+                let mut response = Message::from_bytes(&buffer[..len]).unwrap();
+                response.set_message_type(MessageType::Response);
+                let name = response.queries()[0].name().clone();
+                let a = RData::A(rdata::A::new(10, 0, 0, n));
+                response.add_answer(Record::from_rdata(name, 60, a));
+                let response = response.to_bytes().unwrap();
+                socket.send_to(&response, from).await.unwrap();
+            }
+        });
+        let text = format!(
+            "[general]\n\n[forward]\nupstreams = [\"{}\"]\n\n[forward.cache]\nprefetch = {{ hits = 2, window = \"2m\" }}\n",
+            upstream
+        );
+        let server = TestServer::start(toml::from_str::<RunConfig>(&text)?).await?;
+        for _ in 0..3 {
+            let response = server.query("www.corp.example", RecordType::A).await?;
+            assert_answers(&response, &["10.0.0.1"]);
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let response = server.query("www.corp.example", RecordType::A).await?;
+        assert_answers(&response, &["10.0.0.2"]);
+        let stats = server.server().cache_stats().unwrap();
+        assert_eq!((stats.hits, stats.prefetches), (3, 1));
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[test]
+    fn randomizes_and_checks_the_case() -> Result<()> {
+        let name = Name::from_str("abcdefghijklmnopqrstuvwxyz.example.")?;
+        let query = Query::query(name.clone(), RecordType::A);
+        let request = self::request(&query, false, None);
+        let sent = with_random_case(&request)?;
+        let randomized = sent.queries()[0].name().clone();
+        assert_eq!(randomized, name);
+        assert!(!randomized.eq_case(&name));
+
+        let mut response = sent.clone();
+        response.set_message_type(MessageType::Response);
+        let a = RData::A(rdata::A::new(10, 0, 0, 1));
+        response.add_answer(Record::from_rdata(randomized.clone(), 60, a));
+        assert_eq!(answered(&sent, &response, true), Answered::Question);
+        let response = restore_case(response, &request);
+        assert!(response.queries()[0].name().eq_case(&name));
+        assert!(response.answers()[0].name().eq_case(&name));
+
+        // the case is only checked when randomized
+        assert_eq!(answered(&sent, &response, true), Answered::OtherCase);
+        assert_eq!(answered(&sent, &response, false), Answered::Question);
+        let other = self::request(&Query::query(name, RecordType::AAAA), false, None);
+        assert_eq!(answered(&sent, &other, false), Answered::OtherQuestion);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sends_names_in_random_case() -> Result<()> {
+        // answers the names starting with `lower` in lower case
+        let socket = UdpSocket::bind("127.0.0.1:0").await?;
+        let upstream = socket.local_addr()?;
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sent = received.clone();
+        tokio::spawn(async move {
+            let mut buffer = [0; 512];
+            loop {
+                let (len, from) = socket.recv_from(&mut buffer).await.unwrap();
+                let mut response = Message::from_bytes(&buffer[..len]).unwrap();
+                response.set_message_type(MessageType::Response);
+                let mut name = response.queries()[0].name().clone();
+                sent.lock().unwrap().push((name.clone(), from.port()));
+                if name.to_lowercase().to_string().starts_with("lower") {
+                    name = name.to_lowercase();
+                    response.queries_mut()[0].set_name(name.clone());
+                }
+                let a = RData::A(rdata::A::new(10, 0, 0, 1));
+                response.add_answer(Record::from_rdata(name, 60, a));
+                let response = response.to_bytes().unwrap();
+                socket.send_to(&response, from).await.unwrap();
+            }
+        });
+        let forwarder = Forwarder::new(
+            &ForwardConfigBuilder::default()
+                .upstreams(vec![upstream.to_string()])
+                .cache(CacheConfigBuilder::default().max_entries(0).build()?)
+                .randomize_case(true)
+                .build()?,
+            &Default::default(),
+        )?;
+        let name = Name::from_str("www.example.org.")?;
+        for _ in 0..4 {
+            let response = forwarder
+                .forward(&Query::query(name.clone(), RecordType::A))
+                .await?;
+            assert!(response.queries()[0].name().eq_case(&name));
+            assert!(response.answers()[0].name().eq_case(&name));
+        }
+        let received = received.lock().unwrap().clone();
+        assert!(received.iter().any(|(sent, _)| !sent.eq_case(&name)));
+        // a new source port for each query
+        let ports: std::collections::HashSet<u16> = received.iter().map(|(_, p)| *p).collect();
+        assert!(ports.len() > 1);
+
+        // refused over UDP, and not answered over TCP
+        let lower = Query::query(Name::from_str("lower.example.org.")?, RecordType::A);
+        assert!(forwarder.forward(&lower).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn deduplicates_concurrent_queries() -> Result<()> {
+        let socket = UdpSocket::bind("127.0.0.1:0").await?;
+        let upstream = socket.local_addr()?;
+        let received = Arc::new(AtomicUsize::new(0));
+        let counter = received.clone();
+        tokio::spawn(async move {
+            let mut buffer = [0; 512];
+            loop {
+                let (len, from) = socket.recv_from(&mut buffer).await.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                let mut response = Message::from_bytes(&buffer[..len]).unwrap();
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                let name = response.queries()[0].name().clone();
+                let a = rdata::A(Ipv4Addr::new(10, 0, 0, 1));
+                response
+                    .set_message_type(MessageType::Response)
+                    .add_answer(Record::from_rdata(name, 60, RData::A(a)));
+                socket
+                    .send_to(&response.to_bytes().unwrap(), from)
+                    .await
+                    .unwrap();
+            }
+        });
+        // without a cache, only the queries in flight together are merged
+        let forwarder = Forwarder::new(
+            &ForwardConfigBuilder::default()
+                .upstreams(vec![upstream.to_string()])
+                .cache(CacheConfigBuilder::default().max_entries(0).build()?)
+                .build()?,
+            &Default::default(),
+        )?;
+
+        let query = Query::query(Name::from_str("www.et.top.")?, RecordType::A);
+        let other = Query::query(Name::from_str("api.et.top.")?, RecordType::A);
+        let (first, second, third) = tokio::join!(
+            forwarder.forward(&query),
+            forwarder.forward(&query),
+            forwarder.forward(&other)
+        );
+        assert_eq!(first?.answers(), second?.answers());
+        assert_eq!(third?.answers().len(), 1);
+        assert_eq!(received.load(Ordering::SeqCst), 2);
+        assert!(forwarder.inflight.senders.lock().unwrap().is_empty());
+
+        forwarder.forward(&query).await?;
+        assert_eq!(received.load(Ordering::SeqCst), 3);
+        Ok(())
+    }
+
+    /// A server of www.et.internal over TLS, and over HTTPS when `https`,
+    /// with a certificate for dns.et.internal and localhost written to `dir`.
+    async fn serve_tls(dir: &std::path::Path, https: bool) -> Result<crate::Server> {
+        use crate::config::{
+            GeneralConfigBuilder, HttpsListenConfigBuilder, RecordBuilder, RecordData,
+            RunConfigBuilder, TlsListenConfigBuilder,
+        };
+
+        let names = vec!["dns.et.internal".to_string(), "localhost".to_string()];
+        let cert = rcgen::generate_simple_self_signed(names)?;
+        std::fs::write(dir.join("cert.pem"), cert.serialize_pem()?)?;
+        std::fs::write(dir.join("key.pem"), cert.serialize_private_key_pem())?;
+        let tls = TlsListenConfigBuilder::default()
+            .address("127.0.0.1:0")
+            .cert(dir.join("cert.pem"))
+            .key(dir.join("key.pem"))
+            .build()?;
+        let mut general = GeneralConfigBuilder::default();
+        match https {
+            true => general.listen_https(HttpsListenConfigBuilder::default().tls(tls).build()?),
+            false => general.listen_tls(tls),
+        };
+        let record = RecordBuilder::default()
+            .name("www".to_string())
+            .data(RecordData::A(Ipv4Addr::new(10, 0, 0, 1)))
+            .build()?;
+        let mut server = crate::Server::new(
+            RunConfigBuilder::default()
+                .general(general.build()?)
+                .zones(maplit::hashmap! {
+                    "et.internal".to_string() => vec![record].into(),
+                })
+                .build()?,
+        );
+        server.run().await?;
+        Ok(server)
+    }
+
+    #[tokio::test]
+    async fn forwards_over_tls() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut server = serve_tls(dir.path(), false).await?;
+        let address = server.tls_local_addr().unwrap();
+        let connect = |server_name: &str| {
+            Forwarder::new(
+                &ForwardConfigBuilder::default()
+                    .upstreams(vec![format!("tls://{}#{}", address, server_name)])
+                    .cache(CacheConfigBuilder::default().max_entries(0).build()?)
+                    .ca_file(dir.path().join("cert.pem"))
+                    .build()?,
+                &Default::default(),
+            )
+        };
+
+        let query = Query::query(Name::from_str("www.et.internal.")?, RecordType::A);
+        let forwarder = connect("dns.et.internal")?;
+        for _ in 0..2 {
+            let response = forwarder.forward(&query).await?;
+            assert_eq!(response.answers().len(), 1);
+        }
+        let upstream = &forwarder.upstreams[0];
+        assert_eq!(forwarder.connections.open(upstream), 1);
+
+        // the certificate isn't valid for another name
+        assert!(connect("ns.et.internal")?.forward(&query).await.is_err());
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[cfg(all(feature = "doh", feature = "forward-https"))]
+    #[tokio::test]
+    async fn forwards_over_https() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut server = serve_tls(dir.path(), true).await?;
+        let url = format!(
+            "https://localhost:{}/dns-query",
+            server.https_local_addr().unwrap().port()
+        );
+        let forwarder = Forwarder::new(
+            &ForwardConfigBuilder::default()
+                .upstreams(vec![url])
+                .ca_file(dir.path().join("cert.pem"))
+                .build()?,
+            &Default::default(),
+        )?;
+        let query = Query::query(Name::from_str("www.et.internal.")?, RecordType::A);
+        let response = forwarder.forward(&query).await?;
+        assert_eq!(response.answers().len(), 1);
+        server.shutdown().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,173 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::str::FromStr;
+
+/// Most records a single range generates.
+const MAX_RECORDS: u32 = 65536;
+
+/// Numbers of the records generated from a template, `start-stop` or
+/// `start-stop/step` as in `$GENERATE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Range {
+    start: u32,
+    stop: u32,
+    step: u32,
+}
+
+impl Range {
+    pub(crate) fn numbers(&self) -> impl Iterator<Item = u32> {
+        (self.start..=self.stop).step_by(self.step as usize)
+    }
+}
+
+impl FromStr for Range {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (bounds, step) = match s.split_once('/') {
+            Some((bounds, step)) => (bounds, step.parse()?),
+            None => (s, 1),
+        };
+        let (start, stop) = bounds
+            .split_once('-')
+            .ok_or_else(|| anyhow!("range {} is not start-stop", s))?;
+        let range = Self {
+            start: start.parse()?,
+            stop: stop.parse()?,
+            step,
+        };
+        if range.step == 0 || range.start > range.stop {
+            bail!("empty range {}", s);
+        }
+        if (range.stop - range.start) / range.step >= MAX_RECORDS {
+            bail!("range {} generates more than {} records", s, MAX_RECORDS);
+        }
+        Ok(range)
+    }
+}
+
+/// `template` for the number `n`: `$` stands for it, `${offset,width,base}`
+/// for it shifted by `offset` and padded to `width` digits in `base`, which
+/// is `d`, `o`, `x` or `X`, and `\$` for a dollar sign.
+pub(crate) fn substitute(template: &str, n: u32) -> Result<String> {
+    let mut text = String::with_capacity(template.len());
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'$') => text.push(chars.next().unwrap()),
+            '$' if chars.peek() == Some(&'{') => {
+                chars.next();
+                let modifier: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                text.push_str(
+                    &modified(&modifier, n)
+                        .with_context(|| format!("invalid modifier ${{{}}}", modifier))?,
+                );
+            }
+            '$' => text.push_str(&n.to_string()),
+            c => text.push(c),
+        }
+    }
+    Ok(text)
+}
+
+fn modified(modifier: &str, n: u32) -> Result<String> {
+    let mut parts = modifier.split(',').map(str::trim);
+    let offset: i64 = parts.next().unwrap_or_default().parse()?;
+    let width: usize = parts.next().map_or(Ok(0), str::parse)?;
+    let base = parts.next().unwrap_or("d");
+    if parts.next().is_some() {
+        bail!("too many fields");
+    }
+    let value = u32::try_from(i64::from(n) + offset)?;
+    Ok(match base {
+        "d" => format!("{:0width$}", value),
+        "o" => format!("{:0width$o}", value),
+        "x" => format!("{:0width$x}", value),
+        "X" => format!("{:0width$X}", value),
+        _ => bail!("unsupported base {}", base),
+    })
+}
+
+/// The zone file `text` with its `$GENERATE range owner [ttl] [class] type
+/// data` lines replaced with the records they stand for.
+pub(crate) fn expand(text: &str) -> Result<String> {
+    let mut expanded = String::with_capacity(text.len());
+    for (number, line) in text.lines().enumerate() {
+        let Some(directive) = line
+            .get(..9)
+            .filter(|d| d.eq_ignore_ascii_case("$GENERATE"))
+            .map(|_| &line[9..])
+            .filter(|rest| rest.starts_with(char::is_whitespace))
+        else {
+            expanded.push_str(line);
+            expanded.push('\n');
+            continue;
+        };
+        expand_line(directive, &mut expanded)
+            .with_context(|| format!("invalid $GENERATE on line {}", number + 1))?;
+    }
+    Ok(expanded)
+}
+
+fn expand_line(directive: &str, expanded: &mut String) -> Result<()> {
+    let field = |text: &str| -> Result<(String, String)> {
+        let (field, rest) = text
+            .trim_start()
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| anyhow!("missing fields"))?;
+        Ok((field.to_string(), rest.to_string()))
+    };
+    let (range, rest) = field(directive)?;
+    let (owner, rest) = field(&rest)?;
+    let range: Range = range.parse()?;
+    for n in range.numbers() {
+        expanded.push_str(&substitute(&owner, n)?);
+        expanded.push(' ');
+        expanded.push_str(&substitute(rest.trim(), n)?);
+        expanded.push('\n');
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn substitutes_numbers() -> Result<()> {
+        assert_eq!(substitute("host-$", 7)?, "host-7");
+        assert_eq!(substitute("${10,3}", 7)?, "017");
+        assert_eq!(substitute("${0,4,x}.${-1}", 255)?, "00ff.254");
+        assert_eq!(substitute(r"\$-$", 2)?, "$-2");
+        assert!(substitute("${-8}", 7).is_err());
+        assert!(substitute("${0,2,n}", 7).is_err());
+
+        let range: Range = "1-10/3".parse()?;
+        assert_eq!(range.numbers().collect::<Vec<_>>(), [1, 4, 7, 10]);
+        assert!("10-1".parse::<Range>().is_err());
+        assert!("1-10/0".parse::<Range>().is_err());
+        assert!("0-100000".parse::<Range>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn expands_generate_lines() -> Result<()> {
+        let text = "\
+$ORIGIN pool.et.internal.
+$GENERATE 1-3 host-$ 60 IN A 10.1.0.$
+$generate 4-5/1 ${0,2} CNAME host-${-3}
+";
+        assert_eq!(
+            expand(text)?,
+            "\
+$ORIGIN pool.et.internal.
+host-1 60 IN A 10.1.0.1
+host-2 60 IN A 10.1.0.2
+host-3 60 IN A 10.1.0.3
+04 CNAME host-1
+05 CNAME host-2
+"
+        );
+        assert!(expand("$GENERATE 1-3 host-$\n").is_err());
+        Ok(())
+    }
+}
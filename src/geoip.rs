@@ -0,0 +1,216 @@
+use crate::config::{GeoIpConfig, GeoSelector};
+use anyhow::Result;
+use hickory_proto::rr::{LowerName, Name, RData, Record, RecordType};
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+/// Where a client is, as far as the databases know.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct Location {
+    pub(crate) continent: Option<String>,
+    pub(crate) country: Option<String>,
+    pub(crate) asn: Option<u32>,
+}
+
+impl Location {
+    /// How closely `selector` matches the location, 0 when it doesn't: an
+    /// AS is more specific than a country, which is more than a continent.
+    fn matches(&self, selector: &GeoSelector) -> u8 {
+        match selector {
+            GeoSelector::Continent(code) if self.continent.as_ref() == Some(code) => 1,
+            GeoSelector::Country(code) if self.country.as_ref() == Some(code) => 2,
+            GeoSelector::Asn(asn) if self.asn == Some(*asn) => 3,
+            _ => 0,
+        }
+    }
+}
+
+/// The selectors of the geo-targeted records of a zone, by owner name.
+pub(crate) type GeoRecords = HashMap<LowerName, Vec<(RData, Vec<GeoSelector>)>>;
+
+/// Keeps the records of each RRset in `answers` whose selectors match
+/// `location` most closely. When none match, the records without selectors
+/// are kept, or all of them if every record has selectors.
+///
+/// Returns whether any record was geo-targeted, which makes the answer
+/// depend on the client.
+pub(crate) fn select(answers: &mut Vec<Record>, records: &GeoRecords, location: &Location) -> bool {
+    if records.is_empty() {
+        return false;
+    }
+    let scores: Vec<Option<u8>> = answers
+        .iter()
+        .map(|record| score(records, record, location))
+        .collect();
+    // the closest match of each RRset and whether it has untargeted records
+    let mut rrsets: HashMap<(&Name, RecordType), (u8, bool)> = HashMap::new();
+    for (record, score) in answers.iter().zip(&scores) {
+        let rrset = rrsets
+            .entry((record.name(), record.record_type()))
+            .or_default();
+        match score {
+            Some(score) => rrset.0 = rrset.0.max(*score),
+            None => rrset.1 = true,
+        }
+    }
+    let keep: Vec<bool> = answers
+        .iter()
+        .zip(&scores)
+        .map(|(record, score)| {
+            let (best, untargeted) = rrsets[&(record.name(), record.record_type())];
+            match score {
+                Some(score) => *score == best && (best > 0 || !untargeted),
+                None => best == 0,
+            }
+        })
+        .collect();
+    let mut keep = keep.into_iter();
+    answers.retain(|_| keep.next().unwrap_or(true));
+    scores.iter().any(Option::is_some)
+}
+
+/// How closely the selectors of `record` match `location`, `None` when it
+/// has no selectors.
+fn score(records: &GeoRecords, record: &Record, location: &Location) -> Option<u8> {
+    let (_, selectors) = records
+        .get(&LowerName::from(record.name()))?
+        .iter()
+        .find(|(rdata, _)| Some(rdata) == record.data())?;
+    Some(
+        selectors
+            .iter()
+            .map(|selector| location.matches(selector))
+            .max()
+            .unwrap_or(0),
+    )
+}
+
+/// MaxMind databases locating clients.
+#[cfg_attr(not(feature = "geoip"), allow(dead_code))]
+pub(crate) struct GeoIp {
+    #[cfg(feature = "geoip")]
+    country: Option<maxminddb::Reader<Vec<u8>>>,
+    #[cfg(feature = "geoip")]
+    asn: Option<maxminddb::Reader<Vec<u8>>>,
+}
+
+impl GeoIp {
+    #[cfg(feature = "geoip")]
+    pub(crate) fn open(config: &GeoIpConfig) -> Result<Self> {
+        use anyhow::Context;
+
+        let open = |path: Option<&std::path::Path>| {
+            path.map(|path| {
+                maxminddb::Reader::open_readfile(path)
+                    .with_context(|| format!("failed to open GeoIP database {}", path.display()))
+            })
+            .transpose()
+        };
+        Ok(Self {
+            country: open(config.database())?,
+            asn: open(config.asn_database())?,
+        })
+    }
+
+    #[cfg(not(feature = "geoip"))]
+    pub(crate) fn open(_config: &GeoIpConfig) -> Result<Self> {
+        anyhow::bail!("GeoIP databases require the `geoip` feature")
+    }
+
+    #[cfg(feature = "geoip")]
+    pub(crate) fn locate(&self, address: IpAddr) -> Location {
+        use maxminddb::geoip2;
+
+        let mut location = Location::default();
+        if let Some(reader) = &self.country {
+            match reader.lookup::<geoip2::Country>(address) {
+                Ok(country) => {
+                    location.continent = country
+                        .continent
+                        .and_then(|continent| continent.code)
+                        .map(str::to_string);
+                    location.country = country
+                        .country
+                        .and_then(|country| country.iso_code)
+                        .map(str::to_string);
+                }
+                Err(e) => tracing::debug!("no country of {}: {}", address, e),
+            }
+        }
+        if let Some(reader) = &self.asn {
+            match reader.lookup::<geoip2::Asn>(address) {
+                Ok(asn) => location.asn = asn.autonomous_system_number,
+                Err(e) => tracing::debug!("no AS of {}: {}", address, e),
+            }
+        }
+        location
+    }
+
+    #[cfg(not(feature = "geoip"))]
+    pub(crate) fn locate(&self, _address: IpAddr) -> Location {
+        Location::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn a(address: &str) -> Record {
+        Record::from_rdata(
+            Name::from_str("www.et.internal.").unwrap(),
+            60,
+            RData::A(address.parse().unwrap()),
+        )
+    }
+
+    #[test]
+    fn selects_closest_records() {
+        let selector = |s: &str| GeoSelector::from_str(s).unwrap();
+        let records = GeoRecords::from([(
+            LowerName::from_str("www.et.internal.").unwrap(),
+            vec![
+                (
+                    a("10.0.0.1").data().unwrap().clone(),
+                    vec![selector("continent:eu")],
+                ),
+                (
+                    a("10.0.0.2").data().unwrap().clone(),
+                    vec![selector("country:DE")],
+                ),
+                (
+                    a("10.0.0.3").data().unwrap().clone(),
+                    vec![selector("asn:AS13335")],
+                ),
+            ],
+        )]);
+        let answers = [a("10.0.0.1"), a("10.0.0.2"), a("10.0.0.3"), a("10.0.0.9")];
+        let answer = |location: Location| {
+            let mut answers = answers.to_vec();
+            assert!(select(&mut answers, &records, &location));
+            answers
+        };
+        let france = Location {
+            continent: Some("EU".to_string()),
+            country: Some("FR".to_string()),
+            asn: None,
+        };
+        assert_eq!(answer(france.clone()), [a("10.0.0.1")]);
+        let germany = Location {
+            country: Some("DE".to_string()),
+            ..france.clone()
+        };
+        assert_eq!(answer(germany.clone()), [a("10.0.0.2")]);
+        let cloudflare = Location {
+            asn: Some(13335),
+            ..germany
+        };
+        assert_eq!(answer(cloudflare), [a("10.0.0.3")]);
+        // other clients get the records without selectors
+        assert_eq!(answer(Location::default()), [a("10.0.0.9")]);
+        let mut tagged = answers[..3].to_vec();
+        select(&mut tagged, &records, &Location::default());
+        assert_eq!(tagged, answers[..3]);
+    }
+}
@@ -0,0 +1,406 @@
+//! gRPC control API of `proto/control.proto`, for orchestration systems
+//! that would otherwise poll the admin API.
+
+// the errors of the service are tonic's `Status`
+#![allow(clippy::result_large_err)]
+
+use crate::auth::BearerToken;
+use crate::cache::Flush;
+use crate::config;
+use crate::edit::{self, EditError};
+use crate::handler::CatalogRequestHandler;
+use crate::zonefile;
+use crate::zones::{ZoneChange, ZoneSet};
+use anyhow::anyhow;
+use futures_util::Stream;
+use hickory_proto::rr;
+use hickory_proto::rr::LowerName;
+use hickory_server::authority::Authority;
+use proto::control_server::{Control, ControlServer};
+use proto::zone_change::Kind;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::{BroadcastStream, TcpListenerStream};
+use tokio_stream::StreamExt;
+use tokio_util::sync::CancellationToken;
+use tonic::{Request, Response, Status};
+use tracing::{info, warn};
+
+/// Messages, client and server of the control API.
+pub mod proto {
+    tonic::include_proto!("libdns.control.v1");
+}
+
+struct ControlService {
+    handler: CatalogRequestHandler,
+    zones: Arc<ZoneSet>,
+}
+
+impl From<EditError> for Status {
+    fn from(e: EditError) -> Self {
+        match e {
+            EditError::NotFound(message) => Status::not_found(message),
+            EditError::BadRequest(e) => Status::invalid_argument(format!("{:#}", e)),
+        }
+    }
+}
+
+impl From<ZoneChange> for proto::ZoneChange {
+    fn from(change: ZoneChange) -> Self {
+        let (zone, kind, serial) = match change {
+            ZoneChange::Updated(zone, serial) => (zone, Kind::Updated, serial),
+            ZoneChange::Removed(zone) => (zone, Kind::Removed, 0),
+        };
+        Self {
+            zone: zone.to_string(),
+            kind: kind.into(),
+            serial,
+        }
+    }
+}
+
+impl From<&rr::Record> for proto::Record {
+    fn from(record: &rr::Record) -> Self {
+        Self {
+            r#type: record.record_type().to_string(),
+            name: zonefile::fqdn(record.name()).to_string(),
+            value: record.data().map(ToString::to_string).unwrap_or_default(),
+            ttl: record.ttl(),
+        }
+    }
+}
+
+/// The zone and the record of a record request, as in the zone config.
+fn record_request(request: proto::RecordRequest) -> Result<(String, config::Record), EditError> {
+    let record = request
+        .record
+        .ok_or_else(|| anyhow!("record request without a record"))?;
+    let rr_type = config::RecordType::from_str(&record.r#type.to_ascii_uppercase())
+        .map_err(|e| anyhow!("invalid record type {}: {}", record.r#type, e))?;
+    let record = config::RecordBuilder::default()
+        .name(record.name)
+        .data(config::RecordData::from_text(rr_type, &record.value)?)
+        .ttl(Duration::from_secs(record.ttl.into()))
+        .build()
+        .map_err(anyhow::Error::from)?;
+    Ok((request.zone, record))
+}
+
+type ZoneChanges = Pin<Box<dyn Stream<Item = Result<proto::ZoneChange, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl Control for ControlService {
+    async fn list_zones(
+        &self,
+        _request: Request<proto::ListZonesRequest>,
+    ) -> Result<Response<proto::ListZonesResponse>, Status> {
+        let zones = edit::zone_names(&self.zones).await;
+        Ok(Response::new(proto::ListZonesResponse { zones }))
+    }
+
+    type WatchZonesStream = ZoneChanges;
+
+    async fn watch_zones(
+        &self,
+        _request: Request<proto::WatchZonesRequest>,
+    ) -> Result<Response<ZoneChanges>, Status> {
+        // a watcher that missed changes ends with an error, to list the
+        // zones again
+        let changes = BroadcastStream::new(self.zones.watch()).map(|change| match change {
+            Ok(change) => Ok(change.into()),
+            Err(BroadcastStreamRecvError::Lagged(missed)) => {
+                Err(Status::data_loss(format!("missed {} zone changes", missed)))
+            }
+        });
+        Ok(Response::new(Box::pin(changes)))
+    }
+
+    async fn list_records(
+        &self,
+        request: Request<proto::ListRecordsRequest>,
+    ) -> Result<Response<proto::ListRecordsResponse>, Status> {
+        let zone = request.into_inner().zone;
+        let origin = rr::Name::from_str(&zone)
+            .map_err(|e| Status::invalid_argument(format!("invalid zone name {}: {}", zone, e)))?;
+        let authority = self
+            .zones
+            .find(&origin)
+            .filter(|authority| *authority.origin() == LowerName::from(&origin))
+            .ok_or_else(|| Status::not_found(format!("no such zone: {}", zone)))?;
+        let records = authority
+            .records()
+            .await
+            .values()
+            .flat_map(|rrset| rrset.records_without_rrsigs())
+            .map(proto::Record::from)
+            .collect();
+        Ok(Response::new(proto::ListRecordsResponse { records }))
+    }
+
+    async fn add_record(
+        &self,
+        request: Request<proto::RecordRequest>,
+    ) -> Result<Response<proto::RecordResponse>, Status> {
+        let (zone, record) = record_request(request.into_inner())?;
+        edit::add_record(&self.zones, &zone, record).await?;
+        info!("grpc: added a record to {}", zone);
+        Ok(Response::new(proto::RecordResponse {}))
+    }
+
+    async fn replace_record(
+        &self,
+        request: Request<proto::RecordRequest>,
+    ) -> Result<Response<proto::RecordResponse>, Status> {
+        let (zone, record) = record_request(request.into_inner())?;
+        edit::replace_record(&self.zones, &zone, record).await?;
+        info!("grpc: replaced records of {}", zone);
+        Ok(Response::new(proto::RecordResponse {}))
+    }
+
+    async fn remove_record(
+        &self,
+        request: Request<proto::RecordRequest>,
+    ) -> Result<Response<proto::RecordResponse>, Status> {
+        let (zone, record) = record_request(request.into_inner())?;
+        edit::remove_record(&self.zones, &zone, record).await?;
+        info!("grpc: removed a record from {}", zone);
+        Ok(Response::new(proto::RecordResponse {}))
+    }
+
+    async fn flush_cache(
+        &self,
+        request: Request<proto::FlushCacheRequest>,
+    ) -> Result<Response<proto::FlushCacheResponse>, Status> {
+        let request = request.into_inner();
+        let subtree = !request.exact && request.r#type.is_empty();
+        let flush = Flush::new(&request.name, &request.r#type, subtree)
+            .map_err(|e| Status::invalid_argument(format!("{:#}", e)))?;
+        let flushed = self
+            .handler
+            .forwarder
+            .as_ref()
+            .map_or(0, |f| f.flush_cache(&flush));
+        info!("grpc: flushed {} cached answers", flushed);
+        Ok(Response::new(proto::FlushCacheResponse {
+            flushed: flushed as u64,
+        }))
+    }
+
+    async fn get_stats(
+        &self,
+        _request: Request<proto::GetStatsRequest>,
+    ) -> Result<Response<proto::Stats>, Status> {
+        let cache = self
+            .handler
+            .forwarder
+            .as_ref()
+            .and_then(|f| f.cache_stats())
+            .map(|stats| proto::CacheStats {
+                hits: stats.hits,
+                misses: stats.misses,
+                stale_hits: stats.stale_hits,
+                entries: stats.entries as u64,
+                prefetches: stats.prefetches,
+            });
+        Ok(Response::new(proto::Stats {
+            zones: self.zones.zone_count() as u64,
+            queries_in_flight: self.handler.drain.in_flight() as u64,
+            cache,
+        }))
+    }
+}
+
+/// Serves the control API until `token` is cancelled.
+pub(crate) async fn serve(
+    listener: TcpListener,
+    handler: CatalogRequestHandler,
+    zones: Arc<ZoneSet>,
+    auth_token: Option<String>,
+    token: CancellationToken,
+) {
+    let auth = auth_token.map(|t| Arc::new(BearerToken::new(&t)));
+    let service = ControlServer::with_interceptor(
+        ControlService { handler, zones },
+        move |request: Request<()>| {
+            if let Some(auth) = &auth {
+                let given = request.metadata().get("authorization");
+                if !auth.matches(given.map(|v| v.as_bytes())) {
+                    return Err(Status::unauthenticated("missing or invalid token"));
+                }
+            }
+            Ok(request)
+        },
+    );
+    let result = tonic::transport::Server::builder()
+        .add_service(service)
+        .serve_with_incoming_shutdown(TcpListenerStream::new(listener), token.cancelled_owned())
+        .await;
+    if let Err(e) = result {
+        warn!("gRPC API stopped: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{
+        AdminListenConfigBuilder, GeneralConfigBuilder, RecordBuilder, RecordData, RunConfigBuilder,
+    };
+    use crate::testing;
+    use crate::Server;
+    use anyhow::Result;
+    use maplit::hashmap;
+    use proto::control_client::ControlClient;
+    use std::net::Ipv4Addr;
+    use tonic::transport::Channel;
+
+    async fn start() -> Result<(Server, ControlClient<Channel>)> {
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .listen_udp("127.0.0.1:0")
+                    .listen_grpc(
+                        AdminListenConfigBuilder::default()
+                            .address("127.0.0.1:0")
+                            .token("secret")
+                            .build()?,
+                    )
+                    .build()?,
+            )
+            .zones(hashmap! {
+                "et.internal".to_string() => vec![
+                    RecordBuilder::default()
+                        .name("www.et.internal".to_string())
+                        .data(RecordData::A(Ipv4Addr::new(123, 123, 123, 123)))
+                        .ttl(Duration::from_secs(60))
+                        .build()?,
+                ].into(),
+            })
+            .build()?;
+        let mut server = Server::new(config);
+        server.run().await?;
+        let address = format!("http://{}", server.grpc_local_addr().unwrap());
+        let client = ControlClient::connect(address).await?;
+        Ok((server, client))
+    }
+
+    fn authorized<T>(message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        request
+            .metadata_mut()
+            .insert("authorization", "Bearer secret".parse().unwrap());
+        request
+    }
+
+    fn record_request(name: &str, value: &str) -> Request<proto::RecordRequest> {
+        authorized(proto::RecordRequest {
+            zone: "et.internal".to_string(),
+            record: Some(proto::Record {
+                r#type: "A".to_string(),
+                name: name.to_string(),
+                value: value.to_string(),
+                ttl: 60,
+            }),
+        })
+    }
+
+    #[tokio::test]
+    async fn manages_records_and_watches_zones() -> Result<()> {
+        let (mut server, mut client) = start().await?;
+        let addr = server.udp_local_addr().unwrap();
+
+        let zones = client
+            .list_zones(authorized(proto::ListZonesRequest {}))
+            .await?
+            .into_inner();
+        assert_eq!(zones.zones, ["et.internal"]);
+
+        let mut changes = client
+            .watch_zones(authorized(proto::WatchZonesRequest {}))
+            .await?
+            .into_inner();
+        client
+            .add_record(record_request("api.et.internal", "10.0.0.1"))
+            .await?;
+        let response = testing::query(addr, "api.et.internal", rr::RecordType::A).await?;
+        assert_eq!(response.answers().len(), 1);
+        let change = changes.message().await?.unwrap();
+        assert_eq!(change.zone, "et.internal.");
+        assert_eq!(change.kind(), Kind::Updated);
+
+        let records = client
+            .list_records(authorized(proto::ListRecordsRequest {
+                zone: "et.internal".to_string(),
+            }))
+            .await?
+            .into_inner()
+            .records;
+        assert!(records
+            .iter()
+            .any(|r| r.name == "api.et.internal." && r.value == "10.0.0.1"));
+        assert!(records.iter().any(|r| r.r#type == "SOA"));
+
+        client
+            .replace_record(record_request("api.et.internal", "10.0.0.2"))
+            .await?;
+        let response = testing::query(addr, "api.et.internal", rr::RecordType::A).await?;
+        assert_eq!(
+            response.answers()[0].data().unwrap().to_string(),
+            "10.0.0.2"
+        );
+        client
+            .remove_record(record_request("api.et.internal", "10.0.0.2"))
+            .await?;
+        let response = testing::query(addr, "api.et.internal", rr::RecordType::A).await?;
+        assert!(response.answers().is_empty());
+
+        let status = client
+            .remove_record(record_request("api.et.internal", "10.0.0.2"))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+        let status = client
+            .add_record(record_request("api.et.internal", "nope"))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let stats = client
+            .get_stats(authorized(proto::GetStatsRequest {}))
+            .await?
+            .into_inner();
+        assert_eq!(stats.zones, 1);
+        assert_eq!(stats.cache, None);
+        let flushed = client
+            .flush_cache(authorized(proto::FlushCacheRequest::default()))
+            .await?
+            .into_inner();
+        assert_eq!(flushed.flushed, 0);
+        let request = proto::FlushCacheRequest {
+            name: "www.et.internal".to_string(),
+            r#type: "NOPE".to_string(),
+            ..Default::default()
+        };
+        let e = client.flush_cache(authorized(request)).await.unwrap_err();
+        assert_eq!(e.code(), tonic::Code::InvalidArgument);
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rejects_requests_without_token() -> Result<()> {
+        let (mut server, mut client) = start().await?;
+        let status = client
+            .list_zones(proto::ListZonesRequest {})
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+        server.shutdown().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,1236 @@
+use crate::acl::ClientAcl;
+use crate::blocklist::Blocklist;
+use crate::chaos::Chaos;
+use crate::config::{AnyQueries, RateLimitAction};
+use crate::drain::Drain;
+use crate::ecs::ClientSubnet;
+use crate::ede;
+use crate::forward::Forwarder;
+use crate::geoip::{GeoIp, Location};
+use crate::hosts::Hosts;
+use crate::middleware::{
+    self, Action, Fallback, Middleware, Middlewares, ObservingResponseHandler,
+};
+use crate::nsid;
+use crate::ratelimit::{RateLimiter, ResponseKind, ResponseRateLimiter};
+use crate::redirect::Redirect;
+use crate::rewrite::Rewriter;
+use crate::truncation::UdpSizes;
+use crate::ttl::TtlLimits;
+use crate::validate::Security;
+use crate::views::Views;
+use crate::zones::ZoneSet;
+use hickory_proto::op::{
+    Edns, Header, LowerQuery, Message, MessageType, OpCode, Query, ResponseCode,
+};
+use hickory_proto::rr::dnssec::rdata::DNSSECRData;
+use hickory_proto::rr::dnssec::SupportedAlgorithms;
+use hickory_proto::rr::rdata::opt::{EdnsCode, EdnsOption};
+use hickory_proto::rr::rdata::HINFO;
+use hickory_proto::rr::{DNSClass, LowerName, Name, RData, Record, RecordType};
+use hickory_proto::serialize::binary::BinEncodable;
+use hickory_server::authority::{
+    Catalog, LookupError, LookupObject, LookupOptions, MessageResponse, MessageResponseBuilder,
+};
+use hickory_server::server::{Protocol, Request, RequestHandler, ResponseHandler, ResponseInfo};
+use std::collections::HashSet;
+use std::iter;
+use std::sync::{Arc, RwLock};
+use tracing::{debug, error, info, info_span, instrument, warn, Instrument, Span};
+
+/// Upper bound of the encoded records of one AXFR message.
+const MAX_TRANSFER_MESSAGE_SIZE: usize = 16384;
+
+#[derive(Clone)]
+pub(crate) struct CatalogRequestHandler {
+    pub(crate) views: Arc<Views>,
+    max_cname_depth: usize,
+    any_queries: AnyQueries,
+    clients: ClientAcl,
+    pub(crate) forwarder: Option<Arc<Forwarder>>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    response_rate_limiter: Option<Arc<ResponseRateLimiter>>,
+    pub(crate) blocklist: Option<Arc<Blocklist>>,
+    pub(crate) hosts: Option<Arc<Hosts>>,
+    /// Answers the names that don't exist outside the zones redirecting them.
+    redirect: Option<Arc<Redirect>>,
+    geoip: Option<Arc<GeoIp>>,
+    chaos: Arc<Chaos>,
+    /// NSID option returned to the queries asking for it.
+    nsid: Option<EdnsOption>,
+    /// Bounds of the TTLs of every answer.
+    ttl: TtlLimits,
+    udp_sizes: UdpSizes,
+    /// Rules rewriting the queried names before they are looked up.
+    rewriter: Arc<Rewriter>,
+    /// Queries in flight, waited for on shutdown.
+    pub(crate) drain: Arc<Drain>,
+    /// Hooks run on every request before it is answered.
+    pub(crate) middlewares: Arc<Middlewares>,
+    /// Resolves the names without a zone before they are forwarded.
+    pub(crate) fallback: Arc<RwLock<Option<Arc<dyn Fallback>>>>,
+}
+
+#[derive(Default)]
+struct LookupSections {
+    answers: Vec<Record>,
+    name_servers: Vec<Record>,
+    soa: Vec<Record>,
+    additionals: Vec<Record>,
+    /// Extended DNS Error explaining a failed answer.
+    ede: Option<EdnsOption>,
+    /// Client subnet option scoped to the networks the answer is valid for,
+    /// the whole Internet when unset.
+    client_subnet: Option<ClientSubnet>,
+}
+
+impl LookupSections {
+    /// Clamps the TTLs of the records of every section to `limits`.
+    fn clamp_ttls(&mut self, limits: &TtlLimits) {
+        for records in [
+            &mut self.answers,
+            &mut self.name_servers,
+            &mut self.soa,
+            &mut self.additionals,
+        ] {
+            limits.clamp(records);
+        }
+    }
+}
+
+impl CatalogRequestHandler {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        views: Arc<Views>,
+        max_cname_depth: usize,
+        any_queries: AnyQueries,
+        clients: ClientAcl,
+        forwarder: Option<Forwarder>,
+        rate_limiter: Option<RateLimiter>,
+        response_rate_limiter: Option<ResponseRateLimiter>,
+        blocklist: Option<Blocklist>,
+        hosts: Option<Hosts>,
+        redirect: Option<Redirect>,
+        geoip: Option<GeoIp>,
+        chaos: Chaos,
+        nsid: Option<EdnsOption>,
+        ttl: TtlLimits,
+        udp_sizes: UdpSizes,
+        rewriter: Rewriter,
+    ) -> Self {
+        Self {
+            views,
+            max_cname_depth,
+            any_queries,
+            clients,
+            forwarder: forwarder.map(Arc::new),
+            rate_limiter: rate_limiter.map(Arc::new),
+            response_rate_limiter: response_rate_limiter.map(Arc::new),
+            blocklist: blocklist.map(Arc::new),
+            hosts: hosts.map(Arc::new),
+            redirect: redirect.map(Arc::new),
+            geoip: geoip.map(Arc::new),
+            chaos: Arc::new(chaos),
+            nsid,
+            ttl,
+            udp_sizes,
+            rewriter: Arc::new(rewriter),
+            drain: Arc::new(Drain::default()),
+            middlewares: Arc::new(Middlewares::default()),
+            fallback: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    /// Where the client of `request` is, by its client subnet option when
+    /// it has one.
+    fn locate(&self, request: &Request, client_subnet: Option<ClientSubnet>) -> Location {
+        let Some(geoip) = &self.geoip else {
+            return Location::default();
+        };
+        let address = client_subnet.map_or(request.src().ip(), |subnet| subnet.network().addr());
+        geoip.locate(address)
+    }
+
+    /// What to do with `request` when it is over the rate limit of its
+    /// client. Only UDP queries are limited, TCP clients can't forge their
+    /// address.
+    fn rate_limited(&self, request: &Request) -> Option<RateLimitAction> {
+        let limiter = self.rate_limiter.as_ref()?;
+        if !matches!(request.protocol(), Protocol::Udp) || limiter.allow(request.src().ip()) {
+            return None;
+        }
+        debug!("rate limited query from {}", request.src());
+        Some(limiter.action())
+    }
+
+    /// What to do with the response to `request` when identical responses
+    /// to its client are over the response rate limit, UDP only as well.
+    fn response_rate_limited(
+        &self,
+        request: &Request,
+        header: &Header,
+        sections: &LookupSections,
+    ) -> Option<RateLimitAction> {
+        let limiter = self.response_rate_limiter.as_ref()?;
+        if !matches!(request.protocol(), Protocol::Udp) {
+            return None;
+        }
+        let kind = response_kind(request.request_info().query, header, sections);
+        let action = limiter.check(request.src().ip(), kind)?;
+        debug!("rate limited response to {}", request.src());
+        Some(action)
+    }
+
+    /// Answers signed requests with signed responses, requests with a bad
+    /// or unknown key are not handled at all.
+    async fn verify_and_dispatch<R: ResponseHandler>(
+        &self,
+        request: &Request,
+        response_handle: R,
+    ) -> ResponseInfo {
+        match self.views.default_zones().keyring().verify(request) {
+            Ok(None) => {
+                let response_handle = self.udp_sizes.limit(request, response_handle);
+                self.dispatch(request, None, response_handle).await
+            }
+            Ok(Some(signed)) => {
+                // truncated before being signed
+                let response_handle = self.udp_sizes.limit(request, signed.sign(response_handle));
+                self.dispatch(request, Some(signed.key()), response_handle)
+                    .await
+            }
+            Err(e) => {
+                warn!("rejected request from {}: {:#}", request.src(), e);
+                respond(request, response_handle, ResponseCode::NotAuth).await
+            }
+        }
+    }
+
+    async fn lookup<R: ResponseHandler>(
+        &self,
+        zones: &ZoneSet,
+        catalog: &Catalog,
+        request: &Request,
+        key: Option<&LowerName>,
+        response_handle: R,
+    ) -> ResponseInfo {
+        let mut response_edns = None;
+        if let Some(req_edns) = request.edns() {
+            let mut edns = Edns::new();
+            edns.set_max_payload(req_edns.max_payload().max(512));
+            edns.set_version(0);
+            edns.set_dnssec_ok(req_edns.dnssec_ok());
+            if req_edns.version() > 0 {
+                warn!(
+                    "request edns version greater than 0: {}",
+                    req_edns.version()
+                );
+                let mut header = Header::response_from_request(request.header());
+                header.set_response_code(ResponseCode::BADVERS);
+                edns.set_rcode_high(ResponseCode::BADVERS.high());
+                let mut response = MessageResponseBuilder::from_message_request(request);
+                response.edns(edns);
+                return send(response_handle, response.build_no_records(header)).await;
+            }
+            response_edns = Some(edns);
+        }
+        let client_subnet = match request.edns().map(ClientSubnet::from_edns).transpose() {
+            Ok(client_subnet) => client_subnet.flatten(),
+            Err(e) => {
+                debug!("malformed request from {}: {:#}", request.src(), e);
+                return respond(request, response_handle, ResponseCode::FormErr).await;
+            }
+        };
+
+        let mut header = Header::response_from_request(request.header());
+        let mut sections = self
+            .resolve(zones, catalog, request, key, client_subnet, &mut header)
+            .await;
+        self.redirect_nxdomain(
+            zones,
+            request.query().original(),
+            &mut header,
+            &mut sections,
+        );
+        if request.query().query_type() == RecordType::ANY
+            && self.any_queries == AnyQueries::Minimal
+        {
+            minimize_any(request.query().original(), &mut sections);
+        }
+        sections.clamp_ttls(&self.ttl);
+        match self.response_rate_limited(request, &header, &sections) {
+            Some(RateLimitAction::Truncate) => return truncate(request, response_handle).await,
+            Some(RateLimitAction::Drop) => return dropped(request),
+            None => {}
+        }
+
+        let mut response = MessageResponseBuilder::from_message_request(request);
+        if let Some(mut edns) = response_edns {
+            if let Some(ede) = sections.ede.clone() {
+                edns.options_mut().insert(ede);
+            }
+            if let Some(client_subnet) = client_subnet {
+                let scoped = sections.client_subnet.unwrap_or(client_subnet.scoped(0));
+                edns.options_mut().insert(scoped.option());
+            }
+            if let Some(nsid) = &self.nsid {
+                if request.edns().is_some_and(nsid::requested) {
+                    edns.options_mut().insert(nsid.clone());
+                }
+            }
+            response.edns(edns);
+        }
+        let response = response.build(
+            header,
+            sections.answers.iter(),
+            sections.name_servers.iter(),
+            sections.soa.iter(),
+            sections.additionals.iter(),
+        );
+        send(response_handle, response).await
+    }
+
+    /// Answers the query of `request`, sent on behalf of the clients of
+    /// `client_subnet` when the request carries that option.
+    async fn resolve(
+        &self,
+        zones: &ZoneSet,
+        catalog: &Catalog,
+        request: &Request,
+        key: Option<&LowerName>,
+        client_subnet: Option<ClientSubnet>,
+        header: &mut Header,
+    ) -> LookupSections {
+        let request_info = request.request_info();
+        let query = request_info.query;
+        header.set_recursion_available(self.forwarder.is_some());
+        if query.query_class() == DNSClass::CH {
+            return self.chaos(query.original(), header);
+        }
+        if query.query_type() == RecordType::ANY && self.any_queries == AnyQueries::Refuse {
+            debug!("refused ANY query from {}", request.src());
+            header.set_response_code(ResponseCode::Refused);
+            return LookupSections {
+                ede: Some(ede::option(ede::NOT_SUPPORTED, "ANY queries not answered")),
+                ..Default::default()
+            };
+        }
+        let authority = catalog.find(query.name()).filter(|authority| {
+            let forwarder = self.forwarder.as_ref();
+            !forwarder.is_some_and(|f| f.overrides(authority.origin(), query.name()))
+        });
+        let Some(authority) = authority else {
+            let mut sections = LookupSections::default();
+            if let Some(answers) = self.local_answer(query.original()) {
+                header.set_authoritative(true);
+                sections.answers = answers;
+            } else if self.blocked(query.name()) {
+                self.block(query.original(), header, &mut sections);
+            } else if let Some(answer) = self.fallback_answer(request, query.original()).await {
+                merge_answer(answer, header, &mut sections);
+            } else if request.recursion_desired() && self.forwards(query.name()) {
+                self.forward(
+                    request,
+                    client_subnet,
+                    query.original(),
+                    header,
+                    &mut sections,
+                )
+                .await;
+            } else {
+                header.set_response_code(ResponseCode::Refused);
+                sections.ede = Some(ede::option(
+                    ede::NOT_AUTHORITATIVE,
+                    "no zone or upstream for the name",
+                ));
+            }
+            return sections;
+        };
+        if !self.query_allowed(zones, authority.origin(), request, key) {
+            header.set_response_code(ResponseCode::Refused);
+            return LookupSections {
+                ede: Some(ede::option(
+                    ede::PROHIBITED,
+                    "queries of the zone not allowed",
+                )),
+                ..Default::default()
+            };
+        }
+        if let Some(referral) = zones.referral(query.name(), query.query_type()).await {
+            debug!("referred {} to its child zone", query.name());
+            let mut sections = LookupSections {
+                name_servers: referral.name_servers,
+                additionals: referral.glue,
+                ..Default::default()
+            };
+            zones.clamp_ttls(authority.origin(), &mut sections.name_servers);
+            zones.clamp_ttls(authority.origin(), &mut sections.additionals);
+            return sections;
+        }
+        header.set_authoritative(authority.zone_type().is_authoritative());
+
+        let lookup_options = lookup_options(request.edns());
+        let query_type = query.query_type();
+        let alias = matches!(query_type, RecordType::A | RecordType::AAAA)
+            .then(|| zones.alias(authority.origin(), query.name()))
+            .flatten();
+        if let Some(target) = alias {
+            let mut sections = LookupSections::default();
+            let answers = self
+                .resolve_alias(catalog, request, client_subnet, query.original(), target)
+                .await;
+            let Some(mut answers) = answers else {
+                header.set_response_code(ResponseCode::ServFail);
+                sections.ede = Some(ede::option(
+                    ede::NO_REACHABLE_AUTHORITY,
+                    "alias target not resolved",
+                ));
+                return sections;
+            };
+            zones.clamp_ttls(authority.origin(), &mut answers);
+            if answers.is_empty() {
+                sections.soa = collect(authority.soa_secure(lookup_options).await);
+                zones.clamp_ttls(authority.origin(), &mut sections.soa);
+            }
+            sections.answers = answers;
+            return sections;
+        }
+        let mut sections = LookupSections::default();
+        let mut name = query.name().clone();
+        let mut authority = authority;
+        let mut result = match zones.wildcard(&name, query_type, lookup_options).await {
+            Some(result) => result,
+            None => {
+                authority
+                    .search(request_info, lookup_options)
+                    .instrument(info_span!("lookup", zone = %authority.origin()))
+                    .await
+            }
+        };
+        let mut visited = HashSet::from([name.clone()]);
+
+        loop {
+            let mut records = match result {
+                Ok(records) => records,
+                Err(e) => {
+                    set_error_code(header, &e);
+                    if e.is_nx_domain() || e.is_name_exists() {
+                        sections.soa = collect(authority.soa_secure(lookup_options).await);
+                        zones.clamp_ttls(authority.origin(), &mut sections.soa);
+                        if lookup_options.is_dnssec() {
+                            match zones.nsec3_proof(&name, e.is_nx_domain()).await {
+                                Some(nsec3s) => sections.soa.extend(nsec3s),
+                                None => {
+                                    let nsecs =
+                                        authority.get_nsec_records(&name, lookup_options).await;
+                                    sections.soa.extend(collect(nsecs));
+                                }
+                            }
+                        }
+                    }
+                    break;
+                }
+            };
+            if let Some(additionals) = records.take_additionals() {
+                let mut additionals: Vec<Record> = additionals.iter().cloned().collect();
+                zones.clamp_ttls(authority.origin(), &mut additionals);
+                sections.additionals.extend(additionals);
+            }
+            let mut answers: Vec<Record> = records.iter().cloned().collect();
+            zones.withdraw_unhealthy(&mut answers);
+            let targeted = zones.select_geo(authority.origin(), &mut answers, || {
+                self.locate(request, client_subnet)
+            });
+            if let (true, Some(client_subnet)) = (targeted, client_subnet) {
+                // the answer holds for the whole network of the client
+                let source_prefix = client_subnet.network().prefix_len();
+                sections.client_subnet = Some(client_subnet.scoped(source_prefix));
+            }
+            zones.order_answers(authority.origin(), &mut answers);
+            zones.clamp_ttls(authority.origin(), &mut answers);
+            sections.answers.extend(answers);
+            if query_type == RecordType::SOA {
+                sections.name_servers = collect(authority.ns(lookup_options).await);
+                zones.clamp_ttls(authority.origin(), &mut sections.name_servers);
+            }
+
+            let Some(target) = cname_target(&sections.answers, &name, query_type) else {
+                break;
+            };
+            if !visited.insert(target.clone()) {
+                warn!("cname loop detected at {}", target);
+                break;
+            }
+            // the names visited but the query name are the CNAMEs followed
+            if visited.len() - 1 > self.max_cname_depth {
+                debug!("cname chain exceeds max depth {}", self.max_cname_depth);
+                break;
+            }
+            let Some(next) = catalog.find(&target) else {
+                let query = Query::query(target.clone().into(), query_type);
+                if let Some(answers) = self.local_answer(&query) {
+                    sections.answers.extend(answers);
+                } else if self.blocked(&target) {
+                    self.block(&query, header, &mut sections);
+                } else if let Some(answer) = self.fallback_answer(request, &query).await {
+                    merge_answer(answer, header, &mut sections);
+                } else if request.recursion_desired() && self.forwards(&target) {
+                    self.forward(request, client_subnet, &query, header, &mut sections)
+                        .await;
+                }
+                break;
+            };
+            if !self.query_allowed(zones, next.origin(), request, key) {
+                break;
+            }
+            authority = next;
+            name = target;
+            result = match zones.wildcard(&name, query_type, lookup_options).await {
+                Some(result) => result,
+                None => {
+                    authority
+                        .lookup(&name, query_type, lookup_options)
+                        .instrument(info_span!("lookup", zone = %authority.origin(), qname = %name))
+                        .await
+                }
+            };
+        }
+
+        sections
+            .additionals
+            .retain(|r| !sections.answers.contains(r));
+        sections
+    }
+
+    /// The records of the ALIAS `target` of the name of `query`, renamed to
+    /// it: looked up in the zones, or else forwarded. `None` when the
+    /// upstreams failed.
+    async fn resolve_alias(
+        &self,
+        catalog: &Catalog,
+        request: &Request,
+        client_subnet: Option<ClientSubnet>,
+        query: &Query,
+        target: Name,
+    ) -> Option<Vec<Record>> {
+        let query_type = query.query_type();
+        let name = LowerName::from(&target);
+        let records = match catalog.find(&name) {
+            Some(authority) => collect(
+                authority
+                    .lookup(&name, query_type, LookupOptions::default())
+                    .await,
+            ),
+            None => {
+                let mut header = Header::new();
+                let mut sections = LookupSections::default();
+                let query = Query::query(target, query_type);
+                self.forward(request, client_subnet, &query, &mut header, &mut sections)
+                    .await;
+                if header.response_code() == ResponseCode::ServFail {
+                    return None;
+                }
+                sections.answers
+            }
+        };
+        Some(
+            records
+                .into_iter()
+                .filter(|r| r.record_type() == query_type)
+                .map(|mut r| {
+                    r.set_name(query.name().clone());
+                    r
+                })
+                .collect(),
+        )
+    }
+
+    /// Answers an AXFR query with every record of the zone, spread over as
+    /// many messages as needed. Only allowed clients may transfer a zone, and
+    /// only over a stream transport.
+    ///
+    /// IXFR queries get the lone SOA record when the client holds the current
+    /// serial, and the whole zone otherwise.
+    async fn transfer<R: ResponseHandler>(
+        &self,
+        zones: &ZoneSet,
+        request: &Request,
+        key: Option<&LowerName>,
+        response_handle: R,
+    ) -> ResponseInfo {
+        let zone = request.request_info().query.name().clone();
+        let src = request.src();
+        if matches!(request.protocol(), Protocol::Udp)
+            || !zones.transfer_allowed(&zone, src.ip(), key)
+        {
+            warn!(
+                "refused transfer of {} to {} over {}",
+                zone,
+                src,
+                request.protocol()
+            );
+            return refuse(request, response_handle, "transfer not allowed").await;
+        }
+        let Some(mut records) = zones.transfer_records(&zone).await else {
+            debug!("refused transfer of unknown zone {} to {}", zone, src);
+            let ede = ede::option(ede::NOT_AUTHORITATIVE, "unknown zone");
+            return respond_with_ede(request, response_handle, ResponseCode::Refused, ede).await;
+        };
+        if request.request_info().query.query_type() == RecordType::IXFR
+            && request
+                .name_servers()
+                .iter()
+                .any(|r| soa_serial(r) == soa_serial(&records[0]))
+        {
+            debug!("{} is up to date with zone {}", src, zone);
+            records.truncate(1);
+        }
+
+        let mut header = Header::response_from_request(request.header());
+        header.set_authoritative(true);
+        let mut info = serve_failed();
+        for chunk in transfer_chunks(&records) {
+            let response = MessageResponseBuilder::from_message_request(request).build(
+                header,
+                chunk.iter(),
+                std::iter::empty(),
+                std::iter::empty(),
+                std::iter::empty(),
+            );
+            info = send(response_handle.clone(), response).await;
+        }
+        info!(
+            "transferred {} records of {} to {}",
+            records.len(),
+            zone,
+            src
+        );
+        info
+    }
+
+    /// Answers a NOTIFY from an allowed client and refreshes the secondary
+    /// zone right away.
+    async fn notify<R: ResponseHandler>(
+        &self,
+        zones: &ZoneSet,
+        request: &Request,
+        key: Option<&LowerName>,
+        response_handle: R,
+    ) -> ResponseInfo {
+        let zone = request.request_info().query.name().clone();
+        let src = request.src();
+        if !zones.notify_allowed(&zone, src.ip(), key) {
+            warn!("refused NOTIFY of {} from {}", zone, src);
+            return refuse(request, response_handle, "notify not allowed").await;
+        }
+        if !zones.request_refresh(&zone) {
+            debug!("ignored NOTIFY of non-secondary zone {} from {}", zone, src);
+            return respond(request, response_handle, ResponseCode::NotAuth).await;
+        }
+        info!("received NOTIFY of {} from {}", zone, src);
+        respond(request, response_handle, ResponseCode::NoError).await
+    }
+
+    /// Applies a dynamic update from an allowed client to its zone.
+    async fn update<R: ResponseHandler>(
+        &self,
+        zones: &ZoneSet,
+        request: &Request,
+        key: Option<&LowerName>,
+        response_handle: R,
+    ) -> ResponseInfo {
+        let zone = request.query().name().clone();
+        let src = request.src();
+        if !zones.update_allowed(&zone, src.ip(), key) {
+            warn!("refused update of {} from {}", zone, src);
+            return refuse(request, response_handle, "update not allowed").await;
+        }
+        let code = match zones.update(request).await {
+            Ok(()) => {
+                info!("updated zone {} from {}", zone, src);
+                ResponseCode::NoError
+            }
+            Err(code) => {
+                debug!("update of {} from {} failed: {}", zone, src, code);
+                code
+            }
+        };
+        respond(request, response_handle, code).await
+    }
+
+    /// Handles `request`, which was signed with the TSIG `key` if any, with
+    /// the zones of the client's view. Clients outside the global ACL are
+    /// refused.
+    async fn dispatch<R: ResponseHandler>(
+        &self,
+        request: &Request,
+        key: Option<&LowerName>,
+        response_handle: R,
+    ) -> ResponseInfo {
+        if !self.clients.allows(request.src().ip(), key) {
+            debug!("refused request from {}", request.src());
+            return refuse(request, response_handle, "client not allowed").await;
+        }
+        let zones = self.views.select(request.src().ip(), key);
+        match (request.message_type(), request.op_code()) {
+            (MessageType::Query, OpCode::Query)
+                if matches!(
+                    request.request_info().query.query_type(),
+                    RecordType::AXFR | RecordType::IXFR
+                ) =>
+            {
+                self.transfer(zones, request, key, response_handle).await
+            }
+            (MessageType::Query, OpCode::Notify) => {
+                self.notify(zones, request, key, response_handle).await
+            }
+            (MessageType::Query, OpCode::Update) => {
+                self.update(zones, request, key, response_handle).await
+            }
+            (MessageType::Query, OpCode::Query) => {
+                let catalog = zones.catalog().snapshot();
+                let rewrite = self.rewriter.rewrite(request.query().original().name());
+                let Some(rewrite) = rewrite else {
+                    return self
+                        .lookup(zones, &catalog, request, key, response_handle)
+                        .await;
+                };
+                debug!("rewrote {} to {}", request.query().name(), rewrite.to());
+                let rewritten = match rewrite.request(request) {
+                    Ok(rewritten) => rewritten,
+                    Err(e) => {
+                        warn!("failed to rewrite request from {}: {}", request.src(), e);
+                        return respond(request, response_handle, ResponseCode::ServFail).await;
+                    }
+                };
+                let response_handle = rewrite.respond(request, response_handle);
+                self.lookup(zones, &catalog, &rewritten, key, response_handle)
+                    .await
+            }
+            _ => {
+                let catalog = zones.catalog().snapshot();
+                catalog.handle_request(request, response_handle).await
+            }
+        }
+    }
+
+    fn query_allowed(
+        &self,
+        zones: &ZoneSet,
+        zone: &LowerName,
+        request: &Request,
+        key: Option<&LowerName>,
+    ) -> bool {
+        let allowed = zones.query_allowed(zone, request.src().ip(), key);
+        if !allowed {
+            debug!("refused query of zone {} from {}", zone, request.src());
+        }
+        allowed
+    }
+
+    /// Answers the CHAOS class `query` with the configured strings, refuses
+    /// the names without one.
+    fn chaos(&self, query: &Query, header: &mut Header) -> LookupSections {
+        let mut sections = LookupSections::default();
+        match self.chaos.answer(query) {
+            Some(answers) => {
+                header.set_authoritative(true);
+                sections.answers = answers;
+            }
+            None => {
+                debug!("refused CHAOS query of {}", query.name());
+                header.set_response_code(ResponseCode::Refused);
+            }
+        }
+        sections
+    }
+
+    /// The answer to `query` from the hosts files, or else the local data of
+    /// the forwarder, when they hold its name.
+    fn local_answer(&self, query: &Query) -> Option<Vec<Record>> {
+        match self.hosts.as_ref().and_then(|hosts| hosts.answer(query)) {
+            Some(answers) => Some(answers),
+            None => self.forwarder.as_ref()?.local_answer(query),
+        }
+    }
+
+    /// Replaces an NXDOMAIN answer to `query` with the addresses of the
+    /// NXDOMAIN redirect of its zone, or else the global one. Blocked names
+    /// and other answers explained by an extended error are kept.
+    fn redirect_nxdomain(
+        &self,
+        zones: &ZoneSet,
+        query: &Query,
+        header: &mut Header,
+        sections: &mut LookupSections,
+    ) {
+        if header.response_code() != ResponseCode::NXDomain || sections.ede.is_some() {
+            return;
+        }
+        let redirect = zones
+            .redirect(&LowerName::from(query.name()))
+            .or_else(|| self.redirect.clone());
+        let Some(answers) = redirect.and_then(|redirect| redirect.answer(query)) else {
+            return;
+        };
+        debug!("redirected {} from NXDOMAIN", query);
+        header.set_response_code(ResponseCode::NoError);
+        header.set_authoritative(false);
+        header.set_authentic_data(false);
+        *sections = LookupSections {
+            answers,
+            ede: Some(ede::option(ede::FORGED_ANSWER, "nxdomain redirected")),
+            client_subnet: sections.client_subnet.take(),
+            ..Default::default()
+        };
+    }
+
+    fn blocked(&self, name: &LowerName) -> bool {
+        self.blocklist.as_ref().is_some_and(|b| b.blocks(name))
+    }
+
+    /// Appends the blocklist answer for `query` to `sections`.
+    fn block(&self, query: &Query, header: &mut Header, sections: &mut LookupSections) {
+        let Some(blocklist) = &self.blocklist else {
+            return;
+        };
+        debug!("blocked {}", query);
+        sections.ede = Some(ede::option(ede::BLOCKED, "blocked by blocklist"));
+        match blocklist.answer(query) {
+            Some(records) => sections.answers.extend(records),
+            None => {
+                header.set_response_code(ResponseCode::NXDomain);
+            }
+        }
+    }
+
+    /// The answer of the fallback for `query`, if any.
+    async fn fallback_answer(&self, request: &Request, query: &Query) -> Option<Message> {
+        let fallback = self.fallback.read().unwrap().clone()?;
+        fallback
+            .resolve(&middleware::context(request), query)
+            .instrument(info_span!("fallback"))
+            .await
+    }
+
+    fn forwards(&self, name: &LowerName) -> bool {
+        self.forwarder.as_ref().is_some_and(|f| f.handles(name))
+    }
+
+    /// Appends the upstream answer for `query` to `sections`. With DNSSEC
+    /// validation, secure answers get the AD bit when `request` asks for it
+    /// and bogus ones may be replaced with SERVFAIL. The query is forwarded
+    /// on behalf of `client_subnet`, or else the client address.
+    async fn forward(
+        &self,
+        request: &Request,
+        client_subnet: Option<ClientSubnet>,
+        query: &Query,
+        header: &mut Header,
+        sections: &mut LookupSections,
+    ) {
+        let Some(forwarder) = &self.forwarder else {
+            return;
+        };
+        let client = client_subnet.unwrap_or_else(|| ClientSubnet::new(request.src().ip().into()));
+        let mut response = match forwarder.forward_from(query, Some(client)).await {
+            Ok(response) => response,
+            Err(e) => {
+                warn!("failed to forward {}: {:#}", query, e);
+                header.set_response_code(ResponseCode::ServFail);
+                sections.ede = Some(ede::option(
+                    ede::NO_REACHABLE_AUTHORITY,
+                    "no upstream answered",
+                ));
+                return;
+            }
+        };
+        header.set_authoritative(false);
+        let dnssec_ok = request.edns().is_some_and(Edns::dnssec_ok);
+        match forwarder.validate(query, &response).await {
+            Some(Security::Secure) => {
+                let authenticate = dnssec_ok || request.header().authentic_data();
+                header.set_authentic_data(authenticate && sections.answers.is_empty());
+            }
+            Some(Security::Bogus(bogus)) if forwarder.strict_validation() => {
+                warn!("bogus answer for {}: {}", query, bogus);
+                header.set_response_code(ResponseCode::ServFail);
+                sections.ede = Some(bogus.ede());
+                return;
+            }
+            Some(Security::Bogus(bogus)) => {
+                debug!("passing on bogus answer for {}: {}", query, bogus)
+            }
+            Some(Security::Insecure) | None => {}
+        }
+        header.set_response_code(response.response_code());
+        let upstream_edns = response.extensions().as_ref();
+        if let Some(upstream_ede) = upstream_edns.and_then(ede::from_edns) {
+            sections.ede.get_or_insert(upstream_ede);
+        }
+        let upstream_subnet =
+            upstream_edns.and_then(|edns| ClientSubnet::from_edns(edns).ok().flatten());
+        if let (Some(client_subnet), Some(upstream_subnet)) = (client_subnet, upstream_subnet) {
+            sections.client_subnet = Some(client_subnet.scoped(upstream_subnet.scope_prefix()));
+        }
+        // signatures and denials asked for only to validate are left out
+        let wanted = |r: &Record| {
+            dnssec_ok
+                || r.record_type() == query.query_type()
+                || !matches!(
+                    r.record_type(),
+                    RecordType::RRSIG | RecordType::NSEC | RecordType::NSEC3
+                )
+        };
+        sections
+            .answers
+            .extend(response.take_answers().into_iter().filter(wanted));
+        sections
+            .name_servers
+            .extend(response.take_name_servers().into_iter().filter(wanted));
+        sections
+            .additionals
+            .extend(response.take_additionals().into_iter().filter(wanted));
+    }
+
+    /// Answers `request` unless the rate limits truncate or drop it.
+    async fn answer<R: ResponseHandler>(
+        &self,
+        request: &Request,
+        response_handle: R,
+    ) -> ResponseInfo {
+        match self.rate_limited(request) {
+            Some(RateLimitAction::Truncate) => truncate(request, response_handle).await,
+            Some(RateLimitAction::Drop) => dropped(request),
+            None => self.verify_and_dispatch(request, response_handle).await,
+        }
+    }
+
+    /// Runs the request hooks of `middlewares` on `request`, then answers it
+    /// unless one of them did, letting the hooks observe the response.
+    async fn answer_through<R: ResponseHandler>(
+        &self,
+        middlewares: &[Arc<dyn Middleware>],
+        request: &Request,
+        response_handle: R,
+    ) -> ResponseInfo {
+        let context = middleware::context(request);
+        let mut message = match middleware::to_message(request) {
+            Ok(message) => message,
+            Err(e) => {
+                warn!("failed to decode request for the middlewares: {}", e);
+                return respond(request, response_handle, ResponseCode::ServFail).await;
+            }
+        };
+        let original = message.clone();
+        for (i, hook) in middlewares.iter().enumerate() {
+            match hook.request(&context, &mut message).await {
+                Action::Continue => {}
+                Action::Respond(response) => {
+                    let passed = middlewares[..=i].to_vec();
+                    let response_handle =
+                        ObservingResponseHandler::new(response_handle, context, passed);
+                    let response_handle = self.udp_sizes.limit(request, response_handle);
+                    return respond_with_message(request, response_handle, response).await;
+                }
+                Action::Drop => return dropped(request),
+            }
+        }
+        let response_handle =
+            ObservingResponseHandler::new(response_handle, context, middlewares.to_vec());
+        if message == original {
+            return self.answer(request, response_handle).await;
+        }
+        match middleware::to_request(request, &message) {
+            Ok(changed) => self.answer(&changed, response_handle).await,
+            Err(e) => {
+                debug!("middlewares changed the request into an invalid one: {}", e);
+                respond(request, response_handle, ResponseCode::FormErr).await
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RequestHandler for CatalogRequestHandler {
+    #[instrument(
+        name = "request",
+        skip_all,
+        fields(
+            client = %request.src(),
+            protocol = %request.protocol(),
+            qname = %request.query().name(),
+            qtype = %request.query().query_type(),
+            rcode,
+        )
+    )]
+    async fn handle_request<R: ResponseHandler>(
+        &self,
+        request: &Request,
+        response_handle: R,
+    ) -> ResponseInfo {
+        let Some(_in_flight) = self.drain.enter() else {
+            debug!("refused query while shutting down");
+            return respond(request, response_handle, ResponseCode::Refused).await;
+        };
+        let middlewares = self.middlewares.load();
+        let info = match middlewares.is_empty() {
+            true => self.answer(request, response_handle).await,
+            false => {
+                self.answer_through(&middlewares, request, response_handle)
+                    .await
+            }
+        };
+        Span::current().record("rcode", tracing::field::display(info.response_code()));
+        info
+    }
+}
+
+async fn send<'a, R: ResponseHandler>(
+    mut response_handle: R,
+    response: MessageResponse<
+        '_,
+        'a,
+        impl Iterator<Item = &'a Record> + Send + 'a,
+        impl Iterator<Item = &'a Record> + Send + 'a,
+        impl Iterator<Item = &'a Record> + Send + 'a,
+        impl Iterator<Item = &'a Record> + Send + 'a,
+    >,
+) -> ResponseInfo {
+    match response_handle.send_response(response).await {
+        Ok(info) => info,
+        Err(e) => {
+            error!("error sending response: {}", e);
+            serve_failed()
+        }
+    }
+}
+
+/// Refuses `request` by policy, telling the client why with an Extended
+/// DNS Error.
+async fn refuse<R: ResponseHandler>(
+    request: &Request,
+    response_handle: R,
+    reason: &str,
+) -> ResponseInfo {
+    let ede = ede::option(ede::PROHIBITED, reason);
+    respond_with_ede(request, response_handle, ResponseCode::Refused, ede).await
+}
+
+/// Answers `request` with `code`, no records and the Extended DNS Error
+/// `ede` when the client supports EDNS.
+async fn respond_with_ede<R: ResponseHandler>(
+    request: &Request,
+    response_handle: R,
+    code: ResponseCode,
+    ede: EdnsOption,
+) -> ResponseInfo {
+    let mut header = Header::response_from_request(request.header());
+    header.set_response_code(code);
+    let mut response = MessageResponseBuilder::from_message_request(request);
+    if let Some(req_edns) = request.edns() {
+        let mut edns = Edns::new();
+        edns.set_max_payload(req_edns.max_payload().max(512));
+        edns.options_mut().insert(ede);
+        response.edns(edns);
+    }
+    send(response_handle, response.build_no_records(header)).await
+}
+
+/// Appends the records of the fallback `answer` to `sections`, with its
+/// response code and authoritative flag.
+fn merge_answer(mut answer: Message, header: &mut Header, sections: &mut LookupSections) {
+    header.set_response_code(answer.response_code());
+    header.set_authoritative(answer.authoritative());
+    sections.answers.extend(answer.take_answers());
+    sections.name_servers.extend(answer.take_name_servers());
+    sections.additionals.extend(answer.take_additionals());
+}
+
+/// Answers `request` with the header and records of `message`, keeping the
+/// ID and question of the request.
+async fn respond_with_message<R: ResponseHandler>(
+    request: &Request,
+    response_handle: R,
+    message: Message,
+) -> ResponseInfo {
+    let mut header = *message.header();
+    header.set_id(request.id());
+    header.set_message_type(MessageType::Response);
+    let mut response = MessageResponseBuilder::from_message_request(request);
+    if let Some(edns) = message.extensions() {
+        response.edns(edns.clone());
+    }
+    let response = response.build(
+        header,
+        message.answers().iter(),
+        message.name_servers().iter(),
+        iter::empty(),
+        message.additionals().iter(),
+    );
+    send(response_handle, response).await
+}
+
+/// Replaces the answers to the ANY `query` with a single HINFO record
+/// (RFC 8482, section 4.2), or with their first RRset and its signatures
+/// when they are signed, which a synthesized record can't be.
+fn minimize_any(query: &Query, sections: &mut LookupSections) {
+    let covered = |r: &Record| {
+        r.data()
+            .and_then(RData::as_dnssec)
+            .and_then(DNSSECRData::as_rrsig)
+            .map(|rrsig| rrsig.type_covered())
+    };
+    let answers = &mut sections.answers;
+    let Some(ttl) = answers.iter().map(Record::ttl).min() else {
+        return;
+    };
+    if answers.iter().any(|r| covered(r).is_some()) {
+        let Some(first) = answers.iter().find(|r| covered(r).is_none()) else {
+            return;
+        };
+        let (name, record_type) = (first.name().clone(), first.record_type());
+        answers.retain(|r| {
+            r.name() == &name && (r.record_type() == record_type || covered(r) == Some(record_type))
+        });
+    } else {
+        let hinfo = HINFO::new("RFC8482".to_string(), String::new());
+        *answers = vec![Record::from_rdata(
+            query.name().clone(),
+            ttl,
+            RData::HINFO(hinfo),
+        )];
+    }
+    sections.additionals.clear();
+}
+
+/// Answers `request` with no records and the TC bit, so that the client
+/// retries over TCP.
+async fn truncate<R: ResponseHandler>(request: &Request, response_handle: R) -> ResponseInfo {
+    let mut header = Header::response_from_request(request.header());
+    header.set_truncated(true);
+    let response = MessageResponseBuilder::from_message_request(request).build_no_records(header);
+    send(response_handle, response).await
+}
+
+/// Classifies a response for the response rate limit, denials by the zone
+/// they come from.
+fn response_kind(query: &LowerQuery, header: &Header, sections: &LookupSections) -> ResponseKind {
+    let zone = || {
+        sections
+            .soa
+            .iter()
+            .chain(&sections.name_servers)
+            .find(|r| matches!(r.record_type(), RecordType::SOA | RecordType::NS))
+            .map_or_else(|| query.name().clone(), |r| LowerName::new(r.name()))
+    };
+    match header.response_code() {
+        ResponseCode::NoError if sections.answers.is_empty() => ResponseKind::NoData(zone()),
+        ResponseCode::NoError => ResponseKind::Answer(query.name().clone(), query.query_type()),
+        ResponseCode::NXDomain => ResponseKind::NxDomain(zone()),
+        _ => ResponseKind::Error,
+    }
+}
+
+/// Leaves `request` unanswered.
+fn dropped(request: &Request) -> ResponseInfo {
+    let mut header = Header::response_from_request(request.header());
+    header.set_response_code(ResponseCode::Refused);
+    header.into()
+}
+
+/// Answers `request` with `code` and no records.
+async fn respond<R: ResponseHandler>(
+    request: &Request,
+    response_handle: R,
+    code: ResponseCode,
+) -> ResponseInfo {
+    let mut header = Header::response_from_request(request.header());
+    header.set_response_code(code);
+    let response = MessageResponseBuilder::from_message_request(request).build_no_records(header);
+    send(response_handle, response).await
+}
+
+/// Splits `records` into runs whose encoded size stays below
+/// `MAX_TRANSFER_MESSAGE_SIZE`, each run holding at least one record.
+fn transfer_chunks(records: &[Record]) -> Vec<&[Record]> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    let mut size = 0;
+    for (i, record) in records.iter().enumerate() {
+        let len = record.to_bytes().map_or(0, |bytes| bytes.len());
+        if i > start && size + len > MAX_TRANSFER_MESSAGE_SIZE {
+            chunks.push(&records[start..i]);
+            start = i;
+            size = 0;
+        }
+        size += len;
+    }
+    if start < records.len() {
+        chunks.push(&records[start..]);
+    }
+    chunks
+}
+
+fn soa_serial(record: &Record) -> Option<u32> {
+    record
+        .data()
+        .and_then(RData::as_soa)
+        .map(|soa| soa.serial())
+}
+
+fn serve_failed() -> ResponseInfo {
+    let mut header = Header::new();
+    header.set_response_code(ResponseCode::ServFail);
+    header.into()
+}
+
+fn set_error_code(header: &mut Header, e: &LookupError) {
+    if e.is_nx_domain() {
+        header.set_response_code(ResponseCode::NXDomain);
+    } else if e.is_refused() {
+        header.set_response_code(ResponseCode::Refused);
+    } else if !e.is_name_exists() {
+        header.set_response_code(ResponseCode::ServFail);
+    }
+}
+
+/// Includes the RRSIG records of signed zones when the client set the DO
+/// bit, for the algorithms it understands.
+fn lookup_options(edns: Option<&Edns>) -> LookupOptions {
+    let Some(edns) = edns else {
+        return LookupOptions::default();
+    };
+    let supported_algorithms = match edns.option(EdnsCode::DAU) {
+        Some(&EdnsOption::DAU(algorithms)) => algorithms,
+        _ => SupportedAlgorithms::default(),
+    };
+    LookupOptions::for_dnssec(edns.dnssec_ok(), supported_algorithms)
+}
+
+fn collect(records: Result<Box<dyn LookupObject>, LookupError>) -> Vec<Record> {
+    records
+        .map(|r| r.iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+/// Returns the CNAME target to follow for `name`, unless the answers already
+/// hold records of the requested type for it.
+fn cname_target(answers: &[Record], name: &LowerName, query_type: RecordType) -> Option<LowerName> {
+    if matches!(query_type, RecordType::CNAME | RecordType::ANY) {
+        return None;
+    }
+    let owned_by = |r: &&Record| &LowerName::from(r.name()) == name;
+    if answers
+        .iter()
+        .filter(owned_by)
+        .any(|r| r.record_type() == query_type)
+    {
+        return None;
+    }
+    answers
+        .iter()
+        .filter(owned_by)
+        .find_map(|r| r.data().and_then(|d| d.as_cname()))
+        .map(|cname| LowerName::from(&cname.0))
+}
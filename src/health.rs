@@ -0,0 +1,232 @@
+use crate::config::{HealthCheckConfig, HealthProbe};
+use anyhow::{anyhow, bail, Context, Result};
+use hickory_proto::rr::{LowerName, Name, RData, Record, RecordType};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, RwLock};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+/// The owner name and address of a health-checked A or AAAA record.
+pub(crate) type Target = (LowerName, IpAddr);
+
+/// Addresses of records withdrawn from answers by their health checks.
+#[derive(Default)]
+pub(crate) struct Health {
+    unhealthy: RwLock<HashSet<Target>>,
+}
+
+impl Health {
+    /// Removes the unhealthy addresses from `answers`, unless no address of
+    /// their name and type would be left.
+    pub(crate) fn withdraw(&self, answers: &mut Vec<Record>) {
+        let unhealthy = self.unhealthy.read().unwrap();
+        if unhealthy.is_empty() {
+            return;
+        }
+        let withdrawn = |record: &Record| {
+            address(record).is_some_and(|ip| unhealthy.contains(&(record.name().into(), ip)))
+        };
+        let served: HashSet<(Name, RecordType)> = answers
+            .iter()
+            .filter(|record| !withdrawn(record))
+            .map(|record| (record.name().clone(), record.record_type()))
+            .collect();
+        answers.retain(|record| {
+            !withdrawn(record) || !served.contains(&(record.name().clone(), record.record_type()))
+        });
+    }
+
+    /// Probes `target` as `check` says until `token` is cancelled, then
+    /// serves it again.
+    pub(crate) async fn watch(
+        self: Arc<Self>,
+        target: Target,
+        check: HealthCheckConfig,
+        token: CancellationToken,
+    ) {
+        let (name, address) = &target;
+        let mut ticker = tokio::time::interval(check.interval());
+        let (mut failures, mut successes) = (0, 0);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = token.cancelled() => break,
+            }
+            let healthy = !self.unhealthy.read().unwrap().contains(&target);
+            let result = tokio::time::timeout(check.timeout(), probe(*address, check.probe()))
+                .await
+                .unwrap_or_else(|_| Err(anyhow!("timed out")));
+            match result {
+                Ok(()) => {
+                    failures = 0;
+                    successes += 1;
+                    if !healthy && successes >= check.rise() {
+                        info!("{} of {} is healthy again", address, name);
+                        self.unhealthy.write().unwrap().remove(&target);
+                    }
+                }
+                Err(e) => {
+                    successes = 0;
+                    failures += 1;
+                    debug!("health check of {} of {} failed: {:#}", address, name, e);
+                    if healthy && failures >= check.fall() {
+                        warn!("withdrawing {} of {}: {:#}", address, name, e);
+                        self.unhealthy.write().unwrap().insert(target.clone());
+                    }
+                }
+            }
+        }
+        self.unhealthy.write().unwrap().remove(&target);
+    }
+}
+
+fn address(record: &Record) -> Option<IpAddr> {
+    match record.data()? {
+        RData::A(a) => Some(a.0.into()),
+        RData::AAAA(aaaa) => Some(aaaa.0.into()),
+        _ => None,
+    }
+}
+
+async fn probe(address: IpAddr, probe: &HealthProbe) -> Result<()> {
+    match probe {
+        HealthProbe::Tcp { port } => {
+            TcpStream::connect(SocketAddr::new(address, *port)).await?;
+            Ok(())
+        }
+        HealthProbe::Http { port, path } => http_get(SocketAddr::new(address, *port), path).await,
+        HealthProbe::Icmp => ping(address).await,
+    }
+}
+
+/// GETs `path` from `address`, expecting a 2xx or 3xx status.
+async fn http_get(address: SocketAddr, path: &str) -> Result<()> {
+    let mut stream = TcpStream::connect(address).await?;
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: libdns\r\nConnection: close\r\n\r\n",
+        path, address
+    );
+    stream.write_all(request.as_bytes()).await?;
+    // the status line starts with "HTTP/1.1 200"
+    let mut head = [0; 12];
+    stream.read_exact(&mut head).await?;
+    let status = std::str::from_utf8(&head)
+        .ok()
+        .and_then(|head| head.split_whitespace().nth(1))
+        .and_then(|status| status.parse::<u16>().ok())
+        .context("malformed HTTP response")?;
+    if !(200..400).contains(&status) {
+        bail!("HTTP status {}", status);
+    }
+    Ok(())
+}
+
+/// Sends an ICMP echo request to `address` and waits for the reply. The
+/// kernel sets the identifier and checksum of ICMP datagram sockets.
+async fn ping(address: IpAddr) -> Result<()> {
+    let (domain, protocol, request, reply) = match address {
+        IpAddr::V4(_) => (Domain::IPV4, Protocol::ICMPV4, 8, 0),
+        IpAddr::V6(_) => (Domain::IPV6, Protocol::ICMPV6, 128, 129),
+    };
+    let socket =
+        Socket::new(domain, Type::DGRAM, Some(protocol)).context("failed to open ICMP socket")?;
+    socket.set_nonblocking(true)?;
+    let socket = UdpSocket::from_std(socket.into())?;
+    socket.connect(SocketAddr::new(address, 0)).await?;
+    let sequence = rand::random::<u16>().to_be_bytes();
+    let mut message = vec![request, 0, 0, 0, 0, 0, sequence[0], sequence[1]];
+    message.extend_from_slice(b"libdns");
+    socket.send(&message).await?;
+    let mut buf = [0; 1500];
+    loop {
+        let len = socket.recv(&mut buf).await?;
+        if len >= 8 && buf[0] == reply && buf[6..8] == sequence {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HealthCheckConfigBuilder;
+    use std::str::FromStr;
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+
+    fn a(name: &str, address: &str) -> Record {
+        Record::from_rdata(
+            Name::from_str(name).unwrap(),
+            60,
+            RData::A(address.parse().unwrap()),
+        )
+    }
+
+    #[test]
+    fn withdraws_unhealthy_addresses() {
+        let health = Health::default();
+        let www = LowerName::from_str("www.et.internal.").unwrap();
+        let api = LowerName::from_str("api.et.internal.").unwrap();
+        health.unhealthy.write().unwrap().extend([
+            (www.clone(), "10.0.0.1".parse().unwrap()),
+            (api.clone(), "10.0.0.3".parse().unwrap()),
+        ]);
+        // names match with or without the trailing dot
+        let mut answers = vec![
+            a("www.et.internal", "10.0.0.1"),
+            a("www.et.internal", "10.0.0.2"),
+            a("api.et.internal", "10.0.0.3"),
+        ];
+        health.withdraw(&mut answers);
+        // the last address of a name is served even when unhealthy
+        assert_eq!(
+            answers,
+            [
+                a("www.et.internal", "10.0.0.2"),
+                a("api.et.internal", "10.0.0.3")
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn probes_tcp_and_http() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let port = address.port();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let _ = stream.write_all(b"HTTP/1.1 503 Unavailable\r\n\r\n").await;
+            }
+        });
+        probe(address.ip(), &HealthProbe::Tcp { port }).await?;
+        let http = HealthProbe::Http {
+            port,
+            path: "/".to_string(),
+        };
+        assert!(probe(address.ip(), &http).await.is_err());
+        let closed = TcpListener::bind("127.0.0.1:0").await?.local_addr()?.port();
+        assert!(probe(address.ip(), &HealthProbe::Tcp { port: closed })
+            .await
+            .is_err());
+
+        let health = Arc::new(Health::default());
+        let check = HealthCheckConfigBuilder::default()
+            .probe(HealthProbe::Tcp { port: closed })
+            .interval(Duration::from_millis(10))
+            .fall(2)
+            .build()?;
+        let target = (LowerName::from_str("www.et.internal.")?, address.ip());
+        let token = CancellationToken::new();
+        let task = tokio::spawn(health.clone().watch(target.clone(), check, token.clone()));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(health.unhealthy.read().unwrap().contains(&target));
+        token.cancel();
+        task.await?;
+        assert!(health.unhealthy.read().unwrap().is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,275 @@
+use crate::config::HostsConfig;
+use anyhow::{Context, Result};
+use hickory_proto::op::Query;
+use hickory_proto::rr::rdata::{A, AAAA, PTR};
+use hickory_proto::rr::{LowerName, Name, RData, Record, RecordType};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+/// Names and addresses of a set of hosts files, read again whenever one of
+/// them changes. A file that fails to be read keeps its previous entries.
+pub(crate) struct Hosts {
+    files: Vec<PathBuf>,
+    domain: Option<Name>,
+    interval: Duration,
+    ttl: u32,
+    entries: Mutex<HashMap<PathBuf, FileEntries>>,
+    table: RwLock<Table>,
+}
+
+/// A line of a hosts file: an address and its names, the canonical first.
+type Entry = (IpAddr, Vec<Name>);
+
+/// The entries of a file, with the modification time they were read at.
+type FileEntries = (Option<SystemTime>, Vec<Entry>);
+
+#[derive(Default)]
+struct Table {
+    /// Addresses of every name.
+    addresses: HashMap<LowerName, Vec<IpAddr>>,
+    /// Canonical names of every address, by its reverse name.
+    names: HashMap<LowerName, Vec<Name>>,
+}
+
+impl Hosts {
+    pub(crate) fn new(config: &HostsConfig) -> Result<Self> {
+        let domain = match config.domain() {
+            Some(domain) => Some(
+                Name::from_str(domain)
+                    .with_context(|| format!("invalid hosts domain {}", domain))?,
+            ),
+            None => None,
+        };
+        Ok(Self {
+            files: config.files().clone(),
+            domain,
+            interval: config.interval(),
+            ttl: config.ttl().as_secs().try_into().unwrap_or(u32::MAX),
+            entries: Mutex::new(HashMap::new()),
+            table: RwLock::new(Table::default()),
+        })
+    }
+
+    /// The answer to `query` when the files hold its name, as an address or
+    /// as the reverse name of an address.
+    pub(crate) fn answer(&self, query: &Query) -> Option<Vec<Record>> {
+        let name = LowerName::from(query.name());
+        let query_type = query.query_type();
+        let table = self.table.read().unwrap();
+        let record = |rdata| Record::from_rdata(query.name().clone(), self.ttl, rdata);
+        if let Some(addresses) = table.addresses.get(&name) {
+            let answers = addresses
+                .iter()
+                .filter_map(|ip| match (ip, query_type) {
+                    (IpAddr::V4(ip), RecordType::A | RecordType::ANY) => Some(RData::A(A(*ip))),
+                    (IpAddr::V6(ip), RecordType::AAAA | RecordType::ANY) => {
+                        Some(RData::AAAA(AAAA(*ip)))
+                    }
+                    _ => None,
+                })
+                .map(record)
+                .collect();
+            return Some(answers);
+        }
+        let names = table.names.get(&name)?;
+        let answers = match query_type {
+            RecordType::PTR | RecordType::ANY => names
+                .iter()
+                .map(|name| record(RData::PTR(PTR(name.clone()))))
+                .collect(),
+            _ => Vec::new(),
+        };
+        Some(answers)
+    }
+
+    /// Reads the files that changed since they were last read.
+    pub(crate) async fn load(&self) {
+        let mut changed = false;
+        for file in &self.files {
+            let modified = crate::views::modified(file);
+            let current = self.entries.lock().unwrap().get(file).map(|(m, _)| *m);
+            if current == Some(modified) {
+                continue;
+            }
+            match tokio::fs::read_to_string(file).await {
+                Ok(text) => {
+                    let entries = parse_hosts(&text);
+                    info!("loaded {} hosts from {}", entries.len(), file.display());
+                    self.entries
+                        .lock()
+                        .unwrap()
+                        .insert(file.clone(), (modified, entries));
+                    changed = true;
+                }
+                Err(e) => {
+                    warn!("failed to load hosts file {}: {}", file.display(), e);
+                    // tried again once it changes
+                    let mut entries = self.entries.lock().unwrap();
+                    entries.entry(file.clone()).or_default().0 = modified;
+                }
+            }
+        }
+        if changed {
+            let table = self.build_table();
+            *self.table.write().unwrap() = table;
+        }
+    }
+
+    /// Checks the files for changes every `interval` until `token` is
+    /// cancelled.
+    pub(crate) async fn watch(self: Arc<Self>, token: CancellationToken) {
+        let mut ticker = tokio::time::interval(self.interval);
+        ticker.tick().await;
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = token.cancelled() => break,
+            }
+            self.load().await;
+        }
+    }
+
+    /// The table of the entries of every file, in the order of the files.
+    fn build_table(&self) -> Table {
+        let entries = self.entries.lock().unwrap();
+        let mut table = Table::default();
+        for (ip, names) in self
+            .files
+            .iter()
+            .filter_map(|file| entries.get(file))
+            .flat_map(|(_, entries)| entries)
+        {
+            for name in names.iter().flat_map(|name| self.expand(name)) {
+                let addresses = table.addresses.entry(name.into()).or_default();
+                if !addresses.contains(ip) {
+                    addresses.push(*ip);
+                }
+            }
+            // the first name, qualified with the domain when it applies
+            let Some(canonical) = names.first().and_then(|name| self.expand(name).pop()) else {
+                continue;
+            };
+            let reverse = table.names.entry(Name::from(*ip).into()).or_default();
+            if !reverse.contains(&canonical) {
+                reverse.push(canonical);
+            }
+        }
+        debug!("serving {} names from hosts files", table.addresses.len());
+        table
+    }
+
+    /// `name` and, when it has no dot, `name` under the domain, last.
+    fn expand(&self, name: &Name) -> Vec<Name> {
+        match &self.domain {
+            Some(domain) if name.num_labels() == 1 => {
+                let mut names = vec![name.clone()];
+                names.extend(name.clone().append_domain(domain).ok());
+                names
+            }
+            _ => vec![name.clone()],
+        }
+    }
+}
+
+/// The entries of a hosts file. Invalid addresses and names are skipped.
+fn parse_hosts(text: &str) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut fields = line.split_whitespace();
+        let Some(address) = fields.next() else {
+            continue;
+        };
+        // a zone index, as in `fe80::1%eth0`, doesn't apply to DNS answers
+        let address = address.split('%').next().unwrap_or_default();
+        let Ok(ip) = address.parse::<IpAddr>() else {
+            debug!("skipped hosts line with invalid address {:?}", address);
+            continue;
+        };
+        let names: Vec<Name> = fields
+            .filter_map(|name| match Name::from_ascii(name) {
+                Ok(mut name) => {
+                    name.set_fqdn(true);
+                    Some(name)
+                }
+                Err(_) => {
+                    debug!("skipped invalid host name {:?}", name);
+                    None
+                }
+            })
+            .collect();
+        if !names.is_empty() {
+            entries.push((ip, names));
+        }
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HostsConfigBuilder;
+
+    #[tokio::test]
+    async fn answers_from_hosts_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("hosts");
+        std::fs::write(
+            &file,
+            "# comment\n\
+             127.0.0.1 localhost\n\
+             192.0.2.1 nas storage.example.com # trailing comment\n\
+             192.0.2.1 backup\n\
+             fe80::1%eth0 nas\n\
+             not-an-address ignored\n",
+        )?;
+        let hosts = Hosts::new(
+            &HostsConfigBuilder::default()
+                .files(vec![file.clone(), dir.path().join("missing")])
+                .domain("lan")
+                .build()?,
+        )?;
+        hosts.load().await;
+        let answer = |name: &str, query_type| -> Option<Vec<String>> {
+            let query = Query::query(Name::from_str(name).unwrap(), query_type);
+            let answers = hosts.answer(&query)?;
+            Some(
+                answers
+                    .iter()
+                    .map(|r| r.data().unwrap().to_string())
+                    .collect(),
+            )
+        };
+        assert_eq!(answer("nas.", RecordType::A).unwrap(), ["192.0.2.1"]);
+        assert_eq!(answer("NAS.lan.", RecordType::AAAA).unwrap(), ["fe80::1"]);
+        assert_eq!(
+            answer("storage.example.com.", RecordType::A).unwrap(),
+            ["192.0.2.1"]
+        );
+        assert!(answer("storage.example.com.lan.", RecordType::A).is_none());
+        assert!(answer("nas.", RecordType::MX).unwrap().is_empty());
+        assert!(answer("ignored.", RecordType::A).is_none());
+        assert_eq!(
+            answer("1.2.0.192.in-addr.arpa.", RecordType::PTR).unwrap(),
+            ["nas.lan.", "backup.lan."]
+        );
+
+        // changes are picked up
+        std::fs::write(&file, "192.0.2.2 nas\n")?;
+        let later = SystemTime::now() + Duration::from_secs(10);
+        std::fs::File::options()
+            .write(true)
+            .open(&file)?
+            .set_modified(later)?;
+        hosts.load().await;
+        assert_eq!(answer("nas.lan.", RecordType::A).unwrap(), ["192.0.2.2"]);
+        assert!(answer("backup.", RecordType::A).is_none());
+        Ok(())
+    }
+}
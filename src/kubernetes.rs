@@ -0,0 +1,691 @@
+use crate::config::{self, KubernetesConfig};
+use crate::discovery::{self, address_record};
+use crate::zones::ZoneSet;
+#[cfg(feature = "kubernetes")]
+use anyhow::Context;
+use anyhow::{anyhow, Result};
+use hickory_proto::rr;
+use hickory_proto::rr::RData;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+/// Seconds after which the API server ends a watch, which is then resumed.
+#[cfg_attr(not(feature = "kubernetes"), allow(dead_code))]
+const WATCH_TIMEOUT: u64 = 300;
+
+/// Synthesizes the records of a Kubernetes cluster into the zone of its
+/// `domain`, following the changes of its objects with the watch API.
+pub(crate) struct Kubernetes {
+    origin: rr::Name,
+    ttl: u32,
+    namespaces: Vec<String>,
+    client: Client,
+    cluster: Mutex<Cluster>,
+}
+
+impl Kubernetes {
+    pub(crate) fn new(config: &KubernetesConfig, zones: &config::Zone) -> Result<Self> {
+        Ok(Self {
+            origin: discovery::origin("kubernetes domain", config.domain(), zones)?,
+            ttl: config.ttl().as_secs() as u32,
+            namespaces: config.namespaces().clone(),
+            client: Client::new(config)?,
+            cluster: Mutex::new(Cluster::default()),
+        })
+    }
+
+    /// Serves the zone, empty until the objects are listed, and keeps it in
+    /// sync with the cluster until `token` is cancelled.
+    pub(crate) async fn start(
+        self: Arc<Self>,
+        zones: Arc<ZoneSet>,
+        token: CancellationToken,
+    ) -> Result<()> {
+        zones.serve_records(&self.origin, Vec::new()).await?;
+        let namespaces: Vec<Option<String>> = match self.namespaces.is_empty() {
+            true => vec![None],
+            false => self.namespaces.iter().cloned().map(Some).collect(),
+        };
+        for namespace in namespaces {
+            tokio::spawn(self.clone().watch::<Service>(
+                zones.clone(),
+                namespace.clone(),
+                token.clone(),
+            ));
+            tokio::spawn(self.clone().watch::<Endpoints>(
+                zones.clone(),
+                namespace.clone(),
+                token.clone(),
+            ));
+            tokio::spawn(
+                self.clone()
+                    .watch::<Ingress>(zones.clone(), namespace, token.clone()),
+            );
+        }
+        Ok(())
+    }
+
+    /// Lists and watches the `T` objects of `namespace`, or of every
+    /// namespace, listing them again whenever the watch fails.
+    async fn watch<T: Resource>(
+        self: Arc<Self>,
+        zones: Arc<ZoneSet>,
+        namespace: Option<String>,
+        token: CancellationToken,
+    ) {
+        let what = format!("watch kubernetes {}", T::PLURAL);
+        discovery::follow(&what, &token, || {
+            self.list_and_watch::<T>(&zones, namespace.as_deref())
+        })
+        .await;
+    }
+
+    async fn list_and_watch<T: Resource>(
+        &self,
+        zones: &ZoneSet,
+        namespace: Option<&str>,
+    ) -> Result<()> {
+        let path = T::path(namespace);
+        let list: List<T> = serde_json::from_slice(&self.client.get(&path).await?)?;
+        let mut version = list.metadata.resource_version;
+        {
+            let mut cluster = self.cluster.lock().await;
+            let objects = T::objects(&mut cluster);
+            objects.retain(|(ns, _), _| namespace.is_some_and(|namespace| ns != namespace));
+            objects.extend(list.items.into_iter().map(|item| (item.key(), item)));
+            self.publish(&cluster, zones).await;
+        }
+        info!("listed kubernetes {} at version {}", T::PLURAL, version);
+        loop {
+            let path = format!(
+                "{}?watch=1&allowWatchBookmarks=true&timeoutSeconds={}&resourceVersion={}",
+                path, WATCH_TIMEOUT, version
+            );
+            let mut lines = self.client.watch(&path).await?;
+            while let Some(line) = lines.next().await? {
+                let event: WatchEvent = serde_json::from_slice(&line)?;
+                let mut cluster = self.cluster.lock().await;
+                let (changed, resource_version) = cluster.apply::<T>(event)?;
+                if changed {
+                    self.publish(&cluster, zones).await;
+                }
+                if let Some(resource_version) = resource_version {
+                    version = resource_version;
+                }
+            }
+        }
+    }
+
+    async fn publish(&self, cluster: &Cluster, zones: &ZoneSet) {
+        let records = cluster.records(&self.origin, self.ttl);
+        if let Err(e) = zones.serve_records(&self.origin, records).await {
+            warn!("failed to serve kubernetes zone {}: {:#}", self.origin, e);
+        }
+    }
+}
+
+type Objects<T> = BTreeMap<(String, String), T>;
+
+/// The watched objects of a cluster, by namespace and name.
+#[derive(Default)]
+struct Cluster {
+    services: Objects<Service>,
+    endpoints: Objects<Endpoints>,
+    ingresses: Objects<Ingress>,
+}
+
+impl Cluster {
+    /// Applies a watch event of `T` objects, returns whether the objects
+    /// changed and the resource version the watch is at.
+    fn apply<T: Resource>(&mut self, event: WatchEvent) -> Result<(bool, Option<String>)> {
+        match event.kind.as_str() {
+            "ADDED" | "MODIFIED" | "DELETED" => {
+                let object: T = serde_json::from_value(event.object)?;
+                let version = object.metadata().resource_version.clone();
+                let objects = T::objects(self);
+                if event.kind == "DELETED" {
+                    objects.remove(&object.key());
+                } else {
+                    objects.insert(object.key(), object);
+                }
+                Ok((true, version))
+            }
+            "BOOKMARK" => {
+                let object: Bookmark = serde_json::from_value(event.object)?;
+                Ok((false, object.metadata.resource_version))
+            }
+            // mostly 410 Gone, the version is too old to resume from
+            "ERROR" => Err(anyhow!(
+                "watch of {} failed: {}",
+                T::PLURAL,
+                event.object["message"].as_str().unwrap_or("unknown error")
+            )),
+            kind => {
+                debug!("skipped kubernetes watch event {}", kind);
+                Ok((false, None))
+            }
+        }
+    }
+
+    /// The records of the cluster in the zone `origin`.
+    fn records(&self, origin: &rr::Name, ttl: u32) -> Vec<rr::Record> {
+        let mut records = Vec::new();
+        for ((namespace, name), service) in &self.services {
+            let Ok(service_name) = rr::Name::from_ascii(format!("{}.{}.svc", name, namespace))
+                .and_then(|name| name.append_name(origin))
+            else {
+                debug!("skipped kubernetes service {}/{}", namespace, name);
+                continue;
+            };
+            let spec = &service.spec;
+            if spec.service_type.as_deref() == Some("ExternalName") {
+                if let Some(target) = spec.external_name.as_deref().and_then(absolute) {
+                    let cname = RData::CNAME(rr::rdata::CNAME(target));
+                    records.push(rr::Record::from_rdata(service_name, ttl, cname));
+                }
+                continue;
+            }
+            if spec.cluster_ip.as_deref() == Some("None") {
+                // headless, resolved to its ready endpoints
+                let key = (namespace.clone(), name.clone());
+                let addresses = self
+                    .endpoints
+                    .get(&key)
+                    .into_iter()
+                    .flat_map(|endpoints| &endpoints.subsets)
+                    .flat_map(|subset| &subset.addresses);
+                for address in addresses {
+                    let Ok(ip) = address.ip.parse() else {
+                        continue;
+                    };
+                    records.push(address_record(&service_name, ip, ttl));
+                    let hostname = address.hostname.as_deref().map(|hostname| {
+                        rr::Name::from_ascii(hostname)
+                            .and_then(|hostname| hostname.append_name(&service_name))
+                    });
+                    if let Some(Ok(hostname)) = hostname {
+                        records.push(address_record(&hostname, ip, ttl));
+                    }
+                }
+            } else {
+                let ips = match spec.cluster_ips.is_empty() {
+                    true => spec.cluster_ip.iter().collect::<Vec<_>>(),
+                    false => spec.cluster_ips.iter().collect(),
+                };
+                for ip in ips.into_iter().filter_map(|ip| ip.parse().ok()) {
+                    records.push(address_record(&service_name, ip, ttl));
+                }
+            }
+            for port in &spec.ports {
+                let Some(port_name) = &port.name else {
+                    continue;
+                };
+                let protocol = port.protocol.as_deref().unwrap_or("TCP");
+                let srv_name = rr::Name::from_ascii(format!(
+                    "_{}._{}",
+                    port_name,
+                    protocol.to_ascii_lowercase()
+                ))
+                .and_then(|srv_name| srv_name.append_name(&service_name));
+                if let Ok(srv_name) = srv_name {
+                    let srv = rr::rdata::SRV::new(0, 100, port.port, service_name.clone());
+                    records.push(rr::Record::from_rdata(srv_name, ttl, RData::SRV(srv)));
+                }
+            }
+        }
+        for ((namespace, name), ingress) in &self.ingresses {
+            let balancers = &ingress.status.load_balancer.ingress;
+            let ips: Vec<IpAddr> = balancers
+                .iter()
+                .filter_map(|balancer| balancer.ip.as_deref()?.parse().ok())
+                .collect();
+            let target = balancers
+                .iter()
+                .find_map(|balancer| balancer.hostname.as_deref().and_then(absolute));
+            for host in ingress
+                .spec
+                .rules
+                .iter()
+                .filter_map(|rule| rule.host.as_deref())
+            {
+                let host = match absolute(host) {
+                    Some(host) if origin.zone_of(&host) => host,
+                    _ => {
+                        debug!(
+                            "skipped host {} of kubernetes ingress {}/{} outside {}",
+                            host, namespace, name, origin
+                        );
+                        continue;
+                    }
+                };
+                if !ips.is_empty() {
+                    for ip in &ips {
+                        records.push(address_record(&host, *ip, ttl));
+                    }
+                } else if let Some(target) = &target {
+                    let cname = RData::CNAME(rr::rdata::CNAME(target.clone()));
+                    records.push(rr::Record::from_rdata(host, ttl, cname));
+                }
+            }
+        }
+        records
+    }
+}
+
+/// `name` made absolute, Kubernetes names are.
+fn absolute(name: &str) -> Option<rr::Name> {
+    let mut name = rr::Name::from_ascii(name).ok()?;
+    name.set_fqdn(true);
+    Some(name)
+}
+
+/// A kind of object the backend watches.
+trait Resource: DeserializeOwned + Send + 'static {
+    /// API group and version of the kind, such as `api/v1`.
+    const API: &'static str;
+    const PLURAL: &'static str;
+
+    fn metadata(&self) -> &ObjectMeta;
+
+    fn objects(cluster: &mut Cluster) -> &mut Objects<Self>;
+
+    fn key(&self) -> (String, String) {
+        let metadata = self.metadata();
+        (metadata.namespace.clone(), metadata.name.clone())
+    }
+
+    /// Path of the objects of `namespace`, or of every namespace.
+    fn path(namespace: Option<&str>) -> String {
+        match namespace {
+            Some(namespace) => format!("/{}/namespaces/{}/{}", Self::API, namespace, Self::PLURAL),
+            None => format!("/{}/{}", Self::API, Self::PLURAL),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct ObjectMeta {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    namespace: String,
+    #[serde(default)]
+    resource_version: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct List<T> {
+    metadata: ListMeta,
+    items: Vec<T>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ListMeta {
+    resource_version: String,
+}
+
+#[derive(Deserialize)]
+struct WatchEvent {
+    #[serde(rename = "type")]
+    kind: String,
+    object: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct Bookmark {
+    metadata: ObjectMeta,
+}
+
+#[derive(Deserialize, Debug)]
+struct Service {
+    metadata: ObjectMeta,
+    #[serde(default)]
+    spec: ServiceSpec,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct ServiceSpec {
+    #[serde(default, rename = "type")]
+    service_type: Option<String>,
+    #[serde(default, rename = "clusterIP")]
+    cluster_ip: Option<String>,
+    #[serde(default, rename = "clusterIPs")]
+    cluster_ips: Vec<String>,
+    #[serde(default)]
+    external_name: Option<String>,
+    #[serde(default)]
+    ports: Vec<ServicePort>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ServicePort {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    protocol: Option<String>,
+    port: u16,
+}
+
+impl Resource for Service {
+    const API: &'static str = "api/v1";
+    const PLURAL: &'static str = "services";
+
+    fn metadata(&self) -> &ObjectMeta {
+        &self.metadata
+    }
+
+    fn objects(cluster: &mut Cluster) -> &mut Objects<Self> {
+        &mut cluster.services
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct Endpoints {
+    metadata: ObjectMeta,
+    #[serde(default)]
+    subsets: Vec<EndpointSubset>,
+}
+
+/// The ready addresses of a subset, those not ready are left out.
+#[derive(Deserialize, Debug)]
+struct EndpointSubset {
+    #[serde(default)]
+    addresses: Vec<EndpointAddress>,
+}
+
+#[derive(Deserialize, Debug)]
+struct EndpointAddress {
+    ip: String,
+    #[serde(default)]
+    hostname: Option<String>,
+}
+
+impl Resource for Endpoints {
+    const API: &'static str = "api/v1";
+    const PLURAL: &'static str = "endpoints";
+
+    fn metadata(&self) -> &ObjectMeta {
+        &self.metadata
+    }
+
+    fn objects(cluster: &mut Cluster) -> &mut Objects<Self> {
+        &mut cluster.endpoints
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct Ingress {
+    metadata: ObjectMeta,
+    #[serde(default)]
+    spec: IngressSpec,
+    #[serde(default)]
+    status: IngressStatus,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct IngressSpec {
+    #[serde(default)]
+    rules: Vec<IngressRule>,
+}
+
+#[derive(Deserialize, Debug)]
+struct IngressRule {
+    #[serde(default)]
+    host: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct IngressStatus {
+    #[serde(default)]
+    load_balancer: LoadBalancerStatus,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct LoadBalancerStatus {
+    #[serde(default)]
+    ingress: Vec<LoadBalancerIngress>,
+}
+
+#[derive(Deserialize, Debug)]
+struct LoadBalancerIngress {
+    #[serde(default)]
+    ip: Option<String>,
+    #[serde(default)]
+    hostname: Option<String>,
+}
+
+impl Resource for Ingress {
+    const API: &'static str = "apis/networking.k8s.io/v1";
+    const PLURAL: &'static str = "ingresses";
+
+    fn metadata(&self) -> &ObjectMeta {
+        &self.metadata
+    }
+
+    fn objects(cluster: &mut Cluster) -> &mut Objects<Self> {
+        &mut cluster.ingresses
+    }
+}
+
+#[cfg(feature = "kubernetes")]
+const TOKEN_FILE: &str = "/var/run/secrets/kubernetes.io/serviceaccount/token";
+#[cfg(feature = "kubernetes")]
+const CA_FILE: &str = "/var/run/secrets/kubernetes.io/serviceaccount/ca.crt";
+
+/// Client of the API server, authenticated with a bearer token read again
+/// for every request since service account tokens are rotated.
+#[cfg(feature = "kubernetes")]
+struct Client {
+    http: reqwest::Client,
+    base: String,
+    token_file: Option<std::path::PathBuf>,
+}
+
+#[cfg(feature = "kubernetes")]
+impl Client {
+    fn new(config: &KubernetesConfig) -> Result<Self> {
+        use std::path::Path;
+
+        let base = match config.api_server() {
+            Some(url) => url.trim_end_matches('/').to_string(),
+            None => {
+                let host = std::env::var("KUBERNETES_SERVICE_HOST")
+                    .context("api_server is required outside of a Kubernetes cluster")?;
+                let port = std::env::var("KUBERNETES_SERVICE_PORT").unwrap_or("443".to_string());
+                match host.parse() {
+                    Ok(IpAddr::V6(host)) => format!("https://[{}]:{}", host, port),
+                    _ => format!("https://{}:{}", host, port),
+                }
+            }
+        };
+        let token_file = match config.token_file() {
+            Some(file) => Some(file.to_path_buf()),
+            None => Some(Path::new(TOKEN_FILE).to_path_buf()).filter(|file| file.exists()),
+        };
+        let ca_file = match config.ca_file() {
+            Some(file) => Some(file),
+            None => Some(Path::new(CA_FILE)).filter(|file| file.exists()),
+        };
+        let mut http = reqwest::Client::builder();
+        if let Some(file) = ca_file {
+            let pem = std::fs::read(file)
+                .with_context(|| format!("failed to read {}", file.display()))?;
+            http = http.add_root_certificate(reqwest::Certificate::from_pem(&pem)?);
+        }
+        Ok(Self {
+            http: http.build()?,
+            base,
+            token_file,
+        })
+    }
+
+    async fn request(&self, path: &str) -> Result<reqwest::Response> {
+        let mut request = self.http.get(format!("{}{}", self.base, path));
+        if let Some(file) = &self.token_file {
+            let token = tokio::fs::read_to_string(file)
+                .await
+                .with_context(|| format!("failed to read {}", file.display()))?;
+            request = request.bearer_auth(token.trim());
+        }
+        Ok(request.send().await?.error_for_status()?)
+    }
+
+    async fn get(&self, path: &str) -> Result<Vec<u8>> {
+        Ok(self.request(path).await?.bytes().await?.to_vec())
+    }
+
+    async fn watch(&self, path: &str) -> Result<Lines> {
+        Ok(Lines::new(self.request(path).await?))
+    }
+}
+
+/// The events of a watch, one JSON object per line.
+#[cfg(feature = "kubernetes")]
+type Lines = discovery::Lines<reqwest::Response>;
+
+#[cfg(not(feature = "kubernetes"))]
+enum Client {}
+
+#[cfg(not(feature = "kubernetes"))]
+impl Client {
+    fn new(_config: &KubernetesConfig) -> Result<Self> {
+        anyhow::bail!("the Kubernetes backend requires the `kubernetes` feature")
+    }
+
+    async fn get(&self, _path: &str) -> Result<Vec<u8>> {
+        match *self {}
+    }
+
+    async fn watch(&self, _path: &str) -> Result<Lines> {
+        match *self {}
+    }
+}
+
+#[cfg(not(feature = "kubernetes"))]
+type Lines = discovery::Lines<std::convert::Infallible>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::str::FromStr;
+
+    fn event(kind: &str, object: serde_json::Value) -> WatchEvent {
+        WatchEvent {
+            kind: kind.to_string(),
+            object,
+        }
+    }
+
+    #[test]
+    fn synthesizes_records() -> Result<()> {
+        let mut cluster = Cluster::default();
+        let service = |name: &str, spec: serde_json::Value| {
+            event(
+                "ADDED",
+                json!({ "metadata": { "name": name, "namespace": "default" }, "spec": spec }),
+            )
+        };
+        cluster.apply::<Service>(service(
+            "web",
+            json!({
+                "type": "ClusterIP",
+                "clusterIP": "10.96.0.10",
+                "clusterIPs": ["10.96.0.10", "fd00::10"],
+                "ports": [{ "name": "http", "protocol": "TCP", "port": 80 }, { "port": 8080 }],
+            }),
+        ))?;
+        cluster.apply::<Service>(service("db", json!({ "clusterIP": "None" })))?;
+        cluster.apply::<Service>(service(
+            "upstream",
+            json!({ "type": "ExternalName", "externalName": "api.example.com" }),
+        ))?;
+        cluster.apply::<Endpoints>(event(
+            "ADDED",
+            json!({
+                "metadata": { "name": "db", "namespace": "default" },
+                "subsets": [{
+                    "addresses": [{ "ip": "10.244.0.5", "hostname": "db-0" }],
+                    "notReadyAddresses": [{ "ip": "10.244.0.6" }],
+                }],
+            }),
+        ))?;
+        cluster.apply::<Ingress>(event(
+            "ADDED",
+            json!({
+                "metadata": { "name": "web", "namespace": "default" },
+                "spec": { "rules": [{ "host": "www.cluster.local" }, { "host": "www.example.com" }] },
+                "status": { "loadBalancer": { "ingress": [{ "ip": "192.0.2.10" }] } },
+            }),
+        ))?;
+
+        let origin = rr::Name::from_str("cluster.local.")?;
+        let records = cluster.records(&origin, 30);
+        let find = |name: &str| -> Vec<String> {
+            let name = rr::Name::from_str(name).unwrap();
+            records
+                .iter()
+                .filter(|r| r.name() == &name)
+                .map(|r| format!("{} {}", r.record_type(), r.data().unwrap()))
+                .collect()
+        };
+        assert_eq!(
+            find("web.default.svc.cluster.local."),
+            ["A 10.96.0.10", "AAAA fd00::10"]
+        );
+        assert_eq!(
+            find("_http._tcp.web.default.svc.cluster.local."),
+            ["SRV 0 100 80 web.default.svc.cluster.local."]
+        );
+        assert_eq!(find("db.default.svc.cluster.local."), ["A 10.244.0.5"]);
+        assert_eq!(find("db-0.db.default.svc.cluster.local."), ["A 10.244.0.5"]);
+        assert_eq!(
+            find("upstream.default.svc.cluster.local."),
+            ["CNAME api.example.com."]
+        );
+        assert_eq!(find("www.cluster.local."), ["A 192.0.2.10"]);
+        assert!(find("www.example.com.").is_empty());
+        assert_eq!(records.len(), 7);
+        Ok(())
+    }
+
+    #[test]
+    fn applies_watch_events() -> Result<()> {
+        let mut cluster = Cluster::default();
+        let web = json!({
+            "metadata": { "name": "web", "namespace": "default", "resourceVersion": "7" },
+            "spec": { "clusterIP": "10.96.0.10" },
+        });
+        assert_eq!(
+            cluster.apply::<Service>(event("ADDED", web.clone()))?,
+            (true, Some("7".to_string()))
+        );
+        assert_eq!(cluster.services.len(), 1);
+        let bookmark = json!({ "metadata": { "resourceVersion": "9" } });
+        assert_eq!(
+            cluster.apply::<Service>(event("BOOKMARK", bookmark))?,
+            (false, Some("9".to_string()))
+        );
+        cluster.apply::<Service>(event("DELETED", web))?;
+        assert!(cluster.services.is_empty());
+        let gone = json!({ "kind": "Status", "code": 410, "message": "too old resource version" });
+        assert!(cluster.apply::<Service>(event("ERROR", gone)).is_err());
+
+        assert_eq!(Service::path(None), "/api/v1/services");
+        assert_eq!(
+            Ingress::path(Some("web")),
+            "/apis/networking.k8s.io/v1/namespaces/web/ingresses"
+        );
+        Ok(())
+    }
+}
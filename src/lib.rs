@@ -1,5 +1,76 @@
+mod acl;
+mod acme;
+mod acme_certificate;
+#[cfg(feature = "admin")]
+mod admin;
+#[cfg(any(feature = "admin", feature = "grpc"))]
+mod auth;
+mod blocklist;
+mod cache;
+mod catalog;
+mod catalog_zone;
+mod chaos;
 pub mod config;
+mod connections;
+mod consul;
+#[cfg(unix)]
+pub mod control;
+mod delegation;
+mod discovery;
 pub mod dns;
+mod dnssec;
+mod docker;
+#[cfg(feature = "doh")]
+mod doh;
+mod drain;
+mod ecs;
+mod ede;
+mod edit;
+mod error;
+mod etcd;
+mod forward;
+mod generate;
+mod geoip;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+mod handler;
+mod health;
+mod hosts;
+mod kubernetes;
+mod lint;
+mod mdns;
+pub mod middleware;
+mod notify;
+mod nsid;
+mod postgres;
+mod ratelimit;
+mod redirect;
+mod redis;
+mod rewrite;
+mod secondary;
+pub mod sqlite;
+mod ssh;
+mod strict;
+mod stub;
+#[cfg(feature = "otel")]
+pub mod telemetry;
+pub mod testing;
+mod tls;
+mod truncation;
+mod tsig;
+mod ttl;
+mod update;
+mod upstream;
+mod validate;
+mod views;
+mod wildcard;
+mod zonefile;
+mod zones;
 
+pub use cache::CacheStats;
 pub use config::*;
 pub use dns::*;
+pub use error::Error;
+pub use lint::Diagnostic;
+pub use upstream::UpstreamStats;
+pub use views::CatalogSnapshot;
@@ -0,0 +1,275 @@
+//! Checks of the zones of a config for problems that don't keep it from
+//! loading but break or confuse resolvers, see [`RunConfig::validate`].
+
+use crate::config::{self, RunConfig};
+use hickory_proto::rr::{self, RData, RecordType};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::str::FromStr;
+
+/// A problem of a zone found by [`RunConfig::validate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The view of the zone, `None` for the zones served to every client.
+    pub view: Option<String>,
+    pub zone: String,
+    /// Index of the record in the `records` of the zone, `None` for the
+    /// zone itself and for records from its file, services or generators.
+    pub record: Option<usize>,
+    pub reason: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(view) = &self.view {
+            write!(f, "view {}, ", view)?;
+        }
+        write!(f, "zone {}", self.zone)?;
+        if let Some(record) = self.record {
+            write!(f, ", record {}", record)?;
+        }
+        write!(f, ": {}", self.reason)
+    }
+}
+
+/// Checks every zone of `config` and of its views.
+pub(crate) fn validate(config: &RunConfig) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let views = config
+        .views()
+        .iter()
+        .map(|view| (Some(view.name()), view.zones()));
+    for (view, zones) in std::iter::once((None, config.zones())).chain(views) {
+        let mut domains: Vec<&String> = zones.keys().collect();
+        domains.sort();
+        for domain in domains {
+            let mut report = |record: Option<usize>, reason: String| {
+                diagnostics.push(Diagnostic {
+                    view: view.map(str::to_string),
+                    zone: domain.clone(),
+                    record,
+                    reason,
+                })
+            };
+            check_zone(domain, &zones[domain], &mut report);
+        }
+    }
+    diagnostics
+}
+
+fn check_zone(
+    domain: &str,
+    zone_config: &config::ZoneConfig,
+    report: &mut impl FnMut(Option<usize>, String),
+) {
+    let origin = match rr::Name::from_str(domain) {
+        Ok(mut origin) => {
+            origin.set_fqdn(true);
+            origin
+        }
+        Err(e) => return report(None, format!("invalid zone name: {}", e)),
+    };
+    if zone_config.zone_type() != config::ZoneType::Primary {
+        if !zone_config.records().is_empty() {
+            report(
+                None,
+                format!("records of a {} zone are ignored", zone_config.zone_type()),
+            );
+        }
+        return;
+    }
+
+    let mut records: Vec<(usize, rr::Record)> = Vec::new();
+    let mut valid = zone_config.clone();
+    valid.records_mut().clear();
+    for (index, record) in zone_config.records().iter().enumerate() {
+        match zone_config.build_record(record, &origin) {
+            Ok(converted) => {
+                check_record(&converted, &mut |reason| report(Some(index), reason));
+                records.push((index, converted));
+                valid.records_mut().push(record.clone());
+            }
+            Err(e) => report(Some(index), format!("{:#}", anyhow::Error::from(e))),
+        }
+        if let Some(first) = zone_config.records()[..index]
+            .iter()
+            .position(|other| other.same_data(record))
+        {
+            report(Some(index), format!("duplicate of record {}", first));
+        }
+    }
+
+    // the whole zone, with the records of its file, services and generators
+    // and the synthesized apex
+    let all = match valid.to_records(&origin) {
+        Ok(all) => all,
+        Err(e) => return report(None, format!("{:#}", e)),
+    };
+    let index_of = |record: &rr::Record| {
+        records
+            .iter()
+            .find(|(_, other)| other == record)
+            .map(|(index, _)| *index)
+    };
+    for soa in all.iter().filter(|r| r.record_type() == RecordType::SOA) {
+        if *soa.name() != origin {
+            report(
+                index_of(soa),
+                format!("SOA record below the apex at {}", soa.name()),
+            );
+        }
+    }
+
+    let mut types: BTreeMap<&rr::Name, BTreeSet<RecordType>> = BTreeMap::new();
+    for record in &all {
+        types
+            .entry(record.name())
+            .or_default()
+            .insert(record.record_type());
+    }
+    for cname in all.iter().filter(|r| r.record_type() == RecordType::CNAME) {
+        let others: Vec<String> = types[cname.name()]
+            .iter()
+            .filter(|rr_type| {
+                !matches!(
+                    rr_type,
+                    RecordType::CNAME | RecordType::RRSIG | RecordType::NSEC
+                )
+            })
+            .map(ToString::to_string)
+            .collect();
+        if !others.is_empty() {
+            report(
+                index_of(cname),
+                format!(
+                    "CNAME {} coexists with {} records",
+                    cname.name(),
+                    others.join(", ")
+                ),
+            );
+        }
+        let cnames = all
+            .iter()
+            .filter(|r| r.record_type() == RecordType::CNAME && r.name() == cname.name())
+            .count();
+        if cnames > 1 {
+            report(
+                index_of(cname),
+                format!("{} has {} CNAME records", cname.name(), cnames),
+            );
+        }
+    }
+}
+
+/// Checks a record on its own.
+fn check_record(record: &rr::Record, report: &mut impl FnMut(String)) {
+    let name = record.name();
+    let host = match record.data() {
+        Some(RData::A(_) | RData::AAAA(_)) => Some(name),
+        Some(RData::NS(ns)) => Some(&ns.0),
+        Some(RData::MX(mx)) => Some(mx.exchange()),
+        Some(RData::SRV(srv)) if !srv.target().is_root() => Some(srv.target()),
+        _ => None,
+    };
+    if let Some(host) = host.filter(|host| !is_host_name(host)) {
+        report(format!("{} is not a valid host name", host));
+    }
+    if record.record_type() == RecordType::NS && record.ttl() == 0 {
+        report(format!("NS record of {} with a TTL of 0", name));
+    }
+}
+
+/// Whether `name` is a host name (RFC 952, RFC 1123): letters, digits and
+/// inner hyphens in every label, a leading `*` label aside.
+fn is_host_name(name: &rr::Name) -> bool {
+    let mut labels = name.iter().peekable();
+    if labels.peek() == Some(&&b"*"[..]) {
+        labels.next();
+    }
+    labels.all(|label| {
+        !label.is_empty()
+            && label.first() != Some(&b'-')
+            && label.last() != Some(&b'-')
+            && label
+                .iter()
+                .all(|c| c.is_ascii_alphanumeric() || *c == b'-')
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{
+        GeneralConfigBuilder, RecordBuilder, RecordData, RunConfigBuilder, ZoneConfigBuilder,
+    };
+    use maplit::hashmap;
+    use std::time::Duration;
+
+    fn record(name: &str, data: RecordData, ttl: u64) -> config::Record {
+        RecordBuilder::default()
+            .name(name.to_string())
+            .data(data)
+            .ttl(Duration::from_secs(ttl))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn reports_problems_of_zones() -> anyhow::Result<()> {
+        let name = |name: &str| rr::Name::from_str(name).unwrap();
+        let records = vec![
+            record("www.et.internal", RecordData::A("10.0.0.1".parse()?), 60),
+            record("www.et.top.", RecordData::A("10.0.0.2".parse()?), 60),
+            record(
+                "api.et.internal",
+                RecordData::Cname(name("www.et.internal")),
+                60,
+            ),
+            record(
+                "api.et.internal",
+                RecordData::Txt(vec!["v=1".to_string()]),
+                60,
+            ),
+            record(
+                "lab.et.internal",
+                RecordData::Ns(name("ns1.lab.et.internal")),
+                0,
+            ),
+            record("www.et.internal", RecordData::A("10.0.0.1".parse()?), 300),
+            record(
+                "my_host.et.internal",
+                RecordData::A("10.0.0.3".parse()?),
+                60,
+            ),
+            record("*.dev.et.internal", RecordData::A("10.0.0.4".parse()?), 60),
+        ];
+        let config = RunConfigBuilder::default()
+            .general(GeneralConfigBuilder::default().build()?)
+            .zones(hashmap! {
+                "et.internal".to_string() => records.into(),
+                "et.example".to_string() => ZoneConfigBuilder::default()
+                    .zone_type(config::ZoneType::Secondary)
+                    .primaries(vec!["192.0.2.1".to_string()])
+                    .records(vec![record("www.et.example", RecordData::A("10.0.0.5".parse()?), 60)])
+                    .build()?,
+                "et.clean".to_string() => vec![
+                    record("www.et.clean", RecordData::A("10.0.0.6".parse()?), 60),
+                ].into(),
+            })
+            .build()?;
+
+        let found: Vec<String> = config.validate().iter().map(ToString::to_string).collect();
+        assert_eq!(
+            found,
+            [
+                "zone et.example: records of a secondary zone are ignored",
+                "zone et.internal, record 1: invalid record www.et.top.: www.et.top. is outside zone et.internal.",
+                "zone et.internal, record 4: NS record of lab.et.internal with a TTL of 0",
+                "zone et.internal, record 5: duplicate of record 0",
+                "zone et.internal, record 6: my_host.et.internal is not a valid host name",
+                "zone et.internal, record 2: CNAME api.et.internal coexists with TXT records",
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,352 @@
+use crate::zones::ZoneSet;
+use hickory_proto::op::{Message, MessageType, OpCode, ResponseCode};
+use hickory_proto::rr;
+use hickory_proto::rr::{DNSClass, LowerName, RecordType};
+use hickory_server::authority::{Authority, LookupOptions};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+
+pub(crate) const GROUP_V4: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+pub(crate) const GROUP_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
+
+/// The unicast-response bit of a question class, and the cache-flush bit of
+/// a record class.
+const TOP_BIT: u16 = 0x8000;
+/// TTL cap of the answers to legacy unicast queries (RFC 6762 section 6.7).
+const LEGACY_TTL: u32 = 10;
+const MAX_MESSAGE_SIZE: usize = 9000;
+const ANNOUNCEMENTS: usize = 2;
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Multicast DNS responder (RFC 6762) of the `.local` records of a zone set,
+/// on one multicast group. It never answers negatively and doesn't probe
+/// for conflicts: the zones are authoritative for their names.
+pub(crate) struct Responder {
+    socket: UdpSocket,
+    /// Where multicast responses and announcements go.
+    group: SocketAddr,
+    zones: Arc<ZoneSet>,
+    local: LowerName,
+}
+
+impl Responder {
+    /// Binds the mDNS `port` joined to `group`. Other responders of the
+    /// host can bind it as well.
+    pub(crate) fn bind(group: IpAddr, port: u16, zones: Arc<ZoneSet>) -> io::Result<Self> {
+        let domain = match group {
+            IpAddr::V4(_) => Domain::IPV4,
+            IpAddr::V6(_) => Domain::IPV6,
+        };
+        let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_reuse_address(true)?;
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+        match group {
+            IpAddr::V4(group) => {
+                socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)).into())?;
+                socket.join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED)?;
+                socket.set_multicast_ttl_v4(255)?;
+            }
+            IpAddr::V6(group) => {
+                socket.set_only_v6(true)?;
+                socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)).into())?;
+                socket.join_multicast_v6(&group, 0)?;
+                socket.set_multicast_hops_v6(255)?;
+            }
+        }
+        socket.set_nonblocking(true)?;
+        let socket = UdpSocket::from_std(socket.into())?;
+        let port = socket.local_addr()?.port();
+        Ok(Self {
+            socket,
+            group: SocketAddr::new(group, port),
+            zones,
+            local: LowerName::from(rr::Name::from_ascii("local.").unwrap()),
+        })
+    }
+
+    pub(crate) fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Announces the records, then answers queries and announces the
+    /// records of `changes` until `token` is cancelled and withdraws them.
+    /// Records of `changes` with a TTL of 0 are withdrawn.
+    pub(crate) async fn serve(
+        self: Arc<Self>,
+        mut changes: broadcast::Receiver<Vec<rr::Record>>,
+        token: CancellationToken,
+    ) {
+        self.announce(self.local_records().await);
+        let mut buf = vec![0; MAX_MESSAGE_SIZE];
+        let mut open = true;
+        loop {
+            tokio::select! {
+                received = self.socket.recv_from(&mut buf) => match received {
+                    Ok((len, src)) => self.answer(&buf[..len], src).await,
+                    Err(e) => debug!("error receiving mdns query: {}", e),
+                },
+                changed = changes.recv(), if open => match changed {
+                    Ok(mut records) => {
+                        records.retain(|r| self.local.zone_of(&r.name().into()));
+                        if !records.is_empty() {
+                            self.announce(vec![records]);
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => open = false,
+                },
+                _ = token.cancelled() => break,
+            }
+        }
+        let mut rrsets = self.local_records().await;
+        for record in rrsets.iter_mut().flatten() {
+            record.set_ttl(0);
+        }
+        self.multicast(&rrsets).await;
+    }
+
+    async fn answer(&self, bytes: &[u8], src: SocketAddr) {
+        let query = match Message::from_vec(bytes) {
+            Ok(query) => query,
+            Err(e) => {
+                debug!("invalid mdns message from {}: {}", src, e);
+                return;
+            }
+        };
+        if query.message_type() != MessageType::Query
+            || query.op_code() != OpCode::Query
+            || query.response_code() != ResponseCode::NoError
+        {
+            return;
+        }
+        // queries from another port come from plain resolvers, which expect
+        // an answer to themselves in the unicast format
+        let legacy = src.port() != self.group.port();
+        let mut unicast = legacy;
+        let mut answers: Vec<rr::Record> = Vec::new();
+        for question in query.queries() {
+            let class = u16::from(question.query_class());
+            let name = LowerName::from(question.name());
+            if ![DNSClass::IN, DNSClass::ANY].contains(&DNSClass::from(class & !TOP_BIT))
+                || !self.local.zone_of(&name)
+            {
+                continue;
+            }
+            let Some(authority) = self.zones.find(question.name()) else {
+                continue;
+            };
+            let Ok(found) = authority
+                .lookup(&name, question.query_type(), LookupOptions::default())
+                .await
+            else {
+                continue;
+            };
+            for record in found.iter() {
+                if !answers.contains(record) {
+                    answers.push(record.clone());
+                }
+            }
+            unicast |= class & TOP_BIT != 0;
+        }
+        self.zones.withdraw_unhealthy(&mut answers);
+        // known-answer suppression (RFC 6762 section 7.1)
+        answers.retain(|answer| {
+            !query.answers().iter().any(|known| {
+                known.name() == answer.name()
+                    && known.record_type() == answer.record_type()
+                    && known.data() == answer.data()
+                    && known.ttl() >= answer.ttl() / 2
+            })
+        });
+        if answers.is_empty() {
+            return;
+        }
+
+        let mut response = response();
+        if legacy {
+            for answer in &mut answers {
+                answer.set_ttl(answer.ttl().min(LEGACY_TTL));
+            }
+            response
+                .set_id(query.id())
+                .add_queries(query.queries().to_vec());
+        } else {
+            answers.iter_mut().for_each(cache_flush);
+        }
+        response.add_answers(answers);
+        self.send(&response, if unicast { src } else { self.group })
+            .await;
+    }
+
+    /// Multicasts `rrsets` in the background, repeated a second later (RFC
+    /// 6762 section 8.3).
+    fn announce(self: &Arc<Self>, rrsets: Vec<Vec<rr::Record>>) {
+        let responder = self.clone();
+        tokio::spawn(async move {
+            for announcement in 0..ANNOUNCEMENTS {
+                if announcement > 0 {
+                    tokio::time::sleep(ANNOUNCE_INTERVAL).await;
+                }
+                responder.multicast(&rrsets).await;
+            }
+        });
+    }
+
+    /// Multicasts each of `rrsets` in a response of its own.
+    async fn multicast(&self, rrsets: &[Vec<rr::Record>]) {
+        for rrset in rrsets {
+            let mut response = response();
+            response.add_answers(rrset.iter().cloned().map(|mut record| {
+                cache_flush(&mut record);
+                record
+            }));
+            self.send(&response, self.group).await;
+        }
+    }
+
+    /// The record sets of the `.local` zones, but their SOA and NS records.
+    async fn local_records(&self) -> Vec<Vec<rr::Record>> {
+        let mut rrsets = Vec::new();
+        for authority in self.zones.authorities() {
+            if !self.local.zone_of(authority.origin()) {
+                continue;
+            }
+            for rrset in authority.records().await.values() {
+                if matches!(rrset.record_type(), RecordType::SOA | RecordType::NS) {
+                    continue;
+                }
+                let mut records: Vec<_> = rrset.records_without_rrsigs().cloned().collect();
+                self.zones.withdraw_unhealthy(&mut records);
+                if !records.is_empty() {
+                    rrsets.push(records);
+                }
+            }
+        }
+        rrsets
+    }
+
+    async fn send(&self, message: &Message, target: SocketAddr) {
+        let bytes = match message.to_vec() {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                debug!("failed to encode mdns response: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = self.socket.send_to(&bytes, target).await {
+            debug!("failed to send mdns response to {}: {}", target, e);
+        }
+    }
+}
+
+fn response() -> Message {
+    let mut response = Message::new();
+    response
+        .set_message_type(MessageType::Response)
+        .set_op_code(OpCode::Query)
+        .set_authoritative(true);
+    response
+}
+
+/// Marks `record` as the whole set of its name and type, but PTR records,
+/// which other responders share.
+fn cache_flush(record: &mut rr::Record) {
+    if record.record_type() != RecordType::PTR {
+        let class = u16::from(record.dns_class()) | TOP_BIT;
+        record.set_dns_class(DNSClass::Unknown(class));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{RecordBuilder, RecordData};
+    use crate::tsig::Keyring;
+    use anyhow::Result;
+    use hickory_proto::op::Query;
+    use maplit::hashmap;
+    use std::str::FromStr;
+
+    async fn exchange(socket: &UdpSocket, query: &Message, target: SocketAddr) -> Option<Message> {
+        socket
+            .send_to(&query.to_vec().unwrap(), target)
+            .await
+            .unwrap();
+        let mut buf = vec![0; MAX_MESSAGE_SIZE];
+        let received = tokio::time::timeout(Duration::from_millis(300), socket.recv(&mut buf));
+        let len = received.await.ok()?.unwrap();
+        Some(Message::from_vec(&buf[..len]).unwrap())
+    }
+
+    #[tokio::test]
+    async fn answers_legacy_queries_for_local_names() -> Result<()> {
+        let record = |name: &str, address: Ipv4Addr| {
+            RecordBuilder::default()
+                .name(name.to_string())
+                .data(RecordData::A(address))
+                .ttl(Duration::from_secs(120))
+                .build()
+                .unwrap()
+        };
+        let zones = hashmap! {
+            "local".to_string() => vec![record("printer", Ipv4Addr::new(10, 0, 0, 9))].into(),
+            "et.internal".to_string() => vec![record("www", Ipv4Addr::new(10, 0, 0, 1))].into(),
+        };
+        let zones = Arc::new(ZoneSet::new(&zones, Keyring::new(&[])?)?);
+        let responder = Responder::bind(GROUP_V4.into(), 0, zones)?;
+        let target = SocketAddr::from((Ipv4Addr::LOCALHOST, responder.local_addr()?.port()));
+        let token = CancellationToken::new();
+        let (_changes, receiver) = broadcast::channel(1);
+        tokio::spawn(Arc::new(responder).serve(receiver, token.clone()));
+
+        let client = UdpSocket::bind("127.0.0.1:0").await?;
+        let query = |name: &str| {
+            let mut query = Message::new();
+            query.set_id(7).add_query(Query::query(
+                rr::Name::from_str(name).unwrap(),
+                RecordType::A,
+            ));
+            query
+        };
+        let response = exchange(&client, &query("printer.local."), target)
+            .await
+            .expect("no answer");
+        assert_eq!(response.id(), 7);
+        assert!(response.authoritative());
+        assert_eq!(response.queries().len(), 1);
+        let [answer] = response.answers() else {
+            panic!("expected one answer");
+        };
+        assert_eq!(
+            answer.data(),
+            Some(&rr::RData::A(Ipv4Addr::new(10, 0, 0, 9).into()))
+        );
+        assert_eq!(answer.dns_class(), DNSClass::IN);
+        assert_eq!(answer.ttl(), LEGACY_TTL);
+
+        // names outside `.local` and unknown names are left to others
+        assert!(exchange(&client, &query("www.et.internal."), target)
+            .await
+            .is_none());
+        assert!(exchange(&client, &query("scanner.local."), target)
+            .await
+            .is_none());
+
+        let mut known = query("printer.local.");
+        let mut answer = answer.clone();
+        answer.set_ttl(120);
+        known.add_answer(answer);
+        assert!(exchange(&client, &known, target).await.is_none());
+
+        token.cancel();
+        Ok(())
+    }
+}
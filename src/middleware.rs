@@ -0,0 +1,197 @@
+//! Hooks run on every request of a [`Server`](crate::Server), installed with
+//! [`Server::add_middleware`](crate::Server::add_middleware), and the
+//! resolver of the names it has no zone for, installed with
+//! [`Server::set_fallback`](crate::Server::set_fallback).
+
+use arc_swap::ArcSwap;
+use hickory_proto::op::{Message, Query};
+use hickory_proto::rr::Record;
+use hickory_proto::serialize::binary::{BinDecodable, BinEncodable, BinEncoder};
+use hickory_server::authority::{MessageRequest, MessageResponse, MessageResponseBuilder};
+use hickory_server::server::{Protocol, Request, ResponseHandler, ResponseInfo};
+use std::io;
+use std::iter;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// What a [`Middleware`] does with a request.
+#[derive(Debug)]
+pub enum Action {
+    /// Passes the request on to the next middleware, then to the server.
+    Continue,
+    /// Answers the request with this message, skipping the middlewares after
+    /// this one and the server. Its ID and question are those of the request.
+    Respond(Message),
+    /// Leaves the request unanswered.
+    Drop,
+}
+
+/// The client of a request.
+#[derive(Debug, Clone, Copy)]
+pub struct RequestContext {
+    src: SocketAddr,
+    protocol: Protocol,
+}
+
+impl RequestContext {
+    pub fn src(&self) -> SocketAddr {
+        self.src
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+}
+
+/// A hook on the requests of a server. Middlewares run in the order they
+/// were added, before the server's own ACLs and rate limits.
+#[async_trait::async_trait]
+pub trait Middleware: Send + Sync {
+    /// Inspects `request` and decides what happens to it. Changes to it are
+    /// seen by the next middlewares and answered by the server; a changed
+    /// request that is no longer a single question is refused with FORMERR.
+    async fn request(&self, _context: &RequestContext, _request: &mut Message) -> Action {
+        Action::Continue
+    }
+
+    /// Observes the response sent to a request this middleware let through
+    /// or answered, as sent to the client. Called in the reverse order of
+    /// [`Middleware::request`].
+    async fn response(&self, _context: &RequestContext, _response: &Message) {}
+}
+
+#[async_trait::async_trait]
+impl<M: Middleware + ?Sized> Middleware for Arc<M> {
+    async fn request(&self, context: &RequestContext, request: &mut Message) -> Action {
+        (**self).request(context, request).await
+    }
+
+    async fn response(&self, context: &RequestContext, response: &Message) {
+        (**self).response(context, response).await
+    }
+}
+
+/// Answers the queries of names no zone of the server holds, before they
+/// are forwarded.
+#[async_trait::async_trait]
+pub trait Fallback: Send + Sync {
+    /// The answer to `query`, whose response code, authoritative flag and
+    /// records are served, or `None` to leave it to the forwarder. Names of
+    /// the hosts files and of the blocklist never get here.
+    async fn resolve(&self, context: &RequestContext, query: &Query) -> Option<Message>;
+}
+
+#[async_trait::async_trait]
+impl<F: Fallback + ?Sized> Fallback for Arc<F> {
+    async fn resolve(&self, context: &RequestContext, query: &Query) -> Option<Message> {
+        (**self).resolve(context, query).await
+    }
+}
+
+/// The middlewares of a server, shared by the clones of its handler.
+#[derive(Default)]
+pub(crate) struct Middlewares(ArcSwap<Vec<Arc<dyn Middleware>>>);
+
+impl Middlewares {
+    pub(crate) fn push(&self, middleware: Arc<dyn Middleware>) {
+        self.0.rcu(|middlewares| {
+            let mut middlewares = Vec::clone(middlewares);
+            middlewares.push(middleware.clone());
+            middlewares
+        });
+    }
+
+    pub(crate) fn load(&self) -> Arc<Vec<Arc<dyn Middleware>>> {
+        self.0.load_full()
+    }
+}
+
+pub(crate) fn context(request: &Request) -> RequestContext {
+    RequestContext {
+        src: request.src(),
+        protocol: request.protocol(),
+    }
+}
+
+/// `request` as a message the middlewares can change.
+pub(crate) fn to_message(request: &Request) -> io::Result<Message> {
+    Ok(Message::from_vec(&request.to_bytes()?)?)
+}
+
+/// The request `message` changed by the middlewares into, from the client
+/// of `request`.
+pub(crate) fn to_request(request: &Request, message: &Message) -> io::Result<Request> {
+    let message = MessageRequest::from_bytes(&message.to_vec()?)?;
+    Ok(Request::new(message, request.src(), request.protocol()))
+}
+
+/// Calls the response hook of its middlewares on every response sent.
+#[derive(Clone)]
+pub(crate) struct ObservingResponseHandler<R> {
+    inner: R,
+    context: RequestContext,
+    middlewares: Vec<Arc<dyn Middleware>>,
+}
+
+impl<R> ObservingResponseHandler<R> {
+    pub(crate) fn new(
+        inner: R,
+        context: RequestContext,
+        middlewares: Vec<Arc<dyn Middleware>>,
+    ) -> Self {
+        Self {
+            inner,
+            context,
+            middlewares,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<R: ResponseHandler> ResponseHandler for ObservingResponseHandler<R> {
+    async fn send_response<'a>(
+        &mut self,
+        response: MessageResponse<
+            '_,
+            'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+        >,
+    ) -> io::Result<ResponseInfo> {
+        // the records of a response can only be read by encoding it, so it
+        // is decoded for the hooks and built again from its parts
+        let mut bytes = Vec::with_capacity(512);
+        response.destructive_emit(&mut BinEncoder::new(&mut bytes))?;
+        let observed = Message::from_vec(&bytes)?;
+        for middleware in self.middlewares.iter().rev() {
+            middleware.response(&self.context, &observed).await;
+        }
+        resend(&mut self.inner, &bytes, None).await
+    }
+}
+
+/// Sends the response encoded in `bytes` with `inner`, built again from its
+/// parts, for the handlers that can only change a response once encoded.
+/// `last` is appended after its other additional records. The EDNS option
+/// is sent as a plain record, so `inner` leaves the response as it is.
+pub(crate) async fn resend<R: ResponseHandler>(
+    inner: &mut R,
+    bytes: &[u8],
+    last: Option<Record>,
+) -> io::Result<ResponseInfo> {
+    let message = MessageRequest::from_bytes(bytes)?;
+    let mut additionals = message.additionals().to_vec();
+    additionals.extend(message.edns().map(Record::from));
+    additionals.extend(message.sig0().iter().cloned());
+    additionals.extend(last);
+    let response = MessageResponseBuilder::from_message_request(&message).build(
+        *message.header(),
+        message.answers().iter(),
+        message.name_servers().iter(),
+        iter::empty(),
+        additionals.iter(),
+    );
+    inner.send_response(response).await
+}
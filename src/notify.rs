@@ -0,0 +1,82 @@
+use anyhow::{anyhow, Result};
+use hickory_proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use hickory_proto::rr;
+use hickory_proto::rr::dnssec::tsig::TSigner;
+use hickory_proto::rr::{DNSClass, RecordType};
+use hickory_proto::udp::UdpClientStream;
+use hickory_proto::xfer::{DnsExchange, DnsHandle, DnsRequest, DnsRequestOptions, FirstAnswer};
+use hickory_proto::TokioTime;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tracing::debug;
+
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(2);
+const NOTIFY_ATTEMPTS: usize = 3;
+
+/// Sends a NOTIFY for `zone` to the secondary at `target` (RFC 1996),
+/// retrying until it is answered. With a `signer` the NOTIFY is signed with
+/// TSIG and so must be the answer.
+pub(crate) async fn send(
+    zone: rr::Name,
+    soa: Option<rr::Record>,
+    target: SocketAddr,
+    signer: Option<TSigner>,
+) -> Result<()> {
+    let signer = signer.map(Arc::new);
+    let mut query = Query::query(zone.clone(), RecordType::SOA);
+    query.set_query_class(DNSClass::IN);
+    let mut message = Message::new();
+    message
+        .add_query(query)
+        .set_message_type(MessageType::Query)
+        .set_op_code(OpCode::Notify)
+        .set_authoritative(true);
+    if let Some(soa) = soa {
+        message.add_answer(soa);
+    }
+
+    let mut last_error = anyhow!("no attempts");
+    for attempt in 1..=NOTIFY_ATTEMPTS {
+        match exchange(target, message.clone(), signer.clone()).await {
+            Ok(ResponseCode::NoError) => return Ok(()),
+            Ok(code) => {
+                return Err(anyhow!(
+                    "{} answered {} to NOTIFY of {}",
+                    target,
+                    code,
+                    zone
+                ))
+            }
+            Err(e) => {
+                debug!(
+                    "NOTIFY of {} to {} failed, attempt {}: {}",
+                    zone, target, attempt, e
+                );
+                last_error = e;
+            }
+        }
+    }
+    Err(last_error)
+}
+
+async fn exchange(
+    target: SocketAddr,
+    message: Message,
+    signer: Option<Arc<TSigner>>,
+) -> Result<ResponseCode> {
+    let stream = UdpClientStream::<UdpSocket, TSigner>::with_timeout_and_signer(
+        target,
+        NOTIFY_TIMEOUT,
+        signer,
+    );
+    let (exchange, background) = DnsExchange::connect::<_, _, TokioTime>(stream).await?;
+    let background = tokio::spawn(background);
+    let response = exchange
+        .send(DnsRequest::new(message, DnsRequestOptions::default()))
+        .first_answer()
+        .await;
+    background.abort();
+    Ok(response?.response_code())
+}
@@ -0,0 +1,33 @@
+use hickory_proto::op::Edns;
+use hickory_proto::rr::rdata::opt::{EdnsCode, EdnsOption};
+
+/// Whether `edns` asks for the identifier of the server (RFC 5001).
+pub(crate) fn requested(edns: &Edns) -> bool {
+    edns.option(EdnsCode::NSID).is_some()
+}
+
+/// The NSID option carrying the identifier `id`.
+pub(crate) fn option(id: &str) -> EdnsOption {
+    EdnsOption::Unknown(EdnsCode::NSID.into(), id.as_bytes().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::op::Message;
+
+    #[test]
+    fn answers_requested_nsid() -> anyhow::Result<()> {
+        let mut edns = Edns::new();
+        assert!(!requested(&edns));
+        // as sent by `dig +nsid`
+        edns.options_mut()
+            .insert(EdnsOption::Unknown(3, Vec::new()));
+        let mut message = Message::new();
+        message.set_edns(edns);
+        let message = Message::from_vec(&message.to_vec()?)?;
+        assert!(requested(message.extensions().as_ref().unwrap()));
+        assert_eq!(option("ns1"), EdnsOption::Unknown(3, b"ns1".to_vec()));
+        Ok(())
+    }
+}
@@ -0,0 +1,269 @@
+use crate::config::PostgresConfig;
+use crate::zones::ZoneSet;
+#[cfg(not(feature = "postgres"))]
+use anyhow::bail;
+use anyhow::Result;
+use hickory_proto::rr;
+use std::collections::{BTreeMap, HashSet};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+/// TTL of the records without one, the default of PowerDNS.
+const DEFAULT_TTL: u32 = 3600;
+
+/// The records of the served domains, the domains without records included.
+/// Disabled records and the empty non-terminals PowerDNS keeps for DNSSEC
+/// are left out.
+#[cfg_attr(not(feature = "postgres"), allow(dead_code))]
+const RECORDS_QUERY: &str = "
+    SELECT d.name, r.name, r.type, r.content, r.ttl
+    FROM domains d
+    LEFT JOIN records r
+        ON r.domain_id = d.id AND r.disabled IS NOT TRUE AND r.type <> ''
+    WHERE upper(d.type) IN ('NATIVE', 'MASTER')
+";
+
+/// Serves the domains of a PowerDNS database as zones of their own, read
+/// again every `refresh`.
+pub(crate) struct Postgres {
+    refresh: Duration,
+    client: Client,
+}
+
+impl Postgres {
+    pub(crate) fn new(config: &PostgresConfig) -> Result<Self> {
+        Ok(Self {
+            refresh: config.refresh(),
+            client: Client::new(config)?,
+        })
+    }
+
+    /// Keeps the domains of the database served until `token` is cancelled.
+    pub(crate) async fn start(
+        self: Arc<Self>,
+        zones: Arc<ZoneSet>,
+        token: CancellationToken,
+    ) -> Result<()> {
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = self.follow(&zones) => {}
+                _ = token.cancelled() => {}
+            }
+        });
+        Ok(())
+    }
+
+    async fn follow(&self, zones: &ZoneSet) {
+        let mut served = HashSet::new();
+        loop {
+            match self.client.rows().await {
+                Ok(rows) => served = self.publish(&rows, zones, served).await,
+                Err(e) => warn!("failed to read the postgres zones: {:#}", e),
+            }
+            tokio::time::sleep(self.refresh).await;
+        }
+    }
+
+    /// Serves the domains of `rows`, withdraws those of `served` no longer
+    /// in the database, and returns the domains now served.
+    async fn publish(
+        &self,
+        rows: &[Row],
+        zones: &ZoneSet,
+        served: HashSet<rr::Name>,
+    ) -> HashSet<rr::Name> {
+        let configured: HashSet<rr::Name> = zones
+            .zones()
+            .await
+            .keys()
+            .filter_map(|zone| rr::Name::from_str(zone).ok())
+            .collect();
+        let mut serving = HashSet::new();
+        for (zone, records) in domains(rows) {
+            if configured.contains(&zone) {
+                debug!("skipped postgres domain {}, a configured zone", zone);
+                continue;
+            }
+            match zones.serve_records(&zone, records).await {
+                Ok(()) => {
+                    serving.insert(zone);
+                }
+                Err(e) => warn!("failed to serve postgres domain {}: {:#}", zone, e),
+            }
+        }
+        for zone in served.difference(&serving) {
+            debug!("withdrawing postgres domain {}", zone);
+            zones.withdraw_records(zone).await;
+        }
+        serving
+    }
+}
+
+/// A record of a domain, all fields `None` for a domain without records.
+#[derive(Debug, Default)]
+struct Row {
+    domain: String,
+    name: Option<String>,
+    record_type: Option<String>,
+    content: Option<String>,
+    ttl: Option<i32>,
+}
+
+/// The records of every domain of `rows`. PowerDNS keeps names without the
+/// trailing dot, in the content of records too, so every name is absolute.
+/// Records that don't parse or lie outside their domain are left out.
+fn domains(rows: &[Row]) -> BTreeMap<rr::Name, Vec<rr::Record>> {
+    let mut domains: BTreeMap<rr::Name, Vec<rr::Record>> = BTreeMap::new();
+    for row in rows {
+        let domain = match rr::Name::from_ascii(&row.domain) {
+            Ok(domain) => crate::zonefile::fqdn(&domain),
+            Err(e) => {
+                warn!("invalid postgres domain {:?}: {}", row.domain, e);
+                continue;
+            }
+        };
+        let records = domains.entry(domain.clone()).or_default();
+        let (Some(name), Some(record_type), Some(content)) =
+            (&row.name, &row.record_type, &row.content)
+        else {
+            continue;
+        };
+        let name = format!("{}.", name.trim_end_matches('.'));
+        let ttl = row
+            .ttl
+            .and_then(|ttl| u32::try_from(ttl).ok())
+            .unwrap_or(DEFAULT_TTL);
+        match crate::zonefile::parse_record(&name, ttl, record_type, content, &rr::Name::root()) {
+            Ok(record) if domain.zone_of(record.name()) => records.push(record),
+            Ok(_) => {}
+            Err(e) => warn!(
+                "skipped {} record {} of postgres domain {}: {:#}",
+                record_type, name, domain, e
+            ),
+        }
+    }
+    domains
+}
+
+/// Client of the database, connected again after the connection is lost.
+#[cfg(feature = "postgres")]
+struct Client {
+    config: tokio_postgres::Config,
+    client: tokio::sync::Mutex<Option<tokio_postgres::Client>>,
+}
+
+#[cfg(feature = "postgres")]
+impl Client {
+    fn new(config: &PostgresConfig) -> Result<Self> {
+        use anyhow::Context;
+
+        Ok(Self {
+            config: config
+                .connection()
+                .parse()
+                .context("invalid postgres connection")?,
+            client: tokio::sync::Mutex::new(None),
+        })
+    }
+
+    async fn rows(&self) -> Result<Vec<Row>> {
+        let mut client = self.client.lock().await;
+        if client.as_ref().is_none_or(|client| client.is_closed()) {
+            let (connected, connection) = self.config.connect(tokio_postgres::NoTls).await?;
+            tokio::spawn(async move {
+                if let Err(e) = connection.await {
+                    warn!("lost the postgres connection: {}", e);
+                }
+            });
+            *client = Some(connected);
+        }
+        let rows = client.as_ref().unwrap().query(RECORDS_QUERY, &[]).await?;
+        rows.iter()
+            .map(|row| {
+                Ok(Row {
+                    domain: row.try_get(0)?,
+                    name: row.try_get(1)?,
+                    record_type: row.try_get(2)?,
+                    content: row.try_get(3)?,
+                    ttl: row.try_get(4)?,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(not(feature = "postgres"))]
+enum Client {}
+
+#[cfg(not(feature = "postgres"))]
+impl Client {
+    fn new(_config: &PostgresConfig) -> Result<Self> {
+        bail!("the PostgreSQL backend requires the `postgres` feature")
+    }
+
+    async fn rows(&self) -> Result<Vec<Row>> {
+        match *self {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_powerdns_records() -> Result<()> {
+        let row = |name: &str, record_type: &str, content: &str, ttl: Option<i32>| Row {
+            domain: "example.com".to_string(),
+            name: Some(name.to_string()),
+            record_type: Some(record_type.to_string()),
+            content: Some(content.to_string()),
+            ttl,
+        };
+        let rows = [
+            row(
+                "example.com",
+                "SOA",
+                "ns1.example.com hostmaster.example.com 2024010101 10800 3600 604800 3600",
+                Some(3600),
+            ),
+            row("example.com", "MX", "10 mail.example.com", None),
+            row("www.example.com", "CNAME", "web.example.net", Some(60)),
+            row("txt.example.com", "TXT", "\"v=spf1 -all\"", Some(60)),
+            row("other.example.net", "A", "192.0.2.1", Some(60)),
+            row("bad.example.com", "A", "not an address", Some(60)),
+            Row {
+                domain: "empty.example".to_string(),
+                ..Default::default()
+            },
+        ];
+        let domains = domains(&rows);
+        assert_eq!(domains.len(), 2);
+        assert!(domains[&rr::Name::from_str("empty.example.")?].is_empty());
+        let mut records: Vec<String> = domains[&rr::Name::from_str("example.com.")?]
+            .iter()
+            .map(|r| {
+                format!(
+                    "{} {} {} {}",
+                    r.name(),
+                    r.ttl(),
+                    r.record_type(),
+                    r.data().unwrap()
+                )
+            })
+            .collect();
+        records.sort();
+        assert_eq!(
+            records,
+            [
+                "example.com. 3600 MX 10 mail.example.com.",
+                "example.com. 3600 SOA ns1.example.com. hostmaster.example.com. 2024010101 10800 3600 604800 3600",
+                "txt.example.com. 60 TXT v=spf1 -all",
+                "www.example.com. 60 CNAME web.example.net.",
+            ]
+        );
+        Ok(())
+    }
+}
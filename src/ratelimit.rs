@@ -0,0 +1,322 @@
+use crate::acl::Acl;
+use crate::config::{RateLimitAction, RateLimitConfig, ResponseRateLimitConfig};
+use anyhow::{bail, Result};
+use hickory_proto::rr::{LowerName, RecordType};
+use ipnet::IpNet;
+use lru::LruCache;
+use std::net::IpAddr;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Token buckets limiting the queries of each client network.
+pub(crate) struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    ipv4_prefix: u8,
+    ipv6_prefix: u8,
+    action: RateLimitAction,
+    buckets: Mutex<LruCache<IpNet, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(config: &RateLimitConfig) -> Result<Self> {
+        if config.queries_per_second() == 0 {
+            bail!("rate limit must allow at least one query per second");
+        }
+        if config.ipv4_prefix() > 32 || config.ipv6_prefix() > 128 {
+            bail!(
+                "invalid rate limit prefixes /{} and /{}",
+                config.ipv4_prefix(),
+                config.ipv6_prefix()
+            );
+        }
+        let Some(max_clients) = NonZeroUsize::new(config.max_clients()) else {
+            bail!("rate limit must track at least one client");
+        };
+        Ok(Self {
+            rate: config.queries_per_second().into(),
+            burst: config.burst().max(1).into(),
+            ipv4_prefix: config.ipv4_prefix(),
+            ipv6_prefix: config.ipv6_prefix(),
+            action: config.action(),
+            buckets: Mutex::new(LruCache::new(max_clients)),
+        })
+    }
+
+    pub(crate) fn action(&self) -> RateLimitAction {
+        self.action
+    }
+
+    /// Takes a token from the bucket of the network of `ip`, `false` when it
+    /// is empty.
+    pub(crate) fn allow(&self, ip: IpAddr) -> bool {
+        self.allow_at(ip, Instant::now())
+    }
+
+    fn allow_at(&self, ip: IpAddr, now: Instant) -> bool {
+        let network = self.network(ip);
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.get_or_insert_mut(network, || Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    fn network(&self, ip: IpAddr) -> IpNet {
+        network(ip, self.ipv4_prefix, self.ipv6_prefix)
+    }
+}
+
+/// What a response says, identical responses to a client network share one
+/// account.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum ResponseKind {
+    /// Records of a name and type.
+    Answer(LowerName, RecordType),
+    /// No records, or a referral, counted per zone so that random names
+    /// share an account.
+    NoData(LowerName),
+    /// NXDOMAIN, counted per zone.
+    NxDomain(LowerName),
+    /// Any other error such as REFUSED or SERVFAIL.
+    Error,
+}
+
+/// BIND style response rate limiting: each client network and kind of
+/// response has an account credited with its rate every second, up to one
+/// second of responses, and debited for each response, down to a debt of
+/// `window` seconds. Responses are limited while the account is in debt,
+/// every `slip`-th of them truncated and the others dropped.
+pub(crate) struct ResponseRateLimiter {
+    responses: f64,
+    nxdomains: f64,
+    errors: f64,
+    window: f64,
+    slip: u32,
+    ipv4_prefix: u8,
+    ipv6_prefix: u8,
+    exempt: Acl,
+    accounts: Mutex<LruCache<(IpNet, ResponseKind), Account>>,
+}
+
+struct Account {
+    balance: f64,
+    updated: Instant,
+    limited: u32,
+}
+
+impl ResponseRateLimiter {
+    pub(crate) fn new(config: &ResponseRateLimitConfig) -> Result<Self> {
+        if config.ipv4_prefix() > 32 || config.ipv6_prefix() > 128 {
+            bail!(
+                "invalid response rate limit prefixes /{} and /{}",
+                config.ipv4_prefix(),
+                config.ipv6_prefix()
+            );
+        }
+        let Some(max_entries) = NonZeroUsize::new(config.max_entries()) else {
+            bail!("response rate limit must track at least one response");
+        };
+        Ok(Self {
+            responses: config.responses_per_second().into(),
+            nxdomains: config.nxdomains_per_second().into(),
+            errors: config.errors_per_second().into(),
+            window: config.window().as_secs_f64().max(1.0),
+            slip: config.slip(),
+            ipv4_prefix: config.ipv4_prefix(),
+            ipv6_prefix: config.ipv6_prefix(),
+            exempt: Acl::parse(config.exempt_clients())?,
+            accounts: Mutex::new(LruCache::new(max_entries)),
+        })
+    }
+
+    /// Debits the account of `kind` responses to the network of `ip`, and
+    /// tells what to do with the response when it is over the limit.
+    pub(crate) fn check(&self, ip: IpAddr, kind: ResponseKind) -> Option<RateLimitAction> {
+        self.check_at(ip, kind, Instant::now())
+    }
+
+    fn check_at(&self, ip: IpAddr, kind: ResponseKind, now: Instant) -> Option<RateLimitAction> {
+        let rate = match kind {
+            ResponseKind::Answer(..) | ResponseKind::NoData(_) => self.responses,
+            ResponseKind::NxDomain(_) => self.nxdomains,
+            ResponseKind::Error => self.errors,
+        };
+        // a rate of zero leaves the kind unlimited
+        if rate == 0.0 || self.exempt.allows(ip, None) {
+            return None;
+        }
+        let key = (network(ip, self.ipv4_prefix, self.ipv6_prefix), kind);
+        let mut accounts = self.accounts.lock().unwrap();
+        let account = accounts.get_or_insert_mut(key, || Account {
+            balance: rate,
+            updated: now,
+            limited: 0,
+        });
+        let elapsed = now.saturating_duration_since(account.updated).as_secs_f64();
+        account.balance = (account.balance + elapsed * rate).min(rate) - 1.0;
+        account.balance = account.balance.max(-self.window * rate);
+        account.updated = now;
+        if account.balance >= 0.0 {
+            return None;
+        }
+        account.limited = account.limited.wrapping_add(1);
+        if self.slip > 0 && account.limited.is_multiple_of(self.slip) {
+            Some(RateLimitAction::Truncate)
+        } else {
+            Some(RateLimitAction::Drop)
+        }
+    }
+}
+
+fn network(ip: IpAddr, ipv4_prefix: u8, ipv6_prefix: u8) -> IpNet {
+    let ip = ip.to_canonical();
+    let prefix = match ip {
+        IpAddr::V4(_) => ipv4_prefix,
+        IpAddr::V6(_) => ipv6_prefix,
+    };
+    // prefixes are checked by the limiters
+    IpNet::new(ip, prefix).unwrap().trunc()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{RateLimitConfigBuilder, ResponseRateLimitConfigBuilder};
+    use std::str::FromStr;
+    use std::time::Duration;
+
+    #[test]
+    fn limits_client_networks() -> Result<()> {
+        let limiter = RateLimiter::new(
+            &RateLimitConfigBuilder::default()
+                .queries_per_second(2)
+                .burst(3)
+                .ipv4_prefix(24)
+                .build()?,
+        )?;
+        let now = Instant::now();
+        let client = "10.0.0.1".parse()?;
+        let neighbour = "10.0.0.2".parse()?;
+        let stranger = "10.0.1.1".parse()?;
+        assert!(limiter.allow_at(client, now));
+        assert!(limiter.allow_at(neighbour, now));
+        assert!(limiter.allow_at(client, now));
+        // the /24 shares one bucket
+        assert!(!limiter.allow_at(neighbour, now));
+        assert!(limiter.allow_at(stranger, now));
+        // refilled at two tokens per second
+        assert!(limiter.allow_at(client, now + Duration::from_millis(500)));
+        assert!(!limiter.allow_at(client, now + Duration::from_millis(500)));
+        // up to the burst
+        let later = now + Duration::from_secs(60);
+        assert!((0..3).all(|_| limiter.allow_at(client, later)));
+        assert!(!limiter.allow_at(client, later));
+
+        let v6 = "2001:db8::1".parse()?;
+        let v6_neighbour = "2001:db8::ffff:1".parse()?;
+        assert!((0..3).all(|_| limiter.allow_at(v6, now)));
+        assert!(!limiter.allow_at(v6_neighbour, now));
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_invalid_prefixes() -> Result<()> {
+        let config = RateLimitConfigBuilder::default()
+            .queries_per_second(1)
+            .ipv4_prefix(33)
+            .build()?;
+        assert!(RateLimiter::new(&config).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn limits_identical_responses() -> Result<()> {
+        let limiter = ResponseRateLimiter::new(
+            &ResponseRateLimitConfigBuilder::default()
+                .responses_per_second(2)
+                .window(Duration::from_secs(5))
+                .exempt_clients(vec!["192.0.2.0/24".to_string()])
+                .build()?,
+        )?;
+        let now = Instant::now();
+        let client = "10.0.0.1".parse()?;
+        let www = || {
+            ResponseKind::Answer(
+                LowerName::from_str("www.et.internal.").unwrap(),
+                RecordType::A,
+            )
+        };
+        let mail = ResponseKind::Answer(LowerName::from_str("mail.et.internal.")?, RecordType::A);
+        assert_eq!(limiter.check_at(client, www(), now), None);
+        assert_eq!(limiter.check_at(client, www(), now), None);
+        // every other limited response slips through truncated
+        assert_eq!(
+            limiter.check_at(client, www(), now),
+            Some(RateLimitAction::Drop)
+        );
+        assert_eq!(
+            limiter.check_at(client, www(), now),
+            Some(RateLimitAction::Truncate)
+        );
+        // other responses and networks have their own accounts
+        assert_eq!(limiter.check_at(client, mail, now), None);
+        assert_eq!(limiter.check_at("10.0.1.1".parse()?, www(), now), None);
+        assert_eq!(
+            limiter.check_at("10.0.0.2".parse()?, www(), now),
+            Some(RateLimitAction::Drop)
+        );
+        // exempt clients are never limited
+        let exempt = "192.0.2.1".parse()?;
+        assert!((0..10).all(|_| limiter.check_at(exempt, www(), now).is_none()));
+
+        // the debt is capped at the window, paid back at the rate
+        for _ in 0..100 {
+            limiter.check_at(client, www(), now);
+        }
+        let later = now + Duration::from_secs(5);
+        assert!(limiter.check_at(client, www(), later).is_some());
+        let later = now + Duration::from_secs(12);
+        assert_eq!(limiter.check_at(client, www(), later), None);
+        Ok(())
+    }
+
+    #[test]
+    fn limits_denials_per_zone() -> Result<()> {
+        let limiter = ResponseRateLimiter::new(
+            &ResponseRateLimitConfigBuilder::default()
+                .responses_per_second(5)
+                .nxdomains_per_second(1)
+                .errors_per_second(0)
+                .slip(0)
+                .build()?,
+        )?;
+        let now = Instant::now();
+        let client = "2001:db8::1".parse()?;
+        let zone = LowerName::from_str("et.internal.")?;
+        assert_eq!(
+            limiter.check_at(client, ResponseKind::NxDomain(zone.clone()), now),
+            None
+        );
+        assert_eq!(
+            limiter.check_at("2001:db8::2".parse()?, ResponseKind::NxDomain(zone), now),
+            Some(RateLimitAction::Drop)
+        );
+        assert!((0..10).all(|_| limiter.check_at(client, ResponseKind::Error, now).is_none()));
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+//! NXDOMAIN redirection: the names that don't exist answered with fixed
+//! addresses, as captive portals and search appliances do.
+
+use crate::config::NxdomainRedirectConfig;
+use anyhow::{Context, Result};
+use hickory_proto::op::Query;
+use hickory_proto::rr::rdata::{A, AAAA};
+use hickory_proto::rr::{LowerName, RData, Record, RecordType};
+use std::net::IpAddr;
+use std::str::FromStr;
+
+pub(crate) struct Redirect {
+    /// Domains redirected, every name when empty.
+    suffixes: Vec<LowerName>,
+    exclude: Vec<LowerName>,
+    addresses: Vec<IpAddr>,
+    ttl: u32,
+}
+
+impl Redirect {
+    pub(crate) fn new(config: &NxdomainRedirectConfig) -> Result<Self> {
+        let names = |names: &[String]| {
+            names
+                .iter()
+                .map(|name| {
+                    LowerName::from_str(name).with_context(|| format!("invalid domain {}", name))
+                })
+                .collect::<Result<Vec<_>>>()
+        };
+        Ok(Self {
+            suffixes: names(config.suffixes())?,
+            exclude: names(config.exclude())?,
+            addresses: config.addresses().clone(),
+            ttl: config.ttl().as_secs().try_into().unwrap_or(u32::MAX),
+        })
+    }
+
+    /// The answer replacing NXDOMAIN for `query`, when its name is
+    /// redirected and there are addresses of its type.
+    pub(crate) fn answer(&self, query: &Query) -> Option<Vec<Record>> {
+        let name = LowerName::from(query.name());
+        let matches = |domain: &LowerName| domain.zone_of(&name);
+        if !(self.suffixes.is_empty() || self.suffixes.iter().any(matches))
+            || self.exclude.iter().any(matches)
+        {
+            return None;
+        }
+        let answers: Vec<Record> = self
+            .addresses
+            .iter()
+            .filter_map(|address| match (address, query.query_type()) {
+                (IpAddr::V4(ip), RecordType::A) => Some(RData::A(A(*ip))),
+                (IpAddr::V6(ip), RecordType::AAAA) => Some(RData::AAAA(AAAA(*ip))),
+                _ => None,
+            })
+            .map(|rdata| Record::from_rdata(query.name().clone(), self.ttl, rdata))
+            .collect();
+        (!answers.is_empty()).then_some(answers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NxdomainRedirectConfigBuilder;
+    use hickory_proto::rr::Name;
+
+    #[test]
+    fn redirects_matching_names() -> Result<()> {
+        let redirect = Redirect::new(
+            &NxdomainRedirectConfigBuilder::default()
+                .suffixes(vec!["corp.example".to_string()])
+                .exclude(vec!["mail.corp.example".to_string()])
+                .addresses(vec!["10.0.0.99".parse()?])
+                .build()?,
+        )?;
+        let answer = |name: &str, record_type| -> Result<_> {
+            Ok(redirect.answer(&Query::query(Name::from_str(name)?, record_type)))
+        };
+        let answers = answer("typo.corp.example.", RecordType::A)?.unwrap();
+        assert_eq!(answers.len(), 1);
+        assert_eq!(answers[0].ttl(), 60);
+        assert_eq!(answers[0].data(), Some(&RData::A(A::new(10, 0, 0, 99))));
+        // no IPv6 address, nor other types
+        assert!(answer("typo.corp.example.", RecordType::AAAA)?.is_none());
+        assert!(answer("typo.corp.example.", RecordType::MX)?.is_none());
+        assert!(answer("typo.example.", RecordType::A)?.is_none());
+        assert!(answer("old.mail.corp.example.", RecordType::A)?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn redirects_nxdomain_answers() -> Result<()> {
+        use crate::testing::{assert_answers, TestServer};
+        use hickory_proto::op::ResponseCode;
+
+        let text = r#"
+[general]
+
+[nxdomain_redirect]
+exclude = ["mail.example"]
+addresses = ["10.0.0.99", "fd00::99"]
+
+[[zones."example"]]
+type = "A"
+name = "www"
+value = "10.0.0.1"
+
+[zones."corp.example"]
+nxdomain_redirect = { addresses = ["10.0.1.99"], ttl = "5s" }
+records = [{ type = "A", name = "www", value = "10.0.1.1" }]
+"#;
+        let server = TestServer::start(toml::from_str(text)?).await?;
+        let response = server.query("www.example", RecordType::A).await?;
+        assert_answers(&response, &["10.0.0.1"]);
+        let response = server.query("typo.example", RecordType::A).await?;
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert_answers(&response, &["10.0.0.99"]);
+        assert!(response.name_servers().is_empty());
+        let response = server.query("typo.example", RecordType::AAAA).await?;
+        assert_answers(&response, &["fd00::99"]);
+
+        // the zone's own redirect
+        let response = server.query("typo.corp.example", RecordType::A).await?;
+        assert_answers(&response, &["10.0.1.99"]);
+        assert_eq!(response.answers()[0].ttl(), 5);
+        let response = server.query("typo.corp.example", RecordType::AAAA).await?;
+        assert_eq!(response.response_code(), ResponseCode::NXDomain);
+
+        for (name, record_type) in [
+            ("old.mail.example", RecordType::A),
+            ("typo.example", RecordType::MX),
+        ] {
+            let response = server.query(name, record_type).await?;
+            assert_eq!(response.response_code(), ResponseCode::NXDomain);
+        }
+        server.shutdown().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,271 @@
+use crate::config::RedisConfig;
+use crate::zones::ZoneSet;
+use anyhow::{bail, Context, Result};
+use futures_util::stream::BoxStream;
+use futures_util::StreamExt;
+use hickory_proto::rr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+/// Delay before a lost subscription is made again.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+/// Invalidation message naming every zone.
+const ALL_ZONES: &str = "*";
+
+/// Serves the zones with `backend = "redis"` from their hashes, fetched
+/// again whenever an invalidation message names them.
+pub(crate) struct Redis {
+    prefix: String,
+    channel: String,
+    client: Client,
+}
+
+impl Redis {
+    pub(crate) fn new(config: &RedisConfig) -> Result<Self> {
+        Ok(Self {
+            prefix: config.prefix().to_string(),
+            channel: config.channel().to_string(),
+            client: Client::new(config)?,
+        })
+    }
+
+    /// Keeps the `redis` zones of `zones` fetched until `token` is cancelled.
+    pub(crate) async fn start(
+        self: Arc<Self>,
+        zones: Arc<ZoneSet>,
+        token: CancellationToken,
+    ) -> Result<()> {
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    result = self.follow(&zones) => {
+                        if let Err(e) = result {
+                            warn!("failed to follow redis channel {}: {:#}", self.channel, e);
+                        }
+                    }
+                    _ = token.cancelled() => break,
+                }
+                tokio::select! {
+                    _ = tokio::time::sleep(RETRY_INTERVAL) => {}
+                    _ = token.cancelled() => break,
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Fetches every zone, then those named by the invalidation messages.
+    /// Zones are fetched after subscribing, so no change goes unnoticed.
+    async fn follow(&self, zones: &ZoneSet) -> Result<()> {
+        let mut messages = self.client.subscribe(&self.channel).await?;
+        self.fetch_all(zones).await;
+        loop {
+            tokio::select! {
+                message = messages.next() => {
+                    let Some(message) = message else {
+                        bail!("subscription closed");
+                    };
+                    let message = message.trim();
+                    if message == ALL_ZONES {
+                        self.fetch_all(zones).await;
+                        continue;
+                    }
+                    let zone = match rr::Name::from_str(message) {
+                        Ok(zone) => zone,
+                        Err(e) => {
+                            warn!("invalid zone {:?} on redis channel: {}", message, e);
+                            continue;
+                        }
+                    };
+                    if zones.redis_zones().await.contains(&zone) {
+                        self.fetch(zones, &zone).await;
+                    }
+                }
+                _ = zones.redis_zones_changed() => self.fetch_all(zones).await,
+            }
+        }
+    }
+
+    async fn fetch_all(&self, zones: &ZoneSet) {
+        for zone in zones.redis_zones().await {
+            self.fetch(zones, &zone).await;
+        }
+    }
+
+    /// Serves the records of the hash of `zone`, its records from the config
+    /// while the hash doesn't exist. A zone that fails to be fetched keeps
+    /// its records.
+    async fn fetch(&self, zones: &ZoneSet, zone: &rr::Name) {
+        let result = async {
+            let fields = self.client.hash(&self.key(zone)).await?;
+            let records = match fields.is_empty() {
+                true => None,
+                false => Some(records(&fields, zone)?),
+            };
+            debug!(
+                "fetched {} records of zone {} from redis",
+                records.as_ref().map_or(0, Vec::len),
+                zone
+            );
+            zones.serve_fetched(zone, records).await
+        };
+        if let Err(e) = result.await {
+            warn!("failed to fetch zone {} from redis: {:#}", zone, e);
+        }
+    }
+
+    /// The key of the hash of `zone`.
+    fn key(&self, zone: &rr::Name) -> String {
+        let zone = zone.to_lowercase().to_string();
+        format!("{}{}", self.prefix, zone.trim_end_matches('.'))
+    }
+}
+
+/// The records of the hash fields `fields` of `zone`, a `<name> <type>`
+/// field holding one `<ttl> <data>` line per record. Records outside the
+/// zone are left out.
+fn records(fields: &[(String, String)], zone: &rr::Name) -> Result<Vec<rr::Record>> {
+    let mut records = Vec::new();
+    for (field, value) in fields {
+        let Some((name, record_type)) = field.split_once(' ') else {
+            bail!("invalid field {:?}, expected \"<name> <type>\"", field);
+        };
+        for line in value.lines().filter(|line| !line.trim().is_empty()) {
+            let Some((ttl, data)) = line.trim().split_once(' ') else {
+                bail!(
+                    "invalid record {:?} of {:?}, expected \"<ttl> <data>\"",
+                    line,
+                    field
+                );
+            };
+            let record = ttl
+                .parse()
+                .map_err(anyhow::Error::from)
+                .and_then(|ttl| {
+                    crate::zonefile::parse_record(name.trim(), ttl, record_type.trim(), data, zone)
+                })
+                .with_context(|| format!("invalid record {:?} of {:?}", line, field))?;
+            if !zone.zone_of(record.name()) {
+                warn!("skipped record {} outside zone {}", record.name(), zone);
+                continue;
+            }
+            records.push(record);
+        }
+    }
+    Ok(records)
+}
+
+/// Client of the Redis server, with a connection for commands and one per
+/// subscription.
+#[cfg(feature = "redis")]
+struct Client {
+    client: ::redis::Client,
+    connection: tokio::sync::Mutex<Option<::redis::aio::MultiplexedConnection>>,
+}
+
+#[cfg(feature = "redis")]
+impl Client {
+    fn new(config: &RedisConfig) -> Result<Self> {
+        let client = ::redis::Client::open(config.url())
+            .with_context(|| format!("invalid redis url {}", config.url()))?;
+        Ok(Self {
+            client,
+            connection: tokio::sync::Mutex::new(None),
+        })
+    }
+
+    /// Subscribes to `channel`, returns the payloads of its messages.
+    async fn subscribe(&self, channel: &str) -> Result<BoxStream<'static, String>> {
+        let mut pubsub = self.client.get_async_pubsub().await?;
+        pubsub.subscribe(channel).await?;
+        let messages = pubsub
+            .into_on_message()
+            .filter_map(|message| async move { message.get_payload().ok() });
+        Ok(messages.boxed())
+    }
+
+    /// The fields of the hash at `key`, none when it doesn't exist.
+    async fn hash(&self, key: &str) -> Result<Vec<(String, String)>> {
+        let mut connection = self.connection.lock().await;
+        if connection.is_none() {
+            *connection = Some(self.client.get_multiplexed_async_connection().await?);
+        }
+        let result = ::redis::cmd("HGETALL")
+            .arg(key)
+            .query_async(connection.as_mut().unwrap())
+            .await;
+        if result.is_err() {
+            // connected again on the next command
+            *connection = None;
+        }
+        Ok(result?)
+    }
+}
+
+#[cfg(not(feature = "redis"))]
+enum Client {}
+
+#[cfg(not(feature = "redis"))]
+impl Client {
+    fn new(_config: &RedisConfig) -> Result<Self> {
+        bail!("the Redis backend requires the `redis` feature")
+    }
+
+    async fn subscribe(&self, _channel: &str) -> Result<BoxStream<'static, String>> {
+        match *self {}
+    }
+
+    async fn hash(&self, _key: &str) -> Result<Vec<(String, String)>> {
+        match *self {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hash_fields() -> Result<()> {
+        let zone = rr::Name::from_str("example.com.")?;
+        let field = |field: &str, value: &str| (field.to_string(), value.to_string());
+        let fields = [
+            field("www A", "300 192.0.2.1\n300 192.0.2.2\n"),
+            field("@ MX", "3600 10 mail"),
+            field("txt TXT", "60 \"hello world\""),
+            field("other.net. A", "60 192.0.2.3"),
+        ];
+        let mut records: Vec<String> = records(&fields, &zone)?
+            .iter()
+            .map(|r| {
+                format!(
+                    "{} {} {} {}",
+                    r.name(),
+                    r.ttl(),
+                    r.record_type(),
+                    r.data().unwrap()
+                )
+            })
+            .collect();
+        records.sort();
+        assert_eq!(
+            records,
+            [
+                "example.com. 3600 MX 10 mail.example.com.",
+                "txt.example.com. 60 TXT hello world",
+                "www.example.com. 300 A 192.0.2.1",
+                "www.example.com. 300 A 192.0.2.2",
+            ]
+        );
+
+        assert!(records_of("www", "192.0.2.1", &zone).is_err());
+        assert!(records_of("www A", "300", &zone).is_err());
+        Ok(())
+    }
+
+    fn records_of(field: &str, value: &str, zone: &rr::Name) -> Result<Vec<rr::Record>> {
+        records(&[(field.to_string(), value.to_string())], zone)
+    }
+}
@@ -0,0 +1,276 @@
+use crate::config::{RewriteMatch, RewriteRule};
+use crate::middleware;
+use anyhow::{Context, Result};
+use hickory_proto::op::{Message, Query};
+use hickory_proto::rr::{Name, Record};
+use hickory_proto::serialize::binary::BinEncoder;
+use hickory_server::authority::MessageResponse;
+use hickory_server::server::{Request, ResponseHandler, ResponseInfo};
+use regex::Regex;
+use std::io;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// The rewrite rules of the server, in order.
+pub(crate) struct Rewriter {
+    rules: Vec<Rule>,
+}
+
+struct Rule {
+    pattern: Pattern,
+    rewrite_answers: bool,
+}
+
+enum Pattern {
+    Exact(Name, Name),
+    Suffix(Name, Name),
+    /// Anchored to match whole names, and the replacement.
+    Regex(Regex, String),
+}
+
+/// A queried name and the name it is looked up as.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Rewrite {
+    from: Name,
+    to: Name,
+    /// The suffixes of a suffix rule, whose names in the answers are
+    /// renamed back when `rewrite_answers` is set.
+    suffixes: Option<(Name, Name)>,
+    rewrite_answers: bool,
+}
+
+impl Rewriter {
+    pub(crate) fn new(rules: &[RewriteRule]) -> Result<Self> {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                Ok(Rule {
+                    pattern: Pattern::new(rule)
+                        .with_context(|| format!("invalid rewrite rule for {}", rule.from()))?,
+                    rewrite_answers: rule.rewrite_answers(),
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { rules })
+    }
+
+    /// How the first rule matching `name` rewrites it, if any.
+    pub(crate) fn rewrite(&self, name: &Name) -> Option<Rewrite> {
+        self.rules.iter().find_map(|rule| {
+            let (to, suffixes) = rule.pattern.apply(name)?;
+            Some(Rewrite {
+                from: name.clone(),
+                to,
+                suffixes,
+                rewrite_answers: rule.rewrite_answers,
+            })
+        })
+    }
+}
+
+impl Pattern {
+    fn new(rule: &RewriteRule) -> Result<Self> {
+        let name = |name: &str| -> Result<Name> {
+            let mut name = Name::from_str(name)?;
+            name.set_fqdn(true);
+            Ok(name)
+        };
+        Ok(match rule.match_kind() {
+            RewriteMatch::Exact => Self::Exact(name(rule.from())?, name(rule.to())?),
+            RewriteMatch::Suffix => Self::Suffix(name(rule.from())?, name(rule.to())?),
+            RewriteMatch::Regex => Self::Regex(
+                Regex::new(&format!("^(?:{})$", rule.from()))?,
+                rule.to().to_string(),
+            ),
+        })
+    }
+
+    /// The name `name` is rewritten to, with the suffixes replaced for
+    /// suffix rules.
+    fn apply(&self, name: &Name) -> Option<(Name, Option<(Name, Name)>)> {
+        match self {
+            Self::Exact(from, to) => from
+                .eq_case(&name.to_lowercase())
+                .then(|| (to.clone(), None)),
+            Self::Suffix(from, to) => {
+                let rewritten = replace_suffix(name, from, to)?;
+                Some((rewritten, Some((from.clone(), to.clone()))))
+            }
+            Self::Regex(regex, replacement) => {
+                let text = name.to_lowercase().to_string();
+                let text = text.trim_end_matches('.');
+                if !regex.is_match(text) {
+                    return None;
+                }
+                let rewritten = regex.replace(text, replacement.as_str());
+                match Name::from_str(&format!("{}.", rewritten)) {
+                    Ok(name) => Some((name, None)),
+                    Err(e) => {
+                        tracing::debug!("rewrote {} into invalid name {}: {}", name, rewritten, e);
+                        None
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// `name` with its suffix `from` replaced with `to`, `None` when it isn't
+/// below `from`.
+fn replace_suffix(name: &Name, from: &Name, to: &Name) -> Option<Name> {
+    if !from.zone_of(name) {
+        return None;
+    }
+    let prefix = usize::from(name.num_labels() - from.num_labels());
+    if prefix == 0 {
+        return Some(to.clone());
+    }
+    Name::from_labels(name.iter().take(prefix))
+        .ok()?
+        .append_domain(to)
+        .ok()
+}
+
+impl Rewrite {
+    pub(crate) fn to(&self) -> &Name {
+        &self.to
+    }
+
+    /// The name of the answers `name` is renamed back to.
+    fn restore(&self, name: &Name) -> Option<Name> {
+        match &self.suffixes {
+            Some((from, to)) => replace_suffix(name, to, from),
+            None => self
+                .to
+                .eq_case(&name.to_lowercase())
+                .then(|| self.from.clone()),
+        }
+    }
+
+    /// `request` asking for the rewritten name.
+    pub(crate) fn request(&self, request: &Request) -> io::Result<Request> {
+        let mut message = middleware::to_message(request)?;
+        let mut query = request.query().original().clone();
+        query.set_name(self.to.clone());
+        message.take_queries();
+        message.add_query(query);
+        middleware::to_request(request, &message)
+    }
+
+    /// Wraps `response_handle` to answer the question of `request`, and
+    /// to rename the records of the rewritten name back when the rule says
+    /// so.
+    pub(crate) fn respond<R: ResponseHandler>(
+        self,
+        request: &Request,
+        response_handle: R,
+    ) -> RewritingResponseHandler<R> {
+        RewritingResponseHandler {
+            inner: response_handle,
+            query: request.query().original().clone(),
+            rewrite: self.into(),
+        }
+    }
+}
+
+/// Puts the question of the client back in the responses to a rewritten
+/// request.
+#[derive(Clone)]
+pub(crate) struct RewritingResponseHandler<R> {
+    inner: R,
+    query: Query,
+    rewrite: Arc<Rewrite>,
+}
+
+#[async_trait::async_trait]
+impl<R: ResponseHandler> ResponseHandler for RewritingResponseHandler<R> {
+    async fn send_response<'a>(
+        &mut self,
+        response: MessageResponse<
+            '_,
+            'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+        >,
+    ) -> io::Result<ResponseInfo> {
+        let mut bytes = Vec::with_capacity(512);
+        response.destructive_emit(&mut BinEncoder::new(&mut bytes))?;
+        let mut message = Message::from_vec(&bytes)?;
+        message.take_queries();
+        message.add_query(self.query.clone());
+        if self.rewrite.rewrite_answers {
+            let restore = |records: &mut Vec<Record>| {
+                for record in records {
+                    if let Some(name) = self.rewrite.restore(record.name()) {
+                        record.set_name(name);
+                    }
+                }
+            };
+            restore(message.answers_mut());
+            restore(message.name_servers_mut());
+            restore(message.additionals_mut());
+        }
+
+        middleware::resend(&mut self.inner, &message.to_vec()?, None).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RewriteRuleBuilder;
+
+    fn rule(match_kind: RewriteMatch, from: &str, to: &str) -> RewriteRule {
+        RewriteRuleBuilder::default()
+            .match_kind(match_kind)
+            .from(from)
+            .to(to)
+            .rewrite_answers(true)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn rewrites_names() -> Result<()> {
+        let rewriter = Rewriter::new(&[
+            rule(RewriteMatch::Exact, "legacy.corp", "www.example.com"),
+            rule(RewriteMatch::Suffix, "old.corp", "new.example.com"),
+            rule(
+                RewriteMatch::Regex,
+                r"host-(\d+)\.corp",
+                "host$1.example.com",
+            ),
+        ])?;
+        let rewrite = |name: &str| {
+            let rewrite = rewriter.rewrite(&Name::from_str(name).unwrap())?;
+            Some(rewrite.to().to_string())
+        };
+        assert_eq!(rewrite("Legacy.corp.").unwrap(), "www.example.com.");
+        assert_eq!(rewrite("a.b.old.corp.").unwrap(), "a.b.new.example.com.");
+        assert_eq!(rewrite("old.corp.").unwrap(), "new.example.com.");
+        assert_eq!(rewrite("host-12.corp.").unwrap(), "host12.example.com.");
+        assert_eq!(rewrite("x.host-12.corp."), None);
+        assert_eq!(rewrite("www.legacy.corp."), None);
+        assert_eq!(rewrite("gold.corp."), None);
+
+        let rewrite = rewriter.rewrite(&Name::from_str("a.old.corp.")?).unwrap();
+        assert_eq!(
+            rewrite.restore(&Name::from_str("b.new.example.com.")?),
+            Some(Name::from_str("b.old.corp.")?)
+        );
+        let rewrite = rewriter.rewrite(&Name::from_str("legacy.corp.")?).unwrap();
+        assert_eq!(
+            rewrite.restore(&Name::from_str("www.example.com.")?),
+            Some(Name::from_str("legacy.corp.")?)
+        );
+        assert_eq!(
+            rewrite.restore(&Name::from_str("other.example.com.")?),
+            None
+        );
+
+        assert!(Rewriter::new(&[rule(RewriteMatch::Regex, "(", "x")]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,249 @@
+use anyhow::{anyhow, bail, Result};
+use futures_util::StreamExt;
+use hickory_proto::iocompat::AsyncIoTokioAsStd;
+use hickory_proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use hickory_proto::rr;
+use hickory_proto::rr::dnssec::tsig::TSigner;
+use hickory_proto::rr::{DNSClass, RData, RecordType};
+use hickory_proto::tcp::TcpClientStream;
+use hickory_proto::xfer::{DnsExchange, DnsHandle, DnsMultiplexer, DnsRequest, DnsRequestOptions};
+use hickory_proto::TokioTime;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Result of a zone transfer from a primary.
+#[derive(Debug)]
+pub(crate) enum Transfer {
+    /// The primary has no newer version of the zone.
+    UpToDate,
+    /// Every record of the zone, the SOA included once.
+    Full(Vec<rr::Record>),
+    /// Changes to apply in order, each as the deleted and the added records.
+    Incremental(Vec<(Vec<rr::Record>, Vec<rr::Record>)>),
+}
+
+/// Transfers `zone` from `primary` over TCP. With the `current` SOA record an
+/// IXFR is requested, which the primary may answer with the full zone.
+///
+/// With a `signer` the request is signed with TSIG and every response must
+/// carry a valid signature.
+pub(crate) async fn fetch(
+    primary: SocketAddr,
+    zone: &rr::Name,
+    current: Option<&rr::Record>,
+    signer: Option<TSigner>,
+) -> Result<Transfer> {
+    let records = fetch_records(primary, zone, current, signer);
+    tokio::time::timeout(TRANSFER_TIMEOUT, records)
+        .await
+        .map_err(|_| anyhow!("transfer of {} from {} timed out", zone, primary))?
+        .and_then(|records| parse(records, current.and_then(serial)))
+}
+
+async fn fetch_records(
+    primary: SocketAddr,
+    zone: &rr::Name,
+    current: Option<&rr::Record>,
+    signer: Option<TSigner>,
+) -> Result<Vec<rr::Record>> {
+    let (stream, sender) =
+        TcpClientStream::<AsyncIoTokioAsStd<TcpStream>>::with_timeout(primary, TRANSFER_TIMEOUT);
+    let multiplexer =
+        DnsMultiplexer::with_timeout(stream, sender, TRANSFER_TIMEOUT, signer.map(Arc::new));
+    let (exchange, background) = DnsExchange::connect::<_, _, TokioTime>(multiplexer).await?;
+    let background = tokio::spawn(background);
+
+    let query_type = match current {
+        Some(_) => RecordType::IXFR,
+        None => RecordType::AXFR,
+    };
+    let mut query = Query::query(zone.clone(), query_type);
+    query.set_query_class(DNSClass::IN);
+    let mut message = Message::new();
+    message
+        .add_query(query)
+        .set_message_type(MessageType::Query)
+        .set_op_code(OpCode::Query)
+        .set_recursion_desired(false);
+    if let Some(soa) = current {
+        message.add_name_server(soa.clone());
+    }
+
+    let mut responses = exchange.send(DnsRequest::new(message, DnsRequestOptions::default()));
+    let mut records = Vec::new();
+    let result = loop {
+        let response = match responses.next().await {
+            Some(Ok(response)) => response,
+            Some(Err(e)) => break Err(e.into()),
+            None => break Err(anyhow!("{} closed the transfer of {}", primary, zone)),
+        };
+        if response.response_code() != ResponseCode::NoError {
+            break Err(anyhow!(
+                "{} answered {} to the transfer of {}",
+                primary,
+                response.response_code(),
+                zone
+            ));
+        }
+        records.extend(response.into_message().take_answers());
+        if complete(&records, current.is_some()) {
+            break Ok(records);
+        }
+    };
+    background.abort();
+    result
+}
+
+fn serial(record: &rr::Record) -> Option<u32> {
+    match record.data() {
+        Some(RData::SOA(soa)) => Some(soa.serial()),
+        _ => None,
+    }
+}
+
+/// Whether `records` hold a whole transfer: a lone SOA answering an IXFR,
+/// or the opening SOA repeated at the end. Every SOA in between opens or
+/// closes an IXFR change, so the closing one leaves an odd count.
+fn complete(records: &[rr::Record], ixfr: bool) -> bool {
+    let Some(last) = records.last().and_then(serial) else {
+        return false;
+    };
+    if records.len() == 1 {
+        return ixfr;
+    }
+    let soas = records[1..].iter().filter_map(serial).count();
+    serial(&records[0]) == Some(last) && soas % 2 == 1
+}
+
+/// Interprets the answer records of a transfer, `current` being the serial
+/// the secondary holds.
+fn parse(mut records: Vec<rr::Record>, current: Option<u32>) -> Result<Transfer> {
+    let Some(latest) = records.first().and_then(serial) else {
+        bail!("transfer does not start with an SOA record");
+    };
+    if records.len() == 1 || current == Some(latest) {
+        return Ok(Transfer::UpToDate);
+    }
+    records.pop();
+    if records[1].record_type() != RecordType::SOA {
+        return Ok(Transfer::Full(records));
+    }
+
+    // IXFR: the old SOA and the deleted records, then the new SOA and the
+    // added records, for every version in between.
+    let mut changes = Vec::new();
+    let mut deleting = false;
+    for record in records.into_iter().skip(1) {
+        if record.record_type() == RecordType::SOA {
+            deleting = !deleting;
+            if deleting {
+                changes.push((vec![record], Vec::new()));
+                continue;
+            }
+        }
+        let (deleted, added) = changes.last_mut().unwrap();
+        if deleting {
+            deleted.push(record);
+        } else {
+            added.push(record);
+        }
+    }
+    Ok(Transfer::Incremental(changes))
+}
+
+/// Applies the changes of an incremental transfer to `records`.
+pub(crate) fn apply(
+    records: &mut Vec<rr::Record>,
+    changes: Vec<(Vec<rr::Record>, Vec<rr::Record>)>,
+) {
+    let same = |a: &rr::Record, b: &rr::Record| {
+        a.name() == b.name() && a.record_type() == b.record_type() && a.data() == b.data()
+    };
+    for (deleted, added) in changes {
+        records
+            .retain(|r| r.record_type() != RecordType::SOA && !deleted.iter().any(|d| same(r, d)));
+        records.extend(added);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn soa(serial: u32) -> rr::Record {
+        let name = |name: &str| rr::Name::from_str(name).unwrap();
+        rr::Record::from_rdata(
+            name("et.internal."),
+            3600,
+            RData::SOA(rr::rdata::SOA::new(
+                name("ns1.et.internal."),
+                name("hostmaster.et.internal."),
+                serial,
+                3600,
+                600,
+                604800,
+                60,
+            )),
+        )
+    }
+
+    fn a(name: &str, address: &str) -> rr::Record {
+        rr::Record::from_rdata(
+            rr::Name::from_str(name).unwrap(),
+            60,
+            RData::A(address.parse().unwrap()),
+        )
+    }
+
+    #[test]
+    fn parses_full_and_incremental_transfers() -> Result<()> {
+        let axfr = vec![soa(2), a("www.et.internal.", "10.0.0.1"), soa(2)];
+        assert!(!complete(&axfr[..2], false));
+        assert!(complete(&axfr, false));
+        match parse(axfr, None)? {
+            Transfer::Full(records) => {
+                assert_eq!(records, vec![soa(2), a("www.et.internal.", "10.0.0.1")])
+            }
+            transfer => panic!("unexpected {:?}", transfer),
+        }
+
+        assert!(complete(&[soa(2)], true));
+        assert!(matches!(parse(vec![soa(2)], Some(2))?, Transfer::UpToDate));
+
+        let ixfr = vec![
+            soa(3),
+            soa(1),
+            a("www.et.internal.", "10.0.0.1"),
+            soa(2),
+            a("www.et.internal.", "10.0.0.2"),
+            soa(2),
+            soa(3),
+            a("api.et.internal.", "10.0.0.3"),
+            soa(3),
+        ];
+        // ends on the new SOA of the last change, its additions may follow
+        assert!(!complete(&ixfr[..7], true));
+        assert!(complete(&ixfr, true));
+        let Transfer::Incremental(changes) = parse(ixfr, Some(1))? else {
+            panic!("expected an incremental transfer");
+        };
+        assert_eq!(changes.len(), 2);
+
+        let mut records = vec![soa(1), a("www.et.internal.", "10.0.0.1")];
+        apply(&mut records, changes);
+        assert_eq!(
+            records,
+            vec![
+                a("www.et.internal.", "10.0.0.2"),
+                soa(3),
+                a("api.et.internal.", "10.0.0.3"),
+            ]
+        );
+        Ok(())
+    }
+}
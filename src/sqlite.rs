@@ -0,0 +1,238 @@
+use crate::config::{self, RunConfig};
+use crate::error::Error;
+use anyhow::{bail, Result};
+use hickory_proto::rr;
+use std::path::Path;
+
+/// The schema of the database, one statement list per version. A database
+/// is migrated by applying the lists past its `user_version`.
+#[cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+const MIGRATIONS: &[&str] = &["
+    CREATE TABLE zones (
+        name TEXT PRIMARY KEY NOT NULL
+    );
+    CREATE TABLE records (
+        zone TEXT NOT NULL REFERENCES zones (name) ON DELETE CASCADE,
+        name TEXT NOT NULL,
+        type TEXT NOT NULL,
+        ttl INTEGER NOT NULL,
+        data TEXT NOT NULL,
+        PRIMARY KEY (zone, name, type, data)
+    );
+"];
+
+/// Imports the primary zones of `config` into `database`, replacing what it
+/// held of them, and returns their names. A zone is imported from its
+/// persist file when it has one, from its records and zone file otherwise.
+///
+/// This moves in-memory zones to the `sqlite` backend: once imported, they
+/// come from the database with `backend = "sqlite"`.
+pub fn import(config: &RunConfig, database: &Path) -> Result<Vec<String>, Error> {
+    import_zones(config.zones(), database).map_err(|e| Error::Zone(e.into()))
+}
+
+fn import_zones(zones: &config::Zone, database: &Path) -> Result<Vec<String>> {
+    let mut imported = Vec::new();
+    for (domain, zone_config) in zones {
+        if zone_config.zone_type() != config::ZoneType::Primary {
+            continue;
+        }
+        let origin = rr::Name::from_ascii(domain)?;
+        let records = match zone_config.persisted_records(&origin)? {
+            Some(records) => records,
+            None => zone_config.to_records(&origin)?,
+        };
+        save(database, &origin, &records)?;
+        imported.push(domain.clone());
+    }
+    imported.sort();
+    Ok(imported)
+}
+
+/// The key of `origin` in the database.
+#[cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+fn zone_key(origin: &rr::Name) -> String {
+    crate::zonefile::fqdn(&origin.to_lowercase()).to_string()
+}
+
+/// The records of `origin` held by `database`, `None` if it doesn't hold the
+/// zone.
+#[cfg(feature = "sqlite")]
+pub(crate) fn load(database: &Path, origin: &rr::Name) -> Result<Option<Vec<rr::Record>>> {
+    use anyhow::Context;
+    use rusqlite::OptionalExtension;
+
+    let connection = open(database)?;
+    let zone = zone_key(origin);
+    let held = connection
+        .query_row("SELECT 1 FROM zones WHERE name = ?1", [&zone], |_| Ok(()))
+        .optional()?;
+    if held.is_none() {
+        return Ok(None);
+    }
+    let mut statement =
+        connection.prepare("SELECT name, ttl, type, data FROM records WHERE zone = ?1")?;
+    let mut rows = statement.query([&zone])?;
+    let mut records = Vec::new();
+    while let Some(row) = rows.next()? {
+        let (name, ttl, record_type, data): (String, u32, String, String) =
+            (row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?);
+        let record = crate::zonefile::parse_record(&name, ttl, &record_type, &data, origin)
+            .with_context(|| {
+                format!(
+                    "invalid {} record {} of zone {} in {}",
+                    record_type,
+                    name,
+                    origin,
+                    database.display()
+                )
+            })?;
+        records.push(record);
+    }
+    Ok(Some(records))
+}
+
+/// Replaces the records of `origin` in `database` with `records`, writing
+/// only the rows that changed.
+#[cfg(feature = "sqlite")]
+pub(crate) fn save(database: &Path, origin: &rr::Name, records: &[rr::Record]) -> Result<()> {
+    use crate::zonefile::{fqdn, rdata_text};
+    use std::collections::{HashMap, HashSet};
+
+    let mut connection = open(database)?;
+    let zone = zone_key(origin);
+    let rows: HashMap<(String, String, String), u32> = records
+        .iter()
+        .filter_map(|record| {
+            let data = rdata_text(record.data()?);
+            let key = (
+                fqdn(record.name()).to_string(),
+                record.record_type().to_string(),
+                data,
+            );
+            Some((key, record.ttl()))
+        })
+        .collect();
+
+    let transaction = connection.transaction()?;
+    transaction.execute("INSERT OR IGNORE INTO zones (name) VALUES (?1)", [&zone])?;
+    let mut stored = HashMap::new();
+    {
+        let mut statement =
+            transaction.prepare("SELECT name, type, data, ttl FROM records WHERE zone = ?1")?;
+        let mut query = statement.query([&zone])?;
+        while let Some(row) = query.next()? {
+            stored.insert(
+                (row.get(0)?, row.get(1)?, row.get(2)?),
+                row.get::<_, u32>(3)?,
+            );
+        }
+    }
+    let removed: HashSet<_> = stored
+        .keys()
+        .filter(|key| !rows.contains_key(*key))
+        .collect();
+    for (name, record_type, data) in removed {
+        transaction.execute(
+            "DELETE FROM records WHERE zone = ?1 AND name = ?2 AND type = ?3 AND data = ?4",
+            (&zone, name, record_type, data),
+        )?;
+    }
+    for ((name, record_type, data), ttl) in &rows {
+        if stored.get(&(name.clone(), record_type.clone(), data.clone())) == Some(ttl) {
+            continue;
+        }
+        transaction.execute(
+            "INSERT OR REPLACE INTO records (zone, name, type, ttl, data) \
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            (&zone, name, record_type, ttl, data),
+        )?;
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+/// Opens `database`, creating it if missing, and migrates it to the latest
+/// schema.
+#[cfg(feature = "sqlite")]
+fn open(database: &Path) -> Result<rusqlite::Connection> {
+    use anyhow::Context;
+
+    let mut connection = rusqlite::Connection::open(database)
+        .with_context(|| format!("failed to open database {}", database.display()))?;
+    connection.busy_timeout(std::time::Duration::from_secs(5))?;
+    connection.pragma_update(None, "foreign_keys", true)?;
+    let version: usize = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version > MIGRATIONS.len() {
+        bail!(
+            "database {} has schema version {}, newer than the supported {}",
+            database.display(),
+            version,
+            MIGRATIONS.len()
+        );
+    }
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(migration)?;
+        transaction.pragma_update(None, "user_version", i + 1)?;
+        transaction.commit()?;
+    }
+    Ok(connection)
+}
+
+#[cfg(not(feature = "sqlite"))]
+pub(crate) fn load(_database: &Path, _origin: &rr::Name) -> Result<Option<Vec<rr::Record>>> {
+    bail!("the sqlite backend requires the `sqlite` feature")
+}
+
+#[cfg(not(feature = "sqlite"))]
+pub(crate) fn save(_database: &Path, _origin: &rr::Name, _records: &[rr::Record]) -> Result<()> {
+    bail!("the sqlite backend requires the `sqlite` feature")
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn saves_and_loads_zones() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let database = dir.path().join("zones.db");
+        let origin = rr::Name::from_str("example.com.")?;
+        assert!(load(&database, &origin)?.is_none());
+
+        let zone = config::ZoneConfig::from(vec![
+            config::RecordBuilder::default()
+                .name("www".to_string())
+                .data(config::RecordData::A("192.0.2.1".parse()?))
+                .ttl(std::time::Duration::from_secs(60))
+                .build()?,
+            config::RecordBuilder::default()
+                .name("txt".to_string())
+                .data(config::RecordData::Txt(vec!["hello \"world\"".to_string()]))
+                .ttl(std::time::Duration::from_secs(60))
+                .build()?,
+        ]);
+        let mut records = zone.to_records(&origin)?;
+        save(&database, &origin, &records)?;
+        let mut loaded = load(&database, &origin)?.unwrap();
+        let key = |r: &rr::Record| (r.name().clone(), r.record_type(), r.data().cloned());
+        records.sort_by_key(key);
+        loaded.sort_by_key(key);
+        assert_eq!(loaded, records);
+
+        records.retain(|r| r.name().to_string() != "txt.example.com.");
+        records[0].set_ttl(7);
+        save(&database, &origin, &records)?;
+        let mut loaded = load(&database, &origin)?.unwrap();
+        loaded.sort_by_key(key);
+        assert_eq!(loaded, records);
+
+        // opening again leaves a migrated database as it is
+        let other = rr::Name::from_str("example.net.")?;
+        assert!(load(&database, &other)?.is_none());
+        assert_eq!(load(&database, &origin)?.unwrap().len(), records.len());
+        Ok(())
+    }
+}
@@ -0,0 +1,84 @@
+//! Fingerprints of OpenSSH public keys, for SSHFP records (RFC 4255).
+
+use anyhow::{anyhow, bail, Context, Result};
+use hickory_proto::rr::dnssec::DigestType;
+use std::path::Path;
+
+/// The SSHFP algorithm number of an OpenSSH key type.
+fn algorithm(key_type: &str) -> Option<u8> {
+    match key_type {
+        "ssh-rsa" => Some(1),
+        "ssh-dss" => Some(2),
+        "ecdsa-sha2-nistp256" | "ecdsa-sha2-nistp384" | "ecdsa-sha2-nistp521" => Some(3),
+        "ssh-ed25519" => Some(4),
+        "ssh-ed448" => Some(6),
+        _ => None,
+    }
+}
+
+/// The SSHFP algorithm and fingerprint of the first key of the OpenSSH
+/// public key file at `path`, e.g. `/etc/ssh/ssh_host_ed25519_key.pub`,
+/// hashed with SHA-1 or SHA-256 (`fingerprint_type` 1 or 2).
+pub(crate) fn fingerprint(path: &Path, fingerprint_type: u8) -> Result<(u8, Vec<u8>)> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let line = text
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+        .ok_or_else(|| anyhow!("no public key found in {}", path.display()))?;
+    let mut fields = line.split_whitespace();
+    let (Some(key_type), Some(key)) = (fields.next(), fields.next()) else {
+        bail!("invalid public key in {}", path.display());
+    };
+    let algorithm = algorithm(key_type)
+        .ok_or_else(|| anyhow!("unsupported key type {} in {}", key_type, path.display()))?;
+    let key = data_encoding::BASE64
+        .decode(key.as_bytes())
+        .with_context(|| format!("invalid public key in {}", path.display()))?;
+    let digest = match fingerprint_type {
+        1 => DigestType::SHA1,
+        2 => DigestType::SHA256,
+        _ => bail!("unsupported SSHFP fingerprint type {}", fingerprint_type),
+    };
+    Ok((algorithm, digest.hash(&key)?.as_ref().to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn computes_fingerprints() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("ssh_host_ed25519_key.pub");
+        std::fs::write(
+            &path,
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIO0RWUDHn1LZunfz13G/5Tvg5ThZVVtWwe0fdBdKieqA root@lab\n",
+        )?;
+
+        // as given by `ssh-keygen -r`
+        let hex = |(algorithm, fingerprint): (u8, Vec<u8>)| {
+            (algorithm, data_encoding::HEXLOWER.encode(&fingerprint))
+        };
+        assert_eq!(
+            hex(fingerprint(&path, 1)?),
+            (4, "a20ff79cc35ba1ac2a7f67a8c2e2bd2165b481f0".to_string())
+        );
+        assert_eq!(
+            hex(fingerprint(&path, 2)?),
+            (
+                4,
+                "1e008168bd801dba81ec19ce92ccf7590d29f6d8c28cb23566f521a40731589f".to_string()
+            )
+        );
+
+        assert!(fingerprint(&path, 3).is_err());
+        std::fs::write(&path, "ssh-foo AAAA root@lab\n")?;
+        assert!(fingerprint(&path, 2).is_err());
+        std::fs::write(&path, "ssh-ed25519 not-base64 root@lab\n")?;
+        assert!(fingerprint(&path, 2).is_err());
+        assert!(fingerprint(&dir.path().join("missing.pub"), 2).is_err());
+        Ok(())
+    }
+}
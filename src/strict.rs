@@ -0,0 +1,210 @@
+//! Strict loading of config files, see `general.strict`: keys the config
+//! doesn't know, mostly typos such as `listn_udp`, and listen addresses that
+//! can't be bound are errors instead of being ignored until the server
+//! starts.
+
+use crate::config::{line_column, GeneralConfig};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::Path;
+
+/// A step of the path to a value of the config file.
+#[derive(Debug, Clone)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+#[derive(Debug, Clone, Default)]
+struct KeyPath(Vec<Segment>);
+
+impl fmt::Display for KeyPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, segment) in self.0.iter().enumerate() {
+            match segment {
+                Segment::Key(key) => {
+                    if i > 0 {
+                        f.write_str(".")?;
+                    }
+                    if key.is_empty() || key.contains(['.', ' ', '"']) {
+                        write!(f, "{:?}", key)?;
+                    } else {
+                        f.write_str(key)?;
+                    }
+                }
+                Segment::Index(index) => write!(f, "[{}]", index)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl KeyPath {
+    fn from_ignored(path: &serde_ignored::Path) -> Self {
+        use serde_ignored::Path;
+
+        match path {
+            Path::Root => Self::default(),
+            Path::Seq { parent, index } => {
+                let mut key_path = Self::from_ignored(parent);
+                key_path.0.push(Segment::Index(*index));
+                key_path
+            }
+            Path::Map { parent, key } => {
+                let mut key_path = Self::from_ignored(parent);
+                key_path.0.push(Segment::Key(key.clone()));
+                key_path
+            }
+            Path::Some { parent }
+            | Path::NewtypeStruct { parent }
+            | Path::NewtypeVariant { parent } => Self::from_ignored(parent),
+        }
+    }
+}
+
+/// A problem of a config file.
+pub(crate) struct Problem {
+    /// Line and column in the file, when it can be found.
+    location: Option<(usize, usize)>,
+    message: String,
+}
+
+/// The keys of the config file `text`, which parses as `given`, that
+/// deserializing a `T` ignores, in the order of the file.
+pub(crate) fn unknown_keys<T: DeserializeOwned>(
+    text: &str,
+    given: Value,
+) -> anyhow::Result<Vec<Problem>> {
+    let mut unknown = Vec::new();
+    serde_ignored::deserialize(given, |path| unknown.push(KeyPath::from_ignored(&path)))
+        .map(|_: T| ())?;
+    let mut problems: Vec<_> = unknown
+        .into_iter()
+        .map(|path| Problem {
+            location: locate(text, &path),
+            message: format!("unknown key {}", path),
+        })
+        .collect();
+    problems.sort_by_key(|problem| problem.location);
+    Ok(problems)
+}
+
+/// The listen addresses of `general` that can't be bound, in the config
+/// file `text`.
+pub(crate) fn invalid_listen_addresses(text: &str, general: &GeneralConfig) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    for (key, address) in listen_addresses(general) {
+        if let Err(reason) = check_listen_address(address) {
+            let path = KeyPath(vec![
+                Segment::Key("general".to_string()),
+                Segment::Key(key.to_string()),
+            ]);
+            problems.push(Problem {
+                location: locate(text, &path),
+                message: format!("invalid listen address {:?}: {}", address, reason),
+            });
+        }
+    }
+    problems
+}
+
+/// Fails with the `problems` of the config file at `path`, one per line, if
+/// there are any.
+pub(crate) fn check(path: &Path, problems: Vec<Problem>) -> anyhow::Result<()> {
+    if problems.is_empty() {
+        return Ok(());
+    }
+    let problems: Vec<String> = problems
+        .into_iter()
+        .map(|problem| match problem.location {
+            Some((line, column)) => {
+                format!(
+                    "{}:{}:{}: {}",
+                    path.display(),
+                    line,
+                    column,
+                    problem.message
+                )
+            }
+            None => format!("{}: {}", path.display(), problem.message),
+        })
+        .collect();
+    anyhow::bail!("{}", problems.join("\n"))
+}
+
+/// The listen addresses of `general`, with their keys.
+fn listen_addresses(general: &GeneralConfig) -> Vec<(&'static str, &str)> {
+    let mut addresses = Vec::new();
+    for address in general.listen_tcp() {
+        addresses.push(("listen_tcp", address.as_str()));
+    }
+    for address in general.listen_udp() {
+        addresses.push(("listen_udp", address.as_str()));
+    }
+    if let Some(address) = general.listen_quic() {
+        addresses.push(("listen_quic", address.as_str()));
+    }
+    if let Some(tls) = general.listen_tls() {
+        addresses.push(("listen_tls", tls.address()));
+    }
+    if let Some(https) = general.listen_https() {
+        addresses.push(("listen_https", https.tls().address()));
+    }
+    if let Some(admin) = general.listen_admin() {
+        addresses.push(("listen_admin", admin.address()));
+    }
+    if let Some(grpc) = general.listen_grpc() {
+        addresses.push(("listen_grpc", grpc.address()));
+    }
+    addresses
+}
+
+/// Why `address` can't be a listen address: a socket address or a host
+/// name and a port.
+fn check_listen_address(address: &str) -> Result<(), &'static str> {
+    if address.parse::<SocketAddr>().is_ok() {
+        return Ok(());
+    }
+    let Some((host, port)) = address.rsplit_once(':') else {
+        return Err("missing port");
+    };
+    if port.parse::<u16>().is_err() {
+        return Err("invalid port");
+    }
+    if host.is_empty() || host.contains(['[', ']', ':', '/']) || host.contains(char::is_whitespace)
+    {
+        return Err("invalid host");
+    }
+    Ok(())
+}
+
+/// The line and column of the key at `path` in `text`, found by looking for
+/// each key of the path after the previous one.
+fn locate(text: &str, path: &KeyPath) -> Option<(usize, usize)> {
+    let mut position = 0;
+    let mut found = None;
+    for segment in &path.0 {
+        let Segment::Key(key) = segment else {
+            continue;
+        };
+        let start = find_key(&text[position..], key)? + position;
+        found = Some(start);
+        position = start + key.len();
+    }
+    found.map(|offset| line_column(text, offset))
+}
+
+/// The offset of the first use of `key` as a key in `text`: following the
+/// start of a line, a table or a key, and followed by `=`, `:`, `.` or `]`,
+/// maybe quoted.
+fn find_key(text: &str, key: &str) -> Option<usize> {
+    let is_key_char = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+    text.match_indices(key).map(|(i, _)| i).find(|&i| {
+        let before = text[..i].chars().next_back();
+        let after = text[i + key.len()..].trim_start_matches(['"', '\'']);
+        let after = after.trim_start_matches([' ', '\t']);
+        !before.is_some_and(is_key_char) && after.starts_with(['=', ':', '.', ']'])
+    })
+}
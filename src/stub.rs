@@ -0,0 +1,306 @@
+//! Stub zones: the name servers of a zone, learned from its primaries, are
+//! queried without recursion, following the referrals to the zones below.
+
+use crate::config::Upstream;
+use crate::forward::{is_failure, request, Forwarder};
+use anyhow::{anyhow, bail, Result};
+use hickory_proto::op::{Message, Query, ResponseCode};
+use hickory_proto::rr::{LowerName, Name, RData, Record, RecordType};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+use tracing::{debug, warn};
+
+/// Referrals followed for a query before giving up.
+const MAX_REFERRALS: usize = 8;
+
+/// Longest time learned name servers are used for, whatever the TTL of
+/// their NS records.
+const MAX_REFRESH: Duration = Duration::from_secs(86400);
+
+/// How soon learning the name servers is retried after it failed.
+const RETRY: Duration = Duration::from_secs(60);
+
+pub(crate) struct StubZone {
+    origin: LowerName,
+    primaries: Vec<SocketAddr>,
+    /// Port of the name servers, that of the primaries.
+    port: u16,
+    /// Addresses of the name servers, and when to learn them again. The
+    /// primaries are asked until they are learned.
+    servers: Mutex<(Vec<SocketAddr>, Instant)>,
+}
+
+impl StubZone {
+    pub(crate) fn new(origin: LowerName, primaries: Vec<SocketAddr>) -> Self {
+        Self {
+            origin,
+            port: primaries.first().map_or(53, SocketAddr::port),
+            primaries,
+            servers: Mutex::new((Vec::new(), Instant::now())),
+        }
+    }
+
+    /// Sends `message` to the name servers of the zone, and then to those of
+    /// each zone they refer to. Referrals without glue are answered as they
+    /// are.
+    pub(crate) async fn resolve(
+        &self,
+        forwarder: &Forwarder,
+        mut message: Message,
+    ) -> Result<Message> {
+        message.set_recursion_desired(false);
+        let mut servers = self.servers(forwarder).await;
+        let mut zone = self.origin.clone();
+        for _ in 0..MAX_REFERRALS {
+            let response = ask(forwarder, &servers, &message).await?;
+            match referral(&response, &zone, self.port) {
+                Some((child, addresses)) if !addresses.is_empty() => {
+                    debug!(
+                        "following referral to {} for stub zone {}",
+                        child, self.origin
+                    );
+                    zone = child;
+                    servers = addresses;
+                }
+                _ => return Ok(response),
+            }
+        }
+        bail!("too many referrals below stub zone {}", self.origin)
+    }
+
+    async fn servers(&self, forwarder: &Forwarder) -> Vec<SocketAddr> {
+        let mut servers = self.servers.lock().await;
+        let now = Instant::now();
+        if servers.1 <= now {
+            match self.learn(forwarder).await {
+                Ok((learned, ttl)) => {
+                    debug!(
+                        "learned name servers {:?} of stub zone {}",
+                        learned, self.origin
+                    );
+                    *servers = (learned, now + ttl.min(MAX_REFRESH));
+                }
+                Err(e) => {
+                    warn!(
+                        "failed to learn the name servers of stub zone {}: {:#}",
+                        self.origin, e
+                    );
+                    servers.1 = now + RETRY;
+                }
+            }
+        }
+        match servers.0.is_empty() {
+            true => self.primaries.clone(),
+            false => servers.0.clone(),
+        }
+    }
+
+    /// Asks the primaries for the NS records of the zone and the addresses
+    /// of the name servers, returns them with the TTL of the NS records.
+    /// Name servers outside the zone are only reached through their glue.
+    async fn learn(&self, forwarder: &Forwarder) -> Result<(Vec<SocketAddr>, Duration)> {
+        let origin = Name::from(&self.origin);
+        let ns_query = Query::query(origin.clone(), RecordType::NS);
+        let response = ask(forwarder, &self.primaries, &stub_request(&ns_query)).await?;
+        let ns: Vec<&Record> = response
+            .answers()
+            .iter()
+            .filter(|record| record.record_type() == RecordType::NS && *record.name() == origin)
+            .collect();
+        let ttl = ns.iter().map(|record| record.ttl()).min();
+        let Some(ttl) = ttl else {
+            bail!("no NS records of {}", origin);
+        };
+        let names = name_servers(&ns);
+        let mut addresses = glue(response.additionals(), &names, self.port);
+        for name in names.iter().filter(|name| origin.zone_of(name)) {
+            if glue(
+                response.additionals(),
+                std::slice::from_ref(name),
+                self.port,
+            )
+            .is_empty()
+            {
+                for record_type in [RecordType::A, RecordType::AAAA] {
+                    let query = Query::query(name.clone(), record_type);
+                    let response = ask(forwarder, &self.primaries, &stub_request(&query)).await?;
+                    let name = std::slice::from_ref(name);
+                    addresses.extend(glue(response.answers(), name, self.port));
+                }
+            }
+        }
+        if addresses.is_empty() {
+            bail!("no address of the name servers of {}", origin);
+        }
+        Ok((addresses, Duration::from_secs(ttl.into())))
+    }
+}
+
+fn stub_request(query: &Query) -> Message {
+    let mut message = request(query, false, None);
+    message.set_recursion_desired(false);
+    message
+}
+
+/// Sends `message` to each of `servers` in turn until one answers it
+/// without failing.
+async fn ask(forwarder: &Forwarder, servers: &[SocketAddr], message: &Message) -> Result<Message> {
+    let mut last_response = None;
+    for server in servers {
+        let upstream = Upstream::Plain(*server);
+        match forwarder.exchange(&upstream, message.clone()).await {
+            Ok(response) if is_failure(&response) => last_response = Some(response),
+            Ok(response) => return Ok(response),
+            Err(e) => debug!("name server {} failed: {}", server, e),
+        }
+    }
+    last_response.ok_or_else(|| anyhow!("no name server answered"))
+}
+
+/// The zone below `zone` `response` refers to, with the addresses of the
+/// glue of its name servers.
+fn referral(
+    response: &Message,
+    zone: &LowerName,
+    port: u16,
+) -> Option<(LowerName, Vec<SocketAddr>)> {
+    if !response.answers().is_empty() || response.response_code() != ResponseCode::NoError {
+        return None;
+    }
+    let ns: Vec<&Record> = response
+        .name_servers()
+        .iter()
+        .filter(|record| record.record_type() == RecordType::NS)
+        .collect();
+    let child = LowerName::from(ns.first()?.name());
+    if child == *zone || !zone.zone_of(&child) {
+        return None;
+    }
+    let ns: Vec<&Record> = ns
+        .into_iter()
+        .filter(|record| LowerName::from(record.name()) == child)
+        .collect();
+    Some((
+        child,
+        glue(response.additionals(), &name_servers(&ns), port),
+    ))
+}
+
+fn name_servers(ns: &[&Record]) -> Vec<Name> {
+    ns.iter()
+        .filter_map(|record| match record.data() {
+            Some(RData::NS(ns)) => Some(ns.0.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The addresses of `names` among `records`, on `port`.
+fn glue(records: &[Record], names: &[Name], port: u16) -> Vec<SocketAddr> {
+    records
+        .iter()
+        .filter(|record| names.contains(record.name()))
+        .filter_map(|record| match record.data() {
+            Some(RData::A(a)) => Some(SocketAddr::new(a.0.into(), port)),
+            Some(RData::AAAA(aaaa)) => Some(SocketAddr::new(aaaa.0.into(), port)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{
+        ForwardConfig, GeneralConfigBuilder, RecordType as Type, RunConfigBuilder,
+        ZoneConfigBuilder, ZoneType,
+    };
+    use crate::testing::{assert_answers, record, TestServer};
+    use maplit::hashmap;
+    use std::str::FromStr;
+
+    #[tokio::test]
+    async fn resolves_stub_zones() -> Result<()> {
+        let zone = ZoneConfigBuilder::default()
+            .ns(vec!["ns1.branch.example.".to_string()])
+            .records(vec![
+                record(Type::A, "ns1.branch.example", "127.0.0.1")?,
+                record(Type::A, "www.branch.example", "10.0.0.1")?,
+            ])
+            .build()?;
+        let primary = TestServer::start(
+            RunConfigBuilder::default()
+                .general(GeneralConfigBuilder::default().build()?)
+                .zones(hashmap! { "branch.example".to_string() => zone })
+                .build()?,
+        )
+        .await?;
+
+        // a stub zone below a local zone takes over its names
+        let zones = hashmap! {
+            "example".to_string() => vec![
+                record(Type::A, "www.example", "10.0.0.2")?,
+                record(Type::A, "www.branch.example", "10.0.0.3")?,
+            ].into(),
+            "branch.example".to_string() => ZoneConfigBuilder::default()
+                .zone_type(ZoneType::Stub)
+                .primaries(vec![primary.udp_addr().to_string()])
+                .build()?,
+        };
+        let server = TestServer::start(
+            RunConfigBuilder::default()
+                .general(GeneralConfigBuilder::default().build()?)
+                .zones(zones.clone())
+                .build()?,
+        )
+        .await?;
+        assert_answers(
+            &server.query("www.branch.example", RecordType::A).await?,
+            &["10.0.0.1"],
+        );
+        assert_answers(
+            &server.query("www.example", RecordType::A).await?,
+            &["10.0.0.2"],
+        );
+
+        let forwarder = Forwarder::new(&ForwardConfig::default(), &zones)?;
+        let stub = StubZone::new(
+            LowerName::from_str("branch.example")?,
+            vec![primary.udp_addr()],
+        );
+        assert_eq!(stub.servers(&forwarder).await, [primary.udp_addr()]);
+        assert_eq!(stub.servers.lock().await.0, [primary.udp_addr()]);
+
+        server.shutdown().await?;
+        primary.shutdown().await?;
+        Ok(())
+    }
+
+    #[test]
+    fn follows_referrals_below_the_zone() -> Result<()> {
+        let zone = LowerName::from_str("branch.example.")?;
+        let child = Name::from_str("lab.branch.example.")?;
+        let ns = Name::from_str("ns.lab.branch.example.")?;
+        let mut response = Message::new();
+        response
+            .add_name_server(Record::from_rdata(
+                child.clone(),
+                60,
+                RData::NS(hickory_proto::rr::rdata::NS(ns.clone())),
+            ))
+            .add_additional(Record::from_rdata(
+                ns,
+                60,
+                RData::A(hickory_proto::rr::rdata::A::new(10, 1, 0, 53)),
+            ));
+        let (referred, servers) = referral(&response, &zone, 5353).unwrap();
+        assert_eq!(referred, LowerName::from(&child));
+        assert_eq!(servers, ["10.1.0.53:5353".parse()?]);
+
+        // not below the zone the query was sent to
+        assert!(referral(&response, &LowerName::from(&child), 53).is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,85 @@
+use crate::config::OtlpConfig;
+use anyhow::Result;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::trace::{Config, Tracer, TracerProvider};
+use opentelemetry_sdk::{runtime, Resource};
+use tracing::Subscriber;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::registry::LookupSpan;
+
+/// Exports the spans of handled requests, from the listener through catalog
+/// lookups and forwarding, to an OTLP collector. The embedding program adds
+/// its `layer` to the tracing subscriber it installs:
+///
+/// ```ignore
+/// let exporter = OtlpExporter::new(config.general().otlp().as_ref().unwrap())?;
+/// tracing_subscriber::registry()
+///     .with(tracing_subscriber::fmt::layer())
+///     .with(exporter.layer())
+///     .init();
+/// ```
+pub struct OtlpExporter {
+    provider: TracerProvider,
+}
+
+impl OtlpExporter {
+    /// Starts exporting batches of spans, must be called within a tokio
+    /// runtime.
+    pub fn new(config: &OtlpConfig) -> Result<Self> {
+        let exporter = opentelemetry_otlp::new_exporter()
+            .tonic()
+            .with_endpoint(config.endpoint())
+            .with_timeout(config.timeout());
+        let resource = Resource::new([KeyValue::new(
+            "service.name",
+            config.service_name().to_string(),
+        )]);
+        let provider = opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(exporter)
+            .with_trace_config(Config::default().with_resource(resource))
+            .install_batch(runtime::Tokio)?;
+        Ok(Self { provider })
+    }
+
+    /// A tracing layer turning spans into OpenTelemetry spans.
+    pub fn layer<S>(&self) -> OpenTelemetryLayer<S, Tracer>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        tracing_opentelemetry::layer().with_tracer(self.provider.tracer("libdns"))
+    }
+
+    /// Exports the remaining spans and stops exporting. This blocks, which
+    /// needs a multi-threaded runtime.
+    pub fn shutdown(&self) -> Result<()> {
+        self.provider.shutdown()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::OtlpConfigBuilder;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    // shutting down blocks on the batch task, which needs a second thread
+    #[tokio::test(flavor = "multi_thread")]
+    async fn exports_spans() -> Result<()> {
+        let config = OtlpConfigBuilder::default()
+            .endpoint("http://127.0.0.1:1")
+            .build()?;
+        let exporter = OtlpExporter::new(&config)?;
+        let subscriber = tracing_subscriber::registry().with(exporter.layer());
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("request", qname = "www.et.internal.").in_scope(|| {});
+        });
+        // the span is exported on shutdown, to a collector that isn't there
+        let e = exporter.shutdown().unwrap_err();
+        assert!(format!("{:#}", e).contains("tcp connect error"));
+        Ok(())
+    }
+}
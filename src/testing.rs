@@ -0,0 +1,198 @@
+//! Helpers for the tests of programs embedding a [`Server`]: a server on
+//! ephemeral loopback ports, a client and assertions on its answers.
+
+use crate::config::{self, GeneralConfigBuilder, RecordBuilder, RecordData, RunConfigBuilder};
+use crate::{Error, RunConfig, Server};
+use anyhow::Result;
+use hickory_proto::iocompat::AsyncIoTokioAsStd;
+use hickory_proto::op::{Message, MessageType, NoopMessageFinalizer, OpCode, Query, ResponseCode};
+use hickory_proto::rr;
+use hickory_proto::tcp::TcpClientStream;
+use hickory_proto::udp::UdpClientStream;
+use hickory_proto::xfer::{
+    DnsExchange, DnsHandle, DnsMultiplexer, DnsRequest, DnsRequestOptions, FirstAnswer,
+};
+use hickory_proto::TokioTime;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::net::{TcpStream, UdpSocket};
+
+/// How long a query waits for its answer.
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// TTL of the records made by [`record`].
+const TTL: Duration = Duration::from_secs(60);
+
+/// A running server listening on ephemeral ports of 127.0.0.1.
+pub struct TestServer {
+    server: Server,
+}
+
+impl TestServer {
+    /// Starts a server for `config`, listening for UDP and TCP on ephemeral
+    /// ports of 127.0.0.1 in place of its `listen_udp` and `listen_tcp`.
+    pub async fn start(mut config: RunConfig) -> Result<Self, Error> {
+        config.general_mut().listen_on("127.0.0.1:0");
+        let mut server = Server::try_new(config)?;
+        server.run().await?;
+        Ok(Self { server })
+    }
+
+    /// Starts a server for the zone `zone` holding `records`.
+    pub async fn with_zone(zone: &str, records: Vec<config::Record>) -> Result<Self, Error> {
+        let config = RunConfigBuilder::default()
+            .general(
+                GeneralConfigBuilder::default()
+                    .build()
+                    .map_err(|e| Error::Config(e.into()))?,
+            )
+            .zones([(zone.to_string(), records.into())].into())
+            .build()
+            .map_err(|e| Error::Config(e.into()))?;
+        Self::start(config).await
+    }
+
+    pub fn server(&self) -> &Server {
+        &self.server
+    }
+
+    pub fn server_mut(&mut self) -> &mut Server {
+        &mut self.server
+    }
+
+    pub fn udp_addr(&self) -> SocketAddr {
+        self.server.udp_local_addrs()[0]
+    }
+
+    pub fn tcp_addr(&self) -> SocketAddr {
+        self.server.tcp_local_addrs()[0]
+    }
+
+    /// Queries the server over UDP, see [`query`].
+    pub async fn query(&self, name: &str, record_type: rr::RecordType) -> Result<Message> {
+        query(self.udp_addr(), name, record_type).await
+    }
+
+    /// Queries the server over TCP, see [`query_tcp`].
+    pub async fn query_tcp(&self, name: &str, record_type: rr::RecordType) -> Result<Message> {
+        query_tcp(self.tcp_addr(), name, record_type).await
+    }
+
+    /// Shuts the server down, returns how many queries were dropped.
+    pub async fn shutdown(mut self) -> Result<usize, Error> {
+        self.server.shutdown().await
+    }
+}
+
+/// Asks `server` over UDP for the records of type `record_type` at `name`,
+/// with recursion desired.
+pub async fn query(server: SocketAddr, name: &str, record_type: rr::RecordType) -> Result<Message> {
+    let stream = UdpClientStream::<UdpSocket>::with_timeout(server, TIMEOUT);
+    let (exchange, background) = DnsExchange::connect::<_, _, TokioTime>(stream).await?;
+    let background = tokio::spawn(background);
+    let response = send(&exchange, name, record_type).await;
+    background.abort();
+    response
+}
+
+/// Asks `server` over TCP, see [`query`].
+pub async fn query_tcp(
+    server: SocketAddr,
+    name: &str,
+    record_type: rr::RecordType,
+) -> Result<Message> {
+    let (stream, sender) =
+        TcpClientStream::<AsyncIoTokioAsStd<TcpStream>>::with_timeout(server, TIMEOUT);
+    let multiplexer =
+        DnsMultiplexer::<_, NoopMessageFinalizer>::with_timeout(stream, sender, TIMEOUT, None);
+    let (exchange, background) = DnsExchange::connect::<_, _, TokioTime>(multiplexer).await?;
+    let background = tokio::spawn(background);
+    let response = send(&exchange, name, record_type).await;
+    background.abort();
+    response
+}
+
+async fn send(exchange: &DnsExchange, name: &str, record_type: rr::RecordType) -> Result<Message> {
+    let mut message = Message::new();
+    message
+        .add_query(Query::query(rr::Name::from_str(name)?, record_type))
+        .set_message_type(MessageType::Query)
+        .set_op_code(OpCode::Query)
+        .set_recursion_desired(true);
+    let request = DnsRequest::new(message, DnsRequestOptions::default());
+    let response = exchange.send(request).first_answer().await?;
+    Ok(response.into_message())
+}
+
+/// A record of `record_type` at `name` with the zone file form `value`, and
+/// a TTL of a minute.
+pub fn record(record_type: config::RecordType, name: &str, value: &str) -> Result<config::Record> {
+    Ok(RecordBuilder::default()
+        .name(name.to_string())
+        .data(RecordData::from_text(record_type, value)?)
+        .ttl(TTL)
+        .build()?)
+}
+
+/// The answers of `response` in zone file form, one line each.
+pub fn answers(response: &Message) -> Vec<String> {
+    response.answers().iter().map(ToString::to_string).collect()
+}
+
+/// Asserts that `response` is a NOERROR answering the record data
+/// `expected`, in any order, e.g. `["10.0.0.1", "10.0.0.2"]`.
+#[track_caller]
+pub fn assert_answers(response: &Message, expected: &[&str]) {
+    assert_rcode(response, ResponseCode::NoError);
+    let mut data: Vec<String> = response
+        .answers()
+        .iter()
+        .filter_map(|record| Some(record.data()?.to_string()))
+        .collect();
+    data.sort();
+    let mut expected: Vec<&str> = expected.to_vec();
+    expected.sort();
+    assert_eq!(data, expected, "answers of {:?}", answers(response));
+}
+
+/// Asserts that `response` has the response code `code`.
+#[track_caller]
+pub fn assert_rcode(response: &Message, code: ResponseCode) {
+    assert_eq!(
+        response.response_code(),
+        code,
+        "response code of {:?}",
+        answers(response)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RecordType;
+
+    #[tokio::test]
+    async fn queries_test_server() -> Result<()> {
+        let server = TestServer::with_zone(
+            "et.internal",
+            vec![
+                record(RecordType::A, "www", "10.0.0.1")?,
+                record(RecordType::A, "www", "10.0.0.2")?,
+            ],
+        )
+        .await?;
+
+        let response = server.query("www.et.internal", rr::RecordType::A).await?;
+        assert_answers(&response, &["10.0.0.2", "10.0.0.1"]);
+        let response = server
+            .query_tcp("www.et.internal", rr::RecordType::A)
+            .await?;
+        assert_answers(&response, &["10.0.0.1", "10.0.0.2"]);
+        assert_eq!(answers(&response)[0], "www.et.internal. 60 IN A 10.0.0.1");
+        let response = server.query("nope.et.internal", rr::RecordType::A).await?;
+        assert_rcode(&response, ResponseCode::NXDomain);
+
+        assert_eq!(server.shutdown().await?, 0);
+        Ok(())
+    }
+}
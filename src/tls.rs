@@ -0,0 +1,294 @@
+use anyhow::{anyhow, Context, Result};
+use hickory_proto::rr::dnssec::DigestType;
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{
+    Certificate, ClientConfig, OwnedTrustAnchor, PrivateKey, RootCertStore, ServerConfig,
+};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+/// Serves the certificate loaded from a pair of PEM files, picking up
+/// changes to the files while the server is running.
+pub(crate) struct ReloadingCertResolver {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    current: RwLock<(Arc<CertifiedKey>, Option<SystemTime>)>,
+}
+
+impl ReloadingCertResolver {
+    pub(crate) fn new(cert_path: &Path, key_path: &Path) -> Result<Self> {
+        let key = load_certified_key(cert_path, key_path)?;
+        Ok(Self {
+            cert_path: cert_path.to_path_buf(),
+            key_path: key_path.to_path_buf(),
+            current: RwLock::new((Arc::new(key), modified(cert_path, key_path))),
+        })
+    }
+
+    pub(crate) fn server_config(self: &Arc<Self>, alpn: &[&[u8]]) -> ServerConfig {
+        let mut config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(self.clone());
+        config.alpn_protocols = alpn.iter().map(|p| p.to_vec()).collect();
+        config
+    }
+
+    /// Reloads the certificate if either file changed since the last load.
+    fn reload(&self) {
+        let modified = modified(&self.cert_path, &self.key_path);
+        if modified.is_none() || modified == self.current.read().unwrap().1 {
+            return;
+        }
+        match load_certified_key(&self.cert_path, &self.key_path) {
+            Ok(key) => {
+                info!("reloaded tls certificate {}", self.cert_path.display());
+                *self.current.write().unwrap() = (Arc::new(key), modified);
+            }
+            Err(e) => warn!("failed to reload tls certificate: {:#}", e),
+        }
+    }
+
+    pub(crate) async fn watch(self: Arc<Self>, interval: Duration, token: CancellationToken) {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => self.reload(),
+                _ = token.cancelled() => break,
+            }
+        }
+    }
+}
+
+impl ResolvesServerCert for ReloadingCertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().0.clone())
+    }
+}
+
+fn modified(cert_path: &Path, key_path: &Path) -> Option<SystemTime> {
+    let cert = cert_path.metadata().and_then(|m| m.modified()).ok()?;
+    let key = key_path.metadata().and_then(|m| m.modified()).ok()?;
+    Some(cert.max(key))
+}
+
+fn load_certified_key(cert_path: &Path, key_path: &Path) -> Result<CertifiedKey> {
+    let (certs, key) = load_cert_and_key(cert_path, key_path)?;
+    let key = rustls::sign::any_supported_type(&key)
+        .with_context(|| format!("unsupported private key in {}", key_path.display()))?;
+    Ok(CertifiedKey::new(certs, key))
+}
+
+/// Client config validating server certificates against the CA
+/// certificates of `ca_file`, or else the public roots of webpki-roots.
+pub(crate) fn client_config(ca_file: Option<&Path>) -> Result<ClientConfig> {
+    let mut roots = RootCertStore::empty();
+    match ca_file {
+        Some(path) => {
+            let certs = rustls_pemfile::certs(&mut open(path)?)?;
+            if certs.is_empty() {
+                return Err(anyhow!("no certificate found in {}", path.display()));
+            }
+            for cert in certs {
+                roots
+                    .add(&Certificate(cert))
+                    .with_context(|| format!("invalid CA certificate in {}", path.display()))?;
+            }
+        }
+        None => roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                anchor.subject.as_ref(),
+                anchor.subject_public_key_info.as_ref(),
+                anchor.name_constraints.as_ref().map(|c| c.as_ref()),
+            )
+        })),
+    }
+    Ok(ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth())
+}
+
+/// Reads a PEM certificate chain and the first private key of `key_path`.
+pub(crate) fn load_cert_and_key(
+    cert_path: &Path,
+    key_path: &Path,
+) -> Result<(Vec<Certificate>, PrivateKey)> {
+    let certs = rustls_pemfile::certs(&mut open(cert_path)?)?
+        .into_iter()
+        .map(Certificate)
+        .collect::<Vec<_>>();
+    if certs.is_empty() {
+        return Err(anyhow!("no certificate found in {}", cert_path.display()));
+    }
+
+    let mut reader = open(key_path)?;
+    let key = loop {
+        match rustls_pemfile::read_one(&mut reader)? {
+            Some(rustls_pemfile::Item::PKCS8Key(key))
+            | Some(rustls_pemfile::Item::RSAKey(key))
+            | Some(rustls_pemfile::Item::ECKey(key)) => break PrivateKey(key),
+            Some(_) => continue,
+            None => return Err(anyhow!("no private key found in {}", key_path.display())),
+        }
+    };
+    Ok((certs, key))
+}
+
+/// Certificate association data of a TLSA record for the first certificate
+/// of `cert_path`: the certificate or its public key (`selector` 0 or 1),
+/// in full or hashed with SHA-256 or SHA-512 (`matching` 0, 1 or 2).
+pub(crate) fn association_data(cert_path: &Path, selector: u8, matching: u8) -> Result<Vec<u8>> {
+    let cert = first_certificate(cert_path)?;
+    let parsed = parse_certificate(&cert, cert_path)?;
+    let selected = match selector {
+        0 => &cert[..],
+        1 => parsed.public_key().raw,
+        _ => return Err(anyhow!("unsupported TLSA selector {}", selector)),
+    };
+    let digest = match matching {
+        0 => return Ok(selected.to_vec()),
+        1 => DigestType::SHA256,
+        2 => DigestType::SHA512,
+        _ => return Err(anyhow!("unsupported TLSA matching type {}", matching)),
+    };
+    Ok(digest.hash(selected)?.as_ref().to_vec())
+}
+
+/// When the first certificate of `cert_path` expires.
+pub(crate) fn not_after(cert_path: &Path) -> Result<SystemTime> {
+    let cert = first_certificate(cert_path)?;
+    let seconds = parse_certificate(&cert, cert_path)?
+        .validity()
+        .not_after
+        .timestamp();
+    let seconds = u64::try_from(seconds).map_err(|_| anyhow!("notAfter before 1970"))?;
+    Ok(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+/// The DER encoding of the first certificate of the PEM file `cert_path`.
+fn first_certificate(cert_path: &Path) -> Result<Vec<u8>> {
+    rustls_pemfile::certs(&mut open(cert_path)?)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("no certificate found in {}", cert_path.display()))
+}
+
+/// Parses the DER certificate `cert` read from `cert_path`.
+fn parse_certificate<'a>(cert: &'a [u8], cert_path: &Path) -> Result<X509Certificate<'a>> {
+    let (_, cert) = X509Certificate::from_der(cert)
+        .with_context(|| format!("invalid certificate in {}", cert_path.display()))?;
+    Ok(cert)
+}
+
+fn open(path: &Path) -> Result<BufReader<File>> {
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    Ok(BufReader::new(file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_cert(dir: &Path, name: &str) -> Result<(PathBuf, PathBuf)> {
+        let cert = rcgen::generate_simple_self_signed(vec![name.to_string()])?;
+        let cert_path = dir.join("cert.pem");
+        let key_path = dir.join("key.pem");
+        std::fs::write(&cert_path, cert.serialize_pem()?)?;
+        std::fs::write(&key_path, cert.serialize_private_key_pem())?;
+        Ok((cert_path, key_path))
+    }
+
+    #[test]
+    fn reloads_changed_certificate() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (cert_path, key_path) = write_cert(dir.path(), "a.et.internal")?;
+        let resolver = ReloadingCertResolver::new(&cert_path, &key_path)?;
+        let before = resolver.current.read().unwrap().0.cert.clone();
+
+        resolver.reload();
+        assert_eq!(resolver.current.read().unwrap().0.cert, before);
+
+        std::thread::sleep(Duration::from_millis(10));
+        write_cert(dir.path(), "b.et.internal")?;
+        resolver.reload();
+        assert_ne!(resolver.current.read().unwrap().0.cert, before);
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_certificate_on_invalid_reload() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (cert_path, key_path) = write_cert(dir.path(), "a.et.internal")?;
+        let resolver = ReloadingCertResolver::new(&cert_path, &key_path)?;
+        let before = resolver.current.read().unwrap().0.cert.clone();
+
+        std::thread::sleep(Duration::from_millis(10));
+        std::fs::write(&cert_path, "garbage")?;
+        resolver.reload();
+        assert_eq!(resolver.current.read().unwrap().0.cert, before);
+        Ok(())
+    }
+
+    #[test]
+    fn reads_expiry() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cert_path = dir.path().join("cert.pem");
+        for (year, month, day, seconds) in [(2031, 3, 14, 1931212800), (2051, 1, 1, 2556144000)] {
+            let mut params = rcgen::CertificateParams::new(vec!["www.et.internal".to_string()]);
+            params.not_after = rcgen::date_time_ymd(year, month, day);
+            let cert = rcgen::Certificate::from_params(params)?;
+            std::fs::write(&cert_path, cert.serialize_pem()?)?;
+            assert_eq!(
+                not_after(&cert_path)?,
+                SystemTime::UNIX_EPOCH + Duration::from_secs(seconds)
+            );
+        }
+        std::fs::write(&cert_path, "garbage")?;
+        assert!(not_after(&cert_path).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn computes_association_data() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cert = rcgen::generate_simple_self_signed(vec!["www.et.internal".to_string()])?;
+        // each serialization is signed anew, so the PEM file is written from
+        // the DER bytes compared against
+        let der = cert.serialize_der()?;
+        let cert_path = dir.path().join("cert.pem");
+        std::fs::write(
+            &cert_path,
+            format!(
+                "-----BEGIN CERTIFICATE-----\n{}\n-----END CERTIFICATE-----\n",
+                data_encoding::BASE64.encode(&der)
+            ),
+        )?;
+
+        assert_eq!(association_data(&cert_path, 0, 0)?, der);
+        assert_eq!(
+            association_data(&cert_path, 0, 1)?,
+            DigestType::SHA256.hash(&der)?.as_ref()
+        );
+        let spki = cert.get_key_pair().public_key_der();
+        assert_eq!(association_data(&cert_path, 1, 0)?, spki);
+        assert_eq!(
+            association_data(&cert_path, 1, 2)?,
+            DigestType::SHA512.hash(&spki)?.as_ref()
+        );
+
+        assert!(association_data(&cert_path, 2, 1).is_err());
+        assert!(association_data(&cert_path, 1, 3).is_err());
+        std::fs::write(&cert_path, "garbage")?;
+        assert!(association_data(&cert_path, 1, 1).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,172 @@
+use crate::config::GeneralConfig;
+use crate::middleware::resend;
+use anyhow::{bail, Result};
+use hickory_proto::op::Message;
+use hickory_proto::rr::Record;
+use hickory_proto::serialize::binary::BinEncoder;
+use hickory_server::authority::MessageResponse;
+use hickory_server::server::{Protocol, Request, ResponseHandler, ResponseInfo};
+use std::io;
+
+/// Smallest UDP message every client accepts (RFC 1035).
+const MIN_UDP_SIZE: u16 = 512;
+/// Largest UDP message the listeners receive, which is also the largest
+/// response they send.
+const MAX_UDP_SIZE: u16 = 4096;
+
+/// Sizes of the UDP messages of the server.
+#[derive(Clone, Copy)]
+pub(crate) struct UdpSizes {
+    /// Payload size advertised in the EDNS option of the responses.
+    advertised: u16,
+    /// Largest UDP response, whatever the client advertises.
+    max_response: u16,
+}
+
+impl UdpSizes {
+    pub(crate) fn new(config: &GeneralConfig) -> Result<Self> {
+        for (name, size) in [
+            ("edns_buffer_size", config.edns_buffer_size()),
+            ("max_udp_response_size", config.max_udp_response_size()),
+        ] {
+            if !(MIN_UDP_SIZE..=MAX_UDP_SIZE).contains(&size) {
+                bail!(
+                    "{} must be between {} and {}, not {}",
+                    name,
+                    MIN_UDP_SIZE,
+                    MAX_UDP_SIZE,
+                    size
+                );
+            }
+        }
+        Ok(Self {
+            advertised: config.edns_buffer_size(),
+            max_response: config.max_udp_response_size(),
+        })
+    }
+
+    /// Wraps `response_handle` to fit the responses to `request` in the
+    /// size its client accepts.
+    pub(crate) fn limit<R: ResponseHandler>(
+        &self,
+        request: &Request,
+        response_handle: R,
+    ) -> TruncatingResponseHandler<R> {
+        let max_size = match request.protocol() {
+            // 512 without EDNS
+            Protocol::Udp => Some(request.max_payload().min(self.max_response)),
+            _ => None,
+        };
+        TruncatingResponseHandler {
+            inner: response_handle,
+            advertised: self.advertised,
+            max_size,
+        }
+    }
+}
+
+/// Advertises the EDNS payload size of the server in the responses, and
+/// fits those sent over UDP in `max_size`. The additional records are left
+/// out first, then the answer and authority sections with the TC bit set,
+/// so that the client retries over TCP; RRsets are never cut in half.
+#[derive(Clone)]
+pub(crate) struct TruncatingResponseHandler<R> {
+    inner: R,
+    advertised: u16,
+    /// Largest response, `None` when not over UDP.
+    max_size: Option<u16>,
+}
+
+#[async_trait::async_trait]
+impl<R: ResponseHandler> ResponseHandler for TruncatingResponseHandler<R> {
+    async fn send_response<'a>(
+        &mut self,
+        mut response: MessageResponse<
+            '_,
+            'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+        >,
+    ) -> io::Result<ResponseInfo> {
+        if let Some(mut edns) = response.get_edns().clone() {
+            edns.set_max_payload(self.advertised);
+            response.set_edns(edns);
+        }
+        let Some(max_size) = self.max_size else {
+            return self.inner.send_response(response).await;
+        };
+
+        let mut bytes = Vec::with_capacity(512);
+        response.destructive_emit(&mut BinEncoder::new(&mut bytes))?;
+        if bytes.len() > usize::from(max_size) {
+            bytes = truncate(Message::from_vec(&bytes)?, max_size)?;
+        }
+
+        // resent with the EDNS option as a plain record, the listener would
+        // truncate the response again to its payload size otherwise
+        resend(&mut self.inner, &bytes, None).await
+    }
+}
+
+/// `message` encoded in at most `max_size` bytes: without its additional
+/// records, which clients do without (RFC 2181, section 9), or else without
+/// any record and with the TC bit set.
+fn truncate(mut message: Message, max_size: u16) -> io::Result<Vec<u8>> {
+    message.take_additionals();
+    let bytes = message.to_vec()?;
+    if bytes.len() <= usize::from(max_size) {
+        return Ok(bytes);
+    }
+    message.take_answers();
+    message.take_name_servers();
+    message.set_truncated(true);
+    Ok(message.to_vec()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::op::{Edns, Query};
+    use hickory_proto::rr::rdata::{A, TXT};
+    use hickory_proto::rr::{Name, RData, RecordType};
+    use std::str::FromStr;
+
+    fn message(answers: usize, additionals: usize) -> Message {
+        let name = Name::from_str("txt.example.com.").unwrap();
+        let mut message = Message::new();
+        message.add_query(Query::query(name.clone(), RecordType::TXT));
+        let txt = RData::TXT(TXT::new(vec!["x".repeat(200)]));
+        for _ in 0..answers {
+            message.add_answer(Record::from_rdata(name.clone(), 60, txt.clone()));
+        }
+        let a = RData::A(A::new(192, 0, 2, 1));
+        for _ in 0..additionals {
+            message.add_additional(Record::from_rdata(name.clone(), 60, a.clone()));
+        }
+        message.set_edns(Edns::new());
+        message
+    }
+
+    #[test]
+    fn truncates_whole_sections() -> Result<()> {
+        // the additional records are left out without the TC bit
+        let bytes = truncate(message(2, 30), 512)?;
+        assert!(bytes.len() <= 512);
+        let truncated = Message::from_vec(&bytes)?;
+        assert!(!truncated.truncated());
+        assert_eq!(truncated.answers().len(), 2);
+        assert!(truncated.additionals().is_empty());
+        assert!(truncated.extensions().is_some());
+
+        // answers that don't fit are all left out, with the TC bit
+        let bytes = truncate(message(3, 1), 512)?;
+        let truncated = Message::from_vec(&bytes)?;
+        assert!(truncated.truncated());
+        assert!(truncated.answers().is_empty());
+        assert_eq!(truncated.queries().len(), 1);
+        assert!(truncated.extensions().is_some());
+        Ok(())
+    }
+}
@@ -0,0 +1,231 @@
+use crate::config::TsigKeyConfig;
+use crate::middleware::resend;
+use anyhow::{anyhow, bail, Context, Result};
+use base64::Engine;
+use hickory_proto::op::message::emit_message_parts;
+use hickory_proto::op::Header;
+use hickory_proto::rr::dnssec::rdata::tsig::{make_tsig_record, TsigAlgorithm, TSIG};
+use hickory_proto::rr::dnssec::tsig::TSigner;
+use hickory_proto::rr::{LowerName, Record, RecordType};
+use hickory_proto::serialize::binary::{BinDecodable, BinDecoder, BinEncoder};
+use hickory_server::authority::MessageResponse;
+use hickory_server::server::{Request, ResponseHandler, ResponseInfo};
+use std::collections::HashMap;
+use std::io;
+use std::iter;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Allowed difference between the clocks of the signer and the verifier.
+const FUDGE: u16 = 300;
+
+/// The configured TSIG keys by name.
+#[derive(Default)]
+pub(crate) struct Keyring {
+    signers: HashMap<LowerName, TSigner>,
+}
+
+impl Keyring {
+    pub(crate) fn new(keys: &[TsigKeyConfig]) -> Result<Self> {
+        let mut signers = HashMap::new();
+        for key in keys {
+            let signer = signer(key).with_context(|| format!("invalid TSIG key {}", key.name()))?;
+            if signers
+                .insert(LowerName::from(signer.signer_name()), signer)
+                .is_some()
+            {
+                bail!("duplicate TSIG key {}", key.name());
+            }
+        }
+        Ok(Self { signers })
+    }
+
+    pub(crate) fn get(&self, name: &LowerName) -> Option<&TSigner> {
+        self.signers.get(name)
+    }
+
+    /// Verifies the TSIG record of `request`, returns `None` for unsigned
+    /// requests.
+    pub(crate) fn verify(&self, request: &Request) -> Result<Option<Signed>> {
+        let Some(record) = request
+            .sig0()
+            .iter()
+            .find(|r| r.record_type() == RecordType::TSIG)
+        else {
+            return Ok(None);
+        };
+        let key = LowerName::from(record.name());
+        let signer = self
+            .get(&key)
+            .ok_or_else(|| anyhow!("unknown TSIG key {}", key))?;
+
+        // the request as received: only the query section is kept verbatim,
+        // the records are encoded again
+        let mut bytes = Vec::new();
+        let mut encoder = BinEncoder::new(&mut bytes);
+        emit_message_parts(
+            request.header(),
+            &mut iter::once(request.query().original()),
+            &mut request.answers().iter(),
+            &mut request.name_servers().iter(),
+            &mut request.additionals().iter(),
+            request.edns(),
+            request.sig0(),
+            &mut encoder,
+        )?;
+        let (mac, valid, _) = signer
+            .verify_message_byte(None, &bytes, true)
+            .map_err(|e| anyhow!("bad TSIG signature with key {}: {}", key, e))?;
+        if !valid.contains(&now()) {
+            bail!("TSIG signature with key {} is out of its time window", key);
+        }
+        Ok(Some(Signed {
+            key,
+            signer: signer.clone(),
+            mac,
+        }))
+    }
+}
+
+fn signer(key: &TsigKeyConfig) -> Result<TSigner> {
+    let algorithm = match key.algorithm().to_ascii_lowercase().as_str() {
+        "hmac-sha256" => TsigAlgorithm::HmacSha256,
+        "hmac-sha384" => TsigAlgorithm::HmacSha384,
+        "hmac-sha512" => TsigAlgorithm::HmacSha512,
+        algorithm => bail!("unsupported algorithm {}", algorithm),
+    };
+    let secret = base64::engine::general_purpose::STANDARD
+        .decode(key.secret())
+        .context("secret is not valid base64")?;
+    let name = hickory_proto::rr::Name::from_str(key.name())?;
+    Ok(TSigner::new(secret, algorithm, name, FUDGE)?)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// A request whose TSIG signature was verified.
+pub(crate) struct Signed {
+    key: LowerName,
+    signer: TSigner,
+    mac: Vec<u8>,
+}
+
+impl Signed {
+    /// Name of the key the request is signed with.
+    pub(crate) fn key(&self) -> &LowerName {
+        &self.key
+    }
+
+    /// Wraps `response_handle` to sign every response to the request.
+    pub(crate) fn sign<R: ResponseHandler>(&self, response_handle: R) -> SigningResponseHandler<R> {
+        SigningResponseHandler {
+            inner: response_handle,
+            signer: self.signer.clone(),
+            previous: Arc::new(Mutex::new((self.mac.clone(), true))),
+        }
+    }
+}
+
+/// Signs the responses to a TSIG signed request (RFC 8945, section 5.3). A
+/// request may be answered with several messages, as for zone transfers,
+/// each one chaining the MAC of the one before.
+#[derive(Clone)]
+pub(crate) struct SigningResponseHandler<R> {
+    inner: R,
+    signer: TSigner,
+    /// MAC of the request or of the last response, and whether no response
+    /// was sent yet.
+    previous: Arc<Mutex<(Vec<u8>, bool)>>,
+}
+
+impl<R> SigningResponseHandler<R> {
+    /// Appends the MAC input following the message bytes and returns the
+    /// TSIG record data signing them.
+    fn sign(&self, message: &[u8], id: u16) -> io::Result<TSIG> {
+        let mut previous = self.previous.lock().unwrap();
+        let (mac, first) = &mut *previous;
+        let pre_tsig = TSIG::new(
+            self.signer.algorithm().clone(),
+            now(),
+            self.signer.fudge(),
+            Vec::new(),
+            id,
+            0,
+            Vec::new(),
+        );
+        let mut tbs = Vec::with_capacity(mac.len() + message.len() + 64);
+        let mut encoder = BinEncoder::new(&mut tbs);
+        encoder.emit_u16(mac.len() as u16)?;
+        encoder.emit_vec(mac)?;
+        encoder.emit_vec(message)?;
+        if *first {
+            pre_tsig.emit_tsig_for_mac(&mut encoder, self.signer.signer_name())?;
+        } else {
+            encoder.emit_u16((pre_tsig.time() >> 32) as u16)?;
+            encoder.emit_u32(pre_tsig.time() as u32)?;
+            encoder.emit_u16(pre_tsig.fudge())?;
+        }
+        *mac = self.signer.sign(&tbs)?;
+        *first = false;
+        Ok(pre_tsig.set_mac(mac.clone()))
+    }
+}
+
+#[async_trait::async_trait]
+impl<R: ResponseHandler> ResponseHandler for SigningResponseHandler<R> {
+    async fn send_response<'a>(
+        &mut self,
+        response: MessageResponse<
+            '_,
+            'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+        >,
+    ) -> io::Result<ResponseInfo> {
+        // the builder has no room for a TSIG record, so the response is
+        // encoded, signed and decoded again with the TSIG appended last
+        let mut bytes = Vec::with_capacity(512);
+        response.destructive_emit(&mut BinEncoder::new(&mut bytes))?;
+        let header = Header::read(&mut BinDecoder::new(&bytes))?;
+        let tsig = self.sign(&bytes, header.id())?;
+        let tsig = make_tsig_record(self.signer.signer_name().clone(), tsig);
+        resend(&mut self.inner, &bytes, Some(tsig)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TsigKeyConfigBuilder;
+
+    #[test]
+    fn builds_keyring() -> Result<()> {
+        let key = |name: &str, algorithm: &str, secret: &str| {
+            TsigKeyConfigBuilder::default()
+                .name(name)
+                .algorithm(algorithm)
+                .secret(secret)
+                .build()
+        };
+        let keyring = Keyring::new(&[key("transfer", "hmac-sha256", "c2VjcmV0")?])?;
+        let signer = keyring.get(&LowerName::from_str("Transfer.")?).unwrap();
+        assert_eq!(signer.algorithm(), &TsigAlgorithm::HmacSha256);
+        assert_eq!(signer.key(), b"secret");
+
+        assert!(Keyring::new(&[key("transfer", "hmac-md5", "c2VjcmV0")?]).is_err());
+        assert!(Keyring::new(&[key("transfer", "hmac-sha256", "not base64")?]).is_err());
+        assert!(Keyring::new(&[
+            key("transfer", "hmac-sha256", "c2VjcmV0")?,
+            key("transfer", "hmac-sha512", "c2VjcmV0")?,
+        ])
+        .is_err());
+        Ok(())
+    }
+}
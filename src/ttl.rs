@@ -0,0 +1,87 @@
+use anyhow::{bail, Result};
+use hickory_proto::op::Message;
+use hickory_proto::rr::Record;
+use std::time::Duration;
+
+/// Bounds of the TTLs of the records sent out, set with `min_ttl` and
+/// `max_ttl`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TtlLimits {
+    min: u32,
+    max: u32,
+}
+
+impl Default for TtlLimits {
+    fn default() -> Self {
+        Self {
+            min: 0,
+            max: u32::MAX,
+        }
+    }
+}
+
+impl TtlLimits {
+    pub(crate) fn new(min: Option<Duration>, max: Option<Duration>) -> Result<Self> {
+        let seconds = |ttl: Duration| ttl.as_secs().try_into().unwrap_or(u32::MAX);
+        let limits = Self {
+            min: min.map_or(0, seconds),
+            max: max.map_or(u32::MAX, seconds),
+        };
+        if limits.min > limits.max {
+            bail!(
+                "min_ttl of {}s is above max_ttl of {}s",
+                limits.min,
+                limits.max
+            );
+        }
+        Ok(limits)
+    }
+
+    pub(crate) fn is_unbounded(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub(crate) fn clamp(&self, records: &mut [Record]) {
+        if self.is_unbounded() {
+            return;
+        }
+        for record in records {
+            record.set_ttl(record.ttl().clamp(self.min, self.max));
+        }
+    }
+
+    /// Clamps the TTLs of the records of every section of `message`.
+    pub(crate) fn clamp_message(&self, message: &mut Message) {
+        self.clamp(message.answers_mut());
+        self.clamp(message.name_servers_mut());
+        self.clamp(message.additionals_mut());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::rr::rdata::A;
+    use hickory_proto::rr::{Name, RData};
+
+    #[test]
+    fn clamps_ttls() -> Result<()> {
+        let mut records: Vec<Record> = [5, 60, 86400]
+            .into_iter()
+            .map(|ttl| Record::from_rdata(Name::root(), ttl, RData::A(A::new(192, 0, 2, 1))))
+            .collect();
+        let limits = TtlLimits::new(
+            Some(Duration::from_secs(30)),
+            Some(Duration::from_secs(300)),
+        )?;
+        limits.clamp(&mut records);
+        let ttls: Vec<u32> = records.iter().map(Record::ttl).collect();
+        assert_eq!(ttls, [30, 60, 300]);
+
+        assert!(TtlLimits::new(None, None)?.is_unbounded());
+        assert!(
+            TtlLimits::new(Some(Duration::from_secs(60)), Some(Duration::from_secs(30))).is_err()
+        );
+        Ok(())
+    }
+}
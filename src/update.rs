@@ -0,0 +1,314 @@
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr::{DNSClass, LowerName, RData, Record, RecordSet, RecordType, RrKey};
+use hickory_server::authority::{Authority, MessageRequest, UpdateRequest};
+use hickory_server::store::in_memory::InMemoryAuthority;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use tracing::debug;
+
+type Records = BTreeMap<RrKey, Arc<RecordSet>>;
+
+/// Applies the dynamic update `request` (RFC 2136) to `authority`, returns
+/// whether the zone changed. Prerequisites are checked and updates applied
+/// under one lock of the records, so concurrent updates don't interleave.
+pub(crate) async fn apply(
+    authority: &InMemoryAuthority,
+    request: &MessageRequest,
+) -> Result<bool, ResponseCode> {
+    let origin = authority.origin();
+    let zone = request.zone();
+    if zone.query_class() != DNSClass::IN || zone.query_type() != RecordType::SOA {
+        return Err(ResponseCode::FormErr);
+    }
+    prescan(origin, request.updates())?;
+
+    let mut records = authority.records_mut().await;
+    check_prerequisites(&records, origin, request.prerequisites())?;
+    let mut changed = false;
+    for update in request.updates() {
+        changed |= update_records(&mut records, origin, update);
+    }
+    Ok(changed)
+}
+
+/// Rejects malformed updates before anything is changed (RFC 2136, section
+/// 3.4.1.3).
+fn prescan(origin: &LowerName, updates: &[Record]) -> Result<(), ResponseCode> {
+    for update in updates {
+        if !origin.zone_of(&update.name().into()) {
+            return Err(ResponseCode::NotZone);
+        }
+        let meta_type = matches!(
+            update.record_type(),
+            RecordType::AXFR | RecordType::IXFR | RecordType::OPT
+        );
+        let valid = match update.dns_class() {
+            DNSClass::IN => !meta_type && update.record_type() != RecordType::ANY,
+            DNSClass::ANY => !meta_type && update.ttl() == 0 && update.data().is_none(),
+            DNSClass::NONE => {
+                !meta_type && update.ttl() == 0 && update.record_type() != RecordType::ANY
+            }
+            _ => false,
+        };
+        if !valid {
+            return Err(ResponseCode::FormErr);
+        }
+    }
+    Ok(())
+}
+
+/// Checks the prerequisites of an update against the zone (RFC 2136,
+/// section 3.2.5).
+fn check_prerequisites(
+    records: &Records,
+    origin: &LowerName,
+    prerequisites: &[Record],
+) -> Result<(), ResponseCode> {
+    let name_in_use = |name: &LowerName| records.keys().any(|key| key.name == *name);
+    let rrset = |name: &LowerName, record_type| {
+        records
+            .get(&RrKey::new(name.clone(), record_type))
+            .filter(|rrset| !rrset.is_empty())
+    };
+
+    // value dependent prerequisites must match whole rrsets
+    let mut expected: HashMap<RrKey, Vec<&RData>> = HashMap::new();
+    for prerequisite in prerequisites {
+        let name = LowerName::from(prerequisite.name());
+        if prerequisite.ttl() != 0 {
+            return Err(ResponseCode::FormErr);
+        }
+        if !origin.zone_of(&name) {
+            return Err(ResponseCode::NotZone);
+        }
+        match (prerequisite.dns_class(), prerequisite.data()) {
+            (DNSClass::ANY, None) => {
+                if prerequisite.record_type() == RecordType::ANY {
+                    if !name_in_use(&name) {
+                        return Err(ResponseCode::NXDomain);
+                    }
+                } else if rrset(&name, prerequisite.record_type()).is_none() {
+                    return Err(ResponseCode::NXRRSet);
+                }
+            }
+            (DNSClass::NONE, None) => {
+                if prerequisite.record_type() == RecordType::ANY {
+                    if name_in_use(&name) {
+                        return Err(ResponseCode::YXDomain);
+                    }
+                } else if rrset(&name, prerequisite.record_type()).is_some() {
+                    return Err(ResponseCode::YXRRSet);
+                }
+            }
+            (DNSClass::IN, Some(data)) => expected
+                .entry(RrKey::new(name, prerequisite.record_type()))
+                .or_default()
+                .push(data),
+            _ => return Err(ResponseCode::FormErr),
+        }
+    }
+    for (key, expected) in expected {
+        let current: Vec<&RData> = rrset(&key.name, key.record_type)
+            .map(|rrset| {
+                rrset
+                    .records_without_rrsigs()
+                    .filter_map(Record::data)
+                    .collect()
+            })
+            .unwrap_or_default();
+        if !current.iter().all(|data| expected.contains(data))
+            || !expected.iter().all(|data| current.contains(data))
+        {
+            return Err(ResponseCode::NXRRSet);
+        }
+    }
+    Ok(())
+}
+
+/// Applies one record of the update section (RFC 2136, section 3.4.2),
+/// returns whether the zone changed.
+fn update_records(records: &mut Records, origin: &LowerName, update: &Record) -> bool {
+    let name = LowerName::from(update.name());
+    let key = RrKey::new(name.clone(), update.record_type());
+    let at_apex = name == *origin;
+    match update.dns_class() {
+        DNSClass::IN => {
+            let cname = update.record_type() == RecordType::CNAME;
+            let conflicts = records.keys().any(|k| {
+                k.name == name
+                    && (k.record_type == RecordType::CNAME) != cname
+                    && !matches!(k.record_type, RecordType::RRSIG | RecordType::NSEC)
+            });
+            if conflicts {
+                debug!("ignored update of {} conflicting with a CNAME", update);
+                return false;
+            }
+            if update.record_type() == RecordType::SOA && !at_apex {
+                return false;
+            }
+            let rrset = records.entry(key).or_insert_with(|| {
+                Arc::new(RecordSet::new(update.name(), update.record_type(), 0))
+            });
+            Arc::make_mut(rrset).insert(update.clone(), 0)
+        }
+        DNSClass::ANY if update.record_type() == RecordType::ANY => {
+            let before = records.len();
+            records.retain(|k, _| {
+                k.name != name
+                    || (at_apex && matches!(k.record_type, RecordType::SOA | RecordType::NS))
+            });
+            records.len() != before
+        }
+        DNSClass::ANY => {
+            if at_apex && matches!(update.record_type(), RecordType::SOA | RecordType::NS) {
+                return false;
+            }
+            records.remove(&key).is_some()
+        }
+        DNSClass::NONE => {
+            // the SOA and the last NS record are kept by `RecordSet::remove`
+            let Some(rrset) = records.get_mut(&key) else {
+                return false;
+            };
+            let removed = Arc::make_mut(rrset).remove(update, 0);
+            if rrset.is_empty() {
+                records.remove(&key);
+            }
+            removed
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::rr::Name;
+    use std::str::FromStr;
+
+    fn a(name: &str, address: &str) -> Record {
+        Record::from_rdata(
+            Name::from_str(name).unwrap(),
+            60,
+            RData::A(address.parse().unwrap()),
+        )
+    }
+
+    fn with_class(mut record: Record, class: DNSClass) -> Record {
+        record.set_dns_class(class).set_ttl(0);
+        record
+    }
+
+    fn empty(name: &str, record_type: RecordType, class: DNSClass) -> Record {
+        let mut record = Record::with(Name::from_str(name).unwrap(), record_type, 0);
+        record.set_dns_class(class);
+        record
+    }
+
+    fn zone() -> Records {
+        let mut records = Records::new();
+        for record in [
+            a("www.et.internal.", "10.0.0.1"),
+            a("www.et.internal.", "10.0.0.2"),
+        ] {
+            let key = RrKey::new(record.name().into(), record.record_type());
+            let rrset = records
+                .entry(key)
+                .or_insert_with(|| Arc::new(RecordSet::new(record.name(), RecordType::A, 0)));
+            Arc::make_mut(rrset).insert(record, 0);
+        }
+        records
+    }
+
+    #[test]
+    fn checks_prerequisites() {
+        let origin = LowerName::from_str("et.internal.").unwrap();
+        let records = zone();
+        let check =
+            |prerequisites: &[Record]| check_prerequisites(&records, &origin, prerequisites);
+
+        assert_eq!(
+            check(&[empty("www.et.internal.", RecordType::ANY, DNSClass::ANY)]),
+            Ok(())
+        );
+        assert_eq!(
+            check(&[empty("api.et.internal.", RecordType::ANY, DNSClass::ANY)]),
+            Err(ResponseCode::NXDomain)
+        );
+        assert_eq!(
+            check(&[empty("www.et.internal.", RecordType::AAAA, DNSClass::ANY)]),
+            Err(ResponseCode::NXRRSet)
+        );
+        assert_eq!(
+            check(&[empty("www.et.internal.", RecordType::ANY, DNSClass::NONE)]),
+            Err(ResponseCode::YXDomain)
+        );
+        assert_eq!(
+            check(&[empty("www.et.internal.", RecordType::A, DNSClass::NONE)]),
+            Err(ResponseCode::YXRRSet)
+        );
+        assert_eq!(
+            check(&[empty("www.et.top.", RecordType::ANY, DNSClass::ANY)]),
+            Err(ResponseCode::NotZone)
+        );
+
+        let www = |address| {
+            let mut record = a("www.et.internal.", address);
+            record.set_ttl(0);
+            record
+        };
+        assert_eq!(check(&[www("10.0.0.1"), www("10.0.0.2")]), Ok(()));
+        assert_eq!(check(&[www("10.0.0.1")]), Err(ResponseCode::NXRRSet));
+    }
+
+    #[test]
+    fn applies_updates() {
+        let origin = LowerName::from_str("et.internal.").unwrap();
+        let mut records = zone();
+        let www = RrKey::new(
+            LowerName::from_str("www.et.internal.").unwrap(),
+            RecordType::A,
+        );
+
+        assert_eq!(
+            prescan(
+                &origin,
+                &[empty("www.et.internal.", RecordType::A, DNSClass::CH)]
+            ),
+            Err(ResponseCode::FormErr)
+        );
+        assert_eq!(
+            prescan(&origin, &[a("www.et.top.", "10.0.0.1")]),
+            Err(ResponseCode::NotZone)
+        );
+
+        assert!(update_records(
+            &mut records,
+            &origin,
+            &a("www.et.internal.", "10.0.0.3")
+        ));
+        assert!(!update_records(
+            &mut records,
+            &origin,
+            &a("www.et.internal.", "10.0.0.3")
+        ));
+        assert_eq!(records[&www].records_without_rrsigs().count(), 3);
+
+        let delete = with_class(a("www.et.internal.", "10.0.0.1"), DNSClass::NONE);
+        assert!(update_records(&mut records, &origin, &delete));
+        assert_eq!(records[&www].records_without_rrsigs().count(), 2);
+
+        let cname = Record::from_rdata(
+            Name::from_str("www.et.internal.").unwrap(),
+            60,
+            RData::CNAME(hickory_proto::rr::rdata::CNAME(
+                Name::from_str("api.et.internal.").unwrap(),
+            )),
+        );
+        assert!(!update_records(&mut records, &origin, &cname));
+
+        let delete = empty("www.et.internal.", RecordType::A, DNSClass::ANY);
+        assert!(update_records(&mut records, &origin, &delete));
+        assert!(records.is_empty());
+    }
+}
@@ -0,0 +1,245 @@
+//! Health of the upstream resolvers: their round-trip times and failures,
+//! which order the upstreams tried for a query.
+
+use crate::config::{ForwardConfig, Upstream, UpstreamPolicy};
+use rand::seq::SliceRandom;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Counters of an upstream resolver.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpstreamStats {
+    pub upstream: Upstream,
+    pub queries: u64,
+    /// Queries timed out or otherwise unanswered.
+    pub errors: u64,
+    /// SERVFAIL, REFUSED and NOTIMP answers.
+    pub servfails: u64,
+    /// Smoothed round-trip time, an error counting as the forward timeout.
+    pub rtt: Option<Duration>,
+    pub quarantined: bool,
+}
+
+#[derive(Default)]
+struct Health {
+    queries: u64,
+    errors: u64,
+    servfails: u64,
+    rtt: Option<Duration>,
+    /// Failures since the last answer.
+    failures: u32,
+    quarantined_until: Option<Instant>,
+}
+
+impl Health {
+    fn quarantined(&self, now: Instant) -> bool {
+        self.quarantined_until.is_some_and(|until| until > now)
+    }
+
+    /// Smooths the round-trip time as TCP does (RFC 6298), giving each
+    /// sample an eighth of the weight.
+    fn measure(&mut self, rtt: Duration) {
+        self.rtt = Some(match self.rtt {
+            Some(smoothed) => (smoothed * 7 + rtt) / 8,
+            None => rtt,
+        });
+    }
+}
+
+pub(crate) struct UpstreamHealth {
+    policy: UpstreamPolicy,
+    max_failures: u32,
+    quarantine: Duration,
+    timeout: Duration,
+    /// By upstream, in the order of the config.
+    health: Mutex<Vec<(Upstream, Health)>>,
+}
+
+impl UpstreamHealth {
+    pub(crate) fn new<'a>(
+        config: &ForwardConfig,
+        upstreams: impl IntoIterator<Item = &'a Upstream>,
+    ) -> Self {
+        let mut health: Vec<(Upstream, Health)> = Vec::new();
+        for upstream in upstreams {
+            if !health.iter().any(|(known, _)| known == upstream) {
+                health.push((upstream.clone(), Health::default()));
+            }
+        }
+        Self {
+            policy: config.policy(),
+            max_failures: config.max_failures(),
+            quarantine: config.quarantine(),
+            timeout: config.timeout(),
+            health: Mutex::new(health),
+        }
+    }
+
+    /// The order in which `upstreams` are tried, those in quarantine last.
+    pub(crate) fn order<'a>(&self, upstreams: &'a [Upstream]) -> Vec<&'a Upstream> {
+        let now = Instant::now();
+        let health = self.health.lock().unwrap();
+        let find = |upstream: &Upstream| {
+            health
+                .iter()
+                .find(|(known, _)| known == upstream)
+                .map(|(_, health)| health)
+        };
+        let mut order: Vec<&Upstream> = upstreams.iter().collect();
+        match self.policy {
+            UpstreamPolicy::Sequential => {}
+            // unmeasured upstreams first, for them to be measured
+            UpstreamPolicy::Fastest => {
+                order.sort_by_key(|upstream| find(upstream).and_then(|h| h.rtt).unwrap_or_default())
+            }
+            UpstreamPolicy::Random => order.shuffle(&mut rand::thread_rng()),
+        }
+        order.sort_by_key(|upstream| find(upstream).is_some_and(|h| h.quarantined(now)));
+        order
+    }
+
+    /// Records an answer of `upstream` after `rtt`, a failure if `servfail`.
+    pub(crate) fn answered(&self, upstream: &Upstream, rtt: Duration, servfail: bool) {
+        self.update(upstream, |health| {
+            health.measure(rtt);
+            if servfail {
+                health.servfails += 1;
+            }
+            !servfail
+        });
+    }
+
+    /// Records a query `upstream` didn't answer.
+    pub(crate) fn failed(&self, upstream: &Upstream) {
+        let timeout = self.timeout;
+        self.update(upstream, |health| {
+            health.errors += 1;
+            health.measure(timeout);
+            false
+        });
+    }
+
+    /// Counts a query of `upstream` updated by `update`, which returns
+    /// whether it succeeded, and quarantines it after too many failures.
+    fn update(&self, upstream: &Upstream, update: impl FnOnce(&mut Health) -> bool) {
+        let mut health = self.health.lock().unwrap();
+        let Some((_, health)) = health.iter_mut().find(|(known, _)| known == upstream) else {
+            return;
+        };
+        health.queries += 1;
+        if update(health) {
+            health.failures = 0;
+            health.quarantined_until = None;
+            return;
+        }
+        health.failures = health.failures.saturating_add(1);
+        if self.max_failures > 0 && health.failures >= self.max_failures {
+            let now = Instant::now();
+            if !health.quarantined(now) {
+                warn!(
+                    "quarantining upstream {} for {:?} after {} failures",
+                    upstream, self.quarantine, health.failures
+                );
+            }
+            health.quarantined_until = Some(now + self.quarantine);
+        }
+    }
+
+    pub(crate) fn stats(&self) -> Vec<UpstreamStats> {
+        let now = Instant::now();
+        self.health
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(upstream, health)| UpstreamStats {
+                upstream: upstream.clone(),
+                queries: health.queries,
+                errors: health.errors,
+                servfails: health.servfails,
+                rtt: health.rtt,
+                quarantined: health.quarantined(now),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ForwardConfigBuilder;
+
+    fn upstreams() -> Vec<Upstream> {
+        ["10.0.0.1", "tls://10.0.0.2", "https://10.0.0.3/dns-query"]
+            .iter()
+            .map(|a| a.parse().unwrap())
+            .collect()
+    }
+
+    fn health(policy: UpstreamPolicy) -> UpstreamHealth {
+        let config = ForwardConfigBuilder::default()
+            .policy(policy)
+            .max_failures(2)
+            .quarantine(Duration::from_millis(100))
+            .build()
+            .unwrap();
+        UpstreamHealth::new(&config, &upstreams())
+    }
+
+    #[test]
+    fn quarantines_failing_upstreams() {
+        let upstreams = upstreams();
+        let [first, second, third] = &upstreams[..] else {
+            unreachable!()
+        };
+        let health = health(UpstreamPolicy::Sequential);
+        assert_eq!(health.order(&upstreams), [first, second, third]);
+
+        health.failed(first);
+        assert_eq!(health.order(&upstreams), [first, second, third]);
+        health.answered(first, Duration::from_millis(10), true);
+        assert_eq!(health.order(&upstreams), [second, third, first]);
+        let stats = health.stats();
+        assert_eq!(stats[0].queries, 2);
+        assert_eq!(stats[0].errors, 1);
+        assert_eq!(stats[0].servfails, 1);
+        assert!(stats[0].quarantined);
+        assert!(!stats[1].quarantined);
+
+        // back after the quarantine, and again after a single failure
+        std::thread::sleep(Duration::from_millis(150));
+        assert_eq!(health.order(&upstreams), [first, second, third]);
+        health.failed(first);
+        assert_eq!(health.order(&upstreams), [second, third, first]);
+
+        // an answer ends the quarantine
+        health.answered(first, Duration::from_millis(10), false);
+        assert_eq!(health.order(&upstreams), [first, second, third]);
+        assert!(!health.stats()[0].quarantined);
+    }
+
+    #[test]
+    fn orders_by_policy() {
+        let upstreams = upstreams();
+        let [first, second, third] = &upstreams[..] else {
+            unreachable!()
+        };
+        let health = health(UpstreamPolicy::Fastest);
+        health.answered(first, Duration::from_millis(40), false);
+        health.answered(second, Duration::from_millis(10), false);
+        // unmeasured first
+        assert_eq!(health.order(&upstreams), [third, second, first]);
+        health.answered(third, Duration::from_millis(20), false);
+        assert_eq!(health.order(&upstreams), [second, third, first]);
+        // smoothed, a slow answer doesn't outweigh the history
+        health.answered(second, Duration::from_millis(50), false);
+        assert_eq!(health.stats()[1].rtt, Some(Duration::from_millis(15)));
+        assert_eq!(health.order(&upstreams), [second, third, first]);
+
+        let health = self::health(UpstreamPolicy::Random);
+        let orders: std::collections::HashSet<_> =
+            (0..100).map(|_| health.order(&upstreams)).collect();
+        assert!(orders.len() > 1);
+        assert!(orders.iter().all(|order| order.len() == 3));
+    }
+}
@@ -0,0 +1,602 @@
+use crate::config::{DnssecValidation, ForwardConfig};
+use crate::dnssec::unix_time;
+use crate::ede;
+use crate::forward::Forwarder;
+use anyhow::Result;
+use data_encoding::BASE32_DNSSEC;
+use futures_util::future::{BoxFuture, FutureExt};
+use hickory_proto::op::{Message, Query, ResponseCode};
+use hickory_proto::rr::dnssec::rdata::{DNSSECRData, DNSKEY, DS, NSEC3, RRSIG};
+use hickory_proto::rr::dnssec::Verifier;
+use hickory_proto::rr::rdata::opt::EdnsOption;
+use hickory_proto::rr::{DNSClass, Name, RData, Record, RecordType};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// Longest chain of zones followed from an answer to a trust anchor.
+const MAX_DEPTH: usize = 16;
+
+/// Upper bound of how long validated keys are trusted without asking again.
+const MAX_KEY_TTL: Duration = Duration::from_secs(3600);
+
+/// Outcome of validating an upstream answer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Security {
+    /// Every RRset of the answer chains to a trust anchor.
+    Secure,
+    /// Part of the answer comes from zones proven to be unsigned.
+    Insecure,
+    /// Signatures or proofs are missing, expired or don't verify.
+    Bogus(Bogus),
+}
+
+/// Why an answer is bogus, reported to clients as an Extended DNS Error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Bogus {
+    code: u16,
+    reason: String,
+}
+
+impl Bogus {
+    fn new(code: u16, reason: impl Into<String>) -> Self {
+        Self {
+            code,
+            reason: reason.into(),
+        }
+    }
+
+    /// The Extended DNS Error option describing this.
+    pub(crate) fn ede(&self) -> EdnsOption {
+        ede::option(self.code, &self.reason)
+    }
+}
+
+impl fmt::Display for Bogus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.reason)
+    }
+}
+
+/// Outcome of a validation step, where `true` means secure and `false`
+/// means proven unsigned.
+type Verdict<T> = std::result::Result<T, Bogus>;
+
+/// The validated DNSKEYs of a zone, `None` for a zone proven unsigned.
+type ZoneKeys = Option<Arc<Vec<DNSKEY>>>;
+
+/// Validates upstream answers by following their signatures through the
+/// DNSKEY and DS records of each zone up to a trust anchor (RFC 4035,
+/// section 5). Lookups along the chain go through the forwarder, and thus
+/// its cache.
+pub(crate) struct Validator {
+    mode: DnssecValidation,
+    anchors: Vec<Record>,
+    keys: Mutex<HashMap<Name, (ZoneKeys, Instant)>>,
+}
+
+impl Validator {
+    /// The validator configured for forwarding, `None` when validation is
+    /// off.
+    pub(crate) fn new(config: &ForwardConfig) -> Result<Option<Self>> {
+        if config.dnssec_validation() == DnssecValidation::Off {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            mode: config.dnssec_validation(),
+            anchors: config.trust_anchor_records()?,
+            keys: Mutex::new(HashMap::new()),
+        }))
+    }
+
+    /// Whether bogus answers are refused rather than passed on.
+    pub(crate) fn strict(&self) -> bool {
+        self.mode == DnssecValidation::Strict
+    }
+
+    /// Validates `response`, the upstream answer to `query`.
+    pub(crate) async fn validate(
+        &self,
+        forwarder: &Forwarder,
+        query: &Query,
+        response: &Message,
+    ) -> Security {
+        match self.check(forwarder, query, response).await {
+            Ok(true) => Security::Secure,
+            Ok(false) => Security::Insecure,
+            Err(bogus) => Security::Bogus(bogus),
+        }
+    }
+
+    async fn check(
+        &self,
+        forwarder: &Forwarder,
+        query: &Query,
+        response: &Message,
+    ) -> Verdict<bool> {
+        if !matches!(
+            response.response_code(),
+            ResponseCode::NoError | ResponseCode::NXDomain
+        ) {
+            return Ok(false);
+        }
+        let negative = response
+            .answers()
+            .iter()
+            .all(|r| r.record_type() == RecordType::RRSIG);
+        let section = if negative {
+            response.name_servers()
+        } else {
+            response.answers()
+        };
+        let keys = rrset_keys(section);
+        if keys.is_empty() {
+            return if self.signed(forwarder, query.name(), 0).await? {
+                Err(Bogus::new(
+                    ede::NSEC_MISSING,
+                    format!("no denial of {} in a signed zone", query),
+                ))
+            } else {
+                Ok(false)
+            };
+        }
+        let mut secure = true;
+        for (name, record_type) in keys {
+            let (records, rrsigs) = rrset(section, &name, record_type);
+            secure &= self
+                .verify(forwarder, &name, &records, &rrsigs, &name, 0)
+                .await?;
+        }
+        if secure && negative && !denies(query, response) {
+            return Err(Bogus::new(
+                ede::NSEC_MISSING,
+                format!("no valid denial of {}", query),
+            ));
+        }
+        Ok(secure)
+    }
+
+    /// Verifies the RRset `records` of `name` with one of `rrsigs`. Unsigned
+    /// RRsets are insecure if the zone enclosing `zone_of` is unsigned.
+    fn verify<'a>(
+        &'a self,
+        forwarder: &'a Forwarder,
+        name: &'a Name,
+        records: &'a [Record],
+        rrsigs: &'a [RRSIG],
+        zone_of: &'a Name,
+        depth: usize,
+    ) -> BoxFuture<'a, Verdict<bool>> {
+        async move {
+            let record_type = records
+                .first()
+                .map_or(RecordType::RRSIG, Record::record_type);
+            if rrsigs.is_empty() {
+                return if self.signed(forwarder, zone_of, depth).await? {
+                    Err(Bogus::new(
+                        ede::RRSIGS_MISSING,
+                        format!("no signature of {} {}", name, record_type),
+                    ))
+                } else {
+                    Ok(false)
+                };
+            }
+            let mut bogus = Bogus::new(
+                ede::DNSSEC_BOGUS,
+                format!("no valid signature of {} {}", name, record_type),
+            );
+            for rrsig in rrsigs {
+                let signer = rrsig.signer_name();
+                if !signer.zone_of(name) {
+                    continue;
+                }
+                let Some(keys) = self.zone_keys(forwarder, signer, depth + 1).await? else {
+                    return Ok(false);
+                };
+                if let Err(e) = current(rrsig) {
+                    bogus = e;
+                } else if verifies(&keys, name, rrsig, records) {
+                    return Ok(true);
+                }
+            }
+            Err(bogus)
+        }
+        .boxed()
+    }
+
+    /// Whether the zone enclosing `name` is signed.
+    fn signed<'a>(
+        &'a self,
+        forwarder: &'a Forwarder,
+        name: &'a Name,
+        depth: usize,
+    ) -> BoxFuture<'a, Verdict<bool>> {
+        async move {
+            let response = lookup(forwarder, name, RecordType::SOA, ede::DNSSEC_BOGUS).await?;
+            let zone = response
+                .answers()
+                .iter()
+                .chain(response.name_servers())
+                .find(|r| r.record_type() == RecordType::SOA)
+                .map(|r| r.name().clone())
+                .ok_or_else(|| {
+                    Bogus::new(ede::DNSSEC_BOGUS, format!("no zone encloses {}", name))
+                })?;
+            Ok(self.zone_keys(forwarder, &zone, depth + 1).await?.is_some())
+        }
+        .boxed()
+    }
+
+    /// The DNSKEYs of `zone`, once they chain to a trust anchor.
+    fn zone_keys<'a>(
+        &'a self,
+        forwarder: &'a Forwarder,
+        zone: &'a Name,
+        depth: usize,
+    ) -> BoxFuture<'a, Verdict<ZoneKeys>> {
+        async move {
+            if let Some(keys) = self.cached(zone) {
+                return Ok(keys);
+            }
+            if depth > MAX_DEPTH {
+                return Err(Bogus::new(
+                    ede::DNSSEC_BOGUS,
+                    format!("chain of trust of {} is too long", zone),
+                ));
+            }
+            // zones outside every trust anchor can't be validated
+            if !self.anchors.iter().any(|a| a.name().zone_of(zone)) {
+                return Ok(None);
+            }
+            let anchors: Vec<&DNSSECRData> = self
+                .anchors
+                .iter()
+                .filter(|a| a.name() == zone)
+                .filter_map(|a| a.data()?.as_dnssec())
+                .collect();
+            let ds = if anchors.is_empty() {
+                match self.ds(forwarder, zone, depth).await? {
+                    Some(ds) => ds,
+                    None => {
+                        self.cache(zone, None, MAX_KEY_TTL);
+                        return Ok(None);
+                    }
+                }
+            } else {
+                anchors.iter().filter_map(|a| a.as_ds()).cloned().collect()
+            };
+
+            let response = lookup(forwarder, zone, RecordType::DNSKEY, ede::DNSKEY_MISSING).await?;
+            let (records, rrsigs) = rrset(response.answers(), zone, RecordType::DNSKEY);
+            let dnskeys: Vec<DNSKEY> = records
+                .iter()
+                .filter_map(|r| r.data()?.as_dnssec()?.as_dnskey())
+                .cloned()
+                .collect();
+            let trusted: Vec<DNSKEY> = dnskeys
+                .iter()
+                .filter(|key| key.zone_key() && !key.revoke())
+                .filter(|key| {
+                    anchors.iter().any(|a| a.as_dnskey() == Some(key))
+                        || ds.iter().any(|ds| ds.covers(zone, key).unwrap_or(false))
+                })
+                .cloned()
+                .collect();
+            if trusted.is_empty() {
+                return Err(Bogus::new(
+                    ede::DNSKEY_MISSING,
+                    format!("no DNSKEY of {} matches its trust anchor or DS", zone),
+                ));
+            }
+            let mut bogus = Bogus::new(
+                ede::DNSSEC_BOGUS,
+                format!("DNSKEY of {} is not signed by a trusted key", zone),
+            );
+            for rrsig in &rrsigs {
+                if let Err(e) = current(rrsig) {
+                    bogus = e;
+                } else if rrsig.signer_name() == zone && verifies(&trusted, zone, rrsig, &records) {
+                    let ttl = records.iter().map(Record::ttl).min().unwrap_or_default();
+                    let keys = Some(Arc::new(dnskeys));
+                    self.cache(
+                        zone,
+                        keys.clone(),
+                        MAX_KEY_TTL.min(Duration::from_secs(ttl.into())),
+                    );
+                    return Ok(keys);
+                }
+            }
+            Err(bogus)
+        }
+        .boxed()
+    }
+
+    /// The validated DS records of `zone`, `None` when its parent proves it
+    /// unsigned.
+    async fn ds(
+        &self,
+        forwarder: &Forwarder,
+        zone: &Name,
+        depth: usize,
+    ) -> Verdict<Option<Vec<DS>>> {
+        if zone.is_root() {
+            return Err(Bogus::new(
+                ede::DNSKEY_MISSING,
+                "no trust anchor for the root zone",
+            ));
+        }
+        let response = lookup(forwarder, zone, RecordType::DS, ede::DNSSEC_BOGUS).await?;
+        let (records, rrsigs) = rrset(response.answers(), zone, RecordType::DS);
+        if !records.is_empty() {
+            let secure = self
+                .verify(forwarder, zone, &records, &rrsigs, &zone.base_name(), depth)
+                .await?;
+            let ds = records
+                .iter()
+                .filter_map(|r| r.data()?.as_dnssec()?.as_ds())
+                .cloned()
+                .collect();
+            return Ok(secure.then_some(ds));
+        }
+
+        let authority = response.name_servers();
+        let parent = authority
+            .iter()
+            .find(|r| r.record_type() == RecordType::SOA)
+            .map(|r| r.name().clone())
+            .ok_or_else(|| {
+                Bogus::new(
+                    ede::NSEC_MISSING,
+                    format!("no denial of the DS of {}", zone),
+                )
+            })?;
+        for (name, record_type) in rrset_keys(authority) {
+            let (records, rrsigs) = rrset(authority, &name, record_type);
+            if !self
+                .verify(forwarder, &name, &records, &rrsigs, &parent, depth)
+                .await?
+            {
+                return Ok(None);
+            }
+        }
+        if denies_type(authority, zone, RecordType::DS) {
+            debug!("{} is an unsigned delegation", zone);
+            Ok(None)
+        } else {
+            Err(Bogus::new(
+                ede::NSEC_MISSING,
+                format!("no valid denial of the DS of {}", zone),
+            ))
+        }
+    }
+
+    fn cached(&self, zone: &Name) -> Option<ZoneKeys> {
+        let mut keys = self.keys.lock().unwrap();
+        match keys.get(zone) {
+            Some((zone_keys, expires)) if *expires > Instant::now() => Some(zone_keys.clone()),
+            Some(_) => {
+                keys.remove(zone);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn cache(&self, zone: &Name, zone_keys: ZoneKeys, ttl: Duration) {
+        self.keys
+            .lock()
+            .unwrap()
+            .insert(zone.clone(), (zone_keys, Instant::now() + ttl));
+    }
+}
+
+async fn lookup(
+    forwarder: &Forwarder,
+    name: &Name,
+    record_type: RecordType,
+    code: u16,
+) -> Verdict<Message> {
+    forwarder
+        .forward(&Query::query(name.clone(), record_type))
+        .await
+        .map_err(|e| {
+            Bogus::new(
+                code,
+                format!("failed to look up {} {}: {:#}", name, record_type, e),
+            )
+        })
+}
+
+/// The distinct RRsets of `records`, leaving signatures out.
+fn rrset_keys(records: &[Record]) -> Vec<(Name, RecordType)> {
+    let mut keys: Vec<(Name, RecordType)> = Vec::new();
+    for record in records {
+        let key = (record.name().clone(), record.record_type());
+        if key.1 != RecordType::RRSIG && !keys.contains(&key) {
+            keys.push(key);
+        }
+    }
+    keys
+}
+
+/// The records of the `record_type` RRset of `name` and their signatures.
+fn rrset(records: &[Record], name: &Name, record_type: RecordType) -> (Vec<Record>, Vec<RRSIG>) {
+    let rrset = records
+        .iter()
+        .filter(|r| r.name() == name && r.record_type() == record_type)
+        .cloned()
+        .collect();
+    let rrsigs = records
+        .iter()
+        .filter(|r| r.name() == name)
+        .filter_map(|r| r.data()?.as_dnssec()?.as_rrsig())
+        .filter(|rrsig| rrsig.type_covered() == record_type)
+        .cloned()
+        .collect();
+    (rrset, rrsigs)
+}
+
+/// Fails for signatures outside their validity period.
+fn current(rrsig: &RRSIG) -> Verdict<()> {
+    let now = unix_time();
+    if now > rrsig.sig_expiration() {
+        Err(Bogus::new(ede::SIGNATURE_EXPIRED, "signature expired"))
+    } else if now < rrsig.sig_inception() {
+        Err(Bogus::new(
+            ede::SIGNATURE_NOT_YET_VALID,
+            "signature not yet valid",
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+fn verifies(keys: &[DNSKEY], name: &Name, rrsig: &RRSIG, records: &[Record]) -> bool {
+    keys.iter()
+        .filter(|key| key.algorithm() == rrsig.algorithm())
+        .filter(|key| key.calculate_key_tag().ok() == Some(rrsig.key_tag()))
+        .any(|key| key.verify_rrsig(name, DNSClass::IN, rrsig, records).is_ok())
+}
+
+/// Whether the NSEC or NSEC3 records of a negative `response` deny the name
+/// or type of `query`. Wildcard proofs are not checked.
+fn denies(query: &Query, response: &Message) -> bool {
+    let authority = response.name_servers();
+    if response.response_code() == ResponseCode::NXDomain {
+        denies_name(authority, query.name())
+    } else {
+        denies_type(authority, query.name(), query.query_type())
+    }
+}
+
+fn denies_name(records: &[Record], name: &Name) -> bool {
+    records.iter().any(|r| match r.data() {
+        Some(RData::DNSSEC(DNSSECRData::NSEC(nsec))) => {
+            covers(r.name(), nsec.next_domain_name(), name)
+        }
+        _ => false,
+    }) || nsec3_closest_encloser(records, name).is_some()
+}
+
+fn denies_type(records: &[Record], name: &Name, record_type: RecordType) -> bool {
+    let absent =
+        |types: &[RecordType]| !types.contains(&record_type) && !types.contains(&RecordType::CNAME);
+    records.iter().any(|r| match r.data() {
+        Some(RData::DNSSEC(DNSSECRData::NSEC(nsec))) => {
+            // the name itself, or an empty non-terminal before its children
+            (r.name() == name && absent(nsec.type_bit_maps()))
+                || (covers(r.name(), nsec.next_domain_name(), name)
+                    && name.zone_of(nsec.next_domain_name()))
+        }
+        Some(RData::DNSSEC(DNSSECRData::NSEC3(nsec3))) => {
+            nsec3_hash(nsec3, name).is_some_and(|hash| owner_hash(r.name()) == Some(hash))
+                && absent(nsec3.type_bit_maps())
+        }
+        _ => false,
+    }) || nsec3_closest_encloser(records, name).is_some_and(|opt_out| opt_out)
+}
+
+/// With NSEC3, the closest encloser of `name` proves it doesn't exist when
+/// an NSEC3 covers the next closer name (RFC 5155, section 8.3). Returns
+/// whether that NSEC3 has the opt-out flag.
+fn nsec3_closest_encloser(records: &[Record], name: &Name) -> Option<bool> {
+    let nsec3s: Vec<(Vec<u8>, &NSEC3)> = records
+        .iter()
+        .filter_map(|r| match r.data() {
+            Some(RData::DNSSEC(DNSSECRData::NSEC3(nsec3))) => Some((owner_hash(r.name())?, nsec3)),
+            _ => None,
+        })
+        .collect();
+    let matches = |name: &Name| {
+        nsec3s
+            .iter()
+            .any(|(owner, nsec3)| nsec3_hash(nsec3, name).as_ref() == Some(owner))
+    };
+    let mut closer = name.clone();
+    loop {
+        let encloser = closer.base_name();
+        if matches(&encloser) {
+            return nsec3s.iter().find_map(|(owner, nsec3)| {
+                let hash = nsec3_hash(nsec3, &closer)?;
+                let next = nsec3.next_hashed_owner_name();
+                covers(owner.as_slice(), next, hash.as_slice()).then_some(nsec3.opt_out())
+            });
+        }
+        if encloser.is_root() {
+            return None;
+        }
+        closer = encloser;
+    }
+}
+
+fn nsec3_hash(nsec3: &NSEC3, name: &Name) -> Option<Vec<u8>> {
+    let digest = nsec3
+        .hash_algorithm()
+        .hash(nsec3.salt(), name, nsec3.iterations())
+        .ok()?;
+    Some(digest.as_ref().to_vec())
+}
+
+/// The hash in the first label of an NSEC3 owner name.
+fn owner_hash(owner: &Name) -> Option<Vec<u8>> {
+    BASE32_DNSSEC
+        .decode(&owner.iter().next()?.to_ascii_lowercase())
+        .ok()
+}
+
+/// Whether `target` falls strictly between `owner` and `next`, wrapping
+/// around at the end of the chain.
+fn covers<T: Ord + ?Sized>(owner: &T, next: &T, target: &T) -> bool {
+    if owner < next {
+        owner < target && target < next
+    } else {
+        owner < target || target < next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::rr::dnssec::rdata::NSEC;
+    use std::str::FromStr;
+
+    fn nsec(owner: &str, next: &str, types: &[RecordType]) -> Record {
+        let nsec = NSEC::new_cover_self(Name::from_str(next).unwrap(), types.to_vec());
+        Record::from_rdata(
+            Name::from_str(owner).unwrap(),
+            300,
+            RData::DNSSEC(DNSSECRData::NSEC(nsec)),
+        )
+    }
+
+    #[test]
+    fn checks_nsec_denials() {
+        let records = [
+            nsec("a.example.", "x.c.example.", &[RecordType::A]),
+            nsec("x.c.example.", "d.example.", &[RecordType::A]),
+            nsec("d.example.", "a.example.", &[RecordType::NS]),
+        ];
+        let name = |name: &str| Name::from_str(name).unwrap();
+        assert!(denies_name(&records, &name("b.example.")));
+        assert!(denies_name(&records, &name("e.example.")));
+        assert!(!denies_name(&records, &name("a.example.")));
+        assert!(denies_type(&records, &name("a.example."), RecordType::TXT));
+        assert!(!denies_type(&records, &name("a.example."), RecordType::A));
+        // no DS at an unsigned delegation
+        assert!(denies_type(&records, &name("d.example."), RecordType::DS));
+        // an empty non-terminal has no records, other covered names don't exist
+        assert!(denies_type(&records, &name("c.example."), RecordType::A));
+        assert!(!denies_type(&records, &name("b.example."), RecordType::A));
+    }
+
+    #[test]
+    fn reports_extended_errors() {
+        let bogus = Bogus::new(ede::RRSIGS_MISSING, "no signature");
+        assert_eq!(
+            bogus.ede(),
+            EdnsOption::Unknown(15, b"\x00\x0ano signature".to_vec())
+        );
+    }
+}
@@ -0,0 +1,350 @@
+use crate::acl::Acl;
+use crate::config;
+use crate::tsig::Keyring;
+use crate::zones::{ZoneSet, ZoneSetSnapshot};
+use anyhow::{bail, Context, Result};
+use hickory_proto::rr::LowerName;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// A split-horizon view: its own zones, served to the clients it matches.
+struct View {
+    name: String,
+    match_clients: Vec<String>,
+    clients: Acl,
+    zones: Arc<ZoneSet>,
+}
+
+/// The zones served and their records, runtime changes included, as taken
+/// by [`Server::snapshot`](crate::Server::snapshot). It serializes with
+/// serde, e.g. to be saved as JSON and restored after a crash.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CatalogSnapshot {
+    default: ZoneSetSnapshot,
+    views: BTreeMap<String, ZoneSetSnapshot>,
+}
+
+/// The zones of the config and of its views. Each view has a zone set of
+/// its own, with its own catalog, the zones of the config are served to
+/// the clients matching no view.
+pub(crate) struct Views {
+    default: Arc<ZoneSet>,
+    views: Vec<View>,
+    /// The config file watched, see [`Views::watch`].
+    path: std::sync::Mutex<Option<PathBuf>>,
+}
+
+impl Views {
+    pub(crate) fn new(config: &config::RunConfig) -> Result<Self> {
+        let default = Arc::new(ZoneSet::new(config.zones(), Keyring::new(config.keys())?)?);
+        let mut names = HashSet::new();
+        let mut views = Vec::new();
+        for view in config.views() {
+            if !names.insert(view.name()) {
+                bail!("duplicate view {}", view.name());
+            }
+            let clients = Acl::parse(view.match_clients())
+                .with_context(|| format!("invalid match_clients of view {}", view.name()))?;
+            if clients.is_empty() {
+                bail!("view {} matches no clients", view.name());
+            }
+            if let Some(key) = clients
+                .keys()
+                .iter()
+                .find(|key| default.keyring().get(key).is_none())
+            {
+                bail!("view {} refers to unknown TSIG key {}", view.name(), key);
+            }
+            if let Some((domain, zone_config)) = view.zones().iter().find(|(_, zone_config)| {
+                matches!(
+                    zone_config.zone_type(),
+                    config::ZoneType::Forward | config::ZoneType::Stub
+                )
+            }) {
+                bail!(
+                    "{} zone {} of view {} must be a zone of every view",
+                    zone_config.zone_type(),
+                    domain,
+                    view.name()
+                );
+            }
+            let zones = ZoneSet::new(view.zones(), Keyring::new(config.keys())?)
+                .with_context(|| format!("invalid zones of view {}", view.name()))?;
+            views.push(View {
+                name: view.name().to_string(),
+                match_clients: view.match_clients().clone(),
+                clients,
+                zones: Arc::new(zones),
+            });
+        }
+        Ok(Self {
+            default,
+            views,
+            path: std::sync::Mutex::new(None),
+        })
+    }
+
+    /// The zones of the config, served to the clients matching no view.
+    pub(crate) fn default_zones(&self) -> &Arc<ZoneSet> {
+        &self.default
+    }
+
+    /// The zones served to a client at `ip`, whose request was signed with
+    /// the verified TSIG `key` if any: those of the first view matching it.
+    pub(crate) fn select(&self, ip: IpAddr, key: Option<&LowerName>) -> &Arc<ZoneSet> {
+        self.views
+            .iter()
+            .find(|view| view.clients.allows(ip, key))
+            .map_or(&self.default, |view| &view.zones)
+    }
+
+    /// Every zone set, those of the config first.
+    pub(crate) fn zone_sets(&self) -> impl Iterator<Item = &Arc<ZoneSet>> {
+        std::iter::once(&self.default).chain(self.views.iter().map(|view| &view.zones))
+    }
+
+    /// Serves the zones of `config` and of its views in place of the current
+    /// ones. Views themselves can't be added, removed or matched to other
+    /// clients without a restart.
+    pub(crate) async fn reload(&self, config: &config::RunConfig) -> Result<()> {
+        let same_views = config.views().len() == self.views.len()
+            && config
+                .views()
+                .iter()
+                .zip(&self.views)
+                .all(|(config, view)| {
+                    config.name() == view.name && *config.match_clients() == view.match_clients
+                });
+        if !same_views {
+            bail!("views can't be changed by a reload, only their zones");
+        }
+        // every zone set is built before any is served, for a view failing
+        // to load to leave them all as they are
+        let mut reloads = vec![self.default.prepare_reload(config.zones().clone()).await?];
+        for (view, config) in self.views.iter().zip(config.views()) {
+            let reload = view
+                .zones
+                .prepare_reload(config.zones().clone())
+                .await
+                .with_context(|| format!("failed to reload view {}", view.name))?;
+            reloads.push(reload);
+        }
+        for reload in reloads {
+            reload.commit().await;
+        }
+        Ok(())
+    }
+
+    /// The zones served by every zone set.
+    pub(crate) async fn snapshot(&self) -> CatalogSnapshot {
+        let mut views = BTreeMap::new();
+        for view in &self.views {
+            views.insert(view.name.clone(), view.zones.snapshot().await);
+        }
+        CatalogSnapshot {
+            default: self.default.snapshot().await,
+            views,
+        }
+    }
+
+    /// Serves the zones of `snapshot` in place of the current ones. The
+    /// snapshot must be of the same views.
+    pub(crate) async fn restore_snapshot(&self, mut snapshot: CatalogSnapshot) -> Result<()> {
+        if snapshot.views.len() != self.views.len()
+            || self
+                .views
+                .iter()
+                .any(|view| !snapshot.views.contains_key(&view.name))
+        {
+            bail!("the snapshot is of other views");
+        }
+        self.default.restore_snapshot(snapshot.default).await?;
+        for view in &self.views {
+            let zones = snapshot.views.remove(&view.name).unwrap();
+            view.zones
+                .restore_snapshot(zones)
+                .await
+                .with_context(|| format!("failed to restore view {}", view.name))?;
+        }
+        Ok(())
+    }
+
+    /// Reloads the zones from the config file at `path` on SIGHUP and, when
+    /// `interval` is set, whenever the file changes.
+    pub(crate) async fn watch(
+        self: Arc<Self>,
+        path: PathBuf,
+        interval: Option<Duration>,
+        token: CancellationToken,
+    ) {
+        *self.path.lock().unwrap() = Some(path.clone());
+        let mut last_modified = modified(&path);
+        let mut ticker = tokio::time::interval(interval.unwrap_or(Duration::MAX));
+        ticker.tick().await;
+        #[cfg(unix)]
+        let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+        {
+            Ok(hangup) => Some(hangup),
+            Err(e) => {
+                warn!("failed to listen for SIGHUP: {}", e);
+                None
+            }
+        };
+        loop {
+            #[cfg(unix)]
+            let hangup = async {
+                match hangup.as_mut() {
+                    Some(hangup) => hangup.recv().await,
+                    None => std::future::pending().await,
+                }
+            };
+            #[cfg(not(unix))]
+            let hangup = std::future::pending::<Option<()>>();
+
+            tokio::select! {
+                _ = hangup => info!("received SIGHUP, reloading {}", path.display()),
+                _ = ticker.tick(), if interval.is_some() => {
+                    let current = modified(&path);
+                    if current.is_none() || current == last_modified {
+                        continue;
+                    }
+                    last_modified = current;
+                    info!("{} changed, reloading", path.display());
+                }
+                _ = token.cancelled() => break,
+            }
+            if let Err(e) = self.reload_from(&path).await {
+                warn!("failed to reload {}: {:#}", path.display(), e);
+            }
+        }
+    }
+
+    /// Reloads the config file watched or, without one, serves the zones
+    /// last loaded again, dropping runtime edits.
+    #[cfg_attr(not(unix), allow(dead_code))]
+    pub(crate) async fn reload_config(&self) -> Result<()> {
+        let path = self.path.lock().unwrap().clone();
+        match path {
+            Some(path) => self.reload_from(&path).await,
+            None => {
+                for zones in self.zone_sets() {
+                    zones.restore().await?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    async fn reload_from(&self, path: &Path) -> Result<()> {
+        let path = path.to_path_buf();
+        let config =
+            tokio::task::spawn_blocking(move || config::RunConfig::from_path(path)).await??;
+        self.reload(&config).await
+    }
+}
+
+pub(crate) fn modified(path: &Path) -> Option<SystemTime> {
+    path.metadata().and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn selects_first_matching_view() -> Result<()> {
+        let config: config::RunConfig = toml::from_str(
+            r#"
+[general]
+
+[[keys]]
+name = "internal"
+secret = "c2VjcmV0"
+
+[[zones."et.internal"]]
+type = "A"
+name = "www.et.internal"
+value = "192.0.2.1"
+ttl = "60s"
+
+[[views]]
+name = "office"
+match_clients = ["10.1.0.0/16"]
+
+[[views]]
+name = "internal"
+match_clients = ["10.0.0.0/8", "key internal"]
+
+[[views.zones."et.internal"]]
+type = "A"
+name = "www.et.internal"
+value = "10.0.0.1"
+ttl = "60s"
+"#,
+        )?;
+        let views = Views::new(&config)?;
+        let [office, internal] = &views.views[..] else {
+            panic!("expected two views");
+        };
+        let select = |ip: &str, key: Option<&LowerName>| views.select(ip.parse().unwrap(), key);
+        assert!(Arc::ptr_eq(select("10.1.2.3", None), &office.zones));
+        assert!(Arc::ptr_eq(select("10.2.3.4", None), &internal.zones));
+        let key = LowerName::from_str("internal")?;
+        assert!(Arc::ptr_eq(
+            select("192.0.2.9", Some(&key)),
+            &internal.zones
+        ));
+        assert!(Arc::ptr_eq(
+            select("192.0.2.9", None),
+            views.default_zones()
+        ));
+        assert_eq!(views.zone_sets().count(), 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reload_failing_in_a_view_changes_no_zones() -> Result<()> {
+        let config = |value: &str, view_zone: &str| -> Result<config::RunConfig> {
+            Ok(toml::from_str(&format!(
+                r#"
+[general]
+
+[[zones."et.internal"]]
+type = "A"
+name = "www.et.internal"
+value = "{value}"
+ttl = "60s"
+
+[[views]]
+name = "office"
+match_clients = ["10.1.0.0/16"]
+
+[[views.zones."{view_zone}"]]
+type = "A"
+name = "www.et.office"
+value = "10.0.0.1"
+ttl = "60s"
+"#
+            ))?)
+        };
+        let views = Views::new(&config("192.0.2.1", "et.office")?)?;
+        let err = views
+            .reload(&config("192.0.2.2", "et..office")?)
+            .await
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("view office"), "{err:#}");
+        let zones = views.default_zones().zones().await;
+        assert_eq!(
+            serde_json::to_value(zones)?,
+            serde_json::to_value(config("192.0.2.1", "et.office")?.zones())?
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,164 @@
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr::{LowerName, Name, RecordSet, RecordType, RrKey};
+use hickory_server::authority::LookupError;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+type Records = BTreeMap<RrKey, Arc<RecordSet>>;
+
+/// Whether `records` hold a wildcard owner name such as `*.dev.et.internal`.
+pub(crate) fn has_wildcards(records: &Records) -> bool {
+    records.keys().any(|key| key.name.is_wildcard())
+}
+
+/// Looks `name` up in the zone `origin` holding `records` the way RFC 4592
+/// expands wildcards: the wildcard at the closest encloser of a name that
+/// doesn't exist answers for it, with its owner replaced by `name`.
+///
+/// `None` when the records of `name` answer the query as they are, hickory
+/// looks them up then. Its own wildcard lookup only tries the parent of a
+/// name, even when the name exists.
+pub(crate) fn lookup(
+    records: &Records,
+    origin: &LowerName,
+    name: &LowerName,
+    query_type: RecordType,
+) -> Option<Result<Arc<RecordSet>, LookupError>> {
+    if matches!(
+        query_type,
+        RecordType::SOA | RecordType::ANY | RecordType::AXFR | RecordType::IXFR
+    ) || !origin.zone_of(name)
+    {
+        return None;
+    }
+    if exists(records, name) {
+        let answered = rrsets(records, name).any(|(key, _)| {
+            key.name == *name
+                && (key.record_type == query_type || key.record_type == RecordType::CNAME)
+        });
+        return if answered {
+            None
+        } else {
+            Some(Err(LookupError::NameExists))
+        };
+    }
+
+    let mut encloser = name.base_name();
+    while encloser != *origin && !exists(records, &encloser) {
+        encloser = encloser.base_name();
+    }
+    // wildcards don't apply below a delegation
+    if encloser != *origin && records.contains_key(&RrKey::new(encloser.clone(), RecordType::NS)) {
+        return None;
+    }
+    let source = Name::from_ascii("*")
+        .and_then(|wildcard| wildcard.append_domain(&(&encloser).into()))
+        .ok()?;
+    let source = LowerName::from(source);
+    if !exists(records, &source) {
+        return Some(Err(LookupError::from(ResponseCode::NXDomain)));
+    }
+    let Some(rrset) = records
+        .get(&RrKey::new(source.clone(), query_type))
+        .or_else(|| records.get(&RrKey::new(source, RecordType::CNAME)))
+    else {
+        return Some(Err(LookupError::NameExists));
+    };
+
+    let mut synthesized = RecordSet::with_ttl(name.into(), rrset.record_type(), rrset.ttl());
+    for record in rrset.records_without_rrsigs() {
+        if let Some(rdata) = record.data() {
+            synthesized.add_rdata(rdata.clone());
+        }
+    }
+    for rrsig in rrset.rrsigs() {
+        let mut rrsig = rrsig.clone();
+        rrsig.set_name(name.into());
+        synthesized.insert_rrsig(rrsig);
+    }
+    Some(Ok(Arc::new(synthesized)))
+}
+
+/// Whether `name` owns records or is an empty non-terminal, a name with
+/// records below it.
+fn exists(records: &Records, name: &LowerName) -> bool {
+    rrsets(records, name)
+        .next()
+        .is_some_and(|(key, _)| name.zone_of(&key.name))
+}
+
+/// The RRsets from `name` on in canonical order, those of its subdomains
+/// following its own.
+fn rrsets<'a>(
+    records: &'a Records,
+    name: &LowerName,
+) -> impl Iterator<Item = (&'a RrKey, &'a Arc<RecordSet>)> {
+    records.range(RrKey::new(name.clone(), RecordType::Unknown(u16::MIN))..)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::rr::{RData, Record};
+    use std::str::FromStr;
+
+    fn records(entries: &[(&str, &str)]) -> Records {
+        let mut records = Records::new();
+        for (name, address) in entries {
+            let name = Name::from_str(name).unwrap();
+            let record = Record::from_rdata(name.clone(), 60, RData::A(address.parse().unwrap()));
+            let rrset = records
+                .entry(RrKey::new(name.clone().into(), RecordType::A))
+                .or_insert_with(|| Arc::new(RecordSet::new(&name, RecordType::A, 0)));
+            Arc::make_mut(rrset).insert(record, 0);
+        }
+        records
+    }
+
+    fn name(name: &str) -> LowerName {
+        LowerName::from(Name::from_str(name).unwrap())
+    }
+
+    #[test]
+    fn expands_wildcards() {
+        let records = records(&[
+            ("et.internal.", "10.0.0.1"),
+            ("*.dev.et.internal.", "10.0.0.2"),
+            ("www.dev.et.internal.", "10.0.0.3"),
+            ("a.b.dev.et.internal.", "10.0.0.4"),
+        ]);
+        let origin = name("et.internal.");
+        let lookup = |qname: &str, query_type| lookup(&records, &origin, &name(qname), query_type);
+
+        let rrset = lookup("x.y.dev.et.internal.", RecordType::A)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            rrset.name(),
+            &Name::from_str("x.y.dev.et.internal.").unwrap()
+        );
+        assert_eq!(
+            rrset.records_without_rrsigs().next().unwrap().data(),
+            Some(&RData::A("10.0.0.2".parse().unwrap()))
+        );
+        // more specific names, empty non-terminals included, aren't expanded
+        assert!(lookup("www.dev.et.internal.", RecordType::A).is_none());
+        assert!(lookup("www.dev.et.internal.", RecordType::AAAA)
+            .unwrap()
+            .is_err_and(|e| e.is_name_exists()));
+        assert!(lookup("b.dev.et.internal.", RecordType::A)
+            .unwrap()
+            .is_err_and(|e| e.is_name_exists()));
+        // the closest encloser of c.b.dev is b.dev, which has no wildcard
+        assert!(lookup("c.b.dev.et.internal.", RecordType::A)
+            .unwrap()
+            .is_err_and(|e| e.is_nx_domain()));
+        assert!(lookup("x.dev.et.internal.", RecordType::AAAA)
+            .unwrap()
+            .is_err_and(|e| e.is_name_exists()));
+        assert!(lookup("www.et.internal.", RecordType::A)
+            .unwrap()
+            .is_err_and(|e| e.is_nx_domain()));
+        assert!(lookup("www.et.top.", RecordType::A).is_none());
+    }
+}
@@ -0,0 +1,245 @@
+use hickory_proto::rr::rdata::SVCB;
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use hickory_proto::serialize::txt::Parser;
+use std::fmt::Write;
+
+/// Writes `records` as an RFC 1035 zone file for `origin`, SOA first. Every
+/// name is written fully qualified.
+pub(crate) fn write_zone<'a>(origin: &Name, records: impl Iterator<Item = &'a Record>) -> String {
+    let mut records: Vec<&Record> = records.collect();
+    records.sort_by_key(|r| r.record_type() != RecordType::SOA);
+
+    let mut text = format!("$ORIGIN {}\n", fqdn(origin));
+    for record in records {
+        let Some(rdata) = record.data() else {
+            continue;
+        };
+        writeln!(
+            text,
+            "{}\t{}\t{}\t{}\t{}",
+            fqdn(record.name()),
+            record.ttl(),
+            record.dns_class(),
+            type_text(record.record_type()),
+            rdata_text(rdata)
+        )
+        .unwrap();
+    }
+    text
+}
+
+/// Parses the record `name ttl IN record_type data` of a zone file, relative
+/// names being under `origin`. The parser makes the expire of an SOA record
+/// its TTL, so `ttl` is set afterwards.
+pub(crate) fn parse_record(
+    name: &str,
+    ttl: u32,
+    record_type: &str,
+    data: &str,
+    origin: &Name,
+) -> anyhow::Result<Record> {
+    let line = format!("{} {} IN {} {}\n", name, ttl, record_type, data);
+    let (_, rrsets) = Parser::new(line, None, Some(origin.clone()))
+        .parse()
+        .map_err(|e| anyhow::anyhow!("{}", e))?;
+    let mut record = rrsets
+        .into_values()
+        .flatten()
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("no record in {} {} {}", name, record_type, data))?;
+    record.set_ttl(ttl);
+    Ok(record)
+}
+
+/// The mnemonic of `rr_type`, or `TYPE<code>` (RFC 3597) for the types hickory
+/// doesn't know.
+fn type_text(rr_type: RecordType) -> String {
+    match rr_type {
+        RecordType::Unknown(code) => format!("TYPE{}", code),
+        rr_type => rr_type.to_string(),
+    }
+}
+
+pub(crate) fn fqdn(name: &Name) -> Name {
+    let mut name = name.clone();
+    name.set_fqdn(true);
+    name
+}
+
+/// Presentation format of `rdata`. Names are made fully qualified since the
+/// records may hold names without the trailing dot.
+pub(crate) fn rdata_text(rdata: &RData) -> String {
+    match rdata {
+        RData::CNAME(cname) => fqdn(&cname.0).to_string(),
+        RData::NS(ns) => fqdn(&ns.0).to_string(),
+        RData::PTR(ptr) => fqdn(&ptr.0).to_string(),
+        RData::MX(mx) => format!("{} {}", mx.preference(), fqdn(mx.exchange())),
+        RData::SRV(srv) => format!(
+            "{} {} {} {}",
+            srv.priority(),
+            srv.weight(),
+            srv.port(),
+            fqdn(srv.target())
+        ),
+        RData::SOA(soa) => format!(
+            "{} {} {} {} {} {} {}",
+            fqdn(soa.mname()),
+            fqdn(soa.rname()),
+            soa.serial(),
+            soa.refresh(),
+            soa.retry(),
+            soa.expire(),
+            soa.minimum()
+        ),
+        RData::TXT(txt) => txt
+            .txt_data()
+            .iter()
+            .map(|segment| quote(segment))
+            .collect::<Vec<_>>()
+            .join(" "),
+        RData::SVCB(svcb) => svcb_text(svcb),
+        RData::HTTPS(https) => svcb_text(&https.0),
+        RData::Unknown { rdata, .. } => {
+            let data = rdata.anything();
+            match data.is_empty() {
+                true => "\\# 0".to_string(),
+                false => format!(
+                    "\\# {} {}",
+                    data.len(),
+                    data_encoding::HEXLOWER.encode(data)
+                ),
+            }
+        }
+        rdata => rdata.to_string(),
+    }
+}
+
+/// Presentation format of an SVCB or HTTPS record, in the spelling of
+/// hickory's parser, e.g. `echconfig` for the `ech` key.
+fn svcb_text(svcb: &SVCB) -> String {
+    let mut text = format!("{} {}", svcb.svc_priority(), fqdn(svcb.target_name()));
+    for (key, value) in svcb.svc_params() {
+        write!(text, " {}={}", key, value).unwrap();
+    }
+    text
+}
+
+/// Quotes a character string, escaping quotes, backslashes and unprintable bytes.
+fn quote(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len() + 2);
+    text.push('"');
+    for &b in bytes {
+        match b {
+            b'"' | b'\\' => {
+                text.push('\\');
+                text.push(b as char);
+            }
+            0x20..=0x7e => text.push(b as char),
+            _ => write!(text, "\\{:03}", b).unwrap(),
+        }
+    }
+    text.push('"');
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::rr::rdata::svcb::{Alpn, SvcParamKey, SvcParamValue};
+    use hickory_proto::rr::rdata::{CNAME, HTTPS, MX, SOA, TXT};
+    use std::str::FromStr;
+
+    #[test]
+    fn round_trips_through_parser() -> anyhow::Result<()> {
+        let origin = Name::from_str("et.internal")?;
+        let name = |name: &str| Name::from_str(name).unwrap();
+        let records = [
+            Record::from_rdata(name("www.et.internal"), 60, RData::A("10.0.0.1".parse()?)),
+            Record::from_rdata(
+                name("api.et.internal"),
+                60,
+                RData::CNAME(CNAME(name("www.et.internal"))),
+            ),
+            Record::from_rdata(
+                origin.clone(),
+                300,
+                RData::MX(MX::new(10, name("mail.et.internal"))),
+            ),
+            Record::from_rdata(
+                origin.clone(),
+                300,
+                RData::TXT(TXT::new(vec![
+                    "v=spf1 -all".to_string(),
+                    "say \"hi\"".to_string(),
+                ])),
+            ),
+            Record::from_rdata(
+                origin.clone(),
+                300,
+                RData::SOA(SOA::new(
+                    name("ns1.et.internal"),
+                    name("hostmaster.et.internal"),
+                    42,
+                    3600,
+                    900,
+                    604800,
+                    300,
+                )),
+            ),
+            Record::from_rdata(
+                name("www.et.internal"),
+                60,
+                RData::HTTPS(HTTPS(SVCB::new(
+                    1,
+                    name("cdn.et.internal"),
+                    vec![(
+                        SvcParamKey::Alpn,
+                        SvcParamValue::Alpn(Alpn(vec!["h2".to_string(), "h3".to_string()])),
+                    )],
+                ))),
+            ),
+        ];
+
+        let text = write_zone(&origin, records.iter());
+        assert!(text.starts_with("$ORIGIN et.internal.\net.internal.\t300\tIN\tSOA\t"));
+
+        let (_, parsed) = Parser::new(text, None, None).parse()?;
+        let parsed: Vec<Record> = parsed.into_values().flatten().collect();
+        assert_eq!(parsed.len(), records.len());
+        for record in records.iter() {
+            let mut expected = record.clone();
+            expected.set_name(fqdn(record.name()));
+            let found = parsed
+                .iter()
+                .find(|r| r.name() == expected.name() && r.record_type() == expected.record_type())
+                .unwrap();
+            // hickory's parser gives the SOA record the expire time as TTL
+            if expected.record_type() != RecordType::SOA {
+                assert_eq!(found.ttl(), expected.ttl());
+            }
+            assert_eq!(
+                rdata_text(found.data().unwrap()),
+                rdata_text(expected.data().unwrap())
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn writes_generic_records() -> anyhow::Result<()> {
+        use hickory_proto::rr::rdata::NULL;
+
+        let origin = Name::from_str("et.internal.")?;
+        let record = Record::from_rdata(
+            Name::from_str("www.et.internal.")?,
+            60,
+            RData::Unknown {
+                code: RecordType::Unknown(65280),
+                rdata: NULL::with(vec![10, 0, 0, 1]),
+            },
+        );
+        let text = write_zone(&origin, [record].iter());
+        assert!(text.ends_with("www.et.internal.\t60\tIN\tTYPE65280\t\\# 4 0a000001\n"));
+        Ok(())
+    }
+}
@@ -0,0 +1,2018 @@
+use crate::acl::{Acl, ClientAcl};
+use crate::catalog::SharedCatalog;
+use crate::catalog_zone;
+use crate::config;
+use crate::delegation::{self, Referral};
+use crate::dns::{remove_from, RecordError};
+use crate::dnssec::{self, GeneratedKeys, ZoneSigner};
+use crate::geoip::{self, GeoRecords, Location};
+use crate::health::{self, Health};
+use crate::notify;
+use crate::redirect::Redirect;
+use crate::secondary::{self, Transfer};
+use crate::sqlite;
+use crate::tsig::Keyring;
+use crate::ttl::TtlLimits;
+use crate::update;
+use crate::wildcard;
+use crate::zonefile;
+use anyhow::{anyhow, bail, Context, Result};
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr;
+use hickory_proto::rr::dnssec::rdata::DNSSECRData;
+use hickory_proto::rr::dnssec::tsig::TSigner;
+use hickory_proto::rr::{LowerName, RData, RecordSet, RecordType, RrKey};
+use hickory_server::authority::{
+    AuthLookup, Authority, AuthorityObject, LookupError, LookupObject, LookupOptions,
+    LookupRecords, MessageRequest, ZoneType,
+};
+use hickory_server::store::in_memory::InMemoryAuthority;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, Mutex, MutexGuard, Notify};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+/// Retry interval of a secondary zone that was never transferred.
+const INITIAL_RETRY: Duration = Duration::from_secs(10);
+
+/// How often signed zones are checked for expiring signatures.
+const RESIGN_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Builds an authority serving `records`. RRSIG records, as transferred from
+/// a signed primary, are attached to the RRset they cover.
+fn new_authority(
+    zone: rr::Name,
+    records: Vec<rr::Record>,
+    zone_type: ZoneType,
+) -> InMemoryAuthority {
+    let mut authority = InMemoryAuthority::empty(zone, zone_type, false);
+    let (rrsigs, records): (Vec<_>, Vec<_>) = records
+        .into_iter()
+        .partition(|r| r.record_type() == RecordType::RRSIG);
+    for record in records {
+        authority.upsert_mut(record, 0);
+    }
+    let rrsets = authority.records_get_mut();
+    for rrsig in rrsigs {
+        let Some(covered) = rrsig
+            .data()
+            .and_then(RData::as_dnssec)
+            .and_then(DNSSECRData::as_rrsig)
+            .map(|rrsig| rrsig.type_covered())
+        else {
+            continue;
+        };
+        if let Some(rrset) = rrsets.get_mut(&RrKey::new(rrsig.name().into(), covered)) {
+            Arc::make_mut(rrset).insert_rrsig(rrsig);
+        }
+    }
+    authority
+}
+
+/// Finds the zone in `authorities` that `name` belongs to, if any.
+fn enclosing_zone(
+    authorities: &HashMap<rr::Name, InMemoryAuthority>,
+    name: &rr::Name,
+) -> Option<rr::Name> {
+    let mut name = name.clone();
+    loop {
+        if authorities.contains_key(&name) {
+            return Some(name);
+        }
+        if name.is_root() {
+            return None;
+        }
+        name = name.base_name();
+    }
+}
+
+/// Builds the authorities of the configured zones, including the reverse
+/// zones synthesized for `auto_reverse`. `sqlite` zones come from their
+/// database once it holds them, `redis` zones from their records in
+/// `fetched` once fetched. With `restore`, zones start from the records
+/// saved to their persist file, if any.
+fn build_authorities(
+    zones: &config::Zone,
+    restore: bool,
+    fetched: &HashMap<rr::Name, Vec<rr::Record>>,
+) -> Result<HashMap<rr::Name, InMemoryAuthority>> {
+    let mut authorities = HashMap::new();
+    for (domain, zone_config) in zones.iter() {
+        match zone_config.zone_type() {
+            config::ZoneType::Primary => {}
+            zone_type => {
+                if zone_config.backend() != config::ZoneBackend::Memory {
+                    bail!("{} zone {} must use the memory backend", zone_type, domain);
+                }
+                continue;
+            }
+        }
+        let zone = rr::Name::from_str(domain.as_str())?;
+        let persisted = match restore {
+            true => zone_config.persisted_records(&zone)?,
+            false => None,
+        };
+        let stored = match fetched.get(&zone) {
+            Some(records) if zone_config.backend() == config::ZoneBackend::Redis => {
+                Some(zone_config.with_apex(&zone, records.clone())?)
+            }
+            _ => zone_config
+                .stored_records(&zone)
+                .with_context(|| format!("failed to load zone {}", zone))?,
+        };
+        let records = match (stored, persisted) {
+            (Some(records), _) => {
+                debug!("loaded zone {} from its backend", zone);
+                records
+            }
+            (None, Some(records)) => {
+                info!("restoring zone {} from its persist file", zone);
+                records
+            }
+            (None, None) if zone_config.catalog() => {
+                zone_config.with_apex(&zone, catalog_zone::records(zones, &zone, zone_config)?)?
+            }
+            (None, None) => zone_config.to_records(&zone)?,
+        };
+        authorities.insert(
+            zone.clone(),
+            new_authority(zone, records, ZoneType::Primary),
+        );
+    }
+    for (domain, zone_config) in zones.iter() {
+        if !zone_config.auto_reverse() || zone_config.zone_type() != config::ZoneType::Primary {
+            continue;
+        }
+        let origin = rr::Name::from_str(domain.as_str())?;
+        for (zone, ptrs) in zone_config.reverse_records(&origin)? {
+            let zone = match enclosing_zone(&authorities, &zone) {
+                Some(configured) => configured,
+                None => {
+                    let records = zone_config.reverse_zone(&origin)?.to_records(&zone)?;
+                    authorities.insert(
+                        zone.clone(),
+                        new_authority(zone.clone(), records, ZoneType::Primary),
+                    );
+                    zone
+                }
+            };
+            let authority = authorities.get_mut(&zone).unwrap();
+            for ptr in ptrs {
+                authority.upsert_mut(ptr, 0);
+            }
+        }
+    }
+    Ok(authorities)
+}
+
+/// The primary zones of `zones` with `backend = "redis"`.
+fn redis_zones(zones: &config::Zone) -> HashSet<rr::Name> {
+    zones
+        .iter()
+        .filter(|(_, zone_config)| {
+            zone_config.zone_type() == config::ZoneType::Primary
+                && zone_config.backend() == config::ZoneBackend::Redis
+        })
+        .filter_map(|(domain, _)| rr::Name::from_str(domain).ok())
+        .collect()
+}
+
+/// Whether `zone` is a secondary catalog zone of `zones`.
+fn is_catalog(zones: &config::Zone, zone: &rr::Name) -> bool {
+    zones.iter().any(|(domain, zone_config)| {
+        zone_config.catalog()
+            && zone_config.zone_type() == config::ZoneType::Secondary
+            && rr::Name::from_str(domain).is_ok_and(|name| name == *zone)
+    })
+}
+
+/// `zones` with the `members` of their secondary catalog zones added as
+/// secondary zones, unless configured already.
+fn with_members(
+    zones: &config::Zone,
+    members: &HashMap<rr::Name, BTreeSet<rr::Name>>,
+) -> config::Zone {
+    let configured: HashSet<rr::Name> = zones
+        .keys()
+        .filter_map(|domain| rr::Name::from_str(domain).ok())
+        .collect();
+    let mut expanded = zones.clone();
+    for (domain, zone_config) in zones {
+        let Ok(catalog) = rr::Name::from_str(domain) else {
+            continue;
+        };
+        if !is_catalog(zones, &catalog) {
+            continue;
+        }
+        for member in members.get(&catalog).into_iter().flatten() {
+            if !configured.contains(member) {
+                expanded.insert(member.to_string(), zone_config.member_zone());
+            }
+        }
+    }
+    expanded
+}
+
+/// The primaries of every secondary zone.
+fn build_secondaries(zones: &config::Zone) -> Result<HashMap<rr::Name, Vec<SocketAddr>>> {
+    zones
+        .iter()
+        .filter(|(_, zone_config)| zone_config.zone_type() == config::ZoneType::Secondary)
+        .map(|(domain, zone_config)| {
+            let primaries = zone_config
+                .primary_addrs()
+                .with_context(|| format!("invalid primaries of zone {}", domain))?;
+            if primaries.is_empty() {
+                bail!("secondary zone {} has no primaries", domain);
+            }
+            Ok((rr::Name::from_str(domain)?, primaries))
+        })
+        .collect()
+}
+
+/// The health checks of the A and AAAA records of the primary zones.
+fn build_health_checks(
+    zones: &config::Zone,
+) -> Result<HashMap<health::Target, config::HealthCheckConfig>> {
+    let mut checks = HashMap::new();
+    for (domain, zone_config) in zones.iter() {
+        if zone_config.zone_type() != config::ZoneType::Primary {
+            continue;
+        }
+        let origin = rr::Name::from_str(domain)?;
+        for record in zone_config.records() {
+            let Some(check) = record.health_check() else {
+                continue;
+            };
+            let record = record.to_record(&origin)?;
+            let address: IpAddr = match record.data() {
+                Some(RData::A(a)) => a.0.into(),
+                Some(RData::AAAA(aaaa)) => aaaa.0.into(),
+                _ => bail!(
+                    "health check of {} needs an A or AAAA record",
+                    record.name()
+                ),
+            };
+            checks.insert((record.name().into(), address), check.clone());
+        }
+    }
+    Ok(checks)
+}
+
+/// Access rules of a zone, the secondaries notified of its changes, the
+/// TSIG key signing the messages sent for it, the file its records are
+/// saved to, its DNSSEC signer, the order, geo-targeting and TTL limits
+/// of its answers and its NXDOMAIN redirect.
+#[derive(Default)]
+struct ZonePolicy {
+    allow_transfer: Acl,
+    allow_notify: Acl,
+    allow_update: Acl,
+    query: ClientAcl,
+    notify: Vec<SocketAddr>,
+    key: Option<TSigner>,
+    persist_file: Option<PathBuf>,
+    /// Database of a `sqlite` zone.
+    database: Option<PathBuf>,
+    signer: Option<Arc<ZoneSigner>>,
+    answer_order: config::AnswerOrder,
+    /// Queries answered so far, the rotation of round-robin answers.
+    rotation: AtomicUsize,
+    geo: GeoRecords,
+    ttl: TtlLimits,
+    /// Targets of the ALIAS records, by owner name.
+    aliases: HashMap<LowerName, rr::Name>,
+    serial_policy: config::SerialPolicy,
+    redirect: Option<Arc<Redirect>>,
+}
+
+/// The selectors of the geo-targeted records of the zone `origin`.
+fn build_geo_records(origin: &rr::Name, zone_config: &config::ZoneConfig) -> Result<GeoRecords> {
+    let mut geo = GeoRecords::new();
+    for record in zone_config.records() {
+        let selectors = record.geo();
+        if selectors.is_empty() {
+            continue;
+        }
+        let record = record.to_record(origin)?;
+        let Some(rdata) = record.data() else {
+            continue;
+        };
+        let targeted = geo.entry(record.name().into()).or_default();
+        match targeted.iter_mut().find(|(other, _)| other == rdata) {
+            Some((_, existing)) => existing.extend(selectors.iter().cloned()),
+            None => targeted.push((rdata.clone(), selectors.to_vec())),
+        }
+    }
+    Ok(geo)
+}
+
+/// The targets of the ALIAS records of the zone `origin`, which can't share
+/// their name with A, AAAA or CNAME records.
+fn build_aliases(
+    origin: &rr::Name,
+    zone_config: &config::ZoneConfig,
+) -> Result<HashMap<LowerName, rr::Name>> {
+    let mut aliases = HashMap::new();
+    for alias in zone_config.aliases() {
+        let (name, target) = alias.to_names(origin)?;
+        aliases.insert(LowerName::from(name), target);
+    }
+    for record in zone_config.records() {
+        let record = record.to_record(origin)?;
+        if aliases.contains_key(&LowerName::from(record.name()))
+            && matches!(
+                record.record_type(),
+                RecordType::A | RecordType::AAAA | RecordType::CNAME
+            )
+        {
+            bail!(
+                "{} has both an alias and {} records",
+                record.name(),
+                record.record_type()
+            );
+        }
+    }
+    Ok(aliases)
+}
+
+fn build_policies(
+    zones: &config::Zone,
+    keyring: &Keyring,
+    generated: &GeneratedKeys,
+) -> Result<HashMap<LowerName, ZonePolicy>> {
+    let find_key = |domain: &str, name: &LowerName| {
+        keyring
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow!("zone {} refers to unknown TSIG key {}", domain, name))
+    };
+    zones
+        .iter()
+        .map(|(domain, zone_config)| {
+            let allow_transfer = Acl::parse(zone_config.allow_transfer())
+                .with_context(|| format!("invalid allow_transfer of zone {}", domain))?;
+            let allow_notify = if zone_config.allow_notify().is_empty() {
+                Acl::from_ips(zone_config.primary_addrs()?.iter().map(|addr| addr.ip()))
+            } else {
+                Acl::parse(zone_config.allow_notify())
+                    .with_context(|| format!("invalid allow_notify of zone {}", domain))?
+            };
+            let allow_update = Acl::parse(zone_config.allow_update())
+                .with_context(|| format!("invalid allow_update of zone {}", domain))?;
+            let query = ClientAcl::parse(zone_config.allow_query(), zone_config.deny_query())
+                .with_context(|| format!("invalid allow_query or deny_query of zone {}", domain))?;
+            let notify = zone_config
+                .notify_addrs()
+                .with_context(|| format!("invalid notify of zone {}", domain))?;
+            for key in [&allow_transfer, &allow_notify, &allow_update]
+                .iter()
+                .flat_map(|acl| acl.keys())
+                .chain(query.keys())
+            {
+                find_key(domain, key)?;
+            }
+            let key = match zone_config.key() {
+                Some(key) => Some(find_key(domain, &LowerName::from_str(key)?)?),
+                None => None,
+            };
+            let signer = match zone_config.dnssec() {
+                Some(dnssec) if zone_config.zone_type() == config::ZoneType::Primary => {
+                    let signer = ZoneSigner::new(&rr::Name::from_str(domain)?, dnssec, generated)
+                        .with_context(|| format!("invalid dnssec of zone {}", domain))?;
+                    Some(Arc::new(signer))
+                }
+                _ => None,
+            };
+            let policy = ZonePolicy {
+                allow_transfer,
+                allow_notify,
+                allow_update,
+                query,
+                notify,
+                key,
+                persist_file: zone_config.persist_file().map(Path::to_path_buf),
+                database: zone_config
+                    .database()
+                    .filter(|_| zone_config.backend() == config::ZoneBackend::Sqlite)
+                    .map(Path::to_path_buf),
+                signer,
+                answer_order: zone_config.answer_order(),
+                rotation: AtomicUsize::new(0),
+                geo: build_geo_records(&rr::Name::from_str(domain)?, zone_config)
+                    .with_context(|| format!("invalid records of zone {}", domain))?,
+                ttl: TtlLimits::new(zone_config.min_ttl(), zone_config.max_ttl())
+                    .with_context(|| format!("invalid TTL limits of zone {}", domain))?,
+                aliases: build_aliases(&rr::Name::from_str(domain)?, zone_config)
+                    .with_context(|| format!("invalid aliases of zone {}", domain))?,
+                serial_policy: zone_config.soa().serial_policy(),
+                redirect: zone_config
+                    .nxdomain_redirect()
+                    .map(Redirect::new)
+                    .transpose()
+                    .with_context(|| format!("invalid nxdomain_redirect of zone {}", domain))?
+                    .map(Arc::new),
+            };
+            Ok((LowerName::from_str(domain)?, policy))
+        })
+        .collect()
+}
+
+/// Reorders every run of A or AAAA records of one name in `answers`, by
+/// `rotation` records for round-robin answers.
+fn order_answers(answers: &mut [rr::Record], order: config::AnswerOrder, rotation: usize) {
+    let mut start = 0;
+    while start < answers.len() {
+        let first = &answers[start];
+        let len = answers[start..]
+            .iter()
+            .take_while(|r| r.name() == first.name() && r.record_type() == first.record_type())
+            .count();
+        let rrset = &mut answers[start..start + len];
+        if matches!(rrset[0].record_type(), RecordType::A | RecordType::AAAA) {
+            match order {
+                config::AnswerOrder::Fixed => {}
+                config::AnswerOrder::RoundRobin => rrset.rotate_left(rotation % len),
+                config::AnswerOrder::Random => rrset.shuffle(&mut rand::thread_rng()),
+            }
+        }
+        start += len;
+    }
+}
+
+/// Whether two zones hold the same records. The SOA serial is ignored since
+/// it defaults to the time the zone was built, and so are the records
+/// generated by DNSSEC signing.
+fn same_records(a: &BTreeMap<RrKey, Arc<RecordSet>>, b: &BTreeMap<RrKey, Arc<RecordSet>>) -> bool {
+    let configured = |records: &BTreeMap<RrKey, Arc<RecordSet>>| -> Vec<(RrKey, Arc<RecordSet>)> {
+        records
+            .iter()
+            .filter(|(key, _)| !dnssec::is_generated(key.record_type))
+            .map(|(key, rrset)| (key.clone(), rrset.clone()))
+            .collect()
+    };
+    let (a, b) = (configured(a), configured(b));
+    let without_serial = |rrset: &RecordSet| -> Vec<(u32, Option<RData>)> {
+        rrset
+            .records_without_rrsigs()
+            .map(|r| {
+                let data = match r.data() {
+                    Some(RData::SOA(soa)) => Some(RData::SOA(rr::rdata::SOA::new(
+                        soa.mname().clone(),
+                        soa.rname().clone(),
+                        0,
+                        soa.refresh(),
+                        soa.retry(),
+                        soa.expire(),
+                        soa.minimum(),
+                    ))),
+                    data => data.cloned(),
+                };
+                (r.ttl(), data)
+            })
+            .collect()
+    };
+    a.len() == b.len()
+        && a.iter().zip(b.iter()).all(|((ka, ra), (kb, rb))| {
+            ka == kb
+                && if ka.record_type == RecordType::SOA {
+                    without_serial(ra) == without_serial(rb)
+                } else {
+                    ra.records_without_rrsigs().eq(rb.records_without_rrsigs())
+                }
+        })
+}
+
+/// Refresh, retry and expire timers of the SOA record `soa`.
+fn soa_timers(soa: &rr::Record) -> (Duration, Duration, Duration) {
+    let Some(RData::SOA(soa)) = soa.data() else {
+        return (INITIAL_RETRY, INITIAL_RETRY, INITIAL_RETRY);
+    };
+    let secs = |value: i32| Duration::from_secs(value.max(1) as u64);
+    (secs(soa.refresh()), secs(soa.retry()), secs(soa.expire()))
+}
+
+/// Raises the SOA serial of `authority` above `serial` by `policy`, so that
+/// secondaries holding `serial` transfer the zone again.
+async fn raise_serial(authority: &InMemoryAuthority, serial: u32, policy: config::SerialPolicy) {
+    raise_serial_in(
+        &mut *authority.records_mut().await,
+        authority.origin(),
+        serial,
+        policy,
+    );
+}
+
+/// Raises the SOA serial in the `records` of the zone `origin` above
+/// `serial`, as `raise_serial`.
+fn raise_serial_in(
+    records: &mut BTreeMap<RrKey, Arc<RecordSet>>,
+    origin: &LowerName,
+    serial: u32,
+    policy: config::SerialPolicy,
+) {
+    let key = RrKey::new(origin.clone(), RecordType::SOA);
+    let Some(mut soa) = records
+        .get(&key)
+        .and_then(|rrset| rrset.records_without_rrsigs().next())
+        .cloned()
+    else {
+        return;
+    };
+    let Some(RData::SOA(data)) = soa.data() else {
+        return;
+    };
+    if data.serial() > serial {
+        return;
+    }
+    let data = rr::rdata::SOA::new(
+        data.mname().clone(),
+        data.rname().clone(),
+        policy.next(serial),
+        data.refresh(),
+        data.retry(),
+        data.expire(),
+        data.minimum(),
+    );
+    soa.set_data(Some(RData::SOA(data)));
+    records.insert(key, Arc::new(RecordSet::from(soa)));
+}
+
+/// The SOA serial in the `records` of the zone `origin`.
+fn serial_in(records: &BTreeMap<RrKey, Arc<RecordSet>>, origin: &LowerName) -> Option<u32> {
+    let key = RrKey::new(origin.clone(), RecordType::SOA);
+    let soa = records.get(&key)?.records_without_rrsigs().next()?;
+    Some(soa.data()?.as_soa()?.serial())
+}
+
+async fn soa_record(authority: &InMemoryAuthority) -> Option<rr::Record> {
+    let zone = authority.origin().clone();
+    authority
+        .records()
+        .await
+        .get(&RrKey::new(zone, RecordType::SOA))?
+        .records_without_rrsigs()
+        .next()
+        .cloned()
+}
+
+/// The records of `authority` as an RFC 1035 zone file, leaving out those
+/// generated by DNSSEC signing.
+async fn zone_text(authority: &InMemoryAuthority) -> String {
+    crate::zonefile::write_zone(
+        &authority.origin().into(),
+        saved_records(authority).await.iter(),
+    )
+}
+
+/// The records of `authority` that are saved, those generated by DNSSEC
+/// signing left out.
+async fn saved_records(authority: &InMemoryAuthority) -> Vec<rr::Record> {
+    authority
+        .records()
+        .await
+        .iter()
+        .filter(|(key, _)| !dnssec::is_generated(key.record_type))
+        .flat_map(|(_, rrset)| rrset.records_without_rrsigs().cloned())
+        .collect()
+}
+
+/// The zones of a zone set and the records it serves, see
+/// [`ZoneSet::snapshot`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct ZoneSetSnapshot {
+    configured: config::Zone,
+    zones: config::Zone,
+    /// Records of every zone served, runtime changes included. Signatures
+    /// are left out of primary zones, which are signed again on restore.
+    records: BTreeMap<rr::Name, Vec<rr::Record>>,
+}
+
+/// Zones built by `ZoneSet::build`, not served yet.
+struct Built {
+    zones: config::Zone,
+    members: HashMap<rr::Name, BTreeSet<rr::Name>>,
+    served: HashSet<rr::Name>,
+    /// The zones whose records changed, to load.
+    changed: Vec<(rr::Name, Arc<InMemoryAuthority>)>,
+    policies: HashMap<LowerName, ZonePolicy>,
+    secondaries: HashMap<rr::Name, Vec<SocketAddr>>,
+    health_checks: HashMap<health::Target, config::HealthCheckConfig>,
+}
+
+/// A reload of a zone set, built and waiting to be committed.
+pub(crate) struct PreparedReload<'a> {
+    zones: &'a ZoneSet,
+    state: MutexGuard<'a, State>,
+    built: Built,
+    configured: config::Zone,
+}
+
+impl PreparedReload<'_> {
+    /// Serves the zones of the reload.
+    pub(crate) async fn commit(mut self) {
+        self.zones.apply(&mut self.state, self.built).await;
+        self.state.configured = self.configured;
+    }
+}
+
+struct State {
+    /// Zones of the last loaded config.
+    configured: config::Zone,
+    /// `configured` with the edits made at runtime.
+    zones: config::Zone,
+    /// Zones currently served from `zones`, including synthesized reverse
+    /// zones and transferred secondary zones.
+    served: HashSet<rr::Name>,
+    /// Primaries of the secondary zones in `zones`.
+    secondaries: HashMap<rr::Name, Vec<SocketAddr>>,
+    /// Health checks of the records in `zones`.
+    health_checks: HashMap<health::Target, config::HealthCheckConfig>,
+    /// Records fetched for the `redis` zones in `zones`.
+    fetched: HashMap<rr::Name, Vec<rr::Record>>,
+    /// Member zones listed by the secondary catalog zones transferred.
+    members: HashMap<rr::Name, BTreeSet<rr::Name>>,
+}
+
+/// The task probing a health-checked address.
+struct HealthProbe {
+    check: config::HealthCheckConfig,
+    token: CancellationToken,
+}
+
+/// The task keeping a secondary zone transferred.
+struct Refresher {
+    primaries: Vec<SocketAddr>,
+    token: CancellationToken,
+    /// Wakes the task to refresh the zone right away.
+    wake: Arc<Notify>,
+}
+
+/// The configured zones of a server and the catalog serving them.
+///
+/// In-memory authorities are kept next to the catalog by zone, because the
+/// catalog only hands out trait objects and records are edited in place.
+/// Changes of the zones served buffered for each watcher, which misses the
+/// older ones when it lags behind.
+const ZONE_CHANGES: usize = 1024;
+
+/// A change of the zones served, see [`ZoneSet::watch`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(not(feature = "grpc"), allow(dead_code))]
+pub(crate) enum ZoneChange {
+    /// The zone is served with new records, with this SOA serial.
+    Updated(rr::Name, u32),
+    /// The zone is no longer served.
+    Removed(rr::Name),
+}
+
+/// A change of the records of an in-memory primary zone, applied with
+/// the other changes of a transaction by [`ZoneSet::commit`].
+pub(crate) enum RecordChange {
+    Add(rr::Record),
+    /// Fails the transaction when the record doesn't exist.
+    Remove(rr::Record),
+    /// Replaces the records with the name and type of the set, an empty set
+    /// removes them.
+    Replace(RecordSet),
+}
+
+impl RecordChange {
+    fn name(&self) -> &rr::Name {
+        match self {
+            RecordChange::Add(record) | RecordChange::Remove(record) => record.name(),
+            RecordChange::Replace(rrset) => rrset.name(),
+        }
+    }
+}
+
+pub(crate) struct ZoneSet {
+    catalog: SharedCatalog,
+    authorities: std::sync::RwLock<HashMap<LowerName, Arc<InMemoryAuthority>>>,
+    policies: std::sync::RwLock<HashMap<LowerName, ZonePolicy>>,
+    state: Mutex<State>,
+    /// Notified when the configured secondary zones change.
+    secondaries_changed: Notify,
+    /// Notified when the configured `redis` zones change.
+    redis_zones_changed: Notify,
+    refreshers: std::sync::Mutex<HashMap<LowerName, Refresher>>,
+    /// TSIG keys of the server, which are not reloaded with the zones.
+    keyring: Keyring,
+    /// Serializes writes of persist files.
+    persisting: Mutex<()>,
+    /// DNSSEC keys of the zones signed without key files.
+    generated: GeneratedKeys,
+    /// Zones holding wildcard records, whose lookups expand them.
+    wildcards: std::sync::RwLock<HashSet<LowerName>>,
+    /// Zones delegating child zones, whose names get referrals.
+    delegations: std::sync::RwLock<HashSet<LowerName>>,
+    /// Addresses withdrawn from answers by their health checks.
+    health: Arc<Health>,
+    /// Notified when the configured health checks change.
+    health_checks_changed: Notify,
+    probes: std::sync::Mutex<HashMap<health::Target, HealthProbe>>,
+    changes: broadcast::Sender<ZoneChange>,
+}
+
+impl ZoneSet {
+    pub(crate) fn new(zones: &config::Zone, keyring: Keyring) -> Result<Self> {
+        let mut catalog: HashMap<LowerName, Box<dyn AuthorityObject>> = HashMap::new();
+        let mut authorities = HashMap::new();
+        let mut served = HashSet::new();
+        let mut wildcards = HashSet::new();
+        let mut delegations = HashSet::new();
+        let generated = GeneratedKeys::default();
+        let policies = build_policies(zones, &keyring, &generated)?;
+        for (zone, mut authority) in build_authorities(zones, true, &HashMap::new())? {
+            if let Some(signer) = policies
+                .get(&LowerName::from(&zone))
+                .and_then(|p| p.signer.as_ref())
+            {
+                signer
+                    .sign(authority.records_get_mut())
+                    .with_context(|| format!("failed to sign zone {}", zone))?;
+                info!("signed zone {}, DS record: {}", zone, signer.ds()?);
+            }
+            if wildcard::has_wildcards(authority.records_get_mut()) {
+                wildcards.insert(zone.clone().into());
+            }
+            if delegation::has_delegations(authority.records_get_mut(), &zone.clone().into()) {
+                delegations.insert(zone.clone().into());
+            }
+            let authority = Arc::new(authority);
+            catalog.insert(zone.clone().into(), Box::new(authority.clone()));
+            authorities.insert(zone.clone().into(), authority);
+            served.insert(zone);
+        }
+        Ok(Self {
+            catalog: SharedCatalog::new(catalog),
+            authorities: std::sync::RwLock::new(authorities),
+            policies: std::sync::RwLock::new(policies),
+            state: Mutex::new(State {
+                configured: zones.clone(),
+                zones: zones.clone(),
+                served,
+                secondaries: build_secondaries(zones)?,
+                health_checks: build_health_checks(zones)?,
+                fetched: HashMap::new(),
+                members: HashMap::new(),
+            }),
+            secondaries_changed: Notify::new(),
+            redis_zones_changed: Notify::new(),
+            refreshers: std::sync::Mutex::new(HashMap::new()),
+            keyring,
+            persisting: Mutex::new(()),
+            generated,
+            wildcards: std::sync::RwLock::new(wildcards),
+            delegations: std::sync::RwLock::new(delegations),
+            health: Arc::new(Health::default()),
+            health_checks_changed: Notify::new(),
+            probes: std::sync::Mutex::new(HashMap::new()),
+            changes: broadcast::channel(ZONE_CHANGES).0,
+        })
+    }
+
+    /// Receives the changes of the zones served from now on.
+    #[cfg_attr(not(feature = "grpc"), allow(dead_code))]
+    pub(crate) fn watch(&self) -> broadcast::Receiver<ZoneChange> {
+        self.changes.subscribe()
+    }
+
+    /// Tells the watchers that `authority` is served with new records.
+    async fn announce(&self, authority: &InMemoryAuthority) {
+        let zone = zonefile::fqdn(&authority.origin().into());
+        let _ = self
+            .changes
+            .send(ZoneChange::Updated(zone, authority.serial().await));
+    }
+
+    /// Tells the watchers that `zone` is no longer served.
+    fn announce_removal(&self, zone: &LowerName) {
+        let _ = self
+            .changes
+            .send(ZoneChange::Removed(zonefile::fqdn(&zone.into())));
+    }
+
+    pub(crate) fn catalog(&self) -> &SharedCatalog {
+        &self.catalog
+    }
+
+    /// The zones currently served, including runtime edits.
+    pub(crate) async fn zones(&self) -> config::Zone {
+        self.state.lock().await.zones.clone()
+    }
+
+    /// Builds `zones` to serve in place of the current ones, dropping
+    /// runtime edits, once the returned reload is committed. The zone set
+    /// can't change until then, or until the reload is dropped.
+    pub(crate) async fn prepare_reload(&self, zones: config::Zone) -> Result<PreparedReload<'_>> {
+        let state = self.state.lock().await;
+        let built = self.build(&state, zones.clone()).await?;
+        Ok(PreparedReload {
+            zones: self,
+            state,
+            built,
+            configured: zones,
+        })
+    }
+
+    /// Serves the zones of the last loaded config again, dropping runtime edits.
+    pub(crate) async fn restore(&self) -> Result<()> {
+        let mut state = self.state.lock().await;
+        let zones = state.configured.clone();
+        self.swap(&mut state, zones).await
+    }
+
+    /// The zones and the records currently served.
+    pub(crate) async fn snapshot(&self) -> ZoneSetSnapshot {
+        let state = self.state.lock().await;
+        let mut records = BTreeMap::new();
+        for authority in self.authorities() {
+            let saved = match authority.zone_type() {
+                ZoneType::Secondary => authority
+                    .records()
+                    .await
+                    .values()
+                    .flat_map(|rrset| rrset.records_without_rrsigs().chain(rrset.rrsigs()))
+                    .cloned()
+                    .collect(),
+                _ => saved_records(&authority).await,
+            };
+            records.insert(authority.origin().into(), saved);
+        }
+        ZoneSetSnapshot {
+            configured: state.configured.clone(),
+            zones: state.zones.clone(),
+            records,
+        }
+    }
+
+    /// Serves the zones and records of `snapshot` in place of the current
+    /// ones. The SOA serials of the primary zones restored are raised above
+    /// the current ones, so that their secondaries transfer them again.
+    /// Zones served by `serve_records` are only restored while still served.
+    pub(crate) async fn restore_snapshot(&self, snapshot: ZoneSetSnapshot) -> Result<()> {
+        let mut state = self.state.lock().await;
+        self.swap(&mut state, snapshot.zones).await?;
+        state.configured = snapshot.configured;
+        let secondaries: HashSet<rr::Name> = state.secondaries.keys().cloned().collect();
+        drop(state);
+
+        for (zone, records) in snapshot.records {
+            if secondaries.contains(&zone) {
+                self.load_secondary(&zone, records).await?;
+                continue;
+            }
+            let Some(current) = self
+                .authority(&zone.clone().into())
+                .filter(|current| current.zone_type() == ZoneType::Primary)
+            else {
+                continue;
+            };
+            let origin = current.origin().clone();
+            let mut restored = new_authority(zone, records, ZoneType::Primary);
+            let mut restored = std::mem::take(restored.records_get_mut());
+            let mut records = current.records_mut().await;
+            if same_records(&records, &restored) {
+                continue;
+            }
+            if let Some(serial) = serial_in(&records, &origin) {
+                raise_serial_in(&mut restored, &origin, serial, self.serial_policy(&origin));
+            }
+            if let Some(signer) = self.signer(&origin) {
+                if let Err(e) = signer.sign(&mut restored) {
+                    warn!("failed to sign zone {}: {:#}", origin, e);
+                }
+            }
+            *records = restored;
+            drop(records);
+            self.published(&current).await;
+        }
+        Ok(())
+    }
+
+    /// Applies `edit` to a copy of the current zones and serves the result.
+    pub(crate) async fn edit<E: From<anyhow::Error>>(
+        &self,
+        edit: impl FnOnce(&mut config::Zone) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let mut state = self.state.lock().await;
+        let mut zones = state.zones.clone();
+        edit(&mut zones)?;
+        Ok(self.swap(&mut state, zones).await?)
+    }
+
+    /// Builds `zones` and swaps the authorities whose records changed into
+    /// the catalog in a single write, so every query sees either the old or
+    /// the new zones. Unchanged authorities are kept as they are.
+    async fn swap(&self, state: &mut State, zones: config::Zone) -> Result<()> {
+        let built = self.build(state, zones).await?;
+        self.apply(state, built).await;
+        Ok(())
+    }
+
+    /// Builds the zones of `zones` without serving them, so that a zone
+    /// failing to load leaves the served ones as they are.
+    async fn build(&self, state: &State, zones: config::Zone) -> Result<Built> {
+        let members: HashMap<rr::Name, BTreeSet<rr::Name>> = state
+            .members
+            .iter()
+            .filter(|(catalog, _)| is_catalog(&zones, catalog))
+            .map(|(catalog, members)| (catalog.clone(), members.clone()))
+            .collect();
+        let expanded = with_members(&zones, &members);
+        let built = build_authorities(&zones, false, &state.fetched)?;
+        let policies = build_policies(&expanded, &self.keyring, &self.generated)?;
+        let secondaries = build_secondaries(&expanded)?;
+        let health_checks = build_health_checks(&zones)?;
+        let mut served: HashSet<rr::Name> = built.keys().cloned().collect();
+        // transferred secondary zones stay until their refresh task replaces them
+        served.extend(
+            state
+                .served
+                .iter()
+                .filter(|zone| secondaries.contains_key(*zone) && self.is_secondary(zone))
+                .cloned(),
+        );
+        let mut changed = Vec::new();
+        for (zone, mut authority) in built {
+            let policy = policies.get(&LowerName::from(&zone));
+            let signer = policy.and_then(|policy| policy.signer.as_deref());
+            if let Some(current) = self.authority(&zone.clone().into()) {
+                if current.zone_type() == ZoneType::Primary {
+                    let records = current.records().await;
+                    if same_records(&records, &authority.records().await)
+                        && dnssec::signed_with(&records, signer)
+                    {
+                        continue;
+                    }
+                    let serial_policy = policy.map_or_else(Default::default, |p| p.serial_policy);
+                    raise_serial(&authority, current.serial().await, serial_policy).await;
+                }
+            }
+            if let Some(signer) = signer {
+                signer
+                    .sign(authority.records_get_mut())
+                    .with_context(|| format!("failed to sign zone {}", zone))?;
+            }
+            changed.push((zone, Arc::new(authority)));
+        }
+        Ok(Built {
+            zones,
+            members,
+            served,
+            changed,
+            policies,
+            secondaries,
+            health_checks,
+        })
+    }
+
+    /// Serves the zones `built` in place of the current ones.
+    async fn apply(&self, state: &mut State, built: Built) {
+        let Built {
+            zones,
+            members,
+            served,
+            changed,
+            policies,
+            secondaries,
+            health_checks,
+        } = built;
+        state.members = members;
+        self.catalog.write(|catalog| {
+            let mut authorities = self.authorities.write().unwrap();
+            for zone in state.served.difference(&served) {
+                info!("removing zone {}", zone);
+                let zone = LowerName::from(zone);
+                catalog.remove(&zone);
+                authorities.remove(&zone);
+                self.announce_removal(&zone);
+            }
+            for (zone, authority) in changed.iter() {
+                info!("loading zone {}", zone);
+                catalog.insert(zone.clone().into(), Box::new(authority.clone()));
+                authorities.insert(zone.into(), authority.clone());
+            }
+        });
+        *self.policies.write().unwrap() = policies;
+        for (_, authority) in changed {
+            self.track_records(&authority).await;
+            self.persist(&authority).await;
+            self.notify_secondaries(&authority).await;
+            self.announce(&authority).await;
+        }
+        let previous = std::mem::replace(&mut state.zones, zones);
+        state.served = served;
+        if state.secondaries != secondaries {
+            state.secondaries = secondaries;
+            self.secondaries_changed.notify_one();
+        }
+        let redis = redis_zones(&state.zones);
+        if redis != redis_zones(&previous) {
+            state.fetched.retain(|zone, _| redis.contains(zone));
+            self.redis_zones_changed.notify_one();
+        }
+        if state.health_checks != health_checks {
+            state.health_checks = health_checks;
+            self.health_checks_changed.notify_one();
+        }
+    }
+
+    /// The zones with `backend = "redis"`.
+    pub(crate) async fn redis_zones(&self) -> HashSet<rr::Name> {
+        redis_zones(&self.state.lock().await.zones)
+    }
+
+    /// Waits until the zones with `backend = "redis"` change.
+    pub(crate) async fn redis_zones_changed(&self) {
+        self.redis_zones_changed.notified().await
+    }
+
+    /// Serves `records` fetched for the `redis` zone `zone`, or its records
+    /// from the config when it has none in Redis.
+    pub(crate) async fn serve_fetched(
+        &self,
+        zone: &rr::Name,
+        records: Option<Vec<rr::Record>>,
+    ) -> Result<()> {
+        let mut state = self.state.lock().await;
+        if !redis_zones(&state.zones).contains(zone) {
+            return Ok(());
+        }
+        let previous = match records {
+            Some(records) => state.fetched.insert(zone.clone(), records),
+            None => state.fetched.remove(zone),
+        };
+        let zones = state.zones.clone();
+        if let Err(e) = self.swap(&mut state, zones).await {
+            match previous {
+                Some(records) => state.fetched.insert(zone.clone(), records),
+                None => state.fetched.remove(zone),
+            };
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn authority(&self, zone: &LowerName) -> Option<Arc<InMemoryAuthority>> {
+        self.authorities.read().unwrap().get(zone).cloned()
+    }
+
+    fn is_secondary(&self, zone: &rr::Name) -> bool {
+        self.authority(&zone.into())
+            .is_some_and(|authority| authority.zone_type() == ZoneType::Secondary)
+    }
+
+    /// Keeps every secondary zone transferred from its primaries until
+    /// `token` is cancelled, following changes of the configured zones.
+    pub(crate) async fn refresh_secondaries(self: Arc<Self>, token: CancellationToken) {
+        loop {
+            let secondaries: HashMap<LowerName, (rr::Name, Vec<SocketAddr>)> = self
+                .state
+                .lock()
+                .await
+                .secondaries
+                .iter()
+                .map(|(zone, primaries)| (zone.into(), (zone.clone(), primaries.clone())))
+                .collect();
+            self.start_refreshers(secondaries, &token);
+            tokio::select! {
+                _ = self.secondaries_changed.notified() => {}
+                _ = token.cancelled() => break,
+            }
+        }
+    }
+
+    /// Runs a refresh task for each of `secondaries`, restarting those whose
+    /// primaries changed and stopping those no longer configured.
+    fn start_refreshers(
+        self: &Arc<Self>,
+        secondaries: HashMap<LowerName, (rr::Name, Vec<SocketAddr>)>,
+        token: &CancellationToken,
+    ) {
+        let mut refreshers = self.refreshers.lock().unwrap();
+        refreshers.retain(|zone, refresher| {
+            let keep = secondaries
+                .get(zone)
+                .is_some_and(|(_, primaries)| *primaries == refresher.primaries);
+            if !keep {
+                refresher.token.cancel();
+            }
+            keep
+        });
+        for (key, (zone, primaries)) in secondaries {
+            if refreshers.contains_key(&key) {
+                continue;
+            }
+            let refresher = Refresher {
+                primaries: primaries.clone(),
+                token: token.child_token(),
+                wake: Arc::new(Notify::new()),
+            };
+            tokio::spawn(self.clone().refresh(
+                zone,
+                primaries,
+                refresher.wake.clone(),
+                refresher.token.clone(),
+            ));
+            refreshers.insert(key, refresher);
+        }
+    }
+
+    /// Probes the health-checked addresses of the zones until `token` is
+    /// cancelled, following changes of the configured zones.
+    pub(crate) async fn check_health(self: Arc<Self>, token: CancellationToken) {
+        loop {
+            let checks = self.state.lock().await.health_checks.clone();
+            self.start_probes(checks, &token);
+            tokio::select! {
+                _ = self.health_checks_changed.notified() => {}
+                _ = token.cancelled() => break,
+            }
+        }
+    }
+
+    /// Runs a probe task for each of `checks`, restarting those whose check
+    /// changed and stopping those no longer configured.
+    fn start_probes(
+        &self,
+        checks: HashMap<health::Target, config::HealthCheckConfig>,
+        token: &CancellationToken,
+    ) {
+        let mut probes = self.probes.lock().unwrap();
+        probes.retain(|target, probe| {
+            let keep = checks.get(target) == Some(&probe.check);
+            if !keep {
+                probe.token.cancel();
+            }
+            keep
+        });
+        for (target, check) in checks {
+            if probes.contains_key(&target) {
+                continue;
+            }
+            let probe = HealthProbe {
+                check: check.clone(),
+                token: token.child_token(),
+            };
+            tokio::spawn(
+                self.health
+                    .clone()
+                    .watch(target.clone(), check, probe.token.clone()),
+            );
+            probes.insert(target, probe);
+        }
+    }
+
+    /// Removes the addresses failing their health checks from `answers`.
+    pub(crate) fn withdraw_unhealthy(&self, answers: &mut Vec<rr::Record>) {
+        self.health.withdraw(answers);
+    }
+
+    /// Transfers the secondary `zone` whenever the refresh timer of its SOA
+    /// fires or `wake` is notified, retrying failures after the retry timer
+    /// and no longer serving the zone once it expires.
+    async fn refresh(
+        self: Arc<Self>,
+        zone: rr::Name,
+        primaries: Vec<SocketAddr>,
+        wake: Arc<Notify>,
+        token: CancellationToken,
+    ) {
+        let mut expires = self
+            .secondary_soa(&zone)
+            .await
+            .map(|soa| Instant::now() + soa_timers(&soa).2);
+        loop {
+            let current = self.secondary_soa(&zone).await;
+            let wait = match self.transfer(&zone, &primaries, current.as_ref()).await {
+                Ok(soa) => {
+                    let (refresh, _, expire) = soa_timers(&soa);
+                    expires = Some(Instant::now() + expire);
+                    refresh
+                }
+                Err(e) => {
+                    warn!("failed to refresh secondary zone {}: {:#}", zone, e);
+                    if expires.is_some_and(|expires| expires <= Instant::now()) {
+                        warn!("secondary zone {} expired", zone);
+                        self.expire(&zone).await;
+                        expires = None;
+                    }
+                    let retry = current.map_or(INITIAL_RETRY, |soa| soa_timers(&soa).1);
+                    match expires {
+                        Some(expires) => retry.min(expires - Instant::now()),
+                        None => retry,
+                    }
+                }
+            };
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = wake.notified() => debug!("refreshing secondary zone {} on NOTIFY", zone),
+                _ = token.cancelled() => break,
+            }
+        }
+    }
+
+    /// The SOA record of the transferred copy of the secondary `zone`.
+    async fn secondary_soa(&self, zone: &rr::Name) -> Option<rr::Record> {
+        let authority = self.authority(&zone.into())?;
+        if authority.zone_type() != ZoneType::Secondary {
+            return None;
+        }
+        soa_record(&authority).await
+    }
+
+    /// Transfers `zone` from the first primary that answers and serves the
+    /// result, returns the SOA record served afterwards.
+    async fn transfer(
+        &self,
+        zone: &rr::Name,
+        primaries: &[SocketAddr],
+        current: Option<&rr::Record>,
+    ) -> Result<rr::Record> {
+        let key = self.key(&zone.into());
+        let mut last_error = anyhow!("no primaries");
+        for primary in primaries {
+            let records = match secondary::fetch(*primary, zone, current, key.clone()).await {
+                Ok(Transfer::UpToDate) => {
+                    debug!("secondary zone {} is up to date with {}", zone, primary);
+                    return current
+                        .cloned()
+                        .ok_or_else(|| anyhow!("{} sent no records of {}", primary, zone));
+                }
+                Ok(Transfer::Full(records)) => records,
+                Ok(Transfer::Incremental(changes)) => {
+                    let mut records = match self.authority(&zone.into()) {
+                        Some(authority) => authority
+                            .records()
+                            .await
+                            .values()
+                            .flat_map(|rrset| {
+                                rrset
+                                    .records_without_rrsigs()
+                                    .chain(rrset.rrsigs())
+                                    .cloned()
+                            })
+                            .collect(),
+                        None => Vec::new(),
+                    };
+                    secondary::apply(&mut records, changes);
+                    records
+                }
+                Err(e) => {
+                    debug!("failed to transfer {} from {}: {:#}", zone, primary, e);
+                    last_error = e;
+                    continue;
+                }
+            };
+            let soa = self.load_secondary(zone, records).await?;
+            info!("transferred secondary zone {} from {}", zone, primary);
+            return Ok(soa);
+        }
+        Err(last_error)
+    }
+
+    /// Serves the transferred `records` of the secondary `zone`, returns its
+    /// SOA record.
+    async fn load_secondary(
+        &self,
+        zone: &rr::Name,
+        records: Vec<rr::Record>,
+    ) -> Result<rr::Record> {
+        let soa = records
+            .iter()
+            .find(|r| r.record_type() == RecordType::SOA && r.name() == zone)
+            .cloned()
+            .ok_or_else(|| anyhow!("transfer of {} has no SOA record", zone))?;
+        let mut state = self.state.lock().await;
+        if !state.secondaries.contains_key(zone) {
+            bail!("{} is no longer a secondary zone", zone);
+        }
+        let members = match is_catalog(&state.zones, zone) {
+            true => Some(catalog_zone::members(zone, &records)?),
+            false => None,
+        };
+        let authority = Arc::new(new_authority(zone.clone(), records, ZoneType::Secondary));
+        self.catalog.write(|catalog| {
+            catalog.insert(zone.into(), Box::new(authority.clone()));
+            self.authorities
+                .write()
+                .unwrap()
+                .insert(zone.into(), authority.clone());
+        });
+        state.served.insert(zone.clone());
+        if let Some(members) = members {
+            if state.members.get(zone) != Some(&members) {
+                info!("catalog zone {} lists {} member zones", zone, members.len());
+                state.members.insert(zone.clone(), members);
+                let zones = state.zones.clone();
+                self.swap(&mut state, zones).await?;
+            }
+        }
+        drop(state);
+        self.track_records(&authority).await;
+        self.notify_secondaries(&authority).await;
+        self.announce(&authority).await;
+        Ok(soa)
+    }
+
+    /// Stops serving the transferred copy of the secondary `zone`.
+    async fn expire(&self, zone: &rr::Name) {
+        let mut state = self.state.lock().await;
+        if !self.is_secondary(zone) {
+            return;
+        }
+        let zone = LowerName::from(zone);
+        self.catalog.write(|catalog| {
+            catalog.remove(&zone);
+            self.authorities.write().unwrap().remove(&zone);
+        });
+        self.announce_removal(&zone);
+        state.served.remove(&zone.into());
+    }
+
+    /// Serves `records` as the primary zone `zone`, with a synthesized SOA
+    /// and NS record unless they have their own, in place of its previous
+    /// records. Such zones aren't configured, reloads leave them alone.
+    pub(crate) async fn serve_records(
+        &self,
+        zone: &rr::Name,
+        records: Vec<rr::Record>,
+    ) -> Result<()> {
+        let records = config::ZoneConfig::default().with_apex(zone, records)?;
+        let authority = new_authority(zone.clone(), records, ZoneType::Primary);
+        if let Some(current) = self.authority(&zone.into()) {
+            if same_records(&current.records().await, &authority.records().await) {
+                return Ok(());
+            }
+            raise_serial(&authority, current.serial().await, Default::default()).await;
+        }
+        let authority = Arc::new(authority);
+        self.catalog.write(|catalog| {
+            catalog.insert(zone.into(), Box::new(authority.clone()));
+            self.authorities
+                .write()
+                .unwrap()
+                .insert(zone.into(), authority.clone());
+        });
+        self.track_records(&authority).await;
+        self.announce(&authority).await;
+        Ok(())
+    }
+
+    /// Stops serving the zone `zone` served by `serve_records`. A configured
+    /// zone is left alone.
+    pub(crate) async fn withdraw_records(&self, zone: &rr::Name) {
+        let state = self.state.lock().await;
+        if state.served.contains(zone) {
+            return;
+        }
+        let zone = LowerName::from(zone);
+        let removed = self.catalog.write(|catalog| {
+            catalog.remove(&zone);
+            self.authorities.write().unwrap().remove(&zone).is_some()
+        });
+        if removed {
+            self.announce_removal(&zone);
+        }
+    }
+
+    pub(crate) fn keyring(&self) -> &Keyring {
+        &self.keyring
+    }
+
+    /// Whether the client at `ip` may transfer `zone`, `key` being the TSIG
+    /// key its request was verified with.
+    pub(crate) fn transfer_allowed(
+        &self,
+        zone: &LowerName,
+        ip: IpAddr,
+        key: Option<&LowerName>,
+    ) -> bool {
+        self.policies
+            .read()
+            .unwrap()
+            .get(zone)
+            .is_some_and(|policy| policy.allow_transfer.allows(ip, key))
+    }
+
+    pub(crate) fn notify_allowed(
+        &self,
+        zone: &LowerName,
+        ip: IpAddr,
+        key: Option<&LowerName>,
+    ) -> bool {
+        self.policies
+            .read()
+            .unwrap()
+            .get(zone)
+            .is_some_and(|policy| policy.allow_notify.allows(ip, key))
+    }
+
+    /// Whether the client at `ip` may query `zone`, zones without access
+    /// rules are open to everyone.
+    pub(crate) fn query_allowed(
+        &self,
+        zone: &LowerName,
+        ip: IpAddr,
+        key: Option<&LowerName>,
+    ) -> bool {
+        self.policies
+            .read()
+            .unwrap()
+            .get(zone)
+            .is_none_or(|policy| policy.query.allows(ip, key))
+    }
+
+    pub(crate) fn update_allowed(
+        &self,
+        zone: &LowerName,
+        ip: IpAddr,
+        key: Option<&LowerName>,
+    ) -> bool {
+        self.policies
+            .read()
+            .unwrap()
+            .get(zone)
+            .is_some_and(|policy| policy.allow_update.allows(ip, key))
+    }
+
+    /// Applies the dynamic update `request` to the zone it names. Changes
+    /// raise the SOA serial and are announced to the zone's `notify` list.
+    pub(crate) async fn update(&self, request: &MessageRequest) -> Result<(), ResponseCode> {
+        let zone = request.query().name();
+        let authority = self.authority(zone).ok_or(ResponseCode::NotAuth)?;
+        if authority.zone_type() != ZoneType::Primary {
+            return Err(ResponseCode::Refused);
+        }
+        if update::apply(&authority, request).await? {
+            self.changed(&authority).await;
+        }
+        Ok(())
+    }
+
+    /// The TSIG key signing the messages sent for `zone`.
+    fn key(&self, zone: &LowerName) -> Option<TSigner> {
+        self.policies
+            .read()
+            .unwrap()
+            .get(zone)
+            .and_then(|policy| policy.key.clone())
+    }
+
+    /// Refreshes the secondary `zone` right away, returns whether it is a
+    /// secondary zone.
+    pub(crate) fn request_refresh(&self, zone: &LowerName) -> bool {
+        match self.refreshers.lock().unwrap().get(zone) {
+            Some(refresher) => {
+                refresher.wake.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Records that `authority` changed in place: raises its SOA serial,
+    /// signs the zone again and notifies the secondaries of the zone.
+    pub(crate) async fn changed(&self, authority: &InMemoryAuthority) {
+        let policy = self.serial_policy(authority.origin());
+        raise_serial(authority, authority.serial().await, policy).await;
+        if let Some(signer) = self.signer(authority.origin()) {
+            if let Err(e) = signer.sign(&mut *authority.records_mut().await) {
+                warn!("failed to sign zone {}: {:#}", authority.origin(), e);
+            }
+        }
+        self.published(authority).await;
+    }
+
+    /// Applies `changes` to the in-memory primary zones enclosing their
+    /// names, all of them or none. The zones touched stay locked until the
+    /// changes are applied and their SOA serials raised and signatures
+    /// renewed, so queries see either none or all of the changes. Returns
+    /// whether a zone changed.
+    pub(crate) async fn commit(&self, changes: Vec<RecordChange>) -> Result<bool, RecordError> {
+        let mut zones: BTreeMap<LowerName, (Arc<InMemoryAuthority>, Vec<RecordChange>)> =
+            BTreeMap::new();
+        for change in changes {
+            let authority = self
+                .find(change.name())
+                .ok_or_else(|| RecordError::ZoneNotFound(change.name().clone()))?;
+            if authority.zone_type() == ZoneType::Secondary {
+                return Err(RecordError::ReadOnly(authority.origin().into()));
+            }
+            zones
+                .entry(authority.origin().clone())
+                .or_insert_with(|| (authority, Vec::new()))
+                .1
+                .push(change);
+        }
+
+        // locked in the order of their names, so that commits don't deadlock
+        let mut locked = Vec::with_capacity(zones.len());
+        for (authority, _) in zones.values() {
+            locked.push(authority.records_mut().await);
+        }
+        let mut edited = Vec::new();
+        for ((origin, (_, changes)), records) in zones.iter().zip(&locked) {
+            let mut records = (**records).clone();
+            let mut changed = false;
+            for change in changes {
+                match change {
+                    RecordChange::Add(record) => {
+                        let key = RrKey::new(record.name().into(), record.record_type());
+                        let rrset = records.entry(key).or_insert_with(|| {
+                            Arc::new(RecordSet::new(record.name(), record.record_type(), 0))
+                        });
+                        changed |= Arc::make_mut(rrset).insert(record.clone(), 0);
+                    }
+                    RecordChange::Remove(record) => {
+                        if !remove_from(&mut records, record) {
+                            return Err(RecordError::RecordNotFound(record.name().clone()));
+                        }
+                        changed = true;
+                    }
+                    RecordChange::Replace(rrset) => {
+                        let key = RrKey::new(rrset.name().into(), rrset.record_type());
+                        if rrset.is_empty() {
+                            records.remove(&key);
+                        } else {
+                            records.insert(key, Arc::new(rrset.clone()));
+                        }
+                        changed = true;
+                    }
+                }
+            }
+            if changed {
+                if let Some(serial) = serial_in(&records, origin) {
+                    raise_serial_in(&mut records, origin, serial, self.serial_policy(origin));
+                }
+                if let Some(signer) = self.signer(origin) {
+                    if let Err(e) = signer.sign(&mut records) {
+                        warn!("failed to sign zone {}: {:#}", origin, e);
+                    }
+                }
+            }
+            edited.push(changed.then_some(records));
+        }
+        let mut changed = Vec::with_capacity(edited.len());
+        for (records, edited) in locked.iter_mut().zip(edited) {
+            changed.push(edited.is_some());
+            if let Some(edited) = edited {
+                **records = edited;
+            }
+        }
+        drop(locked);
+
+        for ((authority, _), changed) in zones.values().zip(&changed) {
+            if *changed {
+                self.published(authority).await;
+            }
+        }
+        Ok(changed.contains(&true))
+    }
+
+    /// Follows up on changed and signed records of `authority`: tracks what
+    /// they hold, saves them and tells the secondaries and the watchers.
+    async fn published(&self, authority: &InMemoryAuthority) {
+        self.track_records(authority).await;
+        self.persist(authority).await;
+        self.notify_secondaries(authority).await;
+        self.announce(authority).await;
+    }
+
+    /// Records whether `authority` holds wildcards and delegations after its
+    /// records changed.
+    async fn track_records(&self, authority: &InMemoryAuthority) {
+        let records = authority.records().await;
+        let origin = authority.origin();
+        for (tracked, holds) in [
+            (&self.wildcards, wildcard::has_wildcards(&records)),
+            (
+                &self.delegations,
+                delegation::has_delegations(&records, origin),
+            ),
+        ] {
+            let mut tracked = tracked.write().unwrap();
+            if holds {
+                tracked.insert(origin.clone());
+            } else {
+                tracked.remove(origin);
+            }
+        }
+    }
+
+    /// Orders the A and AAAA records of `answers` from `zone` by the answer
+    /// order of the zone.
+    pub(crate) fn order_answers(&self, zone: &LowerName, answers: &mut [rr::Record]) {
+        let policies = self.policies.read().unwrap();
+        let Some(policy) = policies.get(zone) else {
+            return;
+        };
+        let rotation = match policy.answer_order {
+            config::AnswerOrder::Fixed => return,
+            config::AnswerOrder::RoundRobin => policy.rotation.fetch_add(1, Ordering::Relaxed),
+            config::AnswerOrder::Random => 0,
+        };
+        order_answers(answers, policy.answer_order, rotation);
+    }
+
+    /// Clamps the TTLs of `records` from `zone` to the limits of the zone.
+    pub(crate) fn clamp_ttls(&self, zone: &LowerName, records: &mut [rr::Record]) {
+        if let Some(policy) = self.policies.read().unwrap().get(zone) {
+            policy.ttl.clamp(records);
+        }
+    }
+
+    /// The target of the ALIAS record of `name` in `zone`, if any.
+    pub(crate) fn alias(&self, zone: &LowerName, name: &LowerName) -> Option<rr::Name> {
+        let policies = self.policies.read().unwrap();
+        policies.get(zone)?.aliases.get(name).cloned()
+    }
+
+    /// The NXDOMAIN redirect of the closest zone enclosing `name`, if any.
+    pub(crate) fn redirect(&self, name: &LowerName) -> Option<Arc<Redirect>> {
+        let policies = self.policies.read().unwrap();
+        let mut name = name.clone();
+        loop {
+            if let Some(policy) = policies.get(&name) {
+                return policy.redirect.clone();
+            }
+            if name.is_root() {
+                return None;
+            }
+            name = name.base_name();
+        }
+    }
+
+    /// Keeps the records of `answers` from `zone` targeted at the client at
+    /// `location`, which is only looked up for zones with geo-targeted
+    /// records. Returns whether the answer depends on the location.
+    pub(crate) fn select_geo(
+        &self,
+        zone: &LowerName,
+        answers: &mut Vec<rr::Record>,
+        location: impl FnOnce() -> Location,
+    ) -> bool {
+        let policies = self.policies.read().unwrap();
+        match policies.get(zone) {
+            Some(policy) if !policy.geo.is_empty() => {
+                geoip::select(answers, &policy.geo, &location())
+            }
+            _ => false,
+        }
+    }
+
+    fn serial_policy(&self, zone: &LowerName) -> config::SerialPolicy {
+        self.policies
+            .read()
+            .unwrap()
+            .get(zone)
+            .map_or_else(Default::default, |policy| policy.serial_policy)
+    }
+
+    fn signer(&self, zone: &LowerName) -> Option<Arc<ZoneSigner>> {
+        self.policies
+            .read()
+            .unwrap()
+            .get(zone)
+            .and_then(|policy| policy.signer.clone())
+    }
+
+    /// Signs the signed zones again before their signatures expire, until
+    /// `token` is cancelled.
+    pub(crate) async fn resign(self: Arc<Self>, token: CancellationToken) {
+        let mut ticker = tokio::time::interval(RESIGN_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = token.cancelled() => break,
+            }
+            for authority in self.authorities() {
+                let Some(signer) = self.signer(authority.origin()) else {
+                    continue;
+                };
+                if authority.zone_type() == ZoneType::Primary
+                    && signer.expiring(&authority.records().await)
+                {
+                    debug!("signing zone {} again", authority.origin());
+                    self.changed(&authority).await;
+                }
+            }
+        }
+    }
+
+    /// Saves the records of `authority` to the database of its zone and to
+    /// its persist file, if any. The file is replaced atomically, so a crash
+    /// leaves either the old or the new records.
+    async fn persist(&self, authority: &InMemoryAuthority) {
+        let (database, path) = match self.policies.read().unwrap().get(authority.origin()) {
+            Some(policy) => (policy.database.clone(), policy.persist_file.clone()),
+            None => return,
+        };
+        if database.is_none() && path.is_none() {
+            return;
+        }
+        let _persisting = self.persisting.lock().await;
+        if let Some(database) = database {
+            let zone: rr::Name = authority.origin().into();
+            let records = saved_records(authority).await;
+            let saved = tokio::task::spawn_blocking({
+                let (database, zone) = (database.clone(), zone.clone());
+                move || sqlite::save(&database, &zone, &records)
+            });
+            match saved
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|saved| saved)
+            {
+                Ok(()) => debug!("saved zone {} to {}", zone, database.display()),
+                Err(e) => warn!(
+                    "failed to save zone {} to {}: {:#}",
+                    zone,
+                    database.display(),
+                    e
+                ),
+            }
+        }
+        let Some(path) = path else {
+            return;
+        };
+        let text = zone_text(authority).await;
+        let mut temp = path.clone().into_os_string();
+        temp.push(".tmp");
+        let result = async {
+            tokio::fs::write(&temp, text).await?;
+            tokio::fs::rename(&temp, &path).await
+        };
+        match result.await {
+            Ok(()) => debug!("saved zone {} to {}", authority.origin(), path.display()),
+            Err(e) => warn!(
+                "failed to save zone {} to {}: {}",
+                authority.origin(),
+                path.display(),
+                e
+            ),
+        }
+    }
+
+    /// Sends a NOTIFY with the current SOA of `authority` to the secondaries
+    /// configured for its zone, returns how many there are.
+    async fn notify_secondaries(&self, authority: &InMemoryAuthority) -> usize {
+        let (targets, key) = match self.policies.read().unwrap().get(authority.origin()) {
+            Some(policy) if !policy.notify.is_empty() => {
+                (policy.notify.clone(), policy.key.clone())
+            }
+            _ => return 0,
+        };
+        let count = targets.len();
+        let zone: rr::Name = authority.origin().into();
+        let soa = soa_record(authority).await;
+        for target in targets {
+            let (zone, soa, key) = (zone.clone(), soa.clone(), key.clone());
+            tokio::spawn(async move {
+                match notify::send(zone.clone(), soa, target, key).await {
+                    Ok(()) => debug!("notified {} of zone {}", target, zone),
+                    Err(e) => warn!("failed to notify {} of zone {}: {:#}", target, zone, e),
+                }
+            });
+        }
+        count
+    }
+
+    /// Sends a NOTIFY for `zone` to its `notify` list, as when it changes.
+    /// Returns how many secondaries are notified, `None` without such zone.
+    #[cfg_attr(not(unix), allow(dead_code))]
+    pub(crate) async fn notify(&self, zone: &LowerName) -> Option<usize> {
+        let authority = self.authority(zone)?;
+        Some(self.notify_secondaries(&authority).await)
+    }
+
+    /// Every record of `zone` in AXFR order: the SOA, the other records and
+    /// the SOA again.
+    pub(crate) async fn transfer_records(&self, zone: &LowerName) -> Option<Vec<rr::Record>> {
+        let authority = self.authority(zone)?;
+        let soa = soa_record(&authority).await?;
+        let records = authority.records().await;
+        let mut transfer = vec![soa.clone()];
+        transfer.extend(
+            records
+                .values()
+                .filter(|rrset| rrset.record_type() != RecordType::SOA)
+                .flat_map(|rrset| rrset.records_without_rrsigs().chain(rrset.rrsigs()))
+                .cloned(),
+        );
+        if let Some(rrset) = records.get(&RrKey::new(zone.clone(), RecordType::SOA)) {
+            transfer.extend(rrset.rrsigs().iter().cloned());
+        }
+        transfer.push(soa);
+        Some(transfer)
+    }
+
+    /// The current records of `zone` as an RFC 1035 zone file.
+    pub(crate) async fn export(&self, zone: &LowerName) -> Option<String> {
+        let authority = self.authority(zone)?;
+        Some(zone_text(&authority).await)
+    }
+
+    /// The NSEC3 records denying `name`, or only its queried type without
+    /// `nx_domain`. `None` unless the enclosing zone is signed with NSEC3.
+    pub(crate) async fn nsec3_proof(
+        &self,
+        name: &LowerName,
+        nx_domain: bool,
+    ) -> Option<Vec<rr::Record>> {
+        let authority = self.find(&name.into())?;
+        let signer = self.signer(authority.origin())?;
+        let records = authority.records().await;
+        signer.nsec3_proof(&records, name, nx_domain)
+    }
+
+    /// The answer to `name` expanded from the wildcards of its zone (RFC
+    /// 4592), `None` when the zone has no wildcards or answers `name` with
+    /// its own records.
+    pub(crate) async fn wildcard(
+        &self,
+        name: &LowerName,
+        query_type: RecordType,
+        lookup_options: LookupOptions,
+    ) -> Option<Result<Box<dyn LookupObject>, LookupError>> {
+        let authority = self.find(&name.into())?;
+        if !self.wildcards.read().unwrap().contains(authority.origin()) {
+            return None;
+        }
+        let records = authority.records().await;
+        let result = wildcard::lookup(&records, authority.origin(), name, query_type)?;
+        Some(result.map(|rrset| {
+            let answers = LookupRecords::new(lookup_options, rrset);
+            Box::new(AuthLookup::answers(answers, None)) as Box<dyn LookupObject>
+        }))
+    }
+
+    /// The referral to the child zone holding `name`, when its zone
+    /// delegates it.
+    pub(crate) async fn referral(
+        &self,
+        name: &LowerName,
+        query_type: RecordType,
+    ) -> Option<Referral> {
+        let authority = self.find(&name.into())?;
+        if !self
+            .delegations
+            .read()
+            .unwrap()
+            .contains(authority.origin())
+        {
+            return None;
+        }
+        let records = authority.records().await;
+        delegation::referral(&records, authority.origin(), name, query_type)
+    }
+
+    /// The in-memory authorities currently served.
+    pub(crate) fn authorities(&self) -> Vec<Arc<InMemoryAuthority>> {
+        self.authorities.read().unwrap().values().cloned().collect()
+    }
+
+    /// The number of zones served.
+    #[cfg_attr(not(feature = "grpc"), allow(dead_code))]
+    pub(crate) fn zone_count(&self) -> usize {
+        self.authorities.read().unwrap().len()
+    }
+
+    /// The in-memory authority of the zone enclosing `name`.
+    pub(crate) fn find(&self, name: &rr::Name) -> Option<Arc<InMemoryAuthority>> {
+        let authorities = self.authorities.read().unwrap();
+        let mut zone = LowerName::from(name);
+        loop {
+            if let Some(authority) = authorities.get(&zone) {
+                return Some(authority.clone());
+            }
+            if zone.is_root() {
+                return None;
+            }
+            zone = zone.base_name();
+        }
+    }
+
+    /// Serves `authority` for `name`, its records can't be edited afterwards.
+    pub(crate) fn upsert(&self, name: LowerName, authority: Box<dyn AuthorityObject>) {
+        self.catalog.write(|catalog| {
+            self.authorities.write().unwrap().remove(&name);
+            catalog.insert(name, authority);
+        });
+    }
+
+    pub(crate) fn remove(&self, name: &LowerName) -> Option<Box<dyn AuthorityObject>> {
+        self.catalog.write(|catalog| {
+            self.authorities.write().unwrap().remove(name);
+            catalog.remove(name)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{RecordBuilder, RecordData};
+    use maplit::hashmap;
+
+    fn zone(name: &str, address: &str) -> Result<config::ZoneConfig> {
+        Ok(vec![RecordBuilder::default()
+            .name(name.to_string())
+            .data(RecordData::A(address.parse()?))
+            .ttl(Duration::from_secs(60))
+            .build()?]
+        .into())
+    }
+
+    #[test]
+    fn orders_address_answers() -> Result<()> {
+        let a = |name: &str, address: &str| -> Result<rr::Record> {
+            Ok(rr::Record::from_rdata(
+                rr::Name::from_str(name)?,
+                60,
+                RData::A(address.parse()?),
+            ))
+        };
+        let answers = vec![
+            a("www.et.internal.", "10.0.0.1")?,
+            a("www.et.internal.", "10.0.0.2")?,
+            a("www.et.internal.", "10.0.0.3")?,
+            a("web.et.internal.", "10.0.0.4")?,
+            a("web.et.internal.", "10.0.0.5")?,
+        ];
+        let addresses = |answers: &[rr::Record]| -> Vec<String> {
+            answers
+                .iter()
+                .filter_map(|r| r.data().map(ToString::to_string))
+                .collect()
+        };
+
+        let mut ordered = answers.clone();
+        order_answers(&mut ordered, config::AnswerOrder::Fixed, 1);
+        assert_eq!(ordered, answers);
+        order_answers(&mut ordered, config::AnswerOrder::RoundRobin, 4);
+        assert_eq!(
+            addresses(&ordered),
+            ["10.0.0.2", "10.0.0.3", "10.0.0.1", "10.0.0.4", "10.0.0.5"]
+        );
+        let mut shuffled = answers.clone();
+        order_answers(&mut shuffled, config::AnswerOrder::Random, 0);
+        assert_eq!(
+            shuffled[..3]
+                .iter()
+                .filter(|r| answers[..3].contains(r))
+                .count(),
+            3
+        );
+        assert_eq!(
+            shuffled[3..]
+                .iter()
+                .filter(|r| answers[3..].contains(r))
+                .count(),
+            2
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reload_replaces_only_changed_zones() -> Result<()> {
+        let zones = ZoneSet::new(
+            &hashmap! {
+            "et.internal".to_string() => zone("www.et.internal", "10.0.0.1")?,
+            "et.top".to_string() => zone("www.et.top", "10.0.0.2")?,
+            "et.example".to_string() => zone("www.et.example", "10.0.0.3")?,
+            },
+            Keyring::default(),
+        )?;
+        let lookup = |name: &str| zones.authority(&LowerName::from_str(name).unwrap());
+        let internal = lookup("et.internal").unwrap();
+        let top = lookup("et.top").unwrap();
+
+        zones
+            .prepare_reload(hashmap! {
+                "et.internal".to_string() => zone("www.et.internal", "10.0.0.1")?,
+                "et.top".to_string() => zone("www.et.top", "10.0.0.4")?,
+            })
+            .await?
+            .commit()
+            .await;
+        assert!(Arc::ptr_eq(&internal, &lookup("et.internal").unwrap()));
+        assert!(!Arc::ptr_eq(&top, &lookup("et.top").unwrap()));
+        assert!(lookup("et.example").is_none());
+        let catalog = zones.catalog().snapshot();
+        assert!(catalog.contains(&LowerName::from_str("et.top")?));
+        assert!(!catalog.contains(&LowerName::from_str("et.example")?));
+        Ok(())
+    }
+}